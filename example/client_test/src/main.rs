@@ -3,9 +3,9 @@
 // See LICENSES for license details.
 
 use std::io::{Read, Write};
-use std::time::Instant;
 
 use virga::client::{VirgeClient, ClientConfig};
+use virga::testing;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
@@ -38,21 +38,17 @@ fn test_3(client: &mut VirgeClient) -> Result<(), Box<dyn std::error::Error>> {
 }
 
 fn test_1(client: &mut VirgeClient) -> Result<(), Box<dyn std::error::Error>> {
-    // 处理发送数据, 先发送数据长度，然后发送数据
+    // 处理发送数据, 消息长度由 Virga 分帧携带，不必另发长度
     let data = vec![1; 512];
-    client.write(&data.len().to_be_bytes())?;
-    client.write(&data)?;
+    client.send_sized(&data)?;
 
 
-    // 处理接收数据, 先接收数据长度，然后创建一个足够长的databuf，最后接收数据
-    let mut buf = [0u8; 8];
-    client.read_exact(&mut buf)?;
-    let data_len = usize::from_be_bytes(buf);
+    // 处理接收数据, 先取得消息长度，然后创建一个足够长的databuf，最后读满
+    let data_len = client.recv_sized()?;
 
     let mut data = vec![0; data_len];
-    let actual_data_len_ = client.read(&mut data)?;
-    assert_eq!(data_len, actual_data_len_);
-    println!("len date = {}", actual_data_len_);
+    client.read_exact(&mut data)?;
+    println!("len date = {}", data_len);
     Ok(())
 }
 
@@ -82,67 +78,13 @@ fn test_2(client: &mut VirgeClient) -> Result<(), Box<dyn std::error::Error>> {
 }
 
 fn test_4(client: &mut VirgeClient) -> Result<(), Box<dyn std::error::Error>> {
-    // 性能测试：发送和接收大量数据
-    const DATA_SIZE: usize = 500 * 1024; // 1 MB
-    const ITERATIONS: usize = 10;
-
-    println!("\n=== 性能测试开始 ===");
-    println!("数据大小: {} KB", DATA_SIZE / 1024);
-    println!("测试次数: {}", ITERATIONS);
-    
-    let test_data = vec![0xAB; DATA_SIZE];
-    
-    let mut total_send_time = 0u128;
-    let mut total_recv_time = 0u128;
-    let mut total_bytes_sent = 0usize;
-    let mut total_bytes_received = 0usize;
-
-    for i in 1..=ITERATIONS {
-        // 发送测试
-        let start = Instant::now();
-        let sent = client.send(test_data.clone())?;
-        let send_duration = start.elapsed();
-        total_send_time += send_duration.as_millis();
-        total_bytes_sent += sent;
-
-        println!("第 {} 次 - 发送了 {} bytes ({:.2} KB) in {:.2} ms",
-                 i, sent, sent as f64 / 1024.0,
-                   send_duration.as_secs_f64() * 1000.0);
-        
-        // 接收测试
-        let start = Instant::now();
-        let received = client.recv()?;
-        let recv_duration = start.elapsed();
-        total_recv_time += recv_duration.as_millis();
-        total_bytes_received += received.len();
-        
-        // 验证数据正确性
-        assert_eq!(sent, received.len(), "第 {} 次测试：发送和接收的数据大小不一致", i);
-
-        println!("第 {} 次 - 接收: {:.2} ms, 发送: {:.2} ms",
-                 i, recv_duration.as_secs_f64() * 1000.0,
-                 send_duration.as_secs_f64() * 1000.0);
-    }
-
-    // 计算统计数据
-    let avg_send_time = total_send_time as f64 / ITERATIONS as f64;
-    let avg_recv_time = total_recv_time as f64 / ITERATIONS as f64;
-    let total_data_kb = (total_bytes_sent + total_bytes_received) as f64 / 1024.0;
-    let total_time_sec = (total_send_time + total_recv_time) as f64 / 1000.0;
-    let throughput = total_data_kb / total_time_sec;
-
-    println!("\n=== 性能测试结果 ===");
-    println!("平均发送时间: {:.2} ms", avg_send_time);
-    println!("平均接收时间: {:.2} ms", avg_recv_time);
-    println!("总数据量: {:.2} KB", total_data_kb);
-    println!("总耗时: {:.2} 秒", total_time_sec);
-    println!("平均吞吐量: {:.2} KB/s", throughput);
-
-    // 计算发送和接收速度
-    let send_speed = (total_bytes_sent as f64 / 1024.0) / (total_send_time as f64 / 1000.0);
-    let recv_speed = (total_bytes_received as f64 / 1024.0) / (total_recv_time as f64 / 1000.0);
-    println!("发送速度: {:.2} KB/s", send_speed);
-    println!("接收速度: {:.2} KB/s", recv_speed);
+    // 性能测试：由 virga::testing 发送大消息并等待服务端回显，统计每轮收发耗时
+    let report = testing::throughput_probe(
+        client,
+        testing::DEFAULT_PROBE_MESSAGE_SIZE,
+        testing::DEFAULT_PROBE_ITERATIONS,
+    )?;
+    println!("{}", report);
 
     Ok(())
-}
\ No newline at end of file
+}
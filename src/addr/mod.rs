@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! vsock 地址
+//!
+//! [`Addr`] 把 CID 与端口放在一起，文本形式为 `<cid>:<port>`，CID 可以是十进制数，
+//! 也可以是 `any`、`hypervisor`、`local`、`host` 这几个保留值的名字：
+//!
+//! ```ignore
+//! let addr: virga::Addr = "local:9000".parse()?;
+//! assert_eq!(addr, Addr::local(9000));
+//! assert_eq!(addr.to_string(), "local:9000");
+//! ```
+//!
+//! `Addr` 可与 `vsock::VsockAddr` 互相转换；`tokio_vsock::VsockAddr` 是同一类型的
+//! 再导出，两种传输后端共用这组转换。启用 `serde` 特性后以文本形式序列化。
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::VirgeError;
+
+use crate::{VMADDR_CID_ANY, VMADDR_CID_HOST, VMADDR_CID_LOCAL};
+
+const NAMED_CIDS: [(u32, &str); 4] = [
+    (VMADDR_CID_ANY as u32, "any"),
+    (0, "hypervisor"),
+    (VMADDR_CID_LOCAL as u32, "local"),
+    (VMADDR_CID_HOST as u32, "host"),
+];
+
+/// vsock 地址：CID 与端口
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Addr {
+    pub cid: u32,
+    pub port: u32,
+}
+
+impl Addr {
+    pub const fn new(cid: u32, port: u32) -> Self {
+        Self { cid, port }
+    }
+
+    /// 在任意 CID 上监听 `port`
+    pub const fn any(port: u32) -> Self {
+        Self::new(VMADDR_CID_ANY as u32, port)
+    }
+
+    /// 本机回环（需 Linux 5.6+ 的 vsock_loopback）
+    pub const fn local(port: u32) -> Self {
+        Self::new(VMADDR_CID_LOCAL as u32, port)
+    }
+
+    /// 宿主机
+    pub const fn host(port: u32) -> Self {
+        Self::new(VMADDR_CID_HOST as u32, port)
+    }
+
+    /// CID 是否为 `any`
+    pub const fn is_any(&self) -> bool {
+        self.cid == VMADDR_CID_ANY as u32
+    }
+}
+
+impl fmt::Display for Addr {
+    /// 保留的 CID 输出为名字，如 `any:1234`，其余为 `3:1234`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match NAMED_CIDS.iter().find(|(cid, _)| *cid == self.cid) {
+            Some((_, name)) => write!(f, "{}:{}", name, self.port),
+            None => write!(f, "{}:{}", self.cid, self.port),
+        }
+    }
+}
+
+impl FromStr for Addr {
+    type Err = VirgeError;
+
+    /// 接受 `3:1234`、`local:9000`、`any:1234`，名字不区分大小写，两端可以有空格
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            VirgeError::ConfigError(format!(
+                "invalid vsock address {:?}, expected <cid>:<port>",
+                s
+            ))
+        };
+        let (cid, port) = s.trim().split_once(':').ok_or_else(invalid)?;
+        let cid = match NAMED_CIDS
+            .iter()
+            .find(|(_, name)| cid.eq_ignore_ascii_case(name))
+        {
+            Some((cid, _)) => *cid,
+            None => cid.parse().map_err(|_| invalid())?,
+        };
+        let port = port.parse().map_err(|_| invalid())?;
+        Ok(Self::new(cid, port))
+    }
+}
+
+impl From<(u32, u32)> for Addr {
+    fn from((cid, port): (u32, u32)) -> Self {
+        Self::new(cid, port)
+    }
+}
+
+#[cfg(any(feature = "vsock", feature = "tokio-vsock"))]
+mod conversions {
+    use super::Addr;
+
+    // tokio_vsock::VsockAddr 是 vsock::VsockAddr 的再导出，两个特性都开启时不能各实现一次
+    #[cfg(not(feature = "vsock"))]
+    use tokio_vsock::VsockAddr;
+    #[cfg(feature = "vsock")]
+    use vsock::VsockAddr;
+
+    impl From<Addr> for VsockAddr {
+        fn from(addr: Addr) -> Self {
+            VsockAddr::new(addr.cid, addr.port)
+        }
+    }
+
+    impl From<VsockAddr> for Addr {
+        fn from(addr: VsockAddr) -> Self {
+            Addr::new(addr.cid(), addr.port())
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Addr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Addr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        assert_eq!("3:1234".parse::<Addr>().unwrap(), Addr::new(3, 1234));
+        assert_eq!("local:9000".parse::<Addr>().unwrap(), Addr::local(9000));
+        assert_eq!(" ANY:1234 ".parse::<Addr>().unwrap(), Addr::any(1234));
+        for addr in [
+            Addr::new(3, 1234),
+            Addr::any(1),
+            Addr::host(52),
+            Addr::new(0, 0),
+        ] {
+            assert_eq!(addr.to_string().parse::<Addr>().unwrap(), addr);
+        }
+        assert_eq!(Addr::new(3, 1234).to_string(), "3:1234");
+        assert_eq!(Addr::new(u32::MAX, 5005).to_string(), "any:5005");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_addresses() {
+        for s in [
+            "",
+            "3",
+            "3:",
+            ":1234",
+            "guest:1",
+            "3:1234:5",
+            "-1:2",
+            "3:99999999999",
+        ] {
+            assert!(
+                matches!(s.parse::<Addr>(), Err(VirgeError::ConfigError(_))),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[cfg(any(feature = "vsock", feature = "tokio-vsock"))]
+    #[test]
+    fn converts_to_and_from_vsock_addr() {
+        #[cfg(not(feature = "vsock"))]
+        use tokio_vsock::VsockAddr;
+        #[cfg(feature = "vsock")]
+        use vsock::VsockAddr;
+
+        let vsock: VsockAddr = Addr::new(3, 1234).into();
+        assert_eq!((vsock.cid(), vsock.port()), (3, 1234));
+        assert_eq!(Addr::from(vsock), Addr::new(3, 1234));
+    }
+}
@@ -0,0 +1,289 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 客户机代理骨架
+//!
+//! 客户机代理大多是同一个事件循环：连上宿主机服务，处理宿主机下发的命令，定期发送
+//! 心跳，断线后重连，收到停止请求后处理完当前命令再退出。[`Agent`] 提供这个循环，
+//! 应用只需注册命令：
+//!
+//! ```ignore
+//! let agent = Agent::new(ClientConfig::new(2, 5000, 4096, false), "guest.Agent")
+//!     .command("status", |_| Ok(b"ok".to_vec()))
+//!     .command("sync", |payload| sync_files(payload));
+//! let shutdown = agent.shutdown_handle();
+//! install_sigterm_handler(move || shutdown.shutdown());
+//! agent.run()?;
+//! ```
+//!
+//! 连接经 [`mux`](crate::mux) 复用，宿主机侧同样调用 `into_mux()`：
+//!
+//! - 通道 [`COMMAND_CHANNEL`]：宿主机发送 [`rpc`](crate::rpc) 编码的请求，服务名为代理的
+//!   服务名、方法名为命令名，代理按 `rpc` 的约定回复，也接受单向请求
+//! - 通道 [`HEARTBEAT_CHANNEL`]：代理连上后立即、之后每隔心跳间隔发送 `seq(8, 大端)`，
+//!   每次重连从 0 开始
+//!
+//! 命令在调用 `run()` 的线程中逐条处理。
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::*;
+
+use crate::client::{ClientConfig, VirgeClient};
+use crate::mux::{Channel, Mux};
+use crate::retry::MAX_BACKOFF;
+use crate::rpc::{self, Delivery};
+use crate::sources::{self, Clock};
+
+/// 承载命令请求与回复的通道号
+pub const COMMAND_CHANNEL: u32 = 1;
+/// 承载心跳的通道号
+pub const HEARTBEAT_CHANNEL: u32 = 0;
+/// 默认心跳间隔
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+/// 等待命令时检查停止请求的间隔
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type Command = Box<dyn FnMut(&[u8]) -> Result<Vec<u8>> + Send>;
+
+/// 请求代理或 WebSocket 桥停止，可在线程间共享（如信号处理线程）
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    /// 请求停止：代理处理完当前命令、发出已排队的回复后断开连接，`run()` 返回 `Ok`；
+    /// WebSocket 桥不再接受新连接，`serve()` 返回 `Ok`
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// 是否已请求停止
+    pub fn is_shutdown(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// 客户机代理的事件循环
+pub struct Agent {
+    config: ClientConfig,
+    service: String,
+    commands: HashMap<String, Command>,
+    heartbeat: Duration,
+    reconnect_deadline: Option<Duration>,
+    shutdown: ShutdownHandle,
+    clock: Arc<dyn Clock>,
+}
+
+impl Agent {
+    /// 以 `service` 为服务名连接 `config` 指定的宿主机服务
+    pub fn new(config: ClientConfig, service: impl Into<String>) -> Self {
+        Self {
+            config,
+            service: service.into(),
+            commands: HashMap::new(),
+            heartbeat: DEFAULT_HEARTBEAT_INTERVAL,
+            reconnect_deadline: None,
+            shutdown: ShutdownHandle::default(),
+            clock: sources::clock(),
+        }
+    }
+
+    /// 注册命令 `name`：`handler` 收到请求负载，返回回复负载；同名命令后注册的生效
+    pub fn command(
+        mut self,
+        name: impl Into<String>,
+        handler: impl FnMut(&[u8]) -> Result<Vec<u8>> + Send + 'static,
+    ) -> Self {
+        self.commands.insert(name.into(), Box::new(handler));
+        self
+    }
+
+    /// 心跳间隔，默认 [`DEFAULT_HEARTBEAT_INTERVAL`]
+    pub fn with_heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// 连续 `deadline` 连不上宿主机时 `run()` 返回 `TimedOut`；默认一直重试
+    pub fn with_reconnect_deadline(mut self, deadline: Duration) -> Self {
+        self.reconnect_deadline = Some(deadline);
+        self
+    }
+
+    /// 用于请求停止的句柄
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 运行事件循环，直到请求停止；配置错误、认证失败或超过重连期限时返回错误
+    pub fn run(mut self) -> Result<()> {
+        self.config.validate()?;
+        if self.heartbeat.is_zero() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "agent heartbeat interval must be greater than zero",
+            ));
+        }
+        let mut since = self.clock.now();
+        while !self.shutdown.is_shutdown() {
+            let mut client = VirgeClient::new(self.config.clone());
+            // 每轮最多等一个退避上限，以便及时响应停止请求
+            match client.connect_when_ready(MAX_BACKOFF) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::TimedOut => {
+                    if let Some(deadline) = self.reconnect_deadline {
+                        if self.clock.now().saturating_duration_since(since) >= deadline {
+                            return Err(Error::new(
+                                ErrorKind::TimedOut,
+                                format!("agent could not reach the host within {:?}", deadline),
+                            ));
+                        }
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+            info!("Agent {} connected", self.service);
+            let mux = client.into_mux()?;
+            let result = self.session(&mux);
+            mux.close();
+            if let Err(e) = result {
+                warn!("Agent {} lost the connection: {}", self.service, e);
+            }
+            since = self.clock.now();
+        }
+        info!("Agent {} stopped", self.service);
+        Ok(())
+    }
+
+    /// 一次连接上的循环：发送心跳、处理命令，请求停止时返回 `Ok`
+    fn session(&mut self, mux: &Mux) -> Result<()> {
+        let commands = mux.channel(COMMAND_CHANNEL)?;
+        let heartbeat = mux.channel(HEARTBEAT_CHANNEL)?;
+        let mut seq = 0u64;
+        let mut next_beat = self.clock.now();
+        while !self.shutdown.is_shutdown() {
+            let now = self.clock.now();
+            if now >= next_beat {
+                heartbeat.send(&seq.to_be_bytes())?;
+                seq += 1;
+                next_beat = now + self.heartbeat;
+            }
+            let wait = next_beat
+                .saturating_duration_since(now)
+                .min(SHUTDOWN_POLL_INTERVAL);
+            if let Some(msg) = commands.recv_timeout(wait)? {
+                self.handle(&commands, &msg)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 处理一条命令请求并回复；只有回复发送失败才返回错误
+    fn handle(&mut self, commands: &Channel, msg: &[u8]) -> Result<()> {
+        let request = match rpc::decode_request(msg) {
+            Ok(request) => request,
+            Err(e) => return commands.send(&rpc::encode_response(Err(e))),
+        };
+        let command = if request.service() != self.service {
+            Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("unknown service {:?}", request.service()),
+            ))
+        } else {
+            self.commands.get_mut(request.method()).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::Unsupported,
+                    format!("unknown command {:?}", request.method()),
+                )
+            })
+        };
+        let Some(delivery) = request.delivery() else {
+            let result = command.and_then(|command| command(request.payload()));
+            return commands.send(&rpc::encode_response(result));
+        };
+
+        if delivery == Delivery::Acked {
+            let ack = match &command {
+                Ok(_) => Ok(Vec::new()),
+                Err(e) => Err(Error::new(e.kind(), e.to_string())),
+            };
+            commands.send(&rpc::encode_response(ack))?;
+        }
+        if let Err(e) = command.and_then(|command| command(request.payload())) {
+            warn!("One-way command {} failed: {}", request.method(), e);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Agent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Agent")
+            .field("service", &self.service)
+            .field("commands", &self.commands.keys().collect::<Vec<_>>())
+            .field("heartbeat", &self.heartbeat)
+            .field("reconnect_deadline", &self.reconnect_deadline)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_rejects_invalid_config_and_honours_early_shutdown() {
+        let err = Agent::new(ClientConfig::new(3, 0, 1024, false), "guest.Agent")
+            .run()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = Agent::new(ClientConfig::default(), "guest.Agent")
+            .with_heartbeat(Duration::ZERO)
+            .run()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let agent = Agent::new(ClientConfig::default(), "guest.Agent");
+        agent.shutdown_handle().shutdown();
+        agent.run().unwrap();
+    }
+
+    #[cfg(feature = "use-xtransport")]
+    #[test]
+    fn session_serves_commands_and_heartbeats() {
+        let (host, guest) = crate::mux::testing::pair();
+        let beats = host.channel(HEARTBEAT_CHANNEL).unwrap();
+        let commands = host.channel(COMMAND_CHANNEL).unwrap();
+        let mut agent = Agent::new(ClientConfig::default(), "guest.Agent")
+            .command("echo", |payload| Ok(payload.to_vec()))
+            .with_heartbeat(Duration::from_millis(10));
+        let shutdown = agent.shutdown_handle();
+        let session = std::thread::spawn(move || agent.session(&guest));
+
+        assert_eq!(beats.recv().unwrap(), 0u64.to_be_bytes());
+        assert_eq!(beats.recv().unwrap(), 1u64.to_be_bytes());
+
+        let call = |msg: Vec<u8>| {
+            commands.send(&msg).unwrap();
+            rpc::decode_response(commands.recv().unwrap())
+        };
+        let echo = rpc::encode_request("guest.Agent", "echo", b"hi").unwrap();
+        assert_eq!(call(echo).unwrap(), b"hi");
+        let unknown = rpc::encode_request("guest.Agent", "reboot", b"").unwrap();
+        assert_eq!(call(unknown).unwrap_err().kind(), ErrorKind::Unsupported);
+        let other = rpc::encode_request("host.Other", "echo", b"").unwrap();
+        assert_eq!(call(other).unwrap_err().kind(), ErrorKind::Unsupported);
+        let oneway = rpc::encode_oneway("guest.Agent", "echo", b"x", Delivery::Acked).unwrap();
+        assert_eq!(call(oneway).unwrap(), b"");
+
+        shutdown.shutdown();
+        session.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 基于身份的授权
+//!
+//! 认证确认“对端是谁”，授权决定“对端能调用什么”。服务层在分发每个请求前调用
+//! `VirgeServer::authorize(service, method)`，由配置的 [`Authorizer`] 判断。
+
+use super::PeerIdentity;
+use std::fmt;
+
+/// 授权判断，实现需线程安全，同一实例会被所有连接共享
+pub trait Authorizer: Send + Sync + fmt::Debug {
+    /// `peer` 是否可以调用 `service` 的 `method`
+    fn authorize(&self, peer: &PeerIdentity, service: &str, method: &str) -> bool;
+}
+
+/// 规则中的调用方
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Principal {
+    /// 任意调用方
+    Any,
+    /// 指定 CID
+    Cid(u32),
+    /// 认证时使用的名称
+    Name(String),
+}
+
+impl Principal {
+    pub(crate) fn matches(&self, peer: &PeerIdentity) -> bool {
+        match self {
+            Principal::Any => true,
+            Principal::Cid(cid) => peer.cid == *cid,
+            Principal::Name(name) => !peer.name.is_empty() && peer.name == *name,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    service: String,
+    method: String,
+    principal: Principal,
+}
+
+impl Rule {
+    fn covers(&self, service: &str, method: &str) -> bool {
+        self.service == service && (self.method == "*" || self.method == method)
+    }
+}
+
+/// 按服务/方法配置的允许规则
+///
+/// 某个方法一旦有规则覆盖，就只允许规则中的调用方；没有规则覆盖的方法按默认策略处理。
+///
+/// ```ignore
+/// // 其他方法不受限制，只有 CID 7 可以调用 admin/shutdown
+/// let rules = AccessRules::allow_by_default().allow("admin", "shutdown", Principal::Cid(7));
+/// ```
+#[derive(Clone, Debug)]
+pub struct AccessRules {
+    rules: Vec<Rule>,
+    default_allow: bool,
+}
+
+impl AccessRules {
+    /// 未配置规则的方法一律拒绝
+    pub fn deny_by_default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_allow: false,
+        }
+    }
+
+    /// 未配置规则的方法一律允许
+    pub fn allow_by_default() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_allow: true,
+        }
+    }
+
+    /// 允许 `principal` 调用 `service` 的 `method`，`method` 为 `"*"` 时覆盖整个服务
+    pub fn allow(
+        mut self,
+        service: impl Into<String>,
+        method: impl Into<String>,
+        principal: Principal,
+    ) -> Self {
+        self.rules.push(Rule {
+            service: service.into(),
+            method: method.into(),
+            principal,
+        });
+        self
+    }
+}
+
+impl Authorizer for AccessRules {
+    fn authorize(&self, peer: &PeerIdentity, service: &str, method: &str) -> bool {
+        let mut covered = false;
+        for rule in self.rules.iter().filter(|r| r.covers(service, method)) {
+            if rule.principal.matches(peer) {
+                return true;
+            }
+            covered = true;
+        }
+        !covered && self.default_allow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(cid: u32, name: &str) -> PeerIdentity {
+        PeerIdentity {
+            cid,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_listed_cid_may_call_guarded_method() {
+        let rules = AccessRules::allow_by_default().allow("admin", "shutdown", Principal::Cid(7));
+        assert!(rules.authorize(&peer(7, ""), "admin", "shutdown"));
+        assert!(!rules.authorize(&peer(8, ""), "admin", "shutdown"));
+        assert!(rules.authorize(&peer(8, ""), "admin", "status"));
+        assert!(rules.authorize(&peer(8, ""), "files", "read"));
+    }
+
+    #[test]
+    fn deny_by_default_with_service_wildcard() {
+        let rules = AccessRules::deny_by_default()
+            .allow("files", "*", Principal::Name("agent".to_string()))
+            .allow("health", "ping", Principal::Any);
+        assert!(rules.authorize(&peer(3, "agent"), "files", "write"));
+        assert!(!rules.authorize(&peer(3, "other"), "files", "write"));
+        assert!(rules.authorize(&peer(9, ""), "health", "ping"));
+        assert!(!rules.authorize(&peer(3, "agent"), "admin", "shutdown"));
+    }
+
+    #[test]
+    fn name_rule_needs_authenticated_peer() {
+        let rules = AccessRules::deny_by_default().allow("x", "y", Principal::Name(String::new()));
+        assert!(!rules.authorize(&peer(3, ""), "x", "y"));
+    }
+}
@@ -0,0 +1,716 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 连接认证
+//!
+//! 基于预共享令牌的双向质询-应答握手，在传输层建立后、交给应用之前完成：
+//!
+//! 1. 服务端发送 `MAGIC | flags | server_nonce`
+//! 2. 客户端回复 `MAGIC | client_nonce | HMAC(secret, client 标签, flags, nonces, name) | name`
+//! 3. 服务端校验后回复 `0 | HMAC(secret, server 标签, flags, nonces, name)`，失败时回复 `1 | 原因`
+//! 4. 客户端校验服务端的证明，确认对端同样持有令牌
+//!
+//! `flags` 表示服务端是否要求加密，它参与双方的证明，无法被中间人降级；
+//! 启用加密时双方由同一上下文导出会话密钥，见 [`secure`]。
+//! 令牌本身不经过连接传输。`ServerManager::accept()` 只返回握手成功的连接，
+//! 并通过 `VirgeServer::peer_identity()` 暴露对端身份。自定义传输可用
+//! `TokenAuth::authenticate()` / `TokenCredential::authenticate()` 完成同样的握手（不支持加密）。
+//!
+//! 握手需要 `auth` 特性，加密另需 `secure` 特性，两者都在默认特性中。未启用时配置类型仍然可用，
+//! 但配置了认证（或加密）的客户端与服务端在校验配置时返回 `ConfigError`，
+//! `authenticate()` 返回 `Unsupported`。
+
+mod authorizer;
+pub(crate) mod secure;
+pub use authorizer::{AccessRules, Authorizer, Principal};
+pub use secure::RekeyPolicy;
+
+use crate::transport::Transport;
+#[cfg(feature = "auth")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "auth")]
+use log::*;
+use secure::SecureChannel;
+#[cfg(feature = "auth")]
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+#[cfg(feature = "auth")]
+type HmacSha256 = Hmac<Sha256>;
+
+#[cfg(feature = "auth")]
+const MAGIC: &[u8; 4] = b"VGA1";
+#[cfg(feature = "auth")]
+const NONCE_LEN: usize = 32;
+#[cfg(feature = "auth")]
+const MAC_LEN: usize = 32;
+#[cfg(feature = "auth")]
+const CLIENT_LABEL: &[u8] = b"virga-auth-client";
+#[cfg(feature = "auth")]
+const SERVER_LABEL: &[u8] = b"virga-auth-server";
+#[cfg(feature = "secure")]
+const SESSION_LABEL: &[u8] = b"virga-session";
+#[cfg(feature = "auth")]
+const FLAG_ENCRYPT: u8 = 1;
+#[cfg(feature = "auth")]
+const STATUS_OK: u8 = 0;
+#[cfg(feature = "auth")]
+const STATUS_REJECTED: u8 = 1;
+/// 客户端名称的最大长度
+pub const MAX_NAME_LEN: usize = 255;
+
+/// 握手默认超时：对端在此时间内未完成握手则放弃该连接
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 已认证的对端身份
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PeerIdentity {
+    /// 对端 CID（来自 vsock，由虚拟化层保证）
+    pub cid: u32,
+    /// 客户端在握手中证明的名称
+    pub name: String,
+}
+
+impl fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@cid {}", self.name, self.cid)
+    }
+}
+
+/// 服务端认证配置：名称到令牌的映射
+#[derive(Clone)]
+pub struct TokenAuth {
+    tokens: HashMap<String, Vec<u8>>,
+    timeout: Duration,
+    encryption: Option<RekeyPolicy>,
+}
+
+impl TokenAuth {
+    pub fn new() -> Self {
+        Self {
+            tokens: HashMap::new(),
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            encryption: None,
+        }
+    }
+
+    /// 允许名为 `name`、持有 `secret` 的客户端接入
+    pub fn with_token(mut self, name: impl Into<String>, secret: impl AsRef<[u8]>) -> Self {
+        self.tokens.insert(name.into(), secret.as_ref().to_vec());
+        self
+    }
+
+    /// 设置握手超时
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 认证后加密连接上的所有消息，并按 `rekey` 自动换钥
+    pub fn with_encryption(mut self, rekey: RekeyPolicy) -> Self {
+        self.encryption = Some(rekey);
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    #[cfg(feature = "sync")]
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        check_features(self.encryption.is_some())?;
+        if self.tokens.is_empty() {
+            return Err(crate::VirgeError::ConfigError(
+                "auth enabled but no tokens configured".to_string(),
+            ));
+        }
+        if let Some(name) = self.tokens.keys().find(|n| !valid_name(n)) {
+            return Err(crate::VirgeError::ConfigError(format!(
+                "invalid auth name {:?}",
+                name
+            )));
+        }
+        if self.tokens.values().any(|s| s.is_empty()) || self.timeout.is_zero() {
+            return Err(crate::VirgeError::ConfigError(
+                "auth tokens must be non-empty and timeout non-zero".to_string(),
+            ));
+        }
+        if let Some(rekey) = &self.encryption {
+            rekey.validate()?;
+        }
+        Ok(())
+    }
+
+    /// 服务端握手，成功时返回对端身份与（启用加密时的）加密通道；
+    /// 失败时已告知对端，调用方丢弃连接即可
+    #[cfg(feature = "auth")]
+    pub(crate) fn accept(
+        &self,
+        chan: &mut impl MessageChannel,
+        cid: u32,
+    ) -> Result<(PeerIdentity, Option<SecureChannel>)> {
+        let flags = if self.encryption.is_some() {
+            FLAG_ENCRYPT
+        } else {
+            0
+        };
+        let server_nonce = random_nonce()?;
+        let mut hello = MAGIC.to_vec();
+        hello.push(flags);
+        hello.extend_from_slice(&server_nonce);
+        chan.send_msg(&hello)?;
+
+        let msg = chan.recv_msg()?;
+        let header = MAGIC.len() + NONCE_LEN + MAC_LEN;
+        if msg.len() <= header || &msg[..MAGIC.len()] != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "malformed auth response",
+            ));
+        }
+        let name = String::from_utf8_lossy(&msg[header..]).into_owned();
+        let transcript = Transcript {
+            flags,
+            server_nonce: &server_nonce,
+            client_nonce: &msg[MAGIC.len()..MAGIC.len() + NONCE_LEN],
+            name: &name,
+        };
+        let proof = &msg[MAGIC.len() + NONCE_LEN..header];
+
+        let verified = self.tokens.get(&name).is_some_and(|secret| {
+            transcript
+                .mac(secret, CLIENT_LABEL)
+                .verify_slice(proof)
+                .is_ok()
+        });
+        if !verified {
+            warn!("Authentication failed for {:?} from cid={}", name, cid);
+            let mut reply = vec![STATUS_REJECTED];
+            reply.extend_from_slice(b"authentication failed");
+            // 对端可能已断开，拒绝结果以本端错误为准
+            let _ = chan.send_msg(&reply);
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("authentication failed for cid {}", cid),
+            ));
+        }
+
+        let secret = &self.tokens[&name];
+        let mut reply = vec![STATUS_OK];
+        reply.extend_from_slice(&transcript.mac(secret, SERVER_LABEL).finalize().into_bytes());
+        chan.send_msg(&reply)?;
+
+        let secure = self
+            .encryption
+            .map(|rekey| transcript.secure_channel(secret, false, rekey))
+            .transpose()?;
+        let peer = PeerIdentity { cid, name };
+        info!(
+            "Authenticated {}{}",
+            peer,
+            if secure.is_some() { " (encrypted)" } else { "" }
+        );
+        Ok((peer, secure))
+    }
+
+    #[cfg(not(feature = "auth"))]
+    pub(crate) fn accept(
+        &self,
+        _chan: &mut impl MessageChannel,
+        _cid: u32,
+    ) -> Result<(PeerIdentity, Option<SecureChannel>)> {
+        Err(auth_unavailable())
+    }
+}
+
+impl TokenAuth {
+    /// 在自定义传输上完成服务端握手，返回对端身份。`cid` 是传输层给出的对端 CID；
+    /// 加密需要内置传输的配合，配置了 `with_encryption()` 时返回 `Unsupported`
+    pub fn authenticate<T: Transport>(&self, transport: &mut T, cid: u32) -> Result<PeerIdentity> {
+        if self.encryption.is_some() {
+            return Err(encryption_unsupported());
+        }
+        with_timeout(transport, self.timeout, |t| self.accept(t, cid)).map(|(peer, _)| peer)
+    }
+}
+
+impl Default for TokenAuth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TokenAuth {
+    /// 只输出名称，不输出令牌
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<_> = self.tokens.keys().collect();
+        names.sort();
+        f.debug_struct("TokenAuth")
+            .field("names", &names)
+            .field("timeout", &self.timeout)
+            .field("encryption", &self.encryption)
+            .finish()
+    }
+}
+
+/// 客户端凭据
+#[derive(Clone)]
+pub struct TokenCredential {
+    name: String,
+    #[cfg_attr(not(any(feature = "auth", feature = "sync")), allow(dead_code))]
+    secret: Vec<u8>,
+    timeout: Duration,
+    encryption: Option<RekeyPolicy>,
+}
+
+impl TokenCredential {
+    pub fn new(name: impl Into<String>, secret: impl AsRef<[u8]>) -> Self {
+        Self {
+            name: name.into(),
+            secret: secret.as_ref().to_vec(),
+            timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            encryption: None,
+        }
+    }
+
+    /// 设置握手超时
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 要求加密连接，服务端未启用加密时 `connect()` 失败；
+    /// 未设置时由服务端决定，服务端启用加密时使用默认换钥阈值
+    pub fn with_encryption(mut self, rekey: RekeyPolicy) -> Self {
+        self.encryption = Some(rekey);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// 在自定义传输上完成客户端握手，见 [`TokenAuth::authenticate()`]；
+    /// 服务端启用加密时返回 `Unsupported`
+    pub fn authenticate<T: Transport>(&self, transport: &mut T) -> Result<()> {
+        if self.encryption.is_some() {
+            return Err(encryption_unsupported());
+        }
+        match with_timeout(transport, self.timeout, |t| self.connect(t))? {
+            Some(_) => Err(encryption_unsupported()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "sync")]
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        check_features(self.encryption.is_some())?;
+        if !valid_name(&self.name) || self.secret.is_empty() || self.timeout.is_zero() {
+            return Err(crate::VirgeError::ConfigError(format!(
+                "invalid auth credential for {:?}",
+                self.name
+            )));
+        }
+        if let Some(rekey) = &self.encryption {
+            rekey.validate()?;
+        }
+        Ok(())
+    }
+
+    /// 客户端握手，同时校验服务端持有相同令牌；服务端启用加密时返回加密通道
+    #[cfg(feature = "auth")]
+    pub(crate) fn connect(&self, chan: &mut impl MessageChannel) -> Result<Option<SecureChannel>> {
+        let hello = chan.recv_msg()?;
+        if hello.len() != MAGIC.len() + 1 + NONCE_LEN || &hello[..MAGIC.len()] != MAGIC {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "server did not start authentication",
+            ));
+        }
+        let flags = hello[MAGIC.len()];
+        let encrypt = flags & FLAG_ENCRYPT != 0;
+        if self.encryption.is_some() && !encrypt {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "server does not offer encryption",
+            ));
+        }
+        // 在发出证明前拒绝，服务端不会把连接当作已认证
+        if encrypt && !cfg!(feature = "secure") {
+            return Err(encryption_unavailable());
+        }
+        let client_nonce = random_nonce()?;
+        let transcript = Transcript {
+            flags,
+            server_nonce: &hello[MAGIC.len() + 1..],
+            client_nonce: &client_nonce,
+            name: &self.name,
+        };
+
+        let mut msg = MAGIC.to_vec();
+        msg.extend_from_slice(&client_nonce);
+        msg.extend_from_slice(
+            &transcript
+                .mac(&self.secret, CLIENT_LABEL)
+                .finalize()
+                .into_bytes(),
+        );
+        msg.extend_from_slice(self.name.as_bytes());
+        chan.send_msg(&msg)?;
+
+        let reply = chan.recv_msg()?;
+        match reply.split_first() {
+            Some((&STATUS_OK, proof))
+                if transcript
+                    .mac(&self.secret, SERVER_LABEL)
+                    .verify_slice(proof)
+                    .is_ok() =>
+            {
+                encrypt
+                    .then(|| {
+                        transcript.secure_channel(
+                            &self.secret,
+                            true,
+                            self.encryption.unwrap_or_default(),
+                        )
+                    })
+                    .transpose()
+            }
+            Some((&STATUS_OK, _)) => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "server failed to prove token",
+            )),
+            Some((_, reason)) => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("rejected by server: {}", String::from_utf8_lossy(reason)),
+            )),
+            None => Err(Error::new(ErrorKind::InvalidData, "empty auth reply")),
+        }
+    }
+
+    #[cfg(not(feature = "auth"))]
+    pub(crate) fn connect(&self, _chan: &mut impl MessageChannel) -> Result<Option<SecureChannel>> {
+        Err(auth_unavailable())
+    }
+}
+
+impl fmt::Debug for TokenCredential {
+    /// 不输出令牌
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenCredential")
+            .field("name", &self.name)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// 握手所用的消息通道，由传输层实现
+#[cfg_attr(not(any(feature = "auth", feature = "sync")), allow(dead_code))]
+pub(crate) trait MessageChannel {
+    fn send_msg(&mut self, data: &[u8]) -> Result<()>;
+    fn recv_msg(&mut self) -> Result<Vec<u8>>;
+}
+
+impl<T: Transport + ?Sized> MessageChannel for T {
+    fn send_msg(&mut self, data: &[u8]) -> Result<()> {
+        self.send(data)?;
+        self.flush().map_err(Error::from)
+    }
+
+    fn recv_msg(&mut self) -> Result<Vec<u8>> {
+        self.recv().map_err(Error::from)
+    }
+}
+
+/// 在握手期间为连接设置空闲超时，结束后恢复为一直等待
+fn with_timeout<T: Transport + ?Sized, R>(
+    transport: &mut T,
+    timeout: Duration,
+    handshake: impl FnOnce(&mut T) -> Result<R>,
+) -> Result<R> {
+    transport.set_idle_timeout(Some(timeout))?;
+    let result = handshake(transport)?;
+    transport.set_idle_timeout(None)?;
+    Ok(result)
+}
+
+fn encryption_unsupported() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "encrypted sessions need a built-in transport",
+    )
+}
+
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+fn auth_unavailable() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "token authentication needs the auth feature",
+    )
+}
+
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+fn encryption_unavailable() -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        "encrypted sessions need the secure feature",
+    )
+}
+
+/// 认证或加密所需的特性未启用时，配置校验即返回错误，而不是等到握手
+#[cfg(feature = "sync")]
+fn check_features(encryption: bool) -> crate::Result<()> {
+    let missing = if !cfg!(feature = "auth") {
+        auth_unavailable()
+    } else if encryption && !cfg!(feature = "secure") {
+        encryption_unavailable()
+    } else {
+        return Ok(());
+    };
+    Err(crate::VirgeError::ConfigError(missing.to_string()))
+}
+
+#[cfg(feature = "sync")]
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_NAME_LEN
+}
+
+#[cfg(feature = "auth")]
+fn random_nonce() -> Result<[u8; NONCE_LEN]> {
+    let mut nonce = [0u8; NONCE_LEN];
+    crate::sources::os_random(&mut nonce)?;
+    Ok(nonce)
+}
+
+/// 握手双方都会校验的上下文，所有证明与会话密钥都绑定在它上面
+#[cfg(feature = "auth")]
+struct Transcript<'a> {
+    flags: u8,
+    server_nonce: &'a [u8],
+    client_nonce: &'a [u8],
+    name: &'a str,
+}
+
+#[cfg(feature = "auth")]
+impl Transcript<'_> {
+    fn mac(&self, secret: &[u8], label: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+        for part in self.parts(label) {
+            mac.update(part);
+        }
+        mac
+    }
+
+    /// 由会话密钥建立加密通道，未启用 `secure` 特性时返回 `Unsupported`
+    #[cfg(feature = "secure")]
+    fn secure_channel(
+        &self,
+        secret: &[u8],
+        is_client: bool,
+        rekey: RekeyPolicy,
+    ) -> Result<SecureChannel> {
+        let session_key = secure::derive(secret, &self.parts(SESSION_LABEL));
+        Ok(SecureChannel::new(&session_key, is_client, rekey))
+    }
+
+    #[cfg(not(feature = "secure"))]
+    fn secure_channel(&self, _: &[u8], _: bool, _: RekeyPolicy) -> Result<SecureChannel> {
+        Err(encryption_unavailable())
+    }
+
+    fn parts<'a>(&'a self, label: &'a [u8]) -> [&'a [u8]; 5] {
+        [
+            label,
+            std::slice::from_ref(&self.flags),
+            self.server_nonce,
+            self.client_nonce,
+            self.name.as_bytes(),
+        ]
+    }
+}
+
+#[cfg(all(test, feature = "auth"))]
+mod tests {
+    use super::*;
+    use crate::transport::testing::pipe_pair;
+    use std::thread;
+
+    type Outcome = (
+        Result<(PeerIdentity, Option<SecureChannel>)>,
+        Result<Option<SecureChannel>>,
+    );
+
+    fn handshake(auth: TokenAuth, cred: TokenCredential) -> Outcome {
+        let (mut server, mut client) = pipe_pair();
+        let handle = thread::spawn(move || {
+            let result = auth.accept(&mut server, 7);
+            drop(server);
+            result
+        });
+        let client_result = cred.connect(&mut client);
+        // 客户端提前失败时，服务端需要看到连接关闭才会返回
+        drop(client);
+        (handle.join().unwrap(), client_result)
+    }
+
+    #[test]
+    fn matching_token_authenticates_both_sides() {
+        let auth = TokenAuth::new()
+            .with_token("agent", b"s3cret")
+            .with_token("other", b"x");
+        let (server, client) = handshake(auth, TokenCredential::new("agent", b"s3cret"));
+        let (peer, secure) = server.unwrap();
+        assert_eq!(
+            peer,
+            PeerIdentity {
+                cid: 7,
+                name: "agent".to_string()
+            }
+        );
+        assert!(secure.is_none());
+        assert!(client.unwrap().is_none());
+    }
+
+    #[test]
+    fn custom_transports_authenticate_without_encryption() {
+        let auth = TokenAuth::new().with_token("agent", b"s3cret");
+        let (mut server, mut client) = pipe_pair();
+        let handle = thread::spawn(move || auth.authenticate(&mut server, 7));
+        TokenCredential::new("agent", b"s3cret")
+            .authenticate(&mut client)
+            .unwrap();
+        assert_eq!(handle.join().unwrap().unwrap().name, "agent");
+
+        let encrypted = TokenAuth::new()
+            .with_token("agent", b"s3cret")
+            .with_encryption(RekeyPolicy::default());
+        let err = encrypted.authenticate(&mut pipe_pair().0, 7).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+
+    #[cfg(all(feature = "sync", feature = "secure"))]
+    #[test]
+    fn encryption_is_negotiated_by_server() {
+        let auth = TokenAuth::new()
+            .with_token("agent", b"s3cret")
+            .with_encryption(RekeyPolicy::default());
+        let (server, client) = handshake(auth, TokenCredential::new("agent", b"s3cret"));
+        let mut server = server.unwrap().1.unwrap();
+        let mut client = client.unwrap().unwrap();
+        let mut frame = client.seal(b"ping").unwrap();
+        assert!(server.open_in_place(&mut frame).unwrap());
+        assert_eq!(frame, b"ping");
+    }
+
+    #[test]
+    fn client_requiring_encryption_rejects_plain_server() {
+        let auth = TokenAuth::new().with_token("agent", b"s3cret");
+        let cred = TokenCredential::new("agent", b"s3cret").with_encryption(RekeyPolicy::default());
+        let (server, client) = handshake(auth, cred);
+        assert!(server.is_err());
+        let err = client.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("encryption"));
+    }
+
+    #[test]
+    fn flags_are_bound_to_proofs() {
+        let transcript = |flags| Transcript {
+            flags,
+            server_nonce: &[1; NONCE_LEN],
+            client_nonce: &[2; NONCE_LEN],
+            name: "agent",
+        };
+        let plain = transcript(0)
+            .mac(b"k", CLIENT_LABEL)
+            .finalize()
+            .into_bytes();
+        let encrypted = transcript(FLAG_ENCRYPT)
+            .mac(b"k", CLIENT_LABEL)
+            .finalize()
+            .into_bytes();
+        assert_ne!(plain, encrypted);
+    }
+
+    #[test]
+    fn wrong_secret_is_rejected() {
+        let auth = TokenAuth::new().with_token("agent", b"s3cret");
+        let (server, client) = handshake(auth, TokenCredential::new("agent", b"guess"));
+        assert_eq!(server.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        let err = client.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(err.to_string().contains("rejected by server"));
+    }
+
+    #[test]
+    fn unknown_name_is_rejected() {
+        let auth = TokenAuth::new().with_token("agent", b"s3cret");
+        let (server, _) = handshake(auth, TokenCredential::new("intruder", b"s3cret"));
+        assert_eq!(server.unwrap_err().kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn client_detects_server_without_token() {
+        // 服务端不知道令牌却声称成功，客户端必须拒绝
+        let (mut server, mut client) = pipe_pair();
+        let handle = thread::spawn(move || {
+            let mut hello = MAGIC.to_vec();
+            hello.push(0);
+            hello.extend_from_slice(&[0u8; NONCE_LEN]);
+            server.send_msg(&hello).unwrap();
+            server.recv_msg().unwrap();
+            let mut reply = vec![STATUS_OK];
+            reply.extend_from_slice(&[0u8; MAC_LEN]);
+            server.send_msg(&reply).unwrap();
+        });
+        let err = TokenCredential::new("agent", b"s3cret")
+            .connect(&mut client)
+            .unwrap_err();
+        handle.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn malformed_response_is_invalid_data() {
+        let (mut server, mut client) = pipe_pair();
+        let handle = thread::spawn(move || {
+            client.recv_msg().unwrap();
+            client.send_msg(b"junk").unwrap();
+        });
+        let auth = TokenAuth::new().with_token("agent", b"s3cret");
+        let err = auth.accept(&mut server, 7).unwrap_err();
+        handle.join().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn debug_hides_secrets() {
+        let auth = TokenAuth::new().with_token("agent", b"s3cret");
+        let cred = TokenCredential::new("agent", b"s3cret");
+        assert!(!format!("{:?}", auth).contains("s3cret"));
+        assert!(!format!("{:?}", cred).contains("115"));
+        assert!(format!("{:?}", cred).contains("agent"));
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn validate_rejects_empty() {
+        assert!(TokenAuth::new().validate().is_err());
+        assert!(TokenAuth::new().with_token("", b"x").validate().is_err());
+        assert!(TokenAuth::new().with_token("a", b"").validate().is_err());
+        assert!(TokenCredential::new("a", b"").validate().is_err());
+        assert!(TokenCredential::new("a", b"x")
+            .with_timeout(Duration::ZERO)
+            .validate()
+            .is_err());
+        assert!(TokenCredential::new("a", b"x").validate().is_ok());
+    }
+}
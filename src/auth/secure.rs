@@ -0,0 +1,526 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 认证后的加密通道
+//!
+//! 握手双方由令牌与两端随机数导出会话密钥，再按方向各导出一把
+//! ChaCha20-Poly1305 密钥。每条消息编码为：
+//!
+//! ```text
+//! type (1) | seq (8, 大端) | 密文 | tag (16)
+//! ```
+//!
+//! `type`/`seq` 作为附加认证数据，`seq` 同时构成 nonce。发送方在本方向累计
+//! 字节数或密钥使用时长达到 [`RekeyPolicy`] 的阈值后，先用旧密钥发送一个
+//! `KEY_UPDATE` 控制帧，再把发送密钥推进为 `HMAC(旧密钥, "virga-rekey")`；
+//! 接收方解开该帧后同步推进接收密钥。旧密钥推进后即被丢弃，泄露某一时刻的
+//! 密钥无法解密之前的流量。
+//!
+//! 防重放：接收方只接受序号恰为下一个期望值的帧。同一密钥下重放的帧序号过小，
+//! 换钥前的帧无法用新密钥通过校验，其他连接的帧因会话密钥不同同样无法通过。
+//!
+//! 按消息压缩时帧类型为 `DATA_COMPRESSED`，压缩标记与数据一起受认证保护。
+
+#[cfg(all(feature = "sync", feature = "secure"))]
+use chacha20poly1305::aead::AeadInPlace;
+#[cfg(feature = "secure")]
+use chacha20poly1305::aead::KeyInit;
+#[cfg(feature = "secure")]
+use chacha20poly1305::{ChaCha20Poly1305, Key};
+#[cfg(all(feature = "sync", feature = "secure"))]
+use chacha20poly1305::{Nonce, Tag};
+#[cfg(feature = "secure")]
+use hmac::{Hmac, Mac};
+#[cfg(all(feature = "sync", feature = "secure"))]
+use log::*;
+#[cfg(feature = "secure")]
+use sha2::Sha256;
+#[cfg(feature = "secure")]
+use std::fmt;
+#[cfg(all(feature = "sync", feature = "secure"))]
+use std::io::{Error, ErrorKind, Result};
+#[cfg(feature = "secure")]
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "secure")]
+use std::time::Instant;
+
+#[cfg(feature = "secure")]
+use crate::sources::{self, Clock};
+#[cfg(feature = "secure")]
+use crate::transport::MessageKind;
+
+#[cfg(all(feature = "sync", feature = "secure"))]
+const FRAME_DATA: u8 = 0;
+#[cfg(all(feature = "sync", feature = "secure"))]
+const FRAME_KEY_UPDATE: u8 = 1;
+/// 与 `FRAME_DATA` 相同，但明文为 zstd 压缩后的消息
+#[cfg(all(feature = "sync", feature = "secure"))]
+const FRAME_DATA_COMPRESSED: u8 = 2;
+/// 与 `FRAME_DATA` 相同，但明文为合批的多条消息
+#[cfg(all(feature = "sync", feature = "secure"))]
+const FRAME_DATA_BATCH: u8 = 3;
+#[cfg(all(feature = "sync", feature = "secure"))]
+const HEADER_LEN: usize = 1 + 8;
+#[cfg(all(feature = "sync", feature = "secure"))]
+const TAG_LEN: usize = 16;
+#[cfg(all(feature = "sync", feature = "secure"))]
+const REKEY_LABEL: &[u8] = b"virga-rekey";
+#[cfg(feature = "secure")]
+const CLIENT_TO_SERVER: &[u8] = b"virga-c2s";
+#[cfg(feature = "secure")]
+const SERVER_TO_CLIENT: &[u8] = b"virga-s2c";
+
+/// 自动换钥阈值，任一条件满足即换钥
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    /// 同一密钥最多加密的字节数
+    pub bytes: u64,
+    /// 同一密钥最长使用时间
+    pub interval: Duration,
+}
+
+impl Default for RekeyPolicy {
+    fn default() -> Self {
+        Self {
+            bytes: 1 << 30,
+            interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl RekeyPolicy {
+    #[cfg(feature = "sync")]
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        if self.bytes == 0 || self.interval.is_zero() {
+            return Err(crate::VirgeError::ConfigError(
+                "rekey thresholds must be greater than zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 单个方向的密钥状态
+#[cfg(feature = "secure")]
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+struct Direction {
+    key: [u8; 32],
+    cipher: ChaCha20Poly1305,
+    seq: u64,
+    bytes: u64,
+    since: Instant,
+}
+
+#[cfg(feature = "secure")]
+impl Direction {
+    fn new(key: [u8; 32], since: Instant) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            key,
+            seq: 0,
+            bytes: 0,
+            since,
+        }
+    }
+
+    #[cfg(feature = "sync")]
+    fn ratchet(&mut self, now: Instant) {
+        *self = Self::new(derive(&self.key, &[REKEY_LABEL]), now);
+    }
+}
+
+/// 一条连接的加密状态，由握手建立后交给传输层
+#[cfg(feature = "secure")]
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+pub(crate) struct SecureChannel {
+    send: Direction,
+    recv: Direction,
+    rekey: RekeyPolicy,
+    rekeys: u64,
+    last_kind: MessageKind,
+    clock: Arc<dyn Clock>,
+}
+
+#[cfg(feature = "secure")]
+impl fmt::Debug for SecureChannel {
+    /// 不输出密钥
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecureChannel")
+            .field("rekey", &self.rekey)
+            .field("rekeys", &self.rekeys)
+            .finish()
+    }
+}
+
+#[cfg(feature = "secure")]
+impl SecureChannel {
+    /// 由会话密钥导出双向密钥；`is_client` 决定本端使用哪个方向发送
+    pub(crate) fn new(session_key: &[u8], is_client: bool, rekey: RekeyPolicy) -> Self {
+        Self::with_clock(session_key, is_client, rekey, sources::clock())
+    }
+
+    /// 以 `clock` 计算换钥间隔
+    pub(crate) fn with_clock(
+        session_key: &[u8],
+        is_client: bool,
+        rekey: RekeyPolicy,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        let now = clock.now();
+        let c2s = Direction::new(derive(session_key, &[CLIENT_TO_SERVER]), now);
+        let s2c = Direction::new(derive(session_key, &[SERVER_TO_CLIENT]), now);
+        let (send, recv) = if is_client { (c2s, s2c) } else { (s2c, c2s) };
+        Self {
+            send,
+            recv,
+            rekey,
+            rekeys: 0,
+            last_kind: MessageKind::Plain,
+            clock,
+        }
+    }
+}
+
+#[cfg(all(feature = "sync", feature = "secure"))]
+impl SecureChannel {
+    /// 发送方向已完成的换钥次数
+    pub(crate) fn rekeys(&self) -> u64 {
+        self.rekeys
+    }
+
+    /// 达到换钥阈值时返回需先发送的 `KEY_UPDATE` 帧，并推进发送密钥
+    pub(crate) fn key_update_due(&mut self) -> Result<Option<Vec<u8>>> {
+        let now = self.clock.now();
+        let due = self.send.bytes >= self.rekey.bytes
+            || now.saturating_duration_since(self.send.since) >= self.rekey.interval
+            || self.send.seq == u64::MAX;
+        if !due {
+            return Ok(None);
+        }
+        let frame = self.seal_frame(FRAME_KEY_UPDATE, &[])?;
+        self.send.ratchet(now);
+        self.rekeys += 1;
+        debug!(
+            "Secure channel rotated send key ({} rotations)",
+            self.rekeys
+        );
+        Ok(Some(frame))
+    }
+
+    /// 加密一条消息
+    #[cfg(test)]
+    pub(crate) fn seal(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.seal_as(data, MessageKind::Plain)
+    }
+
+    /// 加密一条消息，内容类型记在帧类型中，接收方解密后据此解压或拆批
+    pub(crate) fn seal_as(&mut self, data: &[u8], kind: MessageKind) -> Result<Vec<u8>> {
+        let frame_type = match kind {
+            MessageKind::Plain => FRAME_DATA,
+            MessageKind::Compressed => FRAME_DATA_COMPRESSED,
+            MessageKind::Batch => FRAME_DATA_BATCH,
+        };
+        self.seal_frame(frame_type, data)
+    }
+
+    /// 最近一次解开的数据帧的内容类型
+    pub(crate) fn last_kind(&self) -> MessageKind {
+        self.last_kind
+    }
+
+    fn seal_frame(&mut self, frame_type: u8, data: &[u8]) -> Result<Vec<u8>> {
+        let seq = self.send.seq;
+        let mut frame = Vec::with_capacity(HEADER_LEN + data.len() + TAG_LEN);
+        frame.push(frame_type);
+        frame.extend_from_slice(&seq.to_be_bytes());
+        frame.extend_from_slice(data);
+        let (aad, body) = frame.split_at_mut(HEADER_LEN);
+        let tag = self
+            .send
+            .cipher
+            .encrypt_in_place_detached(&nonce(seq), aad, body)
+            .map_err(|_| Error::other("secure channel encryption failed"))?;
+        frame.extend_from_slice(&tag);
+        self.send.seq += 1;
+        self.send.bytes += data.len() as u64;
+        Ok(frame)
+    }
+
+    /// 原地解密 `frame`；是数据帧时 `frame` 变为明文并返回 true，
+    /// 是控制帧时处理后返回 false，调用方应继续接收下一帧
+    pub(crate) fn open_in_place(&mut self, frame: &mut Vec<u8>) -> Result<bool> {
+        if frame.len() < HEADER_LEN + TAG_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "secure frame too short"));
+        }
+        let frame_type = frame[0];
+        let seq = u64::from_be_bytes(frame[1..HEADER_LEN].try_into().unwrap());
+        // 底层流可靠有序，合法帧的序号必然连续；更小的是重放，更大的是被删除或重排
+        if seq != self.recv.seq {
+            warn!(
+                "Rejected secure frame with seq {} (expected {})",
+                seq, self.recv.seq
+            );
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} secure frame: seq {}, expected {}",
+                    if seq < self.recv.seq {
+                        "replayed"
+                    } else {
+                        "out-of-order"
+                    },
+                    seq,
+                    self.recv.seq
+                ),
+            ));
+        }
+        let tag_at = frame.len() - TAG_LEN;
+        let tag = *Tag::from_slice(&frame[tag_at..]);
+        let (aad, body) = frame[..tag_at].split_at_mut(HEADER_LEN);
+        self.recv
+            .cipher
+            .decrypt_in_place_detached(&nonce(seq), aad, body, &tag)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "secure frame failed to verify"))?;
+        self.recv.seq += 1;
+
+        match frame_type {
+            FRAME_DATA | FRAME_DATA_COMPRESSED | FRAME_DATA_BATCH => {
+                frame.truncate(tag_at);
+                frame.drain(..HEADER_LEN);
+                self.last_kind = match frame_type {
+                    FRAME_DATA_COMPRESSED => MessageKind::Compressed,
+                    FRAME_DATA_BATCH => MessageKind::Batch,
+                    _ => MessageKind::Plain,
+                };
+                Ok(true)
+            }
+            FRAME_KEY_UPDATE => {
+                self.recv.ratchet(self.clock.now());
+                debug!("Secure channel rotated receive key");
+                Ok(false)
+            }
+            other => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown secure frame type {}", other),
+            )),
+        }
+    }
+}
+
+#[cfg(all(feature = "sync", feature = "secure"))]
+fn nonce(seq: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&seq.to_be_bytes());
+    Nonce::from(nonce)
+}
+
+/// 未启用 `secure` 特性时没有加密通道：握手遇到加密要求即返回 `Unsupported`，
+/// 传输层持有的 `Option<SecureChannel>` 总是 `None`
+#[cfg(not(feature = "secure"))]
+#[derive(Debug)]
+pub(crate) enum SecureChannel {}
+
+#[cfg(all(feature = "sync", not(feature = "secure")))]
+impl SecureChannel {
+    pub(crate) fn rekeys(&self) -> u64 {
+        match *self {}
+    }
+
+    pub(crate) fn key_update_due(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        match *self {}
+    }
+
+    pub(crate) fn seal_as(
+        &mut self,
+        _data: &[u8],
+        _kind: crate::transport::MessageKind,
+    ) -> std::io::Result<Vec<u8>> {
+        match *self {}
+    }
+
+    pub(crate) fn last_kind(&self) -> crate::transport::MessageKind {
+        match *self {}
+    }
+
+    pub(crate) fn open_in_place(&mut self, _frame: &mut Vec<u8>) -> std::io::Result<bool> {
+        match *self {}
+    }
+}
+
+/// `HMAC(key, parts...)`，用于导出各级密钥
+#[cfg(feature = "secure")]
+pub(crate) fn derive(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(all(test, feature = "sync", feature = "secure"))]
+mod tests {
+    use super::*;
+    use crate::sources::ManualClock;
+
+    fn pair(rekey: RekeyPolicy) -> (SecureChannel, SecureChannel) {
+        (
+            SecureChannel::new(b"session", true, rekey),
+            SecureChannel::new(b"session", false, rekey),
+        )
+    }
+
+    /// 模拟发送端：必要时先发 KEY_UPDATE 帧
+    fn send(from: &mut SecureChannel, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        frames.extend(from.key_update_due().unwrap());
+        frames.push(from.seal(data).unwrap());
+        frames
+    }
+
+    fn recv(to: &mut SecureChannel, frames: Vec<Vec<u8>>) -> Vec<u8> {
+        for mut frame in frames {
+            if to.open_in_place(&mut frame).unwrap() {
+                return frame;
+            }
+        }
+        panic!("no data frame");
+    }
+
+    #[test]
+    fn round_trip_both_directions() {
+        let (mut client, mut server) = pair(RekeyPolicy::default());
+        let frame = client.seal(b"hello").unwrap();
+        assert!(!frame.windows(5).any(|w| w == b"hello"));
+        assert_eq!(recv(&mut server, vec![frame]), b"hello");
+        let frame = server.seal(b"world").unwrap();
+        assert_eq!(recv(&mut client, vec![frame]), b"world");
+        // 两个方向使用不同密钥
+        let mut reflected = client.seal(b"x").unwrap();
+        assert!(client.open_in_place(&mut reflected).is_err());
+    }
+
+    #[test]
+    fn message_kind_is_authenticated() {
+        let (mut client, mut server) = pair(RekeyPolicy::default());
+        for kind in [MessageKind::Compressed, MessageKind::Batch] {
+            let mut frame = client.seal_as(b"packed", kind).unwrap();
+            assert!(server.open_in_place(&mut frame).unwrap());
+            assert_eq!(frame, b"packed");
+            assert_eq!(server.last_kind(), kind);
+        }
+
+        let mut frame = client.seal(b"plain").unwrap();
+        assert!(server.open_in_place(&mut frame).unwrap());
+        assert_eq!(server.last_kind(), MessageKind::Plain);
+
+        // 篡改帧类型无法通过校验
+        let mut frame = client.seal(b"plain").unwrap();
+        frame[0] = FRAME_DATA_COMPRESSED;
+        assert!(server.open_in_place(&mut frame).is_err());
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let (mut client, mut server) = pair(RekeyPolicy::default());
+        let mut frame = client.seal(b"payload").unwrap();
+        frame[HEADER_LEN] ^= 1;
+        let err = server.open_in_place(&mut frame).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rekeys_after_byte_limit() {
+        let (mut client, mut server) = pair(RekeyPolicy {
+            bytes: 10,
+            interval: Duration::from_secs(3600),
+        });
+        let old_key = client.send.key;
+        let frames = send(&mut client, &[1; 8]);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(recv(&mut server, frames), [1; 8]);
+        assert_eq!(recv(&mut server, send(&mut client, &[2; 8])), [2; 8]);
+        // 已加密 16 字节，下一条消息前换钥
+        let frames = send(&mut client, &[3; 4]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(client.rekeys(), 1);
+        assert_ne!(client.send.key, old_key);
+        assert_eq!(client.send.seq, 1);
+        assert_eq!(recv(&mut server, frames), [3; 4]);
+        assert_eq!(server.recv.key, client.send.key);
+    }
+
+    #[test]
+    fn rekeys_after_interval() {
+        let rekey = RekeyPolicy {
+            bytes: u64::MAX,
+            interval: Duration::from_secs(60),
+        };
+        let clock = Arc::new(ManualClock::new());
+        let mut client = SecureChannel::with_clock(b"session", true, rekey, clock.clone());
+        let mut server = SecureChannel::new(b"session", false, rekey);
+        clock.advance(Duration::from_secs(59));
+        let frames = send(&mut client, b"early");
+        assert_eq!(frames.len(), 1);
+        assert_eq!(recv(&mut server, frames), b"early");
+        clock.advance(Duration::from_secs(1));
+        let frames = send(&mut client, b"late");
+        assert_eq!(frames.len(), 2);
+        assert_eq!(recv(&mut server, frames), b"late");
+    }
+
+    #[test]
+    fn replayed_frame_is_rejected() {
+        let (mut client, mut server) = pair(RekeyPolicy::default());
+        let frame = client.seal(b"shutdown").unwrap();
+        assert_eq!(recv(&mut server, vec![frame.clone()]), b"shutdown");
+        let err = server.open_in_place(&mut frame.clone()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("replayed"));
+        // 拒绝重放不影响后续合法帧
+        assert_eq!(
+            recv(&mut server, vec![client.seal(b"next").unwrap()]),
+            b"next"
+        );
+    }
+
+    #[test]
+    fn skipped_frame_is_rejected() {
+        let (mut client, mut server) = pair(RekeyPolicy::default());
+        let first = client.seal(b"a").unwrap();
+        let mut second = client.seal(b"b").unwrap();
+        let err = server.open_in_place(&mut second).unwrap_err();
+        assert!(err.to_string().contains("out-of-order"));
+        assert_eq!(recv(&mut server, vec![first]), b"a");
+    }
+
+    #[test]
+    fn frame_replayed_after_rekey_is_rejected() {
+        let (mut client, mut server) = pair(RekeyPolicy {
+            bytes: 1,
+            interval: Duration::from_secs(3600),
+        });
+        assert_eq!(recv(&mut server, send(&mut client, b"a")), b"a");
+        let frames = send(&mut client, b"b");
+        let update = frames[0].clone();
+        assert_eq!(recv(&mut server, frames), b"b");
+        // 新密钥下期望的序号恰好等于旧 KEY_UPDATE 帧的序号，但旧帧无法通过新密钥校验
+        assert_eq!(server.recv.seq, 1);
+        let err = server.open_in_place(&mut update.clone()).unwrap_err();
+        assert!(err.to_string().contains("failed to verify"));
+    }
+
+    #[test]
+    fn old_key_cannot_read_new_traffic() {
+        let (mut client, mut server) = pair(RekeyPolicy {
+            bytes: 1,
+            interval: Duration::from_secs(3600),
+        });
+        recv(&mut server, send(&mut client, b"a"));
+        let mut frames = send(&mut client, b"b");
+        // 丢掉 KEY_UPDATE，接收方仍持有旧密钥
+        let mut data = frames.pop().unwrap();
+        assert!(server.open_in_place(&mut data).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 按内容切分的块：两端以相同的规则切分数据块，只传输对方没有的块
+//!
+//! 块的边界由 gear 滚动哈希决定：哈希只取决于最近 64 个字节，块长在 [`MIN_CHUNK`] 以上且
+//! 哈希的高 16 位为 0 时切分，达到 [`MAX_CHUNK`] 时强制切分，平均约 80 KiB。文件中间插入或
+//! 删除内容只改变附近的边界，之后的块与原来相同。切分规则是协议的一部分，两端必须一致。
+
+use std::io::{ErrorKind, Read, Result};
+
+use sha2::{Digest, Sha256};
+
+use super::BlobHash;
+
+/// 块的最小长度
+pub(super) const MIN_CHUNK: usize = 16 * 1024;
+/// 块的最大长度
+pub(super) const MAX_CHUNK: usize = 256 * 1024;
+
+const BOUNDARY_MASK: u64 = 0xffff << 48;
+
+/// 每个字节值对应的随机数，由 splitmix64 生成，两端一致
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// 数据块中的一个块
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) struct Chunk {
+    pub(super) hash: BlobHash,
+    pub(super) len: u32,
+}
+
+/// 寻找块边界的滚动哈希
+#[derive(Debug, Default)]
+struct Chunker {
+    fingerprint: u64,
+    len: usize,
+}
+
+impl Chunker {
+    /// 接着已扫描的内容扫描 `data`，当前块在其中结束时返回结束位置，否则 `data` 全部属于当前块
+    fn next_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        for (i, &byte) in data.iter().enumerate() {
+            self.len += 1;
+            self.fingerprint = (self.fingerprint << 1).wrapping_add(GEAR[byte as usize]);
+            if self.len >= MAX_CHUNK
+                || (self.len >= MIN_CHUNK && self.fingerprint & BOUNDARY_MASK == 0)
+            {
+                *self = Self::default();
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+/// 逐段输入内容，得到各块的哈希与长度
+#[derive(Debug, Default)]
+pub(super) struct ChunkStream {
+    chunker: Chunker,
+    hasher: Sha256,
+    len: usize,
+    chunks: Vec<Chunk>,
+}
+
+impl ChunkStream {
+    pub(super) fn update(&mut self, mut data: &[u8]) {
+        while let Some(end) = self.chunker.next_boundary(data) {
+            self.hasher.update(&data[..end]);
+            let hasher = std::mem::take(&mut self.hasher);
+            self.chunks.push(Chunk {
+                hash: hasher.into(),
+                len: (self.len + end) as u32,
+            });
+            self.len = 0;
+            data = &data[end..];
+        }
+        self.hasher.update(data);
+        self.len += data.len();
+    }
+
+    /// 结束输入，返回全部块；空内容没有块
+    pub(super) fn finish(mut self) -> Vec<Chunk> {
+        if self.len > 0 {
+            self.chunks.push(Chunk {
+                hash: self.hasher.into(),
+                len: self.len as u32,
+            });
+        }
+        self.chunks
+    }
+}
+
+/// 从 `reader` 中依次读出各块的内容
+pub(super) struct ChunkReader<R> {
+    reader: R,
+    chunker: Chunker,
+    buf: Vec<u8>,
+    /// `buf` 中已扫描、属于当前块的字节数
+    scanned: usize,
+    eof: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub(super) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            chunker: Chunker::default(),
+            buf: Vec::new(),
+            scanned: 0,
+            eof: false,
+        }
+    }
+
+    /// 下一个块的内容，读完时返回 `None`
+    pub(super) fn next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(end) = self.chunker.next_boundary(&self.buf[self.scanned..]) {
+                let rest = self.buf.split_off(self.scanned + end);
+                self.scanned = 0;
+                return Ok(Some(std::mem::replace(&mut self.buf, rest)));
+            }
+            self.scanned = self.buf.len();
+            if self.eof {
+                self.scanned = 0;
+                return Ok(Some(std::mem::take(&mut self.buf)).filter(|chunk| !chunk.is_empty()));
+            }
+            let start = self.buf.len();
+            self.buf.resize(start + MAX_CHUNK, 0);
+            let read = loop {
+                match self.reader.read(&mut self.buf[start..]) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    result => break result,
+                }
+            };
+            let n = read.inspect_err(|_| self.buf.truncate(start))?;
+            self.buf.truncate(start + n);
+            self.eof = n == 0;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// 可重复的伪随机内容
+    pub(in crate::blobs) fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks_of(data: &[u8]) -> Vec<Chunk> {
+        let mut stream = ChunkStream::default();
+        // 分段输入与一次输入的结果相同
+        for part in data.chunks(10_000) {
+            stream.update(part);
+        }
+        stream.finish()
+    }
+
+    #[test]
+    fn boundaries_follow_content() {
+        let data = noise(2 << 20, 7);
+        let chunks = chunks_of(&data);
+        assert!(chunks.len() > 8, "{} chunks", chunks.len());
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|chunk| (MIN_CHUNK..=MAX_CHUNK).contains(&(chunk.len as usize))));
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.len as usize).sum::<usize>(),
+            data.len()
+        );
+
+        let mut reader = ChunkReader::new(&data[..]);
+        let mut offset = 0;
+        for chunk in &chunks {
+            let bytes = reader.next_chunk().unwrap().unwrap();
+            assert_eq!(bytes, &data[offset..offset + chunk.len as usize]);
+            assert_eq!(BlobHash::of(&bytes), chunk.hash);
+            offset += bytes.len();
+        }
+        assert_eq!(reader.next_chunk().unwrap(), None);
+
+        // 中间插入内容后，大部分块不变
+        let mut edited = data.clone();
+        edited.splice(1 << 20..1 << 20, b"inserted".iter().copied());
+        let changed = chunks_of(&edited)
+            .iter()
+            .filter(|chunk| !chunks.contains(chunk))
+            .count();
+        assert!(
+            changed <= 2,
+            "{} of {} chunks changed",
+            changed,
+            chunks.len()
+        );
+        assert!(chunks_of(b"").is_empty());
+    }
+}
@@ -0,0 +1,714 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 一对多推送：宿主机把同一份内容（如配置包）同时推送给多个客户机
+//!
+//! 内容只读一遍，按块放入共享的缓冲，每个客户机由各自的线程发送。每个连接最多有 `window` 个块
+//! 未经客户机确认，慢的客户机不占用其他连接；缓冲中最慢的客户机之后积累 32 块
+//! 时读取等待它，超过 `stall_timeout` 没有确认的客户机被放弃，其余的继续。协议见[上级模块](super)。
+
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::*;
+use sha2::{Digest, Sha256};
+
+use super::{verify, BlobHash, DEFAULT_BLOB_CHUNK, MAX_BLOB_CHUNK};
+use crate::mux::Channel;
+use crate::threads;
+
+/// 承载推送的通道号
+pub const PUSH_CHANNEL: u32 = 6;
+
+/// 默认每个连接未确认的块数
+pub const DEFAULT_PUSH_WINDOW: usize = 4;
+/// 未确认块数的上限，使发送不会因通道的发送队列已满而阻塞
+const MAX_PUSH_WINDOW: usize = 8;
+/// 默认放弃客户机前等待确认的时间
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 共享缓冲中最慢的客户机之后最多缓存的块数
+const BUFFERED_CHUNKS: usize = 32;
+/// 发送线程检查超时的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+const OFFER: u8 = 0;
+const DATA: u8 = 1;
+const END: u8 = 2;
+const ABORT: u8 = 3;
+const ACK: u8 = 4;
+const DONE: u8 = 5;
+const FAIL: u8 = 6;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Message {
+    Offer { size: u64, name: String },
+    Data(Vec<u8>),
+    End(BlobHash),
+    Abort(String),
+    Ack(u64),
+    Done,
+    Fail(String),
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Message::Offer { size, name } => {
+                buf.push(OFFER);
+                buf.extend_from_slice(&size.to_be_bytes());
+                buf.extend_from_slice(name.as_bytes());
+            }
+            Message::Data(data) => return data_frame(data),
+            Message::End(hash) => {
+                buf.push(END);
+                buf.extend_from_slice(hash.as_bytes());
+            }
+            Message::Abort(reason) => {
+                buf.push(ABORT);
+                buf.extend_from_slice(reason.as_bytes());
+            }
+            Message::Ack(count) => {
+                buf.push(ACK);
+                buf.extend_from_slice(&count.to_be_bytes());
+            }
+            Message::Done => buf.push(DONE),
+            Message::Fail(reason) => {
+                buf.push(FAIL);
+                buf.extend_from_slice(reason.as_bytes());
+            }
+        }
+        buf
+    }
+
+    fn decode(message: &[u8]) -> Result<Self> {
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("push {}", what));
+        let (&kind, mut body) = message
+            .split_first()
+            .ok_or_else(|| invalid("message is empty"))?;
+        let u64_of = |body: &[u8]| {
+            body.try_into()
+                .map(u64::from_be_bytes)
+                .map_err(|_| invalid("message is malformed"))
+        };
+        let text = |body: &[u8]| String::from_utf8_lossy(body).into_owned();
+        let message = match kind {
+            OFFER => {
+                if body.len() < 8 {
+                    return Err(invalid("offer is truncated"));
+                }
+                let (size, name) = body.split_at(8);
+                Message::Offer {
+                    size: u64_of(size)?,
+                    name: String::from_utf8(name.to_vec())
+                        .map_err(|_| invalid("name is not UTF-8"))?,
+                }
+            }
+            DATA => Message::Data(body.to_vec()),
+            END => {
+                let hash = BlobHash::take(&mut body)?;
+                if !body.is_empty() {
+                    return Err(invalid("end has trailing bytes"));
+                }
+                Message::End(hash)
+            }
+            ABORT => Message::Abort(text(body)),
+            ACK => Message::Ack(u64_of(body)?),
+            DONE if body.is_empty() => Message::Done,
+            FAIL => Message::Fail(text(body)),
+            _ => return Err(invalid(&format!("message type {} is unexpected", kind))),
+        };
+        Ok(message)
+    }
+}
+
+/// 不经复制构造 DATA 消息
+fn data_frame(data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(1 + data.len());
+    buf.push(DATA);
+    buf.extend_from_slice(data);
+    buf
+}
+
+fn unexpected(message: &Message) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("push message {:?} is unexpected", message),
+    )
+}
+
+/// 发送线程下一步可做的事
+enum Next {
+    Chunk(Arc<Vec<u8>>),
+    End(BlobHash),
+    Failed(String),
+    Pending,
+}
+
+#[derive(Debug)]
+struct BufferState {
+    /// `chunks` 中第一块的序号
+    base: u64,
+    chunks: VecDeque<Arc<Vec<u8>>>,
+    /// 读完时为内容的哈希，读取出错时为原因
+    end: Option<std::result::Result<BlobHash, String>>,
+    /// 各客户机下一个要发送的块，结束后为 `None`
+    positions: Vec<Option<u64>>,
+}
+
+impl BufferState {
+    fn next(&self, index: u64) -> Next {
+        if let Some(Err(reason)) = &self.end {
+            return Next::Failed(reason.clone());
+        }
+        let offset = index.saturating_sub(self.base) as usize;
+        match (self.chunks.get(offset), &self.end) {
+            (Some(chunk), _) => Next::Chunk(chunk.clone()),
+            (None, Some(Ok(hash))) => Next::End(*hash),
+            _ => Next::Pending,
+        }
+    }
+
+    /// 丢弃所有客户机都已发送的块
+    fn trim(&mut self) {
+        let slowest = self.positions.iter().flatten().min().copied();
+        let slowest = slowest.unwrap_or(self.base + self.chunks.len() as u64);
+        while self.base < slowest && self.chunks.pop_front().is_some() {
+            self.base += 1;
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.chunks.len() >= BUFFERED_CHUNKS && self.positions.iter().any(Option::is_some)
+    }
+}
+
+/// 读取线程与各发送线程共享的块
+struct Buffer {
+    state: Mutex<BufferState>,
+    changed: Condvar,
+}
+
+impl Buffer {
+    fn new(destinations: usize) -> Self {
+        Self {
+            state: Mutex::new(BufferState {
+                base: 0,
+                chunks: VecDeque::new(),
+                end: None,
+                positions: vec![Some(0); destinations],
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BufferState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 放入下一块，缓冲已满时等待；所有客户机都已结束时返回 `false`
+    fn push(&self, chunk: Vec<u8>) -> bool {
+        let mut state = self
+            .changed
+            .wait_while(self.lock(), |state| state.is_full())
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.positions.iter().all(Option::is_none) {
+            return false;
+        }
+        state.chunks.push_back(Arc::new(chunk));
+        self.changed.notify_all();
+        true
+    }
+
+    fn finish(&self, end: std::result::Result<BlobHash, String>) {
+        self.lock().end = Some(end);
+        self.changed.notify_all();
+    }
+
+    /// 第 `index` 块，尚未读出时最多等待 `wait`
+    fn next(&self, index: u64, wait: Duration) -> Next {
+        let (state, _) = self
+            .changed
+            .wait_timeout_while(self.lock(), wait, |state| {
+                matches!(state.next(index), Next::Pending)
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        state.next(index)
+    }
+
+    /// 记录第 `dest` 个客户机下一个要发送的块，结束时为 `None`
+    fn advance(&self, dest: usize, position: Option<u64>) {
+        let mut state = self.lock();
+        state.positions[dest] = position;
+        state.trim();
+        self.changed.notify_all();
+    }
+}
+
+/// 读满 `buf`，返回读出的字节数，到结尾时少于 `buf.len()`
+fn read_full(source: &mut dyn Read, buf: &mut [u8]) -> Result<usize> {
+    let mut done = 0;
+    while done < buf.len() {
+        match source.read(&mut buf[done..]) {
+            Ok(0) => break,
+            Ok(n) => done += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(done)
+}
+
+/// 一次推送的结果
+#[derive(Debug)]
+pub struct FanOutReport {
+    /// 内容的哈希
+    pub hash: BlobHash,
+    /// 内容的字节数
+    pub size: u64,
+    /// 各客户机的结果，与传入的通道一一对应
+    pub results: Vec<Result<()>>,
+}
+
+impl FanOutReport {
+    /// 成功收到内容的客户机数
+    pub fn succeeded(&self) -> usize {
+        self.results.iter().filter(|result| result.is_ok()).count()
+    }
+}
+
+/// 宿主机一侧的一对多推送，见[模块文档](self)
+#[derive(Clone, Debug)]
+pub struct FanOut {
+    name: String,
+    chunk: usize,
+    window: usize,
+    stall_timeout: Duration,
+}
+
+impl FanOut {
+    /// 推送名为 `name` 的内容，客户机按名字决定写到哪里
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            chunk: DEFAULT_BLOB_CHUNK,
+            window: DEFAULT_PUSH_WINDOW,
+            stall_timeout: DEFAULT_STALL_TIMEOUT,
+        }
+    }
+
+    /// 每块的字节数，取值限制在 1 到 [`MAX_BLOB_CHUNK`] 之间
+    pub fn with_chunk_size(mut self, chunk: usize) -> Self {
+        self.chunk = chunk.clamp(1, MAX_BLOB_CHUNK);
+        self
+    }
+
+    /// 每个连接未确认的块数（默认 [`DEFAULT_PUSH_WINDOW`]），取值限制在 1 到 8 之间
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = window.clamp(1, MAX_PUSH_WINDOW);
+        self
+    }
+
+    /// 客户机超过 `timeout` 没有确认时放弃它（默认 [`DEFAULT_STALL_TIMEOUT`]）
+    pub fn with_stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = timeout;
+        self
+    }
+
+    /// 把 `source` 的 `size` 字节推送给 `channels`（通道号一般为 [`PUSH_CHANNEL`]）另一端的
+    /// 客户机，全部结束后返回。某个客户机失败不影响其他客户机，结果见返回值；读取出错或内容
+    /// 长度与 `size` 不符时放弃所有推送并返回错误
+    pub fn send(
+        &self,
+        source: &mut dyn Read,
+        size: u64,
+        channels: &mut [Channel],
+    ) -> Result<FanOutReport> {
+        let buffer = Buffer::new(channels.len());
+        let (read, results) = thread::scope(|scope| {
+            let workers: Vec<_> = channels
+                .iter_mut()
+                .enumerate()
+                .map(|(dest, channel)| {
+                    let buffer = &buffer;
+                    let spawned =
+                        threads::spawn_scoped(scope, format!("fanout-{}", dest), move || {
+                            let result = self.push_to(buffer, channel, dest, size);
+                            buffer.advance(dest, None);
+                            result
+                        });
+                    if spawned.is_err() {
+                        buffer.advance(dest, None);
+                    }
+                    spawned
+                })
+                .collect();
+            let read = self.read_source(&buffer, source, size);
+            buffer.finish(read.as_ref().copied().map_err(Error::to_string));
+            let results: Vec<_> = workers
+                .into_iter()
+                .map(|worker| {
+                    worker?
+                        .join()
+                        .unwrap_or_else(|_| Err(Error::other("push thread panicked")))
+                })
+                .collect();
+            (read, results)
+        });
+        let report = FanOutReport {
+            hash: read?,
+            size,
+            results,
+        };
+        info!(
+            "Pushed {} ({}) to {} of {} guests",
+            self.name,
+            report.hash,
+            report.succeeded(),
+            channels.len()
+        );
+        Ok(report)
+    }
+
+    /// 读出内容放入缓冲，返回其哈希
+    fn read_source(&self, buffer: &Buffer, source: &mut dyn Read, size: u64) -> Result<BlobHash> {
+        let mut hasher = Sha256::new();
+        let mut total = 0u64;
+        // 所有客户机都已结束时仍读完内容，以便返回哈希
+        let mut pushing = true;
+        loop {
+            let mut chunk = vec![0u8; self.chunk];
+            let n = read_full(source, &mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            chunk.truncate(n);
+            hasher.update(&chunk);
+            total += n as u64;
+            if total > size {
+                break;
+            }
+            pushing = pushing && buffer.push(chunk);
+        }
+        if total != size {
+            let actual = if total > size {
+                format!("more than {}", size)
+            } else {
+                total.to_string()
+            };
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "push source of {} has {} bytes, expected {}",
+                    self.name, actual, size
+                ),
+            ));
+        }
+        Ok(BlobHash::from(hasher))
+    }
+
+    /// 向第 `dest` 个客户机发送全部内容
+    fn push_to(&self, buffer: &Buffer, channel: &Channel, dest: usize, size: u64) -> Result<()> {
+        let offer = Message::Offer {
+            size,
+            name: self.name.clone(),
+        };
+        channel.send(&offer.encode())?;
+        let (mut sent, mut acked, mut ended) = (0u64, 0u64, false);
+        let mut progress = Instant::now();
+        loop {
+            if !ended && sent - acked < self.window as u64 {
+                // 没有未确认的块时在这里等待内容，否则只查看不等待
+                let wait = if sent == acked {
+                    POLL_INTERVAL
+                } else {
+                    Duration::ZERO
+                };
+                match buffer.next(sent, wait) {
+                    Next::Chunk(chunk) => {
+                        channel.send(&data_frame(&chunk))?;
+                        sent += 1;
+                        buffer.advance(dest, Some(sent));
+                        continue;
+                    }
+                    Next::End(hash) => {
+                        channel.send(&Message::End(hash).encode())?;
+                        ended = true;
+                    }
+                    Next::Failed(reason) => {
+                        let _ = channel.send(&Message::Abort(reason.clone()).encode());
+                        return Err(Error::other(format!("push source failed: {}", reason)));
+                    }
+                    Next::Pending if sent == acked => {
+                        // 等的是内容而不是客户机
+                        progress = Instant::now();
+                        continue;
+                    }
+                    Next::Pending => {}
+                }
+            }
+
+            match channel.recv_timeout(POLL_INTERVAL)? {
+                Some(message) => match Message::decode(&message)? {
+                    Message::Ack(count) if count > acked && count <= sent => {
+                        acked = count;
+                        progress = Instant::now();
+                    }
+                    Message::Done if ended && acked == sent => return Ok(()),
+                    Message::Fail(reason) => {
+                        return Err(Error::other(format!("guest rejected the push: {}", reason)))
+                    }
+                    other => return Err(unexpected(&other)),
+                },
+                None if progress.elapsed() > self.stall_timeout => {
+                    warn!(
+                        "Guest on {:?} acknowledged nothing of {} for {:?}, giving up",
+                        channel, self.name, self.stall_timeout
+                    );
+                    let _ = channel.send(&Message::Abort("guest stalled".to_string()).encode());
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        format!("guest acknowledged nothing for {:?}", self.stall_timeout),
+                    ));
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+/// 推送到客户机的一份内容
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pushed {
+    /// 宿主机给出的名字
+    pub name: String,
+    /// 内容的哈希
+    pub hash: BlobHash,
+    /// 内容的字节数
+    pub size: u64,
+}
+
+/// 客户机一侧接收宿主机的推送
+pub struct PushReceiver {
+    channel: Channel,
+}
+
+impl PushReceiver {
+    /// 在 `channel`（通道号一般为 [`PUSH_CHANNEL`]）上接收推送
+    pub fn new(channel: Channel) -> Self {
+        Self { channel }
+    }
+
+    /// 等待下一次推送，把内容写入 `open(名字, 字节数)` 返回的写入端，校验哈希后返回。
+    /// `open` 或写入出错时拒绝这次推送；内容与哈希不符时返回 `InvalidData`，已写入的内容由
+    /// 调用方丢弃。之前被拒绝的推送残留的消息被跳过
+    pub fn receive<W: Write>(
+        &mut self,
+        open: impl FnOnce(&str, u64) -> Result<W>,
+    ) -> Result<Pushed> {
+        let (size, name) = loop {
+            match Message::decode(&self.channel.recv()?)? {
+                Message::Offer { size, name } => break (size, name),
+                Message::Data(_) | Message::End(_) | Message::Abort(_) => {
+                    debug!("Skipping the rest of a rejected push");
+                }
+                other => return Err(unexpected(&other)),
+            }
+        };
+        let mut writer = open(&name, size).map_err(|e| self.reject(e))?;
+        let mut hasher = Sha256::new();
+        let (mut received, mut count) = (0u64, 0u64);
+        loop {
+            match Message::decode(&self.channel.recv()?)? {
+                Message::Data(data) => {
+                    received += data.len() as u64;
+                    if received > size {
+                        return Err(self.reject(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("push of {} exceeds {} bytes", name, size),
+                        )));
+                    }
+                    hasher.update(&data);
+                    writer.write_all(&data).map_err(|e| self.reject(e))?;
+                    count += 1;
+                    self.channel.send(&Message::Ack(count).encode())?;
+                }
+                Message::End(hash) => {
+                    writer.flush().map_err(|e| self.reject(e))?;
+                    if received != size {
+                        return Err(self.reject(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("push of {} ended at {} of {} bytes", name, received, size),
+                        )));
+                    }
+                    verify(&hash, hasher).map_err(|e| self.reject(e))?;
+                    self.channel.send(&Message::Done.encode())?;
+                    return Ok(Pushed { name, hash, size });
+                }
+                Message::Abort(reason) => {
+                    return Err(Error::new(
+                        ErrorKind::ConnectionAborted,
+                        format!("host aborted the push of {}: {}", name, reason),
+                    ))
+                }
+                other => return Err(self.reject(unexpected(&other))),
+            }
+        }
+    }
+
+    /// 告知宿主机放弃这次推送
+    fn reject(&self, e: Error) -> Error {
+        let _ = self.channel.send(&Message::Fail(e.to_string()).encode());
+        e
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::Offer {
+                size: 1 << 40,
+                name: "config.tar".to_string(),
+            },
+            Message::Data(b"bundle".to_vec()),
+            Message::End(BlobHash::of(b"bundle")),
+            Message::Abort("source failed".to_string()),
+            Message::Ack(7),
+            Message::Done,
+            Message::Fail("disk full".to_string()),
+        ];
+        for message in messages {
+            assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+        }
+        assert!(Message::decode(&[ACK, 1]).is_err());
+        assert!(Message::decode(&[42]).is_err());
+    }
+
+    #[cfg(feature = "use-xtransport")]
+    mod mux {
+        use std::thread::JoinHandle;
+
+        use super::super::super::chunks::tests::noise;
+        use super::*;
+        use crate::mux::testing::pair;
+        use crate::mux::Mux;
+
+        type Received = JoinHandle<Vec<Result<Vec<u8>>>>;
+
+        /// 启动 `count` 个客户机，各自接收 `pushes` 次推送到内存；`accept` 返回 false 的推送被拒绝
+        fn guests(
+            count: usize,
+            pushes: usize,
+            accept: fn(usize, usize) -> bool,
+        ) -> (Vec<Mux>, Vec<Channel>, Vec<Received>) {
+            let mut muxes = Vec::new();
+            let mut channels = Vec::new();
+            let mut receivers = Vec::new();
+            for guest in 0..count {
+                let (host, mux) = pair();
+                channels.push(host.channel(PUSH_CHANNEL).unwrap());
+                let mut receiver = PushReceiver::new(mux.channel(PUSH_CHANNEL).unwrap());
+                receivers.push(thread::spawn(move || {
+                    (0..pushes)
+                        .map(|push| {
+                            let mut data = Vec::new();
+                            receiver
+                                .receive(|_, _| {
+                                    if accept(guest, push) {
+                                        Ok(&mut data)
+                                    } else {
+                                        Err(Error::new(ErrorKind::PermissionDenied, "not wanted"))
+                                    }
+                                })
+                                .map(|_| data)
+                        })
+                        .collect()
+                }));
+                muxes.push(host);
+                muxes.push(mux);
+            }
+            (muxes, channels, receivers)
+        }
+
+        #[test]
+        fn pushes_reach_every_guest() {
+            let (muxes, mut channels, receivers) =
+                guests(3, 3, |guest, push| guest != 2 || push > 0);
+            let data = noise(100_000, 11);
+            let fanout = FanOut::new("config.tar")
+                .with_chunk_size(4096)
+                .with_window(2);
+            let report = fanout.send(&mut &data[..], 100_000, &mut channels).unwrap();
+            assert_eq!(report.hash, BlobHash::of(&data));
+            assert_eq!(report.succeeded(), 2);
+            assert_eq!(
+                report.results[2].as_ref().unwrap_err().kind(),
+                ErrorKind::Other
+            );
+
+            // 拒绝过的客户机跳过残留的消息，接收下一次推送
+            let report = fanout.send(&mut &data[..], 100_000, &mut channels).unwrap();
+            assert_eq!(report.succeeded(), 3);
+
+            // 内容长度不符：所有客户机放弃
+            let err = fanout
+                .send(&mut &data[..10], 100_000, &mut channels)
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+            for (guest, receiver) in receivers.into_iter().enumerate() {
+                let pushes = receiver.join().unwrap();
+                assert_eq!(pushes.len(), 3, "guest {}", guest);
+                assert_eq!(pushes[0].is_ok(), guest != 2);
+                assert_eq!(pushes[1].as_ref().unwrap(), &data);
+                assert_eq!(
+                    pushes[2].as_ref().unwrap_err().kind(),
+                    ErrorKind::ConnectionAborted
+                );
+            }
+            drop(channels);
+            for mux in muxes {
+                mux.close();
+            }
+        }
+
+        #[test]
+        fn stalled_guests_are_dropped() {
+            let (muxes, mut channels, receivers) = guests(1, 1, |_, _| true);
+            // 第二个客户机注册了通道但从不接收
+            let (host, stalled) = pair();
+            channels.push(host.channel(PUSH_CHANNEL).unwrap());
+            let _stalled_channel = stalled.channel(PUSH_CHANNEL).unwrap();
+
+            let data = noise(64 * 1024, 13);
+            let fanout = FanOut::new("bundle")
+                .with_chunk_size(1024)
+                .with_window(2)
+                .with_stall_timeout(Duration::from_millis(200));
+            let report = fanout
+                .send(&mut &data[..], data.len() as u64, &mut channels)
+                .unwrap();
+            assert!(report.results[0].is_ok());
+            assert_eq!(
+                report.results[1].as_ref().unwrap_err().kind(),
+                ErrorKind::TimedOut
+            );
+
+            let pushes = receivers.into_iter().next().unwrap().join().unwrap();
+            assert_eq!(pushes[0].as_ref().unwrap(), &data);
+            drop(channels);
+            for mux in muxes.into_iter().chain([host, stalled]) {
+                mux.close();
+            }
+        }
+    }
+}
@@ -0,0 +1,689 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 按内容寻址的数据块存储：客户机经 vsock 从宿主机的缓存取容器镜像层、模型等大文件，无需 NFS
+//!
+//! 每个数据块以其内容的 SHA-256（[`BlobHash`]）命名。宿主机上的 [`BlobServer`] 把请求交给
+//! 实现了 [`BlobStorage`] 的存储（内置的 [`DirStorage`] 把数据块存为目录中的文件，应用也可以
+//! 自行实现，例如未命中时从镜像仓库拉取）；客户机上的 [`BlobClient`] 分块读写，读出后校验哈希，
+//! 上传的内容由宿主机校验后才保存。
+//!
+//! 更新过的大文件（如新版本的客户机镜像）通常只有少量内容变化。两端以相同的规则按内容把数据块切成
+//! 平均约 80 KiB 的块：客户机有旧版本时以 [`BlobClient::get_with_base`]
+//! 读取，与旧版本相同的块从本地复制；上传时先询问宿主机已有哪些块，已有的由宿主机就地复制。
+//! 宿主机在提交上传或首次被询问块列表时记录数据块中的块，记录只保存在内存中。
+//!
+//! 长时间的传输可经 [`TransferHandle`] 暂停与继续。[`BlobClient::download`] 把已收到的内容留在
+//! 目标旁的 `.partial` 文件中，[`BlobClient::upload_file`] 让宿主机按目标哈希保留已收到的内容
+//! （[`DirStorage`] 存在磁盘上），任何一端重启后以相同的参数再次调用即从断点继续。
+//!
+//! 同一份内容要下发给大量客户机时，宿主机以 [`FanOut`] 同时推送给所有连接，内容只读一遍，
+//! 客户机以 [`PushReceiver`] 接收。
+//!
+//! ```ignore
+//! // 宿主机，每个连接一个线程，共享同一个服务端
+//! let server = BlobServer::new(DirStorage::new("/var/cache/virga")?);
+//! server.serve(&mux.channel(BLOB_CHANNEL)?)?;
+//!
+//! // 客户机
+//! let mut blobs = BlobClient::new(mux.channel(BLOB_CHANNEL)?);
+//! let hash: BlobHash = "sha256:9f86d0...".parse()?;
+//! blobs.get_to(&hash, File::create("/run/layers/base.tar")?)?;
+//! let uploaded = blobs.put(b"model weights")?;
+//! ```
+//!
+//! 连接经 [`mux`](crate::mux) 复用，使用通道 [`BLOB_CHANNEL`]。客户机发出的请求以 1 字节类型开头，
+//! 宿主机对每个请求回复一条 [`rpc`](crate::rpc) 响应（错误保留类别，如不存在为 `NotFound`）：
+//!
+//! ```text
+//! STAT   | hash(32)                          → size(8)
+//! READ   | hash(32) | offset(8) | len(4)     → 数据，到结尾时少于 len
+//! WRITE  | 数据                              → 空，开始或继续一次上传
+//! COMMIT | hash(32)                          → 空，内容与哈希不符时为 InvalidData
+//! ABORT                                      → 空，放弃当前上传，可续传的上传保留已收到的内容
+//! CHUNKS | hash(32)                          → (len(4) | hash(32))*，数据块中的各块
+//! HAVE   | hash(32)*                         → 每个块 1 字节，1 表示宿主机有该块
+//! COPY   | hash(32)                          → 空，把宿主机已有的块追加到当前上传
+//! RESUME | hash(32)                          → offset(8)，开始或继续以 hash 为目标的可续传上传
+//! ```
+//!
+//! 整数均为大端。同一个通道上同时只有一次上传。
+//!
+//! 推送使用通道 [`PUSH_CHANNEL`]，由宿主机发起：
+//!
+//! ```text
+//! 宿主机 → 客户机
+//! OFFER | size(8) | 名字     开始一次推送
+//! DATA  | 数据               依次发送的块
+//! END   | hash(32)           内容结束，附内容的 SHA-256
+//! ABORT | 原因               宿主机放弃这次推送
+//! 客户机 → 宿主机
+//! ACK   | count(8)           已写入的块数
+//! DONE                       已校验并写入
+//! FAIL  | 原因               拒绝这次推送或写入失败
+//! ```
+
+mod chunks;
+mod fanout;
+mod resumable;
+mod server;
+mod storage;
+
+pub use fanout::{
+    FanOut, FanOutReport, PushReceiver, Pushed, DEFAULT_PUSH_WINDOW, DEFAULT_STALL_TIMEOUT,
+    PUSH_CHANNEL,
+};
+pub use resumable::TransferHandle;
+pub use server::BlobServer;
+pub use storage::{BlobStorage, BlobUpload, DirStorage};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use self::chunks::{Chunk, ChunkReader, ChunkStream, MAX_CHUNK};
+use crate::mux::Channel;
+use crate::rpc;
+
+/// 承载数据块请求的通道号
+pub const BLOB_CHANNEL: u32 = 5;
+
+/// 默认每个请求读写的字节数
+pub const DEFAULT_BLOB_CHUNK: usize = 256 * 1024;
+/// 服务端单次读出的上限，客户端的分块大小不能超过此值
+pub const MAX_BLOB_CHUNK: usize = 4 * 1024 * 1024;
+
+const STAT: u8 = 0;
+const READ: u8 = 1;
+const WRITE: u8 = 2;
+const COMMIT: u8 = 3;
+const ABORT: u8 = 4;
+const CHUNKS: u8 = 5;
+const HAVE: u8 = 6;
+const COPY: u8 = 7;
+const RESUME: u8 = 8;
+
+/// 上传时每次询问的块数
+const HAVE_BATCH: usize = 32;
+
+const HASH_LEN: usize = 32;
+const PREFIX: &str = "sha256:";
+
+/// 数据块内容的 SHA-256，以 `sha256:<十六进制>` 的形式显示与解析
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobHash([u8; HASH_LEN]);
+
+impl BlobHash {
+    /// 计算 `data` 的哈希
+    pub fn of(data: &[u8]) -> Self {
+        Self(Sha256::digest(data).into())
+    }
+
+    pub fn from_bytes(bytes: [u8; HASH_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; HASH_LEN] {
+        &self.0
+    }
+
+    /// 不带 `sha256:` 前缀的十六进制形式，可用作文件名
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn take(msg: &mut &[u8]) -> Result<Self> {
+        if msg.len() < HASH_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "blob hash is truncated"));
+        }
+        let (hash, rest) = msg.split_at(HASH_LEN);
+        *msg = rest;
+        Ok(Self(hash.try_into().unwrap()))
+    }
+}
+
+impl From<Sha256> for BlobHash {
+    fn from(hasher: Sha256) -> Self {
+        Self(hasher.finalize().into())
+    }
+}
+
+impl fmt::Display for BlobHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PREFIX, self.to_hex())
+    }
+}
+
+impl fmt::Debug for BlobHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlobHash({})", self)
+    }
+}
+
+impl FromStr for BlobHash {
+    type Err = Error;
+
+    /// 接受 `sha256:<64 位十六进制>`，前缀可省略
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid blob hash {:?}", s),
+            )
+        };
+        let hex = s.strip_prefix(PREFIX).unwrap_or(s);
+        if hex.len() != HASH_LEN * 2 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut bytes = [0u8; HASH_LEN];
+        for (byte, pair) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+/// 客户机发出的一条请求
+#[derive(Debug, PartialEq, Eq)]
+enum Request {
+    Stat(BlobHash),
+    Read {
+        hash: BlobHash,
+        offset: u64,
+        len: u32,
+    },
+    Write(Vec<u8>),
+    Commit(BlobHash),
+    Abort,
+    Chunks(BlobHash),
+    Have(Vec<BlobHash>),
+    Copy(BlobHash),
+    Resume(BlobHash),
+}
+
+impl Request {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Request::Stat(hash) => {
+                buf.push(STAT);
+                buf.extend_from_slice(hash.as_bytes());
+            }
+            Request::Read { hash, offset, len } => {
+                buf.push(READ);
+                buf.extend_from_slice(hash.as_bytes());
+                buf.extend_from_slice(&offset.to_be_bytes());
+                buf.extend_from_slice(&len.to_be_bytes());
+            }
+            Request::Write(data) => {
+                buf.reserve(1 + data.len());
+                buf.push(WRITE);
+                buf.extend_from_slice(data);
+            }
+            Request::Commit(hash) => {
+                buf.push(COMMIT);
+                buf.extend_from_slice(hash.as_bytes());
+            }
+            Request::Abort => buf.push(ABORT),
+            Request::Chunks(hash) => {
+                buf.push(CHUNKS);
+                buf.extend_from_slice(hash.as_bytes());
+            }
+            Request::Have(hashes) => {
+                buf.push(HAVE);
+                for hash in hashes {
+                    buf.extend_from_slice(hash.as_bytes());
+                }
+            }
+            Request::Copy(hash) => {
+                buf.push(COPY);
+                buf.extend_from_slice(hash.as_bytes());
+            }
+            Request::Resume(hash) => {
+                buf.push(RESUME);
+                buf.extend_from_slice(hash.as_bytes());
+            }
+        }
+        buf
+    }
+
+    fn decode(message: &[u8]) -> Result<Self> {
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("blob {}", what));
+        let (&kind, mut body) = message
+            .split_first()
+            .ok_or_else(|| invalid("request is empty"))?;
+        let request = match kind {
+            STAT => Request::Stat(BlobHash::take(&mut body)?),
+            READ => {
+                let hash = BlobHash::take(&mut body)?;
+                if body.len() != 12 {
+                    return Err(invalid("read request is malformed"));
+                }
+                let (offset, len) = body.split_at(8);
+                body = &[];
+                Request::Read {
+                    hash,
+                    offset: u64::from_be_bytes(offset.try_into().unwrap()),
+                    len: u32::from_be_bytes(len.try_into().unwrap()),
+                }
+            }
+            WRITE => {
+                let data = body.to_vec();
+                body = &[];
+                Request::Write(data)
+            }
+            COMMIT => Request::Commit(BlobHash::take(&mut body)?),
+            ABORT => Request::Abort,
+            CHUNKS => Request::Chunks(BlobHash::take(&mut body)?),
+            HAVE => {
+                let mut hashes = Vec::with_capacity(body.len() / HASH_LEN);
+                while !body.is_empty() {
+                    hashes.push(BlobHash::take(&mut body)?);
+                }
+                Request::Have(hashes)
+            }
+            COPY => Request::Copy(BlobHash::take(&mut body)?),
+            RESUME => Request::Resume(BlobHash::take(&mut body)?),
+            _ => return Err(invalid(&format!("request type {} is unexpected", kind))),
+        };
+        if !body.is_empty() {
+            return Err(invalid("request has trailing bytes"));
+        }
+        Ok(request)
+    }
+}
+
+fn encode_chunks(chunks: &[Chunk]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(chunks.len() * (4 + HASH_LEN));
+    for chunk in chunks {
+        buf.extend_from_slice(&chunk.len.to_be_bytes());
+        buf.extend_from_slice(chunk.hash.as_bytes());
+    }
+    buf
+}
+
+fn decode_chunks(mut msg: &[u8]) -> Result<Vec<Chunk>> {
+    let mut chunks = Vec::with_capacity(msg.len() / (4 + HASH_LEN));
+    while !msg.is_empty() {
+        if msg.len() < 4 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "blob chunk list is truncated",
+            ));
+        }
+        let (len, mut rest) = msg.split_at(4);
+        let hash = BlobHash::take(&mut rest)?;
+        msg = rest;
+        chunks.push(Chunk {
+            hash,
+            len: u32::from_be_bytes(len.try_into().unwrap()),
+        });
+    }
+    Ok(chunks)
+}
+
+/// 一次按块去重的传输
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    /// 数据块的哈希
+    pub hash: BlobHash,
+    /// 数据块的字节数
+    pub size: u64,
+    /// 对端已有、未经通道传输的字节数
+    pub reused: u64,
+}
+
+/// 客户机一侧的数据块客户端，见[模块文档](self)
+///
+/// 请求与响应在通道上依次进行，需要并发读写时每个线程使用各自的通道
+pub struct BlobClient {
+    channel: Channel,
+    chunk: usize,
+}
+
+impl BlobClient {
+    /// 在 `channel`（通道号一般为 [`BLOB_CHANNEL`]）上访问宿主机的数据块
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            channel,
+            chunk: DEFAULT_BLOB_CHUNK,
+        }
+    }
+
+    /// 每个请求读写的字节数，取值限制在 1 到 [`MAX_BLOB_CHUNK`] 之间
+    pub fn with_chunk_size(mut self, chunk: usize) -> Self {
+        self.chunk = chunk.clamp(1, MAX_BLOB_CHUNK);
+        self
+    }
+
+    fn call(&self, request: Request) -> Result<Vec<u8>> {
+        self.channel.send(&request.encode())?;
+        rpc::decode_response(self.channel.recv()?)
+    }
+
+    /// 数据块的大小；宿主机上没有时返回 `NotFound`
+    pub fn size(&mut self, hash: &BlobHash) -> Result<u64> {
+        decode_u64(self.call(Request::Stat(*hash))?, "size")
+    }
+
+    /// 宿主机上是否有该数据块
+    pub fn contains(&mut self, hash: &BlobHash) -> Result<bool> {
+        match self.size(hash) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 读出整个数据块并校验哈希
+    pub fn get(&mut self, hash: &BlobHash) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.get_to(hash, &mut data)?;
+        Ok(data)
+    }
+
+    /// 分块读出数据块写入 `writer`，返回字节数。哈希在读完后校验，不符时返回 `InvalidData`，
+    /// 此时已写入的内容不可信
+    pub fn get_to(&mut self, hash: &BlobHash, mut writer: impl Write) -> Result<u64> {
+        let size = self.size(hash)?;
+        let mut hasher = Sha256::new();
+        self.fetch(hash, 0, size, &mut hasher, &mut writer)?;
+        writer.flush()?;
+        verify(hash, hasher)?;
+        Ok(size)
+    }
+
+    /// 客户机上已有旧版本 `base` 时只传输变化的块：与 `base` 中相同的块从本地复制，其余从宿主机读取。
+    /// `writer` 不能写入 `base` 本身；哈希校验与 `get_to()` 相同
+    pub fn get_with_base<B: Read + Seek>(
+        &mut self,
+        hash: &BlobHash,
+        mut base: B,
+        mut writer: impl Write,
+    ) -> Result<Transfer> {
+        let chunks = decode_chunks(&self.call(Request::Chunks(*hash))?)?;
+        let local = index_base(&mut base)?;
+        let mut hasher = Sha256::new();
+        let mut transfer = Transfer {
+            hash: *hash,
+            size: 0,
+            reused: 0,
+        };
+        for chunk in &chunks {
+            let len = chunk.len as u64;
+            let copied = match local.get(&chunk.hash) {
+                Some(&at) => read_chunk(&mut base, at, chunk)?,
+                None => None,
+            };
+            match copied {
+                Some(data) => {
+                    hasher.update(&data);
+                    writer.write_all(&data)?;
+                    transfer.reused += len;
+                }
+                None => self.fetch(hash, transfer.size, len, &mut hasher, &mut writer)?,
+            }
+            transfer.size += len;
+        }
+        writer.flush()?;
+        verify(hash, hasher)?;
+        Ok(transfer)
+    }
+
+    /// 从宿主机读出 `[offset, offset + len)` 写入 `writer`
+    fn fetch(
+        &self,
+        hash: &BlobHash,
+        mut offset: u64,
+        len: u64,
+        hasher: &mut Sha256,
+        writer: &mut dyn Write,
+    ) -> Result<()> {
+        let end = offset + len;
+        while offset < end {
+            let len = (end - offset).min(self.chunk as u64) as u32;
+            let data = self.call(Request::Read {
+                hash: *hash,
+                offset,
+                len,
+            })?;
+            if data.is_empty() || data.len() > len as usize {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("blob {} returned {} bytes at {}", hash, data.len(), offset),
+                ));
+            }
+            hasher.update(&data);
+            writer.write_all(&data)?;
+            offset += data.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// 保存 `data`，返回其哈希；宿主机上已有时不再上传
+    pub fn put(&mut self, data: &[u8]) -> Result<BlobHash> {
+        let hash = BlobHash::of(data);
+        if !self.contains(&hash)? {
+            self.upload(data)?;
+        }
+        Ok(hash)
+    }
+
+    /// 分块上传 `reader` 的全部内容，返回其哈希
+    pub fn put_reader(&mut self, reader: impl Read) -> Result<BlobHash> {
+        self.upload(reader).map(|transfer| transfer.hash)
+    }
+
+    /// 与 `put_reader()` 相同，另返回宿主机已有、未经通道传输的字节数
+    pub fn upload(&mut self, mut reader: impl Read) -> Result<Transfer> {
+        match self.send_chunks(&mut reader) {
+            Ok(transfer) => {
+                self.call(Request::Commit(transfer.hash))?;
+                Ok(transfer)
+            }
+            Err(e) => {
+                // 通道仍可用时让宿主机丢弃已上传的部分
+                if !rpc::is_closed(&e) {
+                    let _ = self.call(Request::Abort);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn send_chunks(&mut self, reader: &mut dyn Read) -> Result<Transfer> {
+        let mut chunks = ChunkReader::new(reader);
+        let mut hasher = Sha256::new();
+        let (mut size, mut reused) = (0, 0);
+        let mut batch = Vec::with_capacity(HAVE_BATCH);
+        let mut done = false;
+        while !done {
+            match chunks.next_chunk()? {
+                Some(chunk) => {
+                    hasher.update(&chunk);
+                    size += chunk.len() as u64;
+                    batch.push(chunk);
+                }
+                None => done = true,
+            }
+            if batch.len() == HAVE_BATCH || (done && !batch.is_empty()) {
+                reused += self.send_batch(&mut batch)?;
+            }
+        }
+        // 空数据块也要开始一次上传
+        if size == 0 {
+            self.call(Request::Write(Vec::new()))?;
+        }
+        Ok(Transfer {
+            hash: hasher.into(),
+            size,
+            reused,
+        })
+    }
+
+    /// 询问宿主机已有 `batch` 中的哪些块，已有的由宿主机复制，其余上传；返回复制的字节数
+    fn send_batch(&mut self, batch: &mut Vec<Vec<u8>>) -> Result<u64> {
+        let hashes: Vec<_> = batch.iter().map(|chunk| BlobHash::of(chunk)).collect();
+        let have = self.call(Request::Have(hashes.clone()))?;
+        if have.len() != hashes.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "blob chunk answer is malformed",
+            ));
+        }
+        let mut reused = 0;
+        for ((chunk, hash), have) in batch.drain(..).zip(hashes).zip(have) {
+            if have != 0 {
+                match self.call(Request::Copy(hash)) {
+                    Ok(_) => {
+                        reused += chunk.len() as u64;
+                        continue;
+                    }
+                    // 宿主机上的块已不可用，改为上传
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            for piece in chunk.chunks(self.chunk) {
+                self.call(Request::Write(piece.to_vec()))?;
+            }
+        }
+        Ok(reused)
+    }
+}
+
+fn decode_u64(response: Vec<u8>, what: &str) -> Result<u64> {
+    let number = response.try_into().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("blob {} is malformed", what),
+        )
+    })?;
+    Ok(u64::from_be_bytes(number))
+}
+
+fn verify(hash: &BlobHash, hasher: Sha256) -> Result<()> {
+    let actual = BlobHash::from(hasher);
+    if actual != *hash {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("blob {} has content {}", hash, actual),
+        ));
+    }
+    Ok(())
+}
+
+/// 旧版本中各块的位置
+fn index_base(base: &mut (impl Read + Seek)) -> Result<HashMap<BlobHash, u64>> {
+    base.rewind()?;
+    let mut stream = ChunkStream::default();
+    let mut buf = vec![0u8; MAX_CHUNK];
+    loop {
+        match base.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => stream.update(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let mut offset = 0;
+    let mut local = HashMap::new();
+    for chunk in stream.finish() {
+        local.entry(chunk.hash).or_insert(offset);
+        offset += chunk.len as u64;
+    }
+    Ok(local)
+}
+
+/// 从旧版本中读出块，内容已变化时返回 `None`
+fn read_chunk(base: &mut (impl Read + Seek), at: u64, chunk: &Chunk) -> Result<Option<Vec<u8>>> {
+    base.seek(SeekFrom::Start(at))?;
+    let mut buf = vec![0u8; chunk.len as usize];
+    match base.read_exact(&mut buf) {
+        Ok(()) => Ok(Some(buf).filter(|buf| BlobHash::of(buf) == chunk.hash)),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl fmt::Debug for BlobClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlobClient")
+            .field("channel", &self.channel.id())
+            .field("chunk", &self.chunk)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_display_and_parse() {
+        let hash = BlobHash::of(b"test");
+        let text = hash.to_string();
+        assert_eq!(
+            text,
+            "sha256:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+        );
+        assert_eq!(text.parse::<BlobHash>().unwrap(), hash);
+        assert_eq!(hash.to_hex().parse::<BlobHash>().unwrap(), hash);
+        let not_hex = text.replace('9', "g");
+        for bad in ["", "sha256:", "sha256:9f86", not_hex.as_str(), "md5:00"] {
+            assert_eq!(
+                bad.parse::<BlobHash>().unwrap_err().kind(),
+                ErrorKind::InvalidInput
+            );
+        }
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let hash = BlobHash::of(b"layer");
+        for request in [
+            Request::Stat(hash),
+            Request::Read {
+                hash,
+                offset: u64::MAX,
+                len: 7,
+            },
+            Request::Write(vec![1, 2, 3]),
+            Request::Write(Vec::new()),
+            Request::Commit(hash),
+            Request::Abort,
+            Request::Chunks(hash),
+            Request::Have(vec![hash, BlobHash::of(b"")]),
+            Request::Have(Vec::new()),
+            Request::Copy(hash),
+            Request::Resume(hash),
+        ] {
+            assert_eq!(Request::decode(&request.encode()).unwrap(), request);
+        }
+        for bad in [
+            vec![],
+            vec![STAT, 1, 2],
+            vec![ABORT, 0],
+            vec![HAVE, 1],
+            vec![9],
+        ] {
+            let err = Request::decode(&bad).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+
+        let chunks = [
+            Chunk { hash, len: 9 },
+            Chunk {
+                hash: BlobHash::of(b""),
+                len: 0,
+            },
+        ];
+        assert_eq!(decode_chunks(&encode_chunks(&chunks)).unwrap(), chunks);
+        assert!(decode_chunks(&[0, 0, 0, 1, 2]).is_err());
+    }
+}
@@ -0,0 +1,345 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 可暂停、可在重启后继续的文件传输
+
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use log::*;
+use sha2::{Digest, Sha256};
+
+use super::chunks::MAX_CHUNK;
+use super::{decode_u64, verify, BlobClient, BlobHash, Request, Transfer};
+use crate::rpc;
+
+#[derive(Default)]
+struct Control {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    position: AtomicU64,
+    size: AtomicU64,
+}
+
+/// 传输的控制句柄，克隆后共享：另一个线程可暂停、继续传输或查看进度
+///
+/// 暂停在当前分块完成后生效，通道保持空闲。暂停期间连接断开（如维护时重启宿主机）时，
+/// 继续后传输返回连接错误，重连后以相同的参数再次调用即从断点继续
+#[derive(Clone, Default)]
+pub struct TransferHandle {
+    control: Arc<Control>,
+}
+
+impl TransferHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 在当前分块完成后暂停
+    pub fn pause(&self) {
+        *self.lock() = true;
+    }
+
+    /// 继续已暂停的传输
+    pub fn resume(&self) {
+        *self.lock() = false;
+        self.control.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.lock()
+    }
+
+    /// 已完成的字节数（包括断点之前的部分）与总字节数；传输开始前均为 0
+    pub fn progress(&self) -> (u64, u64) {
+        (
+            self.control.position.load(Ordering::Relaxed),
+            self.control.size.load(Ordering::Relaxed),
+        )
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, bool> {
+        self.control
+            .paused
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait_while_paused(&self) {
+        let paused = self.lock();
+        drop(
+            self.control
+                .resumed
+                .wait_while(paused, |paused| *paused)
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+    }
+
+    fn set_progress(&self, position: u64, size: u64) {
+        self.control.position.store(position, Ordering::Relaxed);
+        self.control.size.store(size, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for TransferHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransferHandle")
+            .field("paused", &self.is_paused())
+            .field("progress", &self.progress())
+            .finish()
+    }
+}
+
+/// `path` 旁保存已下载内容的文件
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+/// 读出 `reader` 的前 `len` 个字节计入 `hasher`，返回实际读出的字节数
+fn hash_prefix(reader: &mut impl Read, len: u64, hasher: &mut Sha256) -> Result<u64> {
+    let mut buf = vec![0u8; MAX_CHUNK];
+    let mut done = 0;
+    while done < len {
+        let want = (len - done).min(buf.len() as u64) as usize;
+        match reader.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buf[..n]);
+                done += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(done)
+}
+
+impl BlobClient {
+    /// 把数据块下载到 `path`，可经 `handle` 暂停。已收到的内容保存在 `<path>.partial` 中，
+    /// 下载中断（包括任何一端重启）后以相同的参数再次调用即从断点继续；完成并校验哈希后
+    /// 改名为 `path`。哈希不符时删除已下载的内容并返回 `InvalidData`。
+    /// 返回值的 `reused` 为断点之前已有的字节数
+    pub fn download(
+        &mut self,
+        hash: &BlobHash,
+        path: impl AsRef<Path>,
+        handle: &TransferHandle,
+    ) -> Result<Transfer> {
+        let path = path.as_ref();
+        let size = self.size(hash)?;
+        let partial = partial_path(path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&partial)?;
+        let mut hasher = Sha256::new();
+        let mut offset = file.metadata()?.len();
+        if offset > size {
+            file.set_len(0)?;
+            offset = 0;
+        }
+        offset = hash_prefix(&mut file, offset, &mut hasher)?;
+        file.seek(SeekFrom::Start(offset))?;
+        if offset > 0 {
+            info!("Resuming the download of {} at {}", hash, offset);
+        }
+
+        let reused = offset;
+        handle.set_progress(offset, size);
+        while offset < size {
+            handle.wait_while_paused();
+            let len = (size - offset).min(self.chunk as u64);
+            self.fetch(hash, offset, len, &mut hasher, &mut file)?;
+            offset += len;
+            handle.set_progress(offset, size);
+        }
+        file.sync_all()?;
+        if let Err(e) = verify(hash, hasher) {
+            drop(fs::remove_file(&partial));
+            return Err(e);
+        }
+        fs::rename(&partial, path)?;
+        Ok(Transfer {
+            hash: *hash,
+            size,
+            reused,
+        })
+    }
+
+    /// 上传文件 `path`，可经 `handle` 暂停。先读一遍文件计算哈希，宿主机按该哈希保留已收到的
+    /// 内容，上传中断（包括任何一端重启）后以相同的参数再次调用即从断点继续。续传的上传不按块
+    /// 去重；返回值的 `reused` 为宿主机已有的字节数
+    pub fn upload_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        handle: &TransferHandle,
+    ) -> Result<Transfer> {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let size = hash_prefix(&mut file, u64::MAX, &mut hasher)?;
+        let hash = BlobHash::from(hasher);
+        if self.contains(&hash)? {
+            handle.set_progress(size, size);
+            return Ok(Transfer {
+                hash,
+                size,
+                reused: size,
+            });
+        }
+
+        let mut offset = decode_u64(self.call(Request::Resume(hash))?, "upload offset")?;
+        if offset > size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("host holds {} bytes of a {} byte upload", offset, size),
+            ));
+        }
+        let reused = offset;
+        handle.set_progress(offset, size);
+        let mut send = || -> Result<()> {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut buf = vec![0u8; self.chunk];
+            while offset < size {
+                handle.wait_while_paused();
+                let len = (size - offset).min(buf.len() as u64) as usize;
+                // 文件在两遍之间被截短时返回 UnexpectedEof
+                file.read_exact(&mut buf[..len])?;
+                self.call(Request::Write(buf[..len].to_vec()))?;
+                offset += len as u64;
+                handle.set_progress(offset, size);
+            }
+            Ok(())
+        };
+        if let Err(e) = send() {
+            // 结束本通道上的这次上传，宿主机仍保留已收到的内容供下次续传
+            if !rpc::is_closed(&e) {
+                let _ = self.call(Request::Abort);
+            }
+            return Err(e);
+        }
+        self.call(Request::Commit(hash))?;
+        Ok(Transfer { hash, size, reused })
+    }
+}
+
+#[cfg(all(test, feature = "use-xtransport"))]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::super::chunks::tests::noise;
+    use super::super::server::tests::{start, temp_dir};
+    use super::super::{BlobServer, BlobStorage, DirStorage};
+    use super::*;
+
+    #[test]
+    fn paused_downloads_wait_for_resume() {
+        let dir = temp_dir("blob-pause");
+        let storage = DirStorage::new(&dir).unwrap();
+        let data = noise(100_000, 3);
+        let hash = storage.import(&mut &data[..]).unwrap();
+        let (mux, client) = start(BlobServer::new(storage));
+        let mut client = client.with_chunk_size(10_000);
+
+        let handle = TransferHandle::new();
+        handle.pause();
+        let target = dir.join("fetched");
+        let download = {
+            let (handle, target) = (handle.clone(), target.clone());
+            thread::spawn(move || {
+                let _mux = mux;
+                client.download(&hash, &target, &handle)
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!download.is_finished());
+        assert_eq!(handle.progress(), (0, 100_000));
+        handle.resume();
+        let transfer = download.join().unwrap().unwrap();
+        assert_eq!(transfer.reused, 0);
+        assert_eq!(handle.progress(), (100_000, 100_000));
+        assert_eq!(fs::read(&target).unwrap(), data);
+        assert!(!partial_path(&target).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn downloads_continue_from_the_partial_file() {
+        let dir = temp_dir("blob-partial");
+        let storage = DirStorage::new(&dir).unwrap();
+        let data = noise(50_000, 5);
+        let hash = storage.import(&mut &data[..]).unwrap();
+        let (_mux, client) = start(BlobServer::new(storage));
+        let mut client = client.with_chunk_size(8192);
+        let handle = TransferHandle::new();
+
+        let target = dir.join("resumed");
+        fs::write(partial_path(&target), &data[..20_000]).unwrap();
+        let transfer = client.download(&hash, &target, &handle).unwrap();
+        assert_eq!(transfer.reused, 20_000);
+        assert_eq!(fs::read(&target).unwrap(), data);
+
+        // 断点之前的内容已损坏：校验失败并丢弃
+        let corrupted = dir.join("corrupted");
+        fs::write(partial_path(&corrupted), b"not the prefix").unwrap();
+        let err = client.download(&hash, &corrupted, &handle).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(!partial_path(&corrupted).exists());
+        assert_eq!(
+            client.download(&hash, &corrupted, &handle).unwrap().reused,
+            0
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn uploads_continue_after_the_host_restarts() {
+        let dir = temp_dir("blob-resume");
+        let data = noise(60_000, 9);
+        let hash = BlobHash::of(&data);
+        let source = dir.join("source");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&source, &data).unwrap();
+
+        // 第一个宿主机进程收到一部分后连接断开
+        let server = BlobServer::new(DirStorage::new(&dir).unwrap());
+        let (mux, client) = start(server.clone());
+        assert_eq!(
+            decode_u64(client.call(Request::Resume(hash)).unwrap(), "offset").unwrap(),
+            0
+        );
+        client
+            .call(Request::Write(data[..25_000].to_vec()))
+            .unwrap();
+        // 同一目标同时只能有一个连接续传
+        let (_other_mux, other) = start(server);
+        let err = other.call(Request::Resume(hash)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceBusy);
+        mux.close();
+        drop(client);
+
+        // 重启后的宿主机从磁盘上的内容继续
+        let storage = DirStorage::new(&dir).unwrap();
+        let (_mux, client) = start(BlobServer::new(storage.clone()));
+        let mut client = client.with_chunk_size(16_384);
+        let handle = TransferHandle::new();
+        let transfer = client.upload_file(&source, &handle).unwrap();
+        assert_eq!(transfer.hash, hash);
+        assert_eq!(transfer.reused, 25_000);
+        assert_eq!(storage.size(&hash).unwrap(), Some(60_000));
+        assert_eq!(fs::read(storage.path(&hash)).unwrap(), data);
+        assert_eq!(fs::read_dir(dir.join("uploads")).unwrap().count(), 0);
+        assert_eq!(client.upload_file(&source, &handle).unwrap().reused, 60_000);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,489 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 宿主机一侧：在通道上响应客户机的读写请求
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use log::*;
+use sha2::{Digest, Sha256};
+
+use super::chunks::{Chunk, ChunkStream, MAX_CHUNK};
+use super::{encode_chunks, BlobHash, BlobStorage, BlobUpload, Request, MAX_BLOB_CHUNK};
+use crate::mux::Channel;
+use crate::rpc;
+use crate::units::ByteSize;
+
+/// 块索引最多记录的块数（约 80 GiB 的内容），超出后清空重建
+const CHUNK_INDEX_LIMIT: usize = 1 << 20;
+
+/// 进行中的上传
+struct Pending {
+    upload: Box<dyn BlobUpload>,
+    hasher: Sha256,
+    chunks: ChunkStream,
+    size: u64,
+    /// 可续传的上传占用其目标哈希
+    _claim: Option<Claim>,
+}
+
+impl Pending {
+    fn new(upload: Box<dyn BlobUpload>, claim: Option<Claim>) -> Self {
+        Self {
+            upload,
+            hasher: Sha256::new(),
+            chunks: ChunkStream::default(),
+            size: 0,
+            _claim: claim,
+        }
+    }
+}
+
+/// 正在续传的目标哈希，同一目标同时只能有一个连接写入
+type Claims = Arc<Mutex<HashSet<BlobHash>>>;
+
+struct Claim {
+    claims: Claims,
+    hash: BlobHash,
+}
+
+impl Claim {
+    fn new(claims: &Claims, hash: BlobHash) -> Result<Self> {
+        if !claims
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(hash)
+        {
+            return Err(Error::new(
+                ErrorKind::ResourceBusy,
+                format!("upload of {} is in progress on another connection", hash),
+            ));
+        }
+        Ok(Self {
+            claims: claims.clone(),
+            hash,
+        })
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        self.claims
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.hash);
+    }
+}
+
+/// 块在数据块中的位置
+#[derive(Clone, Copy, Debug)]
+struct Location {
+    blob: BlobHash,
+    offset: u64,
+    len: u32,
+}
+
+/// 宿主机已知的块
+#[derive(Default)]
+struct ChunkIndex {
+    chunks: HashMap<BlobHash, Location>,
+    blobs: HashMap<BlobHash, Arc<[Chunk]>>,
+}
+
+impl ChunkIndex {
+    fn insert(&mut self, blob: BlobHash, chunks: Arc<[Chunk]>) {
+        if self.chunks.len() + chunks.len() > CHUNK_INDEX_LIMIT {
+            debug!("Chunk index is full, dropping {} chunks", self.chunks.len());
+            self.chunks.clear();
+            self.blobs.clear();
+        }
+        let mut offset = 0;
+        for chunk in chunks.iter() {
+            self.chunks.entry(chunk.hash).or_insert(Location {
+                blob,
+                offset,
+                len: chunk.len,
+            });
+            offset += chunk.len as u64;
+        }
+        self.blobs.insert(blob, chunks);
+    }
+}
+
+/// 宿主机一侧的数据块服务端，可在多个连接线程间共享（克隆后共享同一个存储与块索引）
+#[derive(Clone)]
+pub struct BlobServer {
+    storage: Arc<dyn BlobStorage>,
+    index: Arc<Mutex<ChunkIndex>>,
+    claims: Claims,
+    max_blob_size: Option<ByteSize>,
+}
+
+impl BlobServer {
+    pub fn new(storage: impl BlobStorage + 'static) -> Self {
+        Self {
+            storage: Arc::new(storage),
+            index: Arc::default(),
+            claims: Claims::default(),
+            max_blob_size: None,
+        }
+    }
+
+    /// 客户机上传的单个数据块最多 `limit` 字节，默认不限制
+    pub fn with_max_blob_size(mut self, limit: ByteSize) -> Self {
+        self.max_blob_size = Some(limit);
+        self
+    }
+
+    /// 使用的存储
+    pub fn storage(&self) -> &Arc<dyn BlobStorage> {
+        &self.storage
+    }
+
+    /// 在 `channel` 上依次处理一个客户机的请求，连接关闭时返回 `Ok`；
+    /// 未提交的上传在返回时丢弃，可续传的上传保留在存储中
+    pub fn serve(&self, channel: &Channel) -> Result<()> {
+        let mut pending = None;
+        loop {
+            let message = match channel.recv() {
+                Ok(message) => message,
+                Err(e) if rpc::is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let result = self.handle(Request::decode(&message)?, &mut pending);
+            if let Err(e) = &result {
+                debug!("Blob request failed: {}", e);
+            }
+            channel.send(&rpc::encode_response(result))?;
+        }
+    }
+
+    fn handle(&self, request: Request, pending: &mut Option<Pending>) -> Result<Vec<u8>> {
+        match request {
+            Request::Stat(hash) => Ok(self.size(&hash)?.to_be_bytes().to_vec()),
+            Request::Read { hash, offset, len } => self.read(&hash, offset, len as usize),
+            Request::Write(data) => {
+                self.append(pending, &data)?;
+                Ok(Vec::new())
+            }
+            Request::Commit(hash) => {
+                let upload = pending.take().ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidInput, "no blob upload to commit")
+                })?;
+                let actual = BlobHash::from(upload.hasher);
+                if actual != hash {
+                    // 续传的内容也不可信，不再保留
+                    upload.upload.abort();
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("uploaded content is {}, not {}", actual, hash),
+                    ));
+                }
+                upload.upload.commit(&hash)?;
+                info!("Stored blob {} of {} bytes", hash, upload.size);
+                self.lock_index()
+                    .insert(hash, upload.chunks.finish().into());
+                Ok(Vec::new())
+            }
+            Request::Abort => {
+                *pending = None;
+                Ok(Vec::new())
+            }
+            Request::Chunks(hash) => Ok(encode_chunks(&self.chunks(&hash)?)),
+            Request::Have(hashes) => {
+                let index = self.lock_index();
+                Ok(hashes
+                    .iter()
+                    .map(|hash| index.chunks.contains_key(hash) as u8)
+                    .collect())
+            }
+            Request::Copy(hash) => {
+                let data = self.copy(&hash)?;
+                self.append(pending, &data)?;
+                Ok(Vec::new())
+            }
+            Request::Resume(hash) => {
+                *pending = None;
+                let upload = self.resume(hash)?;
+                let written = upload.size;
+                *pending = Some(upload);
+                Ok(written.to_be_bytes().to_vec())
+            }
+        }
+    }
+
+    /// 开始或继续以 `hash` 为目标的上传，重新计算已写入部分的哈希
+    fn resume(&self, hash: BlobHash) -> Result<Pending> {
+        let claim = Claim::new(&self.claims, hash)?;
+        let (upload, written) = self.storage.resume(&hash)?;
+        let mut pending = Pending::new(upload, Some(claim));
+        let mut buf = vec![0u8; MAX_CHUNK];
+        while pending.size < written {
+            let len = (written - pending.size).min(buf.len() as u64) as usize;
+            let n = pending.upload.read_at(pending.size, &mut buf[..len])?;
+            if n == 0 {
+                break;
+            }
+            pending.hasher.update(&buf[..n]);
+            pending.chunks.update(&buf[..n]);
+            pending.size += n as u64;
+        }
+        info!("Resuming the upload of {} at {}", hash, pending.size);
+        Ok(pending)
+    }
+
+    fn lock_index(&self) -> MutexGuard<'_, ChunkIndex> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 把 `data` 追加到当前上传，没有时开始一次上传；失败时放弃当前上传
+    fn append(&self, pending: &mut Option<Pending>, data: &[u8]) -> Result<()> {
+        let upload = match pending {
+            Some(upload) => upload,
+            None => pending.insert(Pending::new(self.storage.create()?, None)),
+        };
+        upload.size += data.len() as u64;
+        if let Some(limit) = self.max_blob_size {
+            if upload.size > limit.as_u64() {
+                if let Some(upload) = pending.take() {
+                    upload.upload.abort();
+                }
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("blob is larger than {}", limit),
+                ));
+            }
+        }
+        upload.hasher.update(data);
+        upload.chunks.update(data);
+        if let Err(e) = upload.upload.write(data) {
+            *pending = None;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// 数据块中的各块；首次询问时读出整个数据块切分并记入索引，内容与哈希不符时返回 `InvalidData`
+    fn chunks(&self, hash: &BlobHash) -> Result<Arc<[Chunk]>> {
+        if let Some(chunks) = self.lock_index().blobs.get(hash) {
+            return Ok(chunks.clone());
+        }
+        let size = self.size(hash)?;
+        let mut hasher = Sha256::new();
+        let mut stream = ChunkStream::default();
+        let mut offset = 0;
+        while offset < size {
+            let data = self.read(hash, offset, MAX_CHUNK)?;
+            if data.is_empty() {
+                break;
+            }
+            hasher.update(&data);
+            stream.update(&data);
+            offset += data.len() as u64;
+        }
+        let actual = BlobHash::from(hasher);
+        if actual != *hash {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("stored blob {} has content {}", hash, actual),
+            ));
+        }
+        let chunks: Arc<[Chunk]> = stream.finish().into();
+        self.lock_index().insert(*hash, chunks.clone());
+        Ok(chunks)
+    }
+
+    /// 读出宿主机已有的块；块所在的数据块已变化时从索引中移除并返回 `NotFound`
+    fn copy(&self, hash: &BlobHash) -> Result<Vec<u8>> {
+        let location = self.lock_index().chunks.get(hash).copied();
+        let not_found = || Error::new(ErrorKind::NotFound, format!("no chunk {}", hash));
+        let location = location.ok_or_else(not_found)?;
+        match self.read(&location.blob, location.offset, location.len as usize) {
+            Ok(data) if BlobHash::of(&data) == *hash => Ok(data),
+            Ok(_) => {
+                let mut index = self.lock_index();
+                index.chunks.remove(hash);
+                index.blobs.remove(&location.blob);
+                Err(not_found())
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                self.lock_index().chunks.remove(hash);
+                Err(not_found())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn size(&self, hash: &BlobHash) -> Result<u64> {
+        self.storage
+            .size(hash)?
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no blob {}", hash)))
+    }
+
+    fn read(&self, hash: &BlobHash, offset: u64, len: usize) -> Result<Vec<u8>> {
+        if len > MAX_BLOB_CHUNK {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("blob read of {} bytes is too large", len),
+            ));
+        }
+        let size = self.size(hash)?;
+        let mut buf = vec![0u8; len.min(size.saturating_sub(offset) as usize)];
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self
+                .storage
+                .read_at(hash, offset + filled as u64, &mut buf[filled..])?
+            {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        buf.truncate(filled);
+        Ok(buf)
+    }
+}
+
+impl fmt::Debug for BlobServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlobServer")
+            .field("max_blob_size", &self.max_blob_size)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "use-xtransport"))]
+pub(super) mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::thread;
+
+    use super::super::chunks::tests::noise;
+    use super::super::{BlobClient, DirStorage, BLOB_CHANNEL};
+    use super::*;
+    use crate::mux::testing::pair;
+    use crate::mux::Mux;
+
+    pub(in crate::blobs) fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("virga-{}-{}", name, std::process::id()))
+    }
+
+    /// 启动服务端，返回客户机一侧的连接与客户端
+    pub(in crate::blobs) fn start(server: BlobServer) -> (Mux, BlobClient) {
+        let (host, guest) = pair();
+        let host_channel = host.channel(BLOB_CHANNEL).unwrap();
+        let guest_channel = guest.channel(BLOB_CHANNEL).unwrap();
+        thread::spawn(move || {
+            let _mux = host;
+            server.serve(&host_channel)
+        });
+        (guest, BlobClient::new(guest_channel).with_chunk_size(5))
+    }
+
+    #[test]
+    fn blobs_are_uploaded_and_fetched_in_chunks() {
+        let dir = temp_dir("blob-server");
+        let storage = DirStorage::new(&dir).unwrap();
+        let cached = storage
+            .import(&mut &b"base layer from the host"[..])
+            .unwrap();
+        let (_mux, mut client) = start(BlobServer::new(storage.clone()));
+
+        assert_eq!(client.get(&cached).unwrap(), b"base layer from the host");
+        let hash = client.put(b"weights uploaded by the guest").unwrap();
+        assert_eq!(hash, BlobHash::of(b"weights uploaded by the guest"));
+        assert_eq!(
+            fs::read(storage.path(&hash)).unwrap(),
+            b"weights uploaded by the guest"
+        );
+        assert_eq!(client.put(b"weights uploaded by the guest").unwrap(), hash);
+        let empty = client.put_reader(&b""[..]).unwrap();
+        assert_eq!(client.get(&empty).unwrap(), b"");
+
+        let missing = BlobHash::of(b"missing");
+        assert!(!client.contains(&missing).unwrap());
+        assert_eq!(
+            client.get(&missing).unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+
+        // 缓存中的文件被篡改时客户机拒绝其内容
+        fs::write(storage.path(&cached), b"tampered layer").unwrap();
+        assert_eq!(
+            client.get(&cached).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_and_mismatched_uploads_are_rejected() {
+        let dir = temp_dir("blob-limits");
+        let storage = DirStorage::new(&dir).unwrap();
+        let server = BlobServer::new(storage.clone()).with_max_blob_size(ByteSize::b(8));
+        let (_mux, mut client) = start(server);
+
+        assert!(client.put(b"far too large for the limit").is_err());
+        let small = client.put(b"fits").unwrap();
+        assert!(client.contains(&small).unwrap());
+
+        // 提交的哈希与内容不符
+        client.call(Request::Write(b"abc".to_vec())).unwrap();
+        let err = client
+            .call(Request::Commit(BlobHash::of(b"xyz")))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(!client.contains(&BlobHash::of(b"abc")).unwrap());
+        assert_eq!(fs::read_dir(dir.join("uploads")).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn updated_blobs_only_move_changed_chunks() {
+        let dir = temp_dir("blob-dedup");
+        let storage = DirStorage::new(&dir).unwrap();
+        let v1 = noise(2 << 20, 11);
+        let mut v2 = v1.clone();
+        v2.splice(1 << 20..(1 << 20) + 100, b"patched".iter().copied());
+        let v2_hash = storage.import(&mut &v2[..]).unwrap();
+        let (_mux, client) = start(BlobServer::new(storage.clone()));
+        let mut client = client.with_chunk_size(64 * 1024);
+
+        // 客户机有旧版本，只读取变化的块
+        let mut fetched = Vec::new();
+        let transfer = client
+            .get_with_base(&v2_hash, std::io::Cursor::new(&v1), &mut fetched)
+            .unwrap();
+        assert_eq!(fetched, v2);
+        assert_eq!(transfer.size, v2.len() as u64);
+        assert!(
+            transfer.reused >= transfer.size - 2 * MAX_CHUNK as u64,
+            "{:?}",
+            transfer
+        );
+
+        // 上传新版本时宿主机复制已有的块
+        let mut v3 = v2.clone();
+        v3.extend_from_slice(b"appended by the guest");
+        let transfer = client.upload(&v3[..]).unwrap();
+        assert_eq!(transfer.hash, BlobHash::of(&v3));
+        assert!(
+            transfer.reused >= transfer.size - MAX_CHUNK as u64,
+            "{:?}",
+            transfer
+        );
+        assert_eq!(fs::read(storage.path(&transfer.hash)).unwrap(), v3);
+
+        // 块所在的数据块被篡改后改为上传
+        fs::write(storage.path(&v2_hash), b"gone").unwrap();
+        let mut v4 = v2.clone();
+        v4.truncate(1 << 20);
+        let transfer = client.upload(&v4[..]).unwrap();
+        assert_eq!(fs::read(storage.path(&transfer.hash)).unwrap(), v4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
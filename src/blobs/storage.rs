@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 宿主机一侧的存储接口与基于目录的实现
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use sha2::{Digest, Sha256};
+
+use super::BlobHash;
+
+/// 数据块的存储，由 [`BlobServer`](super::BlobServer) 调用；可在多个连接线程间共享
+///
+/// 读取时哈希由调用方在客户机上校验；上传的内容经服务端校验与哈希一致后才调用 `commit()`
+pub trait BlobStorage: Send + Sync {
+    /// 数据块的大小，不存在时返回 `None`
+    fn size(&self, hash: &BlobHash) -> Result<Option<u64>>;
+
+    /// 从 `offset` 开始读到 `buf`，返回读出的字节数，到结尾时为 0
+    fn read_at(&self, hash: &BlobHash, offset: u64, buf: &mut [u8]) -> Result<usize>;
+
+    /// 开始一次上传
+    fn create(&self) -> Result<Box<dyn BlobUpload>>;
+
+    /// 开始或继续以 `hash` 为目标的可续传上传，返回上传与已写入的字节数。未提交就丢弃时
+    /// 应保留已写入的内容（包括进程重启后），直到 `abort()` 或提交；默认不支持
+    fn resume(&self, hash: &BlobHash) -> Result<(Box<dyn BlobUpload>, u64)> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("storage cannot resume the upload of {}", hash),
+        ))
+    }
+
+    /// 保存 `reader` 的全部内容，返回其哈希，供宿主机预先放入缓存
+    fn import(&self, reader: &mut dyn Read) -> Result<BlobHash> {
+        let mut upload = self.create()?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; super::DEFAULT_BLOB_CHUNK];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            hasher.update(&buf[..n]);
+            upload.write(&buf[..n])?;
+        }
+        let hash = BlobHash::from(hasher);
+        upload.commit(&hash)?;
+        Ok(hash)
+    }
+}
+
+/// 一次进行中的上传；未 `commit()` 就丢弃时应清理已写入的内容（可续传的上传除外）
+pub trait BlobUpload: Send {
+    /// 追加内容
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// 以 `hash` 保存已写入的内容
+    fn commit(self: Box<Self>, hash: &BlobHash) -> Result<()>;
+
+    /// 放弃上传并清理已写入的内容，可续传的上传也一并删除
+    fn abort(self: Box<Self>) {}
+
+    /// 读出已写入的内容，续传时用于重新计算哈希；由 `resume()` 返回的上传须支持
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let _ = (offset, buf);
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "upload cannot be read back",
+        ))
+    }
+}
+
+/// 把数据块存为目录中的文件：`<dir>/sha256/<十六进制哈希>`
+///
+/// 上传先写入 `<dir>/uploads` 中的临时文件，提交时改名，读者不会看到不完整的数据块。
+/// 可续传的上传写入 `<dir>/uploads/<十六进制哈希>.partial`，进程重启后仍可继续。
+/// 宿主机也可以直接按上述路径放入文件，服务端不会校验已有文件的内容
+#[derive(Clone, Debug)]
+pub struct DirStorage {
+    blobs: PathBuf,
+    uploads: PathBuf,
+}
+
+impl DirStorage {
+    /// 使用目录 `dir`，不存在时创建
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let storage = Self {
+            blobs: dir.join("sha256"),
+            uploads: dir.join("uploads"),
+        };
+        fs::create_dir_all(&storage.blobs)?;
+        fs::create_dir_all(&storage.uploads)?;
+        Ok(storage)
+    }
+
+    /// 数据块的文件路径
+    pub fn path(&self, hash: &BlobHash) -> PathBuf {
+        self.blobs.join(hash.to_hex())
+    }
+}
+
+impl BlobStorage for DirStorage {
+    fn size(&self, hash: &BlobHash) -> Result<Option<u64>> {
+        match fs::metadata(self.path(hash)) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_at(&self, hash: &BlobHash, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        File::open(self.path(hash))?.read_at(buf, offset)
+    }
+
+    fn create(&self) -> Result<Box<dyn BlobUpload>> {
+        static SEQ: AtomicU64 = AtomicU64::new(0);
+        let path = self.uploads.join(format!(
+            "{}-{}",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Box::new(DirUpload {
+            file,
+            path,
+            blobs: self.blobs.clone(),
+            keep: false,
+        }))
+    }
+
+    fn resume(&self, hash: &BlobHash) -> Result<(Box<dyn BlobUpload>, u64)> {
+        let path = self.uploads.join(format!("{}.partial", hash.to_hex()));
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let written = file.metadata()?.len();
+        let upload = DirUpload {
+            file,
+            path,
+            blobs: self.blobs.clone(),
+            keep: true,
+        };
+        Ok((Box::new(upload), written))
+    }
+}
+
+struct DirUpload {
+    file: File,
+    path: PathBuf,
+    blobs: PathBuf,
+    /// drop 时保留文件：已提交（改名后不再存在），或可续传
+    keep: bool,
+}
+
+impl BlobUpload for DirUpload {
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data)
+    }
+
+    fn commit(mut self: Box<Self>, hash: &BlobHash) -> Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.path, self.blobs.join(hash.to_hex()))?;
+        self.keep = true;
+        Ok(())
+    }
+
+    fn abort(mut self: Box<Self>) {
+        self.keep = false;
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        self.file.read_at(buf, offset)
+    }
+}
+
+impl Drop for DirUpload {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imported_blobs_are_stored_by_hash() {
+        let dir = std::env::temp_dir().join(format!("virga-blobs-{}", std::process::id()));
+        let storage = DirStorage::new(&dir).unwrap();
+        let hash = storage.import(&mut &b"layer contents"[..]).unwrap();
+        assert_eq!(hash, BlobHash::of(b"layer contents"));
+        assert_eq!(storage.size(&hash).unwrap(), Some(14));
+        let mut buf = [0u8; 8];
+        assert_eq!(storage.read_at(&hash, 6, &mut buf).unwrap(), 8);
+        assert_eq!(&buf, b"contents");
+        assert_eq!(storage.read_at(&hash, 14, &mut buf).unwrap(), 0);
+        assert_eq!(storage.size(&BlobHash::of(b"other")).unwrap(), None);
+
+        // 未提交的上传不留下文件
+        let mut upload = storage.create().unwrap();
+        upload.write(b"partial").unwrap();
+        drop(upload);
+        assert_eq!(fs::read_dir(dir.join("uploads")).unwrap().count(), 0);
+
+        // 可续传的上传在丢弃后保留，`abort()` 才删除
+        let target = BlobHash::of(b"resumed");
+        let (mut upload, written) = storage.resume(&target).unwrap();
+        assert_eq!(written, 0);
+        upload.write(b"resu").unwrap();
+        drop(upload);
+        let (mut upload, written) = storage.resume(&target).unwrap();
+        assert_eq!(written, 4);
+        let mut prefix = [0u8; 4];
+        assert_eq!(upload.read_at(0, &mut prefix).unwrap(), 4);
+        assert_eq!(&prefix, b"resu");
+        upload.write(b"med").unwrap();
+        upload.commit(&target).unwrap();
+        assert_eq!(fs::read(storage.path(&target)).unwrap(), b"resumed");
+        let (upload, _) = storage.resume(&BlobHash::of(b"dropped")).unwrap();
+        upload.abort();
+        assert_eq!(fs::read_dir(dir.join("uploads")).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
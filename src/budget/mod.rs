@@ -0,0 +1,332 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 内存预算
+//!
+//! 每个连接内部缓冲（接收重组与复用的借出缓冲、`Read` 尚未读完的消息）占用的
+//! 字节数计入进程级总量，可通过 [`held()`] 查看。用 [`set_global_limit()`] 设置
+//! 上限后，总量超限时：
+//!
+//! - 连接在接收下一条消息前先释放自己的复用缓冲，再等待其他连接释放内存，
+//!   等待期间不从 socket 读取，对端随 vsock 流控被反压；超过
+//!   [`BUDGET_WAIT_TIMEOUT`] 仍未回落时接收返回 `OutOfMemory`
+//! - `ServerManager::accept()` 拒绝新连接
+//!
+//! 单个连接的上限由 `with_memory_limit()` 配置：连接占用超过上限时，
+//! 每次接收前释放复用缓冲，不影响其他连接。
+//!
+//! 消息长度由对端在消息头中声明，接收时先与 [`room()`] 比较再分配缓冲：声明的长度
+//! 超过连接上限、或超过全局上限中其他连接未占用的部分时，不分配缓冲，接收返回
+//! `OutOfMemory`。XTransport 会读完并丢弃这条消息，连接仍可继续使用。
+
+use std::io::ErrorKind;
+use std::sync::atomic::Ordering;
+use std::sync::PoisonError;
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, VirgeError};
+use crate::loom::{AtomicUsize, Condvar, Mutex};
+use crate::units::ByteSize;
+
+/// 总量超限时接收前最多等待的时长
+pub const BUDGET_WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 一份内存预算：总占用、上限与释放通知。连接使用进程级的 [`GLOBAL`]，
+/// 测试可以创建独立的实例，不受同时运行的其他测试影响
+#[derive(Debug)]
+pub(crate) struct Budget {
+    held: AtomicUsize,
+    /// 0 表示不限制
+    limit: AtomicUsize,
+    released: (Mutex<()>, Condvar),
+}
+
+#[cfg(not(loom))]
+pub(crate) static GLOBAL: Budget = Budget {
+    held: AtomicUsize::new(0),
+    limit: AtomicUsize::new(0),
+    released: (Mutex::new(()), Condvar::new()),
+};
+
+// loom 的原语不能在常量中创建，每次交错各自初始化
+#[cfg(loom)]
+::loom::lazy_static! {
+    pub(crate) static ref GLOBAL: Budget = Budget::new();
+}
+
+impl Budget {
+    #[cfg(any(test, loom))]
+    pub(crate) fn new() -> Self {
+        Budget {
+            held: AtomicUsize::new(0),
+            limit: AtomicUsize::new(0),
+            released: (Mutex::new(()), Condvar::new()),
+        }
+    }
+
+    pub(crate) fn set_limit(&self, limit: Option<ByteSize>) {
+        self.limit
+            .store(limit.map_or(0, |l| l.as_usize().max(1)), Ordering::Relaxed);
+        self.notify();
+    }
+
+    pub(crate) fn limit(&self) -> Option<ByteSize> {
+        match self.limit.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(ByteSize::from(limit)),
+        }
+    }
+
+    pub(crate) fn held(&self) -> ByteSize {
+        ByteSize::from(self.held.load(Ordering::Relaxed))
+    }
+
+    /// 总量是否已达到上限
+    pub(crate) fn over_limit(&self) -> bool {
+        match self.limit.load(Ordering::Relaxed) {
+            0 => false,
+            limit => self.held.load(Ordering::Relaxed) >= limit,
+        }
+    }
+
+    /// 见 [`room()`]
+    pub(crate) fn room(&self, own_limit: Option<usize>, own_held: usize) -> Option<usize> {
+        let global = match self.limit.load(Ordering::Relaxed) {
+            0 => None,
+            limit => {
+                let others = self.held.load(Ordering::Relaxed).saturating_sub(own_held);
+                Some(limit.saturating_sub(others))
+            }
+        };
+        match (own_limit, global) {
+            (Some(own), Some(global)) => Some(own.min(global)),
+            (own, global) => own.or(global),
+        }
+    }
+
+    /// 见 [`wait_for_room()`]
+    pub(crate) fn wait_for_room(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self
+            .released
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        while self.over_limit() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(VirgeError::transport(
+                    ErrorKind::OutOfMemory,
+                    format!(
+                        "memory budget exhausted: {} held, limit {}",
+                        self.held(),
+                        ByteSize::from(self.limit.load(Ordering::Relaxed))
+                    ),
+                ));
+            }
+            guard = self
+                .released
+                .1
+                .wait_timeout(guard, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        Ok(())
+    }
+
+    fn notify(&self) {
+        let _guard = self
+            .released
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.released.1.notify_all();
+    }
+}
+
+/// 设置进程级内存上限，`None` 取消限制
+pub fn set_global_limit(limit: Option<ByteSize>) {
+    GLOBAL.set_limit(limit);
+}
+
+/// 当前的进程级内存上限
+pub fn global_limit() -> Option<ByteSize> {
+    GLOBAL.limit()
+}
+
+/// 所有连接内部缓冲当前占用的字节数
+pub fn held() -> ByteSize {
+    GLOBAL.held()
+}
+
+/// 总量是否已达到上限
+pub(crate) fn over_global_limit() -> bool {
+    GLOBAL.over_limit()
+}
+
+/// 一条消息最多可分配的字节数：不超过连接自身的上限 `own_limit`，也不超过全局上限
+/// 减去其他连接的占用（`own_held` 为本连接已计入总量的部分）；都不限制时为 `None`
+pub(crate) fn room(own_limit: Option<usize>, own_held: usize) -> Option<usize> {
+    GLOBAL.room(own_limit, own_held)
+}
+
+/// 等待总量回落到上限以下，超时返回 `OutOfMemory`
+pub(crate) fn wait_for_room(timeout: Duration) -> Result<()> {
+    GLOBAL.wait_for_room(timeout)
+}
+
+/// 单个连接的占用，drop 时从总量中扣除
+#[derive(Debug)]
+pub(crate) struct BufferAccount {
+    held: usize,
+    limit: Option<usize>,
+    budget: &'static Budget,
+}
+
+impl Default for BufferAccount {
+    fn default() -> Self {
+        Self::with_limit(None)
+    }
+}
+
+impl BufferAccount {
+    pub(crate) fn with_limit(limit: Option<ByteSize>) -> Self {
+        Self {
+            held: 0,
+            limit: limit.map(ByteSize::as_usize),
+            budget: &GLOBAL,
+        }
+    }
+
+    /// 计入 `budget` 而不是进程级总量
+    #[cfg(test)]
+    pub(crate) fn in_budget(budget: &'static Budget, limit: Option<ByteSize>) -> Self {
+        Self {
+            budget,
+            ..Self::with_limit(limit)
+        }
+    }
+
+    /// 更新本连接的占用
+    pub(crate) fn update(&mut self, bytes: usize) {
+        if bytes >= self.held {
+            self.budget
+                .held
+                .fetch_add(bytes - self.held, Ordering::Relaxed);
+        } else {
+            self.budget
+                .held
+                .fetch_sub(self.held - bytes, Ordering::Relaxed);
+            self.budget.notify();
+        }
+        self.held = bytes;
+    }
+
+    /// 本连接自身的上限
+    pub(crate) fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// 本连接的占用是否超过自身上限
+    pub(crate) fn over_limit(&self) -> bool {
+        self.limit.is_some_and(|limit| self.held > limit)
+    }
+}
+
+impl Drop for BufferAccount {
+    fn drop(&mut self) {
+        self.update(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_tracks_own_limit() {
+        let mut account = BufferAccount::with_limit(Some(ByteSize::b(100)));
+        account.update(100);
+        assert!(!account.over_limit());
+        account.update(101);
+        assert!(account.over_limit());
+        account.update(0);
+        assert!(!BufferAccount::default().over_limit());
+    }
+
+    /// 独立的预算，不受同时运行的其他测试中的连接影响
+    fn local_budget() -> &'static Budget {
+        Box::leak(Box::new(Budget::new()))
+    }
+
+    #[test]
+    fn room_is_bounded_by_own_and_global_limit() {
+        let budget = local_budget();
+        let account = BufferAccount::in_budget(budget, Some(ByteSize::b(100)));
+        assert_eq!(budget.room(account.limit(), 0), Some(100));
+        assert_eq!(budget.room(None, 0), None);
+
+        budget.set_limit(Some(ByteSize::b(80)));
+        let mut other = BufferAccount::in_budget(budget, None);
+        other.update(50);
+        // 本连接已计入的部分不算作其他连接的占用
+        assert_eq!(budget.room(account.limit(), 0), Some(30));
+        assert_eq!(budget.room(None, 50), Some(80));
+    }
+
+    #[test]
+    fn limit_blocks_until_released() {
+        let budget = local_budget();
+        budget.set_limit(Some(ByteSize::b(1000)));
+        let mut account = BufferAccount::in_budget(budget, None);
+        account.update(2000);
+        assert!(budget.over_limit());
+        assert_eq!(budget.held(), ByteSize::b(2000));
+        let err = budget.wait_for_room(Duration::from_millis(10)).unwrap_err();
+        assert_eq!(std::io::Error::from(err).kind(), ErrorKind::OutOfMemory);
+
+        let waiter = std::thread::spawn(|| budget.wait_for_room(Duration::from_secs(5)));
+        std::thread::sleep(Duration::from_millis(20));
+        drop(account);
+        assert!(waiter.join().unwrap().is_ok());
+        assert_eq!(budget.held(), ByteSize::b(0));
+        budget.set_limit(None);
+        assert_eq!(budget.limit(), None);
+    }
+
+    /// 接收方在总量超限时等待，另一个连接同时收完消息、断开：释放总要唤醒等待者，
+    /// 不能在检查与等待之间丢失
+    #[cfg(loom)]
+    #[test]
+    fn loom_release_wakes_waiting_receiver() {
+        ::loom::model(|| {
+            set_global_limit(Some(ByteSize::b(100)));
+            let mut other = BufferAccount::default();
+            other.update(150);
+            let disconnect = ::loom::thread::spawn(move || {
+                other.update(120);
+                drop(other);
+            });
+            let mut own = BufferAccount::default();
+            wait_for_room(BUDGET_WAIT_TIMEOUT).unwrap();
+            own.update(60);
+            disconnect.join().unwrap();
+            assert_eq!(held(), ByteSize::b(60));
+        });
+    }
+
+    /// 取消全局上限同样唤醒等待中的接收方
+    #[cfg(loom)]
+    #[test]
+    fn loom_lifting_the_limit_wakes_waiting_receiver() {
+        ::loom::model(|| {
+            set_global_limit(Some(ByteSize::b(100)));
+            let mut account = BufferAccount::default();
+            account.update(150);
+            let lift = ::loom::thread::spawn(|| set_global_limit(None));
+            wait_for_room(BUDGET_WAIT_TIMEOUT).unwrap();
+            lift.join().unwrap();
+        });
+    }
+}
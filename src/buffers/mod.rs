@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 消息缓冲的来源
+//!
+//! 接收消息的缓冲与发送时拼装帧的临时缓冲都经由进程级的 [`BufferSource`] 取得，
+//! 默认每次向全局分配器申请。延迟敏感的场景（如 enclave 内的控制面）可用
+//! [`set_buffer_source()`] 换成预先分配、反复复用的缓冲池，避免收发路径上的分配：
+//!
+//! - 发送时的帧缓冲在写出后立即交还
+//! - 接收到的消息交给应用，用完后可用 [`recycle()`] 交还；不交还则照常释放
+//! - `recv_loan()` 的复用缓冲在换新时交还
+//!
+//! 缓冲以 `Vec<u8>` 的形式交给应用，必须来自全局分配器；需要 bump arena 或大页内存时，
+//! 由 `#[global_allocator]` 提供底层内存，本模块负责复用。[`BufferPool`] 是一个
+//! 按容量复用的简单实现。
+//!
+//! ```ignore
+//! use std::sync::Arc;
+//! use virga::buffers::{self, BufferPool};
+//!
+//! let pool = Arc::new(BufferPool::new(64));
+//! pool.prefill(16, 64 * 1024);
+//! buffers::set_buffer_source(Some(pool));
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+/// 提供与收回消息缓冲
+pub trait BufferSource: Send + Sync {
+    /// 取得一个长度为 0、容量不小于 `capacity` 的缓冲
+    fn acquire(&self, capacity: usize) -> Vec<u8>;
+
+    /// 收回不再使用的缓冲，默认直接释放
+    fn release(&self, buf: Vec<u8>) {
+        drop(buf);
+    }
+}
+
+static SOURCE: RwLock<Option<Arc<dyn BufferSource>>> = RwLock::new(None);
+
+/// 设置进程级的缓冲来源，`None` 恢复为直接向全局分配器申请；只影响之后取得的缓冲
+pub fn set_buffer_source(source: Option<Arc<dyn BufferSource>>) {
+    *SOURCE.write().unwrap_or_else(PoisonError::into_inner) = source;
+}
+
+fn source() -> Option<Arc<dyn BufferSource>> {
+    SOURCE
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// 把用完的消息缓冲交还给当前的缓冲来源
+pub fn recycle(buf: Vec<u8>) {
+    if buf.capacity() == 0 {
+        return;
+    }
+    if let Some(source) = source() {
+        source.release(buf);
+    }
+}
+
+/// 从当前的缓冲来源取得缓冲；来源给出的缓冲不满足约定时在此修正
+#[cfg(feature = "sync")]
+pub(crate) fn acquire(capacity: usize) -> Vec<u8> {
+    let Some(source) = source() else {
+        return Vec::with_capacity(capacity);
+    };
+    let mut buf = source.acquire(capacity);
+    buf.clear();
+    buf.reserve_exact(capacity);
+    buf
+}
+
+/// 按容量复用缓冲的池，最多保留 `max_buffers` 个空闲缓冲，超出的直接释放
+pub struct BufferPool {
+    max_buffers: usize,
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            max_buffers,
+            free: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 预先分配 `count` 个容量为 `capacity` 的缓冲，不超过保留上限
+    pub fn prefill(&self, count: usize, capacity: usize) {
+        let mut free = self.free.lock().unwrap_or_else(PoisonError::into_inner);
+        let room = self.max_buffers.saturating_sub(free.len());
+        free.extend((0..count.min(room)).map(|_| Vec::with_capacity(capacity)));
+    }
+
+    /// 当前空闲的缓冲数
+    pub fn available(&self) -> usize {
+        self.free
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+}
+
+impl BufferSource for BufferPool {
+    /// 取容量足够的空闲缓冲中最小的一个，没有时新分配
+    fn acquire(&self, capacity: usize) -> Vec<u8> {
+        let mut free = self.free.lock().unwrap_or_else(PoisonError::into_inner);
+        let best = free
+            .iter()
+            .enumerate()
+            .filter(|(_, buf)| buf.capacity() >= capacity)
+            .min_by_key(|(_, buf)| buf.capacity())
+            .map(|(i, _)| i);
+        match best {
+            Some(i) => free.swap_remove(i),
+            None => Vec::with_capacity(capacity),
+        }
+    }
+
+    fn release(&self, mut buf: Vec<u8>) {
+        let mut free = self.free.lock().unwrap_or_else(PoisonError::into_inner);
+        if free.len() < self.max_buffers {
+            buf.clear();
+            free.push(buf);
+        }
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("max_buffers", &self.max_buffers)
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_reuses_the_smallest_fitting_buffer() {
+        let pool = BufferPool::new(2);
+        pool.prefill(4, 16);
+        assert_eq!(pool.available(), 2);
+        pool.release(Vec::with_capacity(1024));
+        assert_eq!(pool.available(), 2);
+
+        let small = pool.acquire(8);
+        assert!(small.capacity() >= 8 && small.capacity() < 1024);
+        assert_eq!(pool.available(), 1);
+        let big = pool.acquire(100);
+        assert!(big.capacity() >= 100);
+        pool.release(small);
+        pool.release(big);
+        pool.release(vec![1, 2, 3]);
+        assert_eq!(pool.available(), 2);
+        assert!(pool.acquire(0).is_empty());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn acquire_repairs_a_misbehaving_source() {
+        struct Dirty;
+        impl BufferSource for Dirty {
+            fn acquire(&self, _capacity: usize) -> Vec<u8> {
+                vec![7; 4]
+            }
+        }
+        // 只在本测试内替换，其他测试不依赖缓冲来源
+        set_buffer_source(Some(Arc::new(Dirty)));
+        let buf = acquire(64);
+        set_buffer_source(None);
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 64);
+    }
+}
@@ -0,0 +1,365 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 熔断
+//!
+//! 宿主机服务不可用时，每个调用方都要等满连接或接收超时才得到错误。给
+//! `ClientConfig::with_circuit_breaker()` 配置 [`CircuitBreaker`] 后，客户端的连接、
+//! 发送与接收都先经过它：
+//!
+//! - 关闭：照常执行，记录最近 `window` 次操作的结果；其中至少 `min_calls` 次、且失败比例
+//!   达到 `failure_rate` 时打开
+//! - 打开：操作立即返回 `ConnectionRefused`，不接触连接；`cooldown` 之后转为半开
+//! - 半开：放行至多 `probes` 次探测，全部成功则关闭，任何一次失败则重新打开
+//!
+//! 只有连接断开、被拒绝、超时等说明对端不可用的错误计为失败，服务端回复的业务错误不计。
+//! 克隆得到的是同一个熔断器，放进多个客户端（或连接池）的配置即共享同一份状态。
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::addr::Addr;
+use crate::error::VirgeError;
+use crate::sources::{self, Clock};
+
+/// 熔断器的状态
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BreakerState {
+    /// 照常执行
+    Closed,
+    /// 快速失败，直到冷却结束
+    Open,
+    /// 放行少量探测
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum Phase {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { in_flight: usize, succeeded: usize },
+}
+
+#[derive(Debug)]
+struct State {
+    phase: Phase,
+    /// 关闭状态下最近的操作结果，`true` 为失败
+    outcomes: VecDeque<bool>,
+}
+
+/// 一次放行的操作
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Admission {
+    Normal,
+    Probe,
+}
+
+/// 熔断器，见[模块文档](self)
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    failure_rate: f64,
+    window: usize,
+    min_calls: usize,
+    cooldown: Duration,
+    probes: usize,
+    state: Arc<Mutex<State>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CircuitBreaker {
+    /// 默认最近 20 次中至少 5 次、失败过半时打开，冷却 5 秒，半开时探测 1 次
+    pub fn new() -> Self {
+        Self {
+            failure_rate: 0.5,
+            window: 20,
+            min_calls: 5,
+            cooldown: Duration::from_secs(5),
+            probes: 1,
+            state: Arc::new(Mutex::new(State {
+                phase: Phase::Closed,
+                outcomes: VecDeque::new(),
+            })),
+            clock: sources::clock(),
+        }
+    }
+
+    /// 打开所需的失败比例，取值 (0, 1]
+    pub fn with_failure_rate(mut self, rate: f64) -> Self {
+        self.failure_rate = rate;
+        self
+    }
+
+    /// 统计最近 `window` 次操作，其中至少 `min_calls` 次才可能打开
+    pub fn with_window(mut self, window: usize, min_calls: usize) -> Self {
+        self.window = window;
+        self.min_calls = min_calls;
+        self
+    }
+
+    /// 打开后经过 `cooldown` 转为半开
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// 半开时放行的探测次数，全部成功才关闭
+    pub fn with_probes(mut self, probes: usize) -> Self {
+        self.probes = probes;
+        self
+    }
+
+    /// 以 `clock` 计算冷却，默认为 [`sources::clock()`]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// 当前状态；打开且冷却已结束时为 `HalfOpen`
+    pub fn state(&self) -> BreakerState {
+        match self.lock().phase {
+            Phase::Closed => BreakerState::Closed,
+            Phase::Open { until } if self.clock.now() < until => BreakerState::Open,
+            Phase::Open { .. } | Phase::HalfOpen { .. } => BreakerState::HalfOpen,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        if !(self.failure_rate > 0.0 && self.failure_rate <= 1.0) {
+            return Err(VirgeError::ConfigError(format!(
+                "circuit breaker failure rate {} is not in (0, 1]",
+                self.failure_rate
+            )));
+        }
+        if self.window == 0 || self.min_calls == 0 || self.min_calls > self.window {
+            return Err(VirgeError::ConfigError(format!(
+                "circuit breaker needs 0 < min_calls ({}) <= window ({})",
+                self.min_calls, self.window
+            )));
+        }
+        if self.cooldown.is_zero() || self.probes == 0 {
+            return Err(VirgeError::ConfigError(
+                "circuit breaker cooldown and probes must be greater than zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// 经熔断器执行对 `addr` 的一次操作
+    pub(crate) fn call<T>(&self, addr: Addr, op: impl FnOnce() -> Result<T>) -> Result<T> {
+        let admission = self.admit(addr)?;
+        let result = op();
+        let failed = result.as_ref().is_err_and(super::is_unavailable);
+        self.record(addr, admission, failed);
+        result
+    }
+
+    fn admit(&self, addr: Addr) -> Result<Admission> {
+        let mut state = self.lock();
+        let now = self.clock.now();
+        if let Phase::Open { until } = state.phase {
+            if now < until {
+                return Err(Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!(
+                        "circuit open for {}, retry in {:?}",
+                        addr,
+                        until.duration_since(now)
+                    ),
+                ));
+            }
+            debug!("Circuit for {} half-open, probing", addr);
+            state.phase = Phase::HalfOpen {
+                in_flight: 0,
+                succeeded: 0,
+            };
+        }
+        match &mut state.phase {
+            Phase::Closed => Ok(Admission::Normal),
+            Phase::HalfOpen {
+                in_flight,
+                succeeded,
+            } if *in_flight + *succeeded < self.probes => {
+                *in_flight += 1;
+                Ok(Admission::Probe)
+            }
+            _ => Err(Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("circuit half-open for {}, probe in progress", addr),
+            )),
+        }
+    }
+
+    fn record(&self, addr: Addr, admission: Admission, failed: bool) {
+        let mut state = self.lock();
+        match (&mut state.phase, admission) {
+            (Phase::Closed, Admission::Normal) => {
+                state.outcomes.push_back(failed);
+                if state.outcomes.len() > self.window {
+                    state.outcomes.pop_front();
+                }
+                let calls = state.outcomes.len();
+                let failures = state.outcomes.iter().filter(|&&f| f).count();
+                if calls >= self.min_calls && failures as f64 >= self.failure_rate * calls as f64 {
+                    warn!(
+                        "Circuit for {} opened: {} of the last {} operations failed",
+                        addr, failures, calls
+                    );
+                    self.open(&mut state);
+                }
+            }
+            (
+                Phase::HalfOpen {
+                    in_flight,
+                    succeeded,
+                },
+                Admission::Probe,
+            ) => {
+                *in_flight -= 1;
+                if failed {
+                    warn!("Circuit for {} reopened: probe failed", addr);
+                    self.open(&mut state);
+                } else {
+                    *succeeded += 1;
+                    if *succeeded >= self.probes {
+                        info!("Circuit for {} closed", addr);
+                        state.phase = Phase::Closed;
+                        state.outcomes.clear();
+                    }
+                }
+            }
+            // 状态在操作期间已经改变，结果不再有意义
+            _ => {}
+        }
+    }
+
+    fn open(&self, state: &mut State) {
+        state.phase = Phase::Open {
+            until: self.clock.now() + self.cooldown,
+        };
+        state.outcomes.clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("failure_rate", &self.failure_rate)
+            .field("window", &self.window)
+            .field("min_calls", &self.min_calls)
+            .field("cooldown", &self.cooldown)
+            .field("probes", &self.probes)
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::ManualClock;
+
+    const ADDR: Addr = Addr::new(3, 1234);
+
+    fn fail(breaker: &CircuitBreaker) -> Result<()> {
+        breaker.call(ADDR, || Err(Error::from(ErrorKind::ConnectionReset)))
+    }
+
+    fn succeed(breaker: &CircuitBreaker) -> Result<()> {
+        breaker.call(ADDR, || Ok(()))
+    }
+
+    #[test]
+    fn opens_at_failure_rate_and_fails_fast() {
+        let breaker = CircuitBreaker::new()
+            .with_window(4, 4)
+            .with_cooldown(Duration::from_secs(60));
+        succeed(&breaker).unwrap();
+        succeed(&breaker).unwrap();
+        assert!(fail(&breaker).is_err());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(fail(&breaker).is_err());
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        let mut ran = false;
+        let err = breaker
+            .call(ADDR, || {
+                ran = true;
+                Ok(())
+            })
+            .unwrap_err();
+        assert!(!ran);
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(err.to_string().contains("circuit open for 3:1234"));
+    }
+
+    #[test]
+    fn application_errors_do_not_count() {
+        let breaker = CircuitBreaker::new().with_window(2, 2);
+        for _ in 0..4 {
+            let _ = breaker.call(ADDR, || -> Result<()> {
+                Err(Error::from(ErrorKind::PermissionDenied))
+            });
+        }
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn half_open_probe_closes_or_reopens() {
+        let cooldown = Duration::from_secs(5);
+        let clock = Arc::new(ManualClock::new());
+        let breaker = CircuitBreaker::new()
+            .with_window(1, 1)
+            .with_cooldown(cooldown)
+            .with_clock(clock.clone());
+        let _ = fail(&breaker);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        clock.advance(cooldown - Duration::from_millis(1));
+        assert_eq!(breaker.state(), BreakerState::Open);
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        let _ = fail(&breaker);
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        clock.advance(cooldown);
+        // 探测进行中时其他操作仍快速失败
+        let shared = breaker.clone();
+        breaker
+            .call(ADDR, || {
+                assert!(succeed(&shared).is_err());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[test]
+    fn validate_rejects_bad_settings() {
+        assert!(CircuitBreaker::new().validate().is_ok());
+        for breaker in [
+            CircuitBreaker::new().with_failure_rate(0.0),
+            CircuitBreaker::new().with_failure_rate(1.5),
+            CircuitBreaker::new().with_window(4, 5),
+            CircuitBreaker::new().with_window(0, 0),
+            CircuitBreaker::new().with_cooldown(Duration::ZERO),
+            CircuitBreaker::new().with_probes(0),
+        ] {
+            assert!(breaker.validate().is_err());
+        }
+    }
+}
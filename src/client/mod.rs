@@ -4,44 +4,421 @@
 
 //! 客户端模块
 
+mod virge_client;
+pub use virge_client::VirgeClient;
+
+/// 兼容旧路径 `virga::client::client_sync::VirgeClient`
 #[cfg(feature = "use-xtransport")]
-pub mod client_sync;
-#[cfg(feature = "use-xtransport")]
-pub use client_sync::VirgeClient;
+pub mod client_sync {
+    pub use super::VirgeClient;
+}
 
+/// 兼容旧路径 `virga::client::client_async::VirgeClient`
 #[cfg(feature = "use-yamux")]
-pub mod client_async;
-#[cfg(feature = "use-yamux")]
-pub use client_async::VirgeClient;
+pub mod client_async {
+    pub use super::VirgeClient;
+}
+
+mod breaker;
+mod pool;
+pub use breaker::{BreakerState, CircuitBreaker};
+pub use pool::{VirgeClientPool, DEFAULT_PING_TIMEOUT};
+
+use crate::addr::Addr;
+use crate::auth::TokenCredential;
+use crate::codec::Schema;
+#[cfg(feature = "compression")]
+use crate::compression::Dictionary;
+use crate::defaults::Defaults;
+use crate::error::VirgeError;
+use crate::events::{self, VirgaEvent};
+use crate::logging::log_event;
+use crate::retry::Backoff;
+use crate::transport::Coalescing;
+use crate::units::ByteSize;
+use crate::Leftovers;
+use log::*;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 /// 客户端配置
 #[derive(Clone, Debug)]
 pub struct ClientConfig {
     server_cid: u32,
     server_port: u32,
-    chunk_size: u32,
+    discovery_port: u32,
+    chunk_size: ByteSize,
     is_ack: bool,
+    send_window: usize,
+    #[allow(dead_code)]
+    adaptive_chunk: bool,
+    #[allow(dead_code)]
+    io_uring: bool,
+    #[allow(dead_code)]
+    read_queue_depth: usize,
+    #[allow(dead_code)]
+    max_protocol_version: u8,
+    strict: bool,
+    #[allow(dead_code)]
+    shm: Option<(PathBuf, ByteSize)>,
+    auth: Option<TokenCredential>,
+    schema: Option<Schema>,
+    #[cfg(feature = "compression")]
+    dictionaries: Vec<Dictionary>,
+    memory_limit: Option<ByteSize>,
+    coalescing: Option<Coalescing>,
+    leftovers: Leftovers,
+    goaway_reconnect: Option<Duration>,
+    circuit_breaker: Option<CircuitBreaker>,
+    snapshot_recovery: Option<Duration>,
 }
 
 impl Default for ClientConfig {
     fn default() -> Self {
+        let defaults = Defaults::get();
         Self {
-            server_cid: crate::DEFAULT_SERVER_CID as u32,
-            server_port: crate::DEFAULT_SERVER_PORT as u32,
-            chunk_size: crate::DEAFULT_CHUNK_SIZE as u32,
-            is_ack: crate::DEFAULT_IS_ACK,
+            server_cid: defaults.server_cid,
+            server_port: defaults.server_port,
+            discovery_port: defaults.discovery_port,
+            chunk_size: defaults.chunk_size(),
+            is_ack: defaults.is_ack,
+            send_window: defaults.send_window.get(),
+            adaptive_chunk: false,
+            io_uring: false,
+            read_queue_depth: defaults.read_queue_depth.get(),
+            max_protocol_version: crate::transport::MAX_PROTOCOL_VERSION,
+            strict: false,
+            shm: None,
+            auth: None,
+            schema: None,
+            #[cfg(feature = "compression")]
+            dictionaries: Vec::new(),
+            memory_limit: None,
+            coalescing: None,
+            leftovers: Leftovers::Error,
+            goaway_reconnect: None,
+            circuit_breaker: None,
+            snapshot_recovery: None,
         }
     }
 }
 
 impl ClientConfig {
     pub fn new(cid: u32, port: u32, chunk: u32, isack: bool) -> Self {
+        let defaults = Defaults::get();
         Self {
             server_cid: cid,
             server_port: port,
-            chunk_size: chunk,
+            discovery_port: defaults.discovery_port,
+            chunk_size: ByteSize::from(chunk),
             is_ack: isack,
+            send_window: defaults.send_window.get(),
+            adaptive_chunk: false,
+            io_uring: false,
+            read_queue_depth: defaults.read_queue_depth.get(),
+            max_protocol_version: crate::transport::MAX_PROTOCOL_VERSION,
+            strict: false,
+            shm: None,
+            auth: None,
+            schema: None,
+            #[cfg(feature = "compression")]
+            dictionaries: Vec::new(),
+            memory_limit: None,
+            coalescing: None,
+            leftovers: Leftovers::Error,
+            goaway_reconnect: None,
+            circuit_breaker: None,
+            snapshot_recovery: None,
+        }
+    }
+
+    /// 设置服务端地址
+    pub fn with_server_addr(mut self, addr: Addr) -> Self {
+        self.server_cid = addr.cid;
+        self.server_port = addr.port;
+        self
+    }
+
+    /// 服务端地址
+    pub fn server_addr(&self) -> Addr {
+        Addr::new(self.server_cid, self.server_port)
+    }
+
+    /// 设置 `connect_service()` 查询的发现服务端口，默认 `Defaults::get().discovery_port`
+    pub fn with_discovery_port(mut self, port: u32) -> Self {
+        self.discovery_port = port;
+        self
+    }
+
+    /// 连接服务端 CID 上发现服务的配置：沿用分片、确认与认证设置，不协商消息定义与压缩字典
+    pub(crate) fn discovery_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::default()
+            .with_server_addr(Addr::new(self.server_cid, self.discovery_port))
+            .with_chunk_size(self.chunk_size);
+        config.is_ack = self.is_ack;
+        config.auth = self.auth.clone();
+        config
+    }
+
+    /// 设置分片大小（单个数据包的最大字节数，含 16 字节包头）
+    pub fn with_chunk_size(mut self, size: ByteSize) -> Self {
+        self.chunk_size = size;
+        self
+    }
+
+    /// 设置发送窗口：大消息分片时同时在途（未确认）的最大包数
+    pub fn with_send_window(mut self, packets: usize) -> Self {
+        self.send_window = packets;
+        self
+    }
+
+    /// 开启自适应分片：以 chunk_size 为起点，根据吞吐和 ACK 延迟自动调整，
+    /// 当前值可通过 `stats().chunk_size` 查看
+    pub fn with_adaptive_chunk(mut self, enabled: bool) -> Self {
+        self.adaptive_chunk = enabled;
+        self
+    }
+
+    /// 连接建立后向服务端提议的最高分帧版本（默认 `MAX_PROTOCOL_VERSION`），
+    /// 双方取较小者，旧服务端不应答时保持 v1。设为 1 则不提议，仅说 v1 分帧。
+    /// 仅 XTransport 生效，当前所用版本可通过 `protocol_version()` 查看
+    pub fn with_max_protocol_version(mut self, version: u8) -> Self {
+        self.max_protocol_version = version;
+        self
+    }
+
+    /// 严格模式（默认关闭）：未知类型或不该出现的控制帧、超出定义长度的包头、与声明长度
+    /// 不符的分片等可疑情况按协议错误处理，接收返回错误；默认记录后跳过，
+    /// 以兼容较新的服务端。适合测试与两端版本一致的部署
+    pub fn with_strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    /// 使用 io_uring 读写 vsock，降低高消息速率下的系统调用开销（默认关闭）。
+    /// 需要启用 `use-io-uring` 特性，否则 `connect()` 返回配置错误
+    pub fn with_io_uring(mut self, enabled: bool) -> Self {
+        self.io_uring = enabled;
+        self
+    }
+
+    /// yamux 后台读任务最多预读的消息帧数（默认 64）：读任务持续把消息读入此队列，
+    /// `recv()` 从队列取出；队列满时暂停读取，由流控反压服务端。xtransport 下不使用
+    pub fn with_read_queue_depth(mut self, frames: usize) -> Self {
+        self.read_queue_depth = frames;
+        self
+    }
+
+    /// 启用共享内存快速通道：连接后以 `size` 字节创建 `path`（如 /dev/shm 文件，
+    /// 或虚拟机内的 ivshmem BAR）并向服务端提议，双方确认映射到同一内存后，
+    /// 大消息负载改经共享内存传输；服务端不支持时自动退回 vsock
+    pub fn with_shared_memory(mut self, path: impl Into<PathBuf>, size: ByteSize) -> Self {
+        self.shm = Some((path.into(), size));
+        self
+    }
+
+    /// 连接后以令牌向服务端认证，并校验服务端持有相同令牌；
+    /// 任一方校验失败时 `connect()` 返回 `PermissionDenied`
+    pub fn with_auth(mut self, credential: TokenCredential) -> Self {
+        self.auth = Some(credential);
+        self
+    }
+
+    /// 连接（及认证）后与服务端比对消息定义的指纹，不一致时 `connect()` 返回 `InvalidData`；
+    /// 结果可通过 `schema_match()` 查看
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// 添加压缩字典，按添加顺序向服务端提议；握手选出双方都有的字典后，
+    /// `send_compressed()` 的消息都使用它，结果可通过 `dictionary_id()` 查看。
+    /// 服务端也需配置字典，需要启用 `compression` 特性
+    #[cfg(feature = "compression")]
+    pub fn with_dictionary(mut self, dictionary: Dictionary) -> Self {
+        self.dictionaries.push(dictionary);
+        self
+    }
+
+    /// 本连接内部缓冲的上限，超过时每次接收前释放复用缓冲；
+    /// 进程级上限见 `virga::budget::set_global_limit()`
+    pub fn with_memory_limit(mut self, limit: ByteSize) -> Self {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// 开启发送合批：不超过 `max_bytes` 的小消息先攒在连接内，攒够 `max_bytes` 或最早
+    /// 一条已等待 `max_delay` 时作为一帧发出，对端按原边界逐条接收。等待时长在下一次
+    /// 发送时检查，接收、ping 前会先发出批次；最后一条消息后调用 `flush()`
+    pub fn with_coalescing(mut self, max_delay: Duration, max_bytes: ByteSize) -> Self {
+        self.coalescing = Some(Coalescing {
+            max_delay,
+            max_bytes,
+        });
+        self
+    }
+
+    /// 一条消息经 `Read` 读了一部分时 `recv()` 的处理方式，默认 [`Leftovers::Error`]
+    pub fn with_leftovers(mut self, leftovers: Leftovers) -> Self {
+        self.leftovers = leftovers;
+        self
+    }
+
+    /// 收到服务端 GOAWAY 后自动重连：下一次发送前若已收完到达的消息，先断开并在
+    /// `deadline` 内按 `connect_when_ready()` 的方式重新连接，再发送。默认不自动重连，
+    /// 由应用查看 `goaway()` 自行处理。服务端的会话状态不会随连接迁移
+    pub fn with_reconnect_on_goaway(mut self, deadline: Duration) -> Self {
+        self.goaway_reconnect = Some(deadline);
+        self
+    }
+
+    /// 连接、发送与接收经过熔断器：服务端持续不可用时快速失败，不必每次等满超时，
+    /// 见 [`CircuitBreaker`]。克隆的熔断器共享状态，可放进多个客户端的配置
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// 从虚拟机快照或 CRIU 检查点恢复后自动重连：收发前检测到时钟跳变、或收发返回
+    /// `EIO`/`ENODEV` 时，丢弃快照前的连接，发布 `VirgaEvent::ResumedFromSnapshot`，
+    /// 并在 `deadline` 内按 `connect_when_ready()` 的方式重新连接，见 [`crate::resume`]。
+    /// 因收发出错而重连时，该次操作返回 `ConnectionReset`，由调用方决定是否重发
+    pub fn with_snapshot_recovery(mut self, deadline: Duration) -> Self {
+        self.snapshot_recovery = Some(deadline);
+        self
+    }
+
+    pub fn circuit_breaker(&self) -> Option<&CircuitBreaker> {
+        self.circuit_breaker.as_ref()
+    }
+
+    /// 经熔断器（若配置）执行一次对服务端的操作
+    pub(crate) fn guarded<T>(&self, op: impl FnOnce() -> std::io::Result<T>) -> std::io::Result<T> {
+        match &self.circuit_breaker {
+            Some(breaker) => breaker.call(self.server_addr(), op),
+            None => op(),
+        }
+    }
+
+    /// 校验配置：分片大小、发送窗口、目标地址与共享内存大小，
+    /// 不合法时返回 `ConfigError`。`connect()` 会先调用此方法
+    pub fn validate(&self) -> crate::Result<()> {
+        crate::validate_common(self.chunk_size, self.send_window)?;
+        if self.server_port == 0 || self.server_port as usize == crate::VMADDR_PORT_ANY {
+            return Err(VirgeError::ConfigError(format!(
+                "invalid server_port {}",
+                self.server_port
+            )));
+        }
+        if self.server_cid as usize == crate::VMADDR_CID_ANY {
+            return Err(VirgeError::ConfigError(
+                "server_cid cannot be VMADDR_CID_ANY".to_string(),
+            ));
+        }
+        if self.read_queue_depth == 0 {
+            return Err(VirgeError::ConfigError(
+                "read_queue_depth must be at least 1".to_string(),
+            ));
+        }
+        crate::transport::validate_protocol_version(self.max_protocol_version)?;
+        #[cfg(feature = "use-xtransport")]
+        if let Some((path, size)) = &self.shm {
+            use crate::transport::xtransport::MIN_SHM_SIZE;
+            if size.as_usize() < MIN_SHM_SIZE {
+                return Err(VirgeError::ConfigError(format!(
+                    "shared memory {} is {} bytes, at least {} required",
+                    path.display(),
+                    size,
+                    MIN_SHM_SIZE
+                )));
+            }
+        }
+        if let Some(auth) = &self.auth {
+            auth.validate()?;
+        }
+        if let Some(schema) = &self.schema {
+            schema.validate()?;
+        }
+        #[cfg(feature = "compression")]
+        Dictionary::validate(&self.dictionaries)?;
+        if let Some(coalescing) = &self.coalescing {
+            coalescing.validate()?;
+        }
+        if let Some(breaker) = &self.circuit_breaker {
+            breaker.validate()?;
         }
+        Ok(())
+    }
+}
+
+/// 宿主机服务尚未监听（或 vsock 设备尚未就绪）时常见的连接错误
+fn is_not_ready(e: &Error) -> bool {
+    let os_error = e
+        .raw_os_error()
+        .or_else(|| e.get_ref()?.downcast_ref::<VirgeError>()?.raw_os_error());
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset | ErrorKind::TimedOut
+    ) || matches!(
+        os_error,
+        Some(libc::ENODEV | libc::ENXIO | libc::EHOSTUNREACH | libc::ENETUNREACH)
+    )
+}
+
+/// 连接已断开、被拒绝或对端尚未就绪：重连后可以重试，熔断器计为失败
+pub(crate) fn is_unavailable(e: &Error) -> bool {
+    is_not_ready(e)
+        || matches!(
+            e.kind(),
+            ErrorKind::ConnectionAborted
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::NotConnected
+                | ErrorKind::BrokenPipe
+        )
+}
+
+/// `connect_when_ready()` 的重试循环：对端未就绪时按指数退避重试 `connect`，
+/// 每次重试发布 `VirgaEvent::Reconnecting`；超过期限返回一个 `TimedOut` 错误，
+/// 其他错误立即返回
+pub(crate) fn retry_until_ready(
+    addr: Addr,
+    deadline: Duration,
+    mut connect: impl FnMut() -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let started = Instant::now();
+    let mut backoff = Backoff::new(deadline);
+    loop {
+        let e = match connect() {
+            Ok(()) => return Ok(()),
+            Err(e) if is_not_ready(&e) => e,
+            Err(e) => return Err(e),
+        };
+        let Some(delay) = backoff.next_delay() else {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "{} not ready after {:?} ({} attempts): {}",
+                    addr,
+                    started.elapsed(),
+                    backoff.attempt() + 1,
+                    e
+                ),
+            ));
+        };
+        log_event!(
+            Level::Debug,
+            "server not ready, retrying connect",
+            cid = addr.cid,
+            port = addr.port,
+            attempt = backoff.attempt(),
+        );
+        events::emit(VirgaEvent::Reconnecting {
+            cid: addr.cid,
+            port: addr.port,
+            attempt: backoff.attempt(),
+        });
+        backoff.wait(delay);
     }
 }
 
@@ -52,10 +429,10 @@ mod tests {
     #[test]
     fn client_config_default_values() {
         let config = ClientConfig::default();
-        assert_eq!(config.server_cid, crate::DEFAULT_SERVER_CID as u32);
-        assert_eq!(config.server_port, crate::DEFAULT_SERVER_PORT as u32);
-        assert_eq!(config.chunk_size, crate::DEAFULT_CHUNK_SIZE as u32);
-        assert_eq!(config.is_ack, crate::DEFAULT_IS_ACK);
+        assert_eq!(config.server_cid, Defaults::get().server_cid);
+        assert_eq!(config.server_port, Defaults::get().server_port);
+        assert_eq!(config.chunk_size, Defaults::get().chunk_size());
+        assert_eq!(config.is_ack, Defaults::get().is_ack);
     }
 
     #[test]
@@ -63,16 +440,24 @@ mod tests {
         let config = ClientConfig::new(200, 5678, 2048, true);
         assert_eq!(config.server_cid, 200);
         assert_eq!(config.server_port, 5678);
-        assert_eq!(config.chunk_size, 2048);
+        assert_eq!(config.chunk_size, ByteSize::kib(2));
         assert!(config.is_ack);
     }
 
+    #[test]
+    fn client_config_server_addr() {
+        let config = ClientConfig::default().with_server_addr("host:5005".parse().unwrap());
+        assert_eq!(config.server_cid, 2);
+        assert_eq!(config.server_port, 5005);
+        assert_eq!(config.server_addr().to_string(), "host:5005");
+    }
+
     #[test]
     fn client_config_new_zero() {
         let config = ClientConfig::new(0, 0, 0, false);
         assert_eq!(config.server_cid, 0);
         assert_eq!(config.server_port, 0);
-        assert_eq!(config.chunk_size, 0);
+        assert_eq!(config.chunk_size, ByteSize::b(0));
         assert!(!config.is_ack);
     }
 
@@ -81,7 +466,74 @@ mod tests {
         let config = ClientConfig::new(u32::MAX, u32::MAX, u32::MAX, true);
         assert_eq!(config.server_cid, u32::MAX);
         assert_eq!(config.server_port, u32::MAX);
-        assert_eq!(config.chunk_size, u32::MAX);
+        assert_eq!(config.chunk_size, ByteSize::from(u32::MAX));
+    }
+
+    #[test]
+    fn client_config_with_chunk_size() {
+        let config = ClientConfig::default().with_chunk_size("64KiB".parse().unwrap());
+        assert_eq!(config.chunk_size, ByteSize::kib(64));
+        assert!(config.validate().is_ok());
+        let config = ClientConfig::default().with_chunk_size(ByteSize::mib(1));
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn client_config_default_send_window() {
+        let config = ClientConfig::new(200, 5678, 2048, true);
+        assert_eq!(config.send_window, Defaults::get().send_window.get());
+    }
+
+    #[test]
+    fn client_config_with_send_window() {
+        let config = ClientConfig::default().with_send_window(4);
+        assert_eq!(config.send_window, 4);
+    }
+
+    #[test]
+    fn client_config_with_adaptive_chunk() {
+        assert!(!ClientConfig::default().adaptive_chunk);
+        assert!(
+            ClientConfig::default()
+                .with_adaptive_chunk(true)
+                .adaptive_chunk
+        );
+    }
+
+    #[test]
+    fn client_config_max_protocol_version_is_validated() {
+        let config = ClientConfig::default();
+        assert_eq!(
+            config.max_protocol_version,
+            crate::transport::MAX_PROTOCOL_VERSION
+        );
+        assert!(config
+            .clone()
+            .with_max_protocol_version(1)
+            .validate()
+            .is_ok());
+        for version in [0, crate::transport::MAX_PROTOCOL_VERSION + 1] {
+            assert!(matches!(
+                config.clone().with_max_protocol_version(version).validate(),
+                Err(VirgeError::ConfigError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn client_config_with_shared_memory() {
+        assert!(ClientConfig::default().shm.is_none());
+        let config = ClientConfig::default().with_shared_memory("/dev/shm/virga", ByteSize::mib(1));
+        assert_eq!(
+            config.shm,
+            Some((PathBuf::from("/dev/shm/virga"), ByteSize::mib(1)))
+        );
+    }
+
+    #[test]
+    fn client_config_io_uring_off_by_default() {
+        assert!(!ClientConfig::default().io_uring);
+        assert!(ClientConfig::default().with_io_uring(true).io_uring);
     }
 
     #[test]
@@ -93,4 +545,127 @@ mod tests {
         assert_eq!(config.chunk_size, cloned.chunk_size);
         assert_eq!(config.is_ack, cloned.is_ack);
     }
+
+    #[test]
+    fn client_config_validate_default_ok() {
+        assert!(ClientConfig::default().validate().is_ok());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn client_config_validates_dictionaries() {
+        let config = ClientConfig::default()
+            .with_dictionary(Dictionary::new(1, b"telemetry"))
+            .with_dictionary(Dictionary::new(2, b"logs"));
+        assert!(config.validate().is_ok());
+        assert_eq!(config.dictionaries.len(), 2);
+        let config = config.with_dictionary(Dictionary::new(2, b"other"));
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+    }
+
+    #[test]
+    fn client_config_validates_read_queue_depth() {
+        let config = ClientConfig::default().with_read_queue_depth(8);
+        assert!(config.validate().is_ok());
+        let config = config.with_read_queue_depth(0);
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+    }
+
+    #[test]
+    fn client_config_validates_coalescing() {
+        let config =
+            ClientConfig::default().with_coalescing(Duration::from_millis(1), ByteSize::kib(16));
+        assert!(config.validate().is_ok());
+        let config = config.with_coalescing(Duration::from_millis(1), ByteSize::b(0));
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+    }
+
+    #[test]
+    fn client_config_validate_rejects_bad_values() {
+        let cases = [
+            ClientConfig::new(103, 1234, 0, false),
+            ClientConfig::new(103, 1234, u32::MAX, false),
+            ClientConfig::new(103, 0, 1024, false),
+            ClientConfig::new(103, u32::MAX, 1024, false),
+            ClientConfig::new(u32::MAX, 1234, 1024, false),
+            ClientConfig::default().with_send_window(0),
+            ClientConfig::default().with_auth(TokenCredential::new("agent", b"")),
+            ClientConfig::default().with_schema(Schema::from_fingerprint("", [0; 32])),
+        ];
+        for config in cases {
+            match config.validate() {
+                Err(VirgeError::ConfigError(_)) => {}
+                other => panic!("expected ConfigError for {:?}, got {:?}", config, other),
+            }
+        }
+    }
+
+    #[cfg(feature = "use-xtransport")]
+    #[test]
+    fn client_config_validate_shm_size() {
+        let config = ClientConfig::default().with_shared_memory("/dev/shm/virga", ByteSize::kib(4));
+        assert!(config.validate().is_err());
+        let config = ClientConfig::default().with_shared_memory("/dev/shm/virga", ByteSize::mib(1));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn client_config_circuit_breaker() {
+        let config =
+            ClientConfig::default().with_circuit_breaker(CircuitBreaker::new().with_probes(0));
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+
+        let config =
+            ClientConfig::default().with_circuit_breaker(CircuitBreaker::new().with_window(1, 1));
+        let shared = config.clone();
+        let _ =
+            config.guarded(|| -> std::io::Result<()> { Err(Error::from(ErrorKind::BrokenPipe)) });
+        let breaker = shared.circuit_breaker().unwrap();
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!is_unavailable(&Error::from(ErrorKind::PermissionDenied)));
+        assert!(is_unavailable(&Error::from(ErrorKind::ConnectionReset)));
+    }
+
+    #[test]
+    fn retry_until_ready_retries_refused_connects() {
+        let events = events::subscribe();
+        let mut calls = 0;
+        retry_until_ready(Addr::new(3, 4200), Duration::from_secs(5), || {
+            calls += 1;
+            match calls {
+                1 => Err(Error::from(ErrorKind::ConnectionReset)),
+                2 => Err(VirgeError::connection_io(
+                    "Failed to connect vsock",
+                    Error::from_raw_os_error(libc::ENODEV),
+                )
+                .into()),
+                _ => Ok(()),
+            }
+        })
+        .unwrap();
+        assert_eq!(calls, 3);
+        let retries = std::iter::from_fn(|| events.try_recv())
+            .filter(|event| matches!(event, VirgaEvent::Reconnecting { port: 4200, .. }))
+            .count();
+        assert_eq!(retries, 2);
+    }
+
+    #[test]
+    fn retry_until_ready_times_out_cleanly() {
+        let err = retry_until_ready(Addr::new(3, 4201), Duration::from_millis(120), || {
+            Err(Error::from(ErrorKind::ConnectionRefused))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(err.to_string().contains("3:4201 not ready"));
+
+        let mut calls = 0;
+        let err = retry_until_ready(Addr::new(3, 4201), Duration::from_secs(5), || {
+            calls += 1;
+            Err(Error::from(ErrorKind::PermissionDenied))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(calls, 1);
+    }
 }
@@ -0,0 +1,173 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 客户端连接池
+//!
+//! [`VirgeClientPool::warm()`] 在启动时并发建立若干连接并逐个 ping 验证，
+//! 第一个用户请求直接取用已就绪的连接，不必承担连接与握手的延迟。
+//!
+//! ```ignore
+//! let pool = VirgeClientPool::new(ClientConfig::new(3, 1234, 1024, false));
+//! pool.warm(4)?;
+//! let mut client = pool.get()?;
+//! client.send(request)?;
+//! pool.put(client);
+//! ```
+
+use std::io::Result;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use super::{ClientConfig, VirgeClient};
+use crate::logging::log_event;
+use crate::threads;
+
+/// 预热时等待 ping 回应的默认时长
+pub const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 同一配置的客户端连接池，可在线程间共享
+pub struct VirgeClientPool {
+    config: ClientConfig,
+    ping_timeout: Duration,
+    idle: Mutex<Vec<VirgeClient>>,
+}
+
+impl VirgeClientPool {
+    pub fn new(config: ClientConfig) -> Self {
+        Self {
+            config,
+            ping_timeout: DEFAULT_PING_TIMEOUT,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 预热时等待 ping 回应的时长
+    pub fn with_ping_timeout(mut self, timeout: Duration) -> Self {
+        self.ping_timeout = timeout;
+        self
+    }
+
+    /// 并发建立 `n` 个连接，ping 通过的放入池中，返回放入的数量。
+    /// 部分失败只记录日志；全部失败时返回第一个错误
+    pub fn warm(&self, n: usize) -> Result<usize> {
+        self.config.validate()?;
+        let started = Instant::now();
+        let results: Vec<Result<VirgeClient>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..n)
+                .map(|i| {
+                    let spawned =
+                        threads::spawn_scoped(scope, format!("warm-{}", i), || self.connect_one());
+                    spawned.expect("failed to spawn pool warm-up thread")
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("pool warm-up thread panicked"))
+                .collect()
+        });
+
+        let mut warmed = Vec::with_capacity(n);
+        let mut first_error = None;
+        for result in results {
+            match result {
+                Ok(client) => warmed.push(client),
+                Err(e) => {
+                    log_event!(
+                        Level::Warn,
+                        "pool warm-up connection failed",
+                        cid = self.config.server_cid,
+                        port = self.config.server_port,
+                        error = e.to_string().as_str(),
+                    );
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if let (true, Some(e)) = (warmed.is_empty(), first_error) {
+            return Err(e);
+        }
+
+        let count = warmed.len();
+        log_event!(
+            Level::Info,
+            "pool warmed",
+            cid = self.config.server_cid,
+            port = self.config.server_port,
+            connections = count as u64,
+            duration_ms = started.elapsed().as_millis() as u64,
+        );
+        self.lock().extend(warmed);
+        Ok(count)
+    }
+
+    fn connect_one(&self) -> Result<VirgeClient> {
+        let mut client = VirgeClient::new(self.config.clone());
+        client.connect()?;
+        client.ping(self.ping_timeout)?;
+        Ok(client)
+    }
+
+    /// 取出一个空闲连接，池中没有时新建一个
+    pub fn get(&self) -> Result<VirgeClient> {
+        if let Some(client) = self.lock().pop() {
+            return Ok(client);
+        }
+        let mut client = VirgeClient::new(self.config.clone());
+        client.connect()?;
+        Ok(client)
+    }
+
+    /// 归还连接；已断开、还有未读数据或收到服务端 GOAWAY 的连接直接丢弃，
+    /// 之后的 `get()` 会新建连接
+    pub fn put(&self, client: VirgeClient) {
+        if client.is_connected() && client.no_has_data() && client.goaway().is_none() {
+            self.lock().push(client);
+        }
+    }
+
+    /// 池中空闲连接数
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<VirgeClient>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl std::fmt::Debug for VirgeClientPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VirgeClientPool")
+            .field("config", &self.config)
+            .field("ping_timeout", &self.ping_timeout)
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn warm_rejects_invalid_config() {
+        let pool = VirgeClientPool::new(ClientConfig::new(3, 0, 1024, false));
+        let err = pool.warm(2).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(pool.idle(), 0);
+    }
+
+    #[test]
+    fn warm_zero_and_put_disconnected() {
+        let pool = VirgeClientPool::new(ClientConfig::default())
+            .with_ping_timeout(Duration::from_millis(100));
+        assert_eq!(pool.warm(0).unwrap(), 0);
+        pool.put(VirgeClient::new(ClientConfig::default()));
+        assert_eq!(pool.idle(), 0);
+    }
+}
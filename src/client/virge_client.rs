@@ -0,0 +1,1073 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+use std::io::{Error, ErrorKind, Result};
+use std::io::{IoSliceMut, Read, Write};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use super::ClientConfig;
+use crate::auth::DEFAULT_HANDSHAKE_TIMEOUT;
+use crate::clock::ClockOffset;
+use crate::codec::{Codec, SchemaMatch};
+#[cfg(feature = "compression")]
+use crate::compression::{connect_dictionary, CompressionContext};
+use crate::discovery::{self, ServiceRecord};
+use crate::events::{self, Role, VirgaEvent};
+use crate::logging::log_event;
+use crate::resume::ResumeDetector;
+use crate::selftest::{self, SelfTestReport, SELF_TEST_TIMEOUT};
+use crate::stats::ConnectionStats;
+use crate::transport::RecvLoan;
+#[cfg(feature = "use-xtransport")]
+use crate::transport::XTransportHandler;
+#[cfg(feature = "use-yamux")]
+use crate::transport::{VirgaStream, YamuxTransportHandler};
+use crate::{AtBoundary, ConnContext, GoAwayReason, Leftovers, ReadState};
+
+/// 由 feature 选定的传输实现：`use-xtransport` 下为 XTransport，`use-yamux` 下为
+/// 内部通过 tokio runtime 驱动的 yamux
+#[cfg(feature = "use-xtransport")]
+type TransportHandler = XTransportHandler;
+#[cfg(feature = "use-yamux")]
+type TransportHandler = YamuxTransportHandler;
+
+/// 按配置创建传输实现，各实现支持的选项不同，其余选项被忽略
+#[cfg(feature = "use-xtransport")]
+fn transport_handler(config: &ClientConfig) -> TransportHandler {
+    let handler = XTransportHandler::new()
+        .with_send_window(config.send_window)
+        .with_adaptive_chunk(config.adaptive_chunk)
+        .with_io_uring(config.io_uring)
+        .with_max_version(config.max_protocol_version)
+        .with_strict(config.strict)
+        .with_memory_limit(config.memory_limit)
+        .with_coalescing(config.coalescing);
+    match &config.shm {
+        Some((path, size)) => handler.with_shared_memory(path.clone(), size.as_usize()),
+        None => handler,
+    }
+}
+
+#[cfg(feature = "use-yamux")]
+fn transport_handler(config: &ClientConfig) -> TransportHandler {
+    YamuxTransportHandler::new(yamux::Mode::Client)
+        .with_strict(config.strict)
+        .with_memory_limit(config.memory_limit)
+        .with_coalescing(config.coalescing)
+        .with_read_queue_depth(config.read_queue_depth)
+}
+
+/// 同步客户端，两种传输实现共用同一份实现
+pub struct VirgeClient {
+    transport_handler: TransportHandler,
+    config: ClientConfig,
+    connected: bool,
+    read_buffer: Vec<u8>,  // 读取缓存
+    read_state: ReadState, // 读取状态
+    schema_match: Option<SchemaMatch>,
+    resume: Option<ResumeDetector>,
+}
+
+impl VirgeClient {
+    pub fn new(config: ClientConfig) -> Self {
+        let resume = config.snapshot_recovery.map(|_| ResumeDetector::new());
+        Self {
+            transport_handler: transport_handler(&config),
+            config,
+            connected: false,
+            read_buffer: Vec::new(),
+            read_state: ReadState::Idle,
+            schema_match: None,
+            resume,
+        }
+    }
+
+    /// 建立连接
+    pub fn connect(&mut self) -> Result<()> {
+        log_event!(
+            Level::Info,
+            "client connecting",
+            cid = self.config.server_cid,
+            port = self.config.server_port,
+        );
+        let started = Instant::now();
+
+        self.config.validate()?;
+        self.config.guarded(|| {
+            self.transport_handler
+                .connect(
+                    self.config.server_cid,
+                    self.config.server_port,
+                    self.config.chunk_size.as_u64() as u32,
+                    self.config.is_ack,
+                )
+                .map_err(Error::from)
+        })?;
+        if let Err(e) = self
+            .authenticate()
+            .and_then(|_| self.negotiate_schema())
+            .and_then(|_| self.negotiate_dictionary())
+        {
+            let conn = self.transport_handler.conn();
+            log_event!(
+                Level::Warn,
+                "client handshake failed",
+                conn_id = conn.conn_id,
+                cid = conn.cid,
+                duration_ms = started.elapsed().as_millis() as u64,
+            );
+            events::emit(VirgaEvent::HandshakeFailed {
+                role: Role::Client,
+                conn: self.transport_handler.conn(),
+                reason: e.to_string(),
+            });
+            let _ = self.transport_handler.disconnect();
+            return Err(e);
+        }
+        self.connected = true;
+        let conn = self.transport_handler.conn();
+        log_event!(
+            Level::Info,
+            "client connected",
+            conn_id = conn.conn_id,
+            cid = conn.cid,
+            port = conn.port,
+            duration_ms = started.elapsed().as_millis() as u64,
+        );
+        events::emit(VirgaEvent::Connected {
+            role: Role::Client,
+            conn,
+        });
+        Ok(())
+    }
+
+    /// 经服务端 CID 上的发现服务查出 `name` 的端口后连接，返回查到的登记；
+    /// 之后重连使用同一端口。发现服务的端口见 `ClientConfig::with_discovery_port()`
+    pub fn connect_service(&mut self, name: &str) -> Result<ServiceRecord> {
+        let record = discovery::lookup(&self.config, name)?;
+        self.config.server_port = record.port;
+        self.connect()?;
+        Ok(record)
+    }
+
+    /// 在 `deadline` 内反复尝试连接，直到宿主机服务开始监听（虚拟机启动时常见的竞争）。
+    /// 连接被拒绝、重置或超时时按指数退避重试，每次重试发布 `VirgaEvent::Reconnecting`；
+    /// 期限内仍未连上时返回一个 `TimedOut` 错误，配置错误、认证失败等立即返回
+    pub fn connect_when_ready(&mut self, deadline: Duration) -> Result<()> {
+        self.config.validate()?;
+        let addr = self.config.server_addr();
+        super::retry_until_ready(addr, deadline, || self.connect())
+    }
+
+    /// 配置了凭据时在连接上完成认证握手
+    fn authenticate(&mut self) -> Result<()> {
+        let Some(auth) = &self.config.auth else {
+            return Ok(());
+        };
+        self.transport_handler
+            .set_idle_timeout(Some(auth.timeout()))?;
+        let secure = auth.connect(&mut self.transport_handler)?;
+        self.transport_handler.set_idle_timeout(None)?;
+        self.transport_handler.set_secure(secure);
+        Ok(())
+    }
+
+    /// 配置了消息定义时与服务端比对指纹
+    fn negotiate_schema(&mut self) -> Result<()> {
+        let Some(schema) = &self.config.schema else {
+            return Ok(());
+        };
+        self.transport_handler
+            .set_idle_timeout(Some(DEFAULT_HANDSHAKE_TIMEOUT))?;
+        let verdict = schema.connect(&mut self.transport_handler)?;
+        self.transport_handler.set_idle_timeout(None)?;
+        self.schema_match = Some(verdict);
+        Ok(())
+    }
+
+    /// 配置了压缩字典时与服务端选出共同的字典
+    fn negotiate_dictionary(&mut self) -> Result<()> {
+        #[cfg(feature = "compression")]
+        if !self.config.dictionaries.is_empty() {
+            self.transport_handler
+                .set_idle_timeout(Some(DEFAULT_HANDSHAKE_TIMEOUT))?;
+            let dictionary =
+                connect_dictionary(&self.config.dictionaries, &mut self.transport_handler)?;
+            self.transport_handler.set_idle_timeout(None)?;
+            self.transport_handler
+                .set_compression(CompressionContext::new(dictionary));
+        }
+        Ok(())
+    }
+
+    /// 消息定义协商结果，未配置 `with_schema()` 时为 `None`
+    pub fn schema_match(&self) -> Option<SchemaMatch> {
+        self.schema_match
+    }
+
+    /// 握手协商出的压缩字典 ID，未配置字典或没有共同字典时为 `None`
+    #[cfg(feature = "compression")]
+    pub fn dictionary_id(&self) -> Option<u32> {
+        self.transport_handler.dictionary_id()
+    }
+
+    /// 断开连接
+    pub fn disconnect(&mut self) -> Result<()> {
+        let stats = self.transport_handler.stats();
+        let conn = self.transport_handler.conn();
+        log_event!(
+            Level::Info,
+            "client disconnecting",
+            conn_id = conn.conn_id,
+            cid = conn.cid,
+            bytes_sent = stats.bytes_sent,
+            bytes_received = stats.bytes_received,
+        );
+        if !self.read_buffer.is_empty() {
+            warn!(
+                "Disconnecting with {} bytes of unread data in buffer",
+                self.read_buffer.len()
+            );
+            return Err(Error::other(format!(
+                "Cannot disconnect: {} bytes of unread data remaining",
+                self.read_buffer.len()
+            )));
+        }
+
+        self.transport_handler.close(None)?;
+        self.connected = false;
+        events::emit(VirgaEvent::Disconnected {
+            role: Role::Client,
+            conn,
+        });
+        Ok(())
+    }
+
+    /// 发送数据
+    pub fn send(&mut self, data: Vec<u8>) -> Result<usize> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        self.follow_goaway()?;
+        self.follow_resume()?;
+
+        let sent = self
+            .config
+            .guarded(|| self.transport_handler.send(&data).map_err(Error::from));
+        sent.map_err(|e| self.recover(e))
+    }
+
+    /// 以 zstd 压缩后发送，对端接收时自动解压；返回原文长度。
+    /// 负载本身已压缩（图片、密文等）时用 `send()` 即可省去压缩开销
+    #[cfg(feature = "compression")]
+    pub fn send_compressed(&mut self, data: &[u8]) -> Result<usize> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        self.follow_goaway()?;
+        self.follow_resume()?;
+
+        let sent = self.config.guarded(|| {
+            self.transport_handler
+                .send_compressed(data)
+                .map_err(Error::from)
+        });
+        sent.map_err(|e| self.recover(e))
+    }
+
+    /// 接收数据
+    pub fn recv(&mut self) -> Result<Vec<u8>> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        if self.read_state != ReadState::Idle {
+            match self.config.leftovers {
+                Leftovers::Error => self.read_state.at_boundary(self.read_buffer.len())?,
+                Leftovers::DrainFirst => return Ok(self.take_partial()),
+                Leftovers::Prepend => {}
+            }
+        }
+        self.follow_resume()?;
+
+        let data = match self
+            .config
+            .guarded(|| self.transport_handler.recv().map_err(Error::from))
+        {
+            Ok(data) => data,
+            Err(e) => return Err(self.recover(e)),
+        };
+        if self.read_state == ReadState::Idle {
+            return Ok(data);
+        }
+        // Leftovers::Prepend
+        let mut message = self.take_partial();
+        message.extend_from_slice(&data);
+        Ok(message)
+    }
+
+    /// 把 `data` 作为一条完整的消息发送，返回发送字节数。对端用 `recv()` 整条接收，
+    /// 或用 `recv_sized()` 得知长度后经 `Read` 读取，消息前无需另加长度前缀
+    pub fn send_sized(&mut self, data: &[u8]) -> Result<usize> {
+        Write::write(self, data)
+    }
+
+    /// 把下一条消息交给 `Read` 读取并返回其长度，之后 `read_exact()` 读满这个长度即读完
+    /// 整条消息；上一条消息还没经 `Read` 读完时不接收新消息，返回其剩余长度
+    pub fn recv_sized(&mut self) -> Result<usize> {
+        if self.read_state == ReadState::Idle {
+            let data = self.recv()?;
+            if !data.is_empty() {
+                self.read_state = ReadState::Reading {
+                    total: data.len(),
+                    read: 0,
+                };
+                self.read_buffer = data;
+                self.transport_handler.set_pending(self.read_buffer.len());
+            }
+        }
+        Ok(self.read_buffer.len())
+    }
+
+    /// 取出经 `Read` 读了一部分的消息的其余字节，回到消息边界，之后可以继续用 `recv()`
+    /// 等消息接口；没有读了一半的消息时返回空
+    pub fn take_partial(&mut self) -> Vec<u8> {
+        self.read_state = ReadState::Idle;
+        self.transport_handler.set_pending(0);
+        std::mem::take(&mut self.read_buffer)
+    }
+
+    /// 协议出错后重新对齐到消息边界，而不必重连：丢弃经 `Read` 读了一部分的消息，
+    /// 并让下一次接收跳过上次接收失败时残留的分片。已完整收到、尚未取走的消息保留。
+    /// 返回丢弃的已缓冲字节数；包头损坏或读到一半超时造成的字节级错位无法恢复
+    pub fn resync(&mut self) -> usize {
+        let discarded = self.take_partial().len();
+        self.transport_handler.resync();
+        if discarded > 0 {
+            debug!("Resync discarded {} buffered bytes", discarded);
+        }
+        discarded
+    }
+
+    /// 接收一条消息到连接内部复用的缓冲区，省去为每条消息分配 `Vec`；
+    /// 返回的视图借用连接，在下一次接收或被 drop 之前有效
+    pub fn recv_loan(&mut self) -> Result<RecvLoan<'_>> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        self.read_state.at_boundary(self.read_buffer.len())?;
+        self.follow_resume()?;
+
+        self.config
+            .guarded(|| self.transport_handler.recv_loan().map_err(Error::from))
+    }
+
+    /// 接收一条消息并按顺序填入多个缓冲区（如消息头、消息体各一块），
+    /// 返回消息长度；消息长于缓冲总长时多余部分被丢弃，返回值大于缓冲总长
+    pub fn recv_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        self.read_state.at_boundary(self.read_buffer.len())?;
+        self.follow_resume()?;
+
+        let received = self.config.guarded(|| {
+            self.transport_handler
+                .recv_vectored(bufs)
+                .map_err(Error::from)
+        });
+        received.map_err(|e| self.recover(e))
+    }
+
+    /// 以 `codec` 编码 `value` 后作为一条消息发送，返回编码后的字节数
+    pub fn send_encoded<T, C: Codec<T>>(&mut self, codec: &C, value: &T) -> Result<usize> {
+        let data = codec.encode(value)?;
+        self.send(data)
+    }
+
+    /// 接收一条消息并以 `codec` 解码，格式不符时返回 `InvalidData`
+    pub fn recv_decoded<T, C: Codec<T>>(&mut self, codec: &C) -> Result<T> {
+        let loan = self.recv_loan()?;
+        codec.decode(&loan)
+    }
+
+    /// 以一行 JSON 发送 `value`（JSON 行模式）
+    #[cfg(feature = "codec-json")]
+    pub fn send_json(&mut self, value: &serde_json::Value) -> Result<usize> {
+        self.send(crate::codec::json::encode_line(value)?)
+    }
+
+    /// 接收一条消息并解析为 JSON 值，格式不符时返回 `InvalidData`
+    #[cfg(feature = "codec-json")]
+    pub fn recv_json(&mut self) -> Result<serde_json::Value> {
+        let loan = self.recv_loan()?;
+        crate::codec::json::decode_line(&loan)
+    }
+
+    /// 把连接交给后台线程持续接收，返回解析后的 JSON 值流
+    #[cfg(feature = "codec-json")]
+    pub fn into_json_stream(mut self) -> crate::codec::JsonStream {
+        let conn = self.transport_handler.conn();
+        crate::codec::JsonStream::spawn(conn, move || self.recv())
+    }
+
+    /// 把连接交给后台线程持续接收，超过内存上限的消息暂存到临时文件，见 [`crate::spool`]
+    pub fn into_spooled(
+        mut self,
+        config: crate::spool::SpoolConfig,
+    ) -> Result<crate::spool::Spool> {
+        let conn = self.transport_handler.conn();
+        crate::spool::Spool::spawn(conn, config, move || self.recv())
+    }
+
+    /// 把连接交给后台线程，在其上复用多个逻辑通道，见 [`crate::mux`]
+    pub fn into_mux(self) -> Result<crate::mux::Mux> {
+        let conn = self.transport_handler.conn();
+        crate::mux::Mux::spawn(conn, self)
+    }
+
+    /// 把连接变为类型化的请求/回复会话，见 [`crate::codec::Protocol`]
+    pub fn into_protocol<Req, Resp, C>(
+        self,
+        codec: C,
+        role: crate::codec::Role,
+    ) -> crate::codec::Protocol<Req, Resp, C>
+    where
+        C: crate::codec::Codec<Req> + crate::codec::Codec<Resp>,
+    {
+        crate::codec::Protocol::new(self, codec, role)
+    }
+
+    /// 等待下一条消息到达、被唤醒或超过 `timeout`，有消息可接收时返回 `true`
+    pub(crate) fn wait_readable(
+        &mut self,
+        wake: Option<&crate::mux::Wake>,
+        timeout: Option<Duration>,
+    ) -> Result<bool> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        self.transport_handler
+            .wait_readable(wake, timeout)
+            .map_err(Error::from)
+    }
+
+    /// 在同一条 vsock 连接上打开一条附加流，返回可移交给其他线程的 `VirgaStream`，
+    /// 各条流独立收发、互不阻塞；服务端以 `accept_stream()` 接受。
+    /// 附加流不经过认证建立的加密，配置了凭据时返回 `Unsupported`
+    #[cfg(feature = "use-yamux")]
+    pub fn open_stream(&mut self) -> Result<VirgaStream> {
+        if !self.is_connected() {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        Ok(self.transport_handler.open_stream()?)
+    }
+
+    /// 发送 ping 并等待服务端回应，返回往返时长。服务端在接收消息时自动回应
+    /// （yamux 下由后台读任务收到即回应），
+    /// 超过 `timeout` 未回应返回超时错误
+    pub fn ping(&mut self, timeout: Duration) -> Result<Duration> {
+        if !self.is_connected() {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        Ok(self.transport_handler.ping(timeout)?)
+    }
+
+    /// 在消息流之外发送一个旁路帧，见 [`crate::transport::register_frame_handler()`]：
+    /// 服务端在接收消息时（yamux 下由后台读任务）把它交给注册的处理函数，不进入 `recv()`。旁路帧不加密，不计入统计
+    pub fn send_extension(&mut self, frame_type: u8, data: &[u8]) -> Result<()> {
+        if !self.is_connected() {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        Ok(self.transport_handler.send_extension(frame_type, data)?)
+    }
+
+    /// 与服务端交换 `rounds` 轮时间戳，估计对端时钟相对本端的偏差，见 [`crate::clock`]；
+    /// 每轮超过 `timeout` 未回复返回超时错误
+    pub fn time_sync(&mut self, rounds: usize, timeout: Duration) -> Result<ClockOffset> {
+        if !self.is_connected() {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        crate::clock::sync(rounds, || {
+            self.transport_handler
+                .time_probe(timeout)
+                .map_err(Error::from)
+        })
+    }
+
+    /// 与配合的服务端（调用 `serve_self_test()`）执行内置自检：ping、小消息与 1 MiB 消息回显、
+    /// 交错发送后乱序返回的多组消息，见 [`crate::selftest`]。单项失败记录在报告中，
+    /// 只有连接不可用时返回错误
+    pub fn self_test(&mut self) -> Result<SelfTestReport> {
+        if !self.is_connected() {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        self.read_state.at_boundary(self.read_buffer.len())?;
+        Ok(selftest::run(&mut self.transport_handler, |handler| {
+            handler.ping(SELF_TEST_TIMEOUT)
+        })?)
+    }
+
+    /// 服务端发来 GOAWAY 时返回其原因：应在完成手头的请求后断开并重新连接，
+    /// 配置 `with_reconnect_on_goaway()` 时由下一次发送自动完成。
+    /// XTransport 只在接收消息时处理控制包，因此在收到回复后检查；yamux 下后台读任务
+    /// 收到即记录，不必等应用接收
+    pub fn goaway(&self) -> Option<GoAwayReason> {
+        self.transport_handler.goaway()
+    }
+
+    /// 配置了 `with_reconnect_on_goaway()` 且服务端已发来 GOAWAY 时，等已到达的消息
+    /// 都被取走后断开并重连
+    fn follow_goaway(&mut self) -> Result<()> {
+        let (Some(deadline), Some(reason)) = (self.config.goaway_reconnect, self.goaway()) else {
+            return Ok(());
+        };
+        if !self.no_has_data() || self.wait_readable(None, Some(Duration::ZERO))? {
+            return Ok(());
+        }
+        info!("Server sent GOAWAY ({:?}), reconnecting", reason);
+        self.disconnect()?;
+        self.connect_when_ready(deadline)
+    }
+
+    /// 配置了 `with_snapshot_recovery()` 且自上次收发以来时钟跳变时，丢弃快照前的连接并重连
+    fn follow_resume(&mut self) -> Result<()> {
+        let Some(jump) = self.resume.as_mut().and_then(ResumeDetector::check) else {
+            return Ok(());
+        };
+        self.reestablish(format!("clock jumped by {:?}", jump))
+    }
+
+    /// 收发因快照恢复而失败时重连，并把错误换成 `ConnectionReset`
+    fn recover(&mut self, e: Error) -> Error {
+        if self.resume.is_none() || !crate::resume::is_resume_error(&e) {
+            return e;
+        }
+        match self.reestablish(e.to_string()) {
+            Ok(()) => Error::new(
+                ErrorKind::ConnectionReset,
+                format!("connection lost to snapshot restore ({}), reconnected", e),
+            ),
+            Err(reconnect) => reconnect,
+        }
+    }
+
+    fn reestablish(&mut self, reason: String) -> Result<()> {
+        let Some(deadline) = self.config.snapshot_recovery else {
+            return Ok(());
+        };
+        warn!(
+            "Resumed from snapshot ({}), reconnecting to {}",
+            reason,
+            self.config.server_addr()
+        );
+        events::emit(VirgaEvent::ResumedFromSnapshot {
+            role: Role::Client,
+            cid: self.config.server_cid,
+            port: self.config.server_port,
+            reason,
+        });
+        // 快照前的连接在对端早已不存在，断开时的错误没有意义
+        let _ = self.transport_handler.disconnect();
+        self.connected = false;
+        self.take_partial();
+        self.connect_when_ready(deadline)
+    }
+
+    /// 检查连接状态
+    pub fn is_connected(&self) -> bool {
+        self.connected && self.transport_handler.is_connected()
+    }
+
+    /// 获取连接统计信息
+    pub fn stats(&self) -> ConnectionStats {
+        self.transport_handler.stats()
+    }
+
+    /// 连接上下文：连接 ID 与对端地址
+    pub fn conn(&self) -> ConnContext {
+        self.transport_handler.conn()
+    }
+
+    /// 连接当前使用的分帧版本：服务端应答提议前为 1，见
+    /// `ClientConfig::with_max_protocol_version()`
+    pub fn protocol_version(&self) -> u8 {
+        self.transport_handler.protocol_version()
+    }
+}
+
+impl VirgeClient {
+    fn read_new_message(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.follow_resume()?;
+        match self
+            .config
+            .guarded(|| self.transport_handler.recv().map_err(Error::from))
+        {
+            Ok(data) => {
+                if data.len() <= buf.len() {
+                    buf[..data.len()].copy_from_slice(&data);
+                    Ok(data.len())
+                } else {
+                    let len = buf.len();
+                    buf.copy_from_slice(&data[..len]);
+                    self.read_buffer.extend_from_slice(&data[len..]);
+                    self.transport_handler.set_pending(self.read_buffer.len());
+
+                    self.read_state = ReadState::Reading {
+                        total: data.len(),
+                        read: len,
+                    };
+                    Ok(len)
+                }
+            }
+            Err(e) => Err(self.recover(e)),
+        }
+    }
+
+    /// 检查是否还有数据可读（包括rbuf中的数据）
+    pub fn no_has_data(&self) -> bool {
+        self.read_buffer.is_empty() && self.read_state == ReadState::Idle
+    }
+}
+
+impl Read for VirgeClient {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+
+        match self.read_state {
+            ReadState::Idle => {
+                // 直接从传输层读取
+                self.read_new_message(buf)
+            }
+            ReadState::Reading { .. } => {
+                // 从rbuf中读取剩余数据
+                if !self.read_buffer.is_empty() {
+                    let len = std::cmp::min(self.read_buffer.len(), buf.len());
+                    buf[..len].copy_from_slice(&self.read_buffer[..len]);
+                    self.read_buffer.drain(..len);
+                    self.transport_handler.set_pending(self.read_buffer.len());
+
+                    self.read_state.advance(len);
+                    Ok(len)
+                } else {
+                    // rbuf为空但状态是Reading，这不应该发生
+                    self.read_state = ReadState::Idle;
+                    Ok(0)
+                }
+            }
+        }
+    }
+}
+
+impl Write for VirgeClient {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
+        }
+        self.follow_goaway()?;
+        self.follow_resume()?;
+
+        let sent = self
+            .config
+            .guarded(|| self.transport_handler.send(buf).map_err(Error::from));
+        sent.map_err(|e| self.recover(e))
+    }
+
+    /// 发出开启合批时尚未发送的批次
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.transport_handler.flush().map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientConfig;
+    use std::io::{Read, Write};
+
+    fn make_client() -> VirgeClient {
+        let config = ClientConfig::default();
+        VirgeClient::new(config)
+    }
+
+    #[test]
+    fn new_client_not_connected() {
+        let client = make_client();
+        assert!(!client.is_connected());
+        assert!(!client.connected);
+    }
+
+    #[test]
+    fn new_client_empty_read_buffer() {
+        let client = make_client();
+        assert!(client.read_buffer.is_empty());
+        assert_eq!(client.read_state, ReadState::Idle);
+    }
+
+    #[test]
+    fn no_has_data_initially_true() {
+        let client = make_client();
+        assert!(client.no_has_data());
+    }
+
+    #[test]
+    fn connect_when_ready_rejects_invalid_config() {
+        let mut client = VirgeClient::new(ClientConfig::new(3, 0, 1024, false));
+        let err = client
+            .connect_when_ready(Duration::from_secs(5))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn send_when_not_connected_fails() {
+        let mut client = make_client();
+        let result = client.send(vec![1, 2, 3]);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn send_compressed_when_not_connected_fails() {
+        let mut client = make_client();
+        let err = client.send_compressed(&[0; 64]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn recv_loan_when_not_connected_fails() {
+        let mut client = make_client();
+        let err = client.recv_loan().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn recv_vectored_when_not_connected_fails() {
+        let mut client = make_client();
+        let mut buf = [0u8; 4];
+        let err = client
+            .recv_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn recv_when_not_connected_fails() {
+        let mut client = make_client();
+        let result = client.recv();
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn read_when_not_connected_fails() {
+        let mut client = make_client();
+        let mut buf = [0u8; 10];
+        let result = client.read(&mut buf);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn write_when_not_connected_fails() {
+        let mut client = make_client();
+        let result = client.write(&[1, 2, 3]);
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn stats_initially_empty() {
+        let client = make_client();
+        assert_eq!(client.stats(), ConnectionStats::default());
+    }
+
+    #[test]
+    fn flush_always_ok() {
+        let mut client = make_client();
+        assert!(client.flush().is_ok());
+    }
+
+    #[test]
+    fn send_empty_when_not_connected_fails() {
+        let mut client = make_client();
+        let result = client.send(vec![]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn new_client_with_custom_config() {
+        let config = ClientConfig::new(100, 9999, 4096, true);
+        let client = VirgeClient::new(config);
+        assert!(!client.is_connected());
+        assert_eq!(client.config.server_cid, 100);
+        assert_eq!(client.config.server_port, 9999);
+        assert_eq!(client.config.chunk_size, crate::units::ByteSize::kib(4));
+        assert!(client.config.is_ack);
+    }
+
+    #[test]
+    fn disconnect_with_unread_data_fails() {
+        let mut client = make_client();
+        // Simulate data in read buffer
+        client.read_buffer = vec![1, 2, 3];
+        let result = client.disconnect();
+        assert!(result.is_err());
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert!(err.to_string().contains("unread data"));
+    }
+
+    #[test]
+    fn read_state_updates_correctly() {
+        let mut client = make_client();
+        // Mock connected state and setup reading scenario
+        client.connected = true;
+        client.read_state = ReadState::Reading {
+            total: 100,
+            read: 50,
+        };
+        client.read_buffer = vec![1, 2, 3, 4, 5];
+
+        let mut buf = [0u8; 3];
+        let result = client.read(&mut buf);
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(buf, [1, 2, 3]);
+        assert_eq!(client.read_buffer, vec![4, 5]);
+
+        // State should be updated
+        match client.read_state {
+            ReadState::Reading { total, read } => {
+                assert_eq!(total, 100);
+                assert_eq!(read, 53);
+            }
+            _ => panic!("Expected ReadState::Reading"),
+        }
+    }
+
+    #[test]
+    fn read_state_idle_when_complete() {
+        let mut client = make_client();
+        // Mock connected state and setup completion scenario
+        client.connected = true;
+        client.read_state = ReadState::Reading {
+            total: 100,
+            read: 97,
+        };
+        client.read_buffer = vec![1, 2, 3];
+
+        let mut buf = [0u8; 10];
+        let result = client.read(&mut buf);
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 3);
+        // Should transition to Idle when complete
+        assert_eq!(client.read_state, ReadState::Idle);
+    }
+
+    #[test]
+    fn read_with_empty_buffer_in_reading_state() {
+        let mut client = make_client();
+        // Mock connected state and setup edge case scenario
+        client.connected = true;
+        client.read_state = ReadState::Reading {
+            total: 100,
+            read: 50,
+        };
+        // Empty read buffer but in Reading state
+        client.read_buffer.clear();
+
+        let mut buf = [0u8; 10];
+        let result = client.read(&mut buf);
+
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0);
+        // Should reset to Idle
+        assert_eq!(client.read_state, ReadState::Idle);
+    }
+
+    #[test]
+    fn no_has_data_with_buffer() {
+        let mut client = make_client();
+        client.read_buffer = vec![1, 2, 3];
+        assert!(!client.no_has_data());
+    }
+
+    #[test]
+    fn no_has_data_with_reading_state() {
+        let mut client = make_client();
+        client.read_state = ReadState::Reading {
+            total: 100,
+            read: 50,
+        };
+        assert!(!client.no_has_data());
+    }
+
+    #[test]
+    fn connect_logs_info() {
+        // This tests the logging path in connect
+        let mut client = make_client();
+        // We can't actually connect without a real vsock, but we can test the error path
+        let result = client.connect();
+        assert!(result.is_err());
+        // Should remain not connected on error
+        assert!(!client.connected);
+    }
+
+    #[test]
+    fn disconnect_logs_info() {
+        // Test disconnect logging with connected client
+        let mut client = make_client();
+        client.connected = true; // Simulate connected state
+        let result = client.disconnect();
+        // Will fail due to no actual connection, but tests the logging path
+        assert!(result.is_ok());
+        assert!(!client.connected);
+    }
+
+    #[test]
+    fn recv_sized_reports_the_rest_of_a_partly_read_message() {
+        let mut client = make_client();
+        let err = client.recv_sized().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+
+        client.connected = true;
+        client.read_state = ReadState::Reading { total: 5, read: 2 };
+        client.read_buffer = vec![3, 4, 5];
+        assert_eq!(client.recv_sized().unwrap(), 3);
+        let mut rest = [0u8; 3];
+        client.read_exact(&mut rest).unwrap();
+        assert_eq!(rest, [3, 4, 5]);
+        assert!(client.no_has_data());
+    }
+
+    #[test]
+    fn message_apis_refuse_a_partly_read_message() {
+        let mut client = make_client();
+        client.connected = true;
+        client.read_state = ReadState::Reading { total: 5, read: 2 };
+        client.read_buffer = vec![3, 4, 5];
+        assert_eq!(client.recv().unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(
+            client.recv_loan().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        assert_eq!(client.take_partial(), [3, 4, 5]);
+        assert!(client.no_has_data());
+        // 回到消息边界后由传输层决定结果（此处未连接）
+        assert_eq!(client.recv().unwrap_err().kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn recv_follows_the_leftovers_policy() {
+        let partly_read = |leftovers| {
+            let mut client = VirgeClient::new(ClientConfig::default().with_leftovers(leftovers));
+            client.connected = true;
+            client.read_state = ReadState::Reading { total: 5, read: 2 };
+            client.read_buffer = vec![3, 4, 5];
+            client
+        };
+
+        let mut client = partly_read(Leftovers::DrainFirst);
+        assert_eq!(client.recv().unwrap(), [3, 4, 5]);
+        assert!(client.no_has_data());
+
+        // 接收下一条消息失败时其余字节仍保留
+        let mut client = partly_read(Leftovers::Prepend);
+        assert_eq!(client.recv().unwrap_err().kind(), ErrorKind::NotConnected);
+        assert_eq!(client.take_partial(), [3, 4, 5]);
+
+        let mut client = partly_read(Leftovers::Error);
+        assert_eq!(client.recv().unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(client.read_buffer, [3, 4, 5]);
+    }
+
+    #[test]
+    fn read_new_message_simulation() {
+        // This test exercises the read_new_message path indirectly
+        let mut client = make_client();
+        client.connected = true;
+
+        // Test read when in Idle state (will try to call read_new_message)
+        let mut buf = [0u8; 10];
+        let result = client.read(&mut buf);
+        // Will fail because transport isn't really connected, but exercises the path
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn write_error_conversion() {
+        // Test that write errors are properly converted
+        let mut client = make_client();
+        client.connected = true; // Mock connected but transport will fail
+
+        let result = client.write(&[1, 2, 3]);
+        assert!(result.is_err());
+        // Error should keep the transport error kind
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn is_connected_checks_both_flags() {
+        let mut client = make_client();
+        // Test when client.connected is true but transport is not
+        client.connected = true;
+        assert!(!client.is_connected()); // Should be false because transport not connected
+
+        client.connected = false;
+        assert!(!client.is_connected()); // Should be false
+    }
+
+    #[test]
+    fn recv_vectored_goes_through_the_circuit_breaker() {
+        use crate::client::{BreakerState, CircuitBreaker};
+
+        let breaker = CircuitBreaker::new().with_window(1, 1);
+        let mut client =
+            VirgeClient::new(ClientConfig::default().with_circuit_breaker(breaker.clone()));
+        client.connected = true;
+        let mut buf = [0u8; 8];
+        let err = client
+            .recv_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        let err = client
+            .recv_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        #[cfg(feature = "compression")]
+        assert_eq!(
+            client.send_compressed(&[1, 2, 3]).unwrap_err().kind(),
+            ErrorKind::ConnectionRefused
+        );
+    }
+
+    #[test]
+    fn send_and_recv_error_formatting() {
+        let mut client = make_client();
+        client.connected = true; // Mock connected
+
+        // Test send error message format
+        let send_result = client.send(vec![1, 2, 3]);
+        assert!(send_result.is_err());
+
+        // Test recv error message format
+        let recv_result = client.recv();
+        assert!(recv_result.is_err());
+    }
+}
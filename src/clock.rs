@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 跨虚拟机边界的时钟偏差估计
+//!
+//! 客户机与宿主机的系统时钟各自走时，关联两侧日志的时间戳前需要知道二者之差。
+//! `time_sync()` 在连接的控制通道上做简化的 NTP 交换：本端记下发出请求的时刻 `t1`，
+//! 对端回复收到请求的时刻 `t2` 与发出回复的时刻 `t3`，本端记下收到回复的时刻 `t4`，
+//! 偏差为 `((t2 - t1) + (t3 - t4)) / 2`。多轮交换中取往返时长最短的一轮，其排队
+//! 干扰最小，误差不超过该往返时长的一半。
+//!
+//! 对端由传输层自动回复，应用无需配合：yamux 的后台读任务收到请求即回复，XTransport
+//! 在对端接收消息时回复。较旧的对端不认识该控制帧，请求会超时。
+
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 对端时钟相对本端的偏差估计
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockOffset {
+    /// 对端时钟减本端时钟，单位纳秒；正值表示对端走在前面
+    pub offset_nanos: i64,
+    /// 所选一轮的往返时长（已扣除对端处理时间），偏差的误差不超过其一半
+    pub round_trip: Duration,
+    /// 参与估计的交换轮数
+    pub samples: usize,
+}
+
+impl ClockOffset {
+    /// 把对端时钟下的时刻换算到本端时钟
+    pub fn to_local(&self, peer: SystemTime) -> SystemTime {
+        shift(peer, -self.offset_nanos)
+    }
+
+    /// 把本端时钟下的时刻换算到对端时钟
+    pub fn to_peer(&self, local: SystemTime) -> SystemTime {
+        shift(local, self.offset_nanos)
+    }
+}
+
+fn shift(time: SystemTime, nanos: i64) -> SystemTime {
+    let delta = Duration::from_nanos(nanos.unsigned_abs());
+    if nanos >= 0 {
+        time + delta
+    } else {
+        time - delta
+    }
+}
+
+/// 一轮交换的四个时刻，均为自 Unix 纪元起的纳秒
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Sample {
+    /// 本端发出请求
+    pub(crate) t1: u64,
+    /// 对端收到请求
+    pub(crate) t2: u64,
+    /// 对端发出回复
+    pub(crate) t3: u64,
+    /// 本端收到回复
+    pub(crate) t4: u64,
+}
+
+impl Sample {
+    fn offset(&self) -> i64 {
+        let (t1, t2, t3, t4) = (
+            self.t1 as i128,
+            self.t2 as i128,
+            self.t3 as i128,
+            self.t4 as i128,
+        );
+        (((t2 - t1) + (t3 - t4)) / 2) as i64
+    }
+
+    fn round_trip(&self) -> Duration {
+        let elapsed = self.t4.saturating_sub(self.t1);
+        let held = self.t3.saturating_sub(self.t2);
+        Duration::from_nanos(elapsed.saturating_sub(held))
+    }
+}
+
+/// 当前墙上时刻，自 Unix 纪元起的纳秒，取自 [`crate::sources::clock()`]
+pub(crate) fn now_nanos() -> u64 {
+    crate::sources::clock()
+        .system_time()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// 进行 `rounds` 轮交换，以往返时长最短的一轮估计偏差；任一轮出错即返回该错误
+pub(crate) fn sync(
+    rounds: usize,
+    mut probe: impl FnMut() -> Result<Sample>,
+) -> Result<ClockOffset> {
+    if rounds == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "time_sync needs at least one round",
+        ));
+    }
+    let mut best: Option<Sample> = None;
+    for _ in 0..rounds {
+        let sample = probe()?;
+        if best.is_none_or(|best| sample.round_trip() < best.round_trip()) {
+            best = Some(sample);
+        }
+    }
+    let best = best.expect("rounds > 0");
+    Ok(ClockOffset {
+        offset_nanos: best.offset(),
+        round_trip: best.round_trip(),
+        samples: rounds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_comes_from_the_fastest_round() {
+        // 对端快 1000ns；第一轮回复在路上多耽搁了 400ns
+        let samples = [
+            Sample {
+                t1: 0,
+                t2: 1100,
+                t3: 1150,
+                t4: 650,
+            },
+            Sample {
+                t1: 1000,
+                t2: 2100,
+                t3: 2150,
+                t4: 1250,
+            },
+        ];
+        let mut next = samples.iter().copied();
+        let estimate = sync(2, || Ok(next.next().unwrap())).unwrap();
+        assert_eq!(estimate.offset_nanos, 1000);
+        assert_eq!(estimate.round_trip, Duration::from_nanos(200));
+        assert_eq!(estimate.samples, 2);
+
+        let local = UNIX_EPOCH + Duration::from_secs(10);
+        assert_eq!(estimate.to_local(estimate.to_peer(local)), local);
+        assert_eq!(estimate.to_peer(local), local + Duration::from_nanos(1000));
+    }
+
+    #[test]
+    fn sync_needs_a_round_and_propagates_errors() {
+        let err = sync(0, || unreachable!()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = sync(3, || Err(Error::from(ErrorKind::TimedOut))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+}
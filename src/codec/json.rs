@@ -0,0 +1,195 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! JSON 行模式
+//!
+//! 每条消息是一个 JSON 值，发送时以换行结尾，与按行读写 JSON 的代理程序兼容。
+//! [`JsonStream`] 在后台线程持续接收并解析，供异步代码以 `Stream` 消费。
+
+use std::io::{Error, ErrorKind, Result};
+use std::pin::Pin;
+use std::sync::mpsc::{sync_channel, Receiver, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use serde_json::Value;
+
+use crate::error::ConnContext;
+use crate::events::{self, VirgaEvent};
+use crate::threads;
+
+/// 后台线程最多领先消费者的消息数，超过后暂停接收
+pub const JSON_STREAM_CAPACITY: usize = 64;
+
+/// 编码为一行 JSON
+pub(crate) fn encode_line(value: &Value) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(value).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// 解析一条消息，允许首尾空白（含行尾换行）
+pub(crate) fn decode_line(msg: &[u8]) -> Result<Value> {
+    serde_json::from_slice(msg).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// 连续接收的 JSON 值
+///
+/// 由 `into_json_stream()` 创建，后台线程拥有连接并逐条接收：格式错误的消息产生一个
+/// `InvalidData` 错误后继续，对端关闭时流正常结束，其他错误产生后流结束。
+/// 丢弃流后，后台线程在下一条消息到达或连接关闭时退出。
+pub struct JsonStream {
+    rx: Receiver<Result<Value>>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl JsonStream {
+    pub(crate) fn spawn<F>(conn: ConnContext, mut recv: F) -> Self
+    where
+        F: FnMut() -> Result<Vec<u8>> + Send + 'static,
+    {
+        let (tx, rx) = sync_channel(JSON_STREAM_CAPACITY);
+        let waker: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+        let wake = waker.clone();
+        let spawned = threads::spawn(format!("json-{}", conn.conn_id), move || {
+            let notify = || {
+                if let Some(w) = wake.lock().unwrap().take() {
+                    w.wake();
+                }
+            };
+            loop {
+                let (item, last) = match recv() {
+                    Ok(msg) => (decode_line(&msg), false),
+                    Err(e) if crate::rpc::is_closed(&e) => break,
+                    Err(e) => (Err(e), true),
+                };
+                let sent = match tx.try_send(item) {
+                    Ok(()) => Ok(()),
+                    Err(TrySendError::Full(item)) => {
+                        events::emit(VirgaEvent::SlowConsumer {
+                            conn,
+                            queued: JSON_STREAM_CAPACITY,
+                        });
+                        tx.send(item).map_err(drop)
+                    }
+                    Err(TrySendError::Disconnected(_)) => Err(()),
+                };
+                if sent.is_err() {
+                    break;
+                }
+                notify();
+                if last {
+                    break;
+                }
+            }
+            // 发送端随线程退出被丢弃，唤醒消费者以观察到流结束
+            drop(tx);
+            notify();
+        });
+        spawned.expect("failed to spawn JSON stream thread");
+        Self { rx, waker }
+    }
+
+    /// 同步代码可阻塞等待下一个值，流结束时返回 `None`
+    pub fn next_blocking(&mut self) -> Option<Result<Value>> {
+        self.rx.recv().ok()
+    }
+}
+
+impl futures_core::Stream for JsonStream {
+    type Item = Result<Value>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = |rx: &Receiver<Result<Value>>| match rx.try_recv() {
+            Ok(item) => Some(Poll::Ready(Some(item))),
+            Err(TryRecvError::Disconnected) => Some(Poll::Ready(None)),
+            Err(TryRecvError::Empty) => None,
+        };
+        if let Some(ready) = poll(&self.rx) {
+            return ready;
+        }
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        // 注册唤醒器前后台线程可能刚好送达，再查一次
+        poll(&self.rx).unwrap_or(Poll::Pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_core::Stream;
+    use serde_json::json;
+    use std::thread;
+    use std::time::Duration;
+
+    fn poll_next(stream: &mut JsonStream) -> Option<Result<Value>> {
+        let mut cx = Context::from_waker(Waker::noop());
+        for _ in 0..1000 {
+            if let Poll::Ready(item) = Pin::new(&mut *stream).poll_next(&mut cx) {
+                return item;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("stream stalled");
+    }
+
+    #[test]
+    fn line_round_trip() {
+        let value = json!({"execute": "guest-ping", "id": 1});
+        let line = encode_line(&value).unwrap();
+        assert_eq!(line.last(), Some(&b'\n'));
+        assert_eq!(decode_line(&line).unwrap(), value);
+        assert_eq!(
+            decode_line(b"{").unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn stream_skips_bad_values_and_ends_on_close() {
+        let mut msgs = vec![
+            Err(Error::from(ErrorKind::ConnectionReset)),
+            Ok(b"[1,2]\n".to_vec()),
+            Ok(b"not json".to_vec()),
+            Ok(b"{\"a\":1}".to_vec()),
+        ];
+        let mut stream = JsonStream::spawn(ConnContext::default(), move || msgs.pop().unwrap());
+        assert_eq!(poll_next(&mut stream).unwrap().unwrap(), json!({"a": 1}));
+        assert_eq!(
+            poll_next(&mut stream).unwrap().unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(poll_next(&mut stream).unwrap().unwrap(), json!([1, 2]));
+        assert!(poll_next(&mut stream).is_none());
+    }
+
+    #[test]
+    fn stream_ends_after_transport_error() {
+        let mut msgs = vec![
+            Ok(b"2".to_vec()),
+            Err(Error::from(ErrorKind::TimedOut)),
+            Ok(b"1".to_vec()),
+        ];
+        let mut stream = JsonStream::spawn(ConnContext::default(), move || msgs.pop().unwrap());
+        let items: Vec<_> = std::iter::from_fn(|| stream.next_blocking()).collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap(), &json!(1));
+        assert_eq!(items[1].as_ref().unwrap_err().kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn stream_thread_is_named_after_connection() {
+        let conn = ConnContext {
+            conn_id: 42,
+            cid: 3,
+            port: 1234,
+        };
+        let mut stream = JsonStream::spawn(conn, || {
+            let name = thread::current().name().map(str::to_string);
+            Err(Error::other(name.unwrap_or_default()))
+        });
+        let err = stream.next_blocking().unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "virga-json-42");
+    }
+}
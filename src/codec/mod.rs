@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 消息编解码
+//!
+//! [`Codec`] 负责把类型化的消息与一条 Virga 消息的字节互相转换，
+//! `send_encoded()`/`recv_decoded()` 以及其上的类型化接口都通过它工作。
+//! 各格式的实现由特性开启：
+//!
+//! | 特性 | 实现 | 适用类型 |
+//! |------|------|----------|
+//! | `codec-json` | [`JsonCodec`] | `Serialize + DeserializeOwned` |
+//! | `codec-bincode` | [`BincodeCodec`] | `Serialize + DeserializeOwned` |
+//! | `codec-cbor` | [`CborCodec`] | `Serialize + DeserializeOwned` |
+//! | `codec-prost` | [`ProstCodec`] | `prost::Message + Default` |
+//!
+//! 已有线上格式的团队也可以为自己的类型实现 `Codec`。
+//!
+//! 控制面的请求/回复对话可以交给 [`Protocol`]，由它检查收发交替并处理超时。
+//!
+//! 双方各自配置 [`Schema`] 时，连接建立后会先比对消息定义的指纹，见 [`SchemaMatch`]。
+
+use std::io::{Error, ErrorKind, Result};
+
+#[cfg(all(feature = "codec-json", feature = "sync"))]
+pub(crate) mod json;
+#[cfg(feature = "sync")]
+mod protocol;
+#[cfg(feature = "sync")]
+mod schema;
+#[cfg(all(feature = "codec-json", feature = "sync"))]
+pub use json::{JsonStream, JSON_STREAM_CAPACITY};
+#[cfg(feature = "sync")]
+pub use protocol::{Protocol, Role};
+#[cfg(feature = "sync")]
+pub use schema::{Schema, SchemaMatch, FINGERPRINT_LEN};
+
+/// 类型 `T` 的编解码器
+pub trait Codec<T> {
+    /// 把 `value` 编码为一条消息
+    fn encode(&self, value: &T) -> Result<Vec<u8>>;
+
+    /// 从一条消息解码，格式不符时返回 `InvalidData`
+    fn decode(&self, bytes: &[u8]) -> Result<T>;
+}
+
+/// 直接收发原始字节，不做编码
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RawCodec;
+
+impl Codec<Vec<u8>> for RawCodec {
+    fn encode(&self, value: &Vec<u8>) -> Result<Vec<u8>> {
+        Ok(value.clone())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+}
+
+#[allow(dead_code)]
+fn invalid_data<E: std::fmt::Display>(format: &str) -> impl FnOnce(E) -> Error + '_ {
+    move |e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{} decode error: {}", format, e),
+        )
+    }
+}
+
+/// JSON 编解码（`serde_json`）
+#[cfg(feature = "codec-json")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "codec-json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T> {
+        serde_json::from_slice(bytes).map_err(invalid_data("json"))
+    }
+}
+
+/// bincode 编解码，紧凑的二进制格式，双方须使用相同的类型定义
+#[cfg(feature = "codec-bincode")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "codec-bincode")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T> {
+        bincode::deserialize(bytes).map_err(invalid_data("bincode"))
+    }
+}
+
+/// CBOR 编解码（`ciborium`）
+#[cfg(feature = "codec-cbor")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "codec-cbor")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for CborCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        ciborium::into_writer(value, &mut buf)
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, e.to_string()))?;
+        Ok(buf)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T> {
+        ciborium::from_reader(bytes).map_err(invalid_data("cbor"))
+    }
+}
+
+/// Protobuf 编解码（`prost`）
+#[cfg(feature = "codec-prost")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProstCodec;
+
+#[cfg(feature = "codec-prost")]
+impl<T: prost::Message + Default> Codec<T> for ProstCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(value.encode_to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T> {
+        T::decode(bytes).map_err(invalid_data("protobuf"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T, C>(codec: C, value: T)
+    where
+        T: PartialEq + std::fmt::Debug,
+        C: Codec<T>,
+    {
+        let bytes = codec.encode(&value).unwrap();
+        assert_eq!(codec.decode(&bytes).unwrap(), value);
+    }
+
+    #[test]
+    fn raw_codec_passes_bytes_through() {
+        round_trip(RawCodec, vec![1u8, 2, 3]);
+        assert_eq!(RawCodec.encode(&vec![9]).unwrap(), vec![9]);
+    }
+
+    #[cfg(feature = "codec-json")]
+    #[test]
+    fn json_codec() {
+        round_trip(JsonCodec, ("ping".to_string(), 7u32, vec![true, false]));
+        assert_eq!(JsonCodec.encode(&vec![1, 2]).unwrap(), b"[1,2]");
+        let err = <JsonCodec as Codec<u32>>::decode(&JsonCodec, b"{").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "codec-bincode")]
+    #[test]
+    fn bincode_codec() {
+        round_trip(BincodeCodec, ("ping".to_string(), 7u64));
+        let err = <BincodeCodec as Codec<u64>>::decode(&BincodeCodec, &[1]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "codec-cbor")]
+    #[test]
+    fn cbor_codec() {
+        round_trip(CborCodec, (Some("x".to_string()), -3i64));
+        let err = <CborCodec as Codec<String>>::decode(&CborCodec, &[0xff]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "codec-prost")]
+    #[test]
+    fn prost_codec() {
+        #[derive(Clone, PartialEq, prost::Message)]
+        struct Ping {
+            #[prost(uint32, tag = "1")]
+            id: u32,
+            #[prost(string, tag = "2")]
+            body: String,
+        }
+
+        round_trip(
+            ProstCodec,
+            Ping {
+                id: 5,
+                body: "hello".to_string(),
+            },
+        );
+        let err = <ProstCodec as Codec<Ping>>::decode(&ProstCodec, &[0xff]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
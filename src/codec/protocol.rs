@@ -0,0 +1,320 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 类型化的请求/回复会话
+//!
+//! 控制面协议大多是“发一条请求、等一条回复”。直接在连接上收发字节时，漏收一条回复、
+//! 多发一条请求或者等回复超时后继续使用连接，都会让之后的每条回复错位一条，且不会
+//! 有任何报错。[`Protocol`] 在连接之上维护会话状态：
+//!
+//! - 请求方（[`Role::Requester`]）发送 `Req`、接收 `Resp`；应答方（[`Role::Responder`]）
+//!   接收 `Req`、发送 `Resp`。双方用同一组类型参数
+//! - 默认严格交替：上一条回复收到（或发出）之前不能再发（或收）下一条请求。
+//!   `with_pipeline(n)` 允许最多 `n` 条请求未得到回复，回复按请求顺序对应
+//! - 违反交替或超过流水线深度返回 `InvalidInput`，不会发出任何字节
+//! - 请求方等回复超过 `with_timeout()` 时返回 `TimedOut`，迟到的回复会与之后的请求
+//!   错位，因此会话随即失效，之后的调用都返回错误，只能关闭重连；连接出错同样使会话
+//!   失效。超时只在回复开始到达前生效，已开始到达的回复总是完整收下
+//! - 回复无法解码时返回 `InvalidData`，该回复视为已收到，会话继续可用
+//!
+//! 由 `into_protocol()` 创建：
+//!
+//! ```ignore
+//! let mut control: Protocol<Command, Status, JsonCodec> = client
+//!     .into_protocol(JsonCodec, Role::Requester)
+//!     .with_timeout(Duration::from_secs(3));
+//! let status = control.call(&Command::Status)?;
+//! ```
+
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use log::*;
+
+use super::Codec;
+use crate::mux::Endpoint;
+
+/// 会话中的角色
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// 发送请求、接收回复
+    Requester,
+    /// 接收请求、发送回复
+    Responder,
+}
+
+/// 请求/回复会话，见[模块文档](self)
+pub struct Protocol<Req, Resp, C> {
+    link: Box<dyn Endpoint>,
+    codec: C,
+    role: Role,
+    depth: usize,
+    timeout: Option<Duration>,
+    /// 已发出（请求方）或已收到（应答方）但还没有对应回复的请求数
+    outstanding: usize,
+    /// 会话失效的原因
+    broken: Option<(ErrorKind, String)>,
+    _messages: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req, Resp, C> Protocol<Req, Resp, C>
+where
+    C: Codec<Req> + Codec<Resp>,
+{
+    pub(crate) fn new(link: impl Endpoint, codec: C, role: Role) -> Self {
+        Self {
+            link: Box::new(link),
+            codec,
+            role,
+            depth: 1,
+            timeout: None,
+            outstanding: 0,
+            broken: None,
+            _messages: PhantomData,
+        }
+    }
+
+    /// 允许最多 `depth` 条请求未得到回复，默认 1 即严格交替；`0` 视为 1
+    pub fn with_pipeline(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+
+    /// 请求方等回复、应答方等请求的最长时间，默认一直等待
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// 本端角色
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// 尚未得到回复的请求数
+    pub fn outstanding(&self) -> usize {
+        self.outstanding
+    }
+
+    /// 会话是否已因超时或连接错误失效
+    pub fn is_broken(&self) -> bool {
+        self.broken.is_some()
+    }
+
+    /// 发送一条请求并等待其回复；有未收的回复时返回 `InvalidInput`
+    pub fn call(&mut self, request: &Req) -> Result<Resp> {
+        if self.outstanding > 0 {
+            return Err(self.violation(format!(
+                "call() with {} response(s) pending, recv() them first",
+                self.outstanding
+            )));
+        }
+        self.send(request)?;
+        self.recv()
+    }
+
+    /// 请求方发送一条请求，不等回复
+    pub fn send(&mut self, request: &Req) -> Result<()> {
+        self.expect(Role::Requester, "send")?;
+        if self.outstanding >= self.depth {
+            return Err(self.violation(format!(
+                "{} request(s) already awaiting a response",
+                self.outstanding
+            )));
+        }
+        let frame = Codec::<Req>::encode(&self.codec, request)?;
+        self.transmit(frame)?;
+        self.outstanding += 1;
+        Ok(())
+    }
+
+    /// 请求方接收最早一条未收请求的回复
+    pub fn recv(&mut self) -> Result<Resp> {
+        self.expect(Role::Requester, "recv")?;
+        if self.outstanding == 0 {
+            return Err(self.violation("no request awaiting a response".to_string()));
+        }
+        let frame = match self.receive()? {
+            Some(frame) => frame,
+            None => {
+                let e = Error::new(
+                    ErrorKind::TimedOut,
+                    format!("no response within {:?}", self.timeout.unwrap_or_default()),
+                );
+                return Err(self.fail(e));
+            }
+        };
+        self.outstanding -= 1;
+        Codec::<Resp>::decode(&self.codec, &frame)
+    }
+
+    /// 应答方接收下一条请求；等待超时返回 `TimedOut`，会话仍可用
+    pub fn next_request(&mut self) -> Result<Req> {
+        self.expect(Role::Responder, "next_request")?;
+        if self.outstanding >= self.depth {
+            return Err(self.violation(format!(
+                "{} request(s) still awaiting respond()",
+                self.outstanding
+            )));
+        }
+        let Some(frame) = self.receive()? else {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("no request within {:?}", self.timeout.unwrap_or_default()),
+            ));
+        };
+        self.outstanding += 1;
+        Codec::<Req>::decode(&self.codec, &frame)
+    }
+
+    /// 应答方回复最早一条未回复的请求
+    pub fn respond(&mut self, response: &Resp) -> Result<()> {
+        self.expect(Role::Responder, "respond")?;
+        if self.outstanding == 0 {
+            return Err(self.violation("no request awaiting a response".to_string()));
+        }
+        let frame = Codec::<Resp>::encode(&self.codec, response)?;
+        self.transmit(frame)?;
+        self.outstanding -= 1;
+        Ok(())
+    }
+
+    /// 断开连接
+    pub fn close(mut self) {
+        self.link.close();
+    }
+
+    fn expect(&self, role: Role, op: &str) -> Result<()> {
+        if let Some((kind, reason)) = &self.broken {
+            return Err(Error::new(*kind, format!("protocol broken: {}", reason)));
+        }
+        if self.role != role {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}() is not available to the {:?}", op, self.role),
+            ));
+        }
+        Ok(())
+    }
+
+    fn violation(&self, reason: String) -> Error {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("protocol violation: {}", reason),
+        )
+    }
+
+    /// 使会话失效并返回原错误
+    fn fail(&mut self, e: Error) -> Error {
+        warn!("Protocol broken: {}", e);
+        self.broken = Some((e.kind(), e.to_string()));
+        e
+    }
+
+    fn transmit(&mut self, frame: Vec<u8>) -> Result<()> {
+        let sent = self
+            .link
+            .send_frame(frame)
+            .and_then(|_| self.link.flush_frames());
+        sent.map_err(|e| self.fail(e))
+    }
+
+    /// 接收一条消息，超时未开始到达时返回 `None`
+    fn receive(&mut self) -> Result<Option<Vec<u8>>> {
+        let received = match self.link.wait_readable(None, self.timeout) {
+            Ok(false) => return Ok(None),
+            Ok(true) => self.link.recv_frame().map(Some),
+            Err(e) => Err(e),
+        };
+        received.map_err(|e| self.fail(e))
+    }
+}
+
+impl<Req, Resp, C: fmt::Debug> fmt::Debug for Protocol<Req, Resp, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Protocol")
+            .field("codec", &self.codec)
+            .field("role", &self.role)
+            .field("depth", &self.depth)
+            .field("timeout", &self.timeout)
+            .field("outstanding", &self.outstanding)
+            .field("broken", &self.broken)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "use-xtransport"))]
+mod tests {
+    use super::*;
+    use crate::codec::RawCodec;
+    use crate::mux::testing::link_pair;
+
+    type Raw = Protocol<Vec<u8>, Vec<u8>, RawCodec>;
+
+    fn pair() -> (Raw, Raw) {
+        let (a, b) = link_pair();
+        (
+            Protocol::new(a, RawCodec, Role::Requester),
+            Protocol::new(b, RawCodec, Role::Responder),
+        )
+    }
+
+    fn kind<T: fmt::Debug>(result: Result<T>) -> ErrorKind {
+        result.unwrap_err().kind()
+    }
+
+    #[test]
+    fn strict_alternation_rejects_out_of_turn_calls() {
+        let (mut host, mut guest) = pair();
+        assert_eq!(kind(host.recv()), ErrorKind::InvalidInput);
+        assert_eq!(kind(guest.respond(&vec![])), ErrorKind::InvalidInput);
+        assert_eq!(kind(guest.send(&vec![])), ErrorKind::InvalidInput);
+
+        host.send(&b"status".to_vec()).unwrap();
+        assert_eq!(kind(host.send(&b"again".to_vec())), ErrorKind::InvalidInput);
+        assert_eq!(kind(host.call(&b"again".to_vec())), ErrorKind::InvalidInput);
+        assert_eq!(guest.next_request().unwrap(), b"status");
+        assert_eq!(kind(guest.next_request()), ErrorKind::InvalidInput);
+        guest.respond(&b"ok".to_vec()).unwrap();
+        assert_eq!(host.recv().unwrap(), b"ok");
+        assert_eq!(host.outstanding(), 0);
+        assert!(!host.is_broken());
+    }
+
+    #[test]
+    fn pipelined_responses_follow_request_order() {
+        let (host, guest) = pair();
+        let (mut host, mut guest) = (host.with_pipeline(2), guest.with_pipeline(2));
+        host.send(&b"a".to_vec()).unwrap();
+        host.send(&b"b".to_vec()).unwrap();
+        assert_eq!(kind(host.send(&b"c".to_vec())), ErrorKind::InvalidInput);
+
+        let responder = std::thread::spawn(move || {
+            let (a, b) = (guest.next_request().unwrap(), guest.next_request().unwrap());
+            guest.respond(&[a, b"!".to_vec()].concat()).unwrap();
+            guest.respond(&[b, b"!".to_vec()].concat()).unwrap();
+        });
+        assert_eq!(host.recv().unwrap(), b"a!");
+        assert_eq!(host.recv().unwrap(), b"b!");
+        responder.join().unwrap();
+    }
+
+    #[test]
+    fn response_timeout_breaks_the_conversation() {
+        let (host, guest) = pair();
+        let timeout = Duration::from_millis(20);
+        let (mut host, mut guest) = (host.with_timeout(timeout), guest.with_timeout(timeout));
+        // 应答方等请求超时不影响会话
+        assert_eq!(kind(guest.next_request()), ErrorKind::TimedOut);
+
+        assert_eq!(kind(host.call(&b"slow".to_vec())), ErrorKind::TimedOut);
+        assert!(host.is_broken());
+        guest.next_request().unwrap();
+        guest.respond(&b"late".to_vec()).unwrap();
+        assert_eq!(kind(host.send(&b"next".to_vec())), ErrorKind::TimedOut);
+        assert_eq!(kind(host.recv()), ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,318 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 消息定义协商
+//!
+//! 双方配置 [`Schema`] 后，连接建立（及认证）后交换消息定义的指纹，
+//! 定义不一致时在 `connect()`/`accept()` 就失败，而不是在第一次解码时才报错。
+//! 三条消息：
+//!
+//! ```text
+//! client -> server: MAGIC | fingerprint(32) | name
+//! server -> client: MAGIC | verdict(1) | allow_mismatch(1) | fingerprint(32) | name
+//! client -> server: verdict(1)
+//! ```
+//!
+//! 任一方认为对方的指纹兼容即为 `Compatible`；都不认识对方时为 `Mismatch`，
+//! 只有双方都允许不一致时连接才会以兼容模式继续。
+
+use std::io::{Error, ErrorKind, Result};
+
+#[cfg(feature = "schema")]
+use sha2::{Digest, Sha256};
+
+use crate::auth::{MessageChannel, MAX_NAME_LEN};
+use crate::error::VirgeError;
+
+/// 指纹长度（SHA-256）
+pub const FINGERPRINT_LEN: usize = 32;
+
+const MAGIC: &[u8; 4] = b"VGS1";
+const VERDICT_EXACT: u8 = 0;
+const VERDICT_COMPATIBLE: u8 = 1;
+const VERDICT_MISMATCH: u8 = 2;
+const VERDICT_REJECTED: u8 = 3;
+
+/// 协商结果
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SchemaMatch {
+    /// 双方定义相同
+    Exact,
+    /// 定义不同，但一方将对方的指纹列为兼容
+    Compatible,
+    /// 定义不同且互不认识，双方都允许时以兼容模式继续
+    Mismatch,
+}
+
+impl SchemaMatch {
+    fn to_byte(self) -> u8 {
+        match self {
+            SchemaMatch::Exact => VERDICT_EXACT,
+            SchemaMatch::Compatible => VERDICT_COMPATIBLE,
+            SchemaMatch::Mismatch => VERDICT_MISMATCH,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            VERDICT_EXACT => Some(SchemaMatch::Exact),
+            VERDICT_COMPATIBLE => Some(SchemaMatch::Compatible),
+            VERDICT_MISMATCH => Some(SchemaMatch::Mismatch),
+            _ => None,
+        }
+    }
+}
+
+/// 本端使用的消息定义
+///
+/// ```ignore
+/// let schema = Schema::new("demo.Echo", include_str!("echo.proto"))
+///     .with_compatible(OLD_ECHO_FINGERPRINT);
+/// let config = ClientConfig::default().with_schema(schema);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schema {
+    name: String,
+    fingerprint: [u8; FINGERPRINT_LEN],
+    compatible: Vec<[u8; FINGERPRINT_LEN]>,
+    allow_mismatch: bool,
+}
+
+impl Schema {
+    /// 以名称和消息定义（如 .proto 文件内容）计算指纹，需要 `schema` 特性
+    #[cfg(feature = "schema")]
+    pub fn new(name: impl Into<String>, definition: impl AsRef<[u8]>) -> Self {
+        let name = name.into();
+        let mut hasher = Sha256::new();
+        hasher.update((name.len() as u64).to_be_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(definition.as_ref());
+        Self::from_fingerprint(name, hasher.finalize().into())
+    }
+
+    /// 直接使用已知指纹，例如构建脚本生成的常量
+    pub fn from_fingerprint(name: impl Into<String>, fingerprint: [u8; FINGERPRINT_LEN]) -> Self {
+        Self {
+            name: name.into(),
+            fingerprint,
+            compatible: Vec::new(),
+            allow_mismatch: false,
+        }
+    }
+
+    /// 把另一版本的指纹列为兼容
+    pub fn with_compatible(mut self, fingerprint: [u8; FINGERPRINT_LEN]) -> Self {
+        self.compatible.push(fingerprint);
+        self
+    }
+
+    /// 定义不一致时不断开连接，由上层按 `schema_match()` 降级处理（默认关闭）
+    pub fn with_allow_mismatch(mut self, allow: bool) -> Self {
+        self.allow_mismatch = allow;
+        self
+    }
+
+    /// 名称
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 指纹
+    pub fn fingerprint(&self) -> [u8; FINGERPRINT_LEN] {
+        self.fingerprint
+    }
+
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        if self.name.is_empty() || self.name.len() > MAX_NAME_LEN {
+            return Err(VirgeError::ConfigError(format!(
+                "schema name must be 1..={} bytes",
+                MAX_NAME_LEN
+            )));
+        }
+        Ok(())
+    }
+
+    fn classify(&self, name: &str, fingerprint: &[u8]) -> SchemaMatch {
+        if name != self.name {
+            SchemaMatch::Mismatch
+        } else if fingerprint == self.fingerprint {
+            SchemaMatch::Exact
+        } else if self.compatible.iter().any(|fp| fp[..] == *fingerprint) {
+            SchemaMatch::Compatible
+        } else {
+            SchemaMatch::Mismatch
+        }
+    }
+
+    fn mismatch(&self, name: &str, fingerprint: &[u8]) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "schema mismatch: local {} {}, peer {} {}",
+                self.name,
+                hex(&self.fingerprint),
+                name,
+                hex(fingerprint)
+            ),
+        )
+    }
+
+    fn hello(&self) -> Vec<u8> {
+        let mut msg = Vec::with_capacity(MAGIC.len() + FINGERPRINT_LEN + self.name.len());
+        msg.extend_from_slice(MAGIC);
+        msg.extend_from_slice(&self.fingerprint);
+        msg.extend_from_slice(self.name.as_bytes());
+        msg
+    }
+
+    /// 服务端：读取客户端的指纹并给出本端判断
+    fn answer(&self, hello: &[u8]) -> Result<(Vec<u8>, String, Vec<u8>)> {
+        let rest = hello
+            .strip_prefix(MAGIC)
+            .filter(|r| r.len() >= FINGERPRINT_LEN)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed schema hello"))?;
+        let (fingerprint, name) = rest.split_at(FINGERPRINT_LEN);
+        let name = String::from_utf8_lossy(name).into_owned();
+        let verdict = self.classify(&name, fingerprint);
+
+        let mut reply = Vec::with_capacity(MAGIC.len() + 2 + FINGERPRINT_LEN + self.name.len());
+        reply.extend_from_slice(MAGIC);
+        reply.push(verdict.to_byte());
+        reply.push(self.allow_mismatch as u8);
+        reply.extend_from_slice(&self.fingerprint);
+        reply.extend_from_slice(self.name.as_bytes());
+        Ok((reply, name, fingerprint.to_vec()))
+    }
+
+    /// 客户端：结合服务端判断得出最终结果，返回要回复的字节
+    fn decide(&self, reply: &[u8]) -> (u8, Result<SchemaMatch>) {
+        let parsed = reply
+            .strip_prefix(MAGIC)
+            .filter(|r| r.len() >= 2 + FINGERPRINT_LEN)
+            .and_then(|r| SchemaMatch::from_byte(r[0]).map(|v| (v, r[1] != 0, &r[2..])));
+        let Some((server_verdict, server_allows, rest)) = parsed else {
+            let err = Error::new(ErrorKind::InvalidData, "malformed schema reply");
+            return (VERDICT_REJECTED, Err(err));
+        };
+        let (fingerprint, name) = rest.split_at(FINGERPRINT_LEN);
+        let name = String::from_utf8_lossy(name);
+        let verdict = server_verdict.min(self.classify(&name, fingerprint));
+        if verdict == SchemaMatch::Mismatch && !(self.allow_mismatch && server_allows) {
+            return (VERDICT_REJECTED, Err(self.mismatch(&name, fingerprint)));
+        }
+        (verdict.to_byte(), Ok(verdict))
+    }
+
+    /// 客户端发起协商
+    pub(crate) fn connect(&self, chan: &mut impl MessageChannel) -> Result<SchemaMatch> {
+        chan.send_msg(&self.hello())?;
+        let reply = chan.recv_msg()?;
+        let (byte, result) = self.decide(&reply);
+        chan.send_msg(&[byte])?;
+        result
+    }
+
+    /// 服务端响应协商
+    pub(crate) fn accept(&self, chan: &mut impl MessageChannel) -> Result<SchemaMatch> {
+        let hello = chan.recv_msg()?;
+        let (reply, name, fingerprint) = self.answer(&hello)?;
+        chan.send_msg(&reply)?;
+        match chan.recv_msg()?.as_slice() {
+            [b] => match SchemaMatch::from_byte(*b) {
+                Some(verdict) => Ok(verdict),
+                None => Err(self.mismatch(&name, &fingerprint)),
+            },
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                "malformed schema verdict",
+            )),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().take(8).map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(all(test, feature = "schema"))]
+mod tests {
+    use super::*;
+
+    fn negotiate(server: &Schema, client: &Schema) -> (Result<SchemaMatch>, Result<SchemaMatch>) {
+        let (reply, ..) = server.answer(&client.hello()).unwrap();
+        let (byte, client_result) = client.decide(&reply);
+        let server_result = match SchemaMatch::from_byte(byte) {
+            Some(verdict) => Ok(verdict),
+            None => Err(Error::from(ErrorKind::InvalidData)),
+        };
+        (server_result, client_result)
+    }
+
+    #[test]
+    fn fingerprint_depends_on_name_and_definition() {
+        let a = Schema::new("demo.Echo", "message A {}");
+        assert_eq!(a, Schema::new("demo.Echo", "message A {}"));
+        assert_ne!(
+            a.fingerprint(),
+            Schema::new("demo.Echo", "message B {}").fingerprint()
+        );
+        assert_ne!(
+            a.fingerprint(),
+            Schema::new("demo.Other", "message A {}").fingerprint()
+        );
+        assert!(a.validate().is_ok());
+        assert!(Schema::new("", "x").validate().is_err());
+    }
+
+    #[test]
+    fn same_definition_is_exact() {
+        let schema = Schema::new("demo.Echo", "v1");
+        let (server, client) = negotiate(&schema, &schema);
+        assert_eq!(server.unwrap(), SchemaMatch::Exact);
+        assert_eq!(client.unwrap(), SchemaMatch::Exact);
+    }
+
+    #[test]
+    fn different_definition_fails_fast() {
+        let (server, client) = negotiate(
+            &Schema::new("demo.Echo", "v1"),
+            &Schema::new("demo.Echo", "v2"),
+        );
+        assert!(server.is_err());
+        assert_eq!(client.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn either_side_may_declare_compatibility() {
+        let v1 = Schema::new("demo.Echo", "v1");
+        let v2 = Schema::new("demo.Echo", "v2").with_compatible(v1.fingerprint());
+        for (server, client) in [(&v1, &v2), (&v2, &v1)] {
+            let (s, c) = negotiate(server, client);
+            assert_eq!(s.unwrap(), SchemaMatch::Compatible);
+            assert_eq!(c.unwrap(), SchemaMatch::Compatible);
+        }
+    }
+
+    #[test]
+    fn mismatch_needs_both_sides_to_allow() {
+        let v1 = Schema::new("demo.Echo", "v1").with_allow_mismatch(true);
+        let v2 = Schema::new("demo.Echo", "v2");
+        assert!(negotiate(&v1, &v2).1.is_err());
+        assert!(negotiate(&v2, &v1).1.is_err());
+
+        let v2 = v2.with_allow_mismatch(true);
+        let (server, client) = negotiate(&v1, &v2);
+        assert_eq!(server.unwrap(), SchemaMatch::Mismatch);
+        assert_eq!(client.unwrap(), SchemaMatch::Mismatch);
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let schema = Schema::new("demo.Echo", "v1");
+        assert!(schema.answer(b"VGS1short").is_err());
+        let (byte, result) = schema.decide(b"nope");
+        assert_eq!(byte, VERDICT_REJECTED);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 压缩字典与握手协商
+//!
+//! 成千上万条结构相同的小消息（如遥测）单独压缩几乎没有收益，用同类样本训练的字典
+//! 可把它们压到原来的几分之一。双方配置字典后，连接建立（及认证、消息定义协商）后
+//! 交换一轮：
+//!
+//! ```text
+//! client -> server: MAGIC | (id(4, 大端) | checksum(8))*
+//! server -> client: MAGIC | id(4, 大端)
+//! ```
+//!
+//! 服务端按客户端给出的顺序选第一个 ID 与内容校验和都相同的字典，没有时回复 0，
+//! 连接照常建立，压缩消息不使用字典。
+
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use super::DEFAULT_COMPRESSION_LEVEL;
+use crate::auth::MessageChannel;
+use crate::error::VirgeError;
+use crate::units::ByteSize;
+
+const MAGIC: &[u8; 4] = b"VGD1";
+const CHECKSUM_LEN: usize = 8;
+const ENTRY_LEN: usize = 4 + CHECKSUM_LEN;
+/// 回复中表示“没有共同字典”的 ID
+const NO_DICTIONARY: u32 = 0;
+
+/// zstd 压缩字典，以应用自定的 ID 标识
+///
+/// ```ignore
+/// let dict = Dictionary::train(1, &samples, ByteSize::kib(16))?;
+/// std::fs::write("telemetry.dict", dict.as_bytes())?;
+/// let config = ClientConfig::default().with_dictionary(dict);
+/// ```
+#[derive(Clone)]
+pub struct Dictionary {
+    id: u32,
+    checksum: [u8; CHECKSUM_LEN],
+    bytes: Arc<[u8]>,
+    encoder: Arc<EncoderDictionary<'static>>,
+    decoder: Arc<DecoderDictionary<'static>>,
+}
+
+impl Dictionary {
+    /// 使用已有的字典内容，例如此前 `train()` 后保存的文件
+    pub fn new(id: u32, bytes: impl AsRef<[u8]>) -> Self {
+        let bytes = bytes.as_ref();
+        let digest = Sha256::digest(bytes);
+        Self {
+            id,
+            checksum: digest[..CHECKSUM_LEN].try_into().unwrap(),
+            bytes: Arc::from(bytes),
+            encoder: Arc::new(EncoderDictionary::copy(bytes, DEFAULT_COMPRESSION_LEVEL)),
+            decoder: Arc::new(DecoderDictionary::copy(bytes)),
+        }
+    }
+
+    /// 用应用的样本消息训练字典，字典不超过 `max_size`；样本过少时返回错误
+    pub fn train<S: AsRef<[u8]>>(
+        id: u32,
+        samples: &[S],
+        max_size: ByteSize,
+    ) -> crate::Result<Self> {
+        let bytes = zstd::dict::from_samples(samples, max_size.as_usize())?;
+        Ok(Self::new(id, bytes))
+    }
+
+    /// 字典 ID
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// 字典内容，可保存后以 `new()` 加载
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(super) fn encoder(&self) -> &EncoderDictionary<'static> {
+        &self.encoder
+    }
+
+    pub(super) fn decoder(&self) -> &DecoderDictionary<'static> {
+        &self.decoder
+    }
+
+    /// 校验一组字典：ID 不能为 0 且不能重复，内容不能为空
+    pub(crate) fn validate(dictionaries: &[Dictionary]) -> crate::Result<()> {
+        for (i, dict) in dictionaries.iter().enumerate() {
+            if dict.id == NO_DICTIONARY {
+                return Err(VirgeError::ConfigError(
+                    "dictionary id 0 is reserved".to_string(),
+                ));
+            }
+            if dict.bytes.is_empty() {
+                return Err(VirgeError::ConfigError(format!(
+                    "dictionary {} is empty",
+                    dict.id
+                )));
+            }
+            if dictionaries[..i].iter().any(|d| d.id == dict.id) {
+                return Err(VirgeError::ConfigError(format!(
+                    "duplicate dictionary id {}",
+                    dict.id
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dictionary")
+            .field("id", &self.id)
+            .field("size", &ByteSize::from(self.bytes.len()))
+            .field("checksum", &hex(&self.checksum))
+            .finish()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn offer(dictionaries: &[Dictionary]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(MAGIC.len() + dictionaries.len() * ENTRY_LEN);
+    msg.extend_from_slice(MAGIC);
+    for dict in dictionaries {
+        msg.extend_from_slice(&dict.id.to_be_bytes());
+        msg.extend_from_slice(&dict.checksum);
+    }
+    msg
+}
+
+/// 服务端：在客户端提议中选出第一个本端也有的字典
+fn choose<'a>(dictionaries: &'a [Dictionary], offer: &[u8]) -> Result<Option<&'a Dictionary>> {
+    let entries = offer
+        .strip_prefix(MAGIC)
+        .filter(|r| r.len() % ENTRY_LEN == 0)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed dictionary offer"))?;
+    Ok(entries.chunks_exact(ENTRY_LEN).find_map(|entry| {
+        let (id, checksum) = entry.split_at(4);
+        let id = u32::from_be_bytes(id.try_into().unwrap());
+        dictionaries
+            .iter()
+            .find(|d| d.id == id && d.checksum[..] == *checksum)
+    }))
+}
+
+/// 客户端：解析服务端的选择，只接受本端提议过的 ID
+fn chosen(dictionaries: &[Dictionary], reply: &[u8]) -> Result<Option<Dictionary>> {
+    let id = reply
+        .strip_prefix(MAGIC)
+        .and_then(|r| <[u8; 4]>::try_from(r).ok())
+        .map(u32::from_be_bytes)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed dictionary reply"))?;
+    if id == NO_DICTIONARY {
+        return Ok(None);
+    }
+    match dictionaries.iter().find(|d| d.id == id) {
+        Some(dict) => Ok(Some(dict.clone())),
+        None => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("server chose dictionary {} that was not offered", id),
+        )),
+    }
+}
+
+/// 客户端发起字典协商，返回双方共同的字典
+pub(crate) fn connect_dictionary(
+    dictionaries: &[Dictionary],
+    chan: &mut impl MessageChannel,
+) -> Result<Option<Dictionary>> {
+    chan.send_msg(&offer(dictionaries))?;
+    chosen(dictionaries, &chan.recv_msg()?)
+}
+
+/// 服务端响应字典协商
+pub(crate) fn accept_dictionary(
+    dictionaries: &[Dictionary],
+    chan: &mut impl MessageChannel,
+) -> Result<Option<Dictionary>> {
+    let dict = choose(dictionaries, &chan.recv_msg()?)?;
+    let id = dict.map_or(NO_DICTIONARY, Dictionary::id);
+    let mut reply = MAGIC.to_vec();
+    reply.extend_from_slice(&id.to_be_bytes());
+    chan.send_msg(&reply)?;
+    Ok(dict.cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::CompressionContext;
+
+    fn samples() -> Vec<Vec<u8>> {
+        (0..500)
+            .map(|i| {
+                format!(
+                    r#"{{"host":"vm-{}","metric":"cpu.user","value":{},"unit":"percent","tags":["prod","zone-a"]}}"#,
+                    i % 17,
+                    i * 7 % 100
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trained_dictionary_beats_plain_compression() {
+        let samples = samples();
+        let dict = Dictionary::train(7, &samples, ByteSize::kib(4)).unwrap();
+        assert_eq!(dict.id(), 7);
+        let loaded = Dictionary::new(7, dict.as_bytes());
+        assert_eq!(loaded.checksum, dict.checksum);
+
+        let message = &samples[123];
+        let plain = CompressionContext::default().compress(message).unwrap();
+        let ctx = CompressionContext::new(Some(dict));
+        let packed = ctx.compress(message).unwrap();
+        assert!(packed.len() < plain.len());
+        let peer = CompressionContext::new(Some(loaded));
+        assert_eq!(peer.decompress(&packed).unwrap(), *message);
+        assert!(CompressionContext::default().decompress(&packed).is_err());
+    }
+
+    #[test]
+    fn negotiation_picks_first_shared_dictionary() {
+        let a = Dictionary::new(1, b"dictionary one");
+        let b = Dictionary::new(2, b"dictionary two");
+        let client = [Dictionary::new(3, b"client only"), b.clone(), a.clone()];
+        let server = [a.clone(), b.clone()];
+        let dict = choose(&server, &offer(&client)).unwrap().unwrap();
+        assert_eq!(dict.id(), 2);
+
+        let mut reply = MAGIC.to_vec();
+        reply.extend_from_slice(&2u32.to_be_bytes());
+        assert_eq!(chosen(&client, &reply).unwrap().unwrap().id(), 2);
+    }
+
+    #[test]
+    fn same_id_with_different_content_is_not_shared() {
+        let client = [Dictionary::new(1, b"version one")];
+        let server = [Dictionary::new(1, b"version two")];
+        assert!(choose(&server, &offer(&client)).unwrap().is_none());
+
+        let mut reply = MAGIC.to_vec();
+        reply.extend_from_slice(&NO_DICTIONARY.to_be_bytes());
+        assert!(chosen(&client, &reply).unwrap().is_none());
+        let mut reply = MAGIC.to_vec();
+        reply.extend_from_slice(&9u32.to_be_bytes());
+        assert!(chosen(&client, &reply).is_err());
+        assert!(choose(&server, b"VGD1short").is_err());
+    }
+
+    #[test]
+    fn validate_rejects_reserved_and_duplicate_ids() {
+        let ok = [Dictionary::new(1, b"a"), Dictionary::new(2, b"b")];
+        assert!(Dictionary::validate(&ok).is_ok());
+        assert!(Dictionary::validate(&[Dictionary::new(0, b"a")]).is_err());
+        assert!(Dictionary::validate(&[Dictionary::new(1, b"")]).is_err());
+        let dup = [Dictionary::new(1, b"a"), Dictionary::new(1, b"b")];
+        assert!(Dictionary::validate(&dup).is_err());
+    }
+}
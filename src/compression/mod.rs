@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 按消息压缩
+//!
+//! 是否压缩由调用方逐条决定：`send_compressed()` 以 zstd 压缩后发送，并在帧上标记，
+//! 接收方据标记自动解压，`recv()` 拿到的总是原文；`send()` 从不压缩，适合图片、
+//! 密文等本身已压缩的负载。发送需要启用 `compression` 特性；未启用时收到压缩消息
+//! 返回 `Unsupported`。
+//!
+//! 标记的位置：xtransport 在消息前发送一个控制包，yamux 在消息前发送一个控制帧，
+//! 启用加密时标记在加密帧类型中，一并受认证保护。
+//!
+//! 大量相似的小消息可配置 [`Dictionary`]：握手时双方按 ID 协商出共同的字典，
+//! 之后该连接上的压缩消息都使用它。
+
+#[cfg(feature = "compression")]
+mod dictionary;
+#[cfg(feature = "compression")]
+pub use dictionary::Dictionary;
+#[cfg(feature = "compression")]
+pub(crate) use dictionary::{accept_dictionary, connect_dictionary};
+
+use std::io::ErrorKind;
+
+use crate::error::{Result, VirgeError};
+
+/// 默认压缩级别
+#[cfg(feature = "compression")]
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// 解压后允许的最大长度，防止压缩炸弹
+pub const MAX_DECOMPRESSED_SIZE: usize = crate::GIB;
+
+/// 一条连接的压缩状态：握手协商出的字典
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CompressionContext {
+    #[cfg(feature = "compression")]
+    dictionary: Option<Dictionary>,
+}
+
+impl CompressionContext {
+    #[cfg(feature = "compression")]
+    pub(crate) fn new(dictionary: Option<Dictionary>) -> Self {
+        Self { dictionary }
+    }
+
+    /// 协商出的字典 ID
+    #[cfg(feature = "compression")]
+    pub(crate) fn dictionary_id(&self) -> Option<u32> {
+        self.dictionary.as_ref().map(Dictionary::id)
+    }
+
+    /// 以 zstd 压缩一条消息，帧头记录原文长度
+    #[cfg(feature = "compression")]
+    pub(crate) fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let compressed = match &self.dictionary {
+            Some(dict) => zstd::bulk::Compressor::with_prepared_dictionary(dict.encoder())
+                .and_then(|mut c| c.compress(data)),
+            None => zstd::bulk::compress(data, DEFAULT_COMPRESSION_LEVEL),
+        };
+        compressed.map_err(VirgeError::from)
+    }
+
+    /// 解压一条标记为已压缩的消息
+    #[cfg(feature = "compression")]
+    pub(crate) fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let size = content_size(data)?;
+        let decompressed = match &self.dictionary {
+            Some(dict) => zstd::bulk::Decompressor::with_prepared_dictionary(dict.decoder())
+                .and_then(|mut d| d.decompress(data, size)),
+            None => zstd::bulk::decompress(data, size),
+        };
+        decompressed.map_err(|e| {
+            VirgeError::transport(
+                ErrorKind::InvalidData,
+                format!("failed to decompress message: {}", e),
+            )
+        })
+    }
+
+    #[cfg(not(feature = "compression"))]
+    pub(crate) fn decompress(&self, _data: &[u8]) -> Result<Vec<u8>> {
+        Err(VirgeError::transport(
+            ErrorKind::Unsupported,
+            "received a compressed message but the `compression` feature is disabled",
+        ))
+    }
+}
+
+/// 帧头记录的原文长度，超过上限视为压缩炸弹
+#[cfg(feature = "compression")]
+fn content_size(data: &[u8]) -> Result<usize> {
+    let size = match zstd::zstd_safe::get_frame_content_size(data) {
+        Ok(Some(size)) => size,
+        _ => {
+            return Err(VirgeError::transport(
+                ErrorKind::InvalidData,
+                "compressed message lacks a valid zstd frame header",
+            ))
+        }
+    };
+    if size > MAX_DECOMPRESSED_SIZE as u64 {
+        return Err(VirgeError::transport(
+            ErrorKind::InvalidData,
+            format!(
+                "compressed message expands to {} bytes, limit {}",
+                size, MAX_DECOMPRESSED_SIZE
+            ),
+        ));
+    }
+    Ok(size as usize)
+}
+
+#[cfg(all(test, feature = "compression"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_shrinks_repetitive_data() {
+        let ctx = CompressionContext::default();
+        let data = b"telemetry cpu=3 mem=42 ".repeat(200);
+        let packed = ctx.compress(&data).unwrap();
+        assert!(packed.len() < data.len() / 10);
+        assert_eq!(ctx.decompress(&packed).unwrap(), data);
+        assert_eq!(ctx.decompress(&ctx.compress(&[]).unwrap()).unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_garbage_and_bombs() {
+        let ctx = CompressionContext::default();
+        let err = std::io::Error::from(ctx.decompress(b"not zstd").unwrap_err());
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // 帧头声称的原文长度超过上限：magic + 单段、8 字节内容长度的帧头
+        let mut bomb = vec![0x28, 0xb5, 0x2f, 0xfd, 0xe0];
+        bomb.extend_from_slice(&(MAX_DECOMPRESSED_SIZE as u64 + 1).to_le_bytes());
+        assert!(ctx.decompress(&bomb).is_err());
+    }
+}
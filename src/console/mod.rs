@@ -0,0 +1,227 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 经复用通道转发客户机上的交互式终端
+//!
+//! 客户机在 PTY 上启动 shell（[`PtySession`]），宿主机的 [`Console`] 经一条
+//! [`mux`](crate::mux) 通道收取终端输出、发送键盘输入，并可调整窗口大小、向前台进程组
+//! 发送信号。通道号由双方约定，默认用 [`CONSOLE_CHANNEL`]：
+//!
+//! ```ignore
+//! // 客户机
+//! let mux = client.into_mux()?;
+//! let session = PtySession::spawn(Command::new("/bin/sh"), WindowSize::new(24, 80))?;
+//! let status = session.serve(mux.channel(CONSOLE_CHANNEL)?)?;
+//!
+//! // 宿主机
+//! let mux = server.into_mux()?;
+//! let console = Console::new(mux.channel(CONSOLE_CHANNEL)?);
+//! let input = console.input();
+//! thread::spawn(move || forward_keys(input));
+//! while let ConsoleEvent::Output(bytes) = console.recv()? {
+//!     stdout.write_all(&bytes)?;
+//! }
+//! ```
+//!
+//! 通道上每条消息以 1 字节类型开头：
+//!
+//! ```text
+//! DATA   | bytes                       双向：终端输出或键盘输入
+//! RESIZE | rows(2, 大端) cols(2, 大端)  宿主机 → 客户机
+//! SIGNAL | signo(4, 大端)               宿主机 → 客户机
+//! EXIT   | code(4, 大端)                客户机 → 宿主机，之后不再有消息
+//! ```
+//!
+//! 未知类型的消息记录警告后忽略，便于以后增加类型。
+
+mod pty;
+
+pub use pty::PtySession;
+
+use std::io::{Error, ErrorKind, Result};
+
+use log::*;
+
+use crate::mux::{Channel, ChannelSender};
+
+/// 默认承载终端的通道号
+pub const CONSOLE_CHANNEL: u32 = 2;
+
+const DATA: u8 = 0;
+const RESIZE: u8 = 1;
+const SIGNAL: u8 = 2;
+const EXIT: u8 = 3;
+
+/// 终端窗口大小
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WindowSize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl WindowSize {
+    pub fn new(rows: u16, cols: u16) -> Self {
+        Self { rows, cols }
+    }
+}
+
+/// 通道上的一条消息
+#[derive(Debug, PartialEq, Eq)]
+enum Frame {
+    Data(Vec<u8>),
+    Resize(WindowSize),
+    Signal(i32),
+    Exit(i32),
+}
+
+impl Frame {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Frame::Data(bytes) => {
+                buf.push(DATA);
+                buf.extend_from_slice(bytes);
+            }
+            Frame::Resize(size) => {
+                buf.push(RESIZE);
+                buf.extend_from_slice(&size.rows.to_be_bytes());
+                buf.extend_from_slice(&size.cols.to_be_bytes());
+            }
+            Frame::Signal(signo) => {
+                buf.push(SIGNAL);
+                buf.extend_from_slice(&signo.to_be_bytes());
+            }
+            Frame::Exit(code) => {
+                buf.push(EXIT);
+                buf.extend_from_slice(&code.to_be_bytes());
+            }
+        }
+        buf
+    }
+
+    /// 解析一条消息，未知类型返回 `None`
+    fn decode(mut message: Vec<u8>) -> Result<Option<Self>> {
+        let Some(&kind) = message.first() else {
+            return Err(Error::new(ErrorKind::InvalidData, "empty console frame"));
+        };
+        let body = &message[1..];
+        let fixed = |len: usize| -> Result<&[u8]> {
+            if body.len() == len {
+                Ok(body)
+            } else {
+                Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("console frame {} has {} bytes", kind, body.len()),
+                ))
+            }
+        };
+        let frame = match kind {
+            DATA => {
+                message.remove(0);
+                Frame::Data(message)
+            }
+            RESIZE => {
+                let body = fixed(4)?;
+                Frame::Resize(WindowSize::new(
+                    u16::from_be_bytes([body[0], body[1]]),
+                    u16::from_be_bytes([body[2], body[3]]),
+                ))
+            }
+            SIGNAL => Frame::Signal(i32::from_be_bytes(fixed(4)?.try_into().unwrap())),
+            EXIT => Frame::Exit(i32::from_be_bytes(fixed(4)?.try_into().unwrap())),
+            _ => {
+                warn!("Ignoring console frame of unknown type {}", kind);
+                return Ok(None);
+            }
+        };
+        Ok(Some(frame))
+    }
+}
+
+/// 宿主机收到的终端事件
+#[derive(Debug, PartialEq, Eq)]
+pub enum ConsoleEvent {
+    /// 终端输出
+    Output(Vec<u8>),
+    /// 客户机上的进程已退出：退出码，或被信号终止时为 128 + 信号值（与 shell 的 `$?` 相同）
+    Exited(i32),
+}
+
+/// 宿主机一侧的终端
+#[derive(Debug)]
+pub struct Console {
+    channel: Channel,
+}
+
+impl Console {
+    pub fn new(channel: Channel) -> Self {
+        Self { channel }
+    }
+
+    /// 发送输入与控制消息的句柄，可交给其他线程，与 `recv()` 并行使用
+    pub fn input(&self) -> ConsoleInput {
+        ConsoleInput(self.channel.sender())
+    }
+
+    /// 接收下一个终端事件；`Exited` 之后通道上不再有事件
+    pub fn recv(&self) -> Result<ConsoleEvent> {
+        loop {
+            match Frame::decode(self.channel.recv()?)? {
+                Some(Frame::Data(bytes)) => return Ok(ConsoleEvent::Output(bytes)),
+                Some(Frame::Exit(code)) => return Ok(ConsoleEvent::Exited(code)),
+                Some(frame) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unexpected console frame from guest: {:?}", frame),
+                    ))
+                }
+                None => continue,
+            }
+        }
+    }
+}
+
+/// 向客户机终端发送输入与控制消息，由 [`Console::input()`] 取得
+#[derive(Clone, Debug)]
+pub struct ConsoleInput(ChannelSender);
+
+impl ConsoleInput {
+    /// 发送键盘输入；Ctrl-C 等控制字符由客户机的终端按行规程处理
+    pub fn write(&self, bytes: &[u8]) -> Result<()> {
+        self.0.send(&Frame::Data(bytes.to_vec()).encode())
+    }
+
+    /// 调整窗口大小，客户机的前台进程组随之收到 `SIGWINCH`
+    pub fn resize(&self, size: WindowSize) -> Result<()> {
+        self.0.send(&Frame::Resize(size).encode())
+    }
+
+    /// 向客户机终端的前台进程组发送信号 `signo`（如 `libc::SIGINT`）
+    pub fn signal(&self, signo: i32) -> Result<()> {
+        self.0.send(&Frame::Signal(signo).encode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        for frame in [
+            Frame::Data(b"ls -l\r".to_vec()),
+            Frame::Data(Vec::new()),
+            Frame::Resize(WindowSize::new(50, 132)),
+            Frame::Signal(libc::SIGINT),
+            Frame::Exit(130),
+        ] {
+            assert_eq!(Frame::decode(frame.encode()).unwrap(), Some(frame));
+        }
+        assert_eq!(Frame::decode(vec![9, 1, 2]).unwrap(), None);
+        for bad in [vec![], vec![RESIZE, 0, 24], vec![EXIT, 0, 0, 0, 0, 0]] {
+            let err = Frame::decode(bad).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
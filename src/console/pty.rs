@@ -0,0 +1,295 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 客户机一侧：在 PTY 上运行进程并经通道转发
+
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+use log::*;
+
+use super::{Frame, WindowSize};
+use crate::mux::{Channel, ChannelSender};
+
+/// 等待宿主机消息时检查进程是否已退出的间隔
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 每条输出消息的最大字节数
+const OUTPUT_CHUNK: usize = 4096;
+
+/// 在 PTY 上运行的进程
+#[derive(Debug)]
+pub struct PtySession {
+    master: File,
+    child: Option<Child>,
+}
+
+impl PtySession {
+    /// 打开一个 PTY，以其从端为控制终端与标准输入输出、在新会话中启动 `command`
+    pub fn spawn(mut command: Command, size: WindowSize) -> Result<Self> {
+        // SAFETY: posix_openpt 返回新打开的 fd 或 -1，成功时由 OwnedFd 接管
+        let master = unsafe {
+            let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(Error::last_os_error());
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+        let fd = master.as_raw_fd();
+        let mut name = [0 as libc::c_char; 128];
+        // SAFETY: fd 是有效的 PTY 主端，name 的长度如实传入，ptsname_r 写入以 NUL 结尾的路径
+        let slave = unsafe {
+            if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+                return Err(Error::last_os_error());
+            }
+            let err = libc::ptsname_r(fd, name.as_mut_ptr(), name.len());
+            if err != 0 {
+                return Err(Error::from_raw_os_error(err));
+            }
+            CStr::from_ptr(name.as_ptr())
+        };
+        let slave = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(slave.to_string_lossy().as_ref())?;
+        let master = File::from(master);
+        set_size(&master, size)?;
+
+        command
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // SAFETY: 只调用 async-signal-safe 的 setsid 与 ioctl
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        // 关闭本进程持有的从端，进程退出后读主端才会结束
+        drop(command);
+        info!("Console session started, pid {}", child.id());
+        Ok(Self {
+            master,
+            child: Some(child),
+        })
+    }
+
+    /// 进程号
+    pub fn id(&self) -> u32 {
+        self.child.as_ref().map_or(0, Child::id)
+    }
+
+    /// 在通道上转发终端，直到进程退出并且终端的所有使用者都已关闭它，返回退出状态。
+    /// 通道断开时向会话发送 `SIGHUP`，与挂断终端相同
+    pub fn serve(mut self, channel: Channel) -> Result<ExitStatus> {
+        let pid = self.id() as libc::pid_t;
+        let output = {
+            let master = self.master.try_clone()?;
+            let sender = channel.sender();
+            crate::threads::spawn("console", move || forward_output(master, &sender))?
+        };
+        while !output.is_finished() {
+            match channel.recv_timeout(EXIT_POLL_INTERVAL) {
+                Ok(Some(message)) => {
+                    if let Err(e) = self.apply(message, pid) {
+                        warn!("Console input for pid {} failed: {}", pid, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    info!("Console channel closed ({}), hanging up pid {}", e, pid);
+                    signal(pid, libc::SIGHUP);
+                    break;
+                }
+            }
+        }
+        let forwarded = output
+            .join()
+            .unwrap_or_else(|_| Err(Error::other("console output thread panicked")));
+        if forwarded.is_err() {
+            signal(pid, libc::SIGHUP);
+        }
+        let mut child = self.child.take().expect("child is present until served");
+        let status = child.wait()?;
+        forwarded?;
+        let code = status
+            .code()
+            .or_else(|| status.signal().map(|signo| 128 + signo))
+            .unwrap_or(-1);
+        drop(channel.send(&Frame::Exit(code).encode()));
+        info!("Console session pid {} exited: {}", pid, status);
+        Ok(status)
+    }
+
+    fn apply(&self, message: Vec<u8>, pid: libc::pid_t) -> Result<()> {
+        match Frame::decode(message)? {
+            Some(Frame::Data(bytes)) => (&self.master).write_all(&bytes),
+            Some(Frame::Resize(size)) => set_size(&self.master, size),
+            Some(Frame::Signal(signo)) => {
+                // SAFETY: 对有效的 fd 调用 tcgetpgrp
+                let group = unsafe { libc::tcgetpgrp(self.master.as_raw_fd()) };
+                signal(if group > 0 { group } else { pid }, signo);
+                Ok(())
+            }
+            Some(frame @ Frame::Exit(_)) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected console frame from host: {:?}", frame),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for PtySession {
+    /// 未调用 `serve()` 时挂断终端并回收进程
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            signal(child.id() as libc::pid_t, libc::SIGHUP);
+            let _ = child.wait();
+        }
+    }
+}
+
+/// 把终端输出转发到通道，直到终端的所有使用者都已关闭它
+fn forward_output(mut master: File, sender: &ChannelSender) -> Result<()> {
+    let mut buf = vec![0u8; OUTPUT_CHUNK];
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            // 宿主机已断开时继续读取，直到挂断的进程关闭终端
+            Ok(n) => drop(sender.send(&Frame::Data(buf[..n].to_vec()).encode())),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            // 从端全部关闭后读主端返回 EIO
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn set_size(master: &File, size: WindowSize) -> Result<()> {
+    let winsize = libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCSWINSZ 读取一个有效的 winsize
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ as _, &winsize) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 向进程组 `group`（会话首进程的进程号即其进程组号）发送信号，进程组已不存在时忽略
+fn signal(group: libc::pid_t, signo: i32) {
+    // SAFETY: kill 不涉及内存
+    if unsafe { libc::kill(-group, signo) } < 0 {
+        debug!(
+            "Signal {} to process group {} failed: {}",
+            signo,
+            group,
+            Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(all(test, feature = "use-xtransport"))]
+mod tests {
+    use super::super::{Console, ConsoleEvent, CONSOLE_CHANNEL};
+    use super::*;
+    use crate::mux::testing::pair;
+    use std::thread;
+
+    /// 在客户机一侧运行 `script`，返回宿主机一侧的终端与会话线程
+    fn start(
+        script: &str,
+    ) -> (
+        Console,
+        thread::JoinHandle<Result<ExitStatus>>,
+        crate::mux::Mux,
+    ) {
+        let (host, guest) = pair();
+        let channel = guest.channel(CONSOLE_CHANNEL).unwrap();
+        let console = Console::new(host.channel(CONSOLE_CHANNEL).unwrap());
+        let mut command = Command::new("/bin/sh");
+        command.arg("-c").arg(script);
+        let session = PtySession::spawn(command, WindowSize::new(24, 80)).unwrap();
+        let serve = thread::spawn(move || {
+            let status = session.serve(channel);
+            drop(guest);
+            status
+        });
+        (console, serve, host)
+    }
+
+    /// 收取输出直到包含 `needle`
+    fn read_until(console: &Console, output: &mut String, needle: &str) {
+        while !output.contains(needle) {
+            match console.recv().unwrap() {
+                ConsoleEvent::Output(bytes) => output.push_str(&String::from_utf8_lossy(&bytes)),
+                ConsoleEvent::Exited(code) => {
+                    panic!("exited with {} before {:?}: {}", code, needle, output)
+                }
+            }
+        }
+    }
+
+    fn exit_code(console: &Console) -> i32 {
+        loop {
+            if let ConsoleEvent::Exited(code) = console.recv().unwrap() {
+                return code;
+            }
+        }
+    }
+
+    #[test]
+    fn shell_runs_on_a_terminal() {
+        let (console, serve, _host) =
+            start("stty size; test -t 0 && echo tty; read line; echo got:$line; exit 3");
+        let mut output = String::new();
+        read_until(&console, &mut output, "tty");
+        assert!(output.contains("24 80"), "{}", output);
+
+        console.input().write(b"hello\r").unwrap();
+        read_until(&console, &mut output, "got:hello");
+        assert_eq!(exit_code(&console), 3);
+        assert_eq!(serve.join().unwrap().unwrap().code(), Some(3));
+    }
+
+    #[test]
+    fn resize_and_signals_reach_the_session() {
+        let (console, serve, _host) = start("echo ready; read line; stty size; exec sleep 30");
+        let input = console.input();
+        let mut output = String::new();
+        read_until(&console, &mut output, "ready");
+        input.resize(WindowSize::new(40, 100)).unwrap();
+        input.write(b"\r").unwrap();
+        read_until(&console, &mut output, "40 100");
+
+        input.signal(libc::SIGTERM).unwrap();
+        assert_eq!(exit_code(&console), 128 + libc::SIGTERM);
+        assert_eq!(serve.join().unwrap().unwrap().signal(), Some(libc::SIGTERM));
+    }
+
+    #[test]
+    fn host_disconnect_hangs_up_the_session() {
+        let (console, serve, host) = start("echo ready; exec sleep 30");
+        let mut output = String::new();
+        read_until(&console, &mut output, "ready");
+        drop(console);
+        host.close();
+        let status = serve.join().unwrap().unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGHUP));
+    }
+}
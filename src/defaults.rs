@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 配置的默认值
+//!
+//! `ClientConfig`、`ServerConfig` 的 `Default` 实现与 `new()` 未给出的字段取自
+//! [`Defaults::get()`]：内置值 [`Defaults::BUILTIN`] 叠加以下环境变量，进程内只在首次
+//! 使用时读取一次。无法解析的值记录警告后忽略。
+//!
+//! | 环境变量 | 字段 | 格式 |
+//! |----------|------|------|
+//! | `VIRGA_SERVER_CID` | `server_cid` | 十进制 CID |
+//! | `VIRGA_SERVER_PORT` | `server_port` | 十进制端口 |
+//! | `VIRGA_LISTEN_CID` | `listen_cid` | 十进制 CID |
+//! | `VIRGA_DISCOVERY_PORT` | `discovery_port` | 十进制端口 |
+//! | `VIRGA_CHUNK_SIZE` | `chunk_size` | 字节数，如 `4096`、`4KiB` |
+//! | `VIRGA_ACK` | `is_ack` | `1`/`true`/`0`/`false` |
+//! | `VIRGA_SEND_WINDOW` | `send_window` | 正整数 |
+//! | `VIRGA_READ_QUEUE_DEPTH` | `read_queue_depth` | 正整数 |
+
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use log::*;
+
+use crate::units::ByteSize;
+
+/// 客户端与服务端配置的默认值，见[模块文档](self)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Defaults {
+    /// 客户端连接的服务端 CID
+    pub server_cid: u32,
+    /// 客户端连接、服务端监听的端口
+    pub server_port: u32,
+    /// 服务端监听的 CID
+    pub listen_cid: u32,
+    /// 宿主机发现服务的端口，见 `discovery` 模块
+    pub discovery_port: u32,
+    /// 分片大小（字节）
+    pub chunk_size: NonZeroUsize,
+    /// 是否逐包确认
+    pub is_ack: bool,
+    /// 发送窗口（包数）
+    pub send_window: NonZeroUsize,
+    /// yamux 后台读任务预读的消息帧数
+    pub read_queue_depth: NonZeroUsize,
+}
+
+const fn nonzero(n: usize) -> NonZeroUsize {
+    match NonZeroUsize::new(n) {
+        Some(n) => n,
+        None => panic!("default must be nonzero"),
+    }
+}
+
+impl Defaults {
+    /// 内置默认值
+    pub const BUILTIN: Defaults = Defaults {
+        server_cid: 103,
+        server_port: 1234,
+        listen_cid: u32::MAX,
+        discovery_port: 1023,
+        chunk_size: nonzero(crate::KIB),
+        is_ack: false,
+        send_window: nonzero(16),
+        read_queue_depth: nonzero(64),
+    };
+
+    /// 当前进程使用的默认值：内置值叠加环境变量，首次调用时读取
+    pub fn get() -> Defaults {
+        static DEFAULTS: OnceLock<Defaults> = OnceLock::new();
+        *DEFAULTS.get_or_init(|| Self::with_overrides(|name| std::env::var(name).ok()))
+    }
+
+    /// 以 `lookup` 查到的值覆盖内置值
+    fn with_overrides(lookup: impl Fn(&str) -> Option<String>) -> Defaults {
+        let mut defaults = Self::BUILTIN;
+        let lookup = &lookup;
+        override_with(lookup, "VIRGA_SERVER_CID", &mut defaults.server_cid, parse);
+        override_with(
+            lookup,
+            "VIRGA_SERVER_PORT",
+            &mut defaults.server_port,
+            parse,
+        );
+        override_with(lookup, "VIRGA_LISTEN_CID", &mut defaults.listen_cid, parse);
+        override_with(
+            lookup,
+            "VIRGA_DISCOVERY_PORT",
+            &mut defaults.discovery_port,
+            parse,
+        );
+        override_with(
+            lookup,
+            "VIRGA_CHUNK_SIZE",
+            &mut defaults.chunk_size,
+            |raw| {
+                let size: ByteSize = raw.parse().ok()?;
+                NonZeroUsize::new(size.as_usize())
+            },
+        );
+        override_with(lookup, "VIRGA_ACK", &mut defaults.is_ack, |raw| match raw {
+            "1" | "true" => Some(true),
+            "0" | "false" => Some(false),
+            _ => None,
+        });
+        override_with(
+            lookup,
+            "VIRGA_SEND_WINDOW",
+            &mut defaults.send_window,
+            parse,
+        );
+        override_with(
+            lookup,
+            "VIRGA_READ_QUEUE_DEPTH",
+            &mut defaults.read_queue_depth,
+            parse,
+        );
+        defaults
+    }
+
+    /// 分片大小
+    pub fn chunk_size(&self) -> ByteSize {
+        ByteSize::from(self.chunk_size.get())
+    }
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Self::get()
+    }
+}
+
+/// 环境变量存在且能解析时覆盖 `value`
+fn override_with<T>(
+    lookup: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    value: &mut T,
+    parse: impl Fn(&str) -> Option<T>,
+) {
+    let Some(raw) = lookup(name) else {
+        return;
+    };
+    match parse(raw.trim()) {
+        Some(parsed) => *value = parsed,
+        None => warn!("Ignoring invalid {}={:?}", name, raw),
+    }
+}
+
+fn parse<T: FromStr>(raw: &str) -> Option<T> {
+    raw.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn overridden(vars: &[(&str, &str)]) -> Defaults {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Defaults::with_overrides(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn builtin_values() {
+        let d = Defaults::BUILTIN;
+        assert_eq!(d.server_cid, 103);
+        assert_eq!(d.server_port, 1234);
+        assert_eq!(d.listen_cid, crate::VMADDR_CID_ANY as u32);
+        assert_eq!(d.discovery_port, 1023);
+        assert_eq!(d.chunk_size(), ByteSize::kib(1));
+        assert!(!d.is_ack);
+        assert_eq!(d.send_window.get(), 16);
+        assert_eq!(d.read_queue_depth.get(), 64);
+        assert_eq!(overridden(&[]), d);
+    }
+
+    #[test]
+    fn environment_overrides_valid_values_only() {
+        let d = overridden(&[
+            ("VIRGA_SERVER_CID", "3"),
+            ("VIRGA_SERVER_PORT", " 9000 "),
+            ("VIRGA_CHUNK_SIZE", "4KiB"),
+            ("VIRGA_ACK", "1"),
+            ("VIRGA_SEND_WINDOW", "0"),
+            ("VIRGA_READ_QUEUE_DEPTH", "many"),
+        ]);
+        assert_eq!(d.server_cid, 3);
+        assert_eq!(d.server_port, 9000);
+        assert_eq!(d.chunk_size(), ByteSize::kib(4));
+        assert!(d.is_ack);
+        assert_eq!(d.send_window, Defaults::BUILTIN.send_window);
+        assert_eq!(d.read_queue_depth, Defaults::BUILTIN.read_queue_depth);
+
+        let d = overridden(&[("VIRGA_ACK", "false"), ("VIRGA_CHUNK_SIZE", "0")]);
+        assert!(!d.is_ack);
+        assert_eq!(d.chunk_size, Defaults::BUILTIN.chunk_size);
+    }
+}
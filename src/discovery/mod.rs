@@ -0,0 +1,641 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 服务发现
+//!
+//! 宿主机在固定端口（默认 `Defaults::get().discovery_port`，即 1023）上运行一个发现服务，
+//! 记录“服务名 → 端口、版本”。宿主机上的服务启动时登记、退出时注销，客户机按名字查询，
+//! 不必在两边各维护一张端口表：
+//!
+//! ```ignore
+//! // 宿主机：运行发现服务，同进程的服务可直接登记
+//! let registry = Registry::new();
+//! registry.register(ServiceRecord::new("logs", 5100, "1.2"))?;
+//! let mut manager = ServerManager::new(
+//!     ServerConfig::default().with_listen_addr(Addr::any(Defaults::get().discovery_port)),
+//! );
+//! manager.start()?;
+//! DiscoveryServer::new(registry).run(&mut manager)?;
+//!
+//! // 宿主机上的其他进程经 vsock 回环登记
+//! discovery::register(Addr::local(1023), &ServiceRecord::new("metrics", 5200, "0.3"))?;
+//!
+//! // 客户机：按名字连接
+//! let mut client = VirgeClient::new(ClientConfig::default().with_server_addr(Addr::host(0)));
+//! let record = client.connect_service("logs")?;
+//! ```
+//!
+//! 请求经 [`rpc`](crate::rpc) 编码，服务名为 [`SERVICE`]：
+//!
+//! ```text
+//! 服务记录: name_len(1) | name | port(4, 大端) | version_len(1) | version
+//! register:   服务记录 -> 空
+//! unregister: name_len(1) | name -> 空
+//! resolve:    name_len(1) | name -> 服务记录；未登记时返回 NotFound
+//! list:       空 -> count(2, 大端) | 服务记录...
+//! subscribe:  空 -> 同 list，之后该连接只用于推送通告
+//!
+//! 通告: 1 | 服务记录（登记或更新） 或 2 | name_len(1) | name（注销）
+//! ```
+//!
+//! 客户机代理用 [`subscribe()`] 订阅后，宿主机上的服务登记、注销时发现服务立即向订阅的
+//! 连接推送 [`Announcement`]，代理不必轮询发现端口。订阅时返回的快照与之后的通告之间
+//! 不会遗漏变化；连接断开后重新订阅，以新的快照为准。
+//!
+//! 登记与注销只接受来自 [`DiscoveryServer::with_registrars()`] 中 CID 的连接，默认为本机回环
+//! 与宿主机自身，客户机只能查询；`ServerConfig::with_authorizer()` 的授权规则同样适用。
+//!
+//! 服务端配置了 `ServerConfig::with_tenants()` 时，每个连接只能看到自己租户的命名空间，
+//! 见 [`Registry::for_tenant()`]。
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use log::*;
+
+use crate::addr::Addr;
+use crate::auth::MAX_NAME_LEN;
+use crate::client::{ClientConfig, VirgeClient};
+use crate::codec::RawCodec;
+use crate::rpc;
+use crate::server::{ServerManager, VirgeServer};
+use crate::threads;
+
+/// 发现服务的 rpc 服务名
+pub const SERVICE: &str = "virga.Discovery";
+
+/// 一条服务登记
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ServiceRecord {
+    /// 服务名，不超过 255 字节
+    pub name: String,
+    /// 服务监听的端口
+    pub port: u32,
+    /// 服务自报的版本，不超过 255 字节，可为空
+    pub version: String,
+}
+
+impl ServiceRecord {
+    pub fn new(name: impl Into<String>, port: u32, version: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            port,
+            version: version.into(),
+        }
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) -> Result<()> {
+        put_str(buf, &self.name)?;
+        buf.extend_from_slice(&self.port.to_be_bytes());
+        put_str(buf, &self.version)
+    }
+
+    fn decode(msg: &mut &[u8]) -> Result<Self> {
+        let name = take_str(msg)?;
+        let port = take_u32(msg)?;
+        let version = take_str(msg)?;
+        Ok(Self {
+            name,
+            port,
+            version,
+        })
+    }
+}
+
+fn put_str(buf: &mut Vec<u8>, s: &str) -> Result<()> {
+    if s.len() > MAX_NAME_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} longer than {} bytes", s, MAX_NAME_LEN),
+        ));
+    }
+    buf.push(s.len() as u8);
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::InvalidData, "truncated discovery message")
+}
+
+fn take_str(msg: &mut &[u8]) -> Result<String> {
+    let (&len, rest) = msg.split_first().ok_or_else(truncated)?;
+    if rest.len() < len as usize {
+        return Err(truncated());
+    }
+    let (s, rest) = rest.split_at(len as usize);
+    *msg = rest;
+    String::from_utf8(s.to_vec())
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "discovery name is not utf-8"))
+}
+
+fn encode_list(records: &[ServiceRecord]) -> Result<Vec<u8>> {
+    let count = u16::try_from(records.len()).unwrap_or(u16::MAX);
+    let mut buf = count.to_be_bytes().to_vec();
+    for record in records.iter().take(count as usize) {
+        record.encode(&mut buf)?;
+    }
+    Ok(buf)
+}
+
+fn decode_list(mut msg: &[u8]) -> Result<Vec<ServiceRecord>> {
+    let (count, rest) = msg.split_first_chunk::<2>().ok_or_else(truncated)?;
+    msg = rest;
+    (0..u16::from_be_bytes(*count))
+        .map(|_| ServiceRecord::decode(&mut msg))
+        .collect()
+}
+
+fn take_u32(msg: &mut &[u8]) -> Result<u32> {
+    let (bytes, rest) = msg.split_first_chunk::<4>().ok_or_else(truncated)?;
+    *msg = rest;
+    Ok(u32::from_be_bytes(*bytes))
+}
+
+const ANNOUNCE_REGISTERED: u8 = 1;
+const ANNOUNCE_UNREGISTERED: u8 = 2;
+
+/// 登记表变化的通告
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Announcement {
+    /// 服务登记，或已登记的服务更新了端口、版本
+    Registered(ServiceRecord),
+    /// 服务注销
+    Unregistered(String),
+}
+
+impl Announcement {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        match self {
+            Announcement::Registered(record) => {
+                buf.push(ANNOUNCE_REGISTERED);
+                record.encode(&mut buf)?;
+            }
+            Announcement::Unregistered(name) => {
+                buf.push(ANNOUNCE_UNREGISTERED);
+                put_str(&mut buf, name)?;
+            }
+        }
+        Ok(buf)
+    }
+
+    fn decode(msg: &[u8]) -> Result<Self> {
+        let (&kind, mut rest) = msg.split_first().ok_or_else(truncated)?;
+        match kind {
+            ANNOUNCE_REGISTERED => Ok(Announcement::Registered(ServiceRecord::decode(&mut rest)?)),
+            ANNOUNCE_UNREGISTERED => Ok(Announcement::Unregistered(take_str(&mut rest)?)),
+            _ => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown discovery announcement {}", kind),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Table {
+    services: BTreeMap<String, ServiceRecord>,
+    subscribers: Vec<Sender<Announcement>>,
+}
+
+impl Table {
+    /// 通知所有订阅者，顺带移除已经退订的
+    fn announce(&mut self, announcement: Announcement) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(announcement.clone()).is_ok());
+    }
+}
+
+/// 登记表，可在线程间共享；克隆得到的是同一张表
+///
+/// 表按租户划分命名空间，[`Registry::for_tenant()`] 返回其中一个租户的视图：各租户的登记、
+/// 查询与通告互不可见。`new()` 得到的视图不属于任何租户
+#[derive(Clone, Debug, Default)]
+pub struct Registry {
+    tables: Arc<Mutex<BTreeMap<String, Table>>>,
+    /// 租户名，不属于租户时为空
+    namespace: String,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 同一张表中 `tenant` 的命名空间
+    pub fn for_tenant(&self, tenant: &str) -> Registry {
+        Self {
+            tables: self.tables.clone(),
+            namespace: tenant.to_string(),
+        }
+    }
+
+    /// 所属的租户
+    pub fn tenant(&self) -> Option<&str> {
+        Some(self.namespace.as_str()).filter(|t| !t.is_empty())
+    }
+
+    /// 登记服务，同名的旧登记被替换
+    pub fn register(&self, record: ServiceRecord) -> Result<()> {
+        if record.name.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "service name cannot be empty",
+            ));
+        }
+        // 提前检查长度，避免登记了之后无法编码的记录
+        record.encode(&mut Vec::new())?;
+        info!(
+            "Registered service {:?} on port {} (version {:?}){}",
+            record.name,
+            record.port,
+            record.version,
+            self.describe()
+        );
+        self.with_table(|table| {
+            table.services.insert(record.name.clone(), record.clone());
+            table.announce(Announcement::Registered(record));
+        });
+        Ok(())
+    }
+
+    /// 注销服务，返回原来的登记
+    pub fn unregister(&self, name: &str) -> Option<ServiceRecord> {
+        let removed = self.with_table(|table| {
+            let removed = table.services.remove(name);
+            if removed.is_some() {
+                table.announce(Announcement::Unregistered(name.to_string()));
+            }
+            removed
+        });
+        if removed.is_some() {
+            info!("Unregistered service {:?}{}", name, self.describe());
+        }
+        removed
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<ServiceRecord> {
+        self.with_table(|table| table.services.get(name).cloned())
+    }
+
+    /// 按服务名排序的全部登记
+    pub fn list(&self) -> Vec<ServiceRecord> {
+        self.with_table(|table| table.services.values().cloned().collect())
+    }
+
+    /// 订阅变化：返回当前的全部登记，以及之后每次变化的通告；接收端 drop 即退订
+    pub fn watch(&self) -> (Vec<ServiceRecord>, Receiver<Announcement>) {
+        let (tx, rx) = channel();
+        let records = self.with_table(|table| {
+            table.subscribers.push(tx);
+            table.services.values().cloned().collect()
+        });
+        (records, rx)
+    }
+
+    fn with_table<R>(&self, f: impl FnOnce(&mut Table) -> R) -> R {
+        let mut tables = self.tables.lock().unwrap_or_else(PoisonError::into_inner);
+        f(tables.entry(self.namespace.clone()).or_default())
+    }
+
+    fn describe(&self) -> String {
+        match self.tenant() {
+            Some(tenant) => format!(" for tenant {:?}", tenant),
+            None => String::new(),
+        }
+    }
+}
+
+/// 在 `ServerManager` 接受的连接上提供发现服务
+#[derive(Clone, Debug)]
+pub struct DiscoveryServer {
+    registry: Registry,
+    registrars: Vec<u32>,
+}
+
+impl DiscoveryServer {
+    pub fn new(registry: Registry) -> Self {
+        Self {
+            registry,
+            registrars: vec![
+                crate::VMADDR_CID_LOCAL as u32,
+                crate::VMADDR_CID_HOST as u32,
+            ],
+        }
+    }
+
+    /// 允许登记与注销的对端 CID，默认为本机回环（1）与宿主机（2）
+    pub fn with_registrars(mut self, cids: impl IntoIterator<Item = u32>) -> Self {
+        self.registrars = cids.into_iter().collect();
+        self
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// 处理一条连接上的请求，直到对端关闭连接；对端订阅后改为推送通告，
+    /// 直到推送失败。连接属于某个租户时只能看到该租户的命名空间
+    pub fn serve(&self, server: &mut VirgeServer) -> Result<()> {
+        let cid = server.conn().cid;
+        let registry = match server.tenant() {
+            Some(tenant) => self.registry.for_tenant(tenant),
+            None => self.registry.clone(),
+        };
+        loop {
+            let mut watch = None;
+            let served = rpc::serve_one(server, SERVICE, |method, payload| {
+                if method != "subscribe" {
+                    return self.dispatch(&registry, cid, method, payload);
+                }
+                let (records, announcements) = registry.watch();
+                watch = Some(announcements);
+                encode_list(&records)
+            });
+            match served {
+                Ok(()) => {}
+                Err(e) if rpc::is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            if let Some(announcements) = watch {
+                return Self::push(server, announcements);
+            }
+        }
+    }
+
+    fn push(server: &mut VirgeServer, announcements: Receiver<Announcement>) -> Result<()> {
+        server.flush()?;
+        debug!("Discovery subscriber {} attached", server.conn());
+        for announcement in announcements {
+            server.send(announcement.encode()?)?;
+            server.flush()?;
+        }
+        Ok(())
+    }
+
+    /// 持续接受连接，每个连接在单独的线程（`<前缀>-discovery-<连接 ID>`）中处理；
+    /// 管理器停止或监听出错时返回。被拒绝或握手失败的连接只记录日志
+    pub fn run(&self, manager: &mut ServerManager) -> Result<()> {
+        while manager.is_running() {
+            let incoming = match manager.accept_incoming() {
+                Ok(incoming) => incoming,
+                Err(_) if !manager.is_running() => break,
+                Err(e) if is_per_connection(&e) => {
+                    warn!("Discovery connection not accepted: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let this = self.clone();
+            let conn_id = incoming.conn().conn_id;
+            threads::spawn(format!("discovery-{}", conn_id), move || {
+                let mut server = match incoming.handshake() {
+                    Ok(server) => server,
+                    Err(e) => {
+                        warn!("Discovery connection {} not accepted: {}", conn_id, e);
+                        return;
+                    }
+                };
+                if let Err(e) = this.serve(&mut server) {
+                    warn!("Discovery connection {} failed: {}", conn_id, e);
+                }
+            })?;
+        }
+        Ok(())
+    }
+
+    fn dispatch(
+        &self,
+        registry: &Registry,
+        cid: u32,
+        method: &str,
+        mut payload: &[u8],
+    ) -> Result<Vec<u8>> {
+        match method {
+            "register" | "unregister" if !self.registrars.contains(&cid) => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("cid {} may not {} services", cid, method),
+            )),
+            "register" => {
+                registry.register(ServiceRecord::decode(&mut payload)?)?;
+                Ok(Vec::new())
+            }
+            "unregister" => {
+                registry.unregister(&take_str(&mut payload)?);
+                Ok(Vec::new())
+            }
+            "resolve" => {
+                let name = take_str(&mut payload)?;
+                let record = registry.resolve(&name).ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!("service {:?} is not registered", name),
+                    )
+                })?;
+                let mut buf = Vec::new();
+                record.encode(&mut buf)?;
+                Ok(buf)
+            }
+            "list" => encode_list(&registry.list()),
+            _ => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("unknown discovery method {:?}", method),
+            )),
+        }
+    }
+}
+
+/// 只影响单个连接的接受错误：策略拒绝、内存预算不足、连接初始化失败
+fn is_per_connection(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::PermissionDenied
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::OutOfMemory
+            | ErrorKind::InvalidData
+            | ErrorKind::TimedOut
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+fn call(client: &mut VirgeClient, method: &str, payload: Vec<u8>) -> Result<Vec<u8>> {
+    rpc::call(client, &RawCodec, SERVICE, method, &payload)
+}
+
+fn connect(addr: Addr) -> Result<VirgeClient> {
+    let mut client = VirgeClient::new(ClientConfig::default().with_server_addr(addr));
+    client.connect()?;
+    Ok(client)
+}
+
+/// 向 `addr` 上的发现服务登记
+pub fn register(addr: Addr, record: &ServiceRecord) -> Result<()> {
+    let mut payload = Vec::new();
+    record.encode(&mut payload)?;
+    call(&mut connect(addr)?, "register", payload).map(drop)
+}
+
+/// 从 `addr` 上的发现服务注销
+pub fn unregister(addr: Addr, name: &str) -> Result<()> {
+    let mut payload = Vec::new();
+    put_str(&mut payload, name)?;
+    call(&mut connect(addr)?, "unregister", payload).map(drop)
+}
+
+/// 在已连接发现服务的客户端上查询 `name`，未登记时返回 `NotFound`
+pub fn resolve(client: &mut VirgeClient, name: &str) -> Result<ServiceRecord> {
+    let mut payload = Vec::new();
+    put_str(&mut payload, name)?;
+    let response = call(client, "resolve", payload)?;
+    ServiceRecord::decode(&mut response.as_slice())
+}
+
+/// 在已连接发现服务的客户端上列出全部登记
+pub fn list(client: &mut VirgeClient) -> Result<Vec<ServiceRecord>> {
+    decode_list(&call(client, "list", Vec::new())?)
+}
+
+/// 订阅宿主机上登记表的变化，见[模块文档](self)
+pub struct Subscription {
+    client: VirgeClient,
+}
+
+impl Subscription {
+    /// 阻塞等待下一条通告；连接断开时返回错误，此时应重新订阅
+    pub fn recv(&mut self) -> Result<Announcement> {
+        Announcement::decode(&self.client.recv_loan()?)
+    }
+
+    /// 退订并断开连接
+    pub fn close(mut self) -> Result<()> {
+        self.client.disconnect()
+    }
+}
+
+/// 连接客户端配置中服务端 CID 上的发现服务并订阅变化，返回当前的全部登记
+pub fn subscribe(config: &ClientConfig) -> Result<(Vec<ServiceRecord>, Subscription)> {
+    let mut client = VirgeClient::new(config.discovery_config());
+    client.connect()?;
+    let records = decode_list(&call(&mut client, "subscribe", Vec::new())?)?;
+    Ok((records, Subscription { client }))
+}
+
+/// `VirgeClient::connect_service()` 的查询部分：连接客户端配置中服务端 CID 上的发现服务
+pub(crate) fn lookup(config: &ClientConfig, name: &str) -> Result<ServiceRecord> {
+    let mut client = VirgeClient::new(config.discovery_config());
+    client.connect()?;
+    let record = resolve(&mut client, name);
+    let _ = client.disconnect();
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(record: &ServiceRecord) -> Vec<u8> {
+        let mut buf = Vec::new();
+        record.encode(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let record = ServiceRecord::new("logs", 5100, "1.2");
+        let buf = encoded(&record);
+        assert_eq!(buf, b"\x04logs\x00\x00\x13\xec\x031.2");
+        assert_eq!(ServiceRecord::decode(&mut buf.as_slice()).unwrap(), record);
+        let err = ServiceRecord::decode(&mut &buf[..6]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let long = ServiceRecord::new("x".repeat(256), 1, "");
+        assert!(long.encode(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn dispatch_resolves_and_restricts_registration() {
+        let server = DiscoveryServer::new(Registry::new());
+        let registry = server.registry().clone();
+        let dispatch =
+            |cid, method, payload: &[u8]| server.dispatch(&registry, cid, method, payload);
+        let record = ServiceRecord::new("logs", 5100, "1.2");
+
+        let err = dispatch(3, "register", &encoded(&record)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        dispatch(1, "register", &encoded(&record)).unwrap();
+
+        let found = dispatch(3, "resolve", b"\x04logs").unwrap();
+        assert_eq!(
+            ServiceRecord::decode(&mut found.as_slice()).unwrap(),
+            record
+        );
+        let err = dispatch(3, "resolve", b"\x04nope").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        let listed = dispatch(3, "list", b"").unwrap();
+        assert_eq!(&listed[..2], [0, 1]);
+
+        dispatch(2, "unregister", b"\x04logs").unwrap();
+        assert_eq!(server.registry().resolve("logs"), None);
+        let err = dispatch(3, "lookup", b"").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn tenants_see_only_their_own_namespace() {
+        let registry = Registry::new();
+        let billing = registry.for_tenant("billing");
+        let search = registry.for_tenant("search");
+        assert_eq!(registry.tenant(), None);
+        assert_eq!(billing.tenant(), Some("billing"));
+
+        let (_, billing_news) = billing.watch();
+        let (_, search_news) = search.watch();
+        billing
+            .register(ServiceRecord::new("logs", 5100, ""))
+            .unwrap();
+        assert!(search.resolve("logs").is_none());
+        assert!(registry.list().is_empty());
+        assert!(search.unregister("logs").is_none());
+        assert_eq!(registry.for_tenant("billing").list().len(), 1);
+        assert_eq!(billing_news.try_iter().count(), 1);
+        assert_eq!(search_news.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn watchers_see_every_change_after_the_snapshot() {
+        let registry = Registry::new();
+        registry
+            .register(ServiceRecord::new("logs", 5100, "1.2"))
+            .unwrap();
+        let (snapshot, announcements) = registry.watch();
+        assert_eq!(snapshot, [ServiceRecord::new("logs", 5100, "1.2")]);
+
+        let metrics = ServiceRecord::new("metrics", 5200, "");
+        registry.register(metrics.clone()).unwrap();
+        registry.unregister("logs");
+        registry.unregister("logs");
+        let seen: Vec<Announcement> = announcements.try_iter().collect();
+        assert_eq!(
+            seen,
+            [
+                Announcement::Registered(metrics),
+                Announcement::Unregistered("logs".to_string()),
+            ]
+        );
+        for announcement in seen {
+            let decoded = Announcement::decode(&announcement.encode().unwrap()).unwrap();
+            assert_eq!(decoded, announcement);
+        }
+
+        drop(announcements);
+        registry.unregister("metrics");
+        assert!(registry.with_table(|table| table.subscribers.is_empty()));
+        assert!(Announcement::decode(&[9]).is_err());
+    }
+}
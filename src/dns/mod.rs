@@ -0,0 +1,662 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 经 vsock 的 DNS 解析：没有网络的精简客户机也能解析域名
+//!
+//! 客户机上的 [`DnsStub`] 在 UDP 地址（一般为 `127.0.0.53:53`，写入客户机的
+//! `/etc/resolv.conf`）上接收 DNS 查询，原样转给宿主机；宿主机上的 [`DnsServer`] 用系统解析器
+//! （`getaddrinfo()`，遵循宿主机的 `/etc/hosts` 与 nsswitch 配置）解析，构造回复送回客户机。
+//! 宿主机可以用 [`DnsServer::with_host`] 为客户机提供额外的名字，优先于系统解析器。
+//!
+//! ```ignore
+//! // 宿主机，每个连接一个线程，共享同一个服务端
+//! let dns = DnsServer::new().with_host("registry.internal", "10.0.0.5".parse()?);
+//! dns.serve(&mux.channel(DNS_CHANNEL)?)?;
+//!
+//! // 客户机
+//! let stub = DnsStub::bind("127.0.0.53:53")?;
+//! stub.serve(mux.channel(DNS_CHANNEL)?)?;
+//! ```
+//!
+//! 只回答 IN 类的 A 与 AAAA 查询，其他类型回复 NOTIMP。名字不存在时回复 NXDOMAIN，存在但没有
+//! 所查类型的地址时回复不带记录的 NOERROR，系统解析器出错时回复 SERVFAIL；回复不超过 512 字节，
+//! 地址过多时只带前面的部分。
+//!
+//! 连接经 [`mux`](crate::mux) 复用，使用通道 [`DNS_CHANNEL`]。客户机为每个查询分配一个标签，
+//! 宿主机的回复带同一个标签，客户机据此把回复送回发出查询的地址：
+//!
+//! ```text
+//! QUERY  | tag(4, 大端) | DNS 查询     客户机 → 宿主机
+//! ANSWER | tag(4, 大端) | DNS 回复     宿主机 → 客户机
+//! ```
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::agent::ShutdownHandle;
+use crate::mux::Channel;
+use crate::rpc;
+use crate::threads;
+
+/// 承载 DNS 查询与回复的通道号
+pub const DNS_CHANNEL: u32 = 8;
+
+/// 回复中记录的默认存活时间
+pub const DEFAULT_DNS_TTL: Duration = Duration::from_secs(30);
+/// 客户机等待回复的默认时长，超过后丢弃查询（客户端自行重试）
+pub const DEFAULT_DNS_TIMEOUT: Duration = Duration::from_secs(5);
+/// 宿主机同时进行的解析数上限，超过时回复 SERVFAIL
+const MAX_PENDING_LOOKUPS: usize = 64;
+/// 客户机等待回复的查询数上限，超过时丢弃新的查询
+const MAX_PENDING_QUERIES: usize = 1024;
+/// 等待查询时检查停止请求的间隔
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const QUERY: u8 = 0;
+const ANSWER: u8 = 1;
+
+/// 不带 EDNS 时 UDP 上 DNS 消息的长度上限
+const MAX_UDP_MESSAGE: usize = 512;
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const RCODE_FORMERR: u16 = 1;
+const RCODE_SERVFAIL: u16 = 2;
+const RCODE_NXDOMAIN: u16 = 3;
+const RCODE_NOTIMP: u16 = 4;
+
+/// 通道上的一条消息
+#[derive(Debug, PartialEq, Eq)]
+enum Message {
+    Query { tag: u32, packet: Vec<u8> },
+    Answer { tag: u32, packet: Vec<u8> },
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let (kind, tag, packet) = match self {
+            Message::Query { tag, packet } => (QUERY, tag, packet),
+            Message::Answer { tag, packet } => (ANSWER, tag, packet),
+        };
+        let mut buf = Vec::with_capacity(5 + packet.len());
+        buf.push(kind);
+        buf.extend_from_slice(&tag.to_be_bytes());
+        buf.extend_from_slice(packet);
+        buf
+    }
+
+    fn decode(message: &[u8]) -> Result<Self> {
+        if message.len() < 5 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "dns message is truncated",
+            ));
+        }
+        let tag = u32::from_be_bytes(message[1..5].try_into().unwrap());
+        let packet = message[5..].to_vec();
+        match message[0] {
+            QUERY => Ok(Message::Query { tag, packet }),
+            ANSWER => Ok(Message::Answer { tag, packet }),
+            kind => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("dns message type {} is unexpected", kind),
+            )),
+        }
+    }
+}
+
+/// 一个名字的解析结果
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Resolution {
+    /// 名字存在，可能没有任何地址
+    Addresses(Vec<IpAddr>),
+    NotFound,
+    Failed,
+}
+
+/// 用系统解析器解析 `name`
+fn system_lookup(name: &str) -> Resolution {
+    let Ok(host) = CString::new(name) else {
+        return Resolution::NotFound;
+    };
+    // SAFETY: addrinfo 是普通的 C 结构体，全零即未设置任何提示
+    let mut hints: libc::addrinfo = unsafe { std::mem::zeroed() };
+    hints.ai_family = libc::AF_UNSPEC;
+    // 每个地址只返回一次
+    hints.ai_socktype = libc::SOCK_STREAM;
+    let mut list = std::ptr::null_mut();
+    // SAFETY: host 是以 NUL 结尾的字符串，hints 与 list 在调用期间有效
+    let rc = unsafe { libc::getaddrinfo(host.as_ptr(), std::ptr::null(), &hints, &mut list) };
+    match rc {
+        0 => {}
+        libc::EAI_NONAME => return Resolution::NotFound,
+        libc::EAI_NODATA => return Resolution::Addresses(Vec::new()),
+        _ => return Resolution::Failed,
+    }
+    let mut addrs = Vec::new();
+    let mut entry = list;
+    while !entry.is_null() {
+        // SAFETY: entry 来自 getaddrinfo() 返回的链表，freeaddrinfo() 之前有效；ai_addr 的
+        // 实际类型由 ai_family 决定
+        unsafe {
+            let info = &*entry;
+            let addr = match info.ai_family {
+                libc::AF_INET => {
+                    let sin = &*(info.ai_addr as *const libc::sockaddr_in);
+                    Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                        sin.sin_addr.s_addr,
+                    ))))
+                }
+                libc::AF_INET6 => {
+                    let sin6 = &*(info.ai_addr as *const libc::sockaddr_in6);
+                    Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+                }
+                _ => None,
+            };
+            if let Some(addr) = addr.filter(|addr| !addrs.contains(addr)) {
+                addrs.push(addr);
+            }
+            entry = info.ai_next;
+        }
+    }
+    // SAFETY: list 由成功的 getaddrinfo() 返回，只释放一次
+    unsafe { libc::freeaddrinfo(list) };
+    Resolution::Addresses(addrs)
+}
+
+/// 解析出的查询
+#[derive(Debug)]
+struct Question<'a> {
+    id: u16,
+    /// 查询要求递归（RD 位）
+    recursion: bool,
+    /// 原样回显的问题部分
+    raw: &'a [u8],
+    name: String,
+    qtype: u16,
+    qclass: u16,
+}
+
+/// 解析查询；不是查询或短于消息头时返回 `None`（不回复），格式错误时返回回复码
+fn parse(packet: &[u8]) -> Option<std::result::Result<Question<'_>, (u16, u16)>> {
+    if packet.len() < HEADER_LEN {
+        return None;
+    }
+    let id = u16::from_be_bytes([packet[0], packet[1]]);
+    let flags = u16::from_be_bytes([packet[2], packet[3]]);
+    if flags & 0x8000 != 0 {
+        // 回复，不是查询
+        return None;
+    }
+    let fail = |rcode| Some(Err((id, flags & 0x0100 | rcode)));
+    if (flags >> 11) & 0xf != 0 {
+        return fail(RCODE_NOTIMP);
+    }
+    let qdcount = u16::from_be_bytes([packet[4], packet[5]]);
+    if qdcount != 1 {
+        return fail(RCODE_FORMERR);
+    }
+    let mut pos = HEADER_LEN;
+    let mut labels = Vec::new();
+    loop {
+        let Some(&len) = packet.get(pos) else {
+            return fail(RCODE_FORMERR);
+        };
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // 问题中不应有压缩指针
+        if len & 0xc0 != 0 || pos + len as usize > packet.len() {
+            return fail(RCODE_FORMERR);
+        }
+        labels.push(String::from_utf8_lossy(&packet[pos..pos + len as usize]).into_owned());
+        pos += len as usize;
+    }
+    let name = labels.join(".");
+    if pos + 4 > packet.len() || name.len() > 253 {
+        return fail(RCODE_FORMERR);
+    }
+    Some(Ok(Question {
+        id,
+        recursion: flags & 0x0100 != 0,
+        raw: &packet[HEADER_LEN..pos + 4],
+        name,
+        qtype: u16::from_be_bytes([packet[pos], packet[pos + 1]]),
+        qclass: u16::from_be_bytes([packet[pos + 2], packet[pos + 3]]),
+    }))
+}
+
+/// 不带问题与记录的回复
+fn header(id: u16, flags: u16, questions: u16, answers: u16) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(MAX_UDP_MESSAGE);
+    buf.extend_from_slice(&id.to_be_bytes());
+    // QR 与 RA 位，调用方给出 RD 位与回复码
+    buf.extend_from_slice(&(0x8080 | flags).to_be_bytes());
+    buf.extend_from_slice(&questions.to_be_bytes());
+    buf.extend_from_slice(&answers.to_be_bytes());
+    buf.extend_from_slice(&[0; 4]);
+    buf
+}
+
+/// 构造对 `packet` 的回复，`lookup` 解析名字；不该回复时返回 `None`
+fn respond(
+    packet: &[u8],
+    ttl: Duration,
+    lookup: impl FnOnce(&str) -> Resolution,
+) -> Option<Vec<u8>> {
+    let question = match parse(packet)? {
+        Ok(question) => question,
+        Err((id, flags)) => return Some(header(id, flags, 0, 0)),
+    };
+    let recursion = if question.recursion { 0x0100 } else { 0 };
+    let reply = |rcode: u16, answers: &[IpAddr]| {
+        let mut buf = header(question.id, recursion | rcode, 1, 0);
+        buf.extend_from_slice(question.raw);
+        let mut count = 0u16;
+        for addr in answers {
+            let (rtype, rdata) = match addr {
+                IpAddr::V4(v4) => (TYPE_A, v4.octets().to_vec()),
+                IpAddr::V6(v6) => (TYPE_AAAA, v6.octets().to_vec()),
+            };
+            if buf.len() + 12 + rdata.len() > MAX_UDP_MESSAGE {
+                break;
+            }
+            // 名字指向问题中的名字
+            buf.extend_from_slice(&0xc00cu16.to_be_bytes());
+            buf.extend_from_slice(&rtype.to_be_bytes());
+            buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+            buf.extend_from_slice(&(ttl.as_secs().min(u32::MAX as u64) as u32).to_be_bytes());
+            buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            buf.extend_from_slice(&rdata);
+            count += 1;
+        }
+        buf[6..8].copy_from_slice(&count.to_be_bytes());
+        buf
+    };
+    let wanted = match (question.qclass, question.qtype) {
+        (CLASS_IN, TYPE_A) => IpAddr::is_ipv4,
+        (CLASS_IN, TYPE_AAAA) => IpAddr::is_ipv6,
+        _ => return Some(reply(RCODE_NOTIMP, &[])),
+    };
+    Some(match lookup(&question.name) {
+        Resolution::Addresses(addrs) => {
+            let addrs: Vec<_> = addrs.into_iter().filter(wanted).collect();
+            reply(0, &addrs)
+        }
+        Resolution::NotFound => reply(RCODE_NXDOMAIN, &[]),
+        Resolution::Failed => reply(RCODE_SERVFAIL, &[]),
+    })
+}
+
+/// 宿主机一侧的解析服务，见[模块文档](self)
+///
+/// 克隆后共享配置与同时进行的解析数，每个客户机连接在各自的线程中调用 [`serve()`](Self::serve)
+#[derive(Clone, Debug)]
+pub struct DnsServer {
+    hosts: Arc<HashMap<String, Vec<IpAddr>>>,
+    ttl: Duration,
+    pending: Arc<AtomicUsize>,
+}
+
+impl Default for DnsServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsServer {
+    pub fn new() -> Self {
+        Self {
+            hosts: Arc::default(),
+            ttl: DEFAULT_DNS_TTL,
+            pending: Arc::default(),
+        }
+    }
+
+    /// 把名字 `name`（不区分大小写）解析为 `addr`，优先于系统解析器；同一名字可设置多个地址
+    pub fn with_host(mut self, name: &str, addr: IpAddr) -> Self {
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        Arc::make_mut(&mut self.hosts)
+            .entry(name)
+            .or_default()
+            .push(addr);
+        self
+    }
+
+    /// 回复中记录的存活时间（默认 [`DEFAULT_DNS_TTL`]），客户机上的缓存据此过期
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn lookup(&self, name: &str) -> Resolution {
+        match self.hosts.get(&name.to_ascii_lowercase()) {
+            Some(addrs) => Resolution::Addresses(addrs.clone()),
+            None => system_lookup(name),
+        }
+    }
+
+    /// 回答 `channel`（通道号一般为 [`DNS_CHANNEL`]）上的查询，每个查询在各自的线程中解析；
+    /// 连接关闭时返回 `Ok`
+    pub fn serve(&self, channel: &Channel) -> Result<()> {
+        loop {
+            let message = match channel.recv() {
+                Ok(message) => message,
+                Err(e) if rpc::is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let (tag, packet) = match Message::decode(&message)? {
+                Message::Query { tag, packet } => (tag, packet),
+                other => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("dns message {:?} is unexpected", other),
+                    ))
+                }
+            };
+            let sender = channel.sender();
+            if self.pending.fetch_add(1, Ordering::AcqRel) >= MAX_PENDING_LOOKUPS {
+                self.pending.fetch_sub(1, Ordering::AcqRel);
+                warn!("Too many DNS lookups in progress, failing query {}", tag);
+                let reply = respond(&packet, self.ttl, |_| Resolution::Failed);
+                if let Some(packet) = reply {
+                    sender.send(&Message::Answer { tag, packet }.encode())?;
+                }
+                continue;
+            }
+            let server = self.clone();
+            let spawned = threads::spawn("dns", move || {
+                let reply = respond(&packet, server.ttl, |name| server.lookup(name));
+                server.pending.fetch_sub(1, Ordering::AcqRel);
+                if let Some(packet) = reply {
+                    // 连接已关闭时由 serve() 返回
+                    let _ = sender.send(&Message::Answer { tag, packet }.encode());
+                }
+            });
+            if let Err(e) = spawned {
+                self.pending.fetch_sub(1, Ordering::AcqRel);
+                warn!("Failed to start DNS lookup thread: {}", e);
+            }
+        }
+    }
+}
+
+/// 等待回复的查询：标签 → (查询来源, 发出时刻)
+type Pending = Arc<Mutex<HashMap<u32, (SocketAddr, Instant)>>>;
+
+/// 客户机一侧的 DNS 存根，见[模块文档](self)
+pub struct DnsStub {
+    socket: UdpSocket,
+    timeout: Duration,
+    shutdown: ShutdownHandle,
+}
+
+impl DnsStub {
+    /// 在客户机的 UDP 地址 `addr` 上接收查询
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL))?;
+        Ok(Self {
+            socket,
+            timeout: DEFAULT_DNS_TIMEOUT,
+            shutdown: ShutdownHandle::default(),
+        })
+    }
+
+    /// 等待宿主机回复的时长，超过后丢弃该查询
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 实际监听的地址
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// 用于请求停止的句柄：`serve()` 不再接收查询并返回 `Ok`
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 把收到的查询经 `channel`（通道号一般为 [`DNS_CHANNEL`]）转给宿主机，回复送回查询来源，
+    /// 直到请求停止；连接关闭时返回错误
+    pub fn serve(self, channel: Channel) -> Result<()> {
+        info!("DNS stub listening on {}", self.socket.local_addr()?);
+        let pending = Pending::default();
+        let sender = channel.sender();
+        let replies = {
+            let (socket, pending) = (self.socket.try_clone()?, pending.clone());
+            threads::spawn("dns-replies", move || {
+                forward_replies(&channel, &socket, &pending)
+            })?
+        };
+        let mut buf = [0u8; MAX_UDP_MESSAGE];
+        let mut next_tag = 0u32;
+        while !self.shutdown.is_shutdown() {
+            if replies.is_finished() {
+                return replies
+                    .join()
+                    .unwrap_or_else(|_| Err(Error::other("dns reply thread panicked")));
+            }
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    continue
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let tag = next_tag;
+            next_tag = next_tag.wrapping_add(1);
+            {
+                let mut pending = pending.lock().unwrap_or_else(PoisonError::into_inner);
+                let now = Instant::now();
+                pending.retain(|_, (_, sent)| now.duration_since(*sent) < self.timeout);
+                if pending.len() >= MAX_PENDING_QUERIES {
+                    warn!(
+                        "Too many DNS queries awaiting the host, dropping one from {}",
+                        from
+                    );
+                    continue;
+                }
+                pending.insert(tag, (from, now));
+            }
+            let query = Message::Query {
+                tag,
+                packet: buf[..len].to_vec(),
+            };
+            sender.send(&query.encode())?;
+        }
+        info!("DNS stub stopped");
+        Ok(())
+    }
+}
+
+/// 把宿主机的回复送回查询来源，直到连接关闭
+fn forward_replies(channel: &Channel, socket: &UdpSocket, pending: &Pending) -> Result<()> {
+    loop {
+        let (tag, packet) = match Message::decode(&channel.recv()?)? {
+            Message::Answer { tag, packet } => (tag, packet),
+            other => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("dns message {:?} is unexpected", other),
+                ))
+            }
+        };
+        let from = pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&tag);
+        match from {
+            Some((from, _)) => {
+                if let Err(e) = socket.send_to(&packet, from) {
+                    debug!("DNS reply to {} failed: {}", from, e);
+                }
+            }
+            None => debug!("DNS reply {} arrived after its query expired", tag),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 构造查询 `name` 的 `qtype` 记录的 DNS 查询
+    fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut buf = id.to_be_bytes().to_vec();
+        buf.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+        buf.push(0);
+        buf.extend_from_slice(&qtype.to_be_bytes());
+        buf.extend_from_slice(&CLASS_IN.to_be_bytes());
+        buf
+    }
+
+    /// 回复的 (id, 回复码, 各记录的地址)
+    fn answers(packet: &[u8]) -> (u16, u16, Vec<IpAddr>) {
+        let id = u16::from_be_bytes([packet[0], packet[1]]);
+        let flags = u16::from_be_bytes([packet[2], packet[3]]);
+        assert_eq!(flags & 0x8000, 0x8000, "not a reply");
+        let count = u16::from_be_bytes([packet[6], packet[7]]);
+        // 跳过问题（格式错误的回复不带问题）
+        let mut pos = HEADER_LEN;
+        if packet[5] == 1 {
+            while packet[pos] != 0 {
+                pos += 1 + packet[pos] as usize;
+            }
+            pos += 5;
+        }
+        let mut addrs = Vec::new();
+        for _ in 0..count {
+            let len = u16::from_be_bytes([packet[pos + 10], packet[pos + 11]]) as usize;
+            let rdata = &packet[pos + 12..pos + 12 + len];
+            addrs.push(match len {
+                4 => IpAddr::from(<[u8; 4]>::try_from(rdata).unwrap()),
+                _ => IpAddr::from(<[u8; 16]>::try_from(rdata).unwrap()),
+            });
+            pos += 12 + len;
+        }
+        assert_eq!(pos, packet.len());
+        (id, flags & 0xf, addrs)
+    }
+
+    #[test]
+    fn queries_are_answered_from_the_resolution() {
+        let v4: IpAddr = "10.0.0.5".parse().unwrap();
+        let v6: IpAddr = "fd00::5".parse().unwrap();
+        let both = |_: &str| Resolution::Addresses(vec![v4, v6]);
+        let ttl = DEFAULT_DNS_TTL;
+
+        let reply = respond(&query(7, "registry.internal", TYPE_A), ttl, both).unwrap();
+        assert_eq!(answers(&reply), (7, 0, vec![v4]));
+        let reply = respond(&query(8, "registry.internal", TYPE_AAAA), ttl, both).unwrap();
+        assert_eq!(answers(&reply), (8, 0, vec![v6]));
+
+        // 只有 IPv4 地址的名字：AAAA 查询为不带记录的 NOERROR，而不是 NXDOMAIN
+        let only_v4 = |_: &str| Resolution::Addresses(vec![v4]);
+        let reply = respond(&query(9, "a.internal", TYPE_AAAA), ttl, only_v4).unwrap();
+        assert_eq!(answers(&reply), (9, 0, vec![]));
+
+        let reply = respond(&query(10, "x", TYPE_A), ttl, |_| Resolution::NotFound).unwrap();
+        assert_eq!(answers(&reply), (10, RCODE_NXDOMAIN, vec![]));
+        let reply = respond(&query(11, "x", TYPE_A), ttl, |_| Resolution::Failed).unwrap();
+        assert_eq!(answers(&reply), (11, RCODE_SERVFAIL, vec![]));
+        // MX
+        let reply = respond(&query(12, "x", 15), ttl, |_| unreachable!()).unwrap();
+        assert_eq!(answers(&reply), (12, RCODE_NOTIMP, vec![]));
+
+        // 地址过多时截到 512 字节以内
+        let many = |_: &str| {
+            Resolution::Addresses((0..100).map(|i| IpAddr::from([10, 0, 0, i])).collect())
+        };
+        let reply = respond(&query(13, "many", TYPE_A), ttl, many).unwrap();
+        assert!(reply.len() <= MAX_UDP_MESSAGE);
+        assert!(answers(&reply).2.len() > 20);
+
+        // 格式错误的查询回复 FORMERR，回复与过短的消息不回复
+        let mut truncated = query(14, "x", TYPE_A);
+        truncated.truncate(truncated.len() - 2);
+        let reply = respond(&truncated, ttl, both).unwrap();
+        assert_eq!(answers(&reply), (14, RCODE_FORMERR, vec![]));
+        assert_eq!(respond(&reply, ttl, both), None);
+        assert_eq!(respond(&[0; 4], ttl, both), None);
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        for message in [
+            Message::Query {
+                tag: 3,
+                packet: query(1, "example.com", TYPE_A),
+            },
+            Message::Answer {
+                tag: u32::MAX,
+                packet: vec![1, 2, 3],
+            },
+        ] {
+            assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+        }
+        assert!(Message::decode(&[QUERY, 0]).is_err());
+    }
+
+    #[cfg(feature = "use-xtransport")]
+    #[test]
+    fn guest_queries_reach_the_host() {
+        use crate::mux::testing::pair;
+        use std::thread;
+
+        let (host, guest) = pair();
+        let server = DnsServer::new()
+            .with_host("Registry.Internal.", "10.0.0.5".parse().unwrap())
+            .with_host("registry.internal", "fd00::5".parse().unwrap());
+        let host_channel = host.channel(DNS_CHANNEL).unwrap();
+        thread::spawn(move || server.serve(&host_channel));
+        let stub = DnsStub::bind("127.0.0.1:0").unwrap();
+        let addr = stub.local_addr().unwrap();
+        let shutdown = stub.shutdown_handle();
+        let channel = guest.channel(DNS_CHANNEL).unwrap();
+        let serving = thread::spawn(move || stub.serve(channel));
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0u8; MAX_UDP_MESSAGE];
+        for (id, qtype, expected) in [(1, TYPE_A, "10.0.0.5"), (2, TYPE_AAAA, "fd00::5")] {
+            client
+                .send_to(&query(id, "registry.INTERNAL", qtype), addr)
+                .unwrap();
+            let (len, _) = client.recv_from(&mut buf).unwrap();
+            assert_eq!(
+                answers(&buf[..len]),
+                (id, 0, vec![expected.parse().unwrap()])
+            );
+        }
+
+        shutdown.shutdown();
+        serving.join().unwrap().unwrap();
+        // 连接关闭后 serve() 返回错误
+        let stub = DnsStub::bind("127.0.0.1:0").unwrap();
+        let channel = guest.channel(DNS_CHANNEL + 100).unwrap();
+        guest.close();
+        drop(host);
+        assert!(stub.serve(channel).is_err());
+    }
+}
@@ -9,64 +9,453 @@
 //! # 错误分类
 //! - `ConnectionError`：vsock 连接相关错误（连接失败、超时等）
 //! - `TransportError`：传输协议相关错误（编码、解码、发送、接收失败）
-//! - `InvalidConfig`：配置参数非法
-//! - `Unknown`：未知错误
+//! - `ConfigError`：配置参数非法
+//! - `Rejected`：服务端拒绝了连接，并说明了原因
+//! - `Closed`：连接已结束，附带结束的原因（[`ShutdownReason`](crate::ShutdownReason)）
+//! - `Operation`：一次调用失败，附带两端日志中都记录的操作 ID（[`OpId`]）
+//! - `IoError`：未经包装的 IO 错误
+//! - `Other`：其他错误
+//!
+//! - `Context`：附带连接 ID、对端地址与失败操作的包装错误
+//!
+//! 连接与传输错误保留底层错误及其 `io::ErrorKind`，调用方可通过
+//! [`VirgeError::kind`] 或 [`VirgeError::raw_os_error`] 区分具体原因。
 
 use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+static NEXT_CONN_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_OP_SEQ: AtomicU32 = AtomicU32::new(1);
+
+/// 连接的标识信息，用于为错误附加上下文
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnContext {
+    /// 进程内唯一的连接 ID，从 1 开始递增；0 表示尚未建立连接
+    pub conn_id: u64,
+    /// 对端 CID
+    pub cid: u32,
+    /// 对端端口
+    pub port: u32,
+}
+
+impl ConnContext {
+    /// 为新连接分配 ID
+    pub fn new(cid: u32, port: u32) -> Self {
+        Self {
+            conn_id: NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed),
+            cid,
+            port,
+        }
+    }
+
+    /// 对端地址
+    pub fn addr(&self) -> crate::Addr {
+        crate::Addr::new(self.cid, self.port)
+    }
+}
+
+impl fmt::Display for ConnContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "conn {} (cid={}, port={})",
+            self.conn_id, self.cid, self.port
+        )
+    }
+}
+
+/// 一次调用的操作 ID，随请求发给对端，两端的日志、事件与错误中都带有它，
+/// 据此可在客户机与宿主机的日志中找到同一次调用
+///
+/// 高 32 位为进程启动时随机选取的前缀，低 32 位为进程内递增的序号，不同进程、
+/// 不同客户机产生的 ID 几乎不会相同。显示为 16 位十六进制数，也可由它解析
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OpId(u64);
+
+impl OpId {
+    /// 分配一个新的操作 ID
+    pub fn new() -> Self {
+        static PREFIX: OnceLock<u64> = OnceLock::new();
+        let prefix = *PREFIX.get_or_init(|| crate::sources::entropy().next_u64() >> 32);
+        OpId(prefix << 32 | NEXT_OP_SEQ.fetch_add(1, Ordering::Relaxed) as u64)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for OpId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<u64> for OpId {
+    fn from(id: u64) -> Self {
+        OpId(id)
+    }
+}
+
+impl fmt::Display for OpId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for OpId {
+    type Err = VirgeError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        u64::from_str_radix(s, 16)
+            .map(OpId)
+            .map_err(|_| VirgeError::ConfigError(format!("invalid operation id {:?}", s)))
+    }
+}
 
 /// 库的统一错误类型
 #[derive(Debug)]
 pub enum VirgeError {
-    /// 连接层错误
-    ConnectionError(String),
+    /// 连接层错误，携带对端地址与底层 IO 错误（若有）
+    ConnectionError {
+        message: String,
+        cid: Option<u32>,
+        port: Option<u32>,
+        source: Option<io::Error>,
+    },
 
-    /// 传输层错误
-    TransportError(String),
+    /// 传输层错误，`kind` 可用于区分连接重置、超时等情况
+    TransportError {
+        message: String,
+        kind: io::ErrorKind,
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
 
     /// 配置错误
     ConfigError(String),
 
+    /// 服务端拒绝了连接（CID 不在允许列表、资源配额用尽、正在排空等），附带其给出的原因
+    Rejected(String),
+
+    /// 连接已结束，`reason` 为结束的原因，`source` 为底层的接收错误
+    Closed {
+        reason: crate::ShutdownReason,
+        source: Box<VirgeError>,
+    },
+
+    /// 一次调用失败，`op` 为调用的操作 ID，`source` 为实际失败原因
+    Operation { op: OpId, source: Box<VirgeError> },
+
     /// IO 错误
-    IoError(std::io::Error),
+    IoError(io::Error),
 
     /// 其他错误
     Other(String),
+
+    /// 带连接上下文的错误，`source` 为实际失败原因
+    Context {
+        conn: ConnContext,
+        operation: &'static str,
+        source: Box<VirgeError>,
+    },
+}
+
+impl VirgeError {
+    /// 构造不带底层错误的连接错误
+    pub fn connection(message: impl Into<String>) -> Self {
+        VirgeError::ConnectionError {
+            message: message.into(),
+            cid: None,
+            port: None,
+            source: None,
+        }
+    }
+
+    /// 构造由 IO 错误引起的连接错误
+    pub fn connection_io(message: impl Into<String>, source: io::Error) -> Self {
+        VirgeError::ConnectionError {
+            message: message.into(),
+            cid: None,
+            port: None,
+            source: Some(source),
+        }
+    }
+
+    /// 为连接错误附加对端地址，其他变体原样返回
+    pub fn with_addr(mut self, cid: u32, port: u32) -> Self {
+        if let VirgeError::ConnectionError {
+            cid: c, port: p, ..
+        } = &mut self
+        {
+            *c = Some(cid);
+            *p = Some(port);
+        }
+        self
+    }
+
+    /// 构造指定类别的传输错误
+    pub fn transport(kind: io::ErrorKind, message: impl Into<String>) -> Self {
+        VirgeError::TransportError {
+            message: message.into(),
+            kind,
+            source: None,
+        }
+    }
+
+    /// 构造由 IO 错误引起的传输错误，类别沿用 IO 错误的类别
+    pub fn transport_io(message: impl Into<String>, source: io::Error) -> Self {
+        VirgeError::TransportError {
+            message: message.into(),
+            kind: source.kind(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// 构造由 XTransport 协议错误引起的传输错误；对端拒绝连接时为 `Rejected`
+    #[cfg(feature = "use-xtransport")]
+    pub(crate) fn xtransport(
+        message: impl Into<String>,
+        source: crate::transport::xtransport::Error,
+    ) -> Self {
+        if let Some(reason) = source.rejection() {
+            return VirgeError::Rejected(reason);
+        }
+        VirgeError::TransportError {
+            message: message.into(),
+            kind: source.io_kind(),
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// 构造 yamux 连接层错误，底层错误按类别转换为 `io::Error` 保存
+    #[cfg(feature = "use-yamux")]
+    pub(crate) fn yamux_connection(
+        message: impl Into<String>,
+        err: yamux::ConnectionError,
+    ) -> Self {
+        let source = match err {
+            yamux::ConnectionError::Io(e) => e,
+            yamux::ConnectionError::Closed => io::Error::new(io::ErrorKind::ConnectionAborted, err),
+            yamux::ConnectionError::Decode(_) | yamux::ConnectionError::InvalidWindowUpdate => {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            }
+            err => io::Error::other(err),
+        };
+        VirgeError::connection_io(message, source)
+    }
+
+    /// 构造 yamux 流层错误。对端重置或关闭子流时，yamux 读返回 EOF、写返回
+    /// `WriteZero`，统一归为 `ConnectionReset`，原始错误保留在错误链中
+    #[cfg(feature = "use-yamux")]
+    pub(crate) fn yamux_stream(message: impl Into<String>, source: io::Error) -> Self {
+        let kind = match source.kind() {
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::WriteZero => {
+                io::ErrorKind::ConnectionReset
+            }
+            kind => kind,
+        };
+        VirgeError::TransportError {
+            message: message.into(),
+            kind,
+            source: Some(Box::new(source)),
+        }
+    }
+
+    /// 接收出错后附上连接结束的原因，见 [`ShutdownReason::infer()`](crate::ShutdownReason)；
+    /// 错误不表示连接已结束时原样返回
+    #[cfg(feature = "sync")]
+    pub(crate) fn ended(self, close: Option<u64>, goaway: Option<crate::GoAwayReason>) -> Self {
+        match crate::ShutdownReason::infer(self.kind(), close, goaway) {
+            Some(reason) if self.shutdown_reason().is_none() => VirgeError::Closed {
+                reason,
+                source: Box::new(self),
+            },
+            _ => self,
+        }
+    }
+
+    /// 附加连接上下文；已带上下文的错误保持最内层（最接近失败点）的上下文
+    pub fn with_context(self, conn: &ConnContext, operation: &'static str) -> Self {
+        match self {
+            VirgeError::Context { .. } => self,
+            err => VirgeError::Context {
+                conn: *conn,
+                operation,
+                source: Box::new(err),
+            },
+        }
+    }
+
+    /// 附上失败调用的操作 ID；已带操作 ID 的错误保持原样
+    pub fn with_op(self, op: OpId) -> Self {
+        match self.op_id() {
+            Some(_) => self,
+            None => VirgeError::Operation {
+                op,
+                source: Box::new(self),
+            },
+        }
+    }
+
+    /// 失败调用的操作 ID，沿包装查找
+    pub fn op_id(&self) -> Option<OpId> {
+        match self {
+            VirgeError::Operation { op, .. } => Some(*op),
+            VirgeError::Context { source, .. } | VirgeError::Closed { source, .. } => {
+                source.op_id()
+            }
+            _ => None,
+        }
+    }
+
+    /// 错误发生时的连接信息（若有）
+    pub fn conn(&self) -> Option<&ConnContext> {
+        match self {
+            VirgeError::Context { conn, .. } => Some(conn),
+            _ => None,
+        }
+    }
+
+    /// 失败的操作名称（如 `"send"`、`"recv"`），仅带上下文的错误有
+    pub fn operation(&self) -> Option<&'static str> {
+        match self {
+            VirgeError::Context { operation, .. } => Some(operation),
+            _ => None,
+        }
+    }
+
+    /// 错误对应的 `io::ErrorKind`，可据此区分 `ConnectionReset`、`TimedOut` 等
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            VirgeError::ConnectionError { source, .. } => source
+                .as_ref()
+                .map_or(io::ErrorKind::ConnectionRefused, |e| e.kind()),
+            VirgeError::TransportError { kind, .. } => *kind,
+            VirgeError::ConfigError(_) => io::ErrorKind::InvalidInput,
+            VirgeError::Rejected(_) => io::ErrorKind::ConnectionRefused,
+            VirgeError::Closed { source, .. } => source.kind(),
+            VirgeError::Operation { source, .. } => source.kind(),
+            VirgeError::IoError(e) => e.kind(),
+            VirgeError::Other(_) => io::ErrorKind::Other,
+            VirgeError::Context { source, .. } => source.kind(),
+        }
+    }
+
+    /// 服务端拒绝连接时给出的原因，沿上下文包装查找
+    pub fn rejection(&self) -> Option<&str> {
+        match self {
+            VirgeError::Rejected(reason) => Some(reason),
+            VirgeError::Context { source, .. } | VirgeError::Operation { source, .. } => {
+                source.rejection()
+            }
+            _ => None,
+        }
+    }
+
+    /// 连接结束的原因，沿上下文包装查找；错误不是因连接结束时为 `None`
+    pub fn shutdown_reason(&self) -> Option<crate::ShutdownReason> {
+        match self {
+            VirgeError::Closed { reason, .. } => Some(*reason),
+            VirgeError::Context { source, .. } | VirgeError::Operation { source, .. } => {
+                source.shutdown_reason()
+            }
+            _ => None,
+        }
+    }
+
+    /// 底层操作系统错误码（如 `ECONNRESET`），沿错误链查找
+    pub fn raw_os_error(&self) -> Option<i32> {
+        let mut current: Option<&(dyn std::error::Error + 'static)> = Some(self);
+        while let Some(err) = current {
+            if let Some(code) = err
+                .downcast_ref::<io::Error>()
+                .and_then(|e| e.raw_os_error())
+            {
+                return Some(code);
+            }
+            current = err.source();
+        }
+        None
+    }
 }
 
 impl fmt::Display for VirgeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            VirgeError::ConnectionError(msg) => write!(f, "Connection error: {}", msg),
-            VirgeError::TransportError(msg) => write!(f, "Transport error: {}", msg),
+            VirgeError::ConnectionError {
+                message,
+                cid,
+                port,
+                source,
+            } => {
+                write!(f, "Connection error: {}", message)?;
+                if let (Some(cid), Some(port)) = (cid, port) {
+                    write!(f, " (cid={}, port={})", cid, port)?;
+                }
+                if let Some(source) = source {
+                    write!(f, ": {}", source)?;
+                }
+                Ok(())
+            }
+            VirgeError::TransportError {
+                message, source, ..
+            } => {
+                write!(f, "Transport error: {}", message)?;
+                if let Some(source) = source {
+                    write!(f, ": {}", source)?;
+                }
+                Ok(())
+            }
             VirgeError::ConfigError(msg) => write!(f, "Config error: {}", msg),
+            VirgeError::Rejected(reason) => write!(f, "Rejected by server: {}", reason),
+            VirgeError::Closed { reason, source } => {
+                write!(f, "Connection closed ({}): {}", reason, source)
+            }
+            VirgeError::Operation { op, source } => write!(f, "Operation {}: {}", op, source),
             VirgeError::IoError(e) => write!(f, "IO error: {}", e),
             VirgeError::Other(msg) => write!(f, "Error: {}", msg),
+            VirgeError::Context {
+                conn,
+                operation,
+                source,
+            } => write!(f, "{} failed on {}: {}", operation, conn, source),
         }
     }
 }
 
-impl std::error::Error for VirgeError {}
+impl std::error::Error for VirgeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VirgeError::ConnectionError { source, .. } => source
+                .as_ref()
+                .map(|e| e as &(dyn std::error::Error + 'static)),
+            VirgeError::TransportError { source, .. } => source
+                .as_deref()
+                .map(|e| e as &(dyn std::error::Error + 'static)),
+            VirgeError::Closed { source, .. } => Some(source.as_ref()),
+            VirgeError::Operation { source, .. } => Some(source.as_ref()),
+            VirgeError::IoError(e) => Some(e),
+            VirgeError::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
-impl From<std::io::Error> for VirgeError {
-    fn from(err: std::io::Error) -> Self {
+impl From<io::Error> for VirgeError {
+    fn from(err: io::Error) -> Self {
         VirgeError::IoError(err)
     }
 }
 
-impl From<VirgeError> for std::io::Error {
+impl From<VirgeError> for io::Error {
     fn from(err: VirgeError) -> Self {
         match err {
             VirgeError::IoError(e) => e,
-            VirgeError::ConnectionError(msg) => {
-                std::io::Error::new(std::io::ErrorKind::ConnectionRefused, msg)
-            }
-            VirgeError::TransportError(msg) => {
-                std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
-            }
-            VirgeError::ConfigError(msg) => {
-                std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
-            }
-            VirgeError::Other(msg) => std::io::Error::new(std::io::ErrorKind::Other, msg),
+            VirgeError::Other(msg) => io::Error::other(msg),
+            err => io::Error::new(err.kind(), err),
         }
     }
 }
@@ -74,26 +463,94 @@ impl From<VirgeError> for std::io::Error {
 #[cfg(feature = "use-xtransport")]
 impl From<crate::transport::xtransport::Error> for VirgeError {
     fn from(err: crate::transport::xtransport::Error) -> Self {
-        VirgeError::Other(format!("XTransport error: {}", err))
+        VirgeError::xtransport("XTransport error", err)
     }
 }
 
+#[cfg(feature = "use-yamux")]
+impl From<yamux::ConnectionError> for VirgeError {
+    fn from(err: yamux::ConnectionError) -> Self {
+        VirgeError::yamux_connection("Yamux connection error", err)
+    }
+}
+
+/// 从 `io::Error` 中取出服务端拒绝连接的原因。客户端 API 返回 `io::Error` 时用它代替
+/// 手动 downcast
+pub fn rejection(err: &io::Error) -> Option<&str> {
+    err.get_ref()?.downcast_ref::<VirgeError>()?.rejection()
+}
+
+/// 从 `io::Error` 中取出连接结束的原因，错误不是因连接结束时为 `None`。客户端 API 返回
+/// `io::Error` 时用它代替手动 downcast
+pub fn shutdown_reason(err: &io::Error) -> Option<crate::ShutdownReason> {
+    err.get_ref()?
+        .downcast_ref::<VirgeError>()?
+        .shutdown_reason()
+}
+
+/// 从 `io::Error` 中取出失败调用的操作 ID。客户端 API 返回 `io::Error` 时用它代替
+/// 手动 downcast
+pub fn op_id(err: &io::Error) -> Option<OpId> {
+    err.get_ref()?.downcast_ref::<VirgeError>()?.op_id()
+}
+
+/// 为 `io::Error` 附上失败调用的操作 ID，其中的 `VirgeError` 原样保留，
+/// [`rejection()`] 等仍能取出
+#[cfg(feature = "sync")]
+pub(crate) fn with_op(err: io::Error, op: OpId) -> io::Error {
+    let err = match err.get_ref().map(|e| e.is::<VirgeError>()) {
+        Some(true) => *err
+            .into_inner()
+            .and_then(|e| e.downcast::<VirgeError>().ok())
+            .expect("checked above"),
+        _ => VirgeError::IoError(err),
+    };
+    err.with_op(op).into()
+}
+
 /// 操作结果类型别名
 pub type Result<T> = std::result::Result<T, VirgeError>;
 
+/// 为 `Result` 附加连接上下文
+///
+/// ```ignore
+/// transport.send(data).ctx(&conn, "send")?;
+/// ```
+pub trait ResultExt<T> {
+    fn ctx(self, conn: &ConnContext, operation: &'static str) -> Result<T>;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn ctx(self, conn: &ConnContext, operation: &'static str) -> Result<T> {
+        self.map_err(|e| e.with_context(conn, operation))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn display_connection_error() {
-        let err = VirgeError::ConnectionError("timeout".to_string());
+        let err = VirgeError::connection("timeout");
         assert_eq!(format!("{}", err), "Connection error: timeout");
     }
 
+    #[test]
+    fn display_connection_error_with_addr_and_source() {
+        let io_err = io::Error::new(io::ErrorKind::ConnectionReset, "reset by peer");
+        let err = VirgeError::connection_io("Failed to connect vsock", io_err).with_addr(3, 1234);
+        assert_eq!(
+            format!("{}", err),
+            "Connection error: Failed to connect vsock (cid=3, port=1234): reset by peer"
+        );
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
     #[test]
     fn display_transport_error() {
-        let err = VirgeError::TransportError("decode failed".to_string());
+        let err = VirgeError::transport(io::ErrorKind::InvalidData, "decode failed");
         assert_eq!(format!("{}", err), "Transport error: decode failed");
     }
 
@@ -103,6 +560,65 @@ mod tests {
         assert_eq!(format!("{}", err), "Config error: invalid port");
     }
 
+    #[test]
+    fn rejection_survives_context_and_io_conversion() {
+        let conn = ConnContext::new(3, 1234);
+        let err =
+            VirgeError::Rejected("cid 3 is not allowed".to_string()).with_context(&conn, "recv");
+        assert_eq!(err.rejection(), Some("cid 3 is not allowed"));
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        let io_err: io::Error = err.into();
+        assert_eq!(rejection(&io_err), Some("cid 3 is not allowed"));
+        assert_eq!(rejection(&io::Error::other("reset")), None);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn shutdown_reason_survives_context_and_io_conversion() {
+        use crate::{GoAwayReason, ShutdownReason};
+
+        let conn = ConnContext::new(3, 1234);
+        let eof = || VirgeError::transport(io::ErrorKind::UnexpectedEof, "eof");
+        let err = eof()
+            .ended(Some(ShutdownReason::IdleTimeout.code()), None)
+            .with_context(&conn, "recv");
+        assert_eq!(err.shutdown_reason(), Some(ShutdownReason::IdleTimeout));
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(err.to_string().contains("Connection closed (idle timeout)"));
+        let io_err: io::Error = err.into();
+        assert_eq!(shutdown_reason(&io_err), Some(ShutdownReason::IdleTimeout));
+
+        let err = eof().ended(None, Some(GoAwayReason::Drain));
+        assert_eq!(err.shutdown_reason(), Some(ShutdownReason::ServerDraining));
+        let timeout = VirgeError::transport(io::ErrorKind::TimedOut, "idle");
+        assert_eq!(timeout.ended(None, None).shutdown_reason(), None);
+        assert_eq!(shutdown_reason(&io::Error::other("reset")), None);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn op_ids_are_unique_and_survive_io_conversion() {
+        let (a, b) = (OpId::new(), OpId::new());
+        assert_ne!(a, b);
+        assert_eq!(a.as_u64() >> 32, b.as_u64() >> 32);
+        assert_eq!(a.to_string().len(), 16);
+        assert_eq!(a.to_string().parse::<OpId>().unwrap(), a);
+        assert!("not hex".parse::<OpId>().is_err());
+
+        let op = OpId::from(0x1234);
+        let rejected: io::Error = VirgeError::Rejected("quota".to_string()).into();
+        let err = with_op(rejected, op);
+        assert_eq!(op_id(&err), Some(op));
+        assert_eq!(rejection(&err), Some("quota"));
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(err.to_string().starts_with("Operation 0000000000001234: "));
+
+        let err = with_op(io::Error::new(io::ErrorKind::NotFound, "no such key"), op);
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(op_id(&with_op(err, OpId::new())), Some(op));
+        assert_eq!(op_id(&io::Error::other("plain")), None);
+    }
+
     #[test]
     fn display_io_error() {
         let io_err = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe broken");
@@ -138,18 +654,30 @@ mod tests {
 
     #[test]
     fn into_io_error_connection_error() {
-        let err = VirgeError::ConnectionError("refused".to_string());
+        let err = VirgeError::connection("refused");
         let io_err: std::io::Error = err.into();
         assert_eq!(io_err.kind(), std::io::ErrorKind::ConnectionRefused);
     }
 
     #[test]
     fn into_io_error_transport_error() {
-        let err = VirgeError::TransportError("bad data".to_string());
+        let err = VirgeError::transport(io::ErrorKind::InvalidData, "bad data");
         let io_err: std::io::Error = err.into();
         assert_eq!(io_err.kind(), std::io::ErrorKind::InvalidData);
     }
 
+    #[test]
+    fn transport_io_keeps_kind_and_os_error() {
+        let io_err = io::Error::from_raw_os_error(104);
+        let kind = io_err.kind();
+        let err = VirgeError::transport_io("send failed", io_err);
+        assert_eq!(err.kind(), kind);
+        assert_eq!(err.raw_os_error(), Some(104));
+
+        let io_err: std::io::Error = err.into();
+        assert_eq!(io_err.kind(), kind);
+    }
+
     #[test]
     fn into_io_error_config_error() {
         let err = VirgeError::ConfigError("invalid".to_string());
@@ -166,7 +694,7 @@ mod tests {
 
     #[test]
     fn error_debug_format() {
-        let err = VirgeError::ConnectionError("test".to_string());
+        let err = VirgeError::connection("test");
         let debug = format!("{:?}", err);
         assert!(debug.contains("ConnectionError"));
         assert!(debug.contains("test"));
@@ -185,11 +713,84 @@ mod tests {
             crate::transport::xtransport::error::ErrorKind::CrcMismatch,
         );
         let virge_err: VirgeError = xt_err.into();
-        match virge_err {
-            VirgeError::Other(msg) => {
-                assert!(msg.contains("XTransport error"));
+        match &virge_err {
+            VirgeError::TransportError { message, kind, .. } => {
+                assert!(message.contains("XTransport error"));
+                assert_eq!(*kind, io::ErrorKind::Other);
             }
-            _ => panic!("Expected Other variant"),
+            _ => panic!("Expected TransportError variant"),
         }
+        assert!(format!("{}", virge_err).contains("CRC checksum mismatch"));
+    }
+
+    #[cfg(feature = "use-yamux")]
+    #[test]
+    fn from_yamux_connection_error() {
+        let err: VirgeError = yamux::ConnectionError::Closed.into();
+        assert!(matches!(err, VirgeError::ConnectionError { .. }));
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert!(format!("{}", err).contains("connection is closed"));
+
+        let io_err = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        let err: VirgeError = yamux::ConnectionError::Io(io_err).into();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+        let err: VirgeError = yamux::ConnectionError::TooManyStreams.into();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[cfg(feature = "use-yamux")]
+    #[test]
+    fn yamux_stream_reset_maps_to_connection_reset() {
+        let eof = io::Error::from(io::ErrorKind::UnexpectedEof);
+        let err = VirgeError::yamux_stream("yamux recv error", eof);
+        assert!(matches!(err, VirgeError::TransportError { .. }));
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+        let timed_out = io::Error::from(io::ErrorKind::TimedOut);
+        let err = VirgeError::yamux_stream("yamux send error", timed_out);
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn conn_context_ids_increase() {
+        let a = ConnContext::new(3, 1234);
+        let b = ConnContext::new(3, 1234);
+        assert!(a.conn_id > 0);
+        assert!(b.conn_id > a.conn_id);
+        assert_eq!(ConnContext::default().conn_id, 0);
+    }
+
+    #[test]
+    fn ctx_attaches_connection_and_operation() {
+        let conn = ConnContext {
+            conn_id: 7,
+            cid: 3,
+            port: 1234,
+        };
+        let io_err = io::Error::from_raw_os_error(110);
+        let kind = io_err.kind();
+        let result: Result<()> = Err(VirgeError::transport_io("recv failed", io_err));
+        let err = result.ctx(&conn, "recv").unwrap_err();
+
+        assert_eq!(err.conn(), Some(&conn));
+        assert_eq!(err.operation(), Some("recv"));
+        assert_eq!(err.kind(), kind);
+        assert_eq!(err.raw_os_error(), Some(110));
+        assert!(format!("{}", err).starts_with("recv failed on conn 7 (cid=3, port=1234): "));
+
+        let io_err: std::io::Error = err.into();
+        assert_eq!(io_err.kind(), kind);
+    }
+
+    #[test]
+    fn ctx_keeps_innermost_context() {
+        let inner = ConnContext::new(3, 1);
+        let outer = ConnContext::new(4, 2);
+        let err = VirgeError::Other("x".into())
+            .with_context(&inner, "send")
+            .with_context(&outer, "flush");
+        assert_eq!(err.conn(), Some(&inner));
+        assert_eq!(err.operation(), Some("send"));
     }
 }
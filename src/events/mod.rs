@@ -0,0 +1,306 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 库内部事件
+//!
+//! 连接建立/断开、握手失败、驱动退出等事件广播给所有订阅者，应用可据此告警，
+//! 不必解析日志。每个订阅者有独立的有界队列，队列满时丢弃新事件并计数，
+//! 发布事件不会因订阅者处理慢而阻塞连接。
+//!
+//! ```ignore
+//! let events = virga::events::subscribe();
+//! std::thread::spawn(move || {
+//!     for event in events {
+//!         if let VirgaEvent::HandshakeFailed { conn, reason, .. } = event {
+//!             alert!("handshake failed on {}: {}", conn, reason);
+//!         }
+//!     }
+//! });
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "sync")]
+use std::sync::mpsc::TrySendError;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::error::{ConnContext, OpId};
+
+/// `subscribe()` 的默认队列长度
+pub const DEFAULT_EVENT_CAPACITY: usize = 256;
+
+/// 事件发生在连接的哪一端
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// 库内部事件
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VirgaEvent {
+    /// 连接建立，认证与消息定义协商（若配置）均已完成
+    Connected { role: Role, conn: ConnContext },
+    /// 连接被本端断开
+    Disconnected { role: Role, conn: ConnContext },
+    /// 认证或消息定义协商失败，连接已被丢弃
+    HandshakeFailed {
+        role: Role,
+        conn: ConnContext,
+        reason: String,
+    },
+    /// 消费者跟不上接收速度，后台接收已暂停等待
+    SlowConsumer { conn: ConnContext, queued: usize },
+    /// 连接的后台驱动意外退出，之后该连接上的收发都会失败
+    DriverDied { conn: ConnContext, reason: String },
+    /// 客户端正在重试连接
+    Reconnecting { cid: u32, port: u32, attempt: u32 },
+    /// 服务端绑定监听遇到暂时性错误，正在退避重试
+    BindRetrying {
+        cid: u32,
+        port: u32,
+        attempt: u32,
+        reason: String,
+    },
+    /// 该 CID 超过 `with_liveness()` 的期限没有消息
+    PeerStale { cid: u32, silent_for: Duration },
+    /// 失联的 CID 重新发来消息
+    PeerAlive { cid: u32 },
+    /// 检测到进程从快照恢复，快照前的连接（或监听 socket）已丢弃并重新建立；
+    /// `cid`、`port` 为客户端的服务端地址或服务端的监听地址
+    ResumedFromSnapshot {
+        role: Role,
+        cid: u32,
+        port: u32,
+        reason: String,
+    },
+    /// 一次 RPC 调用失败；`op` 在两端相同，可据此对应客户机与宿主机上的记录，
+    /// 旧版本的客户端不发送操作 ID，服务端此时为 `None`
+    CallFailed {
+        role: Role,
+        conn: ConnContext,
+        op: Option<OpId>,
+        service: String,
+        method: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for VirgaEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VirgaEvent::Connected { role, conn } => write!(f, "{:?} connected: {}", role, conn),
+            VirgaEvent::Disconnected { role, conn } => {
+                write!(f, "{:?} disconnected: {}", role, conn)
+            }
+            VirgaEvent::HandshakeFailed { role, conn, reason } => {
+                write!(f, "{:?} handshake failed on {}: {}", role, conn, reason)
+            }
+            VirgaEvent::SlowConsumer { conn, queued } => {
+                write!(f, "slow consumer on {}: {} messages queued", conn, queued)
+            }
+            VirgaEvent::DriverDied { conn, reason } => {
+                write!(f, "driver died on {}: {}", conn, reason)
+            }
+            VirgaEvent::Reconnecting { cid, port, attempt } => {
+                write!(
+                    f,
+                    "reconnecting to cid={}, port={} (attempt {})",
+                    cid, port, attempt
+                )
+            }
+            VirgaEvent::BindRetrying {
+                cid,
+                port,
+                attempt,
+                reason,
+            } => write!(
+                f,
+                "retrying bind on cid={}, port={} (attempt {}): {}",
+                cid, port, attempt, reason
+            ),
+            VirgaEvent::PeerStale { cid, silent_for } => {
+                write!(f, "peer cid={} silent for {:?}", cid, silent_for)
+            }
+            VirgaEvent::PeerAlive { cid } => write!(f, "peer cid={} alive again", cid),
+            VirgaEvent::ResumedFromSnapshot {
+                role,
+                cid,
+                port,
+                reason,
+            } => write!(
+                f,
+                "{:?} resumed from snapshot on cid={}, port={}: {}",
+                role, cid, port, reason
+            ),
+            VirgaEvent::CallFailed {
+                role,
+                conn,
+                op,
+                service,
+                method,
+                reason,
+            } => {
+                write!(f, "{:?} call {}/{}", role, service, method)?;
+                if let Some(op) = op {
+                    write!(f, " (op {})", op)?;
+                }
+                write!(f, " failed on {}: {}", conn, reason)
+            }
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+struct Subscriber {
+    tx: SyncSender<VirgaEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+/// 订阅之后发生的事件
+pub fn subscribe() -> EventReceiver {
+    subscribe_with_capacity(DEFAULT_EVENT_CAPACITY)
+}
+
+/// 以指定队列长度订阅
+pub fn subscribe_with_capacity(capacity: usize) -> EventReceiver {
+    let (tx, rx) = sync_channel(capacity.max(1));
+    let dropped = Arc::new(AtomicU64::new(0));
+    SUBSCRIBERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(Subscriber {
+            tx,
+            dropped: dropped.clone(),
+        });
+    EventReceiver { rx, dropped }
+}
+
+/// 发布事件；已 drop 的订阅者在此时移除
+#[cfg(feature = "sync")]
+pub(crate) fn emit(event: VirgaEvent) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(PoisonError::into_inner);
+    subscribers.retain(|s| match s.tx.try_send(event.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            s.dropped.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    });
+}
+
+/// 事件订阅，drop 即取消订阅
+#[derive(Debug)]
+pub struct EventReceiver {
+    rx: Receiver<VirgaEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventReceiver {
+    /// 阻塞等待下一个事件
+    pub fn recv(&self) -> Option<VirgaEvent> {
+        self.rx.recv().ok()
+    }
+
+    /// 取出一个已到达的事件，没有时立即返回 `None`
+    pub fn try_recv(&self) -> Option<VirgaEvent> {
+        self.rx.try_recv().ok()
+    }
+
+    /// 最多等待 `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<VirgaEvent> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+
+    /// 因队列已满被丢弃的事件数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Iterator for EventReceiver {
+    type Item = VirgaEvent;
+
+    fn next(&mut self) -> Option<VirgaEvent> {
+        self.recv()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 其他测试也会发布事件，只关注本测试的连接
+    #[cfg(feature = "sync")]
+    fn own(events: &EventReceiver, conn: ConnContext) -> Vec<VirgaEvent> {
+        std::iter::from_fn(|| events.try_recv())
+            .filter(|e| match e {
+                VirgaEvent::Connected { conn: c, .. }
+                | VirgaEvent::Disconnected { conn: c, .. } => *c == conn,
+                _ => false,
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn every_subscriber_sees_events() {
+        let a = subscribe();
+        let b = subscribe();
+        let conn = ConnContext::new(3, 1234);
+        emit(VirgaEvent::Connected {
+            role: Role::Client,
+            conn,
+        });
+        emit(VirgaEvent::Disconnected {
+            role: Role::Client,
+            conn,
+        });
+        for events in [&a, &b] {
+            let seen = own(events, conn);
+            assert_eq!(seen.len(), 2);
+            assert!(matches!(seen[0], VirgaEvent::Connected { .. }));
+            assert!(matches!(seen[1], VirgaEvent::Disconnected { .. }));
+        }
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn full_queue_drops_instead_of_blocking() {
+        let events = subscribe_with_capacity(1);
+        for _ in 0..3 {
+            emit(VirgaEvent::Reconnecting {
+                cid: 3,
+                port: 1234,
+                attempt: 1,
+            });
+        }
+        assert!(events.try_recv().is_some());
+        assert!(events.dropped() >= 2);
+    }
+
+    #[test]
+    fn display_names_the_connection() {
+        let conn = ConnContext {
+            conn_id: 7,
+            cid: 3,
+            port: 1234,
+        };
+        let event = VirgaEvent::HandshakeFailed {
+            role: Role::Server,
+            conn,
+            reason: "bad token".to_string(),
+        };
+        assert_eq!(
+            event.to_string(),
+            "Server handshake failed on conn 7 (cid=3, port=1234): bad token"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 读取与更新仓库根目录 `fixtures/` 下的协议样例，格式见 `fixtures/README.md`
+//!
+//! 每个样例是一串步骤，步骤之后的 `>` 行是执行它时写出的字节。各协议的测试按步骤
+//! 重新执行，检查写出的字节与样例完全相同、读入样例字节得到的结果与步骤一致。
+//! 有意修改线上格式时以 `VIRGA_BLESS=1 cargo test` 重新生成字节行，注释与步骤保持不变。
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+/// 样例中的一个步骤
+#[derive(Debug)]
+pub(crate) struct Step {
+    /// 步骤行按空白拆开的各个词
+    pub(crate) words: Vec<String>,
+    /// 执行该步骤写出的字节
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Step {
+    pub(crate) fn word(&self, i: usize) -> &str {
+        self.words.get(i).map_or("", String::as_str)
+    }
+
+    /// 第 `i` 个词解析为整数
+    #[cfg(feature = "use-xtransport")]
+    pub(crate) fn number(&self, i: usize) -> u64 {
+        self.word(i)
+            .parse()
+            .unwrap_or_else(|_| panic!("{:?}: word {} is not a number", self.words, i))
+    }
+
+    /// 第 `i` 个词解析为负载，见 [`payload()`]
+    pub(crate) fn payload(&self, i: usize) -> Vec<u8> {
+        payload(self.word(i))
+    }
+}
+
+fn path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(name)
+}
+
+/// 读取样例 `name`（相对于 `fixtures/`）
+pub(crate) fn load(name: &str) -> Vec<Step> {
+    let text = std::fs::read_to_string(path(name))
+        .unwrap_or_else(|e| panic!("cannot read fixture {}: {}", name, e));
+    let mut steps: Vec<Step> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.strip_prefix('>') {
+            Some(bytes) => {
+                let step = steps
+                    .last_mut()
+                    .unwrap_or_else(|| panic!("{}: bytes before the first step", name));
+                step.bytes.extend(hex(bytes));
+            }
+            None => steps.push(Step {
+                words: line.split_whitespace().map(str::to_string).collect(),
+                bytes: Vec::new(),
+            }),
+        }
+    }
+    steps
+}
+
+/// 负载的写法：`-` 为空，`<字节>*<个数>` 为重复的同一字节，其余为十六进制
+pub(crate) fn payload(word: &str) -> Vec<u8> {
+    if word == "-" {
+        return Vec::new();
+    }
+    match word.split_once('*') {
+        Some((byte, count)) => vec![hex(byte)[0]; count.parse().expect("repeat count")],
+        None => hex(word),
+    }
+}
+
+fn hex(text: &str) -> Vec<u8> {
+    let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    assert!(
+        digits.len().is_multiple_of(2),
+        "odd number of hex digits in {:?}",
+        text
+    );
+    digits
+        .chunks(2)
+        .map(|pair| {
+            u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16)
+                .unwrap_or_else(|_| panic!("invalid hex in {:?}", text))
+        })
+        .collect()
+}
+
+/// 检查各步骤实际写出的字节（每一项为一次写入）与样例相同；设置了 `VIRGA_BLESS`
+/// 时改为把它们写回样例
+pub(crate) fn verify(name: &str, steps: &[Step], written: &[Vec<Vec<u8>>]) {
+    assert_eq!(steps.len(), written.len(), "{}: one entry per step", name);
+    if std::env::var_os("VIRGA_BLESS").is_some() {
+        bless(name, written);
+        return;
+    }
+    for (step, writes) in steps.iter().zip(written) {
+        let actual = writes.concat();
+        assert!(
+            actual == step.bytes,
+            "{}: `{}` wrote\n{}\nbut the fixture has\n{}",
+            name,
+            step.words.join(" "),
+            format_bytes(std::slice::from_ref(&actual)),
+            format_bytes(std::slice::from_ref(&step.bytes)),
+        );
+    }
+}
+
+/// 保留注释与步骤行，按实际写出的字节重写 `>` 行
+fn bless(name: &str, written: &[Vec<Vec<u8>>]) {
+    let text = std::fs::read_to_string(path(name)).unwrap();
+    let mut out = String::new();
+    let mut writes = written.iter();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('>') {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+        if !trimmed.is_empty() && !trimmed.starts_with('#') {
+            out.push_str(&format_bytes(writes.next().unwrap()));
+        }
+    }
+    std::fs::write(path(name), out).unwrap();
+}
+
+/// 每次写入另起一行，每行至多 16 字节，4 字节一组
+fn format_bytes(writes: &[Vec<u8>]) -> String {
+    let mut out = String::new();
+    for write in writes {
+        for line in write.chunks(16) {
+            out.push('>');
+            for group in line.chunks(4) {
+                out.push(' ');
+                for byte in group {
+                    let _ = write!(out, "{:02x}", byte);
+                }
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
@@ -26,38 +26,355 @@
 
 #[cfg(all(feature = "use-xtransport", feature = "use-yamux"))]
 compile_error!("feature1 and feature2 cannot be enabled at the same time");
+#[cfg(all(
+    feature = "sync",
+    not(any(feature = "use-xtransport", feature = "use-yamux"))
+))]
+compile_error!("the sync feature needs use-xtransport or use-yamux");
 
 pub mod error;
-pub use error::{Result, VirgeError};
-
+pub use error::{ConnContext, OpId, Result, ResultExt, VirgeError};
+
+pub mod addr;
+#[cfg(feature = "sync")]
+pub mod agent;
+pub mod auth;
+#[cfg(feature = "blobs")]
+pub mod blobs;
+#[cfg(feature = "sync")]
+pub mod budget;
+pub mod buffers;
+#[cfg(feature = "sync")]
 pub mod client;
+#[cfg(feature = "sync")]
+pub mod clock;
+pub mod codec;
+#[cfg(feature = "sync")]
+pub mod compression;
+#[cfg(feature = "sync")]
+pub mod console;
+pub mod defaults;
+#[cfg(feature = "sync")]
+pub mod discovery;
+#[cfg(feature = "sync")]
+pub mod dns;
+pub mod events;
+#[cfg(all(test, feature = "sync"))]
+mod fixtures;
+pub mod logging;
+#[cfg(feature = "sync")]
+pub mod logs;
+#[cfg(feature = "sync")]
+pub(crate) mod loom;
+#[cfg(feature = "sync")]
+pub mod metrics;
+#[cfg(feature = "sync")]
+pub mod mux;
+#[cfg(feature = "sync")]
+pub(crate) mod poll;
+pub mod probe;
+#[cfg(feature = "sync")]
+pub mod proxy;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "raw")]
+pub mod raw;
+#[cfg(feature = "sync")]
+pub mod resume;
+#[cfg(feature = "sync")]
+pub(crate) mod retry;
+#[cfg(feature = "sync")]
+pub mod rpc;
+#[cfg(feature = "sync")]
+pub mod selftest;
+#[cfg(feature = "sync")]
 pub mod server;
+pub mod sources;
+#[cfg(feature = "sync")]
+pub mod spool;
+pub mod stats;
+#[cfg(feature = "sync")]
+pub mod testing;
+pub mod threads;
 pub mod transport;
-
-pub use client::{ClientConfig, VirgeClient};
-pub use server::{ServerConfig, ServerManager, VirgeServer};
+pub mod units;
+#[cfg(all(feature = "websocket", feature = "sync"))]
+pub mod websocket;
+
+pub use addr::Addr;
+pub use auth::{
+    AccessRules, Authorizer, PeerIdentity, Principal, RekeyPolicy, TokenAuth, TokenCredential,
+};
+#[cfg(feature = "sync")]
+pub use client::{ClientConfig, VirgeClient, VirgeClientPool};
+pub use defaults::Defaults;
+pub use events::VirgaEvent;
+pub use probe::probe;
+#[cfg(feature = "sync")]
+pub use server::{ServerConfig, ServerManager, TenantMap, VirgeServer};
+pub use stats::{ConnectionStats, SendBacklog};
+pub use transport::RecvLoan;
+#[cfg(feature = "use-yamux")]
+pub use transport::VirgaStream;
+pub use units::ByteSize;
 
 pub const KIB: usize = 1024;
 pub const MIB: usize = KIB * 1024;
 pub const GIB: usize = MIB * 1024;
 
-pub const DEFAULT_SERVER_CID: usize = 103;
+#[deprecated(note = "use `Defaults::get().server_cid`")]
+pub const DEFAULT_SERVER_CID: usize = Defaults::BUILTIN.server_cid as usize;
 pub const VMADDR_CID_ANY: usize = 0xFFFFFFFF;
-pub const DEFAULT_SERVER_PORT: usize = 1234;
+/// vsock 本机回环地址
+pub const VMADDR_CID_LOCAL: usize = 1;
+/// 宿主机地址
+pub const VMADDR_CID_HOST: usize = 2;
+#[deprecated(note = "use `Defaults::get().server_port`")]
+pub const DEFAULT_SERVER_PORT: usize = Defaults::BUILTIN.server_port as usize;
+
+#[deprecated(note = "use `Defaults::get().chunk_size`")]
+pub const DEAFULT_CHUNK_SIZE: usize = Defaults::BUILTIN.chunk_size.get();
+#[deprecated(note = "use `Defaults::get().is_ack`")]
+pub const DEFAULT_IS_ACK: bool = Defaults::BUILTIN.is_ack;
+#[deprecated(note = "use `Defaults::get().send_window`")]
+pub const DEFAULT_SEND_WINDOW: usize = Defaults::BUILTIN.send_window.get();
+#[deprecated(note = "use `Defaults::get().read_queue_depth`")]
+pub const DEFAULT_READ_QUEUE_DEPTH: usize = Defaults::BUILTIN.read_queue_depth.get();
+
+/// 分片大小下限：需容纳 16 字节包头并留出有效负载
+pub const MIN_CHUNK_SIZE: usize = 64;
+/// 分片大小上限：包头 + u16 可表示的最大负载
+pub const MAX_CHUNK_SIZE: usize = 16 + u16::MAX as usize;
+/// vsock 中的“任意端口”，不能作为连接目标
+pub const VMADDR_PORT_ANY: usize = 0xFFFFFFFF;
+
+/// 校验客户端与服务端共用的配置项
+#[cfg(feature = "sync")]
+fn validate_common(chunk_size: ByteSize, send_window: usize) -> Result<()> {
+    let chunk = chunk_size.as_usize();
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk) {
+        return Err(VirgeError::ConfigError(format!(
+            "chunk_size {} out of range [{}, {}]",
+            chunk, MIN_CHUNK_SIZE, MAX_CHUNK_SIZE
+        )));
+    }
+    if send_window == 0 {
+        return Err(VirgeError::ConfigError(
+            "send_window must be at least 1".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// `recv()` 时上一条消息已经 `Read` 读了一部分的处理方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Leftovers {
+    /// 返回 `InvalidInput`，由应用读完或 `take_partial()` 后再接收
+    #[default]
+    Error,
+    /// 把其余字节拼在下一条消息之前一起返回
+    Prepend,
+    /// 先把其余字节作为一条消息返回，下一次 `recv()` 再接收新消息
+    DrainFirst,
+}
 
-pub const DEAFULT_CHUNK_SIZE: usize = KIB;
-pub const DEFAULT_IS_ACK: bool = false;
+/// 服务端发送 GOAWAY 请客户端重连的原因
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GoAwayReason {
+    /// 连接存活超过 `ServerConfig::with_max_connection_age()` 的上限
+    MaxAge,
+    /// 服务端即将维护，调用了 `drain()`
+    Drain,
+    /// 本版本不认识的原因码
+    Other(u64),
+}
+
+#[cfg(feature = "sync")]
+impl GoAwayReason {
+    pub(crate) fn code(self) -> u64 {
+        match self {
+            GoAwayReason::MaxAge => 1,
+            GoAwayReason::Drain => 2,
+            GoAwayReason::Other(code) => code,
+        }
+    }
+
+    pub(crate) fn from_code(code: u64) -> Self {
+        match code {
+            1 => GoAwayReason::MaxAge,
+            2 => GoAwayReason::Drain,
+            code => GoAwayReason::Other(code),
+        }
+    }
+}
 
-#[derive(Debug, PartialEq)]
-enum ReadState {
-    Idle,
-    Reading { total: usize, read: usize },
+/// 连接结束的原因：对端断开前经控制帧告知，`recv()` 随后返回的连接关闭错误中带有它，
+/// 用 [`VirgeError::shutdown_reason()`] 或 [`error::shutdown_reason()`] 取出。
+/// 对端没有告知（进程崩溃、旧版本的对端）时视为 `PeerReset`，此前收到过排空的 GOAWAY
+/// 时视为 `ServerDraining`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ShutdownReason {
+    /// 对端正常断开
+    GracefulClose,
+    /// 对端未说明原因即断开或重置了连接
+    PeerReset,
+    /// 对端等待消息超过空闲超时后断开
+    IdleTimeout,
+    /// 服务端正在排空（`drain()`），稍后或换一个服务端重连
+    ServerDraining,
+    /// 对端已不在服务端的允许列表中
+    AuthRevoked,
+}
+
+impl ShutdownReason {
+    #[cfg(feature = "sync")]
+    pub(crate) fn code(self) -> u64 {
+        match self {
+            ShutdownReason::GracefulClose => 1,
+            ShutdownReason::PeerReset => 2,
+            ShutdownReason::IdleTimeout => 3,
+            ShutdownReason::ServerDraining => 4,
+            ShutdownReason::AuthRevoked => 5,
+        }
+    }
+
+    /// 不认识的原因码视为 `PeerReset`
+    #[cfg(feature = "sync")]
+    pub(crate) fn from_code(code: u64) -> Self {
+        match code {
+            1 => ShutdownReason::GracefulClose,
+            3 => ShutdownReason::IdleTimeout,
+            4 => ShutdownReason::ServerDraining,
+            5 => ShutdownReason::AuthRevoked,
+            _ => ShutdownReason::PeerReset,
+        }
+    }
+
+    /// 接收出错时推断连接结束的原因：`close` 为对端告知的原因码，`goaway` 为对端发来的
+    /// GOAWAY。错误不表示连接已结束（超时、数据损坏等）时返回 `None`
+    #[cfg(feature = "sync")]
+    pub(crate) fn infer(
+        kind: std::io::ErrorKind,
+        close: Option<u64>,
+        goaway: Option<GoAwayReason>,
+    ) -> Option<Self> {
+        use std::io::ErrorKind::*;
+        let ended = matches!(
+            kind,
+            ConnectionReset | ConnectionAborted | UnexpectedEof | BrokenPipe
+        );
+        if !(ended || kind == NotConnected && close.is_some()) {
+            return None;
+        }
+        Some(match (close, goaway) {
+            (Some(code), _) => ShutdownReason::from_code(code),
+            (None, Some(GoAwayReason::Drain)) => ShutdownReason::ServerDraining,
+            (None, _) => ShutdownReason::PeerReset,
+        })
+    }
+
+    /// 重连是否可能成功：对端被撤销授权时重连也会被拒绝，应放弃
+    pub fn should_reconnect(self) -> bool {
+        self != ShutdownReason::AuthRevoked
+    }
+}
+
+impl std::fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ShutdownReason::GracefulClose => "closed by peer",
+            ShutdownReason::PeerReset => "reset by peer",
+            ShutdownReason::IdleTimeout => "idle timeout",
+            ShutdownReason::ServerDraining => "server draining",
+            ShutdownReason::AuthRevoked => "authorization revoked",
+        })
+    }
 }
 
-#[cfg(test)]
+#[cfg(feature = "sync")]
+use virga_core::ReadState;
+
+/// [`ReadState`] 定义在 virga-core，消息接口的边界检查留在这里
+#[cfg(feature = "sync")]
+trait AtBoundary {
+    fn at_boundary(&self, unread: usize) -> std::io::Result<()>;
+}
+
+#[cfg(feature = "sync")]
+impl AtBoundary for ReadState {
+    /// 消息接口（`recv()` 等）只能在消息边界上使用：一条消息已经 `Read` 读了一部分时，
+    /// 再按消息接收会跳过其余 `unread` 字节，因此返回 `InvalidInput`
+    fn at_boundary(&self, unread: usize) -> std::io::Result<()> {
+        match self {
+            ReadState::Idle => Ok(()),
+            ReadState::Reading { total, .. } => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} of {} bytes of the current message are still unread through Read; \
+                     finish reading it or call take_partial() first",
+                    unread, total
+                ),
+            )),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
 mod tests {
     use super::*;
 
+    #[test]
+    fn goaway_reason_codes_round_trip() {
+        for reason in [
+            GoAwayReason::MaxAge,
+            GoAwayReason::Drain,
+            GoAwayReason::Other(42),
+        ] {
+            assert_eq!(GoAwayReason::from_code(reason.code()), reason);
+        }
+    }
+
+    #[test]
+    fn shutdown_reasons_are_inferred_from_what_the_peer_said() {
+        use std::io::ErrorKind::*;
+        for reason in [
+            ShutdownReason::GracefulClose,
+            ShutdownReason::PeerReset,
+            ShutdownReason::IdleTimeout,
+            ShutdownReason::ServerDraining,
+            ShutdownReason::AuthRevoked,
+        ] {
+            assert_eq!(ShutdownReason::from_code(reason.code()), reason);
+            let code = Some(reason.code());
+            assert_eq!(
+                ShutdownReason::infer(UnexpectedEof, code, None),
+                Some(reason)
+            );
+            assert_eq!(
+                ShutdownReason::infer(NotConnected, code, None),
+                Some(reason)
+            );
+        }
+        assert_eq!(ShutdownReason::from_code(42), ShutdownReason::PeerReset);
+
+        let drain = Some(GoAwayReason::Drain);
+        assert_eq!(
+            ShutdownReason::infer(ConnectionReset, None, drain),
+            Some(ShutdownReason::ServerDraining)
+        );
+        assert_eq!(
+            ShutdownReason::infer(BrokenPipe, None, Some(GoAwayReason::MaxAge)),
+            Some(ShutdownReason::PeerReset)
+        );
+        // 没有结束连接的错误不带原因
+        assert_eq!(ShutdownReason::infer(TimedOut, None, drain), None);
+        assert_eq!(ShutdownReason::infer(NotConnected, None, None), None);
+        assert!(!ShutdownReason::AuthRevoked.should_reconnect());
+        assert!(ShutdownReason::ServerDraining.should_reconnect());
+    }
+
     #[test]
     fn constants_kib() {
         assert_eq!(KIB, 1024);
@@ -74,8 +391,10 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn constants_default_server_cid() {
         assert_eq!(DEFAULT_SERVER_CID, 103);
+        assert_eq!(DEFAULT_SERVER_CID, Defaults::BUILTIN.server_cid as usize);
     }
 
     #[test]
@@ -84,18 +403,56 @@ mod tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn constants_default_server_port() {
         assert_eq!(DEFAULT_SERVER_PORT, 1234);
+        assert_eq!(DEFAULT_SERVER_PORT, Defaults::BUILTIN.server_port as usize);
     }
 
     #[test]
+    #[allow(deprecated)]
     fn constants_default_chunk_size() {
         assert_eq!(DEAFULT_CHUNK_SIZE, KIB);
+        assert_eq!(DEAFULT_CHUNK_SIZE, Defaults::BUILTIN.chunk_size.get());
     }
 
     #[test]
+    #[allow(deprecated, clippy::bool_assert_comparison)]
     fn constants_default_is_ack() {
-        assert!(!DEFAULT_IS_ACK);
+        const { assert!(!DEFAULT_IS_ACK) };
+        assert_eq!(DEFAULT_IS_ACK, Defaults::BUILTIN.is_ack);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn constants_default_send_window() {
+        assert_eq!(DEFAULT_SEND_WINDOW, 16);
+        assert_eq!(DEFAULT_SEND_WINDOW, Defaults::BUILTIN.send_window.get());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn constants_default_read_queue_depth() {
+        assert_eq!(DEFAULT_READ_QUEUE_DEPTH, 64);
+        assert_eq!(
+            DEFAULT_READ_QUEUE_DEPTH,
+            Defaults::BUILTIN.read_queue_depth.get()
+        );
+    }
+
+    #[test]
+    fn validate_common_chunk_bounds() {
+        assert!(validate_common(ByteSize::from(MIN_CHUNK_SIZE), 1).is_ok());
+        assert!(validate_common(ByteSize::from(MAX_CHUNK_SIZE), 1).is_ok());
+        assert!(validate_common(ByteSize::b(0), 1).is_err());
+        assert!(validate_common(ByteSize::from(MIN_CHUNK_SIZE - 1), 1).is_err());
+        assert!(validate_common(ByteSize::from(MAX_CHUNK_SIZE + 1), 1).is_err());
+    }
+
+    #[test]
+    fn validate_common_send_window() {
+        let err = validate_common(ByteSize::kib(1), 0).unwrap_err();
+        assert!(matches!(err, VirgeError::ConfigError(msg) if msg.contains("send_window")));
     }
 
     #[test]
@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 结构化日志
+//!
+//! 连接建立、断开、握手失败等日志带有连接 ID、CID、字节数、耗时等字段。
+//! 默认以 `key=value` 追加在消息后；启用 `structured-log` 特性后字段作为
+//! `log` 的键值对输出，可由 [`JsonLogger`] 写成每行一个 JSON 对象，
+//! 便于宿主侧日志管道直接建索引：
+//!
+//! ```text
+//! {"ts":1760000000123,"level":"INFO","target":"virga::client::virge_client","msg":"client connected","conn_id":1,"cid":3,"port":1234,"duration_ms":2}
+//! ```
+
+/// 输出带字段的日志，字段值须为整数、布尔或 `&str`
+#[cfg_attr(not(feature = "sync"), allow(unused_macros))]
+macro_rules! log_event {
+    ($lvl:expr, $msg:literal $(, $key:ident = $val:expr)* $(,)?) => {{
+        #[cfg(feature = "structured-log")]
+        log::log!($lvl, $($key = $val),*; $msg);
+        #[cfg(not(feature = "structured-log"))]
+        log::log!($lvl, concat!($msg $(, " ", stringify!($key), "={}")*) $(, $val)*);
+    }};
+}
+// 只在连接层使用，只启用 `compression` 等非连接特性时用不到
+#[cfg_attr(
+    not(any(feature = "sync", feature = "raw", feature = "quic")),
+    allow(unused_imports)
+)]
+pub(crate) use log_event;
+
+#[cfg(feature = "structured-log")]
+pub use json::{init_json, JsonLogger};
+
+#[cfg(feature = "structured-log")]
+mod json {
+    use std::io::Write;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use log::kv::{Key, Value, VisitSource};
+    use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+    use serde_json::{Map, Value as Json};
+
+    /// 每条日志写成一行 JSON 到 stderr
+    #[derive(Debug)]
+    pub struct JsonLogger {
+        level: LevelFilter,
+    }
+
+    impl JsonLogger {
+        pub fn new(level: LevelFilter) -> Self {
+            Self { level }
+        }
+
+        /// 格式化一条日志（不含换行）
+        pub fn format(&self, record: &Record<'_>) -> String {
+            let mut fields = Map::new();
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64);
+            fields.insert("ts".into(), ts.into());
+            fields.insert("level".into(), record.level().as_str().into());
+            fields.insert("target".into(), record.target().into());
+            fields.insert("msg".into(), record.args().to_string().into());
+            let _ = record.key_values().visit(&mut Fields(&mut fields));
+            Json::Object(fields).to_string()
+        }
+    }
+
+    struct Fields<'a>(&'a mut Map<String, Json>);
+
+    impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+            let json = if let Some(v) = value.to_u64() {
+                v.into()
+            } else if let Some(v) = value.to_i64() {
+                v.into()
+            } else if let Some(v) = value.to_bool() {
+                v.into()
+            } else if let Some(v) = value.to_f64() {
+                v.into()
+            } else if let Some(v) = value.to_borrowed_str() {
+                v.into()
+            } else {
+                value.to_string().into()
+            };
+            self.0.insert(key.as_str().to_string(), json);
+            Ok(())
+        }
+    }
+
+    impl Log for JsonLogger {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.level() <= self.level
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            if self.enabled(record.metadata()) {
+                let line = self.format(record);
+                let _ = writeln!(std::io::stderr().lock(), "{}", line);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// 安装 [`JsonLogger`] 为全局日志器
+    pub fn init_json(level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(JsonLogger::new(level)))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use log::Level;
+
+        #[test]
+        fn record_fields_become_json() {
+            let kvs: &[(&str, Value<'_>)] = &[
+                ("conn_id", Value::from(7u64)),
+                ("cid", Value::from(3u32)),
+                ("role", Value::from("client")),
+            ];
+            let record = Record::builder()
+                .level(Level::Info)
+                .target("virga::client")
+                .args(format_args!("client connected"))
+                .key_values(&kvs)
+                .build();
+            let line = JsonLogger::new(LevelFilter::Info).format(&record);
+            let json: Json = serde_json::from_str(&line).unwrap();
+            assert_eq!(json["level"], "INFO");
+            assert_eq!(json["msg"], "client connected");
+            assert_eq!(json["conn_id"], 7);
+            assert_eq!(json["cid"], 3);
+            assert_eq!(json["role"], "client");
+            assert!(json["ts"].as_u64().unwrap() > 0);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 生产者一侧尚未确认的记录
+//!
+//! 记录按偏移量排列，收集端确认后才移除。内存中的记录超过 [`SpoolConfig`] 的内存上限后，
+//! 新记录写入临时文件，只在内存中保留其位置；记录的顺序由偏移量决定，与存放位置无关。
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::FileExt;
+use std::time::Instant;
+
+use log::*;
+
+use crate::spool::{self, SpoolConfig};
+
+#[derive(Debug)]
+enum Place {
+    Memory(Vec<u8>),
+    Disk { at: u64, len: u32 },
+}
+
+#[derive(Debug)]
+struct Entry {
+    appended: Instant,
+    place: Place,
+}
+
+impl Entry {
+    fn len(&self) -> usize {
+        match &self.place {
+            Place::Memory(data) => data.len(),
+            Place::Disk { len, .. } => *len as usize,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Journal {
+    config: SpoolConfig,
+    entries: VecDeque<Entry>,
+    /// 第一条记录的偏移量，之前的记录都已确认
+    base: u64,
+    memory_bytes: u64,
+    file: Option<File>,
+    write_at: u64,
+    disk_entries: usize,
+}
+
+impl Journal {
+    pub(super) fn new(config: SpoolConfig) -> Self {
+        Self {
+            config,
+            entries: VecDeque::new(),
+            base: 0,
+            memory_bytes: 0,
+            file: None,
+            write_at: 0,
+            disk_entries: 0,
+        }
+    }
+
+    pub(super) fn set_config(&mut self, config: SpoolConfig) {
+        self.config = config;
+    }
+
+    pub(super) fn config(&self) -> &SpoolConfig {
+        &self.config
+    }
+
+    /// 最早的未确认记录的偏移量
+    pub(super) fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// 下一条记录的偏移量
+    pub(super) fn end(&self) -> u64 {
+        self.base + self.entries.len() as u64
+    }
+
+    /// 能否再放入 `len` 字节的记录；文件为空时总是允许写入一条，避免单条记录超过磁盘上限时永远等待
+    pub(super) fn has_room(&self, len: usize) -> bool {
+        let len = len as u64;
+        self.memory_bytes + len <= self.config.memory().as_u64()
+            || self
+                .config
+                .disk()
+                .is_none_or(|disk| self.disk_entries == 0 || self.write_at + len <= disk.as_u64())
+    }
+
+    /// 追加一条记录，返回其偏移量；调用前应以 `has_room()` 确认有空间
+    pub(super) fn push(&mut self, data: &[u8]) -> Result<u64> {
+        let len = data.len() as u64;
+        let place = if self.memory_bytes + len <= self.config.memory().as_u64() {
+            self.memory_bytes += len;
+            Place::Memory(data.to_vec())
+        } else {
+            let len32 = u32::try_from(data.len()).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("log record of {} bytes is too large", data.len()),
+                )
+            })?;
+            if self.file.is_none() {
+                info!(
+                    "Log buffer spilling to disk: {} bytes in memory",
+                    self.memory_bytes
+                );
+                self.file = Some(spool::temp_file(self.config.dir(), "logs")?);
+            }
+            let file = self.file.as_ref().expect("journal file");
+            file.write_all_at(data, self.write_at)?;
+            let place = Place::Disk {
+                at: self.write_at,
+                len: len32,
+            };
+            self.write_at += len;
+            self.disk_entries += 1;
+            place
+        };
+        self.entries.push_back(Entry {
+            appended: Instant::now(),
+            place,
+        });
+        Ok(self.end() - 1)
+    }
+
+    /// 移除 `next` 之前的记录；文件中的记录全部移除后清空文件
+    pub(super) fn ack(&mut self, next: u64) -> Result<()> {
+        while self.base < next {
+            let Some(entry) = self.entries.pop_front() else {
+                break;
+            };
+            self.base += 1;
+            match entry.place {
+                Place::Memory(data) => self.memory_bytes -= data.len() as u64,
+                Place::Disk { .. } => self.disk_entries -= 1,
+            }
+        }
+        if self.disk_entries == 0 && self.write_at > 0 {
+            if let Some(file) = &self.file {
+                file.set_len(0)?;
+            }
+            self.write_at = 0;
+        }
+        Ok(())
+    }
+
+    fn entry(&self, offset: u64) -> Option<&Entry> {
+        let index = offset.checked_sub(self.base)?;
+        self.entries.get(usize::try_from(index).ok()?)
+    }
+
+    /// 偏移量 `offset` 的记录的追加时间
+    pub(super) fn appended(&self, offset: u64) -> Option<Instant> {
+        self.entry(offset).map(|entry| entry.appended)
+    }
+
+    /// 从 `offset` 开始的记录中，总字节数不超过 `limit` 的个数（至少一条）及其字节数
+    pub(super) fn span(&self, offset: u64, limit: usize) -> (u64, usize) {
+        let mut count = 0;
+        let mut bytes = 0;
+        while let Some(entry) = self.entry(offset + count) {
+            if count > 0 && bytes + entry.len() > limit {
+                break;
+            }
+            bytes += entry.len();
+            count += 1;
+        }
+        (count, bytes)
+    }
+
+    /// 读出偏移量 `offset` 的记录
+    pub(super) fn read(&self, offset: u64) -> Result<Vec<u8>> {
+        let entry = self
+            .entry(offset)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("no log record {}", offset)))?;
+        match &entry.place {
+            Place::Memory(data) => Ok(data.clone()),
+            Place::Disk { at, len } => {
+                let mut data = vec![0u8; *len as usize];
+                let file = self.file.as_ref().expect("journal file");
+                file.read_exact_at(&mut data, *at)?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::ByteSize;
+
+    #[test]
+    fn records_spill_to_disk_and_are_removed_on_ack() {
+        let config = SpoolConfig::new(ByteSize::b(10)).with_disk_limit(ByteSize::b(12));
+        let mut journal = Journal::new(config);
+        assert_eq!(journal.push(b"aaaaaaaa").unwrap(), 0);
+        assert_eq!(journal.push(b"bbbbbbbb").unwrap(), 1);
+        assert!(journal.has_room(2));
+        assert_eq!(journal.push(b"cc").unwrap(), 2);
+        assert!(!journal.has_room(8));
+        assert_eq!(journal.push(b"dd").unwrap(), 3);
+        assert_eq!(journal.write_at, 10);
+        assert!(!journal.has_room(3));
+
+        assert_eq!(journal.span(0, 12), (1, 8));
+        assert_eq!(journal.span(0, 16), (2, 16));
+        assert_eq!(journal.span(1, 12), (3, 12));
+        assert_eq!(journal.read(1).unwrap(), b"bbbbbbbb");
+        assert_eq!(journal.read(3).unwrap(), b"dd");
+        assert!(journal.read(4).is_err());
+
+        journal.ack(2).unwrap();
+        assert_eq!((journal.base(), journal.end()), (2, 4));
+        assert!(journal.read(1).is_err());
+        assert!(journal.has_room(8));
+        journal.ack(4).unwrap();
+        assert_eq!(journal.write_at, 0);
+        assert_eq!(journal.span(4, 12), (0, 0));
+    }
+}
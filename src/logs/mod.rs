@@ -0,0 +1,299 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 日志转发：客户机上的生产者与宿主机上的收集端
+//!
+//! 客户机上的 [`LogProducer`] 在后台连接宿主机，应用经 [`LogWriter`] 追加记录，每条记录按
+//! 追加顺序得到一个偏移量。记录先放在内存中，超过上限后写入临时文件（与
+//! [`spool`](crate::spool) 相同的 [`SpoolConfig`]），两者都满时 `append()` 阻塞，形成背压。
+//! 记录攒成批次发送，收集端交给应用处理后确认，生产者收到确认才移除记录；断线重连后
+//! 从收集端确认到的位置继续，不丢失也不重复。
+//!
+//! ```ignore
+//! // 客户机
+//! let producer = LogProducer::new(ClientConfig::new(2, 5001, 4096, false), "guest.kernel")
+//!     .with_buffer(SpoolConfig::new(ByteSize::mib(4)).with_disk_limit(ByteSize::mib(64)));
+//! let log = producer.writer();
+//! thread::spawn(move || producer.run());
+//! log.append(b"eth0: link up")?;
+//!
+//! // 宿主机，每个连接一个线程
+//! let collector = LogCollector::new();
+//! let mux = server.into_mux()?;
+//! collector.serve(&mux.channel(LOG_CHANNEL)?, |record| store(&record.source, &record.data))?;
+//! ```
+//!
+//! 连接经 [`mux`](crate::mux) 复用，使用通道 [`LOG_CHANNEL`]，每条消息以 1 字节类型开头：
+//!
+//! ```text
+//! HELLO  | epoch(8) | source              生产者 → 收集端，连上后第一条
+//! RESUME | next(8)                       收集端 → 生产者，已处理到的位置，未知来源为 0
+//! BATCH  | first(8) | (len(4) | record)*  生产者 → 收集端
+//! ACK    | next(8)                       收集端 → 生产者，`next` 之前的记录已处理
+//! ```
+//!
+//! 整数均为大端。`epoch` 在生产者创建时生成，生产者进程重启后偏移量从 0 重新开始，
+//! 收集端见到新的 `epoch` 时不再沿用原来的位置。收集端的位置只保存在内存中。
+
+mod journal;
+mod producer;
+
+pub use producer::{LogProducer, LogWriter};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use log::*;
+
+use crate::mux::Channel;
+
+/// 承载日志的通道号
+pub const LOG_CHANNEL: u32 = 3;
+
+const HELLO: u8 = 0;
+const RESUME: u8 = 1;
+const BATCH: u8 = 2;
+const ACK: u8 = 3;
+
+/// 通道上的一条消息
+#[derive(Debug, PartialEq, Eq)]
+enum Message {
+    Hello { epoch: u64, source: String },
+    Resume(u64),
+    Batch { first: u64, records: Vec<Vec<u8>> },
+    Ack(u64),
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Message::Hello { epoch, source } => {
+                buf.push(HELLO);
+                buf.extend_from_slice(&epoch.to_be_bytes());
+                buf.extend_from_slice(source.as_bytes());
+            }
+            Message::Resume(next) => {
+                buf.push(RESUME);
+                buf.extend_from_slice(&next.to_be_bytes());
+            }
+            Message::Batch { first, records } => {
+                buf.push(BATCH);
+                buf.extend_from_slice(&first.to_be_bytes());
+                for record in records {
+                    buf.extend_from_slice(&(record.len() as u32).to_be_bytes());
+                    buf.extend_from_slice(record);
+                }
+            }
+            Message::Ack(next) => {
+                buf.push(ACK);
+                buf.extend_from_slice(&next.to_be_bytes());
+            }
+        }
+        buf
+    }
+
+    fn decode(message: &[u8]) -> Result<Self> {
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("log {}", what));
+        let (&kind, body) = message
+            .split_first()
+            .ok_or_else(|| invalid("message is empty"))?;
+        if body.len() < 8 {
+            return Err(invalid("message is too short"));
+        }
+        let (number, mut rest) = body.split_at(8);
+        let number = u64::from_be_bytes(number.try_into().unwrap());
+        let message = match kind {
+            HELLO => Message::Hello {
+                epoch: number,
+                source: String::from_utf8(rest.to_vec())
+                    .map_err(|_| invalid("source is not UTF-8"))?,
+            },
+            RESUME if rest.is_empty() => Message::Resume(number),
+            ACK if rest.is_empty() => Message::Ack(number),
+            BATCH => {
+                let mut records = Vec::new();
+                while !rest.is_empty() {
+                    if rest.len() < 4 {
+                        return Err(invalid("record is truncated"));
+                    }
+                    let (len, tail) = rest.split_at(4);
+                    let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+                    if tail.len() < len {
+                        return Err(invalid("record is truncated"));
+                    }
+                    records.push(tail[..len].to_vec());
+                    rest = &tail[len..];
+                }
+                Message::Batch {
+                    first: number,
+                    records,
+                }
+            }
+            _ => return Err(invalid(&format!("message type {} is unexpected", kind))),
+        };
+        Ok(message)
+    }
+}
+
+/// 收集端收到的一条记录
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogRecord {
+    /// 生产者的来源名
+    pub source: String,
+    /// 记录在该来源中的偏移量
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+/// 各来源已处理到的位置
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    epoch: u64,
+    next: u64,
+}
+
+/// 宿主机一侧的日志收集端，可在多个连接线程间共享（克隆后共享同一份位置）
+#[derive(Clone, Default)]
+pub struct LogCollector {
+    positions: Arc<Mutex<HashMap<String, Position>>>,
+}
+
+impl LogCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 来源 `source` 已处理到的位置：下一条应收到的记录的偏移量
+    pub fn position(&self, source: &str) -> Option<u64> {
+        self.lock().get(source).map(|position| position.next)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Position>> {
+        self.positions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 在 `channel` 上接收一个生产者的记录，按偏移量顺序逐条交给 `sink`，每批处理完后确认。
+    /// 连接关闭时返回 `Ok`；`sink` 返回错误时停止接收并返回该错误，未确认的记录在重连后重发
+    pub fn serve<F>(&self, channel: &Channel, mut sink: F) -> Result<()>
+    where
+        F: FnMut(LogRecord) -> Result<()>,
+    {
+        let (epoch, source) = match Message::decode(&channel.recv()?)? {
+            Message::Hello { epoch, source } => (epoch, source),
+            other => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("expected a log hello, got {:?}", other),
+                ))
+            }
+        };
+        // 新的生产者（epoch 不同）从它发来的第一条记录开始
+        let mut next = self
+            .lock()
+            .get(&source)
+            .filter(|position| position.epoch == epoch)
+            .map(|position| position.next);
+        channel.send(&Message::Resume(next.unwrap_or(0)).encode())?;
+        info!("Collecting logs from {} at {:?}", source, next);
+
+        loop {
+            let message = match channel.recv() {
+                Ok(message) => message,
+                Err(e) if crate::rpc::is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let (first, records) = match Message::decode(&message)? {
+                Message::Batch { first, records } => (first, records),
+                other => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("expected a log batch, got {:?}", other),
+                    ))
+                }
+            };
+            let expected = *next.get_or_insert(first);
+            if first > expected {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "log records {}..{} from {} are missing",
+                        expected, first, source
+                    ),
+                ));
+            }
+            let mut result = Ok(());
+            for (offset, data) in (first..).zip(records) {
+                // 重连后重发的、已处理过的记录
+                if offset < expected {
+                    continue;
+                }
+                result = sink(LogRecord {
+                    source: source.clone(),
+                    offset,
+                    data,
+                });
+                if result.is_err() {
+                    break;
+                }
+                next = Some(offset + 1);
+            }
+            let position = next.unwrap_or(first);
+            self.lock().insert(
+                source.clone(),
+                Position {
+                    epoch,
+                    next: position,
+                },
+            );
+            result?;
+            channel.send(&Message::Ack(position).encode())?;
+        }
+    }
+}
+
+impl fmt::Debug for LogCollector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogCollector")
+            .field("sources", &self.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        for message in [
+            Message::Hello {
+                epoch: 7,
+                source: "guest.kernel".into(),
+            },
+            Message::Resume(42),
+            Message::Batch {
+                first: 3,
+                records: vec![b"a".to_vec(), Vec::new(), vec![9; 300]],
+            },
+            Message::Ack(u64::MAX),
+        ] {
+            assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+        }
+        for bad in [
+            vec![],
+            vec![ACK, 0, 0],
+            vec![RESUME, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![BATCH, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 1],
+            vec![9, 0, 0, 0, 0, 0, 0, 0, 0],
+        ] {
+            let err = Message::decode(&bad).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,484 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 客户机一侧：缓存记录，批量发送，断线后从确认的位置继续
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use super::journal::Journal;
+use super::{Message, LOG_CHANNEL};
+use crate::agent::ShutdownHandle;
+use crate::client::{ClientConfig, VirgeClient};
+use crate::mux::Channel;
+use crate::retry::{INITIAL_BACKOFF, MAX_BACKOFF};
+use crate::spool::SpoolConfig;
+use crate::units::ByteSize;
+
+/// 默认的内存缓存上限
+pub const DEFAULT_LOG_BUFFER: ByteSize = ByteSize::mib(4);
+/// 默认的批次大小
+pub const DEFAULT_LOG_BATCH_SIZE: ByteSize = ByteSize::kib(64);
+/// 默认的攒批等待时长
+pub const DEFAULT_LOG_LINGER: Duration = Duration::from_millis(100);
+
+/// 已发出、尚未确认的批次数上限
+const IN_FLIGHT_BATCHES: usize = 4;
+/// 等待收集端回复 `RESUME` 的时长
+const RESUME_TIMEOUT: Duration = Duration::from_secs(10);
+/// 请求停止后等待已缓存记录确认的时长
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// 等待确认时检查停止请求与新记录的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct Buffer {
+    journal: Journal,
+    /// `run()` 已返回或生产者已丢弃
+    stopped: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    buffer: Mutex<Buffer>,
+    /// 记录被确认移除或生产者停止
+    space: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// 客户机一侧的日志生产者，见[模块文档](super)
+pub struct LogProducer {
+    config: ClientConfig,
+    source: String,
+    epoch: u64,
+    batch_size: ByteSize,
+    linger: Duration,
+    shared: Arc<Shared>,
+    shutdown: ShutdownHandle,
+}
+
+impl LogProducer {
+    /// 以来源名 `source` 向 `config` 指定的宿主机服务转发日志
+    pub fn new(config: ClientConfig, source: impl Into<String>) -> Self {
+        Self {
+            config,
+            source: source.into(),
+            epoch: crate::clock::now_nanos() ^ (u64::from(std::process::id()) << 32),
+            batch_size: DEFAULT_LOG_BATCH_SIZE,
+            linger: DEFAULT_LOG_LINGER,
+            shared: Arc::new(Shared {
+                buffer: Mutex::new(Buffer {
+                    journal: Journal::new(SpoolConfig::new(DEFAULT_LOG_BUFFER)),
+                    stopped: false,
+                }),
+                space: Condvar::new(),
+            }),
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+
+    /// 未确认记录的缓存：内存上限、超出后写入的临时文件及其上限，默认只用内存中的
+    /// [`DEFAULT_LOG_BUFFER`] 与不限大小的文件
+    pub fn with_buffer(self, buffer: SpoolConfig) -> Self {
+        self.shared.lock().journal.set_config(buffer);
+        self
+    }
+
+    /// 每批最多 `size` 字节（单条更大的记录单独成批）
+    pub fn with_batch_size(mut self, size: ByteSize) -> Self {
+        self.batch_size = size;
+        self
+    }
+
+    /// 不足一批时，最早的记录最多等待 `linger` 后发送
+    pub fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    /// 追加记录的句柄，可克隆后交给多个线程
+    pub fn writer(&self) -> LogWriter {
+        LogWriter {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// 用于请求停止的句柄
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 连接宿主机并转发记录，断线后按退避重连，直到请求停止。停止时已连接则最多等待
+    /// 数秒让缓存的记录得到确认；返回后 `append()` 返回 `BrokenPipe`
+    pub fn run(self) -> Result<()> {
+        self.config.validate()?;
+        if self.shared.lock().journal.config().memory().as_u64() == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "log buffer memory limit must be greater than zero",
+            ));
+        }
+        let mut delay = INITIAL_BACKOFF;
+        while !self.shutdown.is_shutdown() {
+            let mut client = VirgeClient::new(self.config.clone());
+            // 每轮最多等一个退避上限，以便及时响应停止请求
+            match client.connect_when_ready(MAX_BACKOFF) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            }
+            let mux = client.into_mux()?;
+            let before = self.shared.lock().journal.base();
+            let result = mux.channel(LOG_CHANNEL).and_then(|ch| self.session(&ch));
+            mux.close();
+            let Err(e) = result else { break };
+            warn!("Log producer {} lost the connection: {}", self.source, e);
+            // 有记录得到确认说明收集端正常，立即重连；否则逐次加长等待
+            if self.shared.lock().journal.base() > before {
+                delay = INITIAL_BACKOFF;
+            } else {
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_BACKOFF);
+            }
+        }
+        let pending = {
+            let buffer = self.shared.lock();
+            buffer.journal.end() - buffer.journal.base()
+        };
+        if pending > 0 {
+            warn!(
+                "Log producer {} stopped with {} unacknowledged records",
+                self.source, pending
+            );
+        }
+        info!("Log producer {} stopped", self.source);
+        Ok(())
+    }
+
+    /// 一次连接上的转发；请求停止且缓存的记录已确认（或等待超时）时返回 `Ok`
+    fn session(&self, channel: &Channel) -> Result<()> {
+        let hello = Message::Hello {
+            epoch: self.epoch,
+            source: self.source.clone(),
+        };
+        channel.send(&hello.encode())?;
+        let resume = match channel.recv_timeout(RESUME_TIMEOUT)? {
+            Some(message) => match Message::decode(&message)? {
+                Message::Resume(next) => next,
+                other => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("expected a log resume, got {:?}", other),
+                    ))
+                }
+            },
+            None => {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    "log collector did not answer",
+                ))
+            }
+        };
+        let mut cursor = self.acknowledge(resume)?;
+        info!(
+            "Log producer {} connected, resuming at {}",
+            self.source, cursor
+        );
+
+        // 已发出的批次的结束位置
+        let mut in_flight = VecDeque::new();
+        let mut drain_deadline = None;
+        loop {
+            if drain_deadline.is_none() && self.shutdown.is_shutdown() {
+                drain_deadline = Some(Instant::now() + DRAIN_TIMEOUT);
+            }
+            let draining = drain_deadline.is_some();
+            let mut wait = POLL_INTERVAL;
+            loop {
+                let buffer = self.shared.lock();
+                let journal = &buffer.journal;
+                while in_flight.front().is_some_and(|&end| end <= journal.base()) {
+                    in_flight.pop_front();
+                }
+                if in_flight.len() >= IN_FLIGHT_BATCHES {
+                    break;
+                }
+                let (count, bytes) = journal.span(cursor, self.batch_size.as_usize());
+                if count == 0 {
+                    break;
+                }
+                // 不足一批时等最早的记录攒够 linger 再发，停止时立即发
+                let full = cursor + count < journal.end() || bytes >= self.batch_size.as_usize();
+                if !full && !draining {
+                    let due = journal.appended(cursor).expect("record at cursor") + self.linger;
+                    let left = due.saturating_duration_since(Instant::now());
+                    if !left.is_zero() {
+                        wait = wait.min(left);
+                        break;
+                    }
+                }
+                let records = (cursor..cursor + count)
+                    .map(|offset| journal.read(offset))
+                    .collect::<Result<Vec<_>>>()?;
+                drop(buffer);
+                let batch = Message::Batch {
+                    first: cursor,
+                    records,
+                };
+                channel.send(&batch.encode())?;
+                cursor += count;
+                in_flight.push_back(cursor);
+            }
+
+            if let Some(deadline) = drain_deadline {
+                let buffer = self.shared.lock();
+                if buffer.journal.base() == buffer.journal.end() || Instant::now() >= deadline {
+                    return Ok(());
+                }
+            }
+            if let Some(message) = channel.recv_timeout(wait)? {
+                match Message::decode(&message)? {
+                    Message::Ack(next) if next <= cursor => {
+                        self.acknowledge(next)?;
+                    }
+                    other => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("unexpected log message {:?}", other),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    /// 移除收集端已处理的记录，返回应从哪里继续发送
+    fn acknowledge(&self, next: u64) -> Result<u64> {
+        let mut buffer = self.shared.lock();
+        if next > buffer.journal.end() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "log collector acknowledged {} beyond the last record {}",
+                    next,
+                    buffer.journal.end()
+                ),
+            ));
+        }
+        buffer.journal.ack(next)?;
+        self.shared.space.notify_all();
+        Ok(next.max(buffer.journal.base()))
+    }
+}
+
+impl fmt::Debug for LogProducer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogProducer")
+            .field("source", &self.source)
+            .field("batch_size", &self.batch_size)
+            .field("linger", &self.linger)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for LogProducer {
+    fn drop(&mut self) {
+        self.shared.lock().stopped = true;
+        self.shared.space.notify_all();
+    }
+}
+
+/// 追加日志记录，由 [`LogProducer::writer()`] 取得
+#[derive(Clone, Debug)]
+pub struct LogWriter {
+    shared: Arc<Shared>,
+}
+
+impl LogWriter {
+    /// 追加一条记录，返回其偏移量；缓存已满时阻塞，直到收集端确认了足够的记录
+    pub fn append(&self, record: &[u8]) -> Result<u64> {
+        let mut buffer = self.shared.lock();
+        loop {
+            if buffer.stopped {
+                return Err(stopped());
+            }
+            if buffer.journal.has_room(record.len()) {
+                return buffer.journal.push(record);
+            }
+            buffer = self
+                .shared
+                .space
+                .wait(buffer)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// 同 [`append()`](Self::append)，缓存已满时返回 `WouldBlock`
+    pub fn try_append(&self, record: &[u8]) -> Result<u64> {
+        let mut buffer = self.shared.lock();
+        if buffer.stopped {
+            return Err(stopped());
+        }
+        if !buffer.journal.has_room(record.len()) {
+            return Err(Error::new(ErrorKind::WouldBlock, "log buffer is full"));
+        }
+        buffer.journal.push(record)
+    }
+
+    /// 尚未得到确认的记录数
+    pub fn pending(&self) -> u64 {
+        let buffer = self.shared.lock();
+        buffer.journal.end() - buffer.journal.base()
+    }
+}
+
+fn stopped() -> Error {
+    Error::new(ErrorKind::BrokenPipe, "log producer stopped")
+}
+
+#[cfg(all(test, feature = "use-xtransport"))]
+mod tests {
+    use super::super::{LogCollector, LogRecord};
+    use super::*;
+    use crate::mux::testing::pair;
+    use std::sync::mpsc;
+
+    type Producing = thread::JoinHandle<(LogProducer, Result<()>)>;
+    type Serving = thread::JoinHandle<Result<()>>;
+
+    fn producer() -> LogProducer {
+        LogProducer::new(ClientConfig::default(), "guest.kernel")
+            .with_batch_size(ByteSize::b(64))
+            .with_linger(Duration::from_millis(5))
+    }
+
+    /// 一次连接：生产者与收集端各在一个线程，收集端处理的记录发往返回的接收端；
+    /// `fail_after` 条之后收集端的处理出错
+    fn connect(
+        producer: LogProducer,
+        collector: &LogCollector,
+        fail_after: Option<usize>,
+    ) -> (Producing, Serving, mpsc::Receiver<LogRecord>) {
+        let (host, guest) = pair();
+        // 先注册两端的通道，避免一端的第一条消息先于对端注册到达而被丢弃
+        let (host_channel, guest_channel) = (
+            host.channel(LOG_CHANNEL).unwrap(),
+            guest.channel(LOG_CHANNEL).unwrap(),
+        );
+        let (records, received) = mpsc::channel();
+        let collector = collector.clone();
+        let serve = thread::spawn(move || {
+            let _mux = host;
+            let mut seen = 0;
+            collector.serve(&host_channel, |record| {
+                if fail_after == Some(seen) {
+                    return Err(Error::other("disk full"));
+                }
+                seen += 1;
+                records.send(record).unwrap();
+                Ok(())
+            })
+        });
+        let produce = thread::spawn(move || {
+            let _mux = guest;
+            let result = producer.session(&guest_channel);
+            (producer, result)
+        });
+        (produce, serve, received)
+    }
+
+    #[test]
+    fn records_arrive_in_order_and_are_released_on_ack() {
+        let producer = producer();
+        let log = producer.writer();
+        let shutdown = producer.shutdown_handle();
+        for i in 0..50u32 {
+            assert_eq!(
+                log.append(format!("line {}", i).as_bytes()).unwrap(),
+                i as u64
+            );
+        }
+        let collector = LogCollector::new();
+        let (produce, serve, received) = connect(producer, &collector, None);
+        for i in 0..50u64 {
+            let record = received.recv().unwrap();
+            assert_eq!((record.offset, record.source.as_str()), (i, "guest.kernel"));
+            assert_eq!(record.data, format!("line {}", i).as_bytes());
+        }
+        log.append(b"late").unwrap();
+        assert_eq!(received.recv().unwrap().data, b"late");
+
+        shutdown.shutdown();
+        let (producer, result) = produce.join().unwrap();
+        result.unwrap();
+        assert_eq!(log.pending(), 0);
+        assert_eq!(collector.position("guest.kernel"), Some(51));
+        drop(producer);
+        assert_eq!(log.append(b"x").unwrap_err().kind(), ErrorKind::BrokenPipe);
+        serve.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn reconnect_resumes_without_gaps_or_duplicates() {
+        let producer = producer();
+        let log = producer.writer();
+        let shutdown = producer.shutdown_handle();
+        for i in 0..30u32 {
+            log.append(&i.to_be_bytes()).unwrap();
+        }
+        let collector = LogCollector::new();
+        let (produce, serve, received) = connect(producer, &collector, Some(12));
+        assert_eq!(serve.join().unwrap().unwrap_err().to_string(), "disk full");
+        let first: Vec<_> = received.try_iter().map(|r| r.offset).collect();
+        assert_eq!(first, (0..12).collect::<Vec<_>>());
+        let (producer, result) = produce.join().unwrap();
+        assert!(result.is_err());
+        assert!(log.pending() >= 18);
+
+        let (produce, serve, received) = connect(producer, &collector, None);
+        for i in 12..30u32 {
+            let record = received.recv().unwrap();
+            assert_eq!(record.offset, i as u64);
+            assert_eq!(record.data, i.to_be_bytes());
+        }
+        shutdown.shutdown();
+        produce.join().unwrap().1.unwrap();
+        assert_eq!(log.pending(), 0);
+        serve.join().unwrap().unwrap();
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn full_buffer_applies_backpressure() {
+        let producer = producer()
+            .with_buffer(SpoolConfig::new(ByteSize::b(8)).with_disk_limit(ByteSize::b(8)));
+        let log = producer.writer();
+        log.append(b"12345678").unwrap();
+        log.append(b"abcdefgh").unwrap();
+        let err = log.try_append(b"x").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+
+        // 确认之后阻塞的追加继续
+        let blocked = {
+            let log = log.clone();
+            thread::spawn(move || log.append(b"x"))
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert!(!blocked.is_finished());
+        producer.acknowledge(1).unwrap();
+        assert_eq!(blocked.join().unwrap().unwrap(), 2);
+        assert_eq!(log.pending(), 2);
+    }
+}
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 共享状态所用的锁与条件变量：平时来自 std，以 `--cfg loom` 编译时换成 [loom] 的模型实现
+//!
+//! loom 在模型中穷举各线程的交错，检查死锁、丢失的唤醒与关闭后仍阻塞的等待者。经本模块取得
+//! 原语的状态有连接复用的停止标志与发送队列、内存预算的总量与释放通知、处理函数并发名额与
+//! 幂等调用去重表，对应的模型测试以 `loom_` 开头，只在 `cfg(loom)` 下编译：
+//!
+//! ```sh
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib loom_
+//! ```
+//!
+//! loom 的原语只能在模型内使用，因此 `cfg(loom)` 下只运行这些测试。`LOOM_MAX_PREEMPTIONS`
+//! 限制每次交错中的抢占次数，默认不限；本地快速检查可设为 2 或 3。
+//!
+//! yamux 的 `Connection` 由 driver 任务独占，流的读写在 tokio 任务之间经 tokio 的锁与通道
+//! 交接。tokio 在 `--cfg loom` 下无法编译，这些任务的同步骨架（写锁、有界读队列、driver 的
+//! 停止与打开流请求）在 `src/loom/yamux_model.rs` 中以本模块的原语重写，模型覆盖发送、接收、断开与
+//! driver 退出之间的竞争，但不执行 yamux 的实际代码。
+//!
+//! [loom]: https://docs.rs/loom
+
+#[cfg(loom)]
+pub(crate) use ::loom::sync::atomic::{AtomicBool, AtomicUsize};
+#[cfg(loom)]
+pub(crate) use ::loom::sync::{Condvar, Mutex, MutexGuard};
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicUsize};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Condvar, Mutex, MutexGuard};
+
+#[cfg(all(test, loom))]
+mod yamux_model;
@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! yamux 连接各任务之间交接的 loom 模型
+//!
+//! tokio 在 `--cfg loom` 下无法编译，`YamuxTransportHandler` 的任务不能直接放进模型。这里按
+//! `transport/yamux_impl/transfer_handler.rs` 的结构以 loom 原语重写各任务的同步骨架：
+//!
+//! - 写半部分 `Writer`：发送任务、读任务回应控制帧与 `disconnect()` 的 flush + close 共用一把锁；
+//! - 读任务：把消息帧放入有界队列，队列满时等待；回应 ping 失败或读到连接结束时把错误放入
+//!   队列后退出，`Reader` 被丢弃后放入失败，同样退出；
+//! - driver：处理 `open_stream()` 的请求，直到连接关闭（即 driver 的停止条件）；退出时丢弃
+//!   请求通道与未处理的请求，等待中的 `open_stream()` 得到错误。
+//!
+//! tokio 的 mpsc / oneshot 以 [`Channel`] 表示，语义取与本模型相关的部分。修改那边的关闭顺序、
+//! 加锁范围或队列时，需同步修改这里。
+
+use crate::loom::{AtomicBool, Condvar, Mutex};
+use ::loom::sync::Arc;
+use ::loom::thread;
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+
+/// tokio 的 mpsc（`capacity` 为 1 时兼作 oneshot）：接收端丢弃后发送失败，排队的项一并
+/// 丢弃；发送端关闭后接收端取完剩余项得到 `None`
+struct Channel<T> {
+    state: Mutex<ChannelState<T>>,
+    changed: Condvar,
+    capacity: usize,
+}
+
+struct ChannelState<T> {
+    items: VecDeque<T>,
+    sender: bool,
+    receiver: bool,
+}
+
+impl<T> Channel<T> {
+    fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(Channel {
+            state: Mutex::new(ChannelState {
+                items: VecDeque::new(),
+                sender: true,
+                receiver: true,
+            }),
+            changed: Condvar::new(),
+            capacity,
+        })
+    }
+
+    /// 队列满时等待，接收端已丢弃时返回 `Err`
+    fn send(&self, item: T) -> Result<(), T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if !state.receiver {
+                return Err(item);
+            }
+            if state.items.len() < self.capacity {
+                state.items.push_back(item);
+                self.changed.notify_all();
+                return Ok(());
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn recv(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.changed.notify_all();
+                return Some(item);
+            }
+            if !state.sender {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn try_recv(&self) -> Option<T> {
+        let item = self.state.lock().unwrap().items.pop_front();
+        if item.is_some() {
+            self.changed.notify_all();
+        }
+        item
+    }
+
+    fn close_sender(&self) {
+        self.state.lock().unwrap().sender = false;
+        self.changed.notify_all();
+    }
+
+    fn close_receiver(&self) {
+        // 先取出再丢弃：项的析构可能关闭别的通道，不能在持锁时进行
+        let dropped = {
+            let mut state = self.state.lock().unwrap();
+            state.receiver = false;
+            std::mem::take(&mut state.items)
+        };
+        self.changed.notify_all();
+        drop(dropped);
+    }
+}
+
+/// `Open`：driver 以它送回打开的流，未回复就被丢弃时请求方得到错误
+struct Reply(Arc<Channel<()>>);
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        self.0.close_sender();
+    }
+}
+
+/// 写半部分：`closed` 之后写入失败
+#[derive(Default)]
+struct WriteHalf {
+    sent: Vec<&'static [u8]>,
+    closed: bool,
+}
+
+impl WriteHalf {
+    fn write(&mut self, frame: &'static [u8]) -> Result<(), ()> {
+        if self.closed {
+            return Err(());
+        }
+        self.sent.push(frame);
+        Ok(())
+    }
+}
+
+type Writer = Arc<Mutex<WriteHalf>>;
+/// 读队列中的项：消息帧或读任务退出前放入的错误
+type Frame = Result<&'static [u8], ()>;
+
+/// 对端发来的包
+#[derive(Clone, Copy)]
+enum Incoming {
+    Data(&'static [u8]),
+    Ping,
+}
+
+/// 对应 `read_loop()`：依次处理 `incoming`，之后连接结束
+fn read_loop(writer: Writer, frames: Arc<Channel<Frame>>, incoming: &[Incoming]) {
+    for packet in incoming {
+        let frame = match *packet {
+            Incoming::Data(data) => Ok(data),
+            // 读任务在写锁下回应 ping，写失败时读取出错
+            Incoming::Ping => match writer.lock().unwrap().write(b"pong") {
+                Ok(()) => continue,
+                Err(()) => Err(()),
+            },
+        };
+        let failed = frame.is_err();
+        if frames.send(frame).is_err() || failed {
+            frames.close_sender();
+            return;
+        }
+    }
+    let _ = frames.send(Err(()));
+    frames.close_sender();
+}
+
+/// 对应 `drive()`：按请求打开新流，直到连接关闭
+fn drive(closed: &AtomicBool, requests: &Channel<Reply>) {
+    while !closed.load(Ordering::Acquire) {
+        match requests.try_recv() {
+            Some(reply) => {
+                let _ = reply.0.send(());
+            }
+            // poll_fn 返回 Pending，等待下一次唤醒
+            None => thread::yield_now(),
+        }
+    }
+    requests.close_receiver();
+}
+
+/// 对应 `open_stream()`：driver 已退出、或退出时丢弃了请求，返回 `Err`
+fn open_stream(requests: &Channel<Reply>) -> Result<(), ()> {
+    let opened = Channel::new(1);
+    requests.send(Reply(opened.clone())).map_err(|_| ())?;
+    opened.recv().ok_or(())
+}
+
+/// 对端关闭连接（driver 的停止条件）与 `open_stream()` 并发：请求方要么得到流，要么得到
+/// 错误，不会一直等待已经退出的 driver
+#[test]
+fn loom_yamux_open_stream_races_driver_stop() {
+    ::loom::model(|| {
+        let closed = Arc::new(AtomicBool::new(false));
+        let requests = Channel::new(usize::MAX);
+        let driver = {
+            let (closed, requests) = (closed.clone(), requests.clone());
+            thread::spawn(move || drive(&closed, &requests))
+        };
+        let opener = {
+            let requests = requests.clone();
+            thread::spawn(move || open_stream(&requests))
+        };
+        closed.store(true, Ordering::Release);
+        let _ = opener.join().unwrap();
+        driver.join().unwrap();
+        // driver 退出后的请求直接失败
+        assert!(open_stream(&requests).is_err());
+    });
+}
+
+/// 发送任务、读任务回应 ping 与 `disconnect()` 的关闭争用写锁：不会死锁；返回成功的写入都在
+/// 关闭前，回应失败的读任务把错误放入队列后退出
+#[test]
+fn loom_yamux_send_and_pong_race_disconnect() {
+    ::loom::model(|| {
+        let writer: Writer = Arc::new(Mutex::new(WriteHalf::default()));
+        let frames = Channel::new(1);
+        let reader = {
+            let (writer, frames) = (writer.clone(), frames.clone());
+            thread::spawn(move || read_loop(writer, frames, &[Incoming::Ping]))
+        };
+        let sender = {
+            let writer = writer.clone();
+            thread::spawn(move || writer.lock().unwrap().write(b"a").is_ok())
+        };
+
+        // disconnect()：flush + close，之后丢弃 Reader
+        writer.lock().unwrap().closed = true;
+        let sent = sender.join().unwrap();
+        assert_eq!(frames.recv(), Some(Err(())));
+        frames.close_receiver();
+        reader.join().unwrap();
+
+        let mut half = writer.lock().unwrap();
+        assert_eq!(half.sent.contains(&&b"a"[..]), sent);
+        assert!(half.write(b"late").is_err());
+    });
+}
+
+/// 读队列已满、读任务等待放入时 `disconnect()` 丢弃 `Reader`：读任务必须被唤醒并退出，
+/// 而不是一直等待永远不会再取的队列
+#[test]
+fn loom_yamux_disconnect_releases_read_loop_on_full_queue() {
+    ::loom::model(|| {
+        let writer: Writer = Arc::new(Mutex::new(WriteHalf::default()));
+        let frames = Channel::new(1);
+        let reader = {
+            let frames = frames.clone();
+            let incoming = [Incoming::Data(b"a"), Incoming::Data(b"b")];
+            thread::spawn(move || read_loop(writer, frames, &incoming))
+        };
+        assert_eq!(frames.recv(), Some(Ok(&b"a"[..])));
+        frames.close_receiver();
+        reader.join().unwrap();
+    });
+}
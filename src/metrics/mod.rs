@@ -0,0 +1,545 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 指标转发：客户机定期上报计数器与仪表，宿主机汇总供采集器抓取
+//!
+//! 客户机在 [`Metrics`] 中登记计数器（只增）与仪表（可设为任意值），[`MetricsReporter`]
+//! 在后台连接宿主机，按间隔发送全部指标的快照。宿主机的 [`MetricsCollector`] 保存每个
+//! 来源最新的快照，`render()` 输出 Prometheus 文本格式，可直接作为 `/metrics` 的响应。
+//!
+//! ```ignore
+//! // 客户机
+//! let reporter = MetricsReporter::new(ClientConfig::new(2, 5002, 4096, false), "guest-1");
+//! let requests = reporter.metrics().counter("http_requests_total")?;
+//! let memory = reporter.metrics().gauge("memory_used_bytes")?;
+//! thread::spawn(move || reporter.run());
+//! requests.inc();
+//! memory.set(123456.0);
+//!
+//! // 宿主机
+//! let collector = MetricsCollector::new();
+//! collector.serve(&mux.channel(METRICS_CHANNEL)?, |_| Ok(()))?;   // 每个连接一个线程
+//! let body = collector.render();                                   // 采集器抓取时
+//! ```
+//!
+//! 收集端确认每个快照。通道拥塞、已有两个快照未确认时，上报方不再排队新的快照，而是把
+//! 上报间隔加倍（降采样，最多到 `with_max_downsample()` 倍），确认跟上后再逐步减半。
+//! 计数器是累计值、仪表是最新值，跳过的快照只降低分辨率，不会丢失计数。
+//!
+//! 连接经 [`mux`](crate::mux) 复用，使用通道 [`METRICS_CHANNEL`]，每条消息以 1 字节类型开头
+//! （整数均为大端）：
+//!
+//! ```text
+//! HELLO    | source                                          上报方 → 收集端，连上后第一条
+//! SNAPSHOT | seq(8) | unix_ms(8) | interval_ms(4) | sample*   上报方 → 收集端
+//! ACK      | seq(8)                                          收集端 → 上报方
+//!
+//! sample = kind(1) | name_len(1) | name | value(8)            kind 0 为计数器（u64），1 为仪表（f64）
+//! ```
+
+mod reporter;
+
+pub use reporter::MetricsReporter;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write as _};
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::*;
+
+use crate::mux::Channel;
+
+/// 承载指标的通道号
+pub const METRICS_CHANNEL: u32 = 4;
+/// 指标名的最大长度
+pub const MAX_METRIC_NAME_LEN: usize = 128;
+
+const HELLO: u8 = 0;
+const SNAPSHOT: u8 = 1;
+const ACK: u8 = 2;
+
+const COUNTER: u8 = 0;
+const GAUGE: u8 = 1;
+
+/// 一个指标的取值
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    /// 计数器的累计值
+    Counter(u64),
+    /// 仪表的当前值
+    Gauge(f64),
+}
+
+/// 快照中的一个指标
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub value: Value,
+}
+
+/// 一个来源在某一时刻的全部指标
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricsSnapshot {
+    /// 上报方的来源名
+    pub source: String,
+    /// 上报方的快照序号，每次连接从 0 开始
+    pub seq: u64,
+    /// 上报方取快照的时间
+    pub timestamp: SystemTime,
+    /// 与上一个快照的间隔；降采样时大于配置的上报间隔
+    pub interval: Duration,
+    pub samples: Vec<Sample>,
+}
+
+/// 通道上的一条消息
+#[derive(Debug, PartialEq)]
+enum Message {
+    Hello(String),
+    Snapshot(MetricsSnapshot),
+    Ack(u64),
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Message::Hello(source) => {
+                buf.push(HELLO);
+                buf.extend_from_slice(source.as_bytes());
+            }
+            Message::Snapshot(snapshot) => {
+                let unix_ms = snapshot
+                    .timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                let interval_ms = snapshot.interval.as_millis().min(u32::MAX as u128) as u32;
+                buf.push(SNAPSHOT);
+                buf.extend_from_slice(&snapshot.seq.to_be_bytes());
+                buf.extend_from_slice(&unix_ms.to_be_bytes());
+                buf.extend_from_slice(&interval_ms.to_be_bytes());
+                for sample in &snapshot.samples {
+                    let (kind, value) = match sample.value {
+                        Value::Counter(n) => (COUNTER, n),
+                        Value::Gauge(x) => (GAUGE, x.to_bits()),
+                    };
+                    buf.push(kind);
+                    buf.push(sample.name.len() as u8);
+                    buf.extend_from_slice(sample.name.as_bytes());
+                    buf.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+            Message::Ack(seq) => {
+                buf.push(ACK);
+                buf.extend_from_slice(&seq.to_be_bytes());
+            }
+        }
+        buf
+    }
+
+    /// 解析一条消息；快照的来源名由调用方填入
+    fn decode(message: &[u8]) -> Result<Self> {
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("metrics {}", what));
+        let (&kind, body) = message
+            .split_first()
+            .ok_or_else(|| invalid("message is empty"))?;
+        let mut reader = Reader(body);
+        let message = match kind {
+            HELLO => Message::Hello(
+                String::from_utf8(body.to_vec()).map_err(|_| invalid("source is not UTF-8"))?,
+            ),
+            ACK => {
+                let seq = reader.u64()?;
+                reader.finish()?;
+                Message::Ack(seq)
+            }
+            SNAPSHOT => {
+                let seq = reader.u64()?;
+                let timestamp = UNIX_EPOCH + Duration::from_millis(reader.u64()?);
+                let interval = Duration::from_millis(u64::from(reader.u32()?));
+                let mut samples = Vec::new();
+                while !reader.0.is_empty() {
+                    let kind = reader.take(1)?[0];
+                    let len = reader.take(1)?[0] as usize;
+                    let name = std::str::from_utf8(reader.take(len)?)
+                        .map_err(|_| invalid("name is not UTF-8"))?
+                        .to_string();
+                    let bits = reader.u64()?;
+                    let value = match kind {
+                        COUNTER => Value::Counter(bits),
+                        GAUGE => Value::Gauge(f64::from_bits(bits)),
+                        _ => return Err(invalid(&format!("sample kind {} is unknown", kind))),
+                    };
+                    samples.push(Sample { name, value });
+                }
+                Message::Snapshot(MetricsSnapshot {
+                    source: String::new(),
+                    seq,
+                    timestamp,
+                    interval,
+                    samples,
+                })
+            }
+            _ => return Err(invalid(&format!("message type {} is unexpected", kind))),
+        };
+        Ok(message)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "metrics message is truncated",
+            ));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn finish(&self) -> Result<()> {
+        if !self.0.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "metrics message has trailing bytes",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 指标名须符合 Prometheus 的约定：`[a-zA-Z_:][a-zA-Z0-9_:]*`
+fn validate_name(name: &str) -> Result<()> {
+    let valid = name.len() <= MAX_METRIC_NAME_LEN
+        && name.chars().enumerate().all(|(i, c)| {
+            c.is_ascii_alphabetic() || c == '_' || c == ':' || (i > 0 && c.is_ascii_digit())
+        })
+        && !name.is_empty();
+    if !valid {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid metric name {:?}", name),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+enum Metric {
+    Counter(Counter),
+    Gauge(Gauge),
+}
+
+/// 只增的计数器，可克隆后在多个线程中使用
+#[derive(Clone, Debug, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// 可设为任意值的仪表，可克隆后在多个线程中使用
+#[derive(Clone, Debug, Default)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// 客户机上登记的指标，可克隆后共享
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    metrics: Arc<Mutex<BTreeMap<String, Metric>>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, Metric>> {
+        self.metrics.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 登记计数器 `name`，已登记时返回同一个计数器；名字已登记为仪表时返回 `AlreadyExists`
+    pub fn counter(&self, name: &str) -> Result<Counter> {
+        validate_name(name)?;
+        match self
+            .lock()
+            .entry(name.to_string())
+            .or_insert_with(|| Metric::Counter(Counter::default()))
+        {
+            Metric::Counter(counter) => Ok(counter.clone()),
+            Metric::Gauge(_) => Err(kind_conflict(name, "gauge")),
+        }
+    }
+
+    /// 登记仪表 `name`，已登记时返回同一个仪表；名字已登记为计数器时返回 `AlreadyExists`
+    pub fn gauge(&self, name: &str) -> Result<Gauge> {
+        validate_name(name)?;
+        match self
+            .lock()
+            .entry(name.to_string())
+            .or_insert_with(|| Metric::Gauge(Gauge::default()))
+        {
+            Metric::Gauge(gauge) => Ok(gauge.clone()),
+            Metric::Counter(_) => Err(kind_conflict(name, "counter")),
+        }
+    }
+
+    /// 全部指标的当前值，按名字排序
+    pub fn samples(&self) -> Vec<Sample> {
+        self.lock()
+            .iter()
+            .map(|(name, metric)| Sample {
+                name: name.clone(),
+                value: match metric {
+                    Metric::Counter(counter) => Value::Counter(counter.get()),
+                    Metric::Gauge(gauge) => Value::Gauge(gauge.get()),
+                },
+            })
+            .collect()
+    }
+}
+
+fn kind_conflict(name: &str, existing: &str) -> Error {
+    Error::new(
+        ErrorKind::AlreadyExists,
+        format!("metric {:?} is already registered as a {}", name, existing),
+    )
+}
+
+/// 宿主机一侧的指标收集端，可在多个连接线程间共享（克隆后共享同一份快照）
+#[derive(Clone, Default)]
+pub struct MetricsCollector {
+    latest: Arc<Mutex<HashMap<String, MetricsSnapshot>>>,
+}
+
+impl MetricsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, MetricsSnapshot>> {
+        self.latest.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 来源 `source` 最新的快照
+    pub fn latest(&self, source: &str) -> Option<MetricsSnapshot> {
+        self.lock().get(source).cloned()
+    }
+
+    /// 移除来源 `source` 的快照，如客户机已销毁时
+    pub fn forget(&self, source: &str) -> Option<MetricsSnapshot> {
+        self.lock().remove(source)
+    }
+
+    /// 在 `channel` 上接收一个上报方的快照：保存为该来源最新的快照，交给 `sink` 处理后确认。
+    /// 连接关闭时返回 `Ok`，`sink` 返回错误时停止并返回该错误
+    pub fn serve<F>(&self, channel: &Channel, mut sink: F) -> Result<()>
+    where
+        F: FnMut(&MetricsSnapshot) -> Result<()>,
+    {
+        let source = match Message::decode(&channel.recv()?)? {
+            Message::Hello(source) => source,
+            other => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("expected a metrics hello, got {:?}", other),
+                ))
+            }
+        };
+        info!("Collecting metrics from {}", source);
+        loop {
+            let message = match channel.recv() {
+                Ok(message) => message,
+                Err(e) if crate::rpc::is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let mut snapshot = match Message::decode(&message)? {
+                Message::Snapshot(snapshot) => snapshot,
+                other => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("expected a metrics snapshot, got {:?}", other),
+                    ))
+                }
+            };
+            snapshot.source = source.clone();
+            let seq = snapshot.seq;
+            self.lock().insert(source.clone(), snapshot.clone());
+            sink(&snapshot)?;
+            channel.send(&Message::Ack(seq).encode())?;
+        }
+    }
+
+    /// 以 Prometheus 文本格式输出各来源最新的快照，来源名作为 `source` 标签
+    pub fn render(&self) -> String {
+        let latest = self.lock();
+        let mut by_name: BTreeMap<&str, Vec<(&str, Value)>> = BTreeMap::new();
+        for snapshot in latest.values() {
+            for sample in &snapshot.samples {
+                by_name
+                    .entry(&sample.name)
+                    .or_default()
+                    .push((&snapshot.source, sample.value));
+            }
+        }
+        let mut out = String::new();
+        for (name, mut values) in by_name {
+            values.sort_by(|a, b| a.0.cmp(b.0));
+            let kind = match values[0].1 {
+                Value::Counter(_) => "counter",
+                Value::Gauge(_) => "gauge",
+            };
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (source, value) in values {
+                let source = source
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n");
+                let _ = match value {
+                    Value::Counter(n) => writeln!(out, "{}{{source=\"{}\"}} {}", name, source, n),
+                    Value::Gauge(x) => writeln!(out, "{}{{source=\"{}\"}} {}", name, source, x),
+                };
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Debug for MetricsCollector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsCollector")
+            .field("sources", &self.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let snapshot = MetricsSnapshot {
+            source: String::new(),
+            seq: 9,
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            interval: Duration::from_secs(20),
+            samples: vec![
+                Sample {
+                    name: "requests_total".into(),
+                    value: Value::Counter(42),
+                },
+                Sample {
+                    name: "load".into(),
+                    value: Value::Gauge(-0.25),
+                },
+            ],
+        };
+        for message in [
+            Message::Hello("guest-1".into()),
+            Message::Snapshot(snapshot),
+            Message::Ack(9),
+        ] {
+            assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+        }
+        for bad in [vec![], vec![ACK, 0], vec![SNAPSHOT, 0, 0], vec![7]] {
+            let err = Message::decode(&bad).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn registry_checks_names_and_kinds() {
+        let metrics = Metrics::new();
+        let requests = metrics.counter("requests_total").unwrap();
+        metrics.counter("requests_total").unwrap().add(2);
+        requests.inc();
+        metrics.gauge("mem:used").unwrap().set(1.5);
+        assert_eq!(
+            metrics.gauge("requests_total").unwrap_err().kind(),
+            ErrorKind::AlreadyExists
+        );
+        for bad in ["", "9lives", "has space", "dash-ed", &"x".repeat(129)] {
+            assert_eq!(
+                metrics.counter(bad).unwrap_err().kind(),
+                ErrorKind::InvalidInput
+            );
+        }
+        assert_eq!(
+            metrics.samples(),
+            vec![
+                Sample {
+                    name: "mem:used".into(),
+                    value: Value::Gauge(1.5),
+                },
+                Sample {
+                    name: "requests_total".into(),
+                    value: Value::Counter(3),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn render_groups_samples_by_name() {
+        let collector = MetricsCollector::new();
+        for (source, n) in [("guest-2", 5), ("guest-\"1\"", 7)] {
+            collector.lock().insert(
+                source.to_string(),
+                MetricsSnapshot {
+                    source: source.to_string(),
+                    seq: 0,
+                    timestamp: SystemTime::now(),
+                    interval: Duration::from_secs(10),
+                    samples: vec![Sample {
+                        name: "requests_total".into(),
+                        value: Value::Counter(n),
+                    }],
+                },
+            );
+        }
+        assert_eq!(
+            collector.render(),
+            "# TYPE requests_total counter\n\
+             requests_total{source=\"guest-\\\"1\\\"\"} 7\n\
+             requests_total{source=\"guest-2\"} 5\n"
+        );
+        assert!(collector.forget("guest-2").is_some());
+        assert!(collector.latest("guest-2").is_none());
+    }
+}
@@ -0,0 +1,278 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 客户机一侧：定期上报快照，拥塞时降采样
+
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::*;
+
+use super::{Message, Metrics, MetricsSnapshot, METRICS_CHANNEL};
+use crate::agent::ShutdownHandle;
+use crate::client::{ClientConfig, VirgeClient};
+use crate::mux::Channel;
+use crate::retry::{INITIAL_BACKOFF, MAX_BACKOFF};
+
+/// 默认的上报间隔
+pub const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(10);
+/// 默认的最大降采样倍数
+pub const DEFAULT_MAX_DOWNSAMPLE: u32 = 8;
+
+/// 未确认的快照达到此数时视为拥塞
+const MAX_IN_FLIGHT: u64 = 2;
+/// 等待确认时检查停止请求的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 客户机一侧的指标上报方，见[模块文档](super)
+pub struct MetricsReporter {
+    config: ClientConfig,
+    source: String,
+    metrics: Metrics,
+    interval: Duration,
+    max_downsample: u32,
+    shutdown: ShutdownHandle,
+}
+
+impl MetricsReporter {
+    /// 以来源名 `source` 向 `config` 指定的宿主机服务上报指标
+    pub fn new(config: ClientConfig, source: impl Into<String>) -> Self {
+        Self {
+            config,
+            source: source.into(),
+            metrics: Metrics::new(),
+            interval: DEFAULT_METRICS_INTERVAL,
+            max_downsample: DEFAULT_MAX_DOWNSAMPLE,
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+
+    /// 上报的指标；克隆后交给各子系统登记
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// 上报已有的指标登记表，替换 `new()` 创建的
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// 上报间隔
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// 拥塞时上报间隔最多放大到 `factor` 倍，为 1 时不降采样
+    pub fn with_max_downsample(mut self, factor: u32) -> Self {
+        self.max_downsample = factor;
+        self
+    }
+
+    /// 用于请求停止的句柄
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 连接宿主机并按间隔上报，断线后按退避重连，直到请求停止
+    pub fn run(self) -> Result<()> {
+        self.config.validate()?;
+        if self.interval.is_zero() || self.max_downsample == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "metrics interval and downsample factor must be greater than zero",
+            ));
+        }
+        let mut delay = INITIAL_BACKOFF;
+        while !self.shutdown.is_shutdown() {
+            let mut client = VirgeClient::new(self.config.clone());
+            // 每轮最多等一个退避上限，以便及时响应停止请求
+            match client.connect_when_ready(MAX_BACKOFF) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            }
+            let mux = client.into_mux()?;
+            let result = mux
+                .channel(METRICS_CHANNEL)
+                .and_then(|channel| self.session(&channel));
+            mux.close();
+            let Err(e) = result else { break };
+            warn!(
+                "Metrics reporter {} lost the connection: {}",
+                self.source, e
+            );
+            thread::sleep(delay);
+            delay = (delay * 2).min(MAX_BACKOFF);
+        }
+        info!("Metrics reporter {} stopped", self.source);
+        Ok(())
+    }
+
+    /// 一次连接上的上报；请求停止时返回 `Ok`
+    fn session(&self, channel: &Channel) -> Result<()> {
+        channel.send(&Message::Hello(self.source.clone()).encode())?;
+        info!("Metrics reporter {} connected", self.source);
+        let mut seq = 0u64;
+        let mut in_flight = 0u64;
+        let mut stride = 1u32;
+        let mut last_sent: Option<Instant> = None;
+        let mut next_due = Instant::now();
+        while !self.shutdown.is_shutdown() {
+            let now = Instant::now();
+            if now >= next_due {
+                if in_flight >= MAX_IN_FLIGHT {
+                    if stride < self.max_downsample {
+                        stride = (stride * 2).min(self.max_downsample);
+                        info!(
+                            "Metrics channel of {} congested, reporting every {:?}",
+                            self.source,
+                            self.interval * stride
+                        );
+                    }
+                } else {
+                    let snapshot = MetricsSnapshot {
+                        source: self.source.clone(),
+                        seq,
+                        timestamp: SystemTime::now(),
+                        interval: last_sent.map_or(self.interval, |at| now - at),
+                        samples: self.metrics.samples(),
+                    };
+                    channel.send(&Message::Snapshot(snapshot).encode())?;
+                    seq += 1;
+                    in_flight += 1;
+                    last_sent = Some(now);
+                }
+                next_due = now + self.interval * stride;
+            }
+
+            let wait = next_due
+                .saturating_duration_since(Instant::now())
+                .min(POLL_INTERVAL);
+            if let Some(message) = channel.recv_timeout(wait)? {
+                match Message::decode(&message)? {
+                    Message::Ack(acked) if acked < seq => {
+                        in_flight = in_flight.min(seq - acked - 1);
+                        if in_flight == 0 && stride > 1 {
+                            stride /= 2;
+                        }
+                    }
+                    other => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidData,
+                            format!("unexpected metrics message {:?}", other),
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MetricsReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsReporter")
+            .field("source", &self.source)
+            .field("interval", &self.interval)
+            .field("max_downsample", &self.max_downsample)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "use-xtransport"))]
+mod tests {
+    use super::super::{MetricsCollector, Value};
+    use super::*;
+    use crate::mux::testing::pair;
+
+    const INTERVAL: Duration = Duration::from_millis(20);
+
+    fn reporter() -> MetricsReporter {
+        MetricsReporter::new(ClientConfig::default(), "guest-1")
+            .with_interval(INTERVAL)
+            .with_max_downsample(4)
+    }
+
+    #[test]
+    fn snapshots_reach_the_collector() {
+        let reporter = reporter();
+        let requests = reporter.metrics().counter("requests_total").unwrap();
+        reporter.metrics().gauge("load").unwrap().set(0.5);
+        requests.add(3);
+        let shutdown = reporter.shutdown_handle();
+        let (host, guest) = pair();
+        let (host_channel, guest_channel) = (
+            host.channel(METRICS_CHANNEL).unwrap(),
+            guest.channel(METRICS_CHANNEL).unwrap(),
+        );
+        let report = thread::spawn(move || reporter.session(&guest_channel));
+
+        let collector = MetricsCollector::new();
+        let serving = collector.clone();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let serve = thread::spawn(move || {
+            serving.serve(&host_channel, |snapshot| {
+                tx.send(snapshot.clone()).unwrap();
+                Ok(())
+            })
+        });
+        let first = rx.recv().unwrap();
+        assert_eq!(first.source, "guest-1");
+        assert_eq!(first.samples[1].value, Value::Counter(3));
+        requests.inc();
+        let counted = rx
+            .iter()
+            .find(|snapshot| snapshot.samples[1].value == Value::Counter(4))
+            .unwrap();
+        assert!(counted.seq > first.seq);
+        assert!(collector
+            .render()
+            .contains("requests_total{source=\"guest-1\"} 4\n"));
+
+        shutdown.shutdown();
+        report.join().unwrap().unwrap();
+        guest.close();
+        serve.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn unacknowledged_snapshots_downsample_the_reports() {
+        let reporter = reporter();
+        let shutdown = reporter.shutdown_handle();
+        let (host, guest) = pair();
+        let channel = host.channel(METRICS_CHANNEL).unwrap();
+        let guest_channel = guest.channel(METRICS_CHANNEL).unwrap();
+        let report = thread::spawn(move || reporter.session(&guest_channel));
+        let next = || match Message::decode(&channel.recv().unwrap()).unwrap() {
+            Message::Snapshot(snapshot) => snapshot,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(
+            Message::decode(&channel.recv().unwrap()).unwrap(),
+            Message::Hello("guest-1".into())
+        );
+
+        // 不确认：两个快照之后不再上报
+        assert_eq!(next().seq, 0);
+        assert_eq!(next().seq, 1);
+        assert_eq!(channel.recv_timeout(INTERVAL * 8).unwrap(), None);
+
+        // 确认后恢复上报，间隔已放大
+        channel.send(&Message::Ack(1).encode()).unwrap();
+        let resumed = next();
+        assert_eq!(resumed.seq, 2);
+        assert!(resumed.interval >= INTERVAL * 4, "{:?}", resumed.interval);
+        // 确认跟上后倍数减半
+        let after = next();
+        assert_eq!(after.seq, 3);
+        assert!(after.interval >= INTERVAL * 2, "{:?}", after.interval);
+
+        shutdown.shutdown();
+        report.join().unwrap().unwrap();
+    }
+}
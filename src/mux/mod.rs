@@ -0,0 +1,653 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 连接复用
+//!
+//! 宿主机上的代理通常有多个子系统（日志、指标、文件传输等）与同一个客户机通信，
+//! 每个子系统各开一条 vsock 连接既占端口，也让重连、认证各做一遍。`into_mux()`
+//! 把一条已建立的连接交给后台线程，进程内的使用方按通道号注册逻辑通道，消息在
+//! 共享的连接上按通道号分发。两端都使用 `Mux`，通道号的用途由双方约定。
+//!
+//! ```ignore
+//! let mux = client.into_mux()?;
+//! let logs = mux.channel(1)?;
+//! let metrics = mux.channel(2)?;
+//! thread::spawn(move || logs.send(b"started"));
+//! let sample = metrics.recv()?;
+//! ```
+//!
+//! 每条消息前加上通道号：
+//!
+//! ```text
+//! channel(4, 大端) | payload
+//! ```
+//!
+//! 收到未注册通道的消息时记录警告并丢弃。各通道的接收队列不设上限，使用方应及时
+//! 接收；发送经各通道的有界队列交给后台线程，队列满时 `send()` 阻塞。后台线程按通道的
+//! 权重（[`Channel::set_weight()`]）轮流发送各队列的消息，大批量传输不会让其他通道的
+//! 消息一直排在后面；[`Mux::set_bandwidth()`] 另外限制发送速率。连接断开或 `Mux`
+//! 关闭后，各通道取完已收到的消息，之后的收发返回连接关闭的原因。
+
+mod scheduler;
+#[cfg(all(test, feature = "use-xtransport"))]
+pub(crate) mod testing;
+pub(crate) mod wake;
+
+pub use scheduler::{Bandwidth, DEFAULT_CHANNEL_WEIGHT};
+#[cfg(feature = "use-xtransport")]
+pub(crate) use wake::poll_readable;
+pub(crate) use wake::Wake;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use log::*;
+
+use crate::client::VirgeClient;
+use crate::error::ConnContext;
+use crate::logging::log_event;
+use crate::loom::{AtomicBool, Mutex};
+use crate::server::VirgeServer;
+use crate::stats::SendBacklog;
+use crate::threads;
+use scheduler::Scheduler;
+
+const CHANNEL_LEN: usize = 4;
+
+/// 复用线程使用的连接操作
+pub(crate) trait Endpoint: Send + 'static {
+    fn send_frame(&mut self, frame: Vec<u8>) -> Result<()>;
+    fn flush_frames(&mut self) -> Result<()>;
+    fn recv_frame(&mut self) -> Result<Vec<u8>>;
+    fn wait_readable(&mut self, wake: Option<&Wake>, timeout: Option<Duration>) -> Result<bool>;
+    fn close(&mut self);
+}
+
+impl Endpoint for VirgeClient {
+    fn send_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        self.send(frame).map(drop)
+    }
+
+    fn flush_frames(&mut self) -> Result<()> {
+        self.flush()
+    }
+
+    fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        self.recv()
+    }
+
+    fn wait_readable(&mut self, wake: Option<&Wake>, timeout: Option<Duration>) -> Result<bool> {
+        VirgeClient::wait_readable(self, wake, timeout)
+    }
+
+    fn close(&mut self) {
+        if let Err(e) = self.disconnect() {
+            debug!("Mux disconnect failed: {}", e);
+        }
+    }
+}
+
+impl Endpoint for VirgeServer {
+    fn send_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        self.send(frame).map(drop)
+    }
+
+    fn flush_frames(&mut self) -> Result<()> {
+        self.flush()
+    }
+
+    fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        self.recv()
+    }
+
+    fn wait_readable(&mut self, wake: Option<&Wake>, timeout: Option<Duration>) -> Result<bool> {
+        VirgeServer::wait_readable(self, wake, timeout)
+    }
+
+    fn close(&mut self) {
+        if let Err(e) = self.disconnect() {
+            debug!("Mux disconnect failed: {}", e);
+        }
+    }
+}
+
+/// `Mux` 与各通道共享的状态
+struct Shared {
+    conn: ConnContext,
+    channels: Mutex<HashMap<u32, Sender<Vec<u8>>>>,
+    outgoing: Scheduler,
+    bandwidth: Mutex<Option<Bandwidth>>,
+    /// 连接关闭的原因
+    closed: Mutex<Option<(ErrorKind, String)>>,
+    stop: AtomicBool,
+    wake: Wake,
+}
+
+impl Shared {
+    fn new(conn: ConnContext) -> Result<Self> {
+        Ok(Self {
+            conn,
+            channels: Mutex::default(),
+            outgoing: Scheduler::default(),
+            bandwidth: Mutex::default(),
+            closed: Mutex::default(),
+            stop: AtomicBool::new(false),
+            wake: Wake::new()?,
+        })
+    }
+
+    fn closed_error(&self) -> Option<Error> {
+        let closed = self.closed.lock().unwrap_or_else(PoisonError::into_inner);
+        closed
+            .as_ref()
+            .map(|(kind, reason)| Error::new(*kind, format!("mux closed: {}", reason)))
+    }
+
+    fn bandwidth(&self) -> Option<Bandwidth> {
+        self.bandwidth
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn closed_or(&self, kind: ErrorKind) -> Error {
+        self.closed_error()
+            .unwrap_or_else(|| Error::new(kind, "mux closed"))
+    }
+
+    /// 按通道号把收到的消息放入对应通道
+    fn route(&self, mut frame: Vec<u8>) -> Result<()> {
+        if frame.len() < CHANNEL_LEN {
+            return Err(Error::new(ErrorKind::InvalidData, "mux frame too short"));
+        }
+        let id = u32::from_be_bytes(frame[..CHANNEL_LEN].try_into().unwrap());
+        frame.drain(..CHANNEL_LEN);
+        let channels = self.channels.lock().unwrap_or_else(PoisonError::into_inner);
+        match channels.get(&id) {
+            // 通道刚被 drop 时接收端已不在，消息同样丢弃
+            Some(tx) => drop(tx.send(frame)),
+            None => log_event!(
+                Level::Warn,
+                "mux message for unregistered channel",
+                conn_id = self.conn.conn_id,
+                channel = id,
+                bytes = frame.len()
+            ),
+        }
+        Ok(())
+    }
+
+    /// 记录关闭原因并断开所有通道的接收队列
+    fn close(&self, reason: &Error) {
+        self.closed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_insert_with(|| (reason.kind(), reason.to_string()));
+        self.channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+/// 在一条连接上复用多个逻辑通道，由 `into_mux()` 创建
+pub struct Mux {
+    shared: Arc<Shared>,
+    driver: Option<JoinHandle<()>>,
+}
+
+impl Mux {
+    pub(crate) fn spawn<E: Endpoint>(conn: ConnContext, link: E) -> Result<Self> {
+        let shared = Arc::new(Shared::new(conn)?);
+        let driver = {
+            let shared = shared.clone();
+            threads::spawn(format!("mux-{}", conn.conn_id), move || {
+                drive(link, &shared)
+            })?
+        };
+        info!("Mux started on {}", conn);
+        Ok(Self {
+            shared,
+            driver: Some(driver),
+        })
+    }
+
+    /// 注册通道 `id`，同一通道号同时只能注册一次，否则返回 `AlreadyExists`；
+    /// 通道 drop 后可重新注册
+    pub fn channel(&self, id: u32) -> Result<Channel> {
+        if let Some(e) = self.shared.closed_error() {
+            return Err(e);
+        }
+        let mut channels = self
+            .shared
+            .channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if channels.contains_key(&id) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("mux channel {} is already registered", id),
+            ));
+        }
+        let (tx, incoming) = channel();
+        channels.insert(id, tx);
+        Ok(Channel {
+            sender: ChannelSender {
+                id,
+                shared: self.shared.clone(),
+            },
+            incoming,
+        })
+    }
+
+    /// 限制本连接的发送速率，`None` 取消限制；同一个 [`Bandwidth`] 可交给多个连接共享
+    pub fn set_bandwidth(&self, bandwidth: Option<Bandwidth>) {
+        *self
+            .shared
+            .bandwidth
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = bandwidth;
+        self.shared.wake.wake();
+    }
+
+    /// 各通道已 `send()`、尚未交给连接发出的消息数与最早一条的等待时长
+    pub fn send_backlog(&self) -> SendBacklog {
+        self.shared.outgoing.backlog()
+    }
+
+    /// 连接是否已关闭
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed_error().is_some()
+    }
+
+    /// 发出已排队的消息后断开连接，等待后台线程退出。drop 时同样关闭，但不等待
+    pub fn close(mut self) {
+        self.stop();
+        if let Some(driver) = self.driver.take() {
+            drop(driver.join());
+        }
+    }
+
+    fn stop(&self) {
+        self.shared.stop.store(true, Ordering::Release);
+        self.shared.wake.wake();
+    }
+}
+
+impl fmt::Debug for Mux {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mux")
+            .field("conn", &self.shared.conn)
+            .field("closed", &self.is_closed())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// 复用连接上的一个逻辑通道，可移交给其他线程；drop 时注销
+pub struct Channel {
+    sender: ChannelSender,
+    incoming: Receiver<Vec<u8>>,
+}
+
+impl Channel {
+    /// 通道号
+    pub fn id(&self) -> u32 {
+        self.sender.id
+    }
+
+    /// 在本通道上发送一条消息；消息交给后台线程即返回
+    pub fn send(&self, data: &[u8]) -> Result<()> {
+        self.sender.send(data)
+    }
+
+    /// 本通道发送时的权重（默认 [`DEFAULT_CHANNEL_WEIGHT`]），见 [`ChannelSender::set_weight()`]
+    pub fn set_weight(&self, weight: u32) {
+        self.sender.set_weight(weight)
+    }
+
+    /// 本通道的发送端，可交给其他线程，与接收并行发送
+    pub fn sender(&self) -> ChannelSender {
+        self.sender.clone()
+    }
+
+    /// 接收本通道的下一条消息
+    pub fn recv(&self) -> Result<Vec<u8>> {
+        self.incoming
+            .recv()
+            .map_err(|_| self.sender.shared.closed_or(ErrorKind::NotConnected))
+    }
+
+    /// 接收本通道的下一条消息，`timeout` 内没有消息时返回 `None`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        match self.incoming.recv_timeout(timeout) {
+            Ok(data) => Ok(Some(data)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(self.sender.shared.closed_or(ErrorKind::NotConnected))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel")
+            .field("id", &self.sender.id)
+            .field("conn", &self.sender.shared.conn)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.sender
+            .shared
+            .channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.sender.id);
+        self.sender.shared.outgoing.reset_weight(self.sender.id);
+    }
+}
+
+/// 通道的发送端，由 [`Channel::sender()`] 取得；通道 drop 后仍可发送，只是不再接收对端的回复
+#[derive(Clone)]
+pub struct ChannelSender {
+    id: u32,
+    shared: Arc<Shared>,
+}
+
+impl ChannelSender {
+    /// 通道号
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// 在通道上发送一条消息；消息交给后台线程即返回
+    pub fn send(&self, data: &[u8]) -> Result<()> {
+        if let Some(e) = self.shared.closed_error() {
+            return Err(e);
+        }
+        let mut frame = Vec::with_capacity(CHANNEL_LEN + data.len());
+        frame.extend_from_slice(&self.id.to_be_bytes());
+        frame.extend_from_slice(data);
+        if !self.shared.outgoing.push(self.id, frame) {
+            return Err(self.shared.closed_or(ErrorKind::NotConnected));
+        }
+        self.shared.wake.wake();
+        Ok(())
+    }
+
+    /// 通道发送时的权重：各通道都有消息待发送时，发出的字节数与权重成正比，0 视为 1。
+    /// 通道注销后恢复默认权重
+    pub fn set_weight(&self, weight: u32) {
+        self.shared.outgoing.set_weight(self.id, weight);
+    }
+}
+
+impl fmt::Debug for ChannelSender {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChannelSender")
+            .field("id", &self.id)
+            .field("conn", &self.shared.conn)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 后台线程：连接出错或 `Mux` 关闭后断开连接，并让各通道返回关闭原因
+fn drive<E: Endpoint>(mut link: E, shared: &Shared) {
+    let reason = match pump(&mut link, shared) {
+        Ok(()) => Error::new(ErrorKind::NotConnected, "closed locally"),
+        Err(e) => {
+            if !crate::rpc::is_closed(&e) {
+                warn!("Mux on {} failed: {}", shared.conn, e);
+            }
+            e
+        }
+    };
+    link.close();
+    shared.outgoing.close();
+    shared.close(&reason);
+    info!("Mux on {} stopped: {}", shared.conn, reason);
+}
+
+/// 交替发出排队的消息与分发收到的消息，两者都没有（或受速率限制）时阻塞等待；
+/// 每发出一条消息都先收完已到达的消息
+fn pump<E: Endpoint>(link: &mut E, shared: &Shared) -> Result<()> {
+    loop {
+        let stopping = shared.stop.load(Ordering::Acquire);
+        if stopping {
+            // 先拒绝之后的消息再发出队列中的，`send()` 成功的消息不会在关闭时被丢下
+            shared.outgoing.close();
+        }
+        // 关闭时不再限速，尽快发出已排队的消息
+        let bandwidth = shared.bandwidth().filter(|_| !stopping);
+        let mut sent = false;
+        let delay = loop {
+            let delay = bandwidth.as_ref().and_then(Bandwidth::delay);
+            if delay.is_some() {
+                break delay;
+            }
+            let Some(frame) = shared.outgoing.pop() else {
+                break None;
+            };
+            if let Some(bandwidth) = &bandwidth {
+                bandwidth.charge(frame.len());
+            }
+            link.send_frame(frame)?;
+            sent = true;
+            // 两个方向都有大量消息时边发边收，避免两端都阻塞在发送上
+            while link.wait_readable(None, Some(Duration::ZERO))? {
+                shared.route(link.recv_frame()?)?;
+            }
+        };
+        if sent {
+            link.flush_frames()?;
+        }
+        if stopping {
+            return Ok(());
+        }
+        if link.wait_readable(Some(&shared.wake), delay)? {
+            shared.route(link.recv_frame()?)?;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "use-xtransport"))]
+mod tests {
+    use super::testing::pair;
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn channels_share_one_connection() {
+        let (host, guest) = pair();
+        let (logs, metrics) = (host.channel(1).unwrap(), host.channel(2).unwrap());
+        let (guest_logs, guest_metrics) = (guest.channel(1).unwrap(), guest.channel(2).unwrap());
+
+        let senders: Vec<_> = [(logs, 1u8), (metrics, 2u8)]
+            .into_iter()
+            .map(|(channel, tag)| {
+                thread::spawn(move || {
+                    for i in 0..100u8 {
+                        channel.send(&[tag, i]).unwrap();
+                    }
+                    channel
+                })
+            })
+            .collect();
+        for (channel, tag) in [(&guest_metrics, 2u8), (&guest_logs, 1u8)] {
+            for i in 0..100u8 {
+                assert_eq!(channel.recv().unwrap(), [tag, i]);
+            }
+        }
+        let logs = senders.into_iter().next().unwrap().join().unwrap();
+        // 发送端交给另一个线程，接收端仍可使用
+        let reply = guest_logs.sender();
+        thread::spawn(move || reply.send(b"ack").unwrap());
+        assert_eq!(logs.recv().unwrap(), b"ack");
+        assert_eq!(logs.recv_timeout(Duration::from_millis(10)).unwrap(), None);
+    }
+
+    #[test]
+    fn bandwidth_limits_the_send_rate() {
+        let (host, guest) = pair();
+        let channel = host.channel(1).unwrap();
+        let peer = guest.channel(1).unwrap();
+        host.set_bandwidth(Some(Bandwidth::new(crate::units::ByteSize::kib(100))));
+        let started = std::time::Instant::now();
+        for _ in 0..4 {
+            channel.send(&[0; 10 * 1024]).unwrap();
+        }
+        for _ in 0..4 {
+            peer.recv().unwrap();
+        }
+        // 第一条立即发出，之后每 10 KiB 约 100 毫秒
+        assert!(
+            started.elapsed() >= Duration::from_millis(290),
+            "{:?}",
+            started.elapsed()
+        );
+
+        host.set_bandwidth(None);
+        channel.send(b"unlimited").unwrap();
+        assert_eq!(
+            peer.recv_timeout(Duration::from_millis(50))
+                .unwrap()
+                .unwrap(),
+            b"unlimited"
+        );
+    }
+
+    #[test]
+    fn bulk_traffic_in_both_directions_does_not_stall() {
+        let (host, guest) = pair();
+        let workers: Vec<_> = [&host, &guest]
+            .into_iter()
+            .map(|mux| {
+                let channel = mux.channel(1).unwrap();
+                let sender = channel.sender();
+                let sending = thread::spawn(move || {
+                    for _ in 0..64 {
+                        sender.send(&[7; 64 * 1024]).unwrap();
+                    }
+                });
+                thread::spawn(move || {
+                    sending.join().unwrap();
+                    (0..64)
+                        .map(|_| channel.recv().unwrap().len())
+                        .sum::<usize>()
+                })
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), 64 * 64 * 1024);
+        }
+    }
+
+    #[test]
+    fn unregistered_and_duplicate_channels() {
+        let (host, guest) = pair();
+        let first = host.channel(7).unwrap();
+        let err = host.channel(7).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        drop(first);
+        let seven = host.channel(7).unwrap();
+
+        let receiver = guest.channel(7).unwrap();
+        // 对端没有通道 9，这条消息被丢弃
+        host.channel(9).unwrap().send(b"lost").unwrap();
+        seven.send(b"kept").unwrap();
+        assert_eq!(receiver.recv().unwrap(), b"kept");
+    }
+
+    #[test]
+    fn closing_one_side_fails_the_peer_channels() {
+        let (host, guest) = pair();
+        let channel = host.channel(1).unwrap();
+        let peer = guest.channel(1).unwrap();
+        peer.send(b"last words").unwrap();
+        guest.close();
+        assert!(peer.send(b"late").is_err());
+
+        // 关闭前发出的消息仍可取出
+        assert_eq!(channel.recv().unwrap(), b"last words");
+        let err = channel.recv().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(host.is_closed());
+        assert!(channel.send(b"x").is_err());
+        assert!(host.channel(2).is_err());
+    }
+
+    /// 只记录发出的消息、永远不可读的连接，供 loom 模型驱动复用线程
+    #[cfg(loom)]
+    struct Recorder {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+    }
+
+    #[cfg(loom)]
+    impl Endpoint for Recorder {
+        fn send_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+            self.sent.lock().unwrap().push(frame);
+            Ok(())
+        }
+
+        fn flush_frames(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn recv_frame(&mut self) -> Result<Vec<u8>> {
+            unreachable!("recorder is never readable")
+        }
+
+        fn wait_readable(&mut self, _: Option<&Wake>, _: Option<Duration>) -> Result<bool> {
+            // 复用线程在这里轮询停止标志，让出以便 loom 调度其他线程
+            ::loom::thread::yield_now();
+            Ok(false)
+        }
+
+        fn close(&mut self) {}
+    }
+
+    /// 通道发送与 `Mux` 关闭并发：复用线程必须退出；`send()` 成功的消息都在断开连接前
+    /// 发出，没赶上的发送返回错误，而不是被悄悄丢弃
+    #[cfg(loom)]
+    #[test]
+    fn loom_stop_sends_every_accepted_frame() {
+        ::loom::model(|| {
+            let shared = Arc::new(Shared::new(ConnContext::default()).unwrap());
+            let sent = Arc::new(Mutex::new(Vec::new()));
+            let driver = {
+                let shared = shared.clone();
+                let link = Recorder { sent: sent.clone() };
+                ::loom::thread::spawn(move || drive(link, &shared))
+            };
+            let sender = ChannelSender {
+                id: 1,
+                shared: shared.clone(),
+            };
+            let producer = ::loom::thread::spawn(move || sender.send(b"a").is_ok());
+            drop(Mux {
+                shared: shared.clone(),
+                driver: None,
+            });
+            let accepted = producer.join().unwrap();
+            driver.join().unwrap();
+            assert_eq!(sent.lock().unwrap().len(), usize::from(accepted));
+            assert!(shared.closed_error().is_some());
+        });
+    }
+}
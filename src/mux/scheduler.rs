@@ -0,0 +1,342 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 发送调度：各通道按权重分享连接的发送带宽
+//!
+//! 每个通道有自己的有界发送队列，后台线程以加权的差额轮询（deficit round robin）从各队列
+//! 取消息：每轮每个有消息的通道得到 `权重 × QUANTUM` 字节的额度，额度够发下一条消息时发出。
+//! 大批量传输只占自己的份额，其他通道上的小消息不必排在它的全部消息之后。
+//! [`Bandwidth`] 另外限制一条或多条连接合计的发送速率。
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::loom::{Condvar, Mutex, MutexGuard};
+use crate::sources::{self, Clock};
+use crate::stats::SendBacklog;
+use crate::units::ByteSize;
+
+/// 通道的默认权重
+pub const DEFAULT_CHANNEL_WEIGHT: u32 = 1;
+
+/// 每个通道待发送的消息数上限，队列满时 `send()` 阻塞
+#[cfg(not(loom))]
+const SEND_QUEUE_DEPTH: usize = 16;
+/// loom 模型中取 1，一条消息就能让发送方阻塞
+#[cfg(loom)]
+const SEND_QUEUE_DEPTH: usize = 1;
+/// 权重为 1 的通道每轮得到的字节数
+const QUANTUM: usize = 64 * 1024;
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// 消息与它入队的时刻
+    frames: VecDeque<(Instant, Vec<u8>)>,
+    deficit: usize,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    queues: HashMap<u32, Queue>,
+    weights: HashMap<u32, u32>,
+    /// 有消息的通道，按轮询顺序
+    active: VecDeque<u32>,
+    closed: bool,
+}
+
+/// 各通道的发送队列
+#[derive(Debug, Default)]
+pub(super) struct Scheduler {
+    state: Mutex<State>,
+    space: Condvar,
+}
+
+impl Scheduler {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 把通道 `id` 的消息放入队列，队列满时等待；已关闭时返回 `false`
+    pub(super) fn push(&self, id: u32, frame: Vec<u8>) -> bool {
+        let mut state = self.lock();
+        loop {
+            if state.closed {
+                return false;
+            }
+            let queue = state.queues.entry(id).or_default();
+            if queue.frames.len() < SEND_QUEUE_DEPTH {
+                queue.frames.push_back((Instant::now(), frame));
+                if queue.frames.len() == 1 {
+                    state.active.push_back(id);
+                }
+                return true;
+            }
+            state = self
+                .space
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// 按权重取下一条要发送的消息
+    pub(super) fn pop(&self) -> Option<Vec<u8>> {
+        let mut state = self.lock();
+        let state = &mut *state;
+        loop {
+            let id = *state.active.front()?;
+            let weight = state
+                .weights
+                .get(&id)
+                .copied()
+                .unwrap_or(DEFAULT_CHANNEL_WEIGHT);
+            let queue = state.queues.get_mut(&id).expect("active channel queue");
+            let len = queue.frames.front().map_or(0, |(_, frame)| frame.len());
+            if queue.deficit < len {
+                // 额度不够：补充本轮额度，轮到下一个通道
+                queue.deficit += weight as usize * QUANTUM;
+                state.active.rotate_left(1);
+                continue;
+            }
+            queue.deficit -= len;
+            let frame = queue.frames.pop_front().map(|(_, frame)| frame);
+            if queue.frames.is_empty() {
+                // 空闲的通道不积累额度
+                queue.deficit = 0;
+                state.active.pop_front();
+            }
+            self.space.notify_all();
+            return frame;
+        }
+    }
+
+    /// 各通道队列中尚未取出的消息
+    pub(super) fn backlog(&self) -> SendBacklog {
+        let state = self.lock();
+        let queues = state.queues.values();
+        SendBacklog {
+            messages: queues.clone().map(|queue| queue.frames.len()).sum(),
+            oldest: queues
+                .filter_map(|queue| queue.frames.front())
+                .map(|(queued, _)| queued.elapsed())
+                .max(),
+        }
+    }
+
+    /// 设置通道 `id` 的权重，0 视为 1
+    pub(super) fn set_weight(&self, id: u32, weight: u32) {
+        self.lock().weights.insert(id, weight.max(1));
+    }
+
+    /// 通道注销时恢复默认权重
+    pub(super) fn reset_weight(&self, id: u32) {
+        self.lock().weights.remove(&id);
+    }
+
+    /// 拒绝之后的消息，唤醒等待队列空间的发送方；已排队的消息仍可取出
+    pub(super) fn close(&self) {
+        self.lock().closed = true;
+        self.space.notify_all();
+    }
+}
+
+/// 发送速率上限，克隆后共享同一个额度：交给多个 `Mux` 时限制它们合计的发送速率，
+/// 模拟宿主机上的一块网卡；各连接内的通道仍按权重分享
+#[derive(Clone)]
+pub struct Bandwidth {
+    rate: u64,
+    /// 下一次可以发送的时刻
+    next_free: Arc<Mutex<Instant>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Bandwidth {
+    /// 每秒最多发送 `rate` 字节
+    pub fn new(rate: ByteSize) -> Self {
+        let clock = sources::clock();
+        Self {
+            rate: rate.as_u64().max(1),
+            next_free: Arc::new(Mutex::new(clock.now())),
+            clock,
+        }
+    }
+
+    /// 以 `clock` 计算发送时刻，默认为 [`sources::clock()`]；复用线程等待额度时仍按
+    /// 真实时间轮询。应在克隆之前设置，已有的克隆不受影响
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            rate: self.rate,
+            next_free: Arc::new(Mutex::new(clock.now())),
+            clock,
+        }
+    }
+
+    /// 每秒字节数
+    pub fn rate(&self) -> ByteSize {
+        ByteSize::b(self.rate)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Instant> {
+        self.next_free
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 还需等待多久才能发送；可以发送时返回 `None`
+    pub(super) fn delay(&self) -> Option<Duration> {
+        let wait = self.lock().saturating_duration_since(self.clock.now());
+        (!wait.is_zero()).then_some(wait)
+    }
+
+    /// 记录发出了 `len` 字节。检查与记录之间不加锁，多条连接同时发送时可能短暂超出一条消息
+    pub(super) fn charge(&self, len: usize) {
+        let cost = Duration::from_secs_f64(len as f64 / self.rate as f64);
+        let mut next_free = self.lock();
+        *next_free = (*next_free).max(self.clock.now()) + cost;
+    }
+}
+
+impl fmt::Debug for Bandwidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bandwidth")
+            .field("rate", &self.rate())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::ManualClock;
+    use std::thread;
+
+    fn drain(scheduler: &Scheduler) -> Vec<u8> {
+        std::iter::from_fn(|| scheduler.pop())
+            .map(|frame| frame[0])
+            .collect()
+    }
+
+    #[test]
+    fn small_messages_are_not_starved_behind_bulk() {
+        let scheduler = Scheduler::default();
+        for _ in 0..SEND_QUEUE_DEPTH {
+            scheduler.push(1, vec![1; QUANTUM * 4]);
+        }
+        scheduler.push(2, vec![2; 100]);
+        let order = drain(&scheduler);
+        let urgent = order.iter().position(|&tag| tag == 2).unwrap();
+        assert!(urgent <= 1, "{:?}", order);
+        assert_eq!(order.len(), SEND_QUEUE_DEPTH + 1);
+    }
+
+    #[test]
+    fn backlog_counts_queued_messages_and_the_oldest_wait() {
+        let scheduler = Scheduler::default();
+        assert_eq!(scheduler.backlog(), SendBacklog::default());
+        scheduler.push(1, vec![1]);
+        thread::sleep(Duration::from_millis(20));
+        scheduler.push(2, vec![2]);
+        scheduler.push(2, vec![2]);
+        let backlog = scheduler.backlog();
+        assert_eq!(backlog.messages, 3);
+        assert!(backlog.oldest.unwrap() >= Duration::from_millis(20));
+        drain(&scheduler);
+        assert_eq!(scheduler.backlog(), SendBacklog::default());
+    }
+
+    #[test]
+    fn channels_share_by_weight() {
+        let scheduler = Scheduler::default();
+        scheduler.set_weight(2, 3);
+        scheduler.set_weight(3, 0);
+        for _ in 0..SEND_QUEUE_DEPTH {
+            for id in [1, 2, 3] {
+                scheduler.push(id, vec![id as u8; QUANTUM]);
+            }
+        }
+        let order = drain(&scheduler);
+        let first: Vec<_> = order[..10].to_vec();
+        let count = |tag| first.iter().filter(|&&t| t == tag).count();
+        assert_eq!((count(1), count(2), count(3)), (2, 6, 2), "{:?}", order);
+
+        scheduler.reset_weight(2);
+        scheduler.push(1, vec![1; QUANTUM]);
+        scheduler.push(2, vec![2; QUANTUM]);
+        scheduler.push(2, vec![2; QUANTUM]);
+        assert_eq!(drain(&scheduler), [1, 2, 2]);
+    }
+
+    #[test]
+    fn full_queues_block_until_sent_or_closed() {
+        let scheduler = Arc::new(Scheduler::default());
+        for _ in 0..SEND_QUEUE_DEPTH {
+            assert!(scheduler.push(1, vec![1]));
+        }
+        let blocked = {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || scheduler.push(1, vec![2]))
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert!(!blocked.is_finished());
+        scheduler.pop().unwrap();
+        assert!(blocked.join().unwrap());
+
+        let blocked = {
+            let scheduler = scheduler.clone();
+            thread::spawn(move || scheduler.push(1, vec![3]))
+        };
+        thread::sleep(Duration::from_millis(20));
+        scheduler.close();
+        assert!(!blocked.join().unwrap());
+        // 关闭前排队的消息仍可取出
+        assert_eq!(drain(&scheduler).len(), SEND_QUEUE_DEPTH);
+    }
+
+    #[test]
+    fn bandwidth_is_shared_between_clones() {
+        let bandwidth = Bandwidth::new(ByteSize::kib(1));
+        let other = bandwidth.clone();
+        assert_eq!(bandwidth.delay(), None);
+        bandwidth.charge(512);
+        let delay = other.delay().unwrap();
+        assert!(
+            delay > Duration::from_millis(400) && delay <= Duration::from_millis(500),
+            "{:?}",
+            delay
+        );
+        other.charge(512);
+        assert!(bandwidth.delay().unwrap() > Duration::from_millis(900));
+    }
+
+    #[test]
+    fn bandwidth_delay_follows_its_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let bandwidth = Bandwidth::new(ByteSize::kib(1)).with_clock(clock.clone());
+        bandwidth.charge(1024);
+        assert_eq!(bandwidth.delay(), Some(Duration::from_secs(1)));
+        clock.advance(Duration::from_millis(750));
+        assert_eq!(bandwidth.delay(), Some(Duration::from_millis(250)));
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(bandwidth.delay(), None);
+    }
+
+    /// 复用线程退出时关闭队列，阻塞在满队列上的发送方必须被唤醒并得到 `false`，
+    /// 而不是一直等待永远不会再取的队列；关闭前排队的消息仍可取出
+    #[cfg(loom)]
+    #[test]
+    fn loom_close_wakes_blocked_senders() {
+        ::loom::model(|| {
+            let scheduler = Arc::new(Scheduler::default());
+            assert!(scheduler.push(1, vec![1]));
+            let sender = {
+                let scheduler = scheduler.clone();
+                ::loom::thread::spawn(move || scheduler.push(1, vec![2]))
+            };
+            scheduler.close();
+            assert!(!sender.join().unwrap());
+            assert_eq!(drain(&scheduler), [1]);
+        });
+    }
+}
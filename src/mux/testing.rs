@@ -0,0 +1,57 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 测试用的复用连接：以长度前缀分帧的 Unix socket 代替 vsock 连接
+
+use std::io::{Read, Result, Write};
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use super::{Endpoint, Mux, Wake};
+use crate::error::ConnContext;
+
+pub(crate) struct PipeLink(UnixStream);
+
+impl Endpoint for PipeLink {
+    fn send_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        self.0.write_all(&(frame.len() as u32).to_be_bytes())?;
+        self.0.write_all(&frame)
+    }
+
+    fn flush_frames(&mut self) -> Result<()> {
+        self.0.flush()
+    }
+
+    fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        let mut len = [0u8; 4];
+        self.0.read_exact(&mut len)?;
+        let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+        self.0.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+
+    fn wait_readable(&mut self, wake: Option<&Wake>, timeout: Option<Duration>) -> Result<bool> {
+        super::poll_readable(self.0.as_fd(), wake, timeout)
+    }
+
+    fn close(&mut self) {
+        drop(self.0.shutdown(std::net::Shutdown::Both));
+    }
+}
+
+/// 一对互连的连接
+pub(crate) fn link_pair() -> (PipeLink, PipeLink) {
+    let (a, b) = UnixStream::pair().unwrap();
+    (PipeLink(a), PipeLink(b))
+}
+
+/// 一对互连的 `Mux`：(宿主机侧, 客户机侧)
+pub(crate) fn pair() -> (Mux, Mux) {
+    let (a, b) = link_pair();
+    (
+        Mux::spawn(ConnContext::new(3, 1), a).unwrap(),
+        Mux::spawn(ConnContext::new(2, 1), b).unwrap(),
+    )
+}
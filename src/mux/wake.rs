@@ -0,0 +1,45 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 等待连接可读
+//!
+//! 复用线程阻塞在“连接可读或被唤醒”上，通道有消息要发送时唤醒它；`Protocol` 则在
+//! “连接可读或超时”上等待。唤醒不会丢失：线程还没开始等待时，下一次等待立即返回。
+//! xtransport 使用 [`crate::poll`] 的 `Wake`，与连接的 fd 一起 poll；yamux 的读任务已把帧放入
+//! 队列，用 `Notify` 与队列一起等待。
+
+#[cfg(feature = "use-xtransport")]
+pub(crate) use crate::poll::Wake;
+
+/// 等待 `fd` 可读（含对端关闭、出错）、被 `wake` 唤醒或超过 `timeout`，可读时返回 `true`
+#[cfg(feature = "use-xtransport")]
+pub(crate) fn poll_readable(
+    fd: std::os::fd::BorrowedFd<'_>,
+    wake: Option<&Wake>,
+    timeout: Option<std::time::Duration>,
+) -> std::io::Result<bool> {
+    crate::poll::wait(fd, libc::POLLIN, wake, timeout)
+}
+
+#[cfg(feature = "use-yamux")]
+#[derive(Debug, Default)]
+pub(crate) struct Wake {
+    notify: tokio::sync::Notify,
+}
+
+#[cfg(feature = "use-yamux")]
+impl Wake {
+    pub(crate) fn new() -> std::io::Result<Self> {
+        Ok(Self::default())
+    }
+
+    pub(crate) fn wake(&self) {
+        self.notify.notify_one();
+    }
+
+    /// 下一次唤醒；此前的唤醒已存为许可时立即完成
+    pub(crate) async fn woken(&self) {
+        self.notify.notified().await
+    }
+}
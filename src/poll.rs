@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 以 `poll(2)` 等待 fd 就绪
+//!
+//! 同步接口中阻塞等待的线程（XTransport 的复用线程、WebSocket 桥的浏览器一侧、客户机代理的
+//! 监听循环）都经这里等待：[`wait()`] 等待一个 fd 或 [`Wake`] 被唤醒，[`wait_all()`] 等待一组
+//! fd。被信号打断时按剩余时间重试，而不是当作超时返回。
+
+use std::io::{ErrorKind, Read, Result, Write};
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
+use std::time::{Duration, Instant};
+
+/// 用一对 Unix socket 唤醒阻塞在 [`wait()`] 上的线程。唤醒不会丢失：线程还没开始等待时，
+/// 下一次等待立即返回
+#[derive(Debug)]
+#[cfg_attr(
+    not(any(feature = "use-xtransport", feature = "websocket")),
+    allow(dead_code)
+)]
+pub(crate) struct Wake {
+    rx: UnixStream,
+    tx: UnixStream,
+}
+
+#[cfg_attr(
+    not(any(feature = "use-xtransport", feature = "websocket")),
+    allow(dead_code)
+)]
+impl Wake {
+    pub(crate) fn new() -> Result<Self> {
+        let (rx, tx) = UnixStream::pair()?;
+        rx.set_nonblocking(true)?;
+        tx.set_nonblocking(true)?;
+        Ok(Self { rx, tx })
+    }
+
+    pub(crate) fn wake(&self) {
+        // 缓冲区满说明已有未处理的唤醒
+        if let Err(e) = (&self.tx).write(&[1]) {
+            if e.kind() != ErrorKind::WouldBlock {
+                log::warn!("Failed to wake waiting thread: {}", e);
+            }
+        }
+    }
+
+    fn drain(&self) {
+        let mut buf = [0u8; 64];
+        while matches!((&self.rx).read(&mut buf), Ok(n) if n > 0) {}
+    }
+}
+
+/// 等待 `fd` 上的 `events`（含对端关闭、出错）、被 `wake` 唤醒或超过 `timeout`，`fd` 就绪时
+/// 返回 `true`。`events` 为 0 时只等待唤醒或超时
+#[cfg_attr(
+    not(any(feature = "use-xtransport", feature = "websocket")),
+    allow(dead_code)
+)]
+pub(crate) fn wait(
+    fd: BorrowedFd<'_>,
+    events: libc::c_short,
+    wake: Option<&Wake>,
+    timeout: Option<Duration>,
+) -> Result<bool> {
+    let pollfd = |fd, events| libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    let mut fds = [
+        pollfd(if events == 0 { -1 } else { fd.as_raw_fd() }, events),
+        pollfd(wake.map_or(-1, |w| w.rx.as_raw_fd()), libc::POLLIN),
+    ];
+    wait_all(&mut fds, timeout)?;
+    if let Some(wake) = wake.filter(|_| fds[1].revents != 0) {
+        wake.drain();
+    }
+    Ok(fds[0].revents != 0)
+}
+
+/// 等待 `fds` 中任一项就绪或超过 `timeout`，就绪情况见各项的 `revents`；fd 为 -1 的项被忽略
+pub(crate) fn wait_all(fds: &mut [libc::pollfd], timeout: Option<Duration>) -> Result<()> {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        // 向上取整到毫秒，避免不足 1ms 的剩余时间变成 0 而空转
+        let ms = deadline.map_or(-1, |d| {
+            let left = d.saturating_duration_since(Instant::now());
+            left.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+        });
+        // SAFETY: fds 是有效的 pollfd 数组，调用期间不被移动
+        let n = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) };
+        if n >= 0 {
+            return Ok(());
+        }
+        let e = std::io::Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsFd;
+
+    #[test]
+    fn wake_before_wait_is_not_lost() {
+        let wake = Wake::new().unwrap();
+        let (a, mut b) = UnixStream::pair().unwrap();
+        wake.wake();
+        wake.wake();
+        assert!(!wait(a.as_fd(), libc::POLLIN, Some(&wake), None).unwrap());
+        b.write_all(b"x").unwrap();
+        assert!(wait(a.as_fd(), libc::POLLIN, Some(&wake), None).unwrap());
+    }
+
+    #[test]
+    fn wait_times_out_without_data() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let started = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert!(!wait(a.as_fd(), libc::POLLIN, None, Some(timeout)).unwrap());
+        assert!(started.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn wait_all_reports_each_ready_fd() {
+        let (a, _a) = UnixStream::pair().unwrap();
+        let (b, mut peer) = UnixStream::pair().unwrap();
+        peer.write_all(b"x").unwrap();
+        let mut fds = [a.as_raw_fd(), b.as_raw_fd()].map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        });
+        wait_all(&mut fds, Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(fds[0].revents, 0);
+        assert_ne!(fds[1].revents, 0);
+    }
+}
@@ -0,0 +1,221 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 端口探测
+//!
+//! 部署工具在切换流量前，可在客户机内用 [`probe()`] 确认宿主机服务已经可达：只建立
+//! 一条 vsock 连接随即关闭，不发送任何数据，不依赖传输特性，也不经过认证握手。
+//! [`scan()`] 依次探测一段端口，用于查找服务实际监听的端口。
+//!
+//! ```ignore
+//! let result = virga::probe(2, 5005, Duration::from_secs(1))?;
+//! if !result.is_open() {
+//!     bail!("host service not reachable: {:?}", result.status);
+//! }
+//! ```
+
+use std::io::{Error, ErrorKind, Result};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::addr::Addr;
+
+/// 单次探测的结论
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProbeStatus {
+    /// 连接成功，端口上有服务在监听
+    Open,
+    /// 对端 CID 存在，但端口上没有监听
+    Refused,
+    /// 对端 CID 不存在或 vsock 设备不可用
+    Unreachable,
+    /// 期限内没有结论
+    TimedOut,
+}
+
+/// 单次探测的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeResult {
+    pub addr: Addr,
+    pub status: ProbeStatus,
+    /// 得出结论所用的时间
+    pub elapsed: Duration,
+}
+
+impl ProbeResult {
+    pub fn is_open(&self) -> bool {
+        self.status == ProbeStatus::Open
+    }
+}
+
+/// 探测 `cid:port` 能否建立连接，最多等待 `timeout`。结论为拒绝、不可达或超时时
+/// 仍返回 `Ok`，本机无法创建 vsock socket 等错误返回 `Err`
+pub fn probe(cid: u32, port: u32, timeout: Duration) -> Result<ProbeResult> {
+    let addr = Addr::new(cid, port);
+    let started = Instant::now();
+    let status = connect(addr, timeout)?;
+    let result = ProbeResult {
+        addr,
+        status,
+        elapsed: started.elapsed(),
+    };
+    debug!("Probed {}: {:?} in {:?}", addr, status, result.elapsed);
+    Ok(result)
+}
+
+/// 依次探测 `cid` 上的 `ports`，每个端口最多等待 `timeout`，按探测顺序返回结果。
+/// 对端 CID 不可达时其余端口同样不可达，不再逐一等待
+pub fn scan(
+    cid: u32,
+    ports: impl IntoIterator<Item = u32>,
+    timeout: Duration,
+) -> Result<Vec<ProbeResult>> {
+    let mut results = Vec::new();
+    let mut unreachable = false;
+    for port in ports {
+        if unreachable {
+            results.push(ProbeResult {
+                addr: Addr::new(cid, port),
+                status: ProbeStatus::Unreachable,
+                elapsed: Duration::ZERO,
+            });
+            continue;
+        }
+        let result = probe(cid, port, timeout)?;
+        unreachable = result.status == ProbeStatus::Unreachable;
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// 非阻塞地发起连接，在 `timeout` 内等待结果
+fn connect(addr: Addr, timeout: Duration) -> Result<ProbeStatus> {
+    // SAFETY: 参数均为常量，返回值在下面检查
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_VSOCK,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    // SAFETY: fd 是刚创建、无人持有的 socket
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: sockaddr_vm 是普通结构体，全零后填写所需字段
+    let mut sa: libc::sockaddr_vm = unsafe { std::mem::zeroed() };
+    sa.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+    sa.svm_cid = addr.cid;
+    sa.svm_port = addr.port;
+    // SAFETY: sa 在调用期间有效，长度与类型一致
+    let rc = unsafe {
+        libc::connect(
+            socket.as_raw_fd(),
+            &sa as *const libc::sockaddr_vm as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t,
+        )
+    };
+    if rc == 0 {
+        return Ok(ProbeStatus::Open);
+    }
+    let e = Error::last_os_error();
+    if e.raw_os_error() != Some(libc::EINPROGRESS) {
+        return classify(e);
+    }
+    if !wait_writable(&socket, timeout)? {
+        return Ok(ProbeStatus::TimedOut);
+    }
+    match socket_error(&socket)? {
+        0 => Ok(ProbeStatus::Open),
+        errno => classify(Error::from_raw_os_error(errno)),
+    }
+}
+
+/// 把连接错误归为探测结论，其他错误原样返回
+fn classify(e: Error) -> Result<ProbeStatus> {
+    match e.raw_os_error() {
+        Some(libc::ECONNREFUSED | libc::ECONNRESET) => Ok(ProbeStatus::Refused),
+        Some(libc::ENODEV | libc::ENXIO | libc::EHOSTUNREACH | libc::ENETUNREACH) => {
+            Ok(ProbeStatus::Unreachable)
+        }
+        Some(libc::ETIMEDOUT) => Ok(ProbeStatus::TimedOut),
+        _ => Err(e),
+    }
+}
+
+/// 等待连接完成，超时返回 `false`
+fn wait_writable(socket: &OwnedFd, timeout: Duration) -> Result<bool> {
+    let mut fds = [libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: libc::POLLOUT,
+        revents: 0,
+    }];
+    let deadline = Instant::now() + timeout;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let ms = left.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+        // SAFETY: fds 是长度为 1 的有效 pollfd 数组
+        let n = unsafe { libc::poll(fds.as_mut_ptr(), 1, ms) };
+        if n >= 0 {
+            return Ok(n > 0);
+        }
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+fn socket_error(socket: &OwnedFd) -> Result<i32> {
+    let mut errno: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: errno 与 len 在调用期间有效，长度与类型一致
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut errno as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(errno)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_errors_map_to_statuses() {
+        let status = |errno| classify(Error::from_raw_os_error(errno)).ok();
+        assert_eq!(status(libc::ECONNREFUSED), Some(ProbeStatus::Refused));
+        assert_eq!(status(libc::ENODEV), Some(ProbeStatus::Unreachable));
+        assert_eq!(status(libc::EHOSTUNREACH), Some(ProbeStatus::Unreachable));
+        assert_eq!(status(libc::ETIMEDOUT), Some(ProbeStatus::TimedOut));
+        assert_eq!(status(libc::EACCES), None);
+    }
+
+    #[test]
+    fn scan_reports_every_port_in_order() {
+        // 沙箱内可能没有 vsock：此时创建 socket 失败，scan 返回错误
+        let Ok(results) = scan(
+            crate::VMADDR_CID_LOCAL as u32,
+            40_000..40_003,
+            Duration::from_millis(200),
+        ) else {
+            return;
+        };
+        let ports: Vec<u32> = results.iter().map(|r| r.addr.port).collect();
+        assert_eq!(ports, [40_000, 40_001, 40_002]);
+        assert!(results.iter().all(|r| r.elapsed < Duration::from_secs(2)));
+    }
+}
@@ -0,0 +1,818 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 客户机到宿主机的反向代理：客户机上的程序访问宿主机本地的服务（如元数据服务）
+//!
+//! 客户机上的 [`GuestProxy`] 为每个路由监听一个 TCP 地址，接受的连接经 Virga 转发到宿主机，
+//! 宿主机上的 [`HostProxy`] 按路由名连接对应的 TCP 地址或 Unix 套接字（[`ProxyRoute`]），
+//! 之后双向转发字节流。客户机只能给出路由名，宿主机只连接配置过的路由，不会按客户机的要求
+//! 连接任意地址；每个路由可限制同时转发的连接数。
+//!
+//! ```ignore
+//! // 宿主机，每个客户机连接调用一次 serve()，共享同一个代理
+//! let proxy = HostProxy::new()
+//!     .route("metadata", ProxyRoute::unix("/run/metadata.sock").with_max_connections(16))
+//!     .route("registry", ProxyRoute::tcp("127.0.0.1:5000".parse()?));
+//! proxy.serve(&mux, &mux.channel(PROXY_CHANNEL)?)?;
+//!
+//! // 客户机
+//! let proxy = GuestProxy::new()
+//!     .route("metadata", "169.254.169.254:80")?
+//!     .route("registry", "127.0.0.1:5000")?;
+//! proxy.serve(&mux)?;
+//! ```
+//!
+//! 连接经 [`mux`](crate::mux) 复用。客户机接受一个连接后注册一条编号不小于
+//! [`PROXY_STREAM_BASE`] 的通道，在通道 [`PROXY_CHANNEL`] 上请求宿主机打开该路由；宿主机在
+//! 新通道上回复 READY 或 RESET，之后两端在新通道上转发。消息以 1 字节类型开头：
+//!
+//! ```text
+//! OPEN  | stream(4, 大端) | 路由名    客户机 → 宿主机，在 PROXY_CHANNEL 上
+//! READY                              宿主机 → 客户机，已连上路由
+//! DATA  | 数据                       双向
+//! EOF                                双向，发送方不再发送数据（半关闭）
+//! RESET | 原因                       双向，拒绝打开或异常断开，对端关闭连接
+//! ```
+//!
+//! 两个方向都结束后两端注销通道。每个转发的连接在每端占用两个线程。
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::*;
+
+use crate::agent::ShutdownHandle;
+use crate::mux::{Channel, ChannelSender, Mux};
+use crate::poll;
+use crate::rpc;
+use crate::threads;
+
+/// 承载打开请求的通道号
+pub const PROXY_CHANNEL: u32 = 7;
+/// 转发连接使用的通道号从此开始，宿主机拒绝更小的通道号
+pub const PROXY_STREAM_BASE: u32 = 0x8000_0000;
+
+/// 默认的同时转发的连接数上限（每个路由）
+pub const DEFAULT_PROXY_MAX_CONNECTIONS: usize = 64;
+/// 宿主机连接路由的默认超时
+pub const DEFAULT_PROXY_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// 客户机等待宿主机打开路由的默认超时
+pub const DEFAULT_PROXY_OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 等待新连接或消息时检查停止请求的间隔
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 每次从套接字读出的字节数上限
+const COPY_BUFFER: usize = 64 * 1024;
+
+const OPEN: u8 = 0;
+const READY: u8 = 1;
+const DATA: u8 = 2;
+const EOF: u8 = 3;
+const RESET: u8 = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Frame {
+    Open { stream: u32, route: String },
+    Ready,
+    Data(Vec<u8>),
+    Eof,
+    Reset(String),
+}
+
+impl Frame {
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Frame::Open { stream, route } => {
+                buf.push(OPEN);
+                buf.extend_from_slice(&stream.to_be_bytes());
+                buf.extend_from_slice(route.as_bytes());
+            }
+            Frame::Ready => buf.push(READY),
+            Frame::Data(data) => return data_frame(data),
+            Frame::Eof => buf.push(EOF),
+            Frame::Reset(reason) => {
+                buf.push(RESET);
+                buf.extend_from_slice(reason.as_bytes());
+            }
+        }
+        buf
+    }
+
+    fn decode(message: &[u8]) -> Result<Self> {
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, format!("proxy {}", what));
+        let (&kind, body) = message
+            .split_first()
+            .ok_or_else(|| invalid("message is empty"))?;
+        let frame = match kind {
+            OPEN => {
+                if body.len() < 4 {
+                    return Err(invalid("open request is truncated"));
+                }
+                let (stream, route) = body.split_at(4);
+                Frame::Open {
+                    stream: u32::from_be_bytes(stream.try_into().unwrap()),
+                    route: String::from_utf8(route.to_vec())
+                        .map_err(|_| invalid("route name is not UTF-8"))?,
+                }
+            }
+            READY if body.is_empty() => Frame::Ready,
+            DATA => Frame::Data(body.to_vec()),
+            EOF if body.is_empty() => Frame::Eof,
+            RESET => Frame::Reset(String::from_utf8_lossy(body).into_owned()),
+            _ => return Err(invalid(&format!("message type {} is unexpected", kind))),
+        };
+        Ok(frame)
+    }
+}
+
+/// 不经复制构造 DATA 消息
+fn data_frame(data: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(1 + data.len());
+    buf.push(DATA);
+    buf.extend_from_slice(data);
+    buf
+}
+
+/// 转发的一端：客户机上接受的 TCP 连接，或宿主机上连到路由的套接字
+enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    fn try_clone(&self) -> Result<Self> {
+        Ok(match self {
+            Socket::Tcp(stream) => Socket::Tcp(stream.try_clone()?),
+            Socket::Unix(stream) => Socket::Unix(stream.try_clone()?),
+        })
+    }
+
+    /// 关闭一个或两个方向，对端已断开时忽略
+    fn shutdown(&self, how: Shutdown) {
+        let result = match self {
+            Socket::Tcp(stream) => stream.shutdown(how),
+            Socket::Unix(stream) => stream.shutdown(how),
+        };
+        if let Err(e) = result {
+            debug!("Proxy socket shutdown failed: {}", e);
+        }
+    }
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.read(buf),
+            Socket::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Socket::Tcp(stream) => stream.write(buf),
+            Socket::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Socket::Tcp(stream) => stream.flush(),
+            Socket::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// 一个转发连接两个方向共享的状态
+#[derive(Debug, Default)]
+struct RelayState {
+    /// 连接异常断开，已有一方向对端发送 RESET 或收到了 RESET
+    reset: AtomicBool,
+    /// 已向对端发送 EOF
+    sent_eof: AtomicBool,
+}
+
+impl RelayState {
+    /// 标记连接已断开，此前未断开时返回 `true`，由调用方通知对端
+    fn reset(&self) -> bool {
+        !self.reset.swap(true, Ordering::AcqRel)
+    }
+
+    fn is_reset(&self) -> bool {
+        self.reset.load(Ordering::Acquire)
+    }
+}
+
+/// 在 `socket` 与 `channel` 之间双向转发，直到两个方向都结束或任一方异常断开
+fn relay(socket: Socket, channel: Channel) -> Result<()> {
+    let reader = socket.try_clone()?;
+    let sender = channel.sender();
+    let state = Arc::new(RelayState::default());
+    let outgoing = {
+        let state = state.clone();
+        threads::spawn("proxy-out", move || copy_out(reader, &sender, &state))?
+    };
+    let result = copy_in(&channel, socket, &state);
+    let copied = outgoing
+        .join()
+        .unwrap_or_else(|_| Err(Error::other("proxy thread panicked")));
+    result.and(copied)
+}
+
+/// 把通道收到的数据写入套接字；收到 EOF 后继续等待，直到另一个方向也结束或连接断开
+fn copy_in(channel: &Channel, mut socket: Socket, state: &RelayState) -> Result<()> {
+    let mut eof = false;
+    let e = loop {
+        let message = match channel.recv_timeout(SHUTDOWN_POLL_INTERVAL) {
+            Ok(Some(message)) => message,
+            Ok(None) if state.is_reset() => {
+                break Error::new(ErrorKind::ConnectionReset, "proxied socket failed")
+            }
+            Ok(None) if eof && state.sent_eof.load(Ordering::Acquire) => return Ok(()),
+            Ok(None) => continue,
+            Err(e) => break e,
+        };
+        match Frame::decode(&message) {
+            Ok(Frame::Data(data)) if !eof => {
+                if let Err(e) = socket.write_all(&data) {
+                    break e;
+                }
+            }
+            Ok(Frame::Eof) if !eof => {
+                socket.shutdown(Shutdown::Write);
+                eof = true;
+                if state.sent_eof.load(Ordering::Acquire) {
+                    return Ok(());
+                }
+            }
+            Ok(Frame::Reset(reason)) => {
+                state.reset();
+                break Error::new(ErrorKind::ConnectionReset, reason);
+            }
+            Ok(other) => {
+                break Error::new(
+                    ErrorKind::InvalidData,
+                    format!("proxy message {:?} is unexpected", other),
+                )
+            }
+            Err(e) => break e,
+        }
+    };
+    if state.reset() {
+        let _ = channel.send(&Frame::Reset(e.to_string()).encode());
+    }
+    // 读线程从阻塞的读取中返回，且不再向对端发送
+    socket.shutdown(Shutdown::Both);
+    Err(e)
+}
+
+/// 把从套接字读出的数据发给对端，读到结尾时发送 EOF
+fn copy_out(mut socket: Socket, sender: &ChannelSender, state: &RelayState) -> Result<()> {
+    let mut buf = vec![0u8; COPY_BUFFER];
+    loop {
+        match socket.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) if !state.is_reset() => sender.send(&data_frame(&buf[..n]))?,
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                if state.reset() {
+                    let _ = sender.send(&Frame::Reset(e.to_string()).encode());
+                }
+                return Err(e);
+            }
+        }
+    }
+    if !state.is_reset() {
+        sender.send(&Frame::Eof.encode())?;
+        state.sent_eof.store(true, Ordering::Release);
+    }
+    Ok(())
+}
+
+/// 宿主机上一个路由的目标
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Upstream {
+    /// 宿主机上的 TCP 地址，一般为本机地址
+    Tcp(SocketAddr),
+    /// Unix 套接字的路径
+    Unix(PathBuf),
+}
+
+/// 宿主机上一个路由的配置
+#[derive(Clone, Debug)]
+pub struct ProxyRoute {
+    upstream: Upstream,
+    max_connections: usize,
+    connect_timeout: Duration,
+}
+
+impl ProxyRoute {
+    /// 转发到 TCP 地址 `addr`
+    pub fn tcp(addr: SocketAddr) -> Self {
+        Self::new(Upstream::Tcp(addr))
+    }
+
+    /// 转发到 Unix 套接字 `path`
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Self::new(Upstream::Unix(path.into()))
+    }
+
+    fn new(upstream: Upstream) -> Self {
+        Self {
+            upstream,
+            max_connections: DEFAULT_PROXY_MAX_CONNECTIONS,
+            connect_timeout: DEFAULT_PROXY_CONNECT_TIMEOUT,
+        }
+    }
+
+    /// 本路由同时转发的连接数上限（所有客户机合计），超过时拒绝新连接
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// 连接 TCP 目标的超时
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn upstream(&self) -> &Upstream {
+        &self.upstream
+    }
+
+    fn connect(&self) -> Result<Socket> {
+        match &self.upstream {
+            Upstream::Tcp(addr) => {
+                let stream = TcpStream::connect_timeout(addr, self.connect_timeout)?;
+                stream.set_nodelay(true)?;
+                Ok(Socket::Tcp(stream))
+            }
+            Upstream::Unix(path) => Ok(Socket::Unix(UnixStream::connect(path)?)),
+        }
+    }
+}
+
+/// 路由与其正在转发的连接数
+#[derive(Debug)]
+struct RouteState {
+    route: ProxyRoute,
+    active: AtomicUsize,
+}
+
+/// 占用路由的一个连接名额，drop 时归还
+struct Permit(Arc<RouteState>);
+
+impl Permit {
+    fn acquire(state: &Arc<RouteState>) -> Option<Self> {
+        let active = state.active.fetch_add(1, Ordering::AcqRel);
+        let permit = Permit(state.clone());
+        (active < state.route.max_connections).then_some(permit)
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// 宿主机一侧的反向代理，见[模块文档](self)
+///
+/// 克隆后共享路由与连接数，每个客户机连接在各自的线程中调用 [`serve()`](Self::serve)
+#[derive(Clone, Debug, Default)]
+pub struct HostProxy {
+    routes: BTreeMap<String, Arc<RouteState>>,
+}
+
+impl HostProxy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 客户机请求的路由 `name` 转发到 `route`；同名路由再次设置时替换
+    pub fn route(mut self, name: impl Into<String>, route: ProxyRoute) -> Self {
+        let state = RouteState {
+            route,
+            active: AtomicUsize::new(0),
+        };
+        self.routes.insert(name.into(), Arc::new(state));
+        self
+    }
+
+    /// 处理 `control`（`mux` 上的通道，通道号一般为 [`PROXY_CHANNEL`]）收到的打开请求，
+    /// 转发的连接在 `mux` 上注册各自的通道，每个由两个工作线程负责。`control` 应在客户机
+    /// 开始转发前注册，否则之前的请求被丢弃。连接关闭时返回 `Ok`，已转发的连接继续到任一方关闭
+    pub fn serve(&self, mux: &Mux, control: &Channel) -> Result<()> {
+        loop {
+            let message = match control.recv() {
+                Ok(message) => message,
+                Err(e) if rpc::is_closed(&e) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let (stream, route) = match Frame::decode(&message)? {
+                Frame::Open { stream, route } => (stream, route),
+                other => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("proxy message {:?} is unexpected", other),
+                    ))
+                }
+            };
+            if stream < PROXY_STREAM_BASE {
+                warn!(
+                    "Proxy request for {} on reserved channel {} ignored",
+                    route, stream
+                );
+                continue;
+            }
+            let channel = match mux.channel(stream) {
+                Ok(channel) => channel,
+                Err(e) => {
+                    warn!(
+                        "Proxy request for {} on channel {} ignored: {}",
+                        route, stream, e
+                    );
+                    continue;
+                }
+            };
+            let state = self.routes.get(&route).cloned();
+            let spawned = threads::spawn(format!("proxy-{}", stream), move || {
+                open(state, &route, channel)
+            });
+            if let Err(e) = spawned {
+                warn!("Failed to start proxy thread: {}", e);
+            }
+        }
+    }
+}
+
+/// 连接路由并转发，拒绝时向客户机回复原因
+fn open(state: Option<Arc<RouteState>>, route: &str, channel: Channel) {
+    let refuse = |reason: String| {
+        warn!("Proxy connection to {} refused: {}", route, reason);
+        let _ = channel.send(&Frame::Reset(reason).encode());
+    };
+    let Some(state) = state else {
+        return refuse(format!("no route named {:?}", route));
+    };
+    let Some(_permit) = Permit::acquire(&state) else {
+        return refuse(format!(
+            "route {} has {} connections",
+            route, state.route.max_connections
+        ));
+    };
+    let socket = match state.route.connect() {
+        Ok(socket) => socket,
+        Err(e) => return refuse(format!("cannot connect to {}: {}", route, e)),
+    };
+    if channel.send(&Frame::Ready.encode()).is_err() {
+        return;
+    }
+    debug!("Proxy connection to {} opened", route);
+    if let Err(e) = relay(socket, channel) {
+        debug!("Proxy connection to {} ended: {}", route, e);
+    }
+}
+
+/// 客户机一侧的反向代理，见[模块文档](self)
+pub struct GuestProxy {
+    routes: Vec<(String, TcpListener)>,
+    open_timeout: Duration,
+    shutdown: ShutdownHandle,
+}
+
+impl Default for GuestProxy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GuestProxy {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            open_timeout: DEFAULT_PROXY_OPEN_TIMEOUT,
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+
+    /// 在客户机地址 `addr` 上监听，接受的连接转发到宿主机上的路由 `name`
+    pub fn route(mut self, name: impl Into<String>, addr: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        self.routes.push((name.into(), listener));
+        Ok(self)
+    }
+
+    /// 等待宿主机打开路由的超时，超时后关闭客户机上的连接
+    pub fn with_open_timeout(mut self, timeout: Duration) -> Self {
+        self.open_timeout = timeout;
+        self
+    }
+
+    /// 路由 `name` 实际监听的地址
+    pub fn local_addr(&self, name: &str) -> Option<SocketAddr> {
+        self.routes
+            .iter()
+            .find(|(route, _)| route == name)
+            .and_then(|(_, listener)| listener.local_addr().ok())
+    }
+
+    /// 用于请求停止的句柄：`serve()` 不再接受新连接并返回 `Ok`，已转发的连接继续
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 接受各路由上的连接并经 `mux` 转发，直到请求停止；连接关闭时返回错误
+    pub fn serve(self, mux: &Mux) -> Result<()> {
+        if self.routes.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "guest proxy has no routes",
+            ));
+        }
+        let control = mux.channel(PROXY_CHANNEL)?;
+        info!(
+            "Guest proxy forwarding {:?}",
+            self.routes
+                .iter()
+                .map(|(name, listener)| (name, listener.local_addr().ok()))
+                .collect::<Vec<_>>()
+        );
+        let mut next_stream = 0u32;
+        while !self.shutdown.is_shutdown() {
+            for ready in wait_any(&self.routes, SHUTDOWN_POLL_INTERVAL)? {
+                let (name, listener) = &self.routes[ready];
+                let (stream, peer) = match listener.accept() {
+                    Ok(accepted) => accepted,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                    Err(e) => {
+                        warn!("Guest proxy accept on {} failed: {}", name, e);
+                        continue;
+                    }
+                };
+                // 通道号在上半区间循环分配，跳过仍在使用的
+                let channel = loop {
+                    let id = PROXY_STREAM_BASE | (next_stream & !PROXY_STREAM_BASE);
+                    next_stream = next_stream.wrapping_add(1);
+                    match mux.channel(id) {
+                        Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                        result => break result?,
+                    }
+                };
+                let open = Frame::Open {
+                    stream: channel.id(),
+                    route: name.clone(),
+                };
+                control.send(&open.encode())?;
+                let (name, timeout) = (name.clone(), self.open_timeout);
+                let spawned = threads::spawn(format!("proxy-{}", channel.id()), move || {
+                    forward(stream, channel, &name, timeout, peer)
+                });
+                if let Err(e) = spawned {
+                    warn!("Failed to start proxy thread: {}", e);
+                }
+            }
+            if mux.is_closed() {
+                return Err(control.recv().unwrap_err());
+            }
+        }
+        info!("Guest proxy stopped");
+        Ok(())
+    }
+}
+
+/// 等待宿主机打开路由后转发客户机上的连接
+fn forward(stream: TcpStream, channel: Channel, route: &str, timeout: Duration, peer: SocketAddr) {
+    let ready = match channel.recv_timeout(timeout) {
+        Ok(Some(message)) => Frame::decode(&message),
+        Ok(None) => Err(Error::new(
+            ErrorKind::TimedOut,
+            format!("host did not open {} within {:?}", route, timeout),
+        )),
+        Err(e) => Err(e),
+    };
+    match ready {
+        Ok(Frame::Ready) => {}
+        Ok(Frame::Reset(reason)) => {
+            warn!(
+                "Proxy connection from {} to {} refused: {}",
+                peer, route, reason
+            );
+            return;
+        }
+        Ok(other) => {
+            warn!(
+                "Proxy connection from {} to {}: unexpected {:?}",
+                peer, route, other
+            );
+            return;
+        }
+        Err(e) => {
+            warn!("Proxy connection from {} to {} failed: {}", peer, route, e);
+            return;
+        }
+    }
+    let result = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_nodelay(true))
+        .and_then(|_| relay(Socket::Tcp(stream), channel));
+    if let Err(e) = result {
+        debug!("Proxy connection from {} to {} ended: {}", peer, route, e);
+    }
+}
+
+/// 等待任一监听套接字上有新连接或超过 `timeout`，返回就绪的序号
+fn wait_any(routes: &[(String, TcpListener)], timeout: Duration) -> Result<Vec<usize>> {
+    let mut fds: Vec<_> = routes
+        .iter()
+        .map(|(_, listener)| libc::pollfd {
+            fd: listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    poll::wait_all(&mut fds, Some(timeout))?;
+    Ok(fds
+        .iter()
+        .enumerate()
+        .filter(|(_, fd)| fd.revents != 0)
+        .map(|(i, _)| i)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_round_trip() {
+        let frames = [
+            Frame::Open {
+                stream: PROXY_STREAM_BASE + 3,
+                route: "metadata".to_string(),
+            },
+            Frame::Ready,
+            Frame::Data(b"GET / HTTP/1.0\r\n\r\n".to_vec()),
+            Frame::Eof,
+            Frame::Reset("connection refused".to_string()),
+        ];
+        for frame in frames {
+            assert_eq!(Frame::decode(&frame.encode()).unwrap(), frame);
+        }
+        assert!(Frame::decode(&[OPEN, 0, 0]).is_err());
+        assert!(Frame::decode(&[EOF, 1]).is_err());
+    }
+
+    #[cfg(feature = "use-xtransport")]
+    mod mux {
+        use std::net::Ipv4Addr;
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+
+        use super::*;
+        use crate::mux::testing::pair;
+
+        /// 读完请求后回复 `<prefix><请求>`，再关闭连接
+        fn answer(mut socket: impl Read + Write, prefix: &str) {
+            let mut request = Vec::new();
+            socket.read_to_end(&mut request).unwrap();
+            let mut response = prefix.as_bytes().to_vec();
+            response.extend_from_slice(&request);
+            socket.write_all(&response).unwrap();
+        }
+
+        /// 发送请求并半关闭，读出全部回复
+        fn request(addr: SocketAddr, body: &[u8]) -> Vec<u8> {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(body).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            response
+        }
+
+        /// 连接被代理关闭，收不到任何内容
+        fn assert_refused(addr: SocketAddr) {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut buf = [0u8; 16];
+            // 客户机关闭未读完的连接时可能以 RST 结束
+            match stream.read(&mut buf) {
+                Ok(n) => assert_eq!(n, 0),
+                Err(e) => assert_eq!(e.kind(), ErrorKind::ConnectionReset),
+            }
+        }
+
+        /// 启动两端的代理，返回客户机上各路由的地址
+        fn start(
+            host: HostProxy,
+            routes: &[&str],
+        ) -> (
+            Vec<SocketAddr>,
+            ShutdownHandle,
+            thread::JoinHandle<Result<()>>,
+        ) {
+            let (host_mux, guest_mux) = pair();
+            let control = host_mux.channel(PROXY_CHANNEL).unwrap();
+            thread::spawn(move || host.serve(&host_mux, &control));
+            let mut guest = GuestProxy::new();
+            for route in routes {
+                guest = guest.route(*route, (Ipv4Addr::LOCALHOST, 0)).unwrap();
+            }
+            let addrs = routes
+                .iter()
+                .map(|r| guest.local_addr(r).unwrap())
+                .collect();
+            let shutdown = guest.shutdown_handle();
+            let serving = thread::spawn(move || guest.serve(&guest_mux));
+            (addrs, shutdown, serving)
+        }
+
+        #[test]
+        fn guest_connections_reach_host_services() {
+            let dir = std::env::temp_dir().join(format!("virga-proxy-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let socket = dir.join("metadata.sock");
+            let _ = std::fs::remove_file(&socket);
+            let unix = UnixListener::bind(&socket).unwrap();
+            let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let tcp_addr = tcp.local_addr().unwrap();
+            thread::spawn(move || {
+                for stream in unix.incoming() {
+                    answer(stream.unwrap(), "metadata:");
+                }
+            });
+            thread::spawn(move || {
+                for stream in tcp.incoming() {
+                    answer(stream.unwrap(), "registry:");
+                }
+            });
+
+            let host = HostProxy::new()
+                .route("metadata", ProxyRoute::unix(&socket))
+                .route("registry", ProxyRoute::tcp(tcp_addr));
+            let (addrs, shutdown, serving) = start(host, &["metadata", "registry"]);
+            let large = vec![7u8; 300_000];
+            let clients: Vec<_> = (0..4)
+                .map(|i| {
+                    let (addr, large) = (addrs[i % 2], large.clone());
+                    thread::spawn(move || request(addr, &large))
+                })
+                .collect();
+            assert_eq!(request(addrs[0], b"instance-id"), b"metadata:instance-id");
+            assert_eq!(request(addrs[1], b"v2/"), b"registry:v2/");
+            for (i, client) in clients.into_iter().enumerate() {
+                let response = client.join().unwrap();
+                let prefix: &[u8] = if i % 2 == 0 {
+                    b"metadata:"
+                } else {
+                    b"registry:"
+                };
+                assert_eq!(&response[..prefix.len()], prefix);
+                assert_eq!(&response[prefix.len()..], &large[..]);
+            }
+
+            shutdown.shutdown();
+            serving.join().unwrap().unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn unknown_and_busy_routes_are_refused() {
+            let tcp = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            let host = HostProxy::new().route(
+                "metadata",
+                ProxyRoute::tcp(tcp.local_addr().unwrap()).with_max_connections(1),
+            );
+            let (addrs, shutdown, serving) = start(host, &["metadata", "secrets"]);
+
+            // 宿主机没有配置的路由
+            assert_refused(addrs[1]);
+
+            // 第一个连接占满名额，第二个被拒绝
+            let mut held = TcpStream::connect(addrs[0]).unwrap();
+            let (mut upstream, _) = tcp.accept().unwrap();
+            assert_refused(addrs[0]);
+            held.write_all(b"first").unwrap();
+            let mut buf = [0u8; 5];
+            upstream.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"first");
+            drop((held, upstream));
+
+            shutdown.shutdown();
+            serving.join().unwrap().unwrap();
+        }
+    }
+}
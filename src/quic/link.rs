@@ -0,0 +1,239 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 把一条字节流（vsock 连接）当作 QUIC 的数据报链路
+//!
+//! 每个数据报以 2 字节大端长度开头写入流中。链路只有一个对端：两端的地址固定为
+//! [`LINK_LOCAL`]/[`LINK_PEER`]，只用来满足 quinn 的接口。流不会丢包也不会分片，
+//! 数据报可以远大于以太网 MTU。
+
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IoSliceMut};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+
+use log::*;
+use quinn::udp::{RecvMeta, Transmit};
+use quinn::{AsyncUdpSocket, UdpPoller};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot};
+
+/// 本端在链路上的地址
+const LINK_LOCAL: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 1));
+/// 对端在链路上的地址
+pub(super) const LINK_PEER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 2));
+/// 链路上的数据报大小；超过 8 KiB 时 quinn 的握手无法完成
+pub(super) const LINK_MTU: u16 = 8 * 1024;
+/// 收发方向各自排队的数据报个数上限；发送队列满时 quinn 暂停发送
+const QUEUE_DEPTH: usize = 64;
+
+/// 字节流上的数据报链路，收发由两个后台任务完成
+pub(super) struct StreamLink {
+    outgoing: mpsc::Sender<Vec<u8>>,
+    incoming: Mutex<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl StreamLink {
+    /// 在当前 tokio 运行时上启动链路的收发任务；返回的接收端在流关闭或出错时完成
+    pub(super) fn spawn<S>(stream: S) -> (Arc<Self>, oneshot::Receiver<()>)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let (outgoing, outgoing_rx) = mpsc::channel(QUEUE_DEPTH);
+        let (incoming_tx, incoming) = mpsc::channel(QUEUE_DEPTH);
+        let (closed_tx, closed) = oneshot::channel();
+        tokio::spawn(write_datagrams(writer, outgoing_rx));
+        tokio::spawn(async move {
+            if let Err(e) = read_datagrams(reader, incoming_tx).await {
+                debug!("QUIC link read failed: {}", e);
+            }
+            let _ = closed_tx.send(());
+        });
+        let link = Arc::new(Self {
+            outgoing,
+            incoming: Mutex::new(incoming),
+        });
+        (link, closed)
+    }
+}
+
+impl fmt::Debug for StreamLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamLink")
+            .field("local", &LINK_LOCAL)
+            .field("peer", &LINK_PEER)
+            .finish()
+    }
+}
+
+impl AsyncUdpSocket for StreamLink {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        Box::pin(Writable {
+            outgoing: self.outgoing.clone(),
+            reserve: Mutex::new(None),
+        })
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> io::Result<()> {
+        match self.outgoing.try_send(transmit.contents.to_vec()) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => Err(io::ErrorKind::WouldBlock.into()),
+            // 链路已关闭，与 UDP 一样丢弃
+            Err(mpsc::error::TrySendError::Closed(_)) => Ok(()),
+        }
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut incoming = self.incoming.lock().unwrap_or_else(PoisonError::into_inner);
+        match incoming.poll_recv(cx) {
+            Poll::Ready(Some(datagram)) => {
+                let len = datagram.len().min(bufs[0].len());
+                bufs[0][..len].copy_from_slice(&datagram[..len]);
+                meta[0] = RecvMeta {
+                    addr: LINK_PEER,
+                    len,
+                    stride: len,
+                    ecn: None,
+                    dst_ip: None,
+                };
+                Poll::Ready(Ok(1))
+            }
+            // 链路关闭后不再有数据报，连接由 `closed` 通知关闭
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(LINK_LOCAL)
+    }
+
+    fn may_fragment(&self) -> bool {
+        false
+    }
+}
+
+/// 发送队列有空位时唤醒 quinn
+struct Writable {
+    outgoing: mpsc::Sender<Vec<u8>>,
+    reserve: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+}
+
+impl fmt::Debug for Writable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Writable").finish_non_exhaustive()
+    }
+}
+
+impl UdpPoller for Writable {
+    fn poll_writable(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let reserve = this
+            .reserve
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        let future = reserve.get_or_insert_with(|| {
+            let outgoing = this.outgoing.clone();
+            // 空位只是预留后立即归还；队列关闭时同样就绪，之后的发送被丢弃
+            Box::pin(async move {
+                let _ = outgoing.reserve().await;
+            })
+        });
+        match future.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                *reserve = None;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+async fn write_datagrams<W>(mut writer: W, mut outgoing: mpsc::Receiver<Vec<u8>>)
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(datagram) = outgoing.recv().await {
+        let len = (datagram.len() as u16).to_be_bytes();
+        let written = async {
+            writer.write_all(&len).await?;
+            writer.write_all(&datagram).await?;
+            // 队列中没有更多数据报时才刷出
+            if outgoing.is_empty() {
+                writer.flush().await?;
+            }
+            io::Result::Ok(())
+        };
+        if let Err(e) = written.await {
+            debug!("QUIC link write failed: {}", e);
+            return;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+async fn read_datagrams<R>(mut reader: R, incoming: mpsc::Sender<Vec<u8>>) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        let mut len = [0u8; 2];
+        match reader.read_exact(&mut len).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        let mut datagram = vec![0u8; u16::from_be_bytes(len) as usize];
+        reader.read_exact(&mut datagram).await?;
+        if incoming.send(datagram).await.is_err() {
+            // 端点已关闭
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::poll_fn;
+
+    #[tokio::test]
+    async fn datagrams_keep_their_boundaries() {
+        let (a, b) = tokio::net::UnixStream::pair().unwrap();
+        let (a, _) = StreamLink::spawn(a);
+        let (b, closed) = StreamLink::spawn(b);
+        for contents in [&b"first"[..], &[7u8; 4000], b""] {
+            let transmit = Transmit {
+                destination: LINK_PEER,
+                ecn: None,
+                contents,
+                segment_size: None,
+                src_ip: None,
+            };
+            a.try_send(&transmit).unwrap();
+        }
+
+        let mut buf = vec![0u8; LINK_MTU as usize];
+        for expected in [5, 4000, 0] {
+            let mut meta = [RecvMeta::default()];
+            let n = poll_fn(|cx| b.poll_recv(cx, &mut [IoSliceMut::new(&mut buf)], &mut meta))
+                .await
+                .unwrap();
+            assert_eq!(n, 1);
+            assert_eq!(meta[0].len, expected);
+            assert_eq!(meta[0].addr, LINK_PEER);
+        }
+
+        drop(a);
+        closed.await.unwrap();
+    }
+}
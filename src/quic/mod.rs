@@ -0,0 +1,720 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 实验性的 QUIC 传输
+//!
+//! yamux 在一条 vsock 连接上复用多个流，但没有按流的流控：一个流的接收方不读，
+//! 整条连接都会停下。[`QuicConnection`] 用 quinn 在一条 vsock 连接上跑 QUIC，
+//! 每个 [`QuicStream`] 有自己的接收窗口，互不阻塞；客户机有网络时也可以改走 UDP，
+//! 由 QUIC 负责丢包重传与拥塞控制。
+//!
+//! vsock 是字节流，每条 vsock 连接被当作只有一个对端的数据报链路，承载一个 QUIC 连接。
+//! 握手使用 TLS 1.3：监听器默认生成自签名证书（[`QuicListener::certificate()`]），
+//! 客户端以 [`QuicConfig::with_server_certificate()`] 固定它；vsock 上未固定时接受任何
+//! 证书（对端身份由 hypervisor 保证），UDP 上必须固定。需要启用 `quic` 特性，并在调用方的
+//! tokio 运行时中使用。
+//!
+//! ```ignore
+//! let connection = QuicConnection::connect(Addr::new(3, 1234), &QuicConfig::default()).await?;
+//! let mut stream = connection.open_stream().await?;
+//! stream.send(b"hello").await?;
+//! let reply = stream.recv().await?;
+//! ```
+
+mod link;
+mod tls;
+
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::*;
+use quinn::{Endpoint, EndpointConfig, IdleTimeout, TokioRuntime, VarInt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_vsock::{VsockListener, VsockStream};
+
+use crate::addr::Addr;
+use crate::error::{ConnContext, Result, VirgeError};
+use crate::logging::log_event;
+use crate::units::ByteSize;
+use link::{StreamLink, LINK_MTU, LINK_PEER};
+
+/// 默认握手超时
+pub const DEFAULT_QUIC_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// 默认空闲超时：期间没有收到对端的任何包即断开
+pub const DEFAULT_QUIC_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 本端关闭连接时的应用错误码
+const CLOSED: VarInt = VarInt::from_u32(0);
+/// 承载连接的 vsock 链路断开时的应用错误码
+const LINK_CLOSED: VarInt = VarInt::from_u32(1);
+
+/// QUIC 连接配置，连接两端各自使用
+#[derive(Clone, Debug)]
+pub struct QuicConfig {
+    handshake_timeout: Duration,
+    idle_timeout: Duration,
+    keep_alive: Option<Duration>,
+    stream_window: ByteSize,
+    max_streams: u32,
+    max_message_size: ByteSize,
+    certificate: Option<(Vec<u8>, Vec<u8>)>,
+    server_certificate: Option<Vec<u8>>,
+}
+
+impl Default for QuicConfig {
+    fn default() -> Self {
+        Self {
+            handshake_timeout: DEFAULT_QUIC_HANDSHAKE_TIMEOUT,
+            idle_timeout: DEFAULT_QUIC_IDLE_TIMEOUT,
+            keep_alive: Some(Duration::from_secs(10)),
+            stream_window: ByteSize::mib(1),
+            max_streams: 100,
+            max_message_size: ByteSize::mib(16),
+            certificate: None,
+            server_certificate: None,
+        }
+    }
+}
+
+impl QuicConfig {
+    /// 建立连接（含 vsock 连接与 TLS 握手）的超时
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// 超过该时长没有收到对端的任何包即断开，收发返回 `TimedOut`
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// 空闲时发送 keepalive 的间隔，须小于空闲超时；`None` 表示不发送
+    pub fn with_keep_alive(mut self, interval: Option<Duration>) -> Self {
+        self.keep_alive = interval;
+        self
+    }
+
+    /// 每个流的接收窗口：对端最多发出这么多未被读取的数据，之后只阻塞这一个流
+    pub fn with_stream_window(mut self, window: ByteSize) -> Self {
+        self.stream_window = window;
+        self
+    }
+
+    /// 允许对端同时打开的流个数
+    pub fn with_max_streams(mut self, streams: u32) -> Self {
+        self.max_streams = streams;
+        self
+    }
+
+    /// 单条消息的上限，超过时发送返回 `InvalidInput`、接收返回 `InvalidData`
+    pub fn with_max_message_size(mut self, size: ByteSize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// 监听器使用的证书与 PKCS#8 私钥（均为 DER）；未设置时每个监听器生成自签名证书
+    pub fn with_certificate(mut self, certificate: Vec<u8>, key: Vec<u8>) -> Self {
+        self.certificate = Some((certificate, key));
+        self
+    }
+
+    /// 客户端只接受与之逐字节一致的服务端证书（DER），通常取自 [`QuicListener::certificate()`]
+    pub fn with_server_certificate(mut self, certificate: Vec<u8>) -> Self {
+        self.server_certificate = Some(certificate);
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.handshake_timeout.is_zero() || self.idle_timeout.is_zero() {
+            return Err(VirgeError::ConfigError(
+                "QUIC timeouts must be greater than zero".to_string(),
+            ));
+        }
+        if self
+            .keep_alive
+            .is_some_and(|k| k.is_zero() || k >= self.idle_timeout)
+        {
+            return Err(VirgeError::ConfigError(
+                "QUIC keep-alive must be shorter than the idle timeout".to_string(),
+            ));
+        }
+        if self.stream_window.as_u64() == 0 || self.max_streams == 0 {
+            return Err(VirgeError::ConfigError(
+                "QUIC stream window and stream count must be greater than zero".to_string(),
+            ));
+        }
+        if self.max_message_size.as_u64() == 0 || self.max_message_size.as_u64() > u32::MAX as u64 {
+            return Err(VirgeError::ConfigError(
+                "QUIC max message size must be between 1 byte and 4 GiB".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// `link` 为 vsock 链路时使用固定的大数据报，不做路径 MTU 探测
+    fn transport(&self, link: bool) -> Result<Arc<quinn::TransportConfig>> {
+        let idle = IdleTimeout::try_from(self.idle_timeout)
+            .map_err(|_| VirgeError::ConfigError("QUIC idle timeout is too long".to_string()))?;
+        let window = VarInt::from_u64(self.stream_window.as_u64())
+            .map_err(|_| VirgeError::ConfigError("QUIC stream window is too large".to_string()))?;
+        let mut transport = quinn::TransportConfig::default();
+        transport
+            .max_idle_timeout(Some(idle))
+            .keep_alive_interval(self.keep_alive)
+            .stream_receive_window(window)
+            .max_concurrent_bidi_streams(VarInt::from_u32(self.max_streams))
+            .max_concurrent_uni_streams(VarInt::from_u32(0));
+        if link {
+            transport
+                .initial_mtu(LINK_MTU)
+                .min_mtu(LINK_MTU)
+                .mtu_discovery_config(None);
+        }
+        Ok(Arc::new(transport))
+    }
+
+    fn client(&self, link: bool) -> Result<quinn::ClientConfig> {
+        let mut client =
+            quinn::ClientConfig::new(tls::client_crypto(self.server_certificate.as_deref())?);
+        client.transport_config(self.transport(link)?);
+        Ok(client)
+    }
+
+    /// 服务端配置与所用的证书
+    fn server(&self, link: bool) -> Result<(quinn::ServerConfig, Vec<u8>)> {
+        let (certificate, key) = match &self.certificate {
+            Some(pair) => pair.clone(),
+            None => tls::self_signed()?,
+        };
+        let mut server = quinn::ServerConfig::with_crypto(tls::server_crypto(&certificate, &key)?);
+        server.transport_config(self.transport(link)?);
+        if link {
+            server.migration(false);
+        }
+        Ok((server, certificate))
+    }
+}
+
+fn link_endpoint<S>(stream: S, server: Option<quinn::ServerConfig>) -> Result<(Endpoint, Link)>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (socket, closed) = StreamLink::spawn(stream);
+    let mut config = EndpointConfig::default();
+    config
+        .max_udp_payload_size(LINK_MTU)
+        .map_err(|e| VirgeError::ConfigError(e.to_string()))?;
+    let endpoint =
+        Endpoint::new_with_abstract_socket(config, server, socket, Arc::new(TokioRuntime))?;
+    Ok((endpoint, Link(closed)))
+}
+
+/// vsock 链路断开的通知
+struct Link(tokio::sync::oneshot::Receiver<()>);
+
+impl Link {
+    /// 链路断开时关闭连接，使阻塞中的收发立即返回而不是等到空闲超时
+    fn watch(self, connection: quinn::Connection) {
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = self.0 => connection.close(LINK_CLOSED, b"link closed"),
+                _ = connection.closed() => {}
+            }
+        });
+    }
+}
+
+async fn handshake<F>(config: &QuicConfig, what: &str, future: F) -> Result<quinn::Connection>
+where
+    F: std::future::Future<Output = std::result::Result<quinn::Connection, quinn::ConnectionError>>,
+{
+    match tokio::time::timeout(config.handshake_timeout, future).await {
+        Ok(Ok(connection)) => Ok(connection),
+        Ok(Err(e)) => Err(VirgeError::transport(
+            std::io::Error::from(e.clone()).kind(),
+            format!("QUIC handshake {} failed: {}", what, e),
+        )),
+        Err(_) => Err(VirgeError::transport(
+            ErrorKind::TimedOut,
+            format!("QUIC handshake {} timed out", what),
+        )),
+    }
+}
+
+/// 一条 QUIC 连接，其上可打开多个 [`QuicStream`]
+pub struct QuicConnection {
+    connection: quinn::Connection,
+    endpoint: Endpoint,
+    max_message_size: usize,
+}
+
+impl QuicConnection {
+    /// 经 vsock 连接 `addr` 上的 [`QuicListener`]
+    pub async fn connect(addr: Addr, config: &QuicConfig) -> Result<Self> {
+        config.validate()?;
+        let stream =
+            tokio::time::timeout(config.handshake_timeout, VsockStream::connect(addr.into()))
+                .await
+                .map_err(|_| {
+                    VirgeError::transport(
+                        ErrorKind::TimedOut,
+                        format!("QUIC connect to {} timed out", addr),
+                    )
+                })??;
+        let connection = Self::connect_over(stream, config).await?;
+        let conn = ConnContext::new(addr.cid, addr.port);
+        log_event!(
+            Level::Info,
+            "quic connection established",
+            conn_id = conn.conn_id,
+            cid = addr.cid,
+            port = addr.port
+        );
+        Ok(connection)
+    }
+
+    /// 在已建立的字节流上作为客户端建立连接
+    async fn connect_over<S>(stream: S, config: &QuicConfig) -> Result<Self>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut endpoint, link) = link_endpoint(stream, None)?;
+        endpoint.set_default_client_config(config.client(true)?);
+        let connecting = endpoint
+            .connect(LINK_PEER, tls::SERVER_NAME)
+            .map_err(|e| VirgeError::ConfigError(e.to_string()))?;
+        let connection = handshake(config, "over vsock", connecting).await?;
+        link.watch(connection.clone());
+        Ok(Self::new(connection, endpoint, config))
+    }
+
+    /// 经 UDP 连接 `server` 上的 [`QuicListener`]；须以
+    /// [`QuicConfig::with_server_certificate()`] 固定服务端证书，否则返回 `ConfigError`
+    pub async fn connect_udp(server: SocketAddr, config: &QuicConfig) -> Result<Self> {
+        config.validate()?;
+        if config.server_certificate.is_none() {
+            return Err(VirgeError::ConfigError(
+                "QUIC over UDP requires a pinned server certificate".to_string(),
+            ));
+        }
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let mut endpoint = Endpoint::client(local)?;
+        endpoint.set_default_client_config(config.client(false)?);
+        let connecting = endpoint
+            .connect(server, tls::SERVER_NAME)
+            .map_err(|e| VirgeError::ConfigError(e.to_string()))?;
+        let connection = handshake(config, "over UDP", connecting).await?;
+        info!("QUIC connection to {} established over UDP", server);
+        Ok(Self::new(connection, endpoint, config))
+    }
+
+    fn new(connection: quinn::Connection, endpoint: Endpoint, config: &QuicConfig) -> Self {
+        Self {
+            connection,
+            endpoint,
+            max_message_size: config.max_message_size.as_usize(),
+        }
+    }
+
+    /// 打开一个双向流。对端在本端第一次发送后才能接受到它
+    pub async fn open_stream(&self) -> Result<QuicStream> {
+        let (send, recv) = self.connection.open_bi().await.map_err(connection_error)?;
+        Ok(QuicStream::new(send, recv, self.max_message_size))
+    }
+
+    /// 接受对端打开的下一个流
+    pub async fn accept_stream(&self) -> Result<QuicStream> {
+        let (send, recv) = self
+            .connection
+            .accept_bi()
+            .await
+            .map_err(connection_error)?;
+        Ok(QuicStream::new(send, recv, self.max_message_size))
+    }
+
+    /// 当前估计的往返时延
+    pub fn rtt(&self) -> Duration {
+        self.connection.rtt()
+    }
+
+    /// 关闭连接并等待关闭通知送达对端；所有流上的收发随即返回错误
+    pub async fn close(self) {
+        self.connection.close(CLOSED, b"closed");
+        self.endpoint.wait_idle().await;
+    }
+}
+
+/// 接受 QUIC 连接的监听器
+pub struct QuicListener {
+    inner: Listener,
+    config: QuicConfig,
+    certificate: Vec<u8>,
+}
+
+enum Listener {
+    /// 每条 vsock 连接各有一个端点，接受时建立
+    Vsock {
+        listener: VsockListener,
+        server: quinn::ServerConfig,
+    },
+    Udp(Endpoint),
+}
+
+impl QuicListener {
+    /// 在 vsock 的 `cid:port` 上监听。每条 vsock 连接的 QUIC 握手在 `accept()` 中完成，
+    /// 握手超时前不接受其他连接
+    pub fn bind(cid: u32, port: u32, config: QuicConfig) -> Result<Self> {
+        config.validate()?;
+        let (server, certificate) = config.server(true)?;
+        let listener = VsockListener::bind(Addr::new(cid, port).into())?;
+        Ok(Self {
+            inner: Listener::Vsock { listener, server },
+            config,
+            certificate,
+        })
+    }
+
+    /// 在 UDP 地址 `addr` 上监听
+    pub fn bind_udp(addr: SocketAddr, config: QuicConfig) -> Result<Self> {
+        config.validate()?;
+        let (server, certificate) = config.server(false)?;
+        let endpoint = Endpoint::server(server, addr)?;
+        Ok(Self {
+            inner: Listener::Udp(endpoint),
+            config,
+            certificate,
+        })
+    }
+
+    /// 监听器的证书（DER），交给客户端的 [`QuicConfig::with_server_certificate()`]
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    /// UDP 监听器实际绑定的地址；vsock 监听器返回 `None`
+    pub fn udp_addr(&self) -> Option<SocketAddr> {
+        match &self.inner {
+            Listener::Vsock { .. } => None,
+            Listener::Udp(endpoint) => endpoint.local_addr().ok(),
+        }
+    }
+
+    /// 接受一个连接；握手失败时返回错误，监听器仍可继续使用
+    pub async fn accept(&self) -> Result<QuicConnection> {
+        match &self.inner {
+            Listener::Vsock { listener, server } => {
+                let (stream, addr) = listener.accept().await?;
+                let connection = Self::accept_over(stream, server.clone(), &self.config).await?;
+                let conn = ConnContext::new(addr.cid(), addr.port());
+                log_event!(
+                    Level::Info,
+                    "quic connection accepted",
+                    conn_id = conn.conn_id,
+                    cid = conn.cid,
+                    port = conn.port
+                );
+                Ok(connection)
+            }
+            Listener::Udp(endpoint) => {
+                let incoming = endpoint.accept().await.ok_or_else(|| {
+                    VirgeError::transport(ErrorKind::NotConnected, "QUIC endpoint closed")
+                })?;
+                let remote = incoming.remote_address();
+                let connecting = incoming.accept().map_err(connection_error)?;
+                let connection = handshake(&self.config, "over UDP", connecting).await?;
+                info!("QUIC connection from {} accepted over UDP", remote);
+                Ok(QuicConnection::new(
+                    connection,
+                    endpoint.clone(),
+                    &self.config,
+                ))
+            }
+        }
+    }
+
+    /// 在已建立的字节流上作为服务端接受连接
+    async fn accept_over<S>(
+        stream: S,
+        server: quinn::ServerConfig,
+        config: &QuicConfig,
+    ) -> Result<QuicConnection>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (endpoint, link) = link_endpoint(stream, Some(server))?;
+        let accepted = async {
+            match endpoint.accept().await {
+                Some(incoming) => incoming.await,
+                None => Err(quinn::ConnectionError::LocallyClosed),
+            }
+        };
+        let connection = handshake(config, "over vsock", accepted).await?;
+        link.watch(connection.clone());
+        Ok(QuicConnection::new(connection, endpoint, config))
+    }
+}
+
+/// QUIC 连接上的一个双向流，按消息收发；每条消息以 4 字节大端长度开头
+pub struct QuicStream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+    max_message_size: usize,
+}
+
+impl QuicStream {
+    fn new(send: quinn::SendStream, recv: quinn::RecvStream, max_message_size: usize) -> Self {
+        Self {
+            send,
+            recv,
+            max_message_size,
+        }
+    }
+
+    /// 流在连接内的编号
+    pub fn id(&self) -> u64 {
+        self.send.id().index()
+    }
+
+    /// 发送一条消息，返回消息的字节数。对端的接收窗口用完时只阻塞这一个流
+    pub async fn send(&mut self, data: &[u8]) -> Result<usize> {
+        if data.len() > self.max_message_size {
+            return Err(VirgeError::transport(
+                ErrorKind::InvalidInput,
+                format!(
+                    "message of {} bytes exceeds the {} byte limit",
+                    data.len(),
+                    self.max_message_size
+                ),
+            ));
+        }
+        let len = (data.len() as u32).to_be_bytes();
+        self.send.write_all(&len).await.map_err(stream_error)?;
+        self.send.write_all(data).await.map_err(stream_error)?;
+        Ok(data.len())
+    }
+
+    /// 接收一条消息；对端结束发送（`finish()`）后返回 `UnexpectedEof`
+    pub async fn recv(&mut self) -> Result<Vec<u8>> {
+        let mut len = [0u8; 4];
+        self.recv.read_exact(&mut len).await.map_err(read_error)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len > self.max_message_size {
+            return Err(VirgeError::transport(
+                ErrorKind::InvalidData,
+                format!(
+                    "message of {} bytes exceeds the {} byte limit",
+                    len, self.max_message_size
+                ),
+            ));
+        }
+        let mut message = vec![0u8; len];
+        self.recv
+            .read_exact(&mut message)
+            .await
+            .map_err(read_error)?;
+        Ok(message)
+    }
+
+    /// 结束本端的发送，已发出的消息仍会送达；接收方向不受影响
+    pub fn finish(&mut self) -> Result<()> {
+        self.send
+            .finish()
+            .map_err(|_| VirgeError::transport(ErrorKind::NotConnected, "QUIC stream closed"))
+    }
+}
+
+/// 本端已关闭的连接按连接关闭处理，其余按 quinn 的约定转换
+fn connection_error(e: quinn::ConnectionError) -> VirgeError {
+    match e {
+        quinn::ConnectionError::LocallyClosed => {
+            VirgeError::transport(ErrorKind::NotConnected, "QUIC connection closed")
+        }
+        e => std::io::Error::from(e).into(),
+    }
+}
+
+fn stream_error(e: quinn::WriteError) -> VirgeError {
+    match e {
+        quinn::WriteError::ConnectionLost(e) => connection_error(e),
+        e => std::io::Error::from(e).into(),
+    }
+}
+
+fn read_error(e: quinn::ReadExactError) -> VirgeError {
+    match e {
+        quinn::ReadExactError::FinishedEarly(_) => {
+            VirgeError::transport(ErrorKind::UnexpectedEof, "QUIC stream finished")
+        }
+        quinn::ReadExactError::ReadError(quinn::ReadError::ConnectionLost(e)) => {
+            connection_error(e)
+        }
+        quinn::ReadExactError::ReadError(e) => std::io::Error::from(e).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 以 Unix socket 对代替 vsock 连接，返回客户端与服务端
+    async fn pair(config: QuicConfig) -> (QuicConnection, QuicConnection) {
+        let (a, b) = tokio::net::UnixStream::pair().unwrap();
+        let (server, certificate) = config.server(true).unwrap();
+        let client_config = config.clone().with_server_certificate(certificate);
+        let (client, server) = tokio::join!(
+            QuicConnection::connect_over(a, &client_config),
+            QuicListener::accept_over(b, server, &config)
+        );
+        (client.unwrap(), server.unwrap())
+    }
+
+    #[test]
+    fn config_validation() {
+        assert!(QuicConfig::default().validate().is_ok());
+        for config in [
+            QuicConfig::default().with_idle_timeout(Duration::ZERO),
+            QuicConfig::default().with_keep_alive(Some(DEFAULT_QUIC_IDLE_TIMEOUT)),
+            QuicConfig::default().with_stream_window(ByteSize::b(0)),
+            QuicConfig::default().with_max_streams(0),
+            QuicConfig::default().with_max_message_size(ByteSize::gib(8)),
+        ] {
+            assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn streams_carry_messages_over_a_stream_link() {
+        let (client, server) = pair(QuicConfig::default()).await;
+        let echo = tokio::spawn(async move {
+            let mut stream = server.accept_stream().await.unwrap();
+            while let Ok(message) = stream.recv().await {
+                stream.send(&message).await.unwrap();
+            }
+            server
+        });
+
+        let mut stream = client.open_stream().await.unwrap();
+        for message in [&b"hello quic"[..], &vec![5u8; 3 * 1024 * 1024]] {
+            stream.send(message).await.unwrap();
+            assert_eq!(stream.recv().await.unwrap(), message);
+        }
+        stream.finish().unwrap();
+        let server = echo.await.unwrap();
+        assert!(client.rtt() > Duration::ZERO);
+
+        client.close().await;
+        let err = server.accept_stream().await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn a_stalled_stream_does_not_block_others() {
+        let config = QuicConfig::default().with_stream_window(ByteSize::kib(64));
+        let (client, server) = pair(config).await;
+
+        // 第一个流发出超过接收窗口的消息，对端暂不读取
+        let mut stalled = client.open_stream().await.unwrap();
+        let large = vec![1u8; 1024 * 1024];
+        let sender = tokio::spawn(async move {
+            stalled.send(&large).await.unwrap();
+            stalled
+        });
+        let mut stalled_peer = server.accept_stream().await.unwrap();
+
+        let mut other = client.open_stream().await.unwrap();
+        other.send(b"still moving").await.unwrap();
+        let mut other_peer = server.accept_stream().await.unwrap();
+        assert_eq!(other_peer.recv().await.unwrap(), b"still moving");
+        assert!(!sender.is_finished());
+
+        assert_eq!(stalled_peer.recv().await.unwrap().len(), 1024 * 1024);
+        sender.await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn oversized_messages_are_rejected() {
+        let (client, server) = pair(QuicConfig::default()).await;
+        let mut stream = client.open_stream().await.unwrap();
+        stream.max_message_size = 16;
+        let err = stream.send(&[0u8; 17]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        stream.max_message_size = usize::MAX;
+        stream.send(&[0u8; 17]).await.unwrap();
+        let mut peer = server.accept_stream().await.unwrap();
+        peer.max_message_size = 16;
+        assert_eq!(
+            peer.recv().await.unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn dropped_link_fails_streams_promptly() {
+        let (a, b) = tokio::net::UnixStream::pair().unwrap();
+        let config = QuicConfig::default();
+        let (server, _) = config.server(true).unwrap();
+        // 服务端的 vsock 连接在握手后被直接丢弃，不发送关闭通知
+        let (proxy, b_end) = tokio::net::UnixStream::pair().unwrap();
+        let relay = tokio::spawn(async move {
+            let (mut b_read, mut b_write) = tokio::io::split(b);
+            let (mut p_read, mut p_write) = tokio::io::split(proxy);
+            tokio::select! {
+                _ = tokio::io::copy(&mut b_read, &mut p_write) => {}
+                _ = tokio::io::copy(&mut p_read, &mut b_write) => {}
+            }
+        });
+        let (client, server) = tokio::join!(
+            QuicConnection::connect_over(a, &config),
+            QuicListener::accept_over(b_end, server, &config)
+        );
+        let (client, _server) = (client.unwrap(), server.unwrap());
+        let mut stream = client.open_stream().await.unwrap();
+        stream.send(b"before").await.unwrap();
+
+        relay.abort();
+        let started = std::time::Instant::now();
+        let err = stream.recv().await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        assert!(started.elapsed() < DEFAULT_QUIC_IDLE_TIMEOUT);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn udp_requires_the_pinned_certificate() {
+        let config = QuicConfig::default().with_handshake_timeout(Duration::from_secs(5));
+        let listener =
+            QuicListener::bind_udp((Ipv4Addr::LOCALHOST, 0).into(), config.clone()).unwrap();
+        let addr = listener.udp_addr().unwrap();
+
+        let err = QuicConnection::connect_udp(addr, &config)
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, VirgeError::ConfigError(_)));
+
+        let (other, _) = tls::self_signed().unwrap();
+        let wrong = config.clone().with_server_certificate(other);
+        let (client, server) =
+            tokio::join!(QuicConnection::connect_udp(addr, &wrong), listener.accept());
+        assert!(client.is_err());
+        assert!(server.is_err());
+
+        let pinned = config.with_server_certificate(listener.certificate().to_vec());
+        let (client, server) = tokio::join!(
+            QuicConnection::connect_udp(addr, &pinned),
+            listener.accept()
+        );
+        let (client, server) = (client.unwrap(), server.unwrap());
+        let mut stream = client.open_stream().await.unwrap();
+        stream.send(b"over udp").await.unwrap();
+        let mut peer = server.accept_stream().await.unwrap();
+        assert_eq!(peer.recv().await.unwrap(), b"over udp");
+    }
+}
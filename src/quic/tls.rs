@@ -0,0 +1,130 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! QUIC 握手使用的证书：监听器的证书与客户端对它的固定校验
+//!
+//! 客户机服务没有可供校验的域名与 CA，客户端不走证书链校验，而是比对服务端证书与
+//! 预先取得的证书是否逐字节一致（证书固定）；未配置时接受任何证书，只用于由 hypervisor
+//! 保证对端身份的 vsock 链路。
+
+use std::sync::Arc;
+
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use quinn::rustls::crypto::{
+    ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider, WebPkiSupportedAlgorithms,
+};
+use quinn::rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime,
+};
+use quinn::rustls::{self, CertificateError, DigitallySignedStruct, SignatureScheme};
+
+use crate::error::{Result, VirgeError};
+
+/// ALPN 协议名，避免与其他基于 QUIC 的协议互通
+const ALPN: &[u8] = b"virga";
+/// 自签名证书中的名称；客户端不校验名称，连接时以它作为 SNI
+pub(super) const SERVER_NAME: &str = "virga";
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn tls_error(what: &str, e: impl std::fmt::Display) -> VirgeError {
+    VirgeError::ConfigError(format!("{}: {}", what, e))
+}
+
+/// 生成自签名证书，返回证书与 PKCS#8 私钥（均为 DER）
+pub(super) fn self_signed() -> Result<(Vec<u8>, Vec<u8>)> {
+    let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+        .map_err(|e| tls_error("failed to generate QUIC certificate", e))?;
+    Ok((
+        certified.cert.der().to_vec(),
+        certified.key_pair.serialize_der(),
+    ))
+}
+
+pub(super) fn server_crypto(certificate: &[u8], key: &[u8]) -> Result<Arc<QuicServerConfig>> {
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| tls_error("invalid QUIC TLS settings", e))?
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![CertificateDer::from(certificate.to_vec())],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.to_vec())),
+        )
+        .map_err(|e| tls_error("invalid QUIC certificate", e))?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(crypto)
+        .map_err(|e| tls_error("invalid QUIC TLS settings", e))?;
+    Ok(Arc::new(crypto))
+}
+
+/// 客户端的 TLS 配置；`pinned` 为空时接受任何服务端证书
+pub(super) fn client_crypto(pinned: Option<&[u8]>) -> Result<Arc<QuicClientConfig>> {
+    let provider = provider();
+    let verifier = Arc::new(PinnedCertificate {
+        certificate: pinned.map(<[u8]>::to_vec),
+        algorithms: provider.signature_verification_algorithms,
+    });
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| tls_error("invalid QUIC TLS settings", e))?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(crypto)
+        .map_err(|e| tls_error("invalid QUIC TLS settings", e))?;
+    Ok(Arc::new(crypto))
+}
+
+/// 比对服务端证书与固定的证书；握手签名仍照常校验，证明对端持有对应的私钥
+#[derive(Debug)]
+struct PinnedCertificate {
+    certificate: Option<Vec<u8>>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        match &self.certificate {
+            Some(pinned) if pinned.as_slice() != end_entity.as_ref() => Err(
+                rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure),
+            ),
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
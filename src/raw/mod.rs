@@ -0,0 +1,315 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 不分帧的原始 vsock 流
+//!
+//! [`RawStream`] 只负责建立连接（连接超时、读写超时、keepalive、收发统计），
+//! 不做任何分帧，实现 tokio 的 `AsyncRead`/`AsyncWrite`，可直接替换
+//! `tokio_vsock::VsockStream`，供已有自有协议的应用使用 Virga 的连接管理。
+//! 需要启用 `raw` 特性，并在调用方的 tokio 运行时中使用。
+//!
+//! ```ignore
+//! let mut stream = RawStream::connect(3, 1234, RawConfig::default()).await?;
+//! stream.write_all(b"hello").await?;
+//! ```
+
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use log::*;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+use tokio_vsock::{VsockListener, VsockStream};
+
+use crate::addr::Addr;
+use crate::error::ConnContext;
+use crate::logging::log_event;
+use crate::stats::ConnectionStats;
+
+/// 默认连接超时
+pub const DEFAULT_RAW_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 原始流配置
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawConfig {
+    connect_timeout: Duration,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    keepalive: bool,
+}
+
+impl Default for RawConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: DEFAULT_RAW_CONNECT_TIMEOUT,
+            read_timeout: None,
+            write_timeout: None,
+            keepalive: false,
+        }
+    }
+}
+
+impl RawConfig {
+    /// 建立连接的超时
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 一次读等待超过该时长时返回 `TimedOut`
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// 一次写等待超过该时长时返回 `TimedOut`
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        self
+    }
+
+    /// 在 socket 上开启 `SO_KEEPALIVE`；是否据此探测对端取决于内核的 vsock 传输
+    pub fn with_keepalive(mut self, enabled: bool) -> Self {
+        self.keepalive = enabled;
+        self
+    }
+}
+
+/// 不分帧的 vsock 连接
+pub struct RawStream {
+    inner: VsockStream,
+    conn: ConnContext,
+    config: RawConfig,
+    read_deadline: Option<Pin<Box<Sleep>>>,
+    write_deadline: Option<Pin<Box<Sleep>>>,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+impl RawStream {
+    /// 连接到 `cid:port`
+    pub async fn connect(cid: u32, port: u32, config: RawConfig) -> Result<Self> {
+        let addr = Addr::new(cid, port);
+        let conn = ConnContext::new(cid, port);
+        let stream =
+            tokio::time::timeout(config.connect_timeout, VsockStream::connect(addr.into()))
+                .await
+                .map_err(|_| {
+                    Error::new(
+                        ErrorKind::TimedOut,
+                        format!("raw connect to {} timed out", addr),
+                    )
+                })??;
+        log_event!(
+            Level::Info,
+            "raw stream connected",
+            conn_id = conn.conn_id,
+            cid = cid,
+            port = port
+        );
+        Self::new(stream, conn, config)
+    }
+
+    fn new(inner: VsockStream, conn: ConnContext, config: RawConfig) -> Result<Self> {
+        if config.keepalive {
+            set_keepalive(&inner)?;
+        }
+        Ok(Self {
+            inner,
+            conn,
+            config,
+            read_deadline: None,
+            write_deadline: None,
+            bytes_read: 0,
+            bytes_written: 0,
+        })
+    }
+
+    /// 当前连接的标识（连接 ID 与对端地址）
+    pub fn conn(&self) -> ConnContext {
+        self.conn
+    }
+
+    /// 收发字节统计；原始流没有消息边界，消息数恒为 0
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            bytes_sent: self.bytes_written,
+            bytes_received: self.bytes_read,
+            ..ConnectionStats::default()
+        }
+    }
+
+    /// 取回底层的 `tokio_vsock::VsockStream`
+    pub fn into_inner(self) -> VsockStream {
+        self.inner
+    }
+}
+
+fn set_keepalive(stream: &VsockStream) -> Result<()> {
+    let enabled: libc::c_int = 1;
+    // SAFETY: fd 在 stream 存活期间有效，选项值指向栈上的 c_int
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_KEEPALIVE,
+            &enabled as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// 等待未就绪时推进超时计时，超时返回 `TimedOut`；无超时配置时不计时
+fn poll_deadline(
+    deadline: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Option<Duration>,
+    cx: &mut Context<'_>,
+    what: &str,
+) -> Poll<Result<()>> {
+    let Some(timeout) = timeout else {
+        return Poll::Pending;
+    };
+    let sleep = deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+    match sleep.as_mut().poll(cx) {
+        Poll::Ready(()) => {
+            *deadline = None;
+            Poll::Ready(Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("raw vsock {} timed out", what),
+            )))
+        }
+        Poll::Pending => Poll::Pending,
+    }
+}
+
+impl AsyncRead for RawStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                this.read_deadline = None;
+                this.bytes_read += (buf.filled().len() - before) as u64;
+                Poll::Ready(result)
+            }
+            Poll::Pending => poll_deadline(
+                &mut this.read_deadline,
+                this.config.read_timeout,
+                cx,
+                "read",
+            ),
+        }
+    }
+}
+
+impl AsyncWrite for RawStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_write(cx, buf) {
+            Poll::Ready(result) => {
+                this.write_deadline = None;
+                if let Ok(n) = result {
+                    this.bytes_written += n as u64;
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => poll_deadline(
+                &mut this.write_deadline,
+                this.config.write_timeout,
+                cx,
+                "write",
+            )
+            .map_ok(|()| 0),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// 接受原始流的监听器
+pub struct RawListener {
+    inner: VsockListener,
+    config: RawConfig,
+}
+
+impl RawListener {
+    /// 在 `cid:port` 上监听，接受的连接都使用 `config`
+    pub fn bind(cid: u32, port: u32, config: RawConfig) -> Result<Self> {
+        Ok(Self {
+            inner: VsockListener::bind(Addr::new(cid, port).into())?,
+            config,
+        })
+    }
+
+    /// 接受一个连接
+    pub async fn accept(&self) -> Result<RawStream> {
+        let (stream, addr) = self.inner.accept().await?;
+        let conn = ConnContext::new(addr.cid(), addr.port());
+        log_event!(
+            Level::Info,
+            "raw stream accepted",
+            conn_id = conn.conn_id,
+            cid = conn.cid,
+            port = conn.port
+        );
+        RawStream::new(stream, conn, self.config.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::poll_fn;
+
+    #[test]
+    fn config_builders() {
+        let config = RawConfig::default()
+            .with_connect_timeout(Duration::from_secs(1))
+            .with_read_timeout(Duration::from_millis(500))
+            .with_keepalive(true);
+        assert_eq!(config.connect_timeout, Duration::from_secs(1));
+        assert_eq!(config.read_timeout, Some(Duration::from_millis(500)));
+        assert_eq!(config.write_timeout, None);
+        assert!(config.keepalive);
+    }
+
+    #[test]
+    fn deadline_fires_once_then_rearms() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut deadline = None;
+            let timeout = Some(Duration::from_millis(10));
+            let err = poll_fn(|cx| poll_deadline(&mut deadline, timeout, cx, "read"))
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::TimedOut);
+            assert!(deadline.is_none());
+
+            let mut cx = Context::from_waker(std::task::Waker::noop());
+            assert!(poll_deadline(&mut deadline, None, &mut cx, "read").is_pending());
+            assert!(deadline.is_none());
+        });
+    }
+}
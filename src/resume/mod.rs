@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 从快照恢复的检测
+//!
+//! Firecracker 等虚拟机快照或 CRIU 检查点恢复后，进程从暂停处继续运行，但快照前建立的
+//! vsock 连接在对端早已不存在：socket 上的收发返回 `EIO`（或 `ENODEV`），有的连接则
+//! 一直收不到任何东西。单调时钟不计暂停的时间，墙上时钟与开机时钟却会在恢复后跳到当前
+//! 时刻，两者之差即暂停的时长。
+//!
+//! 客户端配置 `ClientConfig::with_snapshot_recovery()` 后，每次收发前比较这几个时钟，
+//! 跳变超过 [`CLOCK_JUMP_THRESHOLD`] 或收发返回上述错误时，丢弃旧连接、发布
+//! `VirgaEvent::ResumedFromSnapshot` 并重新连接。`ServerManager::accept()` 在监听
+//! socket 返回上述错误时重新绑定。时钟被手动或由 NTP 大幅校正时同样会触发一次重连。
+
+use std::error::Error as StdError;
+use std::io::Error;
+use std::time::{Duration, Instant, SystemTime};
+
+/// 墙上时钟或开机时钟比单调时钟多走（或少走）超过该值时视为从快照恢复
+pub const CLOCK_JUMP_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// 某一时刻的三个时钟
+#[derive(Clone, Copy, Debug)]
+struct Clocks {
+    monotonic: Instant,
+    wall: SystemTime,
+    boot: Option<Duration>,
+}
+
+impl Clocks {
+    /// 直接读取系统时钟而不经 [`crate::sources::clock()`]：替换后的单调时钟不会随
+    /// `CLOCK_BOOTTIME` 一起走，会被误判为跳变
+    fn now() -> Self {
+        Self {
+            monotonic: Instant::now(),
+            wall: SystemTime::now(),
+            boot: boottime(),
+        }
+    }
+}
+
+/// `CLOCK_BOOTTIME`，计入系统挂起的时间
+fn boottime() -> Option<Duration> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: ts 是有效的可写 timespec
+    if unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts) } != 0 {
+        return None;
+    }
+    Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// 跟踪时钟跳变，每次检查与上一次检查比较
+#[derive(Debug)]
+pub(crate) struct ResumeDetector {
+    last: Clocks,
+}
+
+impl ResumeDetector {
+    pub(crate) fn new() -> Self {
+        Self {
+            last: Clocks::now(),
+        }
+    }
+
+    /// 自上次检查以来时钟跳变超过阈值时返回跳变量
+    pub(crate) fn check(&mut self) -> Option<Duration> {
+        self.check_at(Clocks::now())
+    }
+
+    fn check_at(&mut self, now: Clocks) -> Option<Duration> {
+        let last = std::mem::replace(&mut self.last, now);
+        let elapsed = now.monotonic.saturating_duration_since(last.monotonic);
+        let wall = match now.wall.duration_since(last.wall) {
+            Ok(forward) => forward.abs_diff(elapsed),
+            // 墙上时钟倒退
+            Err(e) => e.duration() + elapsed,
+        };
+        let boot = match (last.boot, now.boot) {
+            (Some(before), Some(after)) => after.saturating_sub(before).saturating_sub(elapsed),
+            _ => Duration::ZERO,
+        };
+        let jump = wall.max(boot);
+        (jump > CLOCK_JUMP_THRESHOLD).then_some(jump)
+    }
+}
+
+/// 错误链中是否有快照恢复后 vsock socket 特有的 `EIO`/`ENODEV`
+pub(crate) fn is_resume_error(e: &Error) -> bool {
+    let mut next: Option<&(dyn StdError + 'static)> = Some(e);
+    while let Some(err) = next {
+        if let Some(io) = err.downcast_ref::<Error>() {
+            if matches!(io.raw_os_error(), Some(libc::EIO | libc::ENODEV)) {
+                return true;
+            }
+            // io::Error 的 source() 跳过了它包着的错误本身
+            if let Some(inner) = io.get_ref() {
+                next = Some(inner);
+                continue;
+            }
+        }
+        next = err.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::VirgeError;
+    use std::io::ErrorKind;
+
+    fn clocks(monotonic: Instant, wall: SystemTime, boot: u64) -> Clocks {
+        Clocks {
+            monotonic,
+            wall,
+            boot: Some(Duration::from_secs(boot)),
+        }
+    }
+
+    #[test]
+    fn detects_wall_and_boot_clock_jumps() {
+        let mono = Instant::now();
+        let wall = SystemTime::now();
+        let secs = Duration::from_secs;
+        let mut detector = ResumeDetector {
+            last: clocks(mono, wall, 100),
+        };
+        assert_eq!(
+            detector.check_at(clocks(mono + secs(1), wall + secs(1), 101)),
+            None
+        );
+        // 暂停一分钟：单调时钟没走，墙上时钟与开机时钟走了
+        assert_eq!(
+            detector.check_at(clocks(mono + secs(2), wall + secs(62), 161)),
+            Some(secs(60))
+        );
+        // 只有开机时钟跳变
+        assert_eq!(
+            detector.check_at(clocks(mono + secs(3), wall + secs(63), 171)),
+            Some(secs(9))
+        );
+        // 墙上时钟倒退
+        assert_eq!(
+            detector.check_at(clocks(mono + secs(4), wall + secs(53), 172)),
+            Some(secs(11))
+        );
+        assert_eq!(ResumeDetector::new().check(), None);
+    }
+
+    #[test]
+    fn finds_eio_through_wrapped_errors() {
+        let eio = Error::from_raw_os_error(libc::EIO);
+        assert!(is_resume_error(&eio));
+        let wrapped: Error = VirgeError::IoError(Error::from_raw_os_error(libc::ENODEV)).into();
+        assert!(is_resume_error(&wrapped));
+        let nested = Error::other(VirgeError::IoError(eio));
+        assert!(is_resume_error(&nested));
+        assert!(!is_resume_error(&Error::from(ErrorKind::ConnectionReset)));
+        assert!(!is_resume_error(&Error::from_raw_os_error(
+            libc::ECONNRESET
+        )));
+    }
+}
@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 重试退避
+//!
+//! 绑定监听、等待宿主机就绪等场景共用的指数退避：从 [`INITIAL_BACKOFF`] 开始
+//! 每次翻倍，不超过 [`MAX_BACKOFF`]，且不超过剩余期限。期限与等待都经
+//! [`crate::sources::Clock`]，测试中可换成手动推进的时钟。
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::sources::{self, Clock};
+
+/// 第一次重试前的等待时长
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+/// 两次重试之间的最长等待
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(2);
+
+/// 带期限的指数退避
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    next: Duration,
+    deadline: Instant,
+    attempt: u32,
+    clock: Arc<dyn Clock>,
+}
+
+impl Backoff {
+    /// 从现在起 `deadline` 内重试
+    pub(crate) fn new(deadline: Duration) -> Self {
+        Self::with_clock(deadline, sources::clock())
+    }
+
+    /// 以 `clock` 计时
+    pub(crate) fn with_clock(deadline: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            next: INITIAL_BACKOFF,
+            deadline: clock.now() + deadline,
+            attempt: 0,
+            clock,
+        }
+    }
+
+    /// 已重试的次数
+    pub(crate) fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// 下一次重试前应等待的时长，期限已到时为 `None`
+    pub(crate) fn next_delay(&mut self) -> Option<Duration> {
+        let remaining = self.deadline.saturating_duration_since(self.clock.now());
+        if remaining.is_zero() {
+            return None;
+        }
+        let delay = self.next.min(remaining);
+        self.next = (self.next * 2).min(MAX_BACKOFF);
+        self.attempt += 1;
+        Some(delay)
+    }
+
+    /// 等待 `next_delay()` 给出的时长
+    pub(crate) fn wait(&self, delay: Duration) {
+        self.clock.sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::ManualClock;
+
+    #[test]
+    fn delays_double_up_to_max_and_stop_at_deadline() {
+        let mut backoff = Backoff::new(Duration::from_secs(60));
+        let delays: Vec<_> = (0..8).map(|_| backoff.next_delay().unwrap()).collect();
+        assert_eq!(delays[0], INITIAL_BACKOFF);
+        assert_eq!(delays[1], INITIAL_BACKOFF * 2);
+        assert_eq!(delays[7], MAX_BACKOFF);
+        assert_eq!(backoff.attempt(), 8);
+
+        let mut backoff = Backoff::new(Duration::ZERO);
+        assert_eq!(backoff.next_delay(), None);
+        assert_eq!(backoff.attempt(), 0);
+    }
+
+    #[test]
+    fn waiting_spends_the_deadline() {
+        let clock = Arc::new(ManualClock::new());
+        let mut backoff = Backoff::with_clock(Duration::from_millis(500), clock.clone());
+        let mut delays = Vec::new();
+        while let Some(delay) = backoff.next_delay() {
+            backoff.wait(delay);
+            delays.push(delay.as_millis());
+        }
+        // 最后一次等待被剩余期限截短
+        assert_eq!(delays, [50, 100, 200, 150]);
+        assert_eq!(clock.elapsed(), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,1156 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 请求/响应式调用
+//!
+//! 一次调用是一条请求消息和一条响应消息，编码方式由 [`Codec`] 决定：
+//!
+//! ```text
+//! 请求: service_len(1) | service | method_len(1) | method | payload
+//! 单向请求: 0 | flags(1) | 请求
+//! 带幂等键的请求: 0 | flags(1) | key_len(1) | key | 请求
+//! 带操作 ID 的请求: 0 | flags(1) | op_id(8) | [key_len(1) | key] | 请求
+//! 响应: status(1) | payload（成功）或错误描述（失败）
+//! ```
+//!
+//! 服务名不能为空，开头的 0 因此用来引出请求标志。客户端发起的每次调用都带有一个
+//! 操作 ID（[`OpId`]），两端的日志、[`VirgaEvent::CallFailed`] 事件与调用失败的错误中都
+//! 带有它（[`error::op_id()`] 取出），据此可在客户机与宿主机的日志中找到同一次调用。单向调用（[`notify`]）不等待
+//! 处理结果，调用方按 [`Delivery`] 选择确认级别：消息交给连接写出即返回，或等服务端
+//! 收到后、处理前回复的确认。
+//!
+//! 幂等调用（[`call_idempotent`]）带有调用方给出的幂等键，连接断开时自动重连并以同一个
+//! 键重发，直到收到响应或期限已到。服务端配置 `ServerConfig::with_idempotency()` 后按键
+//! 去重，重发的请求得到第一次的响应而不会再次执行处理函数。
+//!
+//! 只读调用可用 [`call_hedged`] 降低尾延迟：第一次请求超过给定时长仍未回复时，在连接池的
+//! 另一条连接上再发一次，取先到的响应。
+//!
+//! 启用 `codec-prost` 特性后，可用 [`prost_service!`](crate::prost_service) 从 prost
+//! 消息类型生成客户端与服务端桩代码。
+
+use std::io::{Error, ErrorKind, Result, Write};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::auth::MAX_NAME_LEN;
+use crate::client::{VirgeClient, VirgeClientPool};
+use crate::codec::Codec;
+use crate::error::{self, ConnContext, OpId};
+use crate::events::{self, Role, VirgaEvent};
+use crate::logging::log_event;
+use crate::retry::Backoff;
+use crate::server::VirgeServer;
+use crate::threads;
+
+const STATUS_OK: u8 = 0;
+const STATUS_PERMISSION_DENIED: u8 = 1;
+const STATUS_UNSUPPORTED: u8 = 2;
+const STATUS_INVALID_DATA: u8 = 3;
+const STATUS_FAILED: u8 = 4;
+const STATUS_BUSY: u8 = 5;
+const STATUS_NOT_FOUND: u8 = 6;
+
+/// 单向请求标志：服务端不回复处理结果
+const FLAG_ONEWAY: u8 = 1 << 0;
+/// 单向请求标志：服务端收到后、处理前回复确认
+const FLAG_ACK: u8 = 1 << 1;
+/// 请求标志：标志之后带有幂等键，不能与单向标志同时使用
+const FLAG_KEYED: u8 = 1 << 2;
+/// 请求标志：标志之后带有 8 字节操作 ID（大端），可与其他标志同时使用
+const FLAG_TRACED: u8 = 1 << 3;
+const FLAG_ACKED: u8 = FLAG_ONEWAY | FLAG_ACK;
+
+/// 单向调用的确认级别
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// 消息交给连接写出即返回，不等待服务端
+    Queued,
+    /// 等待服务端确认收到；确认在处理之前发出，不代表处理成功
+    Acked,
+}
+
+/// 单向调用的回执
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Receipt {
+    op_id: OpId,
+    delivery: Delivery,
+    bytes: usize,
+    elapsed: Duration,
+}
+
+impl Receipt {
+    /// 本次调用的操作 ID，服务端日志中带有同一个 ID
+    pub fn op_id(&self) -> OpId {
+        self.op_id
+    }
+
+    /// 回执对应的确认级别
+    pub fn delivery(&self) -> Delivery {
+        self.delivery
+    }
+
+    /// 请求消息的字节数
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// 从发送到达到确认级别的耗时
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+/// 服务端收到的一条请求
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Request {
+    service: String,
+    method: String,
+    payload: Vec<u8>,
+    delivery: Option<Delivery>,
+    idempotency_key: Option<String>,
+    op_id: Option<OpId>,
+}
+
+impl Request {
+    /// 服务名
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// 方法名
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// 编码后的请求参数
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// 单向调用的确认级别，普通调用为 `None`
+    pub fn delivery(&self) -> Option<Delivery> {
+        self.delivery
+    }
+
+    /// 幂等调用的键，其他调用为 `None`
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    /// 客户端为本次调用分配的操作 ID，旧版本的客户端不发送时为 `None`
+    pub fn op_id(&self) -> Option<OpId> {
+        self.op_id
+    }
+}
+
+fn put_name(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+    if name.len() > MAX_NAME_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("name {:?} longer than {} bytes", name, MAX_NAME_LEN),
+        ));
+    }
+    buf.push(name.len() as u8);
+    buf.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+fn take_name(msg: &mut &[u8]) -> Result<String> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "truncated rpc request");
+    let (&len, rest) = msg.split_first().ok_or_else(invalid)?;
+    if rest.len() < len as usize {
+        return Err(invalid());
+    }
+    let (name, rest) = rest.split_at(len as usize);
+    *msg = rest;
+    String::from_utf8(name.to_vec())
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "rpc name is not utf-8"))
+}
+
+/// 编码一条请求消息；服务名不能为空
+pub fn encode_request(service: &str, method: &str, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(4 + service.len() + method.len() + payload.len());
+    put_request(&mut buf, service, method, payload)?;
+    Ok(buf)
+}
+
+/// 编码一条单向请求消息
+pub fn encode_oneway(
+    service: &str,
+    method: &str,
+    payload: &[u8],
+    delivery: Delivery,
+) -> Result<Vec<u8>> {
+    let flags = match delivery {
+        Delivery::Queued => FLAG_ONEWAY,
+        Delivery::Acked => FLAG_ACKED,
+    };
+    let mut buf = Vec::with_capacity(4 + service.len() + method.len() + payload.len());
+    buf.extend_from_slice(&[0, flags]);
+    put_request(&mut buf, service, method, payload)?;
+    Ok(buf)
+}
+
+/// 编码一条带幂等键的请求消息；键不能为空
+pub fn encode_keyed_request(
+    service: &str,
+    method: &str,
+    key: &str,
+    payload: &[u8],
+) -> Result<Vec<u8>> {
+    if key.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "idempotency key cannot be empty",
+        ));
+    }
+    let mut buf = Vec::with_capacity(5 + key.len() + service.len() + method.len() + payload.len());
+    buf.extend_from_slice(&[0, FLAG_KEYED]);
+    put_name(&mut buf, key)?;
+    put_request(&mut buf, service, method, payload)?;
+    Ok(buf)
+}
+
+/// 为编码好的请求附上操作 ID，已带操作 ID 时替换
+pub fn trace_request(msg: &[u8], op: OpId) -> Vec<u8> {
+    let (flags, rest) = match msg {
+        [0, flags, rest @ ..] if flags & FLAG_TRACED != 0 => (*flags, rest.get(8..).unwrap_or(&[])),
+        [0, flags, rest @ ..] => (*flags, rest),
+        _ => (0, msg),
+    };
+    let mut buf = Vec::with_capacity(10 + rest.len());
+    buf.extend_from_slice(&[0, flags | FLAG_TRACED]);
+    buf.extend_from_slice(&op.as_u64().to_be_bytes());
+    buf.extend_from_slice(rest);
+    buf
+}
+
+fn put_request(buf: &mut Vec<u8>, service: &str, method: &str, payload: &[u8]) -> Result<()> {
+    if service.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "rpc service name cannot be empty",
+        ));
+    }
+    put_name(buf, service)?;
+    put_name(buf, method)?;
+    buf.extend_from_slice(payload);
+    Ok(())
+}
+
+/// 解码一条请求消息
+pub fn decode_request(mut msg: &[u8]) -> Result<Request> {
+    let mut op_id = None;
+    let (delivery, idempotency_key) = match msg {
+        [0, flags, rest @ ..] => {
+            msg = rest;
+            if flags & FLAG_TRACED != 0 {
+                let (op, rest) = msg
+                    .split_first_chunk::<8>()
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "truncated rpc request"))?;
+                op_id = Some(OpId::from(u64::from_be_bytes(*op)));
+                msg = rest;
+            }
+            let delivery = match flags & !FLAG_TRACED {
+                0 if op_id.is_some() => None,
+                FLAG_ONEWAY => Some(Delivery::Queued),
+                FLAG_ACKED => Some(Delivery::Acked),
+                FLAG_KEYED => None,
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("unknown rpc request flags {:#04x}", flags),
+                    ))
+                }
+            };
+            let key = match flags & !FLAG_TRACED {
+                FLAG_KEYED => Some(take_name(&mut msg)?),
+                _ => None,
+            };
+            (delivery, key)
+        }
+        _ => (None, None),
+    };
+    let service = take_name(&mut msg)?;
+    let method = take_name(&mut msg)?;
+    Ok(Request {
+        service,
+        method,
+        payload: msg.to_vec(),
+        delivery,
+        idempotency_key,
+        op_id,
+    })
+}
+
+/// 编码一条响应消息；错误只保留类别与描述
+pub fn encode_response(result: Result<Vec<u8>>) -> Vec<u8> {
+    match result {
+        Ok(mut payload) => {
+            payload.insert(0, STATUS_OK);
+            payload
+        }
+        Err(e) => {
+            let status = match e.kind() {
+                ErrorKind::PermissionDenied => STATUS_PERMISSION_DENIED,
+                ErrorKind::Unsupported => STATUS_UNSUPPORTED,
+                ErrorKind::InvalidData => STATUS_INVALID_DATA,
+                ErrorKind::ResourceBusy => STATUS_BUSY,
+                ErrorKind::NotFound => STATUS_NOT_FOUND,
+                _ => STATUS_FAILED,
+            };
+            let mut buf = vec![status];
+            buf.extend_from_slice(e.to_string().as_bytes());
+            buf
+        }
+    }
+}
+
+/// 解码一条响应消息，服务端返回的错误还原为对应类别的 `io::Error`
+pub fn decode_response(mut msg: Vec<u8>) -> Result<Vec<u8>> {
+    if msg.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidData, "empty rpc response"));
+    }
+    let status = msg.remove(0);
+    let kind = match status {
+        STATUS_OK => return Ok(msg),
+        STATUS_PERMISSION_DENIED => ErrorKind::PermissionDenied,
+        STATUS_UNSUPPORTED => ErrorKind::Unsupported,
+        STATUS_INVALID_DATA => ErrorKind::InvalidData,
+        STATUS_BUSY => ErrorKind::ResourceBusy,
+        STATUS_NOT_FOUND => ErrorKind::NotFound,
+        _ => ErrorKind::Other,
+    };
+    Err(Error::new(kind, String::from_utf8_lossy(&msg).into_owned()))
+}
+
+/// 客户端发起一次调用并等待响应
+pub fn call<Req, Resp, C>(
+    client: &mut VirgeClient,
+    codec: &C,
+    service: &str,
+    method: &str,
+    request: &Req,
+) -> Result<Resp>
+where
+    C: Codec<Req> + Codec<Resp>,
+{
+    call_traced(client, codec, service, method, request).map(|(_, response)| response)
+}
+
+/// 与 [`call`] 相同，另外返回本次调用的操作 ID；失败时用 [`error::op_id()`] 从错误中取出
+pub fn call_traced<Req, Resp, C>(
+    client: &mut VirgeClient,
+    codec: &C,
+    service: &str,
+    method: &str,
+    request: &Req,
+) -> Result<(OpId, Resp)>
+where
+    C: Codec<Req> + Codec<Resp>,
+{
+    let op = OpId::new();
+    let result = codec
+        .encode(request)
+        .and_then(|payload| encode_request(service, method, &payload))
+        .and_then(|msg| client.send(trace_request(&msg, op)))
+        .and_then(|_| client.recv())
+        .and_then(decode_response)
+        .and_then(|response| codec.decode(&response));
+    match result {
+        Ok(response) => Ok((op, response)),
+        Err(e) => Err(call_failed(
+            Role::Client,
+            client.conn(),
+            op,
+            service,
+            method,
+            e,
+        )),
+    }
+}
+
+/// 客户端发起一次单向调用，不等待处理结果：`Delivery::Queued` 在消息交给连接写出
+/// （开启合批时先发出批次）后返回，`Delivery::Acked` 再等服务端确认收到；
+/// 服务端拒绝（未知服务、未授权）时确认携带对应的错误
+pub fn notify<Req, C>(
+    client: &mut VirgeClient,
+    codec: &C,
+    service: &str,
+    method: &str,
+    request: &Req,
+    delivery: Delivery,
+) -> Result<Receipt>
+where
+    C: Codec<Req>,
+{
+    let started = Instant::now();
+    let op = OpId::new();
+    let result = codec
+        .encode(request)
+        .and_then(|payload| encode_oneway(service, method, &payload, delivery))
+        .and_then(|msg| client.send(trace_request(&msg, op)))
+        .and_then(|bytes| {
+            client.flush()?;
+            if delivery == Delivery::Acked {
+                decode_response(client.recv()?)?;
+            }
+            Ok(bytes)
+        });
+    match result {
+        Ok(bytes) => Ok(Receipt {
+            op_id: op,
+            delivery,
+            bytes,
+            elapsed: started.elapsed(),
+        }),
+        Err(e) => Err(call_failed(
+            Role::Client,
+            client.conn(),
+            op,
+            service,
+            method,
+            e,
+        )),
+    }
+}
+
+/// 发起一次幂等调用：连接断开或对端未就绪时重连，并以同一个 `key` 重发，直到收到响应或
+/// `deadline` 已到（此时返回最后一次的错误）。服务端配置了 `ServerConfig::with_idempotency()`
+/// 时同一个键的处理函数只执行一次；服务端返回的错误不重试。`key` 应在调用方的业务中唯一，
+/// 如订单号或随机生成的请求 ID
+pub fn call_idempotent<Req, Resp, C>(
+    client: &mut VirgeClient,
+    codec: &C,
+    service: &str,
+    method: &str,
+    key: &str,
+    request: &Req,
+    deadline: Duration,
+) -> Result<Resp>
+where
+    C: Codec<Req> + Codec<Resp>,
+{
+    // 每次重发都带同一个操作 ID
+    let op = OpId::new();
+    let fail =
+        |client: &VirgeClient, e| call_failed(Role::Client, client.conn(), op, service, method, e);
+    let payload = codec.encode(request).map_err(|e| fail(client, e))?;
+    let msg = encode_keyed_request(service, method, key, &payload)
+        .map(|msg| trace_request(&msg, op))
+        .map_err(|e| fail(client, e))?;
+    let mut backoff = Backoff::new(deadline);
+    let mut reconnect = !client.is_connected();
+    loop {
+        let sent = if reconnect { client.connect() } else { Ok(()) }
+            .and_then(|_| client.send(msg.clone()))
+            .and_then(|_| client.recv());
+        let e = match sent {
+            Ok(response) => {
+                return decode_response(response)
+                    .and_then(|response| codec.decode(&response))
+                    .map_err(|e| fail(client, e))
+            }
+            Err(e) if crate::client::is_unavailable(&e) => e,
+            Err(e) => return Err(fail(client, e)),
+        };
+        let Some(delay) = backoff.next_delay() else {
+            return Err(fail(client, e));
+        };
+        warn!(
+            "Call {}/{} (op {}) with key {:?} failed ({}), retrying (attempt {})",
+            service,
+            method,
+            op,
+            key,
+            e,
+            backoff.attempt()
+        );
+        // 丢弃旧连接上残留的数据，断开失败不影响重连
+        client.resync();
+        let _ = client.disconnect();
+        reconnect = true;
+        backoff.wait(delay);
+    }
+}
+
+/// 发起一次对冲调用：从 `pool` 取一条连接发送请求，`after` 之后仍未回复时再从池中取
+/// 另一条连接发送同一请求，返回先到的成功响应；一次失败时不再等待，立即改用另一条连接。
+/// 两次都失败时返回第一个错误。
+///
+/// 同一请求可能被服务端处理两次（两次带同一个操作 ID），只用于只读或幂等的方法。
+/// 每次请求在单独的线程（`<前缀>-hedge`）中收发，较慢的一次完成后把连接归还到池中
+pub fn call_hedged<Req, Resp, C>(
+    pool: &Arc<VirgeClientPool>,
+    codec: &C,
+    service: &str,
+    method: &str,
+    request: &Req,
+    after: Duration,
+) -> Result<Resp>
+where
+    C: Codec<Req> + Codec<Resp>,
+{
+    let op = OpId::new();
+    let fail = |e| call_failed(Role::Client, ConnContext::default(), op, service, method, e);
+    let payload = codec.encode(request).map_err(fail)?;
+    let msg = encode_request(service, method, &payload)
+        .map(|msg| trace_request(&msg, op))
+        .map_err(fail)?;
+    let (tx, rx) = mpsc::channel();
+    let mut pending = 0;
+    let mut first_error = None;
+    match start_attempt(pool, msg.clone(), tx.clone()) {
+        Ok(()) => pending += 1,
+        Err(e) => {
+            first_error.get_or_insert(e);
+        }
+    }
+    let mut hedged = false;
+    loop {
+        if pending > 0 {
+            let outcome = if hedged {
+                rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                rx.recv_timeout(after)
+            };
+            match outcome {
+                Ok(Ok(response)) => {
+                    return decode_response(response)
+                        .and_then(|response| codec.decode(&response))
+                        .map_err(fail)
+                }
+                Ok(Err(e)) => {
+                    pending -= 1;
+                    first_error.get_or_insert(e);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    debug!(
+                        "Hedging {}/{} (op {}) after {:?}",
+                        service, method, op, after
+                    )
+                }
+                // 发送端由本函数持有，不会全部断开
+                Err(RecvTimeoutError::Disconnected) => unreachable!("hedge sender dropped"),
+            }
+        }
+        if !hedged {
+            hedged = true;
+            match start_attempt(pool, msg.clone(), tx.clone()) {
+                Ok(()) => pending += 1,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if pending == 0 {
+            return Err(fail(first_error.expect("failed attempt")));
+        }
+    }
+}
+
+/// 记录失败的调用并发布 [`VirgaEvent::CallFailed`]，返回带上操作 ID 的错误
+fn call_failed(
+    role: Role,
+    conn: ConnContext,
+    op: OpId,
+    service: &str,
+    method: &str,
+    e: Error,
+) -> Error {
+    report_failure(Level::Info, role, conn, Some(op), service, method, &e);
+    error::with_op(e, op)
+}
+
+fn report_failure(
+    level: Level,
+    role: Role,
+    conn: ConnContext,
+    op: Option<OpId>,
+    service: &str,
+    method: &str,
+    e: &Error,
+) {
+    let op_id = op.map(|op| op.to_string()).unwrap_or_default();
+    log_event!(
+        level,
+        "call failed",
+        role = if role == Role::Client {
+            "client"
+        } else {
+            "server"
+        },
+        conn_id = conn.conn_id,
+        cid = conn.cid,
+        op_id = op_id.as_str(),
+        service = service,
+        method = method,
+        error = e.to_string().as_str(),
+    );
+    events::emit(VirgaEvent::CallFailed {
+        role,
+        conn,
+        op,
+        service: service.to_string(),
+        method: method.to_string(),
+        reason: e.to_string(),
+    });
+}
+
+/// 在单独的线程中用池中的一条连接完成一次请求，结果发到 `results`
+fn start_attempt(
+    pool: &Arc<VirgeClientPool>,
+    msg: Vec<u8>,
+    results: Sender<Result<Vec<u8>>>,
+) -> Result<()> {
+    let mut client = pool.get()?;
+    let pool = pool.clone();
+    threads::spawn("hedge", move || {
+        let result = client.send(msg).and_then(|_| client.recv());
+        // 调用方已取走另一次的响应时丢弃本次结果
+        let _ = results.send(result);
+        pool.put(client);
+    })?;
+    Ok(())
+}
+
+/// 服务端接收下一条请求
+pub fn recv_request(server: &mut VirgeServer) -> Result<Request> {
+    let msg = server.recv_loan()?;
+    decode_request(&msg)
+}
+
+/// 服务端回复当前请求
+pub fn send_response(server: &mut VirgeServer, result: Result<Vec<u8>>) -> Result<()> {
+    server.send(encode_response(result))?;
+    Ok(())
+}
+
+/// 解码请求参数、调用 `handler` 并编码返回值
+pub fn handle<Req, Resp, C, F>(codec: &C, payload: &[u8], handler: F) -> Result<Vec<u8>>
+where
+    C: Codec<Req> + Codec<Resp>,
+    F: FnOnce(Req) -> Result<Resp>,
+{
+    let request = codec.decode(payload)?;
+    let response = handler(request)?;
+    codec.encode(&response)
+}
+
+/// 服务端处理一条请求：检查服务名与授权、申请处理函数名额（见
+/// `ServerConfig::with_handler_limits()`）后交给 `dispatch`，并回复结果。
+/// 调用失败只回复给客户端并记录（带客户端给出的操作 ID），连接错误才返回。
+/// 单向请求不回复处理结果，失败只记录日志；要求确认的在处理前回复确认
+pub fn serve_one<F>(server: &mut VirgeServer, service: &str, dispatch: F) -> Result<()>
+where
+    F: FnOnce(&str, &[u8]) -> Result<Vec<u8>>,
+{
+    let request = recv_request(server)?;
+    let accepted = if request.service() != service {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("unknown service {:?}", request.service()),
+        ))
+    } else {
+        server.authorize(request.service(), request.method())
+    };
+    let accepted = accepted.and_then(|_| server.admit());
+    let conn = server.conn();
+    // 单向调用的失败没有回复，只能从日志得知
+    let level = match request.delivery() {
+        Some(_) => Level::Warn,
+        None => Level::Info,
+    };
+    let reported = |result: Result<Vec<u8>>| {
+        if let Err(e) = &result {
+            let (service, method) = (request.service(), request.method());
+            report_failure(
+                level,
+                Role::Server,
+                conn,
+                request.op_id(),
+                service,
+                method,
+                e,
+            );
+        }
+        result
+    };
+    let Some(delivery) = request.delivery() else {
+        // 幂等调用只在通过检查后去重，被拒绝的调用重发时重新检查
+        let response = match (accepted, request.idempotency_key()) {
+            (Ok(_permit), Some(key)) => server.run_idempotent(key, || {
+                encode_response(reported(dispatch(request.method(), request.payload())))
+            }),
+            (accepted, _) => encode_response(reported(
+                accepted.and_then(|_permit| dispatch(request.method(), request.payload())),
+            )),
+        };
+        server.send(response)?;
+        return Ok(());
+    };
+
+    if delivery == Delivery::Acked {
+        let ack = match &accepted {
+            Ok(_) => Ok(Vec::new()),
+            Err(e) => Err(Error::new(e.kind(), e.to_string())),
+        };
+        send_response(server, ack)?;
+        server.flush()?;
+    }
+    let _ = reported(accepted.and_then(|_permit| dispatch(request.method(), request.payload())));
+    Ok(())
+}
+
+/// 对端关闭连接时 `serve` 正常返回
+pub(crate) fn is_closed(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::UnexpectedEof
+    )
+}
+
+/// 持续处理请求，直到对端关闭连接
+pub fn serve<F>(server: &mut VirgeServer, service: &str, mut dispatch: F) -> Result<()>
+where
+    F: FnMut(&str, &[u8]) -> Result<Vec<u8>>,
+{
+    loop {
+        match serve_one(server, service, &mut dispatch) {
+            Ok(()) => {}
+            Err(e) if is_closed(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// 从 prost 消息类型生成服务的客户端与服务端桩代码（需启用 `codec-prost` 特性）
+///
+/// 生成的模块包含：
+///
+/// - `NAME`：服务名
+/// - `Service` trait：服务端实现其中的方法
+/// - `Client`：借用已连接的 `VirgeClient`，每个方法发起一次调用
+/// - `dispatch()`/`serve_one()`/`serve()`：服务端按方法名分发请求
+///
+/// ```ignore
+/// virga::prost_service! {
+///     /// 回显服务
+///     pub mod echo ("demo.Echo") {
+///         fn say(EchoRequest) -> EchoReply;
+///     }
+/// }
+///
+/// // 客户端
+/// let reply = echo::Client::new(&mut client).say(&EchoRequest { text: "hi".into() })?;
+///
+/// // 服务端
+/// struct Impl;
+/// impl echo::Service for Impl {
+///     fn say(&mut self, request: EchoRequest) -> std::io::Result<EchoReply> {
+///         Ok(EchoReply { text: request.text })
+///     }
+/// }
+/// echo::serve(&mut server, &mut Impl)?;
+/// ```
+#[cfg(feature = "codec-prost")]
+#[macro_export]
+macro_rules! prost_service {
+    (
+        $(#[$attr:meta])*
+        $vis:vis mod $module:ident ($name:literal) {
+            $(
+                $(#[$method_attr:meta])*
+                fn $method:ident($request:ty) -> $response:ty;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis mod $module {
+            #[allow(unused_imports)]
+            use super::*;
+
+            /// 服务名
+            pub const NAME: &str = $name;
+
+            /// 服务端实现
+            pub trait Service {
+                $(
+                    $(#[$method_attr])*
+                    fn $method(&mut self, request: $request) -> ::std::io::Result<$response>;
+                )*
+            }
+
+            /// 客户端桩
+            pub struct Client<'a> {
+                conn: &'a mut $crate::client::VirgeClient,
+            }
+
+            impl<'a> Client<'a> {
+                /// 借用一个已连接的客户端
+                pub fn new(conn: &'a mut $crate::client::VirgeClient) -> Self {
+                    Self { conn }
+                }
+
+                $(
+                    $(#[$method_attr])*
+                    pub fn $method(&mut self, request: &$request) -> ::std::io::Result<$response> {
+                        $crate::rpc::call(
+                            self.conn,
+                            &$crate::codec::ProstCodec,
+                            NAME,
+                            stringify!($method),
+                            request,
+                        )
+                    }
+                )*
+            }
+
+            /// 按方法名调用 `service`，未知方法返回 `Unsupported`
+            pub fn dispatch<S: Service + ?Sized>(
+                service: &mut S,
+                method: &str,
+                payload: &[u8],
+            ) -> ::std::io::Result<::std::vec::Vec<u8>> {
+                match method {
+                    $(
+                        stringify!($method) => $crate::rpc::handle(
+                            &$crate::codec::ProstCodec,
+                            payload,
+                            |request| service.$method(request),
+                        ),
+                    )*
+                    _ => Err(::std::io::Error::new(
+                        ::std::io::ErrorKind::Unsupported,
+                        format!("unknown method {}/{}", NAME, method),
+                    )),
+                }
+            }
+
+            /// 处理一条请求
+            pub fn serve_one<S: Service + ?Sized>(
+                conn: &mut $crate::server::VirgeServer,
+                service: &mut S,
+            ) -> ::std::io::Result<()> {
+                $crate::rpc::serve_one(conn, NAME, |method, payload| {
+                    dispatch(service, method, payload)
+                })
+            }
+
+            /// 持续处理请求，直到对端关闭连接
+            pub fn serve<S: Service + ?Sized>(
+                conn: &mut $crate::server::VirgeServer,
+                service: &mut S,
+            ) -> ::std::io::Result<()> {
+                $crate::rpc::serve(conn, NAME, |method, payload| {
+                    dispatch(service, method, payload)
+                })
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let msg = encode_request("demo.Echo", "say", b"payload").unwrap();
+        let request = decode_request(&msg).unwrap();
+        assert_eq!(request.service(), "demo.Echo");
+        assert_eq!(request.method(), "say");
+        assert_eq!(request.payload(), b"payload");
+
+        let long = "x".repeat(MAX_NAME_LEN + 1);
+        let err = encode_request(&long, "say", b"").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = decode_request(&[5, b'a']).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(request.delivery(), None);
+        let err = encode_request("", "say", b"").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn oneway_request_carries_delivery() {
+        for delivery in [Delivery::Queued, Delivery::Acked] {
+            let msg = encode_oneway("demo.Log", "append", b"line", delivery).unwrap();
+            let request = decode_request(&msg).unwrap();
+            assert_eq!(request.delivery(), Some(delivery));
+            assert_eq!(request.service(), "demo.Log");
+            assert_eq!(request.method(), "append");
+            assert_eq!(request.payload(), b"line");
+        }
+        let err = decode_request(&[0, FLAG_ACK, 1, b'a', 0]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn keyed_request_carries_idempotency_key() {
+        let msg = encode_keyed_request("demo.Orders", "place", "order-17", b"item").unwrap();
+        let request = decode_request(&msg).unwrap();
+        assert_eq!(request.idempotency_key(), Some("order-17"));
+        assert_eq!(request.delivery(), None);
+        assert_eq!(request.service(), "demo.Orders");
+        assert_eq!(request.payload(), b"item");
+
+        let err = encode_keyed_request("demo.Orders", "place", "", b"").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = decode_request(&[0, FLAG_ONEWAY | FLAG_KEYED, 1, b'k', 1, b'a', 0]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = decode_request(&[0, FLAG_KEYED, 3, b'k']).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn traced_request_carries_op_id() {
+        let op = OpId::from(0x0102_0304_0506_0708);
+        let plain = encode_request("demo.Echo", "say", b"hi").unwrap();
+        let oneway = encode_oneway("demo.Log", "append", b"line", Delivery::Acked).unwrap();
+        let keyed = encode_keyed_request("demo.Orders", "place", "order-17", b"item").unwrap();
+        for msg in [plain, oneway, keyed] {
+            let untraced = decode_request(&msg).unwrap();
+            assert_eq!(untraced.op_id(), None);
+            let traced = trace_request(&msg, op);
+            assert_eq!(traced[..10], [0, traced[1], 1, 2, 3, 4, 5, 6, 7, 8]);
+            let request = decode_request(&traced).unwrap();
+            assert_eq!(request.op_id(), Some(op));
+            assert_eq!(
+                (request.delivery(), request.idempotency_key()),
+                (untraced.delivery(), untraced.idempotency_key())
+            );
+            assert_eq!(request.payload(), untraced.payload());
+
+            // 重新附上操作 ID 时替换旧的
+            let retraced = decode_request(&trace_request(&traced, OpId::from(9))).unwrap();
+            assert_eq!(retraced.op_id(), Some(OpId::from(9)));
+            assert_eq!(retraced.service(), untraced.service());
+        }
+        let err = decode_request(&[0, FLAG_TRACED, 1, 2]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = decode_request(&[0, 0, 1, b'a', 0]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    /// `fixtures/rpc.txt` 中错误类别的写法
+    fn fixture_kind(name: &str) -> ErrorKind {
+        match name {
+            "permission_denied" => ErrorKind::PermissionDenied,
+            "unsupported" => ErrorKind::Unsupported,
+            "invalid_data" => ErrorKind::InvalidData,
+            "resource_busy" => ErrorKind::ResourceBusy,
+            "not_found" => ErrorKind::NotFound,
+            "other" => ErrorKind::Other,
+            other => panic!("unknown error kind {:?} in fixture", other),
+        }
+    }
+
+    #[test]
+    fn wire_format_matches_fixtures() {
+        let steps = crate::fixtures::load("rpc.txt");
+        let mut written = Vec::new();
+        for step in &steps {
+            let context = step.words.join(" ");
+            // 请求的最后一个词可以是 `op=<十六进制>`
+            let op = step
+                .words
+                .last()
+                .and_then(|word| word.strip_prefix("op="))
+                .map(|op| op.parse::<OpId>().unwrap());
+            let (service, method) = (step.word(1), step.word(2));
+            let (msg, delivery, key) = match step.word(0) {
+                "request" => (
+                    encode_request(service, method, &step.payload(3)).unwrap(),
+                    None,
+                    None,
+                ),
+                "oneway" => {
+                    let delivery = match step.word(3) {
+                        "queued" => Delivery::Queued,
+                        "acked" => Delivery::Acked,
+                        other => panic!("unknown delivery {:?}", other),
+                    };
+                    let msg = encode_oneway(service, method, &step.payload(4), delivery).unwrap();
+                    (msg, Some(delivery), None)
+                }
+                "keyed" => (
+                    encode_keyed_request(service, method, step.word(3), &step.payload(4)).unwrap(),
+                    None,
+                    Some(step.word(3)),
+                ),
+                "ok" => {
+                    let msg = encode_response(Ok(step.payload(1)));
+                    assert_eq!(decode_response(msg.clone()).unwrap(), step.payload(1));
+                    written.push(vec![msg]);
+                    continue;
+                }
+                "error" => {
+                    let (kind, text) = (fixture_kind(step.word(1)), step.words[2..].join(" "));
+                    let msg = encode_response(Err(Error::new(kind, text.clone())));
+                    let err = decode_response(msg.clone()).unwrap_err();
+                    assert_eq!((err.kind(), err.to_string()), (kind, text), "{}", context);
+                    written.push(vec![msg]);
+                    continue;
+                }
+                other => panic!("unknown rpc fixture step {:?}", other),
+            };
+            let msg = match op {
+                Some(op) => trace_request(&msg, op),
+                None => msg,
+            };
+            let request = decode_request(&msg).unwrap();
+            assert_eq!(request.service(), service, "{}", context);
+            assert_eq!(request.method(), method, "{}", context);
+            assert_eq!(request.delivery(), delivery, "{}", context);
+            assert_eq!(request.idempotency_key(), key, "{}", context);
+            assert_eq!(request.op_id(), op, "{}", context);
+            written.push(vec![msg]);
+        }
+        // 编码结果与样例逐字节相同，解码样例因此得到上面检查过的各字段
+        crate::fixtures::verify("rpc.txt", &steps, &written);
+    }
+
+    #[test]
+    fn hedged_call_returns_first_error_when_every_attempt_fails() {
+        use crate::client::ClientConfig;
+        use crate::codec::RawCodec;
+
+        // 端口 0 不合法，每次取连接都立即失败
+        let pool = Arc::new(VirgeClientPool::new(ClientConfig::new(3, 0, 1024, false)));
+        let started = Instant::now();
+        let err = call_hedged::<Vec<u8>, Vec<u8>, _>(
+            &pool,
+            &RawCodec,
+            "demo.Echo",
+            "say",
+            &b"hi".to_vec(),
+            Duration::from_secs(10),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn failed_calls_carry_their_op_id() {
+        use crate::client::ClientConfig;
+        use crate::codec::RawCodec;
+
+        let events = events::subscribe();
+        let mut client = VirgeClient::new(ClientConfig::new(3, 1234, 1024, false));
+        let err = call::<Vec<u8>, Vec<u8>, _>(&mut client, &RawCodec, "demo.Echo", "say", &vec![])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        let op = error::op_id(&err).unwrap();
+        assert!(err.to_string().contains(&op.to_string()));
+        let event = events
+            .into_iter()
+            .find(|e| matches!(e, VirgaEvent::CallFailed { op: Some(o), .. } if *o == op))
+            .unwrap();
+        assert!(matches!(
+            event,
+            VirgaEvent::CallFailed { role: Role::Client, ref method, .. } if method == "say"
+        ));
+
+        let err = notify(
+            &mut client,
+            &RawCodec,
+            "demo.Log",
+            "append",
+            &vec![],
+            Delivery::Queued,
+        )
+        .unwrap_err();
+        assert_ne!(error::op_id(&err), Some(op));
+        assert!(error::op_id(&err).is_some());
+    }
+
+    #[test]
+    fn response_carries_error_kind() {
+        assert_eq!(
+            decode_response(encode_response(Ok(vec![1, 2]))).unwrap(),
+            vec![1, 2]
+        );
+        for kind in [
+            ErrorKind::PermissionDenied,
+            ErrorKind::Unsupported,
+            ErrorKind::InvalidData,
+            ErrorKind::ResourceBusy,
+            ErrorKind::NotFound,
+        ] {
+            let msg = encode_response(Err(Error::new(kind, "nope")));
+            let err = decode_response(msg).unwrap_err();
+            assert_eq!(err.kind(), kind);
+            assert_eq!(err.to_string(), "nope");
+        }
+        let msg = encode_response(Err(Error::from(ErrorKind::TimedOut)));
+        assert_eq!(decode_response(msg).unwrap_err().kind(), ErrorKind::Other);
+        assert!(decode_response(Vec::new()).is_err());
+    }
+
+    #[cfg(feature = "codec-prost")]
+    mod generated {
+        use std::io::{ErrorKind, Result};
+
+        #[derive(Clone, PartialEq, prost::Message)]
+        pub struct EchoRequest {
+            #[prost(string, tag = "1")]
+            pub text: String,
+        }
+
+        #[derive(Clone, PartialEq, prost::Message)]
+        pub struct EchoReply {
+            #[prost(string, tag = "1")]
+            pub text: String,
+        }
+
+        crate::prost_service! {
+            // 测试中不会构造 Client
+            #[allow(dead_code)]
+            mod echo ("demo.Echo") {
+                fn say(EchoRequest) -> EchoReply;
+                fn shout(EchoRequest) -> EchoReply;
+            }
+        }
+
+        struct Impl;
+
+        impl echo::Service for Impl {
+            fn say(&mut self, request: EchoRequest) -> Result<EchoReply> {
+                Ok(EchoReply { text: request.text })
+            }
+
+            fn shout(&mut self, request: EchoRequest) -> Result<EchoReply> {
+                Ok(EchoReply {
+                    text: request.text.to_uppercase(),
+                })
+            }
+        }
+
+        #[test]
+        fn dispatch_by_method_name() {
+            use crate::codec::{Codec, ProstCodec};
+
+            let request = ProstCodec
+                .encode(&EchoRequest {
+                    text: "hi".to_string(),
+                })
+                .unwrap();
+            let reply = echo::dispatch(&mut Impl, "shout", &request).unwrap();
+            let reply: EchoReply = ProstCodec.decode(&reply).unwrap();
+            assert_eq!(reply.text, "HI");
+            assert_eq!(echo::NAME, "demo.Echo");
+
+            let err = echo::dispatch(&mut Impl, "whisper", &request).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+            let err = echo::dispatch(&mut Impl, "say", &[0xff]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
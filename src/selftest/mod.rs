@@ -0,0 +1,381 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 应用层自检
+//!
+//! 启动诊断需要确认的不只是“能连上”：消息能否完整往返、大消息的分片与重组是否正常、
+//! 交错发送的多组消息乱序返回后能否按编号对上。`VirgeClient::self_test()` 依次执行
+//! 内置的检查并返回 [`SelfTestReport`]，对端调用 `VirgeServer::serve_self_test()` 配合：
+//!
+//! | 检查 | 内容 |
+//! |------|------|
+//! | `ping` | 传输层 ping 的往返时长 |
+//! | `echo-small` | 64 字节消息回显 |
+//! | `echo-1mb` | 1 MiB 消息回显，报告吞吐 |
+//! | `out-of-order-streams` | 4 组各 8 条消息交错发出，对端收齐后倒序回复，按组号与序号核对 |
+//!
+//! 单项失败不影响之后的检查；每项等待回复的期限为 [`SELF_TEST_TIMEOUT`]。
+//!
+//! 自检消息：
+//!
+//! ```text
+//! magic "VSLF"(4) | op(1) | stream(4, 大端) | seq(4, 大端) | payload
+//! ```
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, Instant};
+
+use crate::transport::Transport;
+
+/// 每项检查等待回复的期限
+pub const SELF_TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MAGIC: &[u8; 4] = b"VSLF";
+const HEADER: usize = 13;
+/// 交错发送的组数与每组消息数
+const STREAMS: u32 = 4;
+const PER_STREAM: u32 = 8;
+
+const OP_ECHO: u8 = 1;
+/// 对端暂存，收到 `OP_RELEASE` 后倒序回复
+const OP_HOLD: u8 = 2;
+const OP_RELEASE: u8 = 3;
+/// 结束自检，对端回复后 `serve_self_test()` 返回
+const OP_END: u8 = 4;
+
+/// 一项检查的结果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfTestCheck {
+    pub name: &'static str,
+    /// 检查耗时，ping 为往返时长
+    pub elapsed: Duration,
+    /// 往返的负载字节数（单向）
+    pub bytes: usize,
+    /// 失败原因，通过时为 `None`
+    pub failure: Option<String>,
+}
+
+impl SelfTestCheck {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// `self_test()` 的结果，按执行顺序列出各项检查
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub checks: Vec<SelfTestCheck>,
+    /// 自检总耗时
+    pub elapsed: Duration,
+}
+
+impl SelfTestReport {
+    /// 各项检查是否都通过
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(SelfTestCheck::passed)
+    }
+
+    /// 未通过的检查
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestCheck> {
+        self.checks.iter().filter(|check| !check.passed())
+    }
+
+    /// 名为 `name` 的检查
+    pub fn check(&self, name: &str) -> Option<&SelfTestCheck> {
+        self.checks.iter().find(|check| check.name == name)
+    }
+}
+
+/// 每项一行，便于写入启动日志
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let passed = self.checks.iter().filter(|check| check.passed()).count();
+        write!(
+            f,
+            "self-test {}/{} passed in {:?}",
+            passed,
+            self.checks.len(),
+            self.elapsed
+        )?;
+        for check in &self.checks {
+            write!(f, "\n  {}: ", check.name)?;
+            match &check.failure {
+                Some(reason) => write!(f, "FAILED ({})", reason)?,
+                None => write!(f, "ok, {:?}", check.elapsed)?,
+            }
+            if check.passed() && check.bytes >= 1 << 20 {
+                let secs = check.elapsed.as_secs_f64().max(f64::EPSILON);
+                write!(
+                    f,
+                    ", {:.1} MiB/s",
+                    check.bytes as f64 / secs / (1 << 20) as f64
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn encode(op: u8, stream: u32, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER + payload.len());
+    message.extend_from_slice(MAGIC);
+    message.push(op);
+    message.extend_from_slice(&stream.to_be_bytes());
+    message.extend_from_slice(&seq.to_be_bytes());
+    message.extend_from_slice(payload);
+    message
+}
+
+/// 拆出 `(op, stream, seq, payload)`，不是自检消息时返回 `None`
+fn decode(message: &[u8]) -> Option<(u8, u32, u32, &[u8])> {
+    let (header, payload) = message.split_first_chunk::<HEADER>()?;
+    if &header[..4] != MAGIC {
+        return None;
+    }
+    let stream = u32::from_be_bytes(header[5..9].try_into().unwrap());
+    let seq = u32::from_be_bytes(header[9..13].try_into().unwrap());
+    Some((header[4], stream, seq, payload))
+}
+
+/// 每组消息的内容由组号与序号决定，回复与原消息对不上时可以发现
+fn pattern(stream: u32, seq: u32, len: usize) -> Vec<u8> {
+    let seed = stream.wrapping_mul(31).wrapping_add(seq) as usize;
+    (0..len)
+        .map(|i| (i.wrapping_add(seed) % 251) as u8)
+        .collect()
+}
+
+/// 在 `chan` 上执行全部检查，`ping` 执行传输层 ping
+pub(crate) fn run<T: Transport + ?Sized>(
+    chan: &mut T,
+    ping: impl FnOnce(&mut T) -> crate::Result<Duration>,
+) -> crate::Result<SelfTestReport> {
+    let started = Instant::now();
+    chan.set_idle_timeout(Some(SELF_TEST_TIMEOUT))?;
+    let mut checks = vec![match ping(chan) {
+        Ok(round_trip) => SelfTestCheck {
+            name: "ping",
+            elapsed: round_trip,
+            bytes: 0,
+            failure: None,
+        },
+        Err(e) => SelfTestCheck {
+            name: "ping",
+            elapsed: Duration::ZERO,
+            bytes: 0,
+            failure: Some(e.to_string()),
+        },
+    }];
+    checks.push(check("echo-small", 64, || echo(chan, 1, 64)));
+    checks.push(check("echo-1mb", 1 << 20, || echo(chan, 2, 1 << 20)));
+    checks.push(check(
+        "out-of-order-streams",
+        (STREAMS * PER_STREAM) as usize * 256,
+        || out_of_order(chan),
+    ));
+    // 对端可能已不在配合，结束消息的回复只尽力等待
+    let end = (|| {
+        chan.send(&encode(OP_END, 0, 0, &[]))?;
+        chan.flush()?;
+        reply(chan, OP_END, |_, _| true).map(drop)
+    })();
+    if let Err(e) = end {
+        log::debug!("Peer did not confirm the end of the self-test: {}", e);
+    }
+    chan.set_idle_timeout(None)?;
+    Ok(SelfTestReport {
+        checks,
+        elapsed: started.elapsed(),
+    })
+}
+
+fn check(
+    name: &'static str,
+    bytes: usize,
+    run: impl FnOnce() -> crate::Result<()>,
+) -> SelfTestCheck {
+    let started = Instant::now();
+    let failure = run().err().map(|e| e.to_string());
+    SelfTestCheck {
+        name,
+        elapsed: started.elapsed(),
+        bytes,
+        failure,
+    }
+}
+
+fn echo<T: Transport + ?Sized>(chan: &mut T, stream: u32, len: usize) -> crate::Result<()> {
+    let payload = pattern(stream, 0, len);
+    chan.send(&encode(OP_ECHO, stream, 0, &payload))?;
+    chan.flush()?;
+    let (_, _, echoed) = reply(chan, OP_ECHO, |s, _| s == stream)?;
+    if echoed != payload {
+        return Err(mismatch(format!(
+            "{} byte echo came back altered ({} bytes)",
+            len,
+            echoed.len()
+        )));
+    }
+    Ok(())
+}
+
+fn out_of_order<T: Transport + ?Sized>(chan: &mut T) -> crate::Result<()> {
+    // 组号从 16 起，与回显检查的组号分开
+    let streams = 16..16 + STREAMS;
+    for seq in 0..PER_STREAM {
+        for stream in streams.clone() {
+            let payload = pattern(stream, seq, 256);
+            chan.send(&encode(OP_HOLD, stream, seq, &payload))?;
+        }
+    }
+    chan.send(&encode(OP_RELEASE, 0, 0, &[]))?;
+    chan.flush()?;
+
+    let mut seen = vec![false; (STREAMS * PER_STREAM) as usize];
+    for _ in 0..seen.len() {
+        let (stream, seq, payload) = reply(chan, OP_HOLD, |s, _| streams.contains(&s))?;
+        let slot = (seq < PER_STREAM).then(|| ((stream - 16) * PER_STREAM + seq) as usize);
+        let Some(slot) = slot.filter(|&slot| !seen[slot]) else {
+            return Err(mismatch(format!("unexpected reply {}/{}", stream, seq)));
+        };
+        if payload != pattern(stream, seq, 256) {
+            return Err(mismatch(format!(
+                "reply {}/{} came back altered",
+                stream, seq
+            )));
+        }
+        seen[slot] = true;
+    }
+    Ok(())
+}
+
+/// 等待 `op` 的回复，跳过之前超时的检查迟到的回复
+fn reply<T: Transport + ?Sized>(
+    chan: &mut T,
+    op: u8,
+    wanted: impl Fn(u32, u32) -> bool,
+) -> crate::Result<(u32, u32, Vec<u8>)> {
+    loop {
+        let message = chan.recv()?;
+        let Some((got, stream, seq, payload)) = decode(&message) else {
+            return Err(mismatch(
+                "peer answered with a non self-test message".into(),
+            ));
+        };
+        if got == op && wanted(stream, seq) {
+            return Ok((stream, seq, payload.to_vec()));
+        }
+        log::debug!("Skipping stale self-test reply {} {}/{}", got, stream, seq);
+    }
+}
+
+fn mismatch(message: String) -> crate::VirgeError {
+    Error::new(ErrorKind::InvalidData, message).into()
+}
+
+/// 配合对端的自检，直到对端结束自检；返回处理的消息数
+pub(crate) fn serve<T: Transport + ?Sized>(chan: &mut T) -> crate::Result<usize> {
+    let mut held = Vec::new();
+    let mut handled = 0;
+    loop {
+        let message = chan.recv()?;
+        handled += 1;
+        let Some((op, ..)) = decode(&message) else {
+            return Err(mismatch("peer sent a non self-test message".into()));
+        };
+        match op {
+            OP_ECHO => {
+                chan.send(&message)?;
+            }
+            OP_HOLD => held.push(message),
+            OP_RELEASE => {
+                for message in held.drain(..).rev() {
+                    chan.send(&message)?;
+                }
+            }
+            OP_END => {
+                chan.send(&message)?;
+                chan.flush()?;
+                return Ok(handled);
+            }
+            _ => return Err(mismatch(format!("unknown self-test op {}", op))),
+        }
+        chan.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::testing::pipe_pair;
+    use std::thread;
+
+    #[test]
+    fn all_checks_pass_against_a_cooperating_peer() {
+        let (mut client, mut server) = pipe_pair();
+        let peer = thread::spawn(move || serve(&mut server).unwrap());
+        let report = run(&mut client, |_| Ok(Duration::from_micros(50))).unwrap();
+        assert!(report.passed(), "{}", report);
+        let names: Vec<_> = report.checks.iter().map(|check| check.name).collect();
+        assert_eq!(
+            names,
+            ["ping", "echo-small", "echo-1mb", "out-of-order-streams"]
+        );
+        assert_eq!(report.check("echo-1mb").unwrap().bytes, 1 << 20);
+        // 两次回显、32 条暂存、释放与结束
+        assert_eq!(peer.join().unwrap(), 2 + 32 + 1 + 1);
+        assert!(report.to_string().starts_with("self-test 4/4 passed"));
+        assert_eq!(client.timeout, None);
+    }
+
+    #[test]
+    fn failures_are_reported_per_check() {
+        let (mut client, mut server) = pipe_pair();
+        // 只回显、倒序回复时改动一条消息的对端
+        let peer = thread::spawn(move || {
+            let mut held = Vec::new();
+            while let Ok(message) = server.recv() {
+                match decode(&message).unwrap().0 {
+                    OP_HOLD => held.push(message),
+                    OP_RELEASE => {
+                        held.reverse();
+                        *held[3].last_mut().unwrap() ^= 1;
+                        for message in held.drain(..) {
+                            server.send(&message).unwrap();
+                        }
+                    }
+                    _ => {
+                        server.send(&message).unwrap();
+                    }
+                }
+            }
+        });
+        let report = run(&mut client, |_| {
+            Err(Error::new(ErrorKind::TimedOut, "no pong").into())
+        })
+        .unwrap();
+        assert!(!report.passed());
+        let failed: Vec<_> = report.failures().map(|check| check.name).collect();
+        assert_eq!(failed, ["ping", "out-of-order-streams"]);
+        assert!(report.check("echo-1mb").unwrap().passed());
+        let text = report.to_string();
+        assert!(
+            text.contains("ping: FAILED") && text.contains("no pong"),
+            "{}",
+            text
+        );
+        assert!(text.contains("came back altered"), "{}", text);
+        drop(client);
+        peer.join().unwrap();
+    }
+
+    #[test]
+    fn serving_stops_on_foreign_messages() {
+        let (mut client, mut server) = pipe_pair();
+        client.send(b"hello").unwrap();
+        let err = std::io::Error::from(serve(&mut server).unwrap_err());
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
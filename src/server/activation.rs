@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! systemd 套接字激活
+//!
+//! 由 `.socket` 单元（`ListenStream=vsock:...`）启动时，systemd 通过
+//! `LISTEN_PID`/`LISTEN_FDS` 把已监听的 vsock socket 从 fd 3 起传给进程，
+//! 服务端直接接管，无需自己绑定 vsock。接管后清除这些环境变量，
+//! 并给 fd 设置 `FD_CLOEXEC`，避免子进程再次继承。
+
+use std::env;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::RawFd;
+
+/// systemd 传递的第一个 fd
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// 取出 systemd 传给本进程的 fd；没有传递（或传给的是其他进程）时为空
+pub(crate) fn listen_fds() -> Result<Vec<RawFd>> {
+    let fds = parse_listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )?;
+    if !fds.is_empty() {
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        env::remove_var("LISTEN_FDNAMES");
+    }
+    Ok(fds)
+}
+
+fn parse_listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> Result<Vec<RawFd>> {
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(Vec::new());
+    };
+    if pid.trim().parse::<u32>().ok() != Some(own_pid) {
+        return Ok(Vec::new());
+    }
+    let count: RawFd = fds.trim().parse().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid LISTEN_FDS value: {:?}", fds),
+        )
+    })?;
+    Ok((SD_LISTEN_FDS_START..SD_LISTEN_FDS_START.saturating_add(count)).collect())
+}
+
+/// 在继承的 fd 中找出第一个处于监听状态的 vsock 流 socket，并设置 `FD_CLOEXEC`
+pub(crate) fn find_vsock_listener(fds: &[RawFd]) -> Result<RawFd> {
+    let fd = fds
+        .iter()
+        .copied()
+        .find(|&fd| is_vsock_listener(fd))
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "none of the inherited fds {:?} is a listening vsock socket",
+                    fds
+                ),
+            )
+        })?;
+    // SAFETY: fd 由 systemd 传入且仍然打开，F_SETFD 不涉及内存
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+fn is_vsock_listener(fd: RawFd) -> bool {
+    // SAFETY: 以下调用只写入栈上大小已给出的缓冲，fd 无效时返回错误
+    unsafe {
+        let mut addr: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) < 0
+            || i32::from(addr.ss_family) != libc::AF_VSOCK
+        {
+            return false;
+        }
+        sockopt(fd, libc::SO_TYPE) == Some(libc::SOCK_STREAM)
+            && sockopt(fd, libc::SO_ACCEPTCONN) == Some(1)
+    }
+}
+
+unsafe fn sockopt(fd: RawFd, name: libc::c_int) -> Option<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = libc::getsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        name,
+        &mut value as *mut libc::c_int as *mut libc::c_void,
+        &mut len,
+    );
+    (ret == 0).then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn parse_listen_fds_checks_pid_and_count() {
+        assert!(parse_listen_fds(None, None, 42).unwrap().is_empty());
+        assert!(parse_listen_fds(Some("41"), Some("1"), 42)
+            .unwrap()
+            .is_empty());
+        assert_eq!(parse_listen_fds(Some("42"), Some("2"), 42).unwrap(), [3, 4]);
+        assert!(parse_listen_fds(Some("42"), Some("0"), 42)
+            .unwrap()
+            .is_empty());
+        let err = parse_listen_fds(Some("42"), Some("x"), 42).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn non_vsock_fds_are_rejected() {
+        let (a, _b) = std::os::unix::net::UnixStream::pair().unwrap();
+        assert!(!is_vsock_listener(a.as_raw_fd()));
+        assert!(!is_vsock_listener(-1));
+        let err = find_vsock_listener(&[a.as_raw_fd()]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 按对端 CID 汇总的流量统计
+//!
+//! `ServerManager` 接受的每个连接把收发的负载字节计入所属 CID，
+//! 连接断开后累计值仍然保留，可用于共享宿主机上按租户计费或配额。
+//! 快照可随时通过 `ServerManager::bandwidth()` 获取；配置
+//! `ServerConfig::with_bandwidth_report()` 后由后台线程按周期回调。
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use crate::threads;
+
+/// 单个 CID 的累计流量
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CidUsage {
+    /// 服务端发往该 CID 的负载字节数
+    pub bytes_sent: u64,
+    /// 从该 CID 收到的负载字节数
+    pub bytes_received: u64,
+    /// 已接受的连接数
+    pub connections: u64,
+}
+
+/// 某一时刻各 CID 的累计流量
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BandwidthSnapshot {
+    /// 快照时间
+    pub taken_at: SystemTime,
+    /// 按 CID 排序的累计流量
+    pub usage: BTreeMap<u32, CidUsage>,
+}
+
+impl BandwidthSnapshot {
+    /// `cid` 的累计流量，没有记录时为全零
+    pub fn get(&self, cid: u32) -> CidUsage {
+        self.usage.get(&cid).copied().unwrap_or_default()
+    }
+}
+
+/// 周期性回调配置
+#[derive(Clone)]
+pub(crate) struct BandwidthReport {
+    pub(crate) interval: Duration,
+    pub(crate) callback: Arc<dyn Fn(&BandwidthSnapshot) + Send + Sync>,
+}
+
+impl fmt::Debug for BandwidthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BandwidthReport")
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Direction {
+    Sent,
+    Received,
+}
+
+/// 管理器持有的流量账本
+#[derive(Debug, Default)]
+pub(crate) struct BandwidthLedger {
+    usage: Mutex<BTreeMap<u32, CidUsage>>,
+}
+
+impl BandwidthLedger {
+    /// 为新接受的连接开户
+    pub(crate) fn open(self: &Arc<Self>, cid: u32) -> CidAccount {
+        self.with(cid, |usage| usage.connections += 1);
+        CidAccount {
+            ledger: self.clone(),
+            cid,
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> BandwidthSnapshot {
+        BandwidthSnapshot {
+            taken_at: SystemTime::now(),
+            usage: self
+                .usage
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        }
+    }
+
+    fn with(&self, cid: u32, f: impl FnOnce(&mut CidUsage)) {
+        let mut usage = self.usage.lock().unwrap_or_else(PoisonError::into_inner);
+        f(usage.entry(cid).or_default());
+    }
+
+    /// 启动周期回调线程，返回的发送端被 drop 后线程退出
+    pub(crate) fn spawn_reporter(self: &Arc<Self>, report: BandwidthReport) -> Sender<()> {
+        let (stop, stopped) = channel::<()>();
+        let ledger = self.clone();
+        let spawned = threads::spawn("bandwidth", move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(report.interval) {
+                (report.callback)(&ledger.snapshot());
+            }
+        });
+        spawned.expect("failed to spawn bandwidth report thread");
+        stop
+    }
+}
+
+/// 单个连接在账本中的账户
+pub(crate) struct CidAccount {
+    ledger: Arc<BandwidthLedger>,
+    cid: u32,
+}
+
+impl CidAccount {
+    pub(crate) fn record(&self, direction: Direction, bytes: usize) {
+        self.ledger.with(self.cid, |usage| match direction {
+            Direction::Sent => usage.bytes_sent += bytes as u64,
+            Direction::Received => usage.bytes_received += bytes as u64,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::sync_channel;
+
+    #[test]
+    fn usage_accumulates_per_cid() {
+        let ledger = Arc::new(BandwidthLedger::default());
+        let a = ledger.open(3);
+        let b = ledger.open(3);
+        let c = ledger.open(4);
+        a.record(Direction::Sent, 100);
+        b.record(Direction::Received, 7);
+        c.record(Direction::Sent, 1);
+        drop(a);
+
+        let snapshot = ledger.snapshot();
+        assert_eq!(
+            snapshot.get(3),
+            CidUsage {
+                bytes_sent: 100,
+                bytes_received: 7,
+                connections: 2,
+            }
+        );
+        assert_eq!(snapshot.get(4).bytes_sent, 1);
+        assert_eq!(snapshot.get(5), CidUsage::default());
+        assert_eq!(snapshot.usage.keys().copied().collect::<Vec<_>>(), [3, 4]);
+    }
+
+    #[test]
+    fn reporter_runs_until_stopped() {
+        let ledger = Arc::new(BandwidthLedger::default());
+        ledger.open(3).record(Direction::Received, 42);
+        let (tx, rx) = sync_channel(16);
+        let stop = ledger.spawn_reporter(BandwidthReport {
+            interval: Duration::from_millis(5),
+            callback: Arc::new(move |snapshot: &BandwidthSnapshot| {
+                let _ = tx.try_send(snapshot.get(3).bytes_received);
+            }),
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 42);
+        drop(stop);
+        // 线程退出后回调连同发送端一起被释放
+        loop {
+            match rx.recv_timeout(Duration::from_secs(5)) {
+                Ok(_) => continue,
+                Err(e) => {
+                    assert_eq!(e, RecvTimeoutError::Disconnected);
+                    break;
+                }
+            }
+        }
+    }
+}
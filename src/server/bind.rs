@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 监听绑定重试
+//!
+//! 虚拟机刚启动时 vsock 设备或 CID 可能尚未就绪，上一个实例退出后端口也可能仍被占用。
+//! 配置 `ServerConfig::with_bind_retry()` 后，这类暂时性错误按指数退避重试，
+//! 直到绑定成功或超过期限；每次重试发布 `VirgaEvent::BindRetrying`。
+
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+use log::*;
+
+use crate::addr::Addr;
+use crate::events::{self, VirgaEvent};
+use crate::logging::log_event;
+use crate::retry::Backoff;
+
+/// 端口占用、地址不可用或 vsock 设备尚未就绪
+fn is_transient(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::AddrInUse | ErrorKind::AddrNotAvailable)
+        || matches!(
+            e.raw_os_error(),
+            Some(libc::ENODEV | libc::ENXIO | libc::EAFNOSUPPORT)
+        )
+}
+
+/// 调用 `bind` 直至成功；`deadline` 为 `None` 时不重试，非暂时性错误立即返回
+pub(crate) fn bind_with_retry<T>(
+    addr: Addr,
+    deadline: Option<Duration>,
+    mut bind: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let Some(deadline) = deadline else {
+        return bind();
+    };
+    let mut backoff = Backoff::new(deadline);
+    loop {
+        let e = match bind() {
+            Ok(listener) => {
+                if backoff.attempt() > 0 {
+                    log_event!(
+                        Level::Info,
+                        "listener bound after retry",
+                        cid = addr.cid,
+                        port = addr.port,
+                        attempts = backoff.attempt() + 1,
+                    );
+                }
+                return Ok(listener);
+            }
+            Err(e) => e,
+        };
+        if !is_transient(&e) {
+            return Err(e);
+        }
+        let Some(delay) = backoff.next_delay() else {
+            return Err(e);
+        };
+        let reason = e.to_string();
+        log_event!(
+            Level::Warn,
+            "bind failed, retrying",
+            cid = addr.cid,
+            port = addr.port,
+            attempt = backoff.attempt(),
+            error = reason.as_str(),
+        );
+        events::emit(VirgaEvent::BindRetrying {
+            cid: addr.cid,
+            port: addr.port,
+            attempt: backoff.attempt(),
+            reason,
+        });
+        backoff.wait(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn transient_errors_are_retried_until_bound() {
+        let events = events::subscribe();
+        let mut calls = 0;
+        let bound = bind_with_retry(Addr::new(3, 4100), Some(Duration::from_secs(5)), || {
+            calls += 1;
+            match calls {
+                1 => Err(Error::from(ErrorKind::AddrInUse)),
+                2 => Err(Error::from_raw_os_error(libc::ENODEV)),
+                _ => Ok("listener"),
+            }
+        });
+        assert_eq!(bound.unwrap(), "listener");
+        assert_eq!(calls, 3);
+        let attempts: Vec<u32> = std::iter::from_fn(|| events.try_recv())
+            .filter_map(|event| match event {
+                VirgaEvent::BindRetrying {
+                    port: 4100,
+                    attempt,
+                    ..
+                } => Some(attempt),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(attempts, [1, 2]);
+    }
+
+    #[test]
+    fn gives_up_on_permanent_error_or_deadline() {
+        let mut calls = 0;
+        let err = bind_with_retry::<()>(Addr::new(3, 4101), Some(Duration::from_secs(5)), || {
+            calls += 1;
+            Err(Error::from(ErrorKind::PermissionDenied))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(calls, 1);
+
+        let started = Instant::now();
+        let err =
+            bind_with_retry::<()>(Addr::new(3, 4101), Some(Duration::from_millis(120)), || {
+                Err(Error::from(ErrorKind::AddrInUse))
+            })
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AddrInUse);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let mut calls = 0;
+        let _ = bind_with_retry::<()>(Addr::new(3, 4101), None, || {
+            calls += 1;
+            Err(Error::from(ErrorKind::AddrInUse))
+        });
+        assert_eq!(calls, 1);
+    }
+}
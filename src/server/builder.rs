@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! `ServerManager` 构建器与常见部署环境的预设
+//!
+//! ```ignore
+//! let mut manager = ServerManager::builder().for_nitro().port(5005).build()?;
+//! manager.start()?;
+//! ```
+//!
+//! 传输后端（xtransport / yamux）由编译特性决定，预设只选择地址与连接策略。
+
+use super::{ServerConfig, ServerManager};
+use crate::addr::Addr;
+use crate::auth::TokenAuth;
+use crate::defaults::Defaults;
+use crate::units::ByteSize;
+use std::time::Duration;
+
+/// Nitro Enclaves 中父实例的固定 CID
+pub const NITRO_PARENT_CID: u32 = 3;
+/// Nitro 预设的默认端口
+pub const NITRO_DEFAULT_PORT: u32 = 5005;
+/// Firecracker 预设的默认端口（与其 vsock 示例一致）
+pub const FIRECRACKER_DEFAULT_PORT: u32 = 52;
+
+/// `ServerManager` 构建器，从 [`ServerConfig::default()`] 开始
+#[derive(Clone, Debug, Default)]
+pub struct ServerManagerBuilder {
+    config: ServerConfig,
+}
+
+impl ServerManagerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从已有配置开始
+    pub fn from_config(config: ServerConfig) -> Self {
+        Self { config }
+    }
+
+    /// Nitro Enclaves：在 enclave 内监听任意 CID，只接受父实例（CID 3）的连接
+    pub fn for_nitro(mut self) -> Self {
+        self.config.listen_cid = crate::VMADDR_CID_ANY as u32;
+        self.config.listen_port = NITRO_DEFAULT_PORT;
+        self.config = self.config.with_allowed_cids([NITRO_PARENT_CID]);
+        self
+    }
+
+    /// Firecracker：在 guest 内监听任意 CID，只接受宿主机（CID 2）经
+    /// vsock 代理发起的连接
+    pub fn for_firecracker(mut self) -> Self {
+        self.config.listen_cid = crate::VMADDR_CID_ANY as u32;
+        self.config.listen_port = FIRECRACKER_DEFAULT_PORT;
+        self.config = self
+            .config
+            .with_allowed_cids([crate::VMADDR_CID_HOST as u32]);
+        self
+    }
+
+    /// 本机测试：监听 vsock 回环（CID 1，需 Linux 5.6+ 的 vsock_loopback），
+    /// 不限制来源，使用较短的空闲超时以免测试挂起
+    pub fn for_local_testing(mut self) -> Self {
+        self.config.listen_cid = crate::VMADDR_CID_LOCAL as u32;
+        self.config.listen_port = Defaults::get().server_port;
+        self.config.policy.allowed_cids = None;
+        self.config = self.config.with_idle_timeout(Duration::from_secs(10));
+        self
+    }
+
+    /// 监听地址，如 `"any:5005".parse()?`
+    pub fn addr(mut self, addr: Addr) -> Self {
+        self.config = self.config.with_listen_addr(addr);
+        self
+    }
+
+    pub fn listen_cid(mut self, cid: u32) -> Self {
+        self.config.listen_cid = cid;
+        self
+    }
+
+    pub fn port(mut self, port: u32) -> Self {
+        self.config.listen_port = port;
+        self
+    }
+
+    pub fn chunk_size(mut self, size: ByteSize) -> Self {
+        self.config = self.config.with_chunk_size(size);
+        self
+    }
+
+    pub fn ack(mut self, enabled: bool) -> Self {
+        self.config.is_ack = enabled;
+        self
+    }
+
+    pub fn allowed_cids(mut self, cids: impl IntoIterator<Item = u32>) -> Self {
+        self.config = self.config.with_allowed_cids(cids);
+        self
+    }
+
+    /// 取消来源 CID 限制
+    pub fn allow_any_cid(mut self) -> Self {
+        self.config.policy.allowed_cids = None;
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.config = self.config.with_idle_timeout(timeout);
+        self
+    }
+
+    /// 要求客户端令牌认证
+    pub fn auth(mut self, auth: TokenAuth) -> Self {
+        self.config = self.config.with_auth(auth);
+        self
+    }
+
+    /// 对配置做预设之外的调整
+    pub fn configure(mut self, f: impl FnOnce(ServerConfig) -> ServerConfig) -> Self {
+        self.config = f(self.config);
+        self
+    }
+
+    /// 构建好的配置
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// 校验配置并创建 `ServerManager`（尚未开始监听）
+    pub fn build(self) -> std::io::Result<ServerManager> {
+        self.config.validate()?;
+        Ok(ServerManager::new(self.config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_defaults_match_server_config() {
+        let builder = ServerManagerBuilder::new();
+        assert_eq!(builder.config().listen_cid, Defaults::get().listen_cid);
+        assert_eq!(builder.config().listen_port, Defaults::get().server_port);
+        assert!(builder.config().policy().allowed_cids.is_none());
+    }
+
+    #[test]
+    fn nitro_preset() {
+        let builder = ServerManager::builder().for_nitro();
+        assert_eq!(builder.config().listen_port, NITRO_DEFAULT_PORT);
+        assert_eq!(
+            builder.config().policy().allowed_cids,
+            Some(vec![NITRO_PARENT_CID])
+        );
+    }
+
+    #[test]
+    fn firecracker_preset() {
+        let builder = ServerManager::builder().for_firecracker();
+        assert_eq!(builder.config().listen_cid, crate::VMADDR_CID_ANY as u32);
+        assert_eq!(builder.config().listen_port, FIRECRACKER_DEFAULT_PORT);
+        assert_eq!(builder.config().policy().allowed_cids, Some(vec![2]));
+    }
+
+    #[test]
+    fn addr_sets_cid_and_port() {
+        let builder = ServerManager::builder().addr(Addr::local(9000));
+        assert_eq!(builder.config().listen_addr(), Addr::new(1, 9000));
+        assert_eq!(builder.config().listen_port, 9000);
+    }
+
+    #[test]
+    fn local_testing_preset_clears_restrictions() {
+        let builder = ServerManager::builder().for_nitro().for_local_testing();
+        assert_eq!(builder.config().listen_cid, 1);
+        assert!(builder.config().policy().allowed_cids.is_none());
+        assert!(builder.config().policy().idle_timeout.is_some());
+    }
+
+    #[test]
+    fn overrides_after_preset() {
+        let manager = ServerManager::builder()
+            .for_nitro()
+            .port(6000)
+            .allow_any_cid()
+            .chunk_size(ByteSize::kib(4))
+            .configure(|c| c.with_send_window(4))
+            .build()
+            .unwrap();
+        assert_eq!(manager.config().listen_port, 6000);
+        assert!(manager.config().policy().allowed_cids.is_none());
+        assert_eq!(manager.config().chunk_size, ByteSize::kib(4));
+        assert_eq!(manager.config().send_window, 4);
+        assert!(!manager.is_running());
+    }
+
+    #[test]
+    fn build_validates() {
+        let result = ServerManager::builder().port(0).build();
+        assert_eq!(
+            result.err().map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidInput)
+        );
+    }
+}
@@ -0,0 +1,246 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 幂等调用去重
+//!
+//! 客户端用 `rpc::call_idempotent()` 发起的调用带有调用方给出的幂等键，连接断开后重连
+//! 并以同一个键重发。配置 `ServerConfig::with_idempotency()` 后，`rpc::serve_one()` 按
+//! “对端 CID + 幂等键”记住处理结果：重发的请求直接得到第一次的响应，处理函数不会再执行；
+//! 第一次仍在执行时（原连接的线程还没返回），重发的请求等它完成后取同一个结果。
+//! 结果在完成后保留 `ttl`，超过 `capacity` 条时先丢弃最早完成的。
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::loom::{Condvar, Mutex, MutexGuard};
+use crate::sources::{self, Clock};
+
+type Key = (u32, String);
+
+#[derive(Debug)]
+enum Entry {
+    /// 第一次调用正在执行
+    Running,
+    /// 编码后的响应与完成时刻
+    Done(Vec<u8>, Instant),
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<Key, Entry>,
+    /// 按完成顺序排列，用于过期与容量淘汰
+    completed: VecDeque<(Instant, Key)>,
+}
+
+/// 管理器与连接共享的去重表
+#[derive(Debug)]
+pub(crate) struct IdempotencyCache {
+    entries: Mutex<Entries>,
+    changed: Condvar,
+    ttl: Duration,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl IdempotencyCache {
+    pub(crate) fn new(ttl: Duration, capacity: usize) -> Self {
+        Self::with_clock(ttl, capacity, sources::clock())
+    }
+
+    /// 以 `clock` 计算结果的保留期限
+    pub(crate) fn with_clock(ttl: Duration, capacity: usize, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            entries: Mutex::default(),
+            changed: Condvar::new(),
+            ttl,
+            capacity,
+            clock,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> (Duration, usize) {
+        (self.ttl, self.capacity)
+    }
+
+    /// `cid` 的调用 `key` 第一次到达时执行 `f` 并记住响应，之后直接返回记住的响应
+    pub(crate) fn run(&self, cid: u32, key: &str, f: impl FnOnce() -> Vec<u8>) -> Vec<u8> {
+        let id = (cid, key.to_string());
+        let mut entries = self.lock();
+        self.expire(&mut entries, self.clock.now());
+        loop {
+            match entries.map.get(&id) {
+                Some(Entry::Done(response, _)) => {
+                    debug!("Replaying response to call {:?} from cid {}", key, cid);
+                    return response.clone();
+                }
+                Some(Entry::Running) => {
+                    entries = self
+                        .changed
+                        .wait(entries)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                None => break,
+            }
+        }
+        entries.map.insert(id.clone(), Entry::Running);
+        drop(entries);
+
+        // 处理函数 panic 时移除占位，等待者改为自己执行
+        let mut pending = Pending {
+            cache: self,
+            id: Some(id),
+        };
+        let response = f();
+        let id = pending.id.take().expect("pending call");
+        let now = self.clock.now();
+        let mut entries = self.lock();
+        entries
+            .map
+            .insert(id.clone(), Entry::Done(response.clone(), now));
+        entries.completed.push_back((now, id));
+        self.expire(&mut entries, now);
+        self.changed.notify_all();
+        response
+    }
+
+    /// 丢弃过期与超出容量的结果
+    fn expire(&self, entries: &mut Entries, now: Instant) {
+        while let Some((completed_at, _)) = entries.completed.front() {
+            let expired = now.duration_since(*completed_at) >= self.ttl;
+            if !expired && entries.completed.len() <= self.capacity {
+                break;
+            }
+            let (completed_at, id) = entries.completed.pop_front().expect("front");
+            // 同一个键过期后可能再次执行，只移除这一次的结果
+            if matches!(entries.map.get(&id), Some(Entry::Done(_, at)) if *at == completed_at) {
+                entries.map.remove(&id);
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// 正在执行的调用，未完成就 drop 时移除占位
+struct Pending<'a> {
+    cache: &'a IdempotencyCache,
+    id: Option<Key>,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.cache.lock().map.remove(&id);
+            self.cache.changed.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::ManualClock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn repeated_key_replays_first_response() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 16);
+        let runs = AtomicUsize::new(0);
+        let run = |cid, key| {
+            cache.run(cid, key, || {
+                let n = runs.fetch_add(1, Ordering::SeqCst);
+                vec![n as u8]
+            })
+        };
+        assert_eq!(run(3, "a"), [0]);
+        assert_eq!(run(3, "a"), [0]);
+        assert_eq!(run(4, "a"), [1]);
+        assert_eq!(run(3, "b"), [2]);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn retry_waits_for_running_call() {
+        let cache = Arc::new(IdempotencyCache::new(Duration::from_secs(60), 16));
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let first = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.run(3, "a", || {
+                    started_tx.send(()).unwrap();
+                    thread::sleep(Duration::from_millis(50));
+                    b"first".to_vec()
+                })
+            })
+        };
+        started_rx.recv().unwrap();
+        let retried = cache.run(3, "a", || b"second".to_vec());
+        assert_eq!(retried, b"first");
+        assert_eq!(first.join().unwrap(), b"first");
+    }
+
+    #[test]
+    fn results_expire_and_respect_capacity() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 2);
+        for key in ["a", "b", "c"] {
+            cache.run(3, key, || key.as_bytes().to_vec());
+        }
+        assert_eq!(cache.lock().map.len(), 2);
+        assert_eq!(cache.run(3, "a", || b"again".to_vec()), b"again");
+
+        let cache = IdempotencyCache::new(Duration::ZERO, 16);
+        cache.run(3, "a", || b"first".to_vec());
+        assert_eq!(cache.run(3, "a", || b"second".to_vec()), b"second");
+    }
+
+    #[test]
+    fn results_expire_after_ttl_on_the_given_clock() {
+        let clock = Arc::new(ManualClock::new());
+        let cache = IdempotencyCache::with_clock(Duration::from_secs(60), 16, clock.clone());
+        cache.run(3, "a", || b"first".to_vec());
+        clock.advance(Duration::from_secs(59));
+        assert_eq!(cache.run(3, "a", || b"second".to_vec()), b"first");
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(cache.run(3, "a", || b"third".to_vec()), b"third");
+    }
+
+    #[test]
+    fn panicking_call_releases_its_key() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 16);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cache.run(3, "a", || panic!("handler failed"))
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(cache.run(3, "a", || b"ok".to_vec()), b"ok");
+    }
+
+    /// 同一个键的两次调用同时到达时处理函数只执行一次，等待者不会错过完成的通知
+    #[cfg(loom)]
+    #[test]
+    fn loom_concurrent_retries_run_once() {
+        ::loom::model(|| {
+            let cache = Arc::new(IdempotencyCache::new(Duration::from_secs(60), 16));
+            let runs = Arc::new(::loom::sync::atomic::AtomicUsize::new(0));
+            let call = {
+                let (cache, runs) = (cache.clone(), runs.clone());
+                move || {
+                    cache.run(3, "a", || {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        b"done".to_vec()
+                    })
+                }
+            };
+            let retry = ::loom::thread::spawn(call.clone());
+            assert_eq!(call(), b"done");
+            assert_eq!(retry.join().unwrap(), b"done");
+            assert_eq!(runs.load(Ordering::SeqCst), 1);
+        });
+    }
+}
@@ -0,0 +1,219 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 已接受、尚未握手的连接
+//!
+//! [`ServerManager::accept_incoming()`](super::ServerManager::accept_incoming) 只接受连接并做
+//! CID、排空与内存预算检查，认证、消息定义与压缩字典协商留给 [`Incoming::handshake()`]，
+//! 由处理该连接的线程完成，慢的或不应答的客户端不会阻塞接受循环。
+
+use super::bandwidth::BandwidthLedger;
+use super::idempotency::IdempotencyCache;
+use super::limits::HandlerGate;
+use super::liveness::LivenessRegistry;
+use super::policy::{PolicyWatch, SharedPolicy};
+use super::versions::VersionTally;
+use super::{jitter, TenantMap, Transport, VirgeServer};
+use crate::auth::{Authorizer, PeerIdentity, TokenAuth, DEFAULT_HANDSHAKE_TIMEOUT};
+use crate::codec::{Schema, SchemaMatch};
+#[cfg(feature = "compression")]
+use crate::compression::{accept_dictionary, CompressionContext, Dictionary};
+use crate::error::ConnContext;
+use crate::events::{self, Role, VirgaEvent};
+use crate::logging::log_event;
+use crate::Leftovers;
+use log::*;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// 握手与构造连接所需的配置与共享状态，`start()` 后首次接受连接时生成，
+/// `update_config()` 与 `stop()` 时作废
+pub(super) struct Setup {
+    pub(super) auth: Option<TokenAuth>,
+    pub(super) schema: Option<Schema>,
+    #[cfg(feature = "compression")]
+    pub(super) dictionaries: Vec<Dictionary>,
+    pub(super) tenants: Option<TenantMap>,
+    pub(super) authorizer: Option<Arc<dyn Authorizer>>,
+    pub(super) max_connection_age: Option<(Duration, Duration)>,
+    pub(super) leftovers: Leftovers,
+    pub(super) policy: Option<Arc<SharedPolicy>>,
+    pub(super) bandwidth: Option<Arc<BandwidthLedger>>,
+    pub(super) versions: Option<Arc<VersionTally>>,
+    pub(super) handlers: Option<Arc<HandlerGate>>,
+    pub(super) idempotency: Option<Arc<IdempotencyCache>>,
+    pub(super) liveness: Option<Arc<LivenessRegistry>>,
+    pub(super) draining: Option<Arc<AtomicBool>>,
+}
+
+impl Setup {
+    /// 按配置完成认证、消息定义协商与压缩字典协商
+    fn handshake(
+        &self,
+        transport: &mut Transport,
+        cid: u32,
+    ) -> Result<(Option<PeerIdentity>, Option<SchemaMatch>)> {
+        let peer = match &self.auth {
+            Some(auth) => {
+                transport.set_idle_timeout(Some(auth.timeout()))?;
+                let (peer, secure) = auth.accept(transport, cid)?;
+                transport.set_idle_timeout(None)?;
+                transport.set_secure(secure);
+                Some(peer)
+            }
+            None => None,
+        };
+        let schema_match = match &self.schema {
+            Some(schema) => {
+                transport.set_idle_timeout(Some(DEFAULT_HANDSHAKE_TIMEOUT))?;
+                let verdict = schema.accept(transport)?;
+                transport.set_idle_timeout(None)?;
+                Some(verdict)
+            }
+            None => None,
+        };
+        #[cfg(feature = "compression")]
+        if !self.dictionaries.is_empty() {
+            transport.set_idle_timeout(Some(DEFAULT_HANDSHAKE_TIMEOUT))?;
+            let dictionary = accept_dictionary(&self.dictionaries, transport)?;
+            transport.set_idle_timeout(None)?;
+            transport.set_compression(CompressionContext::new(dictionary));
+        }
+        Ok((peer, schema_match))
+    }
+
+    /// 配置了租户时按对端身份确定租户，没有租户的连接被拒绝
+    pub(super) fn assign_tenant(
+        &self,
+        cid: u32,
+        peer: Option<&PeerIdentity>,
+    ) -> Result<Option<Arc<str>>> {
+        let Some(tenants) = &self.tenants else {
+            return Ok(None);
+        };
+        let anonymous = PeerIdentity {
+            cid,
+            name: String::new(),
+        };
+        let peer = peer.unwrap_or(&anonymous);
+        if let Some(tenant) = tenants.resolve(peer) {
+            return Ok(Some(Arc::from(tenant)));
+        }
+        log_event!(Level::Warn, "connection rejected: no tenant", cid = cid);
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is not assigned to a tenant", peer),
+        ))
+    }
+}
+
+/// 已接受、尚未握手的连接，由 `accept_incoming()` 返回。在处理该连接的线程中调用
+/// [`handshake()`](Self::handshake) 得到 [`VirgeServer`]；直接丢弃即关闭连接
+pub struct Incoming {
+    transport: Transport,
+    cid: u32,
+    setup: Arc<Setup>,
+}
+
+impl fmt::Debug for Incoming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Incoming")
+            .field("conn", &self.conn())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Incoming {
+    pub(super) fn new(transport: Transport, cid: u32, setup: Arc<Setup>) -> Self {
+        Self {
+            transport,
+            cid,
+            setup,
+        }
+    }
+
+    /// 连接上下文，握手前即可用于日志与线程命名
+    pub fn conn(&self) -> ConnContext {
+        self.transport.conn()
+    }
+
+    /// 按接受连接时的配置完成认证、消息定义与压缩字典协商并确定租户，成功后返回可收发的
+    /// 连接。握手各步按各自的超时进行；失败时连接已关闭，没有租户的连接先被告知原因
+    pub fn handshake(self) -> Result<VirgeServer> {
+        let Self {
+            mut transport,
+            cid,
+            setup,
+        } = self;
+        let started = Instant::now();
+        let handshake = setup.handshake(&mut transport, cid);
+        let conn = transport.conn();
+        let (peer, schema_match) = match handshake {
+            Ok(result) => result,
+            Err(e) => {
+                log_event!(
+                    Level::Warn,
+                    "server handshake failed",
+                    conn_id = conn.conn_id,
+                    cid = conn.cid,
+                    duration_ms = started.elapsed().as_millis() as u64,
+                );
+                events::emit(VirgaEvent::HandshakeFailed {
+                    role: Role::Server,
+                    conn,
+                    reason: e.to_string(),
+                });
+                return Err(e);
+            }
+        };
+        let tenant = match setup.assign_tenant(cid, peer.as_ref()) {
+            Ok(tenant) => tenant,
+            Err(e) => {
+                if let Err(sent) = transport.reject(&e.to_string()) {
+                    warn!("Failed to send rejection to cid {}: {}", cid, sent);
+                }
+                let _ = transport.disconnect();
+                return Err(e);
+            }
+        };
+        log_event!(
+            Level::Info,
+            "server accepted",
+            conn_id = conn.conn_id,
+            cid = conn.cid,
+            port = conn.port,
+            duration_ms = started.elapsed().as_millis() as u64,
+        );
+        events::emit(VirgaEvent::Connected {
+            role: Role::Server,
+            conn,
+        });
+
+        let version = transport.protocol_version();
+        let server = VirgeServer::new(transport, true)
+            .with_account(setup.bandwidth.as_ref().map(|ledger| ledger.open(cid)))
+            .with_versions(setup.versions.as_ref().map(|tally| tally.open(version)))
+            .with_peer(peer)
+            .with_tenant(tenant)
+            .with_schema_match(schema_match)
+            .with_authorizer(setup.authorizer.clone())
+            .with_handler_gate(setup.handlers.clone())
+            .with_idempotency(setup.idempotency.clone())
+            .with_liveness(setup.liveness.as_ref().map(|registry| registry.open(cid)))
+            .with_draining(setup.draining.clone())
+            .with_max_age(
+                setup
+                    .max_connection_age
+                    .map(|(age, grace)| (age - jitter(age / 10), grace)),
+            )
+            .with_leftovers(setup.leftovers);
+        Ok(match &setup.policy {
+            Some(shared) => server.with_policy(PolicyWatch::new(shared.clone())),
+            None => server,
+        })
+    }
+}
@@ -0,0 +1,470 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 处理函数并发上限
+//!
+//! 多个客户机同时突发请求时，每个连接线程各自调用处理函数，宿主机上的下游资源
+//! （磁盘、数据库、外部服务）可能被压垮。配置 `ServerConfig::with_handler_limits()`
+//! 后，`rpc::serve_one()` 在调用处理函数前申请名额：全部连接合计不超过 `global`，
+//! 同一 CID（即同一客户机）的全部连接合计不超过 `per_peer`，单个连接不超过
+//! `per_connection`（自行分发、持有名额时继续接收的连接可能同时执行多个调用）。名额
+//! 不足时按 [`Overflow`] 排队等待或立即拒绝，拒绝以 `ResourceBusy` 作为该次调用的错误
+//! 返回给客户端。
+//!
+//! 排队的调用按连接轮转放行，而不是全局先来先到：每有名额空出，就轮到上次放行的连接
+//! 之后下一个有等待者（且该连接与其 CID 未达 `per_connection`、`per_peer` 上限）的
+//! 连接，同一连接内按到达顺序。一个连接排再多请求，也只占轮转中的一格，不会饿死其他
+//! 连接；一个客户机开多个连接时每个连接各占一格，其总量由 `per_peer` 限制。
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Error, ErrorKind, Result};
+use std::ops::Bound;
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+
+use log::Level;
+
+use crate::error::VirgeError;
+use crate::logging::log_event;
+use crate::loom::{Condvar, Mutex, MutexGuard};
+
+/// 名额不足时的处理方式
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// 排队等待，超过给定时长仍未轮到时拒绝
+    Queue(Duration),
+    /// 立即拒绝
+    Reject,
+}
+
+/// 同时执行的处理函数个数上限，`None` 表示不限
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandlerLimits {
+    /// 全部连接合计
+    pub global: Option<usize>,
+    /// 同一 CID（客户机）的全部连接合计
+    pub per_peer: Option<usize>,
+    /// 单个连接
+    pub per_connection: Option<usize>,
+    /// 名额不足时的处理方式
+    pub overflow: Overflow,
+}
+
+impl HandlerLimits {
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        if [self.global, self.per_peer, self.per_connection].contains(&Some(0)) {
+            return Err(VirgeError::ConfigError(
+                "handler limits must be greater than zero".to_string(),
+            ));
+        }
+        if self.overflow == Overflow::Queue(Duration::ZERO) {
+            return Err(VirgeError::ConfigError(
+                "handler queue timeout must be greater than zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 调用来自的对端与连接
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Caller {
+    pub(crate) cid: u32,
+    pub(crate) conn_id: u64,
+}
+
+#[derive(Debug, Default)]
+struct InFlight {
+    total: usize,
+    per_cid: HashMap<u32, usize>,
+    per_conn: HashMap<u64, usize>,
+    /// 排队中的调用，按连接分组（连自哪个 CID 与按到达顺序的号码）
+    waiting: BTreeMap<u64, (u32, VecDeque<u64>)>,
+    next_ticket: u64,
+    /// 上次放行的连接，轮转从它之后开始
+    last_served: Option<u64>,
+}
+
+impl InFlight {
+    fn dequeue(&mut self, conn_id: u64, ticket: u64) {
+        if let Some((_, queue)) = self.waiting.get_mut(&conn_id) {
+            queue.retain(|t| *t != ticket);
+            if queue.is_empty() {
+                self.waiting.remove(&conn_id);
+            }
+        }
+    }
+}
+
+/// `ServerManager` 各连接共享的名额计数
+#[derive(Debug)]
+pub(crate) struct HandlerGate {
+    limits: HandlerLimits,
+    in_flight: Mutex<InFlight>,
+    freed: Condvar,
+}
+
+impl HandlerGate {
+    pub(crate) fn new(limits: HandlerLimits) -> Self {
+        Self {
+            limits,
+            in_flight: Mutex::default(),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, InFlight> {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn has_room(&self, in_flight: &InFlight, caller: Caller) -> bool {
+        self.has_global_room(in_flight)
+            && self.has_peer_room(in_flight, caller.cid)
+            && self.has_conn_room(in_flight, caller.conn_id)
+    }
+
+    fn has_global_room(&self, in_flight: &InFlight) -> bool {
+        self.limits.global.is_none_or(|max| in_flight.total < max)
+    }
+
+    fn has_peer_room(&self, in_flight: &InFlight, cid: u32) -> bool {
+        self.limits
+            .per_peer
+            .is_none_or(|max| in_flight.per_cid.get(&cid).copied().unwrap_or(0) < max)
+    }
+
+    fn has_conn_room(&self, in_flight: &InFlight, conn_id: u64) -> bool {
+        self.limits
+            .per_connection
+            .is_none_or(|max| in_flight.per_conn.get(&conn_id).copied().unwrap_or(0) < max)
+    }
+
+    /// 轮转中下一个可放行的排队调用
+    fn turn(&self, in_flight: &InFlight) -> Option<u64> {
+        let start = in_flight
+            .last_served
+            .map_or(Bound::Unbounded, Bound::Excluded);
+        // 先看上次放行的连接之后的，再从头绕回
+        in_flight
+            .waiting
+            .range((start, Bound::Unbounded))
+            .chain(in_flight.waiting.iter())
+            .find(|(conn_id, (cid, _))| {
+                self.has_conn_room(in_flight, **conn_id) && self.has_peer_room(in_flight, *cid)
+            })
+            .and_then(|(_, (_, queue))| queue.front().copied())
+    }
+
+    /// 排队等到轮到本调用或超时，返回时已离开队列
+    fn wait_turn<'a>(
+        &self,
+        mut in_flight: MutexGuard<'a, InFlight>,
+        caller: Caller,
+        timeout: Duration,
+    ) -> MutexGuard<'a, InFlight> {
+        let ticket = in_flight.next_ticket;
+        in_flight.next_ticket += 1;
+        in_flight
+            .waiting
+            .entry(caller.conn_id)
+            .or_insert_with(|| (caller.cid, VecDeque::new()))
+            .1
+            .push_back(ticket);
+        let deadline = Instant::now() + timeout;
+        while !(self.has_global_room(&in_flight) && self.turn(&in_flight) == Some(ticket)) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            in_flight = self
+                .freed
+                .wait_timeout(in_flight, left)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        in_flight.dequeue(caller.conn_id, ticket);
+        // 本调用离开队列后可能轮到其他等待者
+        self.freed.notify_all();
+        in_flight
+    }
+
+    /// 为 `caller` 的一次调用申请名额；已有调用排队时新调用也排队，不插队
+    pub(crate) fn acquire(self: &Arc<Self>, caller: Caller) -> Result<HandlerPermit> {
+        let mut in_flight = self.lock();
+        if let Overflow::Queue(timeout) = self.limits.overflow {
+            if !in_flight.waiting.is_empty() || !self.has_room(&in_flight, caller) {
+                in_flight = self.wait_turn(in_flight, caller, timeout);
+            }
+        }
+        if !self.has_room(&in_flight, caller) {
+            log_event!(
+                Level::Warn,
+                "handler limit reached",
+                cid = caller.cid,
+                conn_id = caller.conn_id,
+                in_flight = in_flight.total
+            );
+            return Err(Error::new(
+                ErrorKind::ResourceBusy,
+                format!(
+                    "server is busy, handler limit reached for cid {}",
+                    caller.cid
+                ),
+            ));
+        }
+        in_flight.total += 1;
+        *in_flight.per_cid.entry(caller.cid).or_default() += 1;
+        *in_flight.per_conn.entry(caller.conn_id).or_default() += 1;
+        in_flight.last_served = Some(caller.conn_id);
+        Ok(HandlerPermit {
+            slot: Some((self.clone(), caller)),
+        })
+    }
+
+    /// 正在执行的处理函数个数
+    pub(crate) fn in_flight(&self) -> usize {
+        self.lock().total
+    }
+
+    fn release(&self, caller: Caller) {
+        let mut in_flight = self.lock();
+        in_flight.total -= 1;
+        if let Some(count) = in_flight.per_cid.get_mut(&caller.cid) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.per_cid.remove(&caller.cid);
+            }
+        }
+        if let Some(count) = in_flight.per_conn.get_mut(&caller.conn_id) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.per_conn.remove(&caller.conn_id);
+            }
+        }
+        drop(in_flight);
+        self.freed.notify_all();
+    }
+}
+
+/// 一次调用的名额，drop 时归还
+#[derive(Debug)]
+pub struct HandlerPermit {
+    slot: Option<(Arc<HandlerGate>, Caller)>,
+}
+
+impl HandlerPermit {
+    /// 未配置上限时的空名额
+    pub(crate) const fn unlimited() -> Self {
+        Self { slot: None }
+    }
+}
+
+impl Drop for HandlerPermit {
+    fn drop(&mut self) {
+        if let Some((gate, caller)) = self.slot.take() {
+            gate.release(caller);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn gate(
+        global: Option<usize>,
+        per_peer: Option<usize>,
+        overflow: Overflow,
+    ) -> Arc<HandlerGate> {
+        Arc::new(HandlerGate::new(HandlerLimits {
+            global,
+            per_peer,
+            per_connection: None,
+            overflow,
+        }))
+    }
+
+    /// 每个 CID 一个连接
+    fn peer(cid: u32) -> Caller {
+        Caller {
+            cid,
+            conn_id: cid.into(),
+        }
+    }
+
+    #[test]
+    fn reject_when_global_or_per_peer_limit_is_reached() {
+        let gate = gate(Some(3), Some(2), Overflow::Reject);
+        let a1 = gate.acquire(peer(3)).unwrap();
+        let _a2 = gate.acquire(peer(3)).unwrap();
+        let err = gate.acquire(peer(3)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceBusy);
+
+        let _b1 = gate.acquire(peer(4)).unwrap();
+        assert_eq!(gate.in_flight(), 3);
+        assert!(gate.acquire(peer(5)).is_err());
+
+        drop(a1);
+        assert_eq!(gate.in_flight(), 2);
+        let _a3 = gate.acquire(peer(3)).unwrap();
+    }
+
+    #[test]
+    fn queued_caller_runs_when_a_permit_is_released() {
+        let gate = gate(Some(1), None, Overflow::Queue(Duration::from_secs(10)));
+        let held = gate.acquire(peer(3)).unwrap();
+        let waiter = {
+            let gate = gate.clone();
+            thread::spawn(move || gate.acquire(peer(4)).map(|_| ()))
+        };
+        thread::sleep(Duration::from_millis(20));
+        drop(held);
+        waiter.join().unwrap().unwrap();
+        assert_eq!(gate.in_flight(), 0);
+
+        let gate = self::gate(None, Some(1), Overflow::Queue(Duration::from_millis(10)));
+        let _held = gate.acquire(peer(3)).unwrap();
+        let err = gate.acquire(peer(3)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ResourceBusy);
+    }
+
+    #[test]
+    fn queued_callers_are_served_round_robin_across_connections() {
+        let gate = gate(Some(1), None, Overflow::Queue(Duration::from_secs(10)));
+        let conn = |cid, conn_id| Caller { cid, conn_id };
+        let held = gate.acquire(conn(3, 1)).unwrap();
+        let served = Arc::new(Mutex::new(Vec::new()));
+        // 连接 1 先排了三个调用，同一客户机的连接 2 与另一客户机的连接 3 之后才来
+        let waiters: Vec<_> = [conn(3, 1), conn(3, 1), conn(3, 1), conn(3, 2), conn(4, 3)]
+            .into_iter()
+            .enumerate()
+            .map(|(queued, caller)| {
+                let (shared, served) = (gate.clone(), served.clone());
+                let waiter = thread::spawn(move || {
+                    let _permit = shared.acquire(caller).unwrap();
+                    served.lock().unwrap().push(caller.conn_id);
+                });
+                while gate
+                    .lock()
+                    .waiting
+                    .values()
+                    .map(|(_, queue)| queue.len())
+                    .sum::<usize>()
+                    <= queued
+                {
+                    thread::yield_now();
+                }
+                waiter
+            })
+            .collect();
+        drop(held);
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!(*served.lock().unwrap(), [2, 3, 1, 1, 1]);
+        assert!(gate.lock().waiting.is_empty());
+    }
+
+    #[test]
+    fn per_connection_limit_leaves_room_for_other_connections_of_the_peer() {
+        let gate = Arc::new(HandlerGate::new(HandlerLimits {
+            global: None,
+            per_peer: Some(3),
+            per_connection: Some(2),
+            overflow: Overflow::Reject,
+        }));
+        let conn = |conn_id| Caller { cid: 3, conn_id };
+        let _a1 = gate.acquire(conn(1)).unwrap();
+        let a2 = gate.acquire(conn(1)).unwrap();
+        assert_eq!(
+            gate.acquire(conn(1)).unwrap_err().kind(),
+            ErrorKind::ResourceBusy
+        );
+        let _b1 = gate.acquire(conn(2)).unwrap();
+        // 同一 CID 合计已达 per_peer
+        assert!(gate.acquire(conn(2)).is_err());
+        drop(a2);
+        let _b2 = gate.acquire(conn(2)).unwrap();
+    }
+
+    #[test]
+    fn queued_caller_skips_a_full_connection_of_the_same_peer() {
+        let gate = Arc::new(HandlerGate::new(HandlerLimits {
+            global: Some(2),
+            per_peer: None,
+            per_connection: Some(1),
+            overflow: Overflow::Queue(Duration::from_secs(10)),
+        }));
+        let held = gate.acquire(Caller { cid: 3, conn_id: 1 }).unwrap();
+        let other = gate.acquire(peer(4)).unwrap();
+        // 连接 1 的调用排在前面，但连接 1 已满，连接 2 的调用先放行
+        let blocked = {
+            let gate = gate.clone();
+            thread::spawn(move || gate.acquire(Caller { cid: 3, conn_id: 1 }).map(|_| ()))
+        };
+        while gate.lock().waiting.is_empty() {
+            thread::yield_now();
+        }
+        let waiter = {
+            let gate = gate.clone();
+            thread::spawn(move || gate.acquire(Caller { cid: 3, conn_id: 2 }).map(|_| ()))
+        };
+        drop(other);
+        waiter.join().unwrap().unwrap();
+        drop(held);
+        blocked.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn limits_must_be_positive() {
+        let limits = |global, overflow| HandlerLimits {
+            global,
+            per_peer: None,
+            per_connection: None,
+            overflow,
+        };
+        assert!(limits(Some(1), Overflow::Reject).validate().is_ok());
+        assert!(limits(None, Overflow::Queue(Duration::from_secs(1)))
+            .validate()
+            .is_ok());
+        assert!(limits(Some(0), Overflow::Reject).validate().is_err());
+        let per_connection = HandlerLimits {
+            per_connection: Some(0),
+            ..limits(None, Overflow::Reject)
+        };
+        assert!(per_connection.validate().is_err());
+        assert!(limits(None, Overflow::Queue(Duration::ZERO))
+            .validate()
+            .is_err());
+    }
+
+    /// 名额归还后排队的调用都能轮到，且同时执行的不超过上限
+    #[cfg(loom)]
+    #[test]
+    fn loom_queued_callers_are_released_without_exceeding_the_limit() {
+        ::loom::model(|| {
+            let gate = gate(Some(1), None, Overflow::Queue(Duration::from_secs(60)));
+            let held = gate.acquire(peer(3)).unwrap();
+            let waiters: Vec<_> = [4, 5]
+                .into_iter()
+                .map(|cid| {
+                    let gate = gate.clone();
+                    ::loom::thread::spawn(move || {
+                        let _permit = gate.acquire(peer(cid)).unwrap();
+                        assert_eq!(gate.in_flight(), 1);
+                    })
+                })
+                .collect();
+            drop(held);
+            for waiter in waiters {
+                waiter.join().unwrap();
+            }
+            assert_eq!(gate.in_flight(), 0);
+            assert!(gate.lock().waiting.is_empty());
+        });
+    }
+}
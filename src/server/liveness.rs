@@ -0,0 +1,292 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 按对端 CID 记录的存活状态
+//!
+//! 配置 `ServerConfig::with_liveness()` 后，`ServerManager` 接受的每个连接从对端读到
+//! 任何帧（消息、客户机代理的心跳，以及传输内部回应的 ping/pong 等控制帧）时刷新所属
+//! CID 的“最后一次收到消息”时刻。yamux 由后台读任务读取，应用不在 `recv()` 中时同样
+//! 刷新；xtransport 在本端的每次读取（`recv()`、`wait_readable()`、`ping()` 等）中刷新。
+//! 快照可随时通过 `ServerManager::liveness()` 获取；后台线程在某个 CID 超过期限没有
+//! 消息时发布 `VirgaEvent::PeerStale`，之后再收到消息时发布 `VirgaEvent::PeerAlive`，
+//! 每次状态变化只发布一次。连接断开后记录仍然保留，客户机停止发送心跳与客户机消失
+//! 同样会被发现。
+
+use std::collections::BTreeMap;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::events::{self, VirgaEvent};
+use crate::sources::{self, Clock};
+use crate::threads;
+use crate::transport::FrameHook;
+
+/// 单个 CID 的存活状态
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerLiveness {
+    /// 最后一次收到该 CID 消息的时刻；还没有收到过消息时为连接被接受的时刻
+    pub last_seen: SystemTime,
+    /// 距最后一次收到消息的时长
+    pub silent_for: Duration,
+    /// 当前打开的连接数
+    pub connections: usize,
+    /// 是否已超过期限没有消息
+    pub stale: bool,
+}
+
+/// 某一时刻各 CID 的存活状态
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LivenessSnapshot {
+    /// 快照时间
+    pub taken_at: SystemTime,
+    /// 按 CID 排序的存活状态
+    pub peers: BTreeMap<u32, PeerLiveness>,
+}
+
+impl LivenessSnapshot {
+    /// `cid` 的存活状态，没有记录时为 `None`
+    pub fn get(&self, cid: u32) -> Option<PeerLiveness> {
+        self.peers.get(&cid).copied()
+    }
+
+    /// 已超过期限没有消息的 CID
+    pub fn stale(&self) -> impl Iterator<Item = u32> + '_ {
+        self.peers
+            .iter()
+            .filter(|(_, peer)| peer.stale)
+            .map(|(cid, _)| *cid)
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    last_seen: Instant,
+    last_seen_at: SystemTime,
+    connections: usize,
+    stale: bool,
+}
+
+/// 管理器持有的存活登记表
+#[derive(Debug)]
+pub(crate) struct LivenessRegistry {
+    stale_after: Duration,
+    peers: Mutex<BTreeMap<u32, Entry>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl LivenessRegistry {
+    pub(crate) fn new(stale_after: Duration) -> Self {
+        Self::with_clock(stale_after, sources::clock())
+    }
+
+    /// 以 `clock` 计算沉默时长；失联检查线程的轮询间隔仍按真实时间
+    pub(crate) fn with_clock(stale_after: Duration, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            stale_after,
+            peers: Mutex::new(BTreeMap::new()),
+            clock,
+        }
+    }
+
+    pub(crate) fn stale_after(&self) -> Duration {
+        self.stale_after
+    }
+
+    /// 为新接受的连接登记，接受连接本身算作一次收到消息
+    pub(crate) fn open(self: &Arc<Self>, cid: u32) -> PeerBeat {
+        self.with(cid, |entry| entry.connections += 1);
+        self.seen(cid);
+        PeerBeat {
+            registry: self.clone(),
+            cid,
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> LivenessSnapshot {
+        let now = self.clock.now();
+        let peers = self.peers.lock().unwrap_or_else(PoisonError::into_inner);
+        LivenessSnapshot {
+            taken_at: self.clock.system_time(),
+            peers: peers
+                .iter()
+                .map(|(cid, entry)| {
+                    let silent_for = now.saturating_duration_since(entry.last_seen);
+                    let peer = PeerLiveness {
+                        last_seen: entry.last_seen_at,
+                        silent_for,
+                        connections: entry.connections,
+                        stale: silent_for >= self.stale_after,
+                    };
+                    (*cid, peer)
+                })
+                .collect(),
+        }
+    }
+
+    fn with(&self, cid: u32, f: impl FnOnce(&mut Entry)) {
+        let mut peers = self.peers.lock().unwrap_or_else(PoisonError::into_inner);
+        f(peers.entry(cid).or_insert_with(|| Entry {
+            last_seen: self.clock.now(),
+            last_seen_at: self.clock.system_time(),
+            connections: 0,
+            stale: false,
+        }));
+    }
+
+    fn seen(&self, cid: u32) {
+        let mut recovered = false;
+        self.with(cid, |entry| {
+            entry.last_seen = self.clock.now();
+            entry.last_seen_at = self.clock.system_time();
+            recovered = std::mem::replace(&mut entry.stale, false);
+        });
+        if recovered {
+            events::emit(VirgaEvent::PeerAlive { cid });
+        }
+    }
+
+    /// 把超过期限的 CID 标记为失联，返回新失联的 CID 与其沉默时长
+    fn sweep(&self) -> Vec<(u32, Duration)> {
+        let now = self.clock.now();
+        let mut peers = self.peers.lock().unwrap_or_else(PoisonError::into_inner);
+        peers
+            .iter_mut()
+            .filter_map(|(cid, entry)| {
+                let silent_for = now.saturating_duration_since(entry.last_seen);
+                if entry.stale || silent_for < self.stale_after {
+                    return None;
+                }
+                entry.stale = true;
+                Some((*cid, silent_for))
+            })
+            .collect()
+    }
+
+    /// 启动失联检查线程，返回的发送端被 drop 后线程退出
+    pub(crate) fn spawn_watchdog(self: &Arc<Self>) -> Sender<()> {
+        let (stop, stopped) = channel::<()>();
+        let registry = self.clone();
+        let interval = self.stale_after / 4;
+        let spawned = threads::spawn("liveness", move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                for (cid, silent_for) in registry.sweep() {
+                    log::warn!("Peer cid={} silent for {:?}", cid, silent_for);
+                    events::emit(VirgaEvent::PeerStale { cid, silent_for });
+                }
+            }
+        });
+        spawned.expect("failed to spawn liveness watchdog thread");
+        stop
+    }
+}
+
+/// 单个连接在登记表中的条目，drop 时减少该 CID 的连接数
+pub(crate) struct PeerBeat {
+    registry: Arc<LivenessRegistry>,
+    cid: u32,
+}
+
+impl PeerBeat {
+    /// 交给传输的回调：读到对端任何帧时刷新存活记录
+    pub(crate) fn frame_hook(&self) -> FrameHook {
+        let (registry, cid) = (self.registry.clone(), self.cid);
+        Arc::new(move || registry.seen(cid))
+    }
+}
+
+impl Drop for PeerBeat {
+    fn drop(&mut self) {
+        self.registry.with(self.cid, |entry| entry.connections -= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::ManualClock;
+
+    const STALE_AFTER: Duration = Duration::from_secs(20);
+
+    fn registry() -> (Arc<LivenessRegistry>, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        let registry = LivenessRegistry::with_clock(STALE_AFTER, clock.clone());
+        (Arc::new(registry), clock)
+    }
+
+    #[test]
+    fn snapshot_tracks_connections_and_staleness() {
+        let (registry, clock) = registry();
+        let a = registry.open(3);
+        let b = registry.open(3);
+        drop(b);
+        let snapshot = registry.snapshot();
+        let peer = snapshot.get(3).unwrap();
+        assert_eq!(peer.connections, 1);
+        assert!(!peer.stale);
+        assert_eq!(snapshot.get(4), None);
+
+        clock.advance(STALE_AFTER - Duration::from_secs(1));
+        assert!(!registry.snapshot().get(3).unwrap().stale);
+        clock.advance(Duration::from_secs(1));
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.stale().collect::<Vec<_>>(), [3]);
+        assert_eq!(snapshot.get(3).unwrap().silent_for, STALE_AFTER);
+        assert_eq!(
+            snapshot.taken_at.duration_since(peer.last_seen).unwrap(),
+            STALE_AFTER
+        );
+        a.frame_hook()();
+        assert!(!registry.snapshot().get(3).unwrap().stale);
+    }
+
+    #[test]
+    fn staleness_is_reported_once_per_transition() {
+        let (registry, clock) = registry();
+        // 取一个其他测试不会用到的 CID
+        let cid = 0xfeed_0001;
+        let beat = registry.open(cid);
+        let events = events::subscribe();
+        clock.advance(STALE_AFTER);
+        let swept = registry.sweep();
+        assert_eq!(swept.len(), 1);
+        assert_eq!(swept[0].0, cid);
+        assert!(registry.sweep().is_empty());
+
+        let seen = beat.frame_hook();
+        seen();
+        seen();
+        let alive = std::iter::from_fn(|| events.try_recv())
+            .filter(|e| *e == VirgaEvent::PeerAlive { cid })
+            .count();
+        assert_eq!(alive, 1);
+    }
+
+    #[cfg(feature = "use-xtransport")]
+    #[test]
+    fn pings_alone_keep_a_peer_live() {
+        use crate::transport::xtransport::{TransportConfig, XTransport};
+        use std::os::unix::net::UnixStream;
+
+        let (registry, clock) = registry();
+        let cid = 0xfeed_0002;
+        let beat = registry.open(cid);
+        let (guest, host) = UnixStream::pair().unwrap();
+        let mut host = XTransport::new(host, TransportConfig::default());
+        let seen = beat.frame_hook();
+        host.set_frame_hook(Some(Box::new(move || seen())));
+        let guest = std::thread::spawn(move || {
+            XTransport::new(guest, TransportConfig::default())
+                .ping(1)
+                .unwrap();
+        });
+
+        clock.advance(STALE_AFTER);
+        assert!(registry.snapshot().get(cid).unwrap().stale);
+        // 宿主机一侧只回应了 ping，没有收到任何消息
+        assert!(!host.poll_message().unwrap());
+        guest.join().unwrap();
+        assert!(!registry.snapshot().get(cid).unwrap().stale);
+    }
+}
@@ -4,6 +4,27 @@
 
 //! 服务器模块
 
+mod activation;
+mod bandwidth;
+mod bind;
+mod builder;
+mod idempotency;
+mod incoming;
+mod limits;
+mod liveness;
+mod policy;
+mod tenant;
+mod versions;
+pub use bandwidth::{BandwidthSnapshot, CidUsage};
+pub use builder::{
+    ServerManagerBuilder, FIRECRACKER_DEFAULT_PORT, NITRO_DEFAULT_PORT, NITRO_PARENT_CID,
+};
+pub use incoming::Incoming;
+pub use limits::{HandlerLimits, HandlerPermit, Overflow};
+pub use liveness::{LivenessSnapshot, PeerLiveness};
+pub use policy::ServerPolicy;
+pub use tenant::TenantMap;
+pub use versions::ProtocolVersions;
 #[cfg(feature = "use-xtransport")]
 pub mod server_sync;
 #[cfg(feature = "use-xtransport")]
@@ -20,8 +41,44 @@ pub use crate::transport::YamuxTransportHandler;
 #[cfg(feature = "use-yamux")]
 pub use server_async::VirgeServer;
 
+#[cfg(feature = "use-xtransport")]
+use crate::mux::wake::poll_readable;
+#[cfg(feature = "use-xtransport")]
+use std::os::fd::AsFd;
+
+#[cfg(feature = "use-xtransport")]
+type Transport = XTransportHandler;
+#[cfg(feature = "use-yamux")]
+type Transport = YamuxTransportHandler;
+
+use crate::addr::Addr;
+use crate::auth::{Authorizer, TokenAuth};
+use crate::budget;
+use crate::codec::Schema;
+#[cfg(feature = "compression")]
+use crate::compression::Dictionary;
+use crate::defaults::Defaults;
+use crate::error::VirgeError;
+use crate::events::{self, Role, VirgaEvent};
+use crate::logging::log_event;
+use crate::transport::Coalescing;
+use crate::units::ByteSize;
+use crate::Leftovers;
+use bandwidth::{BandwidthLedger, BandwidthReport};
+use idempotency::IdempotencyCache;
+use incoming::Setup;
+use limits::HandlerGate;
+use liveness::LivenessRegistry;
 use log::*;
+use policy::SharedPolicy;
 use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+use versions::VersionTally;
 
 /// 监听器枚举
 enum Listener {
@@ -37,18 +94,69 @@ pub struct ServerConfig {
     listen_cid: u32,
     listen_port: u32,
     #[allow(dead_code)]
-    chunk_size: u32,
+    chunk_size: ByteSize,
     #[allow(dead_code)]
     is_ack: bool,
+    send_window: usize,
+    #[allow(dead_code)]
+    adaptive_chunk: bool,
+    #[allow(dead_code)]
+    io_uring: bool,
+    #[allow(dead_code)]
+    max_protocol_version: u8,
+    strict: bool,
+    #[allow(dead_code)]
+    shm: Option<(PathBuf, ByteSize)>,
+    policy: ServerPolicy,
+    auth: Option<TokenAuth>,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    schema: Option<Schema>,
+    #[cfg(feature = "compression")]
+    dictionaries: Vec<Dictionary>,
+    bandwidth_report: Option<BandwidthReport>,
+    memory_limit: Option<ByteSize>,
+    coalescing: Option<Coalescing>,
+    leftovers: Leftovers,
+    handler_limits: Option<HandlerLimits>,
+    socket_activation: bool,
+    bind_retry: Option<Duration>,
+    liveness: Option<Duration>,
+    max_connection_age: Option<(Duration, Duration)>,
+    tenants: Option<TenantMap>,
+    idempotency: Option<(Duration, usize)>,
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
+        let defaults = Defaults::get();
         Self {
-            listen_cid: crate::VMADDR_CID_ANY as u32,
-            listen_port: crate::DEFAULT_SERVER_PORT as u32,
-            chunk_size: crate::DEAFULT_CHUNK_SIZE as u32,
-            is_ack: crate::DEFAULT_IS_ACK,
+            listen_cid: defaults.listen_cid,
+            listen_port: defaults.server_port,
+            chunk_size: defaults.chunk_size(),
+            is_ack: defaults.is_ack,
+            send_window: defaults.send_window.get(),
+            adaptive_chunk: false,
+            io_uring: false,
+            max_protocol_version: crate::transport::MAX_PROTOCOL_VERSION,
+            strict: false,
+            shm: None,
+            policy: ServerPolicy::new(),
+            auth: None,
+            authorizer: None,
+            schema: None,
+            #[cfg(feature = "compression")]
+            dictionaries: Vec::new(),
+            bandwidth_report: None,
+            memory_limit: None,
+            coalescing: None,
+            leftovers: Leftovers::Error,
+            handler_limits: None,
+            socket_activation: false,
+            bind_retry: None,
+            liveness: None,
+            max_connection_age: None,
+            tenants: None,
+            idempotency: None,
         }
     }
 }
@@ -58,9 +166,318 @@ impl ServerConfig {
         Self {
             listen_cid: cid,
             listen_port: port,
-            chunk_size: chunk,
+            chunk_size: ByteSize::from(chunk),
             is_ack: isack,
+            send_window: Defaults::get().send_window.get(),
+            adaptive_chunk: false,
+            io_uring: false,
+            max_protocol_version: crate::transport::MAX_PROTOCOL_VERSION,
+            strict: false,
+            shm: None,
+            policy: ServerPolicy::new(),
+            auth: None,
+            authorizer: None,
+            schema: None,
+            #[cfg(feature = "compression")]
+            dictionaries: Vec::new(),
+            bandwidth_report: None,
+            memory_limit: None,
+            coalescing: None,
+            leftovers: Leftovers::Error,
+            handler_limits: None,
+            socket_activation: false,
+            bind_retry: None,
+            liveness: None,
+            max_connection_age: None,
+            tenants: None,
+            idempotency: None,
+        }
+    }
+
+    /// 设置监听地址
+    pub fn with_listen_addr(mut self, addr: Addr) -> Self {
+        self.listen_cid = addr.cid;
+        self.listen_port = addr.port;
+        self
+    }
+
+    /// 监听地址
+    pub fn listen_addr(&self) -> Addr {
+        Addr::new(self.listen_cid, self.listen_port)
+    }
+
+    /// 设置分片大小（单个数据包的最大字节数，含 16 字节包头）
+    pub fn with_chunk_size(mut self, size: ByteSize) -> Self {
+        self.chunk_size = size;
+        self
+    }
+
+    /// 设置发送窗口：大消息分片时同时在途（未确认）的最大包数
+    pub fn with_send_window(mut self, packets: usize) -> Self {
+        self.send_window = packets;
+        self
+    }
+
+    /// 开启自适应分片：以 chunk_size 为起点，根据吞吐和 ACK 延迟自动调整，
+    /// 当前值可通过 `stats().chunk_size` 查看
+    pub fn with_adaptive_chunk(mut self, enabled: bool) -> Self {
+        self.adaptive_chunk = enabled;
+        self
+    }
+
+    /// 接受的最高分帧版本（默认 `MAX_PROTOCOL_VERSION`）。客户端提议更高版本时按此
+    /// 应答，未提议的旧客户端保持 v1，同一服务端可同时服务两种分帧，各版本的连接数见
+    /// `ServerManager::protocol_versions()`。设为 1 则全部连接保持 v1。仅 XTransport 生效
+    pub fn with_max_protocol_version(mut self, version: u8) -> Self {
+        self.max_protocol_version = version;
+        self
+    }
+
+    /// 严格模式（默认关闭）：未知类型或不该出现的控制帧、超出定义长度的包头、与声明长度
+    /// 不符的分片等可疑情况按协议错误处理，接收返回错误；默认记录后跳过，
+    /// 以兼容较新的客户端
+    pub fn with_strict(mut self, enabled: bool) -> Self {
+        self.strict = enabled;
+        self
+    }
+
+    /// 使用 io_uring 读写 vsock，降低高消息速率下的系统调用开销（默认关闭）。
+    /// 需要启用 `use-io-uring` 特性，否则 `accept()` 返回配置错误
+    pub fn with_io_uring(mut self, enabled: bool) -> Self {
+        self.io_uring = enabled;
+        self
+    }
+
+    /// 接受客户端的共享内存提议：`path` 需与客户端映射同一块内存（宿主机上的
+    /// 同一文件，或 ivshmem 的后端文件），校验通过后大消息负载经共享内存传输
+    pub fn with_shared_memory(mut self, path: impl Into<PathBuf>) -> Self {
+        self.shm = Some((path.into(), ByteSize::b(0)));
+        self
+    }
+
+    /// 只允许这些 CID 的客户端接入；运行中更新后，已建立的连接在下一次收发时
+    /// 若对端不在列表内将返回 `PermissionDenied`，随后断开时客户端得知原因为
+    /// [`ShutdownReason::AuthRevoked`](crate::ShutdownReason::AuthRevoked)
+    pub fn with_allowed_cids(mut self, cids: impl IntoIterator<Item = u32>) -> Self {
+        self.policy.allowed_cids = Some(cids.into_iter().collect());
+        self
+    }
+
+    /// 空闲超时：等待下一条消息超过 `timeout` 时接收返回超时错误，
+    /// 随后应断开该连接，客户端得知原因为
+    /// [`ShutdownReason::IdleTimeout`](crate::ShutdownReason::IdleTimeout)
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.policy.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// 限制单个连接的收发速率（字节/秒），超出时收发调用阻塞等待
+    pub fn with_rate_limit(mut self, bytes_per_sec: ByteSize) -> Self {
+        self.policy.rate_limit = Some(bytes_per_sec);
+        self
+    }
+
+    /// 要求客户端在 `accept()` 内完成令牌认证，未通过的连接被丢弃，
+    /// 不会交给调用方；通过的连接可由 `peer_identity()` 取得对端身份
+    pub fn with_auth(mut self, auth: TokenAuth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// 设置授权规则，服务层通过 `VirgeServer::authorize()` 查询；
+    /// 连接在 `accept()` 时取得当时的规则
+    pub fn with_authorizer(mut self, authorizer: impl Authorizer + 'static) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
+    /// 认证后与客户端比对消息定义的指纹，不一致的连接在 `accept()` 内被丢弃；
+    /// 结果可通过 `VirgeServer::schema_match()` 查看
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// 添加压缩字典。客户端也配置字典时，握手中选出客户端提议里第一个双方都有
+    /// （ID 与内容都相同）的字典，之后该连接上的 `send_compressed()` 都使用它；
+    /// 没有共同字典时照常连接。需要启用 `compression` 特性
+    #[cfg(feature = "compression")]
+    pub fn with_dictionary(mut self, dictionary: Dictionary) -> Self {
+        self.dictionaries.push(dictionary);
+        self
+    }
+
+    /// 每隔 `interval` 以各 CID 的累计流量快照调用 `callback`，在 `start()` 时生效，
+    /// 运行中可经 `update_config()` 替换，`stop()` 后停止；回调在后台线程中执行
+    pub fn with_bandwidth_report(
+        mut self,
+        interval: Duration,
+        callback: impl Fn(&BandwidthSnapshot) + Send + Sync + 'static,
+    ) -> Self {
+        self.bandwidth_report = Some(BandwidthReport {
+            interval,
+            callback: Arc::new(callback),
+        });
+        self
+    }
+
+    /// 单个连接内部缓冲的上限，超过时该连接每次接收前释放复用缓冲；
+    /// 进程级上限见 `virga::budget::set_global_limit()`
+    pub fn with_memory_limit(mut self, limit: ByteSize) -> Self {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// 开启发送合批：不超过 `max_bytes` 的小消息先攒在连接内，攒够 `max_bytes` 或最早
+    /// 一条已等待 `max_delay` 时作为一帧发出，对端按原边界逐条接收。等待时长在下一次
+    /// 发送时检查，接收、ping 前会先发出批次；最后一条消息后调用 `flush()`
+    pub fn with_coalescing(mut self, max_delay: Duration, max_bytes: ByteSize) -> Self {
+        self.coalescing = Some(Coalescing {
+            max_delay,
+            max_bytes,
+        });
+        self
+    }
+
+    /// 一条消息经 `Read` 读了一部分时 `recv()` 的处理方式，默认 [`Leftovers::Error`]
+    pub fn with_leftovers(mut self, leftovers: Leftovers) -> Self {
+        self.leftovers = leftovers;
+        self
+    }
+
+    /// 限制 `rpc::serve_one()` 同时执行的处理函数个数，在 `start()` 时生效。
+    /// 上限对全部连接（`global`）、同一 CID 即同一客户机的全部连接（`per_peer`）与单个连接
+    /// （`per_connection`）分别计数，
+    /// 名额不足的调用按 `overflow` 排队或以 `ResourceBusy` 拒绝，排队的调用按连接轮转放行
+    pub fn with_handler_limits(mut self, limits: HandlerLimits) -> Self {
+        self.handler_limits = Some(limits);
+        self
+    }
+
+    /// 由 systemd 套接字激活启动时（设置了本进程的 `LISTEN_FDS`），`start()`
+    /// 接管继承的 vsock 监听 socket 而不自行绑定，实际地址以 `.socket` 单元为准；
+    /// 未被激活启动时照常按 `listen_cid`/`listen_port` 绑定
+    pub fn with_socket_activation(mut self, enabled: bool) -> Self {
+        self.socket_activation = enabled;
+        self
+    }
+
+    /// 绑定监听遇到端口占用、vsock 设备未就绪等暂时性错误时，在 `deadline` 内按
+    /// 指数退避重试，每次重试发布 `VirgaEvent::BindRetrying`；默认不重试
+    pub fn with_bind_retry(mut self, deadline: Duration) -> Self {
+        self.bind_retry = Some(deadline);
+        self
+    }
+
+    /// 按 CID 记录最后一次收到消息的时刻，可由 `ServerManager::liveness()` 查看；
+    /// 某个 CID 超过 `stale_after` 没有消息时发布 `VirgaEvent::PeerStale`，恢复后发布
+    /// `VirgaEvent::PeerAlive`。客户机应以短于 `stale_after` 的间隔发送消息或心跳
+    pub fn with_liveness(mut self, stale_after: Duration) -> Self {
+        self.liveness = Some(stale_after);
+        self
+    }
+
+    /// 连接存活超过 `age` 后，服务端在下一次收发时发送一次 GOAWAY，请客户端完成手头的
+    /// 请求后重连；再过 `grace` 仍未断开时由服务端断开，收发返回 `ConnectionAborted`。
+    /// 每个连接的 `age` 随机提前至多 10%，避免同时建立的连接同时重连。用于在机群中
+    /// 逐步推广新的配置与密钥
+    pub fn with_max_connection_age(mut self, age: Duration, grace: Duration) -> Self {
+        self.max_connection_age = Some((age, grace));
+        self
+    }
+
+    /// 按 `tenants` 给连接标上租户，没有租户的连接在握手后被拒绝，见 [`TenantMap`]
+    pub fn with_tenants(mut self, tenants: TenantMap) -> Self {
+        self.tenants = Some(tenants);
+        self
+    }
+
+    /// 记住带幂等键的调用（见 `rpc::call_idempotent()`）的结果，客户端重连后以同一个键
+    /// 重发时直接回复第一次的结果而不再执行处理函数。结果在完成后保留 `ttl`，至多
+    /// `capacity` 条，超出时先丢弃最早完成的；重启 `ServerManager` 后保留
+    pub fn with_idempotency(mut self, ttl: Duration, capacity: usize) -> Self {
+        self.idempotency = Some((ttl, capacity));
+        self
+    }
+
+    /// 运行时可更新的策略部分
+    pub fn policy(&self) -> &ServerPolicy {
+        &self.policy
+    }
+
+    /// 校验配置：分片大小、发送窗口、监听端口与策略参数，不合法时返回
+    /// `ConfigError`。`ServerManager::start()` 会先调用此方法
+    pub fn validate(&self) -> crate::Result<()> {
+        crate::validate_common(self.chunk_size, self.send_window)?;
+        if self.listen_port == 0 {
+            return Err(VirgeError::ConfigError(
+                "listen_port cannot be 0".to_string(),
+            ));
+        }
+        crate::transport::validate_protocol_version(self.max_protocol_version)?;
+        if self.policy.idle_timeout == Some(Duration::ZERO) {
+            return Err(VirgeError::ConfigError(
+                "idle_timeout must be greater than zero".to_string(),
+            ));
+        }
+        if self.policy.rate_limit == Some(ByteSize::b(0)) {
+            return Err(VirgeError::ConfigError(
+                "rate_limit must be greater than zero".to_string(),
+            ));
+        }
+        if let Some(auth) = &self.auth {
+            auth.validate()?;
+        }
+        if let Some(schema) = &self.schema {
+            schema.validate()?;
+        }
+        if let Some(tenants) = &self.tenants {
+            tenants.validate()?;
+        }
+        #[cfg(feature = "compression")]
+        Dictionary::validate(&self.dictionaries)?;
+        if let Some(coalescing) = &self.coalescing {
+            coalescing.validate()?;
+        }
+        if let Some(limits) = &self.handler_limits {
+            limits.validate()?;
+        }
+        if self.bind_retry == Some(Duration::ZERO) {
+            return Err(VirgeError::ConfigError(
+                "bind retry deadline must be greater than zero".to_string(),
+            ));
+        }
+        if self
+            .max_connection_age
+            .is_some_and(|(age, _)| age.is_zero())
+        {
+            return Err(VirgeError::ConfigError(
+                "max connection age must be greater than zero".to_string(),
+            ));
+        }
+        if self
+            .idempotency
+            .is_some_and(|(ttl, capacity)| ttl.is_zero() || capacity == 0)
+        {
+            return Err(VirgeError::ConfigError(
+                "idempotency ttl and capacity must be greater than zero".to_string(),
+            ));
+        }
+        if self.liveness == Some(Duration::ZERO) {
+            return Err(VirgeError::ConfigError(
+                "liveness period must be greater than zero".to_string(),
+            ));
         }
+        if self
+            .bandwidth_report
+            .as_ref()
+            .is_some_and(|r| r.interval.is_zero())
+        {
+            return Err(VirgeError::ConfigError(
+                "bandwidth report interval must be greater than zero".to_string(),
+            ));
+        }
+        Ok(())
     }
 }
 
@@ -69,6 +486,16 @@ pub struct ServerManager {
     config: ServerConfig,
     listener: Option<Listener>,
     running: bool,
+    policy: Option<Arc<SharedPolicy>>,
+    bandwidth: Option<Arc<BandwidthLedger>>,
+    versions: Option<Arc<VersionTally>>,
+    handlers: Option<Arc<HandlerGate>>,
+    reporter: Option<Sender<()>>,
+    liveness: Option<Arc<LivenessRegistry>>,
+    idempotency: Option<Arc<IdempotencyCache>>,
+    watchdog: Option<Sender<()>>,
+    draining: Option<Arc<AtomicBool>>,
+    setup: Option<Arc<Setup>>,
 }
 
 impl ServerManager {
@@ -77,72 +504,441 @@ impl ServerManager {
             config,
             listener: None,
             running: false,
+            policy: None,
+            bandwidth: None,
+            versions: None,
+            handlers: None,
+            reporter: None,
+            liveness: None,
+            idempotency: None,
+            watchdog: None,
+            draining: None,
+            setup: None,
+        }
+    }
+
+    /// 创建构建器，可用 `for_nitro()` 等预设选择部署环境的默认值
+    pub fn builder() -> ServerManagerBuilder {
+        ServerManagerBuilder::new()
+    }
+
+    /// 当前配置
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// 运行时更新配置：
+    ///
+    /// - 策略（允许的 CID、空闲超时、速率上限）整体替换，对已接受的连接在其下一次收发时生效；
+    /// - 流量报告（`with_bandwidth_report()`）立即按新的周期与回调重新开始；
+    /// - 分片、窗口、认证、schema 等连接参数只影响之后接受的连接；
+    /// - 监听地址、处理函数并发上限、存活跟踪与幂等缓存由已接受的连接共享，运行中修改返回
+    ///   `ConfigError`，需 `stop()` 后再更新
+    pub fn update_config(&mut self, config: ServerConfig) -> Result<()> {
+        config.validate()?;
+        if self.running {
+            let fixed = [
+                (
+                    "listen address",
+                    config.listen_cid != self.config.listen_cid
+                        || config.listen_port != self.config.listen_port,
+                ),
+                (
+                    "handler limits",
+                    config.handler_limits != self.config.handler_limits,
+                ),
+                ("liveness", config.liveness != self.config.liveness),
+                ("idempotency", config.idempotency != self.config.idempotency),
+            ];
+            if let Some((name, _)) = fixed.iter().find(|(_, changed)| *changed) {
+                return Err(VirgeError::ConfigError(format!(
+                    "{} cannot be changed while running",
+                    name
+                ))
+                .into());
+            }
+        }
+
+        info!("ServerManager applying new config: {:?}", config.policy);
+        if let Some(shared) = &self.policy {
+            shared.update(config.policy.clone());
         }
+        if self.running {
+            // 丢弃旧的 Sender 即停止旧的报告线程
+            self.reporter = match (&config.bandwidth_report, &self.bandwidth) {
+                (Some(report), Some(ledger)) => Some(ledger.spawn_reporter(report.clone())),
+                _ => None,
+            };
+        }
+        self.config = config;
+        self.setup = None;
+        Ok(())
     }
 
     pub fn start(&mut self) -> Result<()> {
-        info!(
-            "ServerManager starting on cid={}, port={}",
-            self.config.listen_cid, self.config.listen_port
-        );
+        info!("ServerManager starting on {}", self.config.listen_addr());
 
+        self.config.validate()?;
         self.listener = Some(self.create_listener()?);
+        self.policy = Some(SharedPolicy::new(self.config.policy.clone()));
+        self.versions.get_or_insert_with(Default::default);
+        let ledger = self.bandwidth.get_or_insert_with(Default::default);
+        self.reporter = self
+            .config
+            .bandwidth_report
+            .clone()
+            .map(|report| ledger.spawn_reporter(report));
+        self.handlers = self
+            .config
+            .handler_limits
+            .map(|limits| Arc::new(HandlerGate::new(limits)));
+        self.liveness = match self.config.liveness {
+            // 期限不变时保留已有记录，重启后继续跟踪
+            Some(stale_after) => match self.liveness.take() {
+                Some(registry) if registry.stale_after() == stale_after => Some(registry),
+                _ => Some(Arc::new(LivenessRegistry::new(stale_after))),
+            },
+            None => None,
+        };
+        self.idempotency = match self.config.idempotency {
+            // 设置不变时保留已记住的结果，重启前发出的调用重发时仍不会重复执行
+            Some((ttl, capacity)) => match self.idempotency.take() {
+                Some(cache) if cache.settings() == (ttl, capacity) => Some(cache),
+                _ => Some(Arc::new(IdempotencyCache::new(ttl, capacity))),
+            },
+            None => None,
+        };
+        self.watchdog = self.liveness.as_ref().map(|r| r.spawn_watchdog());
+        self.draining = Some(Arc::new(AtomicBool::new(false)));
         self.running = true;
         Ok(())
     }
 
+    /// 自首次 `start()` 以来各 CID 的累计流量，重启后继续累计
+    pub fn bandwidth(&self) -> BandwidthSnapshot {
+        self.bandwidth.as_ref().map_or_else(
+            || BandwidthLedger::default().snapshot(),
+            |ledger| ledger.snapshot(),
+        )
+    }
+
+    /// 各分帧版本的连接数，重启后继续累计已关闭的连接。升级过渡期内据此判断旧客户端
+    /// 是否都已升级，见 `ServerConfig::with_max_protocol_version()`
+    pub fn protocol_versions(&self) -> ProtocolVersions {
+        self.versions
+            .as_ref()
+            .map_or_else(ProtocolVersions::default, |tally| tally.snapshot())
+    }
+
+    /// 开始排空以便维护：已接受的连接在下一次收发时向客户端发送 GOAWAY，客户端完成
+    /// 手头的请求后断开并重连到其他实例或稍后重连；之后到来的连接被拒绝。连接照常服务到
+    /// 客户端断开，之后调用 `stop()`；再次 `start()` 时结束排空。未运行时不起作用
+    pub fn drain(&self) {
+        if let Some(draining) = &self.draining {
+            info!("ServerManager draining connections");
+            draining.store(true, Ordering::Release);
+        }
+    }
+
+    /// 是否正在排空
+    pub fn is_draining(&self) -> bool {
+        self.draining
+            .as_ref()
+            .is_some_and(|d| d.load(Ordering::Acquire))
+    }
+
+    /// 各 CID 最后一次收到消息的时刻与是否失联，未配置 `with_liveness()` 时为空
+    pub fn liveness(&self) -> LivenessSnapshot {
+        self.liveness.as_ref().map_or_else(
+            || LivenessSnapshot {
+                taken_at: std::time::SystemTime::now(),
+                peers: Default::default(),
+            },
+            |registry| registry.snapshot(),
+        )
+    }
+
+    /// 正在执行的处理函数个数，未配置 `with_handler_limits()` 时为 0
+    pub fn handlers_in_flight(&self) -> usize {
+        self.handlers.as_ref().map_or(0, |gate| gate.in_flight())
+    }
+
     fn create_listener(&self) -> Result<Listener> {
+        if self.config.socket_activation {
+            let fds = activation::listen_fds()?;
+            if !fds.is_empty() {
+                return self.adopt_listener(activation::find_vsock_listener(&fds)?);
+            }
+            info!("No LISTEN_FDS passed, binding vsock listener");
+        }
+
+        let addr = self.config.listen_addr();
+
         #[cfg(feature = "use-yamux")]
         {
-            let addr = tokio_vsock::VsockAddr::new(self.config.listen_cid, self.config.listen_port);
-            let listener =
-                get_runtime().block_on(async { tokio_vsock::VsockListener::bind(addr) })?;
-            return Ok(Listener::Yamux(listener));
+            let listener = bind::bind_with_retry(addr, self.config.bind_retry, || {
+                get_runtime().block_on(async { tokio_vsock::VsockListener::bind(addr.into()) })
+            })?;
+            Ok(Listener::Yamux(listener))
         }
 
         #[cfg(feature = "use-xtransport")]
         {
-            let addr = vsock::VsockAddr::new(self.config.listen_cid, self.config.listen_port);
-            let listener = vsock::VsockListener::bind(&addr)?;
-            return Ok(Listener::XTransport(listener));
+            let listener = bind::bind_with_retry(addr, self.config.bind_retry, || {
+                vsock::VsockListener::bind(&vsock::VsockAddr::from(addr))
+            })?;
+            Ok(Listener::XTransport(listener))
         }
     }
 
+    /// 监听 socket 因快照恢复失效时重新绑定，返回的错误让调用方再次 `accept()`
+    fn relisten(&mut self, e: Error) -> Error {
+        if !crate::resume::is_resume_error(&e) {
+            return e;
+        }
+        let addr = self.config.listen_addr();
+        warn!("Resumed from snapshot ({}), re-binding {}", e, addr);
+        events::emit(VirgaEvent::ResumedFromSnapshot {
+            role: Role::Server,
+            cid: addr.cid,
+            port: addr.port,
+            reason: e.to_string(),
+        });
+        self.listener = None;
+        match self.create_listener() {
+            Ok(listener) => {
+                self.listener = Some(listener);
+                Error::new(
+                    ErrorKind::ConnectionAborted,
+                    format!("listener lost to snapshot restore ({}), re-bound", e),
+                )
+            }
+            Err(rebind) => rebind,
+        }
+    }
+
+    /// 接管 systemd 传入的监听 socket
+    fn adopt_listener(&self, fd: RawFd) -> Result<Listener> {
+        #[cfg(feature = "use-yamux")]
+        {
+            // SAFETY: fd 已确认是处于监听状态的 vsock socket，所有权转交给监听器
+            let listener = get_runtime()
+                .block_on(async { unsafe { tokio_vsock::VsockListener::from_raw_fd(fd) } });
+            let addr = listener.local_addr()?;
+            log_event!(
+                Level::Info,
+                "adopted socket-activated listener",
+                fd = fd,
+                cid = addr.cid(),
+                port = addr.port()
+            );
+            Ok(Listener::Yamux(listener))
+        }
+
+        #[cfg(feature = "use-xtransport")]
+        {
+            // SAFETY: fd 已确认是处于监听状态的 vsock socket，所有权转交给监听器
+            let listener = unsafe { vsock::VsockListener::from_raw_fd(fd) };
+            let addr = listener.local_addr()?;
+            log_event!(
+                Level::Info,
+                "adopted socket-activated listener",
+                fd = fd,
+                cid = addr.cid(),
+                port = addr.port()
+            );
+            Ok(Listener::XTransport(listener))
+        }
+    }
+
+    /// 接受连接并在当前线程完成握手，等同于 `accept_incoming()?.handshake()`。握手期间不会
+    /// 接受其他连接，同时服务多个连接时应改用 [`accept_incoming()`](Self::accept_incoming)
     pub fn accept(&mut self) -> Result<VirgeServer> {
+        self.accept_incoming()?.handshake()
+    }
+
+    /// 同 [`accept()`](Self::accept)，`timeout` 内没有连接到来时返回 `None`，监管循环可借此
+    /// 在等待连接的间隙刷新指标、重新加载配置等。期限只约束等待连接，连接到来后的认证等握手
+    /// 仍按各自的超时进行
+    pub fn accept_timeout(&mut self, timeout: Duration) -> Result<Option<VirgeServer>> {
+        self.accept_incoming_timeout(timeout)?
+            .map(Incoming::handshake)
+            .transpose()
+    }
+
+    /// 接受连接并做 CID、排空与内存预算检查，不进行握手。接受循环把返回的 [`Incoming`]
+    /// 交给处理该连接的线程，由其调用 [`Incoming::handshake()`]，慢的或不应答的客户端不会
+    /// 阻塞其他连接的接受
+    pub fn accept_incoming(&mut self) -> Result<Incoming> {
+        self.accept_within(None)
+            .map(|incoming| incoming.expect("accept without a deadline waits for a connection"))
+    }
+
+    /// 同 [`accept_incoming()`](Self::accept_incoming)，`timeout` 内没有连接到来时返回 `None`
+    pub fn accept_incoming_timeout(&mut self, timeout: Duration) -> Result<Option<Incoming>> {
+        self.accept_within(Some(timeout))
+    }
+
+    fn accept_within(&mut self, timeout: Option<Duration>) -> Result<Option<Incoming>> {
         if !self.running {
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!("ServerManager not running"),
-            ));
+            return Err(Error::other("ServerManager not running"));
         }
 
-        let transport = match &self.listener {
+        let (mut transport, cid) = match &self.listener {
             #[cfg(feature = "use-xtransport")]
             Some(Listener::XTransport(xtransport_listener)) => {
-                let (stream, addr) = xtransport_listener.accept()?;
-                info!("Accepted xtransport connection from {:?}", addr);
+                if let Some(timeout) = timeout {
+                    match poll_readable(xtransport_listener.as_fd(), None, Some(timeout)) {
+                        Ok(true) => {}
+                        Ok(false) => return Ok(None),
+                        Err(e) => return Err(self.relisten(e)),
+                    }
+                }
+                let (stream, addr) = match xtransport_listener.accept() {
+                    Ok(accepted) => accepted,
+                    Err(e) => return Err(self.relisten(e)),
+                };
+                info!("Accepted xtransport connection from {}", Addr::from(addr));
 
                 // 创建 XTransportHandler 实例并从流初始化
-                let mut transport = XTransportHandler::new();
-                transport.from_stream(stream, self.config.chunk_size, self.config.is_ack)?;
-                transport
+                let mut transport = XTransportHandler::new()
+                    .with_send_window(self.config.send_window)
+                    .with_adaptive_chunk(self.config.adaptive_chunk)
+                    .with_io_uring(self.config.io_uring)
+                    .with_max_version(self.config.max_protocol_version)
+                    .with_strict(self.config.strict)
+                    .with_memory_limit(self.config.memory_limit)
+                    .with_coalescing(self.config.coalescing);
+                if let Some((path, size)) = &self.config.shm {
+                    transport = transport.with_shared_memory(path.clone(), size.as_usize());
+                }
+                transport.from_stream(
+                    stream,
+                    self.config.chunk_size.as_u64() as u32,
+                    self.config.is_ack,
+                )?;
+                (transport, addr.cid())
             }
             #[cfg(feature = "use-yamux")]
             Some(Listener::Yamux(yamux_listener)) => {
-                let (stream, addr) =
-                    get_runtime().block_on(async { yamux_listener.accept().await })?;
-                info!("Accepted yamux connection from {:?}", addr);
+                let accepted = get_runtime().block_on(async {
+                    match timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, yamux_listener.accept())
+                            .await
+                            .ok(),
+                        None => Some(yamux_listener.accept().await),
+                    }
+                });
+                let (stream, addr) = match accepted {
+                    Some(Ok(accepted)) => accepted,
+                    Some(Err(e)) => return Err(self.relisten(e)),
+                    None => return Ok(None),
+                };
+                info!("Accepted yamux connection from {}", Addr::from(addr));
                 // 创建 YamuxTransport 实例并从流初始化
-                let mut transport = YamuxTransportHandler::new(yamux::Mode::Server);
+                let mut transport = YamuxTransportHandler::new(yamux::Mode::Server)
+                    .with_strict(self.config.strict)
+                    .with_memory_limit(self.config.memory_limit)
+                    .with_coalescing(self.config.coalescing);
                 transport.from_tokio_stream(stream)?;
-                transport
+                (transport, addr.cid())
             }
             None => {
-                return Err(Error::other(format!("Listener not initialized")));
+                return Err(Error::other("Listener not initialized"));
+            }
+        };
+
+        // 拒绝前先告知客户端原因，客户端的收发返回 `VirgeError::Rejected`
+        if let Err(e) = self.admit(cid) {
+            if let Err(sent) = transport.reject(&e.to_string()) {
+                warn!("Failed to send rejection to cid {}: {}", cid, sent);
             }
+            let _ = transport.disconnect();
+            return Err(e);
+        }
+
+        let setup = match &self.setup {
+            Some(setup) => setup.clone(),
+            None => self.setup.insert(Arc::new(self.snapshot())).clone(),
         };
+        Ok(Some(Incoming::new(transport, cid, setup)))
+    }
+
+    /// 当前配置中握手与构造连接所需的部分，连同各连接共享的状态
+    fn snapshot(&self) -> Setup {
+        Setup {
+            auth: self.config.auth.clone(),
+            schema: self.config.schema.clone(),
+            #[cfg(feature = "compression")]
+            dictionaries: self.config.dictionaries.clone(),
+            tenants: self.config.tenants.clone(),
+            authorizer: self.config.authorizer.clone(),
+            max_connection_age: self.config.max_connection_age,
+            leftovers: self.config.leftovers,
+            policy: self.policy.clone(),
+            bandwidth: self.bandwidth.clone(),
+            versions: self.versions.clone(),
+            handlers: self.handlers.clone(),
+            idempotency: self.idempotency.clone(),
+            liveness: self.liveness.clone(),
+            draining: self.draining.clone(),
+        }
+    }
+
+    /// 接受连接前的检查：排空、CID 策略与内存预算
+    fn admit(&self, cid: u32) -> Result<()> {
+        self.check_draining(cid)?;
+        self.check_peer(cid)?;
+        self.check_budget(cid)
+    }
 
-        Ok(VirgeServer::new(transport, true))
+    /// 排空期间拒绝新连接
+    fn check_draining(&self, cid: u32) -> Result<()> {
+        if !self.is_draining() {
+            return Ok(());
+        }
+        log_event!(
+            Level::Info,
+            "connection rejected: server draining",
+            cid = cid
+        );
+        Err(Error::new(
+            ErrorKind::ConnectionRefused,
+            "server is draining, connect to another instance",
+        ))
+    }
+
+    /// 拒绝不在允许列表中的 CID
+    fn check_peer(&self, cid: u32) -> Result<()> {
+        if self.config.policy.allows(cid) {
+            return Ok(());
+        }
+        log_event!(
+            Level::Warn,
+            "connection rejected: cid not allowed",
+            cid = cid
+        );
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("connection from cid {} is not allowed", cid),
+        ))
+    }
+
+    /// 进程内存预算已用尽时拒绝新连接
+    fn check_budget(&self, cid: u32) -> Result<()> {
+        if !budget::over_global_limit() {
+            return Ok(());
+        }
+        log_event!(
+            Level::Warn,
+            "connection rejected: memory budget exhausted",
+            cid = cid,
+            held = budget::held().as_u64(),
+        );
+        Err(Error::new(
+            ErrorKind::OutOfMemory,
+            format!("memory budget exhausted, rejecting cid {}", cid),
+        ))
     }
 
     /// 停止服务器
@@ -150,6 +946,11 @@ impl ServerManager {
         info!("ServerManager stopping");
         self.listener = None;
         self.running = false;
+        self.policy = None;
+        self.reporter = None;
+        self.watchdog = None;
+        self.draining = None;
+        self.setup = None;
         Ok(())
     }
 
@@ -158,17 +959,30 @@ impl ServerManager {
     }
 }
 
+/// `[0, max)` 内的随机时长，取自 [`crate::sources::entropy()`]
+fn jitter(max: Duration) -> Duration {
+    let nanos = max.as_nanos() as u64;
+    if nanos == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos(crate::sources::entropy().next_u64() % nanos)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::auth::PeerIdentity;
+    use policy::PolicyWatch;
+    use std::io::ErrorKind;
+    use std::time::Instant;
 
     #[test]
     fn server_config_default_values() {
         let config = ServerConfig::default();
-        assert_eq!(config.listen_cid, crate::VMADDR_CID_ANY as u32);
-        assert_eq!(config.listen_port, crate::DEFAULT_SERVER_PORT as u32);
-        assert_eq!(config.chunk_size, crate::DEAFULT_CHUNK_SIZE as u32);
-        assert_eq!(config.is_ack, crate::DEFAULT_IS_ACK);
+        assert_eq!(config.listen_cid, Defaults::get().listen_cid);
+        assert_eq!(config.listen_port, Defaults::get().server_port);
+        assert_eq!(config.chunk_size, Defaults::get().chunk_size());
+        assert_eq!(config.is_ack, Defaults::get().is_ack);
     }
 
     #[test]
@@ -176,7 +990,7 @@ mod tests {
         let config = ServerConfig::new(100, 9999, 4096, true);
         assert_eq!(config.listen_cid, 100);
         assert_eq!(config.listen_port, 9999);
-        assert_eq!(config.chunk_size, 4096);
+        assert_eq!(config.chunk_size, ByteSize::kib(4));
         assert!(config.is_ack);
     }
 
@@ -185,7 +999,7 @@ mod tests {
         let config = ServerConfig::new(0, 0, 0, false);
         assert_eq!(config.listen_cid, 0);
         assert_eq!(config.listen_port, 0);
-        assert_eq!(config.chunk_size, 0);
+        assert_eq!(config.chunk_size, ByteSize::b(0));
         assert!(!config.is_ack);
     }
 
@@ -194,7 +1008,7 @@ mod tests {
         let config = ServerConfig::new(u32::MAX, u32::MAX, u32::MAX, true);
         assert_eq!(config.listen_cid, u32::MAX);
         assert_eq!(config.listen_port, u32::MAX);
-        assert_eq!(config.chunk_size, u32::MAX);
+        assert_eq!(config.chunk_size, ByteSize::from(u32::MAX));
     }
 
     #[test]
@@ -207,6 +1021,58 @@ mod tests {
         assert_eq!(config.is_ack, cloned.is_ack);
     }
 
+    #[test]
+    fn server_config_with_send_window() {
+        let config = ServerConfig::default().with_send_window(8);
+        assert_eq!(config.send_window, 8);
+        assert_eq!(
+            ServerConfig::default().send_window,
+            Defaults::get().send_window.get()
+        );
+    }
+
+    #[test]
+    fn server_config_with_adaptive_chunk() {
+        assert!(!ServerConfig::default().adaptive_chunk);
+        assert!(
+            ServerConfig::default()
+                .with_adaptive_chunk(true)
+                .adaptive_chunk
+        );
+    }
+
+    #[test]
+    fn server_config_max_protocol_version_is_validated() {
+        let config = ServerConfig::default();
+        assert!(config
+            .clone()
+            .with_max_protocol_version(1)
+            .validate()
+            .is_ok());
+        assert!(matches!(
+            config.with_max_protocol_version(0).validate(),
+            Err(VirgeError::ConfigError(_))
+        ));
+        let manager = ServerManager::new(ServerConfig::default());
+        assert_eq!(manager.protocol_versions(), ProtocolVersions::default());
+    }
+
+    #[test]
+    fn server_config_with_shared_memory() {
+        assert!(ServerConfig::default().shm.is_none());
+        let config = ServerConfig::default().with_shared_memory("/dev/shm/virga");
+        assert_eq!(
+            config.shm,
+            Some((PathBuf::from("/dev/shm/virga"), ByteSize::b(0)))
+        );
+    }
+
+    #[test]
+    fn server_config_io_uring_off_by_default() {
+        assert!(!ServerConfig::default().io_uring);
+        assert!(ServerConfig::default().with_io_uring(true).io_uring);
+    }
+
     #[test]
     fn server_manager_new_initial_state() {
         let config = ServerConfig::default();
@@ -253,6 +1119,48 @@ mod tests {
         assert!(err.to_string().contains("not running"));
     }
 
+    #[test]
+    fn server_manager_accept_timeout_returns_none_when_idle() {
+        let mut manager = ServerManager::new(ServerConfig::new(u32::MAX, 51998, 1024, false));
+        assert!(manager.accept_timeout(Duration::from_millis(10)).is_err());
+        // 没有 vsock 的环境无法监听，只检查未启动时的错误
+        if manager.start().is_err() {
+            return;
+        }
+        let started = Instant::now();
+        let accepted = manager.accept_timeout(Duration::from_millis(100)).unwrap();
+        assert!(accepted.is_none());
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        manager.stop().unwrap();
+    }
+
+    #[test]
+    fn server_manager_accept_incoming_timeout_returns_none_when_idle() {
+        let mut manager = ServerManager::new(ServerConfig::new(u32::MAX, 51997, 1024, false));
+        assert!(manager.accept_incoming().is_err());
+        if manager.start().is_err() {
+            return;
+        }
+        let accepted = manager
+            .accept_incoming_timeout(Duration::from_millis(10))
+            .unwrap();
+        assert!(accepted.is_none());
+        manager.stop().unwrap();
+    }
+
+    #[test]
+    fn update_config_applies_to_handshakes_of_later_connections() {
+        let mut manager = ServerManager::new(ServerConfig::default());
+        manager.setup = Some(Arc::new(manager.snapshot()));
+        let tenants = TenantMap::new().with_default("shared");
+        manager
+            .update_config(ServerConfig::default().with_tenants(tenants))
+            .unwrap();
+        assert!(manager.setup.is_none());
+        let tenant = manager.snapshot().assign_tenant(3, None).unwrap();
+        assert_eq!(tenant.as_deref(), Some("shared"));
+    }
+
     #[test]
     fn server_config_is_ack_false_default() {
         let config = ServerConfig::default();
@@ -307,6 +1215,263 @@ mod tests {
         }
     }
 
+    #[test]
+    fn server_config_validate() {
+        assert!(ServerConfig::default().validate().is_ok());
+        assert!(ServerConfig::new(u32::MAX, u32::MAX, 1024, false)
+            .validate()
+            .is_ok());
+        for config in [
+            ServerConfig::new(u32::MAX, 0, 1024, false),
+            ServerConfig::new(u32::MAX, 1234, 0, false),
+            ServerConfig::default().with_send_window(0),
+        ] {
+            assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        }
+    }
+
+    #[test]
+    fn server_config_policy_builders() {
+        assert_eq!(ServerConfig::default().policy(), &ServerPolicy::default());
+        let config = ServerConfig::default()
+            .with_allowed_cids([3, 4])
+            .with_idle_timeout(Duration::from_secs(30))
+            .with_rate_limit(ByteSize::mib(1));
+        assert_eq!(config.policy().allowed_cids, Some(vec![3, 4]));
+        assert_eq!(config.policy().idle_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.policy().rate_limit, Some(ByteSize::mib(1)));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn server_config_validate_policy() {
+        let config = ServerConfig::default().with_idle_timeout(Duration::ZERO);
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        let config = ServerConfig::default().with_rate_limit(ByteSize::b(0));
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        let config = ServerConfig::default().with_auth(TokenAuth::new());
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        let config = ServerConfig::default().with_auth(TokenAuth::new().with_token("agent", b"k"));
+        // 未启用 auth 特性时配置认证即是错误
+        assert_eq!(config.validate().is_ok(), cfg!(feature = "auth"));
+    }
+
+    #[test]
+    fn server_config_bandwidth_report() {
+        let config = ServerConfig::default().with_bandwidth_report(Duration::ZERO, |_| {});
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        let config = ServerConfig::default().with_bandwidth_report(Duration::from_secs(60), |_| {});
+        assert!(config.validate().is_ok());
+        let manager = ServerManager::new(config);
+        assert!(manager.bandwidth().usage.is_empty());
+    }
+
+    #[test]
+    fn server_config_handler_limits() {
+        let limits = HandlerLimits {
+            global: Some(64),
+            per_peer: Some(0),
+            per_connection: None,
+            overflow: Overflow::Reject,
+        };
+        let config = ServerConfig::default().with_handler_limits(limits);
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        let config = ServerConfig::default().with_handler_limits(HandlerLimits {
+            per_peer: Some(8),
+            per_connection: Some(4),
+            ..limits
+        });
+        assert!(config.validate().is_ok());
+        assert_eq!(ServerManager::new(config).handlers_in_flight(), 0);
+    }
+
+    #[test]
+    fn server_manager_socket_activation_without_listen_fds_binds() {
+        // 测试进程不是由 systemd 激活的，应退回到自行绑定
+        let config = ServerConfig::default().with_socket_activation(true);
+        assert!(config.socket_activation);
+        let mut manager = ServerManager::new(config);
+        if manager.start().is_err() {
+            assert!(!manager.is_running());
+        }
+    }
+
+    #[test]
+    fn server_config_bind_retry() {
+        let config = ServerConfig::default().with_bind_retry(Duration::ZERO);
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        let config = ServerConfig::default().with_bind_retry(Duration::from_secs(30));
+        assert_eq!(config.bind_retry, Some(Duration::from_secs(30)));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn server_config_max_connection_age() {
+        let config =
+            ServerConfig::default().with_max_connection_age(Duration::ZERO, Duration::ZERO);
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        let age = Duration::from_secs(3600);
+        let config = ServerConfig::default().with_max_connection_age(age, Duration::from_secs(30));
+        assert!(config.validate().is_ok());
+        assert!(jitter(age / 10) < age / 10);
+        assert_eq!(jitter(Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn drain_only_applies_while_running() {
+        let mut manager = ServerManager::new(ServerConfig::default());
+        manager.drain();
+        assert!(!manager.is_draining());
+        manager.draining = Some(Arc::new(AtomicBool::new(false)));
+        manager.drain();
+        assert!(manager.is_draining());
+        manager.stop().unwrap();
+        assert!(!manager.is_draining());
+    }
+
+    #[test]
+    fn connections_without_a_tenant_are_rejected() {
+        use crate::auth::Principal;
+
+        let manager = ServerManager::new(ServerConfig::default());
+        assert_eq!(manager.snapshot().assign_tenant(3, None).unwrap(), None);
+
+        let tenants = TenantMap::new()
+            .assign(Principal::Name("billing".to_string()), "billing")
+            .assign(Principal::Cid(7), "search");
+        let manager = ServerManager::new(ServerConfig::default().with_tenants(tenants));
+        let peer = PeerIdentity {
+            cid: 3,
+            name: "billing".to_string(),
+        };
+        let tenant = manager.snapshot().assign_tenant(3, Some(&peer)).unwrap();
+        assert_eq!(tenant.as_deref(), Some("billing"));
+        let tenant = manager.snapshot().assign_tenant(7, None).unwrap();
+        assert_eq!(tenant.as_deref(), Some("search"));
+        let err = manager.snapshot().assign_tenant(8, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        let config = ServerConfig::default().with_tenants(TenantMap::new().with_default(""));
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+    }
+
+    #[test]
+    fn server_config_idempotency() {
+        for (ttl, capacity) in [(Duration::ZERO, 16), (Duration::from_secs(60), 0)] {
+            let config = ServerConfig::default().with_idempotency(ttl, capacity);
+            assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        }
+        let config = ServerConfig::default().with_idempotency(Duration::from_secs(60), 16);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn server_config_liveness() {
+        let config = ServerConfig::default().with_liveness(Duration::ZERO);
+        assert!(matches!(config.validate(), Err(VirgeError::ConfigError(_))));
+        let manager = ServerManager::new(ServerConfig::default());
+        assert!(manager.liveness().peers.is_empty());
+    }
+
+    #[test]
+    fn server_manager_update_config_before_start() {
+        let mut manager = ServerManager::new(ServerConfig::default());
+        let config = ServerConfig::new(100, 4321, 2048, true).with_allowed_cids([3]);
+        assert!(manager.update_config(config).is_ok());
+        assert_eq!(manager.config().listen_port, 4321);
+        assert_eq!(manager.config().policy().allowed_cids, Some(vec![3]));
+        assert!(manager.check_peer(3).is_ok());
+        assert_eq!(
+            manager.check_peer(4).unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn server_manager_update_config_rejects_invalid() {
+        let mut manager = ServerManager::new(ServerConfig::default());
+        let err = manager
+            .update_config(ServerConfig::default().with_send_window(0))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(
+            manager.config().send_window,
+            Defaults::get().send_window.get()
+        );
+    }
+
+    #[test]
+    fn server_manager_update_config_while_running() {
+        let mut manager = ServerManager::new(ServerConfig::default());
+        let shared = SharedPolicy::new(ServerPolicy::default());
+        manager.policy = Some(shared.clone());
+        manager.running = true;
+
+        let mut watch = PolicyWatch::new(shared);
+        assert!(watch.refresh());
+
+        let moved = ServerConfig::new(u32::MAX, 9999, 1024, false);
+        assert!(manager.update_config(moved).is_err());
+
+        let tightened = ServerConfig::default()
+            .with_allowed_cids([3])
+            .with_idle_timeout(Duration::from_secs(10));
+        assert!(manager.update_config(tightened).is_ok());
+        assert!(watch.refresh());
+        assert_eq!(watch.policy().idle_timeout, Some(Duration::from_secs(10)));
+        assert!(watch.check_peer(5).is_err());
+    }
+
+    #[test]
+    fn server_manager_update_config_rejects_shared_state_changes_while_running() {
+        let mut manager = ServerManager::new(ServerConfig::default());
+        manager.running = true;
+        for config in [
+            ServerConfig::default().with_handler_limits(HandlerLimits {
+                global: Some(4),
+                per_peer: None,
+                per_connection: None,
+                overflow: Overflow::Reject,
+            }),
+            ServerConfig::default().with_liveness(Duration::from_secs(30)),
+            ServerConfig::default().with_idempotency(Duration::from_secs(60), 16),
+        ] {
+            let err = manager.update_config(config).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        assert_eq!(manager.config().handler_limits, None);
+
+        manager.running = false;
+        let config = ServerConfig::default().with_liveness(Duration::from_secs(30));
+        assert!(manager.update_config(config).is_ok());
+    }
+
+    #[test]
+    fn server_manager_update_config_restarts_bandwidth_report() {
+        let mut manager = ServerManager::new(ServerConfig::default());
+        manager.bandwidth = Some(Default::default());
+        manager.running = true;
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let config =
+            ServerConfig::default().with_bandwidth_report(Duration::from_millis(10), move |_| {
+                let _ = tx.lock().unwrap().send(());
+            });
+        manager.update_config(config).unwrap();
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        manager.update_config(ServerConfig::default()).unwrap();
+        assert!(manager.reporter.is_none());
+    }
+
+    #[test]
+    fn server_manager_start_rejects_invalid_config() {
+        let mut manager = ServerManager::new(ServerConfig::new(u32::MAX, 0, 1024, false));
+        let err = manager.start().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(!manager.is_running());
+    }
+
     #[test]
     fn server_manager_create_listener_xtransport() {
         let config = ServerConfig::new(0, 12345, 1024, false);
@@ -337,8 +1502,8 @@ mod tests {
         let config = ServerConfig::new(123, 456, 789, true);
         assert_eq!(config.listen_cid, 123);
         assert_eq!(config.listen_port, 456);
-        assert_eq!(config.chunk_size, 789);
-        assert_eq!(config.is_ack, true);
+        assert_eq!(config.chunk_size, ByteSize::b(789));
+        assert!(config.is_ack);
     }
 
     #[test]
@@ -347,10 +1512,34 @@ mod tests {
         const CONFIG: ServerConfig = ServerConfig {
             listen_cid: 100,
             listen_port: 1234,
-            chunk_size: 1024,
+            chunk_size: ByteSize::kib(1),
             is_ack: false,
+            send_window: Defaults::BUILTIN.send_window.get(),
+            adaptive_chunk: false,
+            io_uring: false,
+            max_protocol_version: crate::transport::MAX_PROTOCOL_VERSION,
+            strict: false,
+            shm: None,
+            policy: ServerPolicy::new(),
+            auth: None,
+            authorizer: None,
+            schema: None,
+            #[cfg(feature = "compression")]
+            dictionaries: Vec::new(),
+            bandwidth_report: None,
+            memory_limit: None,
+            coalescing: None,
+            leftovers: Leftovers::Error,
+            handler_limits: None,
+            socket_activation: false,
+            bind_retry: None,
+            liveness: None,
+            max_connection_age: None,
+            tenants: None,
+            idempotency: None,
         };
         const MANAGER: ServerManager = ServerManager::new(CONFIG);
-        assert!(!MANAGER.running);
+        let manager = MANAGER;
+        assert!(!manager.running);
     }
 }
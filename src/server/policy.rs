@@ -0,0 +1,267 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 运行时可更新的服务端策略
+//!
+//! `ServerManager` 持有一份共享策略，接受的每个连接持有它的观察者；
+//! `update_config()` 整体替换策略并递增版本号，连接在下一次收发时发现版本
+//! 变化后重新读取，因此无需重启即可收紧限制。
+
+use crate::sources::{self, Clock};
+use crate::units::ByteSize;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// 服务端连接策略，对已建立和之后建立的连接同时生效
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServerPolicy {
+    /// 允许接入的客户端 CID，`None` 表示不限制
+    pub allowed_cids: Option<Vec<u32>>,
+    /// 空闲超时：等待下一条消息超过该时长时接收返回超时错误
+    pub idle_timeout: Option<Duration>,
+    /// 单个连接的收发速率上限（字节/秒）
+    pub rate_limit: Option<ByteSize>,
+}
+
+impl ServerPolicy {
+    pub const fn new() -> Self {
+        Self {
+            allowed_cids: None,
+            idle_timeout: None,
+            rate_limit: None,
+        }
+    }
+
+    /// `cid` 是否允许接入
+    pub fn allows(&self, cid: u32) -> bool {
+        self.allowed_cids
+            .as_ref()
+            .is_none_or(|cids| cids.contains(&cid))
+    }
+}
+
+/// 管理器与连接共享的策略
+pub(crate) struct SharedPolicy {
+    policy: RwLock<ServerPolicy>,
+    version: AtomicU64,
+}
+
+impl SharedPolicy {
+    pub(crate) fn new(policy: ServerPolicy) -> Arc<Self> {
+        Arc::new(Self {
+            policy: RwLock::new(policy),
+            version: AtomicU64::new(1),
+        })
+    }
+
+    /// 整体替换策略
+    pub(crate) fn update(&self, policy: ServerPolicy) {
+        let mut guard = self.policy.write().unwrap_or_else(|e| e.into_inner());
+        *guard = policy;
+        self.version.fetch_add(1, Ordering::Release);
+    }
+
+    fn load(&self) -> (u64, ServerPolicy) {
+        let guard = self.policy.read().unwrap_or_else(|e| e.into_inner());
+        (self.version.load(Ordering::Acquire), guard.clone())
+    }
+}
+
+/// 单个连接持有的策略视图，只在版本变化时重新读取
+pub(crate) struct PolicyWatch {
+    shared: Arc<SharedPolicy>,
+    version: u64,
+    current: ServerPolicy,
+    limiter: Option<RateLimiter>,
+    clock: Arc<dyn Clock>,
+}
+
+impl PolicyWatch {
+    pub(crate) fn new(shared: Arc<SharedPolicy>) -> Self {
+        Self::with_clock(shared, sources::clock())
+    }
+
+    /// 以 `clock` 计算限速的补充额度与等待
+    pub(crate) fn with_clock(shared: Arc<SharedPolicy>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            shared,
+            version: 0,
+            current: ServerPolicy::default(),
+            limiter: None,
+            clock,
+        }
+    }
+
+    /// 拉取最新策略，有变化时返回 true（调用方据此重新应用空闲超时）
+    pub(crate) fn refresh(&mut self) -> bool {
+        if self.shared.version.load(Ordering::Acquire) == self.version {
+            return false;
+        }
+        let (version, policy) = self.shared.load();
+        self.limiter = match (policy.rate_limit, self.limiter.take()) {
+            (Some(rate), Some(mut limiter)) => {
+                limiter.set_rate(rate.as_u64());
+                Some(limiter)
+            }
+            (Some(rate), None) => Some(RateLimiter::new(rate.as_u64(), self.clock.now())),
+            (None, _) => None,
+        };
+        self.version = version;
+        self.current = policy;
+        true
+    }
+
+    pub(crate) fn policy(&self) -> &ServerPolicy {
+        &self.current
+    }
+
+    /// 对端 CID 不再被允许时返回 `PermissionDenied`
+    pub(crate) fn check_peer(&self, cid: u32) -> Result<()> {
+        if self.current.allows(cid) {
+            Ok(())
+        } else {
+            Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("peer cid {} is not allowed", cid),
+            ))
+        }
+    }
+
+    /// 记录本次收发的字节数，超过速率上限时阻塞等待
+    pub(crate) fn throttle(&mut self, bytes: usize) {
+        if let Some(limiter) = &mut self.limiter {
+            let delay = limiter.consume(bytes as u64, self.clock.now());
+            if !delay.is_zero() {
+                self.clock.sleep(delay);
+            }
+        }
+    }
+}
+
+/// 令牌桶限速器，桶容量为一秒的额度
+struct RateLimiter {
+    rate: u64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            last: now,
+        }
+    }
+
+    fn set_rate(&mut self, rate: u64) {
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate as f64);
+    }
+
+    /// 扣除 `bytes` 个令牌，返回需要等待的时长；令牌可以透支，
+    /// 因此单条大于一秒额度的消息也能通过，只是随后需要等待更久
+    fn consume(&mut self, bytes: u64, now: Instant) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        let rate = self.rate as f64;
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * rate).min(rate) - bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::ManualClock;
+
+    #[test]
+    fn policy_allows_any_cid_by_default() {
+        let policy = ServerPolicy::default();
+        assert!(policy.allows(3));
+        assert!(policy.allows(u32::MAX));
+    }
+
+    #[test]
+    fn policy_allowed_cids() {
+        let policy = ServerPolicy {
+            allowed_cids: Some(vec![3, 4]),
+            ..ServerPolicy::default()
+        };
+        assert!(policy.allows(3));
+        assert!(!policy.allows(5));
+    }
+
+    #[test]
+    fn watch_sees_updates_once() {
+        let shared = SharedPolicy::new(ServerPolicy::default());
+        let mut watch = PolicyWatch::new(shared.clone());
+        assert!(watch.refresh());
+        assert!(!watch.refresh());
+        assert!(watch.check_peer(7).is_ok());
+
+        shared.update(ServerPolicy {
+            allowed_cids: Some(vec![3]),
+            idle_timeout: Some(Duration::from_secs(5)),
+            rate_limit: None,
+        });
+        assert!(watch.refresh());
+        assert_eq!(watch.policy().idle_timeout, Some(Duration::from_secs(5)));
+        let err = watch.check_peer(7).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert!(watch.check_peer(3).is_ok());
+    }
+
+    #[test]
+    fn throttle_waits_on_the_watch_clock() {
+        let shared = SharedPolicy::new(ServerPolicy {
+            rate_limit: Some(ByteSize::b(1000)),
+            ..ServerPolicy::default()
+        });
+        let clock = Arc::new(ManualClock::new());
+        let mut watch = PolicyWatch::with_clock(shared, clock.clone());
+        assert!(watch.refresh());
+        watch.throttle(1000);
+        assert_eq!(clock.elapsed(), Duration::ZERO);
+        watch.throttle(250);
+        assert_eq!(clock.elapsed(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn rate_limiter_delays_after_burst() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(1000, start);
+        assert_eq!(limiter.consume(600, start), Duration::ZERO);
+        assert_eq!(limiter.consume(400, start), Duration::ZERO);
+        let delay = limiter.consume(500, start);
+        assert_eq!(delay, Duration::from_millis(500));
+        // 等待结束后额度恢复到透支前
+        assert_eq!(
+            limiter.consume(0, start + Duration::from_millis(500)),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn rate_limiter_refills_over_time() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(1000, start);
+        limiter.consume(1000, start);
+        assert_eq!(
+            limiter.consume(250, start + Duration::from_millis(250)),
+            Duration::ZERO
+        );
+        limiter.set_rate(100);
+        assert!(limiter.consume(50, start + Duration::from_millis(250)) > Duration::ZERO);
+    }
+}
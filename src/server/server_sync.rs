@@ -2,11 +2,30 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
+use super::bandwidth::{CidAccount, Direction};
+use super::idempotency::IdempotencyCache;
+use super::limits::{Caller, HandlerGate, HandlerPermit};
+use super::liveness::PeerBeat;
+use super::policy::PolicyWatch;
+use super::versions::VersionSlot;
+use crate::auth::{Authorizer, PeerIdentity};
+use crate::clock::ClockOffset;
+use crate::codec::{Codec, SchemaMatch};
+use crate::error::ConnContext;
+use crate::events::{self, Role, VirgaEvent};
+use crate::logging::log_event;
+use crate::selftest;
+use crate::sources::{self, Clock};
+use crate::stats::ConnectionStats;
+use crate::transport::RecvLoan;
 use crate::transport::XTransportHandler;
-use crate::ReadState;
+use crate::{AtBoundary, GoAwayReason, Leftovers, ReadState, ShutdownReason};
 use log::*;
 use std::io::{Error, ErrorKind, Result};
-use std::io::{Read, Write};
+use std::io::{IoSliceMut, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Virga 服务器连接：与VirgeClient类似，负责单个连接的数据传输。
 pub struct VirgeServer {
@@ -14,15 +33,300 @@ pub struct VirgeServer {
     connected: bool,
     read_buffer: Vec<u8>,  // 读取缓存
     read_state: ReadState, // 读取状态
+    policy: Option<PolicyWatch>,
+    peer: Option<PeerIdentity>,
+    tenant: Option<Arc<str>>,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    schema_match: Option<SchemaMatch>,
+    account: Option<CidAccount>,
+    versions: Option<VersionSlot>,
+    handlers: Option<Arc<HandlerGate>>,
+    idempotency: Option<Arc<IdempotencyCache>>,
+    leftovers: Leftovers,
+    liveness: Option<PeerBeat>,
+    accepted_at: Instant,
+    /// 计算连接年龄的时钟
+    clock: Arc<dyn Clock>,
+    /// 最大存活时长与之后的宽限期
+    max_age: Option<(Duration, Duration)>,
+    draining: Option<Arc<AtomicBool>>,
+    goaway_sent: bool,
+    /// 断开时告知客户端的结束原因，`None` 时由传输层判断
+    ending: Option<ShutdownReason>,
 }
 
 impl VirgeServer {
     pub fn new(trans: XTransportHandler, conn: bool) -> Self {
+        let clock = sources::clock();
         Self {
             transport_handler: trans,
             connected: conn,
             read_buffer: Vec::new(),
             read_state: ReadState::Idle,
+            policy: None,
+            peer: None,
+            tenant: None,
+            authorizer: None,
+            schema_match: None,
+            account: None,
+            versions: None,
+            handlers: None,
+            idempotency: None,
+            leftovers: Leftovers::Error,
+            liveness: None,
+            accepted_at: clock.now(),
+            clock,
+            max_age: None,
+            draining: None,
+            goaway_sent: false,
+            ending: None,
+        }
+    }
+
+    /// 一条消息经 `Read` 读了一部分时 `recv()` 的处理方式
+    pub(crate) fn with_leftovers(mut self, leftovers: Leftovers) -> Self {
+        self.leftovers = leftovers;
+        self
+    }
+
+    /// 收到对端任何帧（含 ping 等控制帧）时刷新 `ServerManager` 中该 CID 的存活记录
+    pub(crate) fn with_liveness(mut self, liveness: Option<PeerBeat>) -> Self {
+        if let Some(beat) = &liveness {
+            self.transport_handler.set_frame_hook(beat.frame_hook());
+        }
+        self.liveness = liveness;
+        self
+    }
+
+    /// 存活超过 `age` 后发送 GOAWAY 请客户端重连，再过 `grace` 仍未断开时由本端断开
+    pub(crate) fn with_max_age(mut self, max_age: Option<(Duration, Duration)>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// `ServerManager::drain()` 置位后，下一次收发时发送 GOAWAY
+    pub(crate) fn with_draining(mut self, draining: Option<Arc<AtomicBool>>) -> Self {
+        self.draining = draining;
+        self
+    }
+
+    /// 发送 GOAWAY：请客户端完成手头的请求后断开，稍后或换一个服务端重连，用于不中断
+    /// 服务的维护。连接仍可继续收发，直到客户端断开；每个连接只发送一次
+    pub fn drain(&mut self) -> Result<()> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
+        }
+        self.go_away(GoAwayReason::Drain)
+    }
+
+    fn go_away(&mut self, reason: GoAwayReason) -> Result<()> {
+        if self.goaway_sent {
+            return Ok(());
+        }
+        self.goaway_sent = true;
+        if reason == GoAwayReason::Drain {
+            self.ending.get_or_insert(ShutdownReason::ServerDraining);
+        }
+        let conn = self.transport_handler.conn();
+        log_event!(
+            Level::Info,
+            "sending goaway",
+            conn_id = conn.conn_id,
+            cid = conn.cid,
+            reason = format!("{:?}", reason).as_str(),
+            age_ms = self.age().as_millis() as u64,
+        );
+        Ok(self.transport_handler.go_away(reason)?)
+    }
+
+    /// 连接被接受以来的时长
+    fn age(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.accepted_at)
+    }
+
+    /// 跟随 `ServerManager` 的共享策略
+    pub(crate) fn with_policy(mut self, policy: PolicyWatch) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// 收发字节计入 `ServerManager` 的按 CID 流量统计
+    pub(crate) fn with_account(mut self, account: Option<CidAccount>) -> Self {
+        self.account = account;
+        self
+    }
+
+    /// 按所用分帧版本计入 `ServerManager::protocol_versions()`
+    pub(crate) fn with_versions(mut self, versions: Option<VersionSlot>) -> Self {
+        self.versions = versions;
+        self
+    }
+
+    pub(crate) fn with_peer(mut self, peer: Option<PeerIdentity>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    pub(crate) fn with_tenant(mut self, tenant: Option<Arc<str>>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
+    /// 连接上下文：连接 ID 与对端地址
+    pub fn conn(&self) -> ConnContext {
+        self.transport_handler.conn()
+    }
+
+    /// 连接当前使用的分帧版本，客户端提议更高版本前为 1
+    pub fn protocol_version(&self) -> u8 {
+        self.transport_handler.protocol_version()
+    }
+
+    /// 握手认证得到的对端身份，未启用认证时为 `None`
+    pub fn peer_identity(&self) -> Option<&PeerIdentity> {
+        self.peer.as_ref()
+    }
+
+    /// 连接所属的租户，未配置 `ServerConfig::with_tenants()` 时为 `None`
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub(crate) fn with_schema_match(mut self, schema_match: Option<SchemaMatch>) -> Self {
+        self.schema_match = schema_match;
+        self
+    }
+
+    /// 消息定义协商结果，服务端未配置 `with_schema()` 时为 `None`
+    pub fn schema_match(&self) -> Option<SchemaMatch> {
+        self.schema_match
+    }
+
+    pub(crate) fn with_authorizer(mut self, authorizer: Option<Arc<dyn Authorizer>>) -> Self {
+        self.authorizer = authorizer;
+        self
+    }
+
+    /// 查询对端能否调用 `service` 的 `method`，不允许时返回 `PermissionDenied`；
+    /// 未配置授权规则时总是允许。未启用认证时只能按 CID 授权
+    pub fn authorize(&self, service: &str, method: &str) -> Result<()> {
+        let Some(authorizer) = &self.authorizer else {
+            return Ok(());
+        };
+        let peer = self.peer.clone().unwrap_or_else(|| PeerIdentity {
+            cid: self.transport_handler.conn().cid,
+            name: String::new(),
+        });
+        if authorizer.authorize(&peer, service, method) {
+            return Ok(());
+        }
+        warn!("Denied {}/{} for {}", service, method, peer);
+        Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} may not call {}/{}", peer, service, method),
+        ))
+    }
+
+    pub(crate) fn with_handler_gate(mut self, handlers: Option<Arc<HandlerGate>>) -> Self {
+        self.handlers = handlers;
+        self
+    }
+
+    pub(crate) fn with_idempotency(mut self, cache: Option<Arc<IdempotencyCache>>) -> Self {
+        self.idempotency = cache;
+        self
+    }
+
+    /// 带幂等键的调用：配置了 `with_idempotency()` 时同一对端、同一个键只执行一次 `f`，
+    /// 之后返回第一次的响应；未配置时照常执行
+    pub(crate) fn run_idempotent(&self, key: &str, f: impl FnOnce() -> Vec<u8>) -> Vec<u8> {
+        match &self.idempotency {
+            Some(cache) => cache.run(self.transport_handler.conn().cid, key, f),
+            None => f(),
+        }
+    }
+
+    /// 为一次处理函数调用申请名额，持有期间计入 `with_handler_limits()` 的上限；
+    /// 未配置上限时立即返回
+    pub fn admit(&self) -> Result<HandlerPermit> {
+        match &self.handlers {
+            Some(gate) => {
+                let conn = self.transport_handler.conn();
+                gate.acquire(Caller {
+                    cid: conn.cid,
+                    conn_id: conn.conn_id,
+                })
+            }
+            None => Ok(HandlerPermit::unlimited()),
+        }
+    }
+
+    /// 收发前应用最新策略：更新空闲超时，并拒绝已不在允许列表中的对端
+    fn enforce_policy(&mut self) -> Result<()> {
+        self.check_recycle()?;
+        let Some(policy) = &mut self.policy else {
+            return Ok(());
+        };
+        if policy.refresh() {
+            self.transport_handler
+                .set_idle_timeout(policy.policy().idle_timeout)?;
+        }
+        let checked = policy.check_peer(self.transport_handler.conn().cid);
+        if checked.is_err() {
+            self.ending = Some(ShutdownReason::AuthRevoked);
+        }
+        checked
+    }
+
+    /// 管理器要求排空或超过最大存活时长时发送一次 GOAWAY，超过宽限期后断开连接
+    fn check_recycle(&mut self) -> Result<()> {
+        if self
+            .draining
+            .as_ref()
+            .is_some_and(|d| d.load(Ordering::Acquire))
+        {
+            self.go_away(GoAwayReason::Drain)?;
+        }
+        let Some((age, grace)) = self.max_age else {
+            return Ok(());
+        };
+        let elapsed = self.age();
+        if elapsed < age {
+            return Ok(());
+        }
+        if elapsed >= age + grace {
+            warn!("Closing connection still open {:?} after GOAWAY", grace);
+            self.disconnect()?;
+            return Err(Error::new(
+                ErrorKind::ConnectionAborted,
+                format!("connection exceeded its max age of {:?}", age + grace),
+            ));
+        }
+        self.go_away(GoAwayReason::MaxAge)
+    }
+
+    /// 按策略限速，计入按 CID 的流量统计
+    fn account(&mut self, direction: Direction, bytes: usize) {
+        if let Some(policy) = &mut self.policy {
+            policy.throttle(bytes);
+        }
+        if let Some(account) = &self.account {
+            account.record(direction, bytes);
+        }
+        let version = self.transport_handler.protocol_version();
+        if self
+            .versions
+            .as_mut()
+            .is_some_and(|slot| slot.observe(version))
+        {
+            let conn = self.transport_handler.conn();
+            log_event!(
+                Level::Info,
+                "protocol version negotiated",
+                conn_id = conn.conn_id,
+                cid = conn.cid,
+                version = version,
+            );
         }
     }
 }
@@ -33,40 +337,266 @@ impl VirgeServer {
         if !self.connected {
             return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
         }
-        self.transport_handler
-            .send(&data)
-            .map_err(|e| Error::other(format!("send error: {}", e)))
+        self.enforce_policy()?;
+        let len = self.transport_handler.send(&data).map_err(Error::from)?;
+        self.account(Direction::Sent, len);
+        Ok(len)
+    }
+
+    /// 以 zstd 压缩后发送，对端接收时自动解压；返回原文长度
+    #[cfg(feature = "compression")]
+    pub fn send_compressed(&mut self, data: &[u8]) -> Result<usize> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
+        }
+        self.enforce_policy()?;
+        let len = self
+            .transport_handler
+            .send_compressed(data)
+            .map_err(Error::from)?;
+        self.account(Direction::Sent, len);
+        Ok(len)
     }
 
     /// 接收数据
     pub fn recv(&mut self) -> Result<Vec<u8>> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
+        }
+        self.enforce_policy()?;
+        if self.read_state != ReadState::Idle {
+            match self.leftovers {
+                Leftovers::Error => self.read_state.at_boundary(self.read_buffer.len())?,
+                Leftovers::DrainFirst => return Ok(self.take_partial()),
+                Leftovers::Prepend => {}
+            }
+        }
+        let data = self.transport_handler.recv().map_err(Error::from)?;
+        self.account(Direction::Received, data.len());
+        if self.read_state == ReadState::Idle {
+            return Ok(data);
+        }
+        // Leftovers::Prepend
+        let mut message = self.take_partial();
+        message.extend_from_slice(&data);
+        Ok(message)
+    }
+
+    /// 把 `data` 作为一条完整的消息发送，返回发送字节数。对端用 `recv()` 整条接收，
+    /// 或用 `recv_sized()` 得知长度后经 `Read` 读取，消息前无需另加长度前缀
+    pub fn send_sized(&mut self, data: &[u8]) -> Result<usize> {
+        Write::write(self, data)
+    }
+
+    /// 把下一条消息交给 `Read` 读取并返回其长度，之后 `read_exact()` 读满这个长度即读完
+    /// 整条消息；上一条消息还没经 `Read` 读完时不接收新消息，返回其剩余长度
+    pub fn recv_sized(&mut self) -> Result<usize> {
+        if self.read_state == ReadState::Idle {
+            let data = self.recv()?;
+            if !data.is_empty() {
+                self.read_state = ReadState::Reading {
+                    total: data.len(),
+                    read: 0,
+                };
+                self.read_buffer = data;
+                self.transport_handler.set_pending(self.read_buffer.len());
+            }
+        }
+        Ok(self.read_buffer.len())
+    }
+
+    /// 取出经 `Read` 读了一部分的消息的其余字节，回到消息边界，之后可以继续用 `recv()`
+    /// 等消息接口；没有读了一半的消息时返回空
+    pub fn take_partial(&mut self) -> Vec<u8> {
+        self.read_state = ReadState::Idle;
+        self.transport_handler.set_pending(0);
+        std::mem::take(&mut self.read_buffer)
+    }
+
+    /// 协议出错后重新对齐到消息边界，而不必重连：丢弃经 `Read` 读了一部分的消息，
+    /// 并让下一次接收跳过上次接收失败时残留的分片。已完整收到、尚未取走的消息保留。
+    /// 返回丢弃的已缓冲字节数；包头损坏或读到一半超时造成的字节级错位无法恢复
+    pub fn resync(&mut self) -> usize {
+        let discarded = self.take_partial().len();
+        self.transport_handler.resync();
+        if discarded > 0 {
+            debug!("Resync discarded {} buffered bytes", discarded);
+        }
+        discarded
+    }
+
+    /// 接收一条消息到连接内部复用的缓冲区，省去为每条消息分配 `Vec`；
+    /// 返回的视图借用连接，在下一次接收或被 drop 之前有效
+    pub fn recv_loan(&mut self) -> Result<RecvLoan<'_>> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
+        }
+        self.read_state.at_boundary(self.read_buffer.len())?;
+
+        self.enforce_policy()?;
+        let loan = self.transport_handler.recv_loan().map_err(Error::from)?;
+        if let Some(policy) = &mut self.policy {
+            policy.throttle(loan.len());
+        }
+        if let Some(account) = &self.account {
+            account.record(Direction::Received, loan.len());
+        }
+        Ok(loan)
+    }
+
+    /// 接收一条消息并按顺序填入多个缓冲区（如消息头、消息体各一块），
+    /// 返回消息长度；消息长于缓冲总长时多余部分被丢弃，返回值大于缓冲总长
+    pub fn recv_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        if !self.connected {
+            return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
+        }
+        self.read_state.at_boundary(self.read_buffer.len())?;
+
+        self.enforce_policy()?;
+        let len = self
+            .transport_handler
+            .recv_vectored(bufs)
+            .map_err(Error::from)?;
+        self.account(Direction::Received, len);
+        Ok(len)
+    }
+
+    /// 以 `codec` 编码 `value` 后作为一条消息发送，返回编码后的字节数
+    pub fn send_encoded<T, C: Codec<T>>(&mut self, codec: &C, value: &T) -> Result<usize> {
+        let data = codec.encode(value)?;
+        self.send(data)
+    }
+
+    /// 接收一条消息并以 `codec` 解码，格式不符时返回 `InvalidData`
+    pub fn recv_decoded<T, C: Codec<T>>(&mut self, codec: &C) -> Result<T> {
+        let loan = self.recv_loan()?;
+        codec.decode(&loan)
+    }
+
+    /// 以一行 JSON 发送 `value`（JSON 行模式）
+    #[cfg(feature = "codec-json")]
+    pub fn send_json(&mut self, value: &serde_json::Value) -> Result<usize> {
+        self.send(crate::codec::json::encode_line(value)?)
+    }
+
+    /// 接收一条消息并解析为 JSON 值，格式不符时返回 `InvalidData`
+    #[cfg(feature = "codec-json")]
+    pub fn recv_json(&mut self) -> Result<serde_json::Value> {
+        let loan = self.recv_loan()?;
+        crate::codec::json::decode_line(&loan)
+    }
+
+    /// 把连接交给后台线程持续接收，返回解析后的 JSON 值流
+    #[cfg(feature = "codec-json")]
+    pub fn into_json_stream(mut self) -> crate::codec::JsonStream {
+        let conn = self.transport_handler.conn();
+        crate::codec::JsonStream::spawn(conn, move || self.recv())
+    }
+
+    /// 把连接交给后台线程持续接收，超过内存上限的消息暂存到临时文件，见 [`crate::spool`]
+    pub fn into_spooled(
+        mut self,
+        config: crate::spool::SpoolConfig,
+    ) -> Result<crate::spool::Spool> {
+        let conn = self.transport_handler.conn();
+        crate::spool::Spool::spawn(conn, config, move || self.recv())
+    }
+
+    /// 把连接交给后台线程，在其上复用多个逻辑通道，见 [`crate::mux`]
+    pub fn into_mux(self) -> Result<crate::mux::Mux> {
+        let conn = self.transport_handler.conn();
+        crate::mux::Mux::spawn(conn, self)
+    }
+
+    /// 把连接变为类型化的请求/回复会话，见 [`crate::codec::Protocol`]
+    pub fn into_protocol<Req, Resp, C>(
+        self,
+        codec: C,
+        role: crate::codec::Role,
+    ) -> crate::codec::Protocol<Req, Resp, C>
+    where
+        C: crate::codec::Codec<Req> + crate::codec::Codec<Resp>,
+    {
+        crate::codec::Protocol::new(self, codec, role)
+    }
+
+    /// 等待下一条消息到达、被唤醒或超过 `timeout`，有消息可接收时返回 `true`
+    pub(crate) fn wait_readable(
+        &mut self,
+        wake: Option<&crate::mux::Wake>,
+        timeout: Option<Duration>,
+    ) -> Result<bool> {
         if !self.connected {
             return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
         }
         self.transport_handler
-            .recv()
-            .map_err(|e| Error::other(format!("send error: {}", e)))
+            .wait_readable(wake, timeout)
+            .map_err(Error::from)
     }
 
     /// 断开连接
     pub fn disconnect(&mut self) -> Result<()> {
-        info!("VirgeServer disconnecting");
+        let stats = self.transport_handler.stats();
+        let conn = self.transport_handler.conn();
+        log_event!(
+            Level::Info,
+            "server disconnecting",
+            conn_id = conn.conn_id,
+            cid = conn.cid,
+            bytes_sent = stats.bytes_sent,
+            bytes_received = stats.bytes_received,
+        );
         if !self.read_buffer.is_empty() {
             warn!(
                 "Disconnecting with {} bytes of unread data in buffer",
                 self.read_buffer.len()
             );
-            return Err(Error::new(
-                ErrorKind::Other,
-                format!(
-                    "Cannot disconnect: {} bytes of unread data remaining",
-                    self.read_buffer.len()
-                ),
-            ));
+            return Err(Error::other(format!(
+                "Cannot disconnect: {} bytes of unread data remaining",
+                self.read_buffer.len()
+            )));
         }
 
-        self.transport_handler.disconnect()?;
+        self.transport_handler.close(self.ending)?;
         self.connected = false;
+        events::emit(VirgaEvent::Disconnected {
+            role: Role::Server,
+            conn,
+        });
+        Ok(())
+    }
+
+    /// 在消息流之外发送一个旁路帧，见 [`crate::transport::register_frame_handler()`]：
+    /// 客户端在接收消息时把它交给注册的处理函数，不进入 `recv()`。旁路帧不加密，不计入统计
+    pub fn send_extension(&mut self, frame_type: u8, data: &[u8]) -> Result<()> {
+        if !self.is_connected() {
+            return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
+        }
+        Ok(self.transport_handler.send_extension(frame_type, data)?)
+    }
+
+    /// 与客户端交换 `rounds` 轮时间戳，估计对端时钟相对本端的偏差，见 [`crate::clock`]；
+    /// 每轮超过 `timeout` 未回复返回超时错误
+    pub fn time_sync(&mut self, rounds: usize, timeout: Duration) -> Result<ClockOffset> {
+        if !self.is_connected() {
+            return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
+        }
+        crate::clock::sync(rounds, || {
+            self.transport_handler
+                .time_probe(timeout)
+                .map_err(Error::from)
+        })
+    }
+
+    /// 配合客户端的 `self_test()`：回复自检消息，直到客户端结束自检，见 [`crate::selftest`]。
+    /// 其间收到其他消息时返回 `InvalidData`
+    pub fn serve_self_test(&mut self) -> Result<()> {
+        if !self.is_connected() {
+            return Err(Error::new(ErrorKind::NotConnected, "Server not connected"));
+        }
+        self.read_state.at_boundary(self.read_buffer.len())?;
+        let handled = selftest::serve(&mut self.transport_handler)?;
+        debug!("Self-test finished after {} messages", handled);
         Ok(())
     }
 
@@ -74,12 +604,19 @@ impl VirgeServer {
     pub fn is_connected(&self) -> bool {
         self.connected && self.transport_handler.is_connected()
     }
+
+    /// 获取连接统计信息
+    pub fn stats(&self) -> ConnectionStats {
+        self.transport_handler.stats()
+    }
 }
 
 impl VirgeServer {
     fn read_new_message(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.enforce_policy()?;
         match self.transport_handler.recv() {
             Ok(data) => {
+                self.account(Direction::Received, data.len());
                 if data.len() <= buf.len() {
                     buf[..data.len()].copy_from_slice(&data);
                     Ok(data.len())
@@ -87,6 +624,7 @@ impl VirgeServer {
                     let len = buf.len();
                     buf.copy_from_slice(&data[..len]);
                     self.read_buffer.extend_from_slice(&data[len..]);
+                    self.transport_handler.set_pending(self.read_buffer.len());
 
                     self.read_state = ReadState::Reading {
                         total: data.len(),
@@ -95,7 +633,7 @@ impl VirgeServer {
                     Ok(len)
                 }
             }
-            Err(e) => return Err(Error::new(ErrorKind::Other, format!("Read error: {}", e))),
+            Err(e) => Err(e.into()),
         }
     }
 
@@ -114,26 +652,17 @@ impl Read for VirgeServer {
         match self.read_state {
             ReadState::Idle => {
                 // 直接从传输层读取
-                return self.read_new_message(buf);
+                self.read_new_message(buf)
             }
-            ReadState::Reading { total, read, .. } => {
+            ReadState::Reading { .. } => {
                 // 从rbuf中读取剩余数据
                 if !self.read_buffer.is_empty() {
                     let len = std::cmp::min(self.read_buffer.len(), buf.len());
                     buf[..len].copy_from_slice(&self.read_buffer[..len]);
                     self.read_buffer.drain(..len);
+                    self.transport_handler.set_pending(self.read_buffer.len());
 
-                    let new_read = read + len;
-                    if new_read == total {
-                        // 消息读取完成
-                        self.read_state = ReadState::Idle;
-                    } else {
-                        // 更新状态
-                        self.read_state = ReadState::Reading {
-                            total,
-                            read: new_read,
-                        };
-                    }
+                    self.read_state.advance(len);
                     Ok(len)
                 } else {
                     // rbuf为空但状态是Reading，这不应该发生
@@ -151,14 +680,19 @@ impl Write for VirgeServer {
             return Err(Error::new(ErrorKind::NotConnected, "Client not connected"));
         }
 
+        self.enforce_policy()?;
         match self.transport_handler.send(buf) {
-            Ok(len) => Ok(len),
-            Err(e) => Err(Error::new(ErrorKind::Other, format!("Write error: {}", e))),
+            Ok(len) => {
+                self.account(Direction::Sent, len);
+                Ok(len)
+            }
+            Err(e) => Err(e.into()),
         }
     }
 
+    /// 发出开启合批时尚未发送的批次
     fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+        self.transport_handler.flush().map_err(Into::into)
     }
 }
 
@@ -173,6 +707,59 @@ mod tests {
         VirgeServer::new(handler, false)
     }
 
+    #[test]
+    fn policy_rejects_disallowed_peer() {
+        use crate::server::policy::{PolicyWatch, SharedPolicy};
+        use crate::server::ServerPolicy;
+
+        let shared = SharedPolicy::new(ServerPolicy {
+            allowed_cids: Some(vec![3]),
+            ..ServerPolicy::default()
+        });
+        let mut server =
+            VirgeServer::new(XTransportHandler::new(), true).with_policy(PolicyWatch::new(shared));
+        let err = server.send(vec![1, 2, 3]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        let err = server.recv().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn connection_past_max_age_and_grace_is_closed() {
+        let mut server = VirgeServer::new(XTransportHandler::new(), true)
+            .with_max_age(Some((Duration::ZERO, Duration::ZERO)));
+        let err = server.send(vec![1, 2, 3]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionAborted);
+        assert!(!server.is_connected());
+    }
+
+    #[test]
+    fn drain_requires_a_connection() {
+        let err = make_disconnected_server().drain().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn authorize_uses_peer_identity() {
+        use crate::auth::{AccessRules, Principal};
+
+        let rules = AccessRules::allow_by_default().allow("admin", "shutdown", Principal::Cid(7));
+        let server = VirgeServer::new(XTransportHandler::new(), true)
+            .with_authorizer(Some(Arc::new(rules)))
+            .with_peer(Some(PeerIdentity {
+                cid: 8,
+                name: "agent".to_string(),
+            }));
+        assert!(server.authorize("admin", "status").is_ok());
+        let err = server.authorize("admin", "shutdown").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        // 未配置授权规则时总是允许
+        assert!(make_disconnected_server()
+            .authorize("admin", "shutdown")
+            .is_ok());
+    }
+
     #[test]
     fn new_server_not_connected() {
         let server = make_disconnected_server();
@@ -202,6 +789,34 @@ mod tests {
         assert_eq!(err.kind(), ErrorKind::NotConnected);
     }
 
+    #[test]
+    fn recv_loan_when_not_connected_fails() {
+        let mut server = make_disconnected_server();
+        let err = server.recv_loan().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn recv_vectored_when_not_connected_fails() {
+        let mut server = make_disconnected_server();
+        let mut buf = [0u8; 4];
+        let err = server
+            .recv_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn typed_io_when_not_connected_fails() {
+        use crate::codec::RawCodec;
+
+        let mut server = make_disconnected_server();
+        let err = server.send_encoded(&RawCodec, &vec![1, 2, 3]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        let err = server.recv_decoded(&RawCodec).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
     #[test]
     fn recv_when_not_connected_fails() {
         let mut server = make_disconnected_server();
@@ -230,6 +845,12 @@ mod tests {
         assert_eq!(err.kind(), ErrorKind::NotConnected);
     }
 
+    #[test]
+    fn stats_initially_empty() {
+        let server = make_disconnected_server();
+        assert_eq!(server.stats(), ConnectionStats::default());
+    }
+
     #[test]
     fn flush_always_ok() {
         let mut server = make_disconnected_server();
@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 多租户
+//!
+//! 一个服务进程同时服务多个互相隔离的工作负载时，用 [`TenantMap`] 按认证名称或 CID
+//! 给每个连接标上租户。`accept()` 在握手后按规则确定租户，没有规则匹配且未设置默认
+//! 租户的连接被拒绝；通过的连接可由 `VirgeServer::tenant()` 取得租户，按租户划分的
+//! 共享状态（如发现服务的登记表与订阅）据此隔离。
+
+use crate::auth::{PeerIdentity, Principal};
+use crate::error::VirgeError;
+
+/// 连接到租户的映射，按添加顺序取第一条匹配的规则
+///
+/// ```ignore
+/// let tenants = TenantMap::new()
+///     .assign(Principal::Name("billing".to_string()), "billing")
+///     .assign(Principal::Cid(7), "search");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TenantMap {
+    rules: Vec<(Principal, String)>,
+    fallback: Option<String>,
+}
+
+impl TenantMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 把 `principal` 的连接归入 `tenant`
+    pub fn assign(mut self, principal: Principal, tenant: impl Into<String>) -> Self {
+        self.rules.push((principal, tenant.into()));
+        self
+    }
+
+    /// 没有规则匹配的连接归入 `tenant`，未设置时这些连接被拒绝
+    pub fn with_default(mut self, tenant: impl Into<String>) -> Self {
+        self.fallback = Some(tenant.into());
+        self
+    }
+
+    /// `peer` 所属的租户，未启用认证时名称为空，只有 CID 规则能匹配
+    pub fn resolve(&self, peer: &PeerIdentity) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(principal, _)| principal.matches(peer))
+            .map(|(_, tenant)| tenant.as_str())
+            .or(self.fallback.as_deref())
+    }
+
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        let names = self.rules.iter().map(|(_, t)| t).chain(&self.fallback);
+        for tenant in names {
+            if tenant.is_empty() || tenant.len() > crate::auth::MAX_NAME_LEN {
+                return Err(VirgeError::ConfigError(format!(
+                    "invalid tenant name {:?}",
+                    tenant
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(cid: u32, name: &str) -> PeerIdentity {
+        PeerIdentity {
+            cid,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let tenants = TenantMap::new()
+            .assign(Principal::Name("billing".to_string()), "billing")
+            .assign(Principal::Cid(7), "search");
+        assert_eq!(tenants.resolve(&peer(7, "billing")), Some("billing"));
+        assert_eq!(tenants.resolve(&peer(7, "")), Some("search"));
+        assert_eq!(tenants.resolve(&peer(8, "other")), None);
+
+        let tenants = tenants.with_default("shared");
+        assert_eq!(tenants.resolve(&peer(8, "other")), Some("shared"));
+    }
+
+    #[test]
+    fn validate_rejects_empty_tenant_names() {
+        assert!(TenantMap::new().validate().is_ok());
+        assert!(TenantMap::new()
+            .assign(Principal::Any, "")
+            .validate()
+            .is_err());
+        assert!(TenantMap::new()
+            .with_default("x".repeat(256))
+            .validate()
+            .is_err());
+    }
+}
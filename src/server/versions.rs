@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 按分帧版本统计连接
+//!
+//! 升级过渡期内服务端同时接受 v1 与 v2 分帧的客户端（XTransport 连接建立后由客户端
+//! 提议、服务端应答选定，见 `ServerConfig::with_max_protocol_version()`）。
+//! `ServerManager::protocol_versions()` 给出当前各版本的连接数与已关闭连接的累计数，
+//! 据此判断何时所有客户端都已升级。连接在收发时更新所用的版本；yamux 连接固定计为 1。
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// 各分帧版本的连接数
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProtocolVersions {
+    /// 当前打开的连接按所用版本计数
+    pub open: BTreeMap<u8, usize>,
+    /// 自首次 `start()` 以来关闭的连接按关闭时所用版本累计
+    pub closed: BTreeMap<u8, u64>,
+}
+
+/// 管理器与连接共享的计数
+#[derive(Debug, Default)]
+pub(crate) struct VersionTally {
+    counts: Mutex<ProtocolVersions>,
+}
+
+impl VersionTally {
+    /// 为新接受的连接计数
+    pub(crate) fn open(self: &Arc<Self>, version: u8) -> VersionSlot {
+        *self.lock().open.entry(version).or_default() += 1;
+        VersionSlot {
+            tally: self.clone(),
+            version,
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> ProtocolVersions {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ProtocolVersions> {
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// 单个连接的计数，drop 时计入已关闭
+#[derive(Debug)]
+pub(crate) struct VersionSlot {
+    tally: Arc<VersionTally>,
+    version: u8,
+}
+
+impl VersionSlot {
+    /// 连接改用 `version` 时移到该版本下，返回是否有变化
+    pub(crate) fn observe(&mut self, version: u8) -> bool {
+        if version == self.version {
+            return false;
+        }
+        let mut counts = self.tally.lock();
+        release(&mut counts.open, self.version);
+        *counts.open.entry(version).or_default() += 1;
+        self.version = version;
+        true
+    }
+}
+
+impl Drop for VersionSlot {
+    fn drop(&mut self) {
+        let mut counts = self.tally.lock();
+        release(&mut counts.open, self.version);
+        *counts.closed.entry(self.version).or_default() += 1;
+    }
+}
+
+fn release(open: &mut BTreeMap<u8, usize>, version: u8) {
+    if let Some(n) = open.get_mut(&version) {
+        *n -= 1;
+        if *n == 0 {
+            open.remove(&version);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_move_between_versions_and_close() {
+        let tally = Arc::new(VersionTally::default());
+        let mut upgraded = tally.open(1);
+        let legacy = tally.open(1);
+        assert_eq!(tally.snapshot().open, BTreeMap::from([(1, 2)]));
+
+        assert!(upgraded.observe(2));
+        assert!(!upgraded.observe(2));
+        assert_eq!(tally.snapshot().open, BTreeMap::from([(1, 1), (2, 1)]));
+
+        drop(legacy);
+        drop(upgraded);
+        let snapshot = tally.snapshot();
+        assert!(snapshot.open.is_empty());
+        assert_eq!(snapshot.closed, BTreeMap::from([(1, 1), (2, 1)]));
+    }
+}
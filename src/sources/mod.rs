@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 可替换的时钟与随机源
+//!
+//! 以下逻辑经 [`Clock`] 取时刻、等待：重试退避、熔断冷却、连接最大存活时长、存活登记、
+//! 幂等结果的保留期、服务端限速与 `mux::Bandwidth` 的发送额度、
+//! 客户机代理的心跳与重连期限、加密通道的换钥间隔；连接年龄抖动与操作 ID 前缀经
+//! [`Entropy`] 取随机数。默认使用系统时钟与操作系统随机源；
+//! 测试中换成 [`ManualClock`] 与 [`SeededEntropy`] 后，时间只在显式推进时流逝，随机数
+//! 由种子决定，依赖时间的行为可以稳定复现，不必真的等待。
+//!
+//! ```ignore
+//! let clock = Arc::new(ManualClock::new());
+//! let breaker = CircuitBreaker::new().with_clock(clock.clone());
+//! // ... 连续失败使熔断器打开
+//! clock.advance(Duration::from_secs(30));
+//! assert_eq!(breaker.state(), BreakerState::HalfOpen);
+//! ```
+//!
+//! 组件在创建时取得时钟，支持的组件提供 `with_clock()`；其余的使用 [`set_clock()`] /
+//! [`set_entropy()`] 设置的进程级默认值，在同一进程中并行运行的测试会相互影响，宜放在
+//! 单独的测试二进制中。
+//!
+//! 其余计时直接使用系统时钟，不受这里的时钟影响：socket 读写超时与等待数据的轮询由内核
+//! 计时，延迟直方图、发送积压等统计按真实耗时记录，从快照恢复的检测需要与内核的
+//! `CLOCK_BOOTTIME` 比较。认证 nonce 与会话密钥始终取自操作系统随机源。
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 时间来源
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// 单调时刻，用于计算时长与期限
+    fn now(&self) -> Instant;
+
+    /// 墙上时间，用于时间戳
+    fn system_time(&self) -> SystemTime;
+
+    /// 等待 `duration`
+    fn sleep(&self, duration: Duration);
+}
+
+/// 非密码学用途的随机源
+pub trait Entropy: Send + Sync + fmt::Debug {
+    /// 以随机字节填满 `buf`
+    fn fill(&self, buf: &mut [u8]);
+
+    fn next_u64(&self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// 系统时钟
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// 操作系统随机源，不可用时退回标准库以随机种子初始化的哈希
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsEntropy;
+
+impl Entropy for OsEntropy {
+    fn fill(&self, buf: &mut [u8]) {
+        if os_random(buf).is_ok() {
+            return;
+        }
+        for chunk in buf.chunks_mut(8) {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_usize(chunk.as_ptr() as usize);
+            chunk.copy_from_slice(&hasher.finish().to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+/// 以内核的 `getrandom(2)` 填满 `buf`，用于认证 nonce 等不能由 [`Entropy`] 替换的场合。
+/// 内核的随机源尚未初始化时阻塞等待
+pub(crate) fn os_random(buf: &mut [u8]) -> std::io::Result<()> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let rest = &mut buf[filled..];
+        // SAFETY: 指针与长度来自同一个可写切片
+        let ret = unsafe { libc::getrandom(rest.as_mut_ptr().cast(), rest.len(), 0) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        filled += ret as usize;
+    }
+    Ok(())
+}
+
+/// 手动推进的时钟：创建后停在原地，只在 [`advance()`](Self::advance) 或 `sleep()` 时
+/// 前进，`sleep()` 推进时钟后立即返回。墙上时间从 [`ManualClock::START`] 开始
+#[derive(Debug)]
+pub struct ManualClock {
+    origin: Instant,
+    start: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// 默认的起始墙上时间：2025-01-01 00:00:00 UTC
+    pub const START: Duration = Duration::from_secs(1_735_689_600);
+
+    pub fn new() -> Self {
+        Self::starting_at(UNIX_EPOCH + Self::START)
+    }
+
+    /// 墙上时间从 `start` 开始
+    pub fn starting_at(start: SystemTime) -> Self {
+        Self {
+            origin: Instant::now(),
+            start,
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// 让时间前进 `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.lock() += duration;
+    }
+
+    /// 创建以来推进的总时长
+    pub fn elapsed(&self) -> Duration {
+        *self.lock()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Duration> {
+        self.elapsed.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.origin + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// 由种子决定的随机序列（SplitMix64），同一种子总是给出同样的字节
+#[derive(Debug)]
+pub struct SeededEntropy {
+    state: AtomicU64,
+}
+
+impl SeededEntropy {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+        }
+    }
+}
+
+impl Entropy for SeededEntropy {
+    fn fill(&self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn next_u64(&self) -> u64 {
+        const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+static CLOCK: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+static ENTROPY: RwLock<Option<Arc<dyn Entropy>>> = RwLock::new(None);
+
+/// 设置进程级默认时钟，`None` 恢复系统时钟；只影响之后创建的组件
+pub fn set_clock(clock: Option<Arc<dyn Clock>>) {
+    *CLOCK.write().unwrap_or_else(PoisonError::into_inner) = clock;
+}
+
+/// 设置进程级默认随机源，`None` 恢复操作系统随机源
+pub fn set_entropy(entropy: Option<Arc<dyn Entropy>>) {
+    *ENTROPY.write().unwrap_or_else(PoisonError::into_inner) = entropy;
+}
+
+/// 当前的进程级默认时钟
+pub fn clock() -> Arc<dyn Clock> {
+    CLOCK
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .unwrap_or_else(|| Arc::new(SystemClock))
+}
+
+/// 当前的进程级默认随机源
+pub fn entropy() -> Arc<dyn Entropy> {
+    ENTROPY
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .unwrap_or_else(|| Arc::new(OsEntropy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_moves_only_when_advanced() {
+        let clock = ManualClock::new();
+        let (now, wall) = (clock.now(), clock.system_time());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.now(), now);
+
+        clock.advance(Duration::from_secs(3));
+        clock.sleep(Duration::from_secs(2));
+        assert_eq!(clock.now() - now, Duration::from_secs(5));
+        assert_eq!(
+            clock.system_time().duration_since(wall).unwrap(),
+            Duration::from_secs(5)
+        );
+        assert_eq!(wall.duration_since(UNIX_EPOCH).unwrap(), ManualClock::START);
+    }
+
+    #[test]
+    fn seeded_entropy_is_reproducible() {
+        let draw = |seed| {
+            let entropy = SeededEntropy::new(seed);
+            let mut bytes = [0u8; 12];
+            entropy.fill(&mut bytes);
+            (entropy.next_u64(), bytes)
+        };
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+        // SplitMix64 参考输出
+        assert_eq!(SeededEntropy::new(0).next_u64(), 0xE220_A839_7B1D_CDAF);
+    }
+
+    #[test]
+    fn os_random_fills_the_whole_buffer() {
+        let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+        os_random(&mut a).unwrap();
+        os_random(&mut b).unwrap();
+        assert_ne!(a, b);
+        os_random(&mut []).unwrap();
+    }
+}
@@ -0,0 +1,462 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 接收溢写
+//!
+//! 日志转发等场景的流量是突发的：对端短时间内发来大量消息，消费者一时处理不完，
+//! 而接收一旦暂停，对端的发送也会被阻塞甚至超时。`into_spooled()` 把连接交给后台
+//! 线程持续接收，消息先放在内存中，超过 [`SpoolConfig`] 的内存上限后依次追加到临时
+//! 文件，消费者按到达顺序先取内存中的、再取文件中的消息。
+//!
+//! ```ignore
+//! let spool = server.into_spooled(SpoolConfig::new(ByteSize::mib(8)).with_dir("/var/tmp"))?;
+//! while let Ok(line) = spool.recv() {
+//!     forward(&line)?;
+//! }
+//! ```
+//!
+//! 开始溢写后，新消息一律写入文件，直到文件中的消息全部取完、文件清空，之后才重新
+//! 放入内存，因此顺序不会被打乱。文件每条消息前加上长度：
+//!
+//! ```text
+//! len(4, 大端) | payload
+//! ```
+//!
+//! 临时文件创建后立即删除目录项，进程退出后不会留下。设置了磁盘上限时，文件写满后
+//! 暂停接收，直到消费者取走消息，与不溢写时一样对对端形成背压而不丢弃消息。
+//! 连接断开后，消费者取完已收到的消息，之后的 `recv()` 返回连接断开的原因；写入
+//! 文件失败时同样结束接收并在取完后返回写入错误。
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::error::ConnContext;
+use crate::events::{self, VirgaEvent};
+use crate::threads;
+use crate::units::ByteSize;
+
+const LEN_PREFIX: u64 = 4;
+
+/// 溢写的设置
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpoolConfig {
+    memory: ByteSize,
+    disk: Option<ByteSize>,
+    dir: Option<PathBuf>,
+}
+
+impl SpoolConfig {
+    /// 内存中最多保留 `memory` 字节的消息，超出部分写入临时文件
+    pub fn new(memory: ByteSize) -> Self {
+        Self {
+            memory,
+            disk: None,
+            dir: None,
+        }
+    }
+
+    /// 临时文件最多 `disk` 字节，写满后暂停接收；默认不限
+    pub fn with_disk_limit(mut self, disk: ByteSize) -> Self {
+        self.disk = Some(disk);
+        self
+    }
+
+    /// 临时文件所在的目录，默认为 `std::env::temp_dir()`
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    pub(crate) fn memory(&self) -> ByteSize {
+        self.memory
+    }
+
+    pub(crate) fn disk(&self) -> Option<ByteSize> {
+        self.disk
+    }
+
+    pub(crate) fn dir(&self) -> Option<&PathBuf> {
+        self.dir.as_ref()
+    }
+}
+
+/// 在 `dir`（默认为 `std::env::temp_dir()`）中创建并立即删除目录项的临时文件，
+/// 文件名含 `label` 便于排查
+pub(crate) fn temp_file(dir: Option<&PathBuf>, label: &str) -> Result<File> {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let dir = dir.cloned().unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!(
+        "virga-{}-{}-{}",
+        label,
+        std::process::id(),
+        SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    // 只通过打开的文件访问，删除目录项后进程退出时自动回收
+    fs::remove_file(&path)?;
+    debug!("Temporary file {} created", path.display());
+    Ok(file)
+}
+
+/// 溢写队列当前的占用
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SpoolStats {
+    /// 内存中的消息数
+    pub memory_messages: usize,
+    /// 内存中的消息字节数
+    pub memory_bytes: u64,
+    /// 文件中的消息数
+    pub spooled_messages: usize,
+    /// 文件中的消息字节数（含长度前缀）
+    pub spooled_bytes: u64,
+}
+
+/// 已追加到临时文件、尚未取走的消息
+#[derive(Debug)]
+struct SpoolFile {
+    file: File,
+    read_at: u64,
+    write_at: u64,
+    messages: usize,
+}
+
+impl SpoolFile {
+    fn create(dir: Option<&PathBuf>, conn: &ConnContext) -> Result<Self> {
+        let file = temp_file(dir, &format!("spool-{}", conn.conn_id))?;
+        Ok(Self {
+            file,
+            read_at: 0,
+            write_at: 0,
+            messages: 0,
+        })
+    }
+
+    fn bytes(&self) -> u64 {
+        self.write_at - self.read_at
+    }
+
+    fn append(&mut self, msg: &[u8]) -> Result<()> {
+        let len = u32::try_from(msg.len()).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("message of {} bytes is too large to spool", msg.len()),
+            )
+        })?;
+        self.file.write_all_at(&len.to_be_bytes(), self.write_at)?;
+        self.file.write_all_at(msg, self.write_at + LEN_PREFIX)?;
+        self.write_at += LEN_PREFIX + msg.len() as u64;
+        self.messages += 1;
+        Ok(())
+    }
+
+    /// 取出最早的消息，全部取完后清空文件
+    fn take(&mut self) -> Result<Vec<u8>> {
+        let mut len = [0u8; LEN_PREFIX as usize];
+        self.file.read_exact_at(&mut len, self.read_at)?;
+        let mut msg = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.file
+            .read_exact_at(&mut msg, self.read_at + LEN_PREFIX)?;
+        self.read_at += LEN_PREFIX + msg.len() as u64;
+        self.messages -= 1;
+        if self.messages == 0 {
+            self.file.set_len(0)?;
+            self.read_at = 0;
+            self.write_at = 0;
+        }
+        Ok(msg)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    memory: VecDeque<Vec<u8>>,
+    memory_bytes: u64,
+    file: Option<SpoolFile>,
+    /// 接收结束的原因
+    closed: Option<(ErrorKind, String)>,
+    /// 消费者已丢弃
+    dropped: bool,
+}
+
+impl Queue {
+    fn spooling(&self) -> bool {
+        self.file.as_ref().is_some_and(|f| f.messages > 0)
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    conn: ConnContext,
+    config: SpoolConfig,
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, queue: MutexGuard<'a, Queue>) -> MutexGuard<'a, Queue> {
+        self.changed
+            .wait(queue)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 放入一条收到的消息，消费者已丢弃时返回 `false`
+    fn push(&self, msg: Vec<u8>) -> Result<bool> {
+        let len = msg.len() as u64;
+        let mut queue = self.lock();
+        loop {
+            if queue.dropped {
+                return Ok(false);
+            }
+            if !queue.spooling() && queue.memory_bytes + len <= self.config.memory.as_u64() {
+                queue.memory_bytes += len;
+                queue.memory.push_back(msg);
+                self.changed.notify_all();
+                return Ok(true);
+            }
+            // 文件为空时总是允许写入一条，避免单条消息超过磁盘上限时永远等待
+            let full = self.config.disk.is_some_and(|disk| {
+                queue.spooling()
+                    && queue.file.as_ref().map_or(0, SpoolFile::bytes) + LEN_PREFIX + len
+                        > disk.as_u64()
+            });
+            if !full {
+                break;
+            }
+            queue = self.wait(queue);
+        }
+
+        if !queue.spooling() {
+            events::emit(VirgaEvent::SlowConsumer {
+                conn: self.conn,
+                queued: queue.memory.len(),
+            });
+            info!(
+                "Spooling messages on {} to disk: {} bytes in memory",
+                self.conn, queue.memory_bytes
+            );
+        }
+        if queue.file.is_none() {
+            queue.file = Some(SpoolFile::create(self.config.dir.as_ref(), &self.conn)?);
+        }
+        queue.file.as_mut().expect("spool file").append(&msg)?;
+        self.changed.notify_all();
+        Ok(true)
+    }
+
+    fn close(&self, e: &Error) {
+        let mut queue = self.lock();
+        queue.closed = Some((e.kind(), e.to_string()));
+        self.changed.notify_all();
+    }
+}
+
+/// 带溢写的接收队列，见[模块文档](self)
+///
+/// 由 `into_spooled()` 创建。丢弃后，后台线程在下一条消息到达或连接关闭时退出，
+/// 临时文件随之回收。
+#[derive(Debug)]
+pub struct Spool {
+    shared: Arc<Shared>,
+}
+
+impl Spool {
+    pub(crate) fn spawn<F>(conn: ConnContext, config: SpoolConfig, mut recv: F) -> Result<Self>
+    where
+        F: FnMut() -> Result<Vec<u8>> + Send + 'static,
+    {
+        if config.memory.as_u64() == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "spool memory limit must be greater than zero",
+            ));
+        }
+        let shared = Arc::new(Shared {
+            conn,
+            config,
+            queue: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        let receiver = shared.clone();
+        threads::spawn(format!("spool-{}", conn.conn_id), move || loop {
+            let pushed = recv().and_then(|msg| receiver.push(msg));
+            match pushed {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    if !crate::rpc::is_closed(&e) {
+                        warn!("Spool on {} stopped receiving: {}", conn, e);
+                    }
+                    receiver.close(&e);
+                    break;
+                }
+            }
+        })?;
+        Ok(Self { shared })
+    }
+
+    /// 阻塞等待下一条消息；接收已结束且消息取完时返回结束的原因
+    pub fn recv(&self) -> Result<Vec<u8>> {
+        self.next(None)
+    }
+
+    /// 同 [`recv()`](Self::recv)，超过 `timeout` 仍没有消息时返回 `TimedOut`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Vec<u8>> {
+        self.next(Some(Instant::now() + timeout))
+    }
+
+    /// 当前内存与文件中的消息
+    pub fn stats(&self) -> SpoolStats {
+        let queue = self.shared.lock();
+        SpoolStats {
+            memory_messages: queue.memory.len(),
+            memory_bytes: queue.memory_bytes,
+            spooled_messages: queue.file.as_ref().map_or(0, |f| f.messages),
+            spooled_bytes: queue.file.as_ref().map_or(0, SpoolFile::bytes),
+        }
+    }
+
+    fn next(&self, deadline: Option<Instant>) -> Result<Vec<u8>> {
+        let mut queue = self.shared.lock();
+        loop {
+            if let Some(msg) = queue.memory.pop_front() {
+                queue.memory_bytes -= msg.len() as u64;
+                self.shared.changed.notify_all();
+                return Ok(msg);
+            }
+            if queue.spooling() {
+                let msg = queue.file.as_mut().expect("spool file").take()?;
+                self.shared.changed.notify_all();
+                return Ok(msg);
+            }
+            if let Some((kind, reason)) = &queue.closed {
+                return Err(Error::new(*kind, reason.clone()));
+            }
+            queue = match deadline {
+                None => self.shared.wait(queue),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(Error::new(ErrorKind::TimedOut, "spool recv timed out"));
+                    }
+                    self.shared
+                        .changed
+                        .wait_timeout(queue, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        self.shared.lock().dropped = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{channel, Receiver};
+
+    fn feed(rx: Receiver<Vec<u8>>) -> impl FnMut() -> Result<Vec<u8>> + Send + 'static {
+        move || {
+            rx.recv()
+                .map_err(|_| Error::new(ErrorKind::ConnectionReset, "peer closed"))
+        }
+    }
+
+    fn wait_until(spool: &Spool, f: impl Fn(SpoolStats) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !f(spool.stats()) {
+            assert!(
+                Instant::now() < deadline,
+                "spool stuck at {:?}",
+                spool.stats()
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn burst_beyond_memory_is_replayed_in_order() {
+        let (tx, rx) = channel();
+        let config = SpoolConfig::new(ByteSize::b(16));
+        let spool = Spool::spawn(ConnContext::default(), config, feed(rx)).unwrap();
+        for i in 0..100u32 {
+            tx.send(i.to_be_bytes().to_vec()).unwrap();
+        }
+        wait_until(&spool, |s| s.memory_messages + s.spooled_messages == 100);
+        let stats = spool.stats();
+        assert_eq!((stats.memory_messages, stats.memory_bytes), (4, 16));
+        assert_eq!(stats.spooled_bytes, 96 * 8);
+
+        for i in 0..50u32 {
+            assert_eq!(spool.recv().unwrap(), i.to_be_bytes());
+        }
+        // 文件未取完时新消息继续写入文件
+        tx.send(b"late".to_vec()).unwrap();
+        wait_until(&spool, |s| s.spooled_messages == 51);
+        for i in 50..100u32 {
+            assert_eq!(spool.recv().unwrap(), i.to_be_bytes());
+        }
+        assert_eq!(spool.recv().unwrap(), b"late");
+        assert_eq!(spool.stats(), SpoolStats::default());
+
+        // 文件取完后回到内存
+        tx.send(b"again".to_vec()).unwrap();
+        wait_until(&spool, |s| s.memory_messages == 1);
+        assert_eq!(spool.recv().unwrap(), b"again");
+
+        drop(tx);
+        assert_eq!(spool.recv().unwrap_err().kind(), ErrorKind::ConnectionReset);
+        assert_eq!(spool.recv().unwrap_err().kind(), ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn disk_limit_pauses_receiving() {
+        let (tx, rx) = channel();
+        let config = SpoolConfig::new(ByteSize::b(8)).with_disk_limit(ByteSize::b(24));
+        let spool = Spool::spawn(ConnContext::default(), config, feed(rx)).unwrap();
+        for i in 0..10u64 {
+            tx.send(i.to_be_bytes().to_vec()).unwrap();
+        }
+        wait_until(&spool, |s| s.spooled_messages == 2);
+        std::thread::sleep(Duration::from_millis(20));
+        let stats = spool.stats();
+        assert_eq!((stats.memory_messages, stats.spooled_messages), (1, 2));
+
+        for i in 0..10u64 {
+            assert_eq!(spool.recv().unwrap(), i.to_be_bytes());
+        }
+        let err = spool.recv_timeout(Duration::from_millis(10)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn spawn_rejects_zero_memory_limit() {
+        let (_tx, rx) = channel();
+        let config = SpoolConfig::new(ByteSize::b(0));
+        let err = Spool::spawn(ConnContext::default(), config, feed(rx)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
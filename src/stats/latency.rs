@@ -0,0 +1,205 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 操作延迟直方图
+//!
+//! 每次发送、接收、连接的耗时记入所在连接的直方图，同时汇总到进程级的
+//! 直方图，可用于长期跟踪客户机与宿主机之间往返的 p99。
+//! 直方图以微秒为单位，覆盖 1µs 到 60s，保留两位有效数字（误差不超过 1%），
+//! 超出上限的耗时按上限记录。
+
+use std::fmt;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+/// 可记录的最大耗时（微秒）
+const MAX_MICROS: u64 = 60_000_000;
+
+/// 有效数字位数
+const SIGFIG: u8 = 2;
+
+/// 一类操作的延迟分布
+#[derive(Clone)]
+pub struct LatencyHistogram {
+    hist: Histogram<u64>,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            hist: Histogram::new_with_bounds(1, MAX_MICROS, SIGFIG)
+                .expect("latency histogram bounds are valid"),
+        }
+    }
+
+    #[cfg(feature = "sync")]
+    pub(crate) fn record(&mut self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.hist.saturating_record(micros.clamp(1, MAX_MICROS));
+    }
+
+    /// 已记录的次数
+    pub fn count(&self) -> u64 {
+        self.hist.len()
+    }
+
+    /// 百分位耗时，`percentile` 取 0 到 100，如 `99.0`；未记录时为 0
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        Duration::from_micros(self.hist.value_at_percentile(percentile))
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_micros(self.hist.min())
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.hist.max())
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_secs_f64(self.hist.mean() / 1_000_000.0)
+    }
+
+    /// 把 `other` 的记录并入本直方图
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        // 两者边界相同，合并不会失败
+        let _ = self.hist.add(&other.hist);
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PartialEq for LatencyHistogram {
+    fn eq(&self, other: &Self) -> bool {
+        self.hist == other.hist
+    }
+}
+
+impl Eq for LatencyHistogram {}
+
+impl fmt::Debug for LatencyHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LatencyHistogram")
+            .field("count", &self.count())
+            .field("p50", &self.percentile(50.0))
+            .field("p99", &self.percentile(99.0))
+            .field("max", &self.max())
+            .finish()
+    }
+}
+
+/// 各类操作的延迟分布
+///
+/// `connect` 只在客户端记录，为建立传输连接（不含认证握手）的耗时；
+/// `recv` 从调用接收开始计时，包含等待对端发送的时间。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub send: LatencyHistogram,
+    pub recv: LatencyHistogram,
+    pub connect: LatencyHistogram,
+}
+
+impl LatencyStats {
+    /// 把 `other` 的各项记录并入
+    pub fn merge(&mut self, other: &LatencyStats) {
+        self.send.merge(&other.send);
+        self.recv.merge(&other.recv);
+        self.connect.merge(&other.connect);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg(feature = "sync")]
+pub(crate) enum Op {
+    Send,
+    Recv,
+    Connect,
+}
+
+impl LatencyStats {
+    /// 记入本连接，并汇总到进程级直方图
+    #[cfg(feature = "sync")]
+    pub(crate) fn record(&mut self, op: Op, elapsed: Duration) {
+        self.histogram_mut(op).record(elapsed);
+        aggregate()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .histogram_mut(op)
+            .record(elapsed);
+    }
+
+    #[cfg(feature = "sync")]
+    fn histogram_mut(&mut self, op: Op) -> &mut LatencyHistogram {
+        match op {
+            Op::Send => &mut self.send,
+            Op::Recv => &mut self.recv,
+            Op::Connect => &mut self.connect,
+        }
+    }
+}
+
+fn aggregate() -> &'static Mutex<LatencyStats> {
+    static AGGREGATE: OnceLock<Mutex<LatencyStats>> = OnceLock::new();
+    AGGREGATE.get_or_init(|| Mutex::new(LatencyStats::default()))
+}
+
+/// 进程内所有连接的延迟分布汇总
+pub fn aggregate_latency() -> LatencyStats {
+    aggregate()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_within_precision() {
+        let mut hist = LatencyHistogram::new();
+        for ms in 1..=100 {
+            hist.record(Duration::from_millis(ms));
+        }
+        assert_eq!(hist.count(), 100);
+        let p99 = hist.percentile(99.0).as_micros() as f64;
+        assert!((p99 - 99_000.0).abs() / 99_000.0 < 0.01, "p99 = {}", p99);
+        assert!(hist.min() <= Duration::from_millis(1));
+        assert!(hist.max() >= Duration::from_millis(99));
+        assert_eq!(LatencyHistogram::new().percentile(99.0), Duration::ZERO);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let mut hist = LatencyHistogram::new();
+        hist.record(Duration::ZERO);
+        hist.record(Duration::from_secs(3600));
+        assert_eq!(hist.count(), 2);
+        assert!(hist.max() <= Duration::from_secs(61));
+    }
+
+    #[test]
+    fn records_reach_connection_and_aggregate() {
+        let before = aggregate_latency().send.count();
+        let mut stats = LatencyStats::default();
+        stats.record(Op::Send, Duration::from_micros(250));
+        stats.record(Op::Connect, Duration::from_millis(3));
+        assert_eq!(stats.send.count(), 1);
+        assert_eq!(stats.recv.count(), 0);
+        assert_eq!(stats.connect.count(), 1);
+        // 其他测试可能并发记录，只能断言增长
+        assert!(aggregate_latency().send.count() > before);
+
+        let mut merged = LatencyStats::default();
+        merged.merge(&stats);
+        merged.merge(&stats);
+        assert_eq!(merged.send.count(), 2);
+    }
+}
@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 统计模块
+//!
+//! 连接级别的收发统计，由传输处理器维护，通过 `VirgeClient::stats()` /
+//! `VirgeServer::stats()` 暴露给调用方。各类操作的延迟分布见 `LatencyStats`，
+//! 进程内所有连接的汇总由 `aggregate_latency()` 获取，两者需要 `latency-stats` 特性。
+
+use std::time::Duration;
+
+#[cfg(feature = "latency-stats")]
+mod latency;
+#[cfg(all(feature = "sync", feature = "latency-stats"))]
+use latency::Op;
+#[cfg(feature = "latency-stats")]
+pub use latency::{aggregate_latency, LatencyHistogram, LatencyStats};
+
+/// 连接统计信息
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// 已发送的消息负载字节数（不含协议头）
+    pub bytes_sent: u64,
+    /// 已接收的消息负载字节数（不含协议头）
+    pub bytes_received: u64,
+    /// 已发送消息数
+    pub messages_sent: u64,
+    /// 已接收消息数
+    pub messages_received: u64,
+    /// 当前分片帧大小（含包头），开启自适应分片时随传输情况变化；
+    /// yamux 由其自身分帧，此项为 0
+    pub chunk_size: usize,
+    /// 加密通道发送方向已完成的换钥次数，未启用加密时为 0
+    pub key_rotations: u64,
+    /// 当前内部缓冲占用的字节数，计入内存预算
+    pub buffered_bytes: usize,
+    /// 合批（`with_coalescing()`）中已交给 `send()`、尚未写到连接上的消息
+    pub send_backlog: SendBacklog,
+    /// 发送、接收、连接的延迟分布
+    #[cfg(feature = "latency-stats")]
+    pub latency: LatencyStats,
+}
+
+/// 已交给发送接口、尚未写到连接上的消息。积压持续增长或最早一条等待过久时，
+/// 发送方很快会遇到超时，可据此提前告警
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SendBacklog {
+    /// 排队中的消息数
+    pub messages: usize,
+    /// 最早一条已排队的时长，没有排队的消息时为 `None`
+    pub oldest: Option<Duration>,
+}
+
+#[cfg(feature = "sync")]
+#[cfg_attr(not(feature = "latency-stats"), allow(unused_variables))]
+impl ConnectionStats {
+    pub(crate) fn record_send(&mut self, bytes: usize, elapsed: Duration) {
+        self.bytes_sent += bytes as u64;
+        self.messages_sent += 1;
+        #[cfg(feature = "latency-stats")]
+        self.latency.record(Op::Send, elapsed);
+    }
+
+    pub(crate) fn record_recv(&mut self, bytes: usize, elapsed: Duration) {
+        self.bytes_received += bytes as u64;
+        self.messages_received += 1;
+        #[cfg(feature = "latency-stats")]
+        self.latency.record(Op::Recv, elapsed);
+    }
+
+    pub(crate) fn record_connect(&mut self, elapsed: Duration) {
+        #[cfg(feature = "latency-stats")]
+        self.latency.record(Op::Connect, elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_zeroed() {
+        let stats = ConnectionStats::default();
+        assert_eq!(stats.bytes_sent, 0);
+        assert_eq!(stats.bytes_received, 0);
+        assert_eq!(stats.messages_sent, 0);
+        assert_eq!(stats.messages_received, 0);
+        assert_eq!(stats.chunk_size, 0);
+        assert_eq!(stats.key_rotations, 0);
+        assert_eq!(stats.buffered_bytes, 0);
+        assert_eq!(stats.send_backlog, SendBacklog::default());
+        #[cfg(feature = "latency-stats")]
+        assert_eq!(stats.latency, LatencyStats::default());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn record_send_accumulates() {
+        let mut stats = ConnectionStats::default();
+        stats.record_send(100, Duration::from_micros(10));
+        stats.record_send(0, Duration::from_micros(10));
+        assert_eq!(stats.bytes_sent, 100);
+        assert_eq!(stats.messages_sent, 2);
+        assert_eq!(stats.messages_received, 0);
+        #[cfg(feature = "latency-stats")]
+        {
+            assert_eq!(stats.latency.send.count(), 2);
+            assert_eq!(stats.latency.recv.count(), 0);
+        }
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn record_recv_accumulates() {
+        let mut stats = ConnectionStats::default();
+        stats.record_recv(7, Duration::from_millis(1));
+        stats.record_recv(8, Duration::from_millis(1));
+        assert_eq!(stats.bytes_received, 15);
+        assert_eq!(stats.messages_received, 2);
+        assert_eq!(stats.bytes_sent, 0);
+    }
+}
@@ -0,0 +1,398 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 回显服务与吞吐测量
+//!
+//! 性能回归的固定流程：一端运行 [`echo_server()`]，把每个连接收到的消息原样送回；另一端以
+//! [`throughput_probe()`] 连续发送固定大小的消息并等待回显，记录每轮的发送与接收耗时。
+//!
+//! ```ignore
+//! // 宿主机
+//! let echo = virga::testing::echo_server(ServerConfig::new(u32::MAX, 1234, 1024, false))?;
+//!
+//! // 客户机
+//! let mut client = VirgeClient::new(ClientConfig::new(2, 1234, 1024, false));
+//! client.connect()?;
+//! let report = virga::testing::throughput_probe(&mut client, 500 * 1024, 10)?;
+//! println!("{}", report);
+//! ```
+//!
+//! 测得的是经公开 `send()`/`recv()` 的端到端往返，包含复制、压缩、加密等全部开销；只关心
+//! 传输本身能否工作时用 `self_test()`。
+
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use crate::agent::ShutdownHandle;
+use crate::client::VirgeClient;
+use crate::rpc;
+use crate::server::{ServerConfig, ServerManager, VirgeServer};
+use crate::threads;
+
+/// 原 `example/client_test` 使用的消息大小
+pub const DEFAULT_PROBE_MESSAGE_SIZE: usize = 500 * 1024;
+/// 原 `example/client_test` 使用的轮数
+pub const DEFAULT_PROBE_ITERATIONS: usize = 10;
+/// 回显服务等待新连接时检查停止请求的间隔
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 测量消息的填充字节
+const PROBE_FILL: u8 = 0xAB;
+
+/// 按消息收发的一端，测试中以内存通道代替
+trait Exchange {
+    fn send(&mut self, data: Vec<u8>) -> Result<usize>;
+    fn recv(&mut self) -> Result<Vec<u8>>;
+}
+
+impl Exchange for VirgeClient {
+    fn send(&mut self, data: Vec<u8>) -> Result<usize> {
+        VirgeClient::send(self, data)
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>> {
+        VirgeClient::recv(self)
+    }
+}
+
+impl Exchange for VirgeServer {
+    fn send(&mut self, data: Vec<u8>) -> Result<usize> {
+        VirgeServer::send(self, data)
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>> {
+        VirgeServer::recv(self)
+    }
+}
+
+/// 把收到的每条消息原样送回，直到对端关闭连接，返回回显的消息数；其他错误原样返回
+pub fn echo(server: &mut VirgeServer) -> Result<u64> {
+    echo_loop(server, &AtomicU64::new(0))
+}
+
+fn echo_loop(chan: &mut impl Exchange, messages: &AtomicU64) -> Result<u64> {
+    let mut echoed = 0;
+    loop {
+        let message = match chan.recv() {
+            Ok(message) => message,
+            Err(e) if rpc::is_closed(&e) => return Ok(echoed),
+            Err(e) => return Err(e),
+        };
+        chan.send(message)?;
+        echoed += 1;
+        messages.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// 回显服务的计数
+#[derive(Debug, Default)]
+struct EchoCounters {
+    connections: AtomicU64,
+    messages: AtomicU64,
+}
+
+/// 后台运行的回显服务，见 [`echo_server()`]；drop 时停止接受新连接
+#[derive(Debug)]
+pub struct EchoServer {
+    shutdown: ShutdownHandle,
+    acceptor: Option<JoinHandle<()>>,
+    counters: Arc<EchoCounters>,
+}
+
+impl EchoServer {
+    /// 握手成功的连接数
+    pub fn connections(&self) -> u64 {
+        self.counters.connections.load(Ordering::Relaxed)
+    }
+
+    /// 已回显的消息数
+    pub fn messages(&self) -> u64 {
+        self.counters.messages.load(Ordering::Relaxed)
+    }
+
+    /// 用于在其他线程请求停止的句柄
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 停止接受新连接并关闭监听；已接受的连接继续回显，直到对端断开
+    pub fn stop(mut self) {
+        self.shutdown_and_join();
+    }
+
+    fn shutdown_and_join(&mut self) {
+        self.shutdown.shutdown();
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+    }
+}
+
+impl Drop for EchoServer {
+    fn drop(&mut self) {
+        self.shutdown_and_join();
+    }
+}
+
+/// 按 `config` 开始监听，每个接受的连接由单独的线程以 [`echo()`] 回显；监听失败时返回错误
+pub fn echo_server(config: ServerConfig) -> Result<EchoServer> {
+    let mut manager = ServerManager::new(config);
+    manager.start()?;
+    let shutdown = ShutdownHandle::default();
+    let counters = Arc::new(EchoCounters::default());
+    let acceptor = {
+        let (shutdown, counters) = (shutdown.clone(), counters.clone());
+        threads::spawn("echo", move || accept_loop(manager, shutdown, counters))?
+    };
+    Ok(EchoServer {
+        shutdown,
+        acceptor: Some(acceptor),
+        counters,
+    })
+}
+
+fn accept_loop(mut manager: ServerManager, shutdown: ShutdownHandle, counters: Arc<EchoCounters>) {
+    while !shutdown.is_shutdown() {
+        let incoming = match manager.accept_incoming_timeout(ACCEPT_POLL_INTERVAL) {
+            Ok(Some(incoming)) => incoming,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Echo server accept failed: {}", e);
+                std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+        };
+        let conn = incoming.conn();
+        let counters = counters.clone();
+        let spawned = threads::spawn(format_args!("echo-{}", conn.cid), move || {
+            let mut server = match incoming.handshake() {
+                Ok(server) => server,
+                Err(e) => {
+                    warn!("Echo server handshake with {} failed: {}", conn, e);
+                    return;
+                }
+            };
+            counters.connections.fetch_add(1, Ordering::Relaxed);
+            match echo_loop(&mut server, &counters.messages) {
+                Ok(echoed) => debug!("Echoed {} messages on {}", echoed, conn),
+                Err(e) => warn!("Echo on {} failed: {}", conn, e),
+            }
+        });
+        if let Err(e) = spawned {
+            warn!("Failed to spawn echo thread for {}: {}", conn, e);
+        }
+    }
+    if let Err(e) = manager.stop() {
+        warn!("Echo server stop failed: {}", e);
+    }
+}
+
+/// 一轮测量：发出一条消息与收到其回显各自的耗时
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProbeRound {
+    pub send: Duration,
+    pub recv: Duration,
+}
+
+/// [`throughput_probe()`] 的结果
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThroughputReport {
+    /// 每条消息的字节数
+    pub message_size: usize,
+    /// 按顺序的各轮耗时
+    pub rounds: Vec<ProbeRound>,
+}
+
+impl ThroughputReport {
+    /// 发送耗时之和
+    pub fn send_time(&self) -> Duration {
+        self.rounds.iter().map(|round| round.send).sum()
+    }
+
+    /// 接收耗时之和
+    pub fn recv_time(&self) -> Duration {
+        self.rounds.iter().map(|round| round.recv).sum()
+    }
+
+    /// 单向传输的总字节数
+    pub fn bytes(&self) -> u64 {
+        (self.message_size * self.rounds.len()) as u64
+    }
+
+    /// 发送速率，字节/秒
+    pub fn send_rate(&self) -> f64 {
+        rate(self.bytes(), self.send_time())
+    }
+
+    /// 接收速率，字节/秒
+    pub fn recv_rate(&self) -> f64 {
+        rate(self.bytes(), self.recv_time())
+    }
+
+    /// 往返吞吐：两个方向的字节数除以收发总耗时，字节/秒
+    pub fn throughput(&self) -> f64 {
+        rate(2 * self.bytes(), self.send_time() + self.recv_time())
+    }
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// 汇总一行，随后每轮一行
+impl fmt::Display for ThroughputReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: f64 = (1 << 20) as f64;
+        let rounds = self.rounds.len().max(1) as u32;
+        write!(
+            f,
+            "{} x {} bytes: {:.1} MiB/s (send {:.1} MiB/s, avg {:?}; recv {:.1} MiB/s, avg {:?})",
+            self.rounds.len(),
+            self.message_size,
+            self.throughput() / MIB,
+            self.send_rate() / MIB,
+            self.send_time() / rounds,
+            self.recv_rate() / MIB,
+            self.recv_time() / rounds,
+        )?;
+        for (i, round) in self.rounds.iter().enumerate() {
+            write!(
+                f,
+                "\n  #{}: send {:?}, recv {:?}",
+                i + 1,
+                round.send,
+                round.recv
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// 向回显服务发送 `iterations` 条 `message_size` 字节的消息，每条等到回显后再发下一条。
+/// 回显的长度或内容不符时返回 `InvalidData`
+pub fn throughput_probe(
+    client: &mut VirgeClient,
+    message_size: usize,
+    iterations: usize,
+) -> Result<ThroughputReport> {
+    probe(client, message_size, iterations)
+}
+
+fn probe(
+    chan: &mut impl Exchange,
+    message_size: usize,
+    iterations: usize,
+) -> Result<ThroughputReport> {
+    if message_size == 0 || iterations == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "throughput probe needs a non-empty message and at least one round",
+        ));
+    }
+    let message = vec![PROBE_FILL; message_size];
+    let mut rounds = Vec::with_capacity(iterations);
+    for i in 1..=iterations {
+        let started = Instant::now();
+        chan.send(message.clone())?;
+        let send = started.elapsed();
+
+        let started = Instant::now();
+        let echoed = chan.recv()?;
+        let recv = started.elapsed();
+        if echoed != message {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "round {}: sent {} bytes but the echo was {} bytes or differed",
+                    i,
+                    message_size,
+                    echoed.len()
+                ),
+            ));
+        }
+        debug!("Probe round {}: send {:?}, recv {:?}", i, send, recv);
+        rounds.push(ProbeRound { send, recv });
+    }
+    Ok(ThroughputReport {
+        message_size,
+        rounds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::testing::{pipe_pair, Pipe};
+    use std::thread;
+
+    impl Exchange for Pipe {
+        fn send(&mut self, data: Vec<u8>) -> Result<usize> {
+            Ok(crate::transport::Transport::send(self, &data)?)
+        }
+
+        fn recv(&mut self) -> Result<Vec<u8>> {
+            Ok(crate::transport::Transport::recv(self)?)
+        }
+    }
+
+    #[test]
+    fn probe_measures_each_round_against_an_echo() {
+        let (mut client, mut server) = pipe_pair();
+        let messages = Arc::new(AtomicU64::new(0));
+        let counted = messages.clone();
+        let echo = thread::spawn(move || echo_loop(&mut server, &counted));
+
+        let report = probe(&mut client, 64 * 1024, 3).unwrap();
+        assert_eq!(report.rounds.len(), 3);
+        assert_eq!(report.bytes(), 3 * 64 * 1024);
+        assert!(report.throughput() > 0.0);
+        let text = report.to_string();
+        assert!(text.starts_with("3 x 65536 bytes: "), "{}", text);
+        assert!(text.contains("\n  #3: send "), "{}", text);
+
+        // 对端关闭连接时回显正常结束
+        drop(client);
+        assert_eq!(echo.join().unwrap().unwrap(), 3);
+        assert_eq!(messages.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn probe_rejects_a_wrong_echo_and_empty_runs() {
+        let (mut client, mut server) = pipe_pair();
+        let echo = thread::spawn(move || {
+            let message = server.recv().unwrap();
+            server.send(message[1..].to_vec()).unwrap();
+        });
+        let err = probe(&mut client, 16, 2).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("round 1"), "{}", err);
+        echo.join().unwrap();
+
+        for (size, iterations) in [(0, 1), (1, 0)] {
+            let err = probe(&mut client, size, iterations).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn rates_use_the_summed_round_times() {
+        let round = |ms| ProbeRound {
+            send: Duration::from_millis(ms),
+            recv: Duration::from_millis(ms * 3),
+        };
+        let report = ThroughputReport {
+            message_size: 1000,
+            rounds: vec![round(100), round(150)],
+        };
+        assert_eq!(report.send_time(), Duration::from_millis(250));
+        assert_eq!(report.recv_time(), Duration::from_millis(750));
+        assert_eq!(report.send_rate(), 8000.0);
+        assert_eq!(report.throughput(), 4000.0);
+    }
+}
@@ -0,0 +1,303 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 内部线程的命名与 CPU 亲和性
+//!
+//! 库在后台使用两类线程：
+//!
+//! - 运行时线程：yamux 的 tokio 运行时工作线程，运行各连接的驱动、读、发送任务，
+//!   名为 `<前缀>-yamux`
+//! - 工作线程：复用（`<前缀>-mux-<连接 ID>`）、JSON 行模式接收（`<前缀>-json-<连接 ID>`）、
+//!   接收溢写（`<前缀>-spool-<连接 ID>`）、存活检查、流量回调与连接池预热等线程
+//!
+//! 用 [`configure()`] 设置线程名前缀（默认 `virga`）、运行时线程数（或改用不启动线程的
+//! 单线程运行时），以及两类线程各自
+//! 允许运行的 CPU 核，把库的后台工作与应用的延迟敏感线程隔离开。运行时在首次使用时
+//! 按当时的配置创建，之后不能再修改运行时相关的设置；工作线程的设置对之后启动的线程
+//! 生效。Linux 上线程名最长 15 字节，超出部分被截断。
+//!
+//! ```ignore
+//! virga::threads::configure(
+//!     ThreadConfig::new()
+//!         .with_runtime_threads(2)
+//!         .with_runtime_cores([2, 3])
+//!         .with_worker_cores([3]),
+//! )?;
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+#[cfg(feature = "sync")]
+use std::thread::{Scope, ScopedJoinHandle};
+
+use log::*;
+
+use crate::error::{Result, VirgeError};
+
+/// 默认的线程名前缀
+pub const DEFAULT_NAME_PREFIX: &str = "virga";
+/// 默认的运行时线程数
+pub const DEFAULT_RUNTIME_THREADS: usize = 4;
+
+/// 内部线程的命名与 CPU 亲和性，见[模块文档](self)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThreadConfig {
+    name_prefix: String,
+    runtime_threads: usize,
+    current_thread_runtime: bool,
+    runtime_cores: Option<Vec<usize>>,
+    worker_cores: Option<Vec<usize>>,
+}
+
+impl ThreadConfig {
+    pub const fn new() -> Self {
+        Self {
+            name_prefix: String::new(),
+            runtime_threads: DEFAULT_RUNTIME_THREADS,
+            current_thread_runtime: false,
+            runtime_cores: None,
+            worker_cores: None,
+        }
+    }
+
+    /// 线程名前缀，默认 `virga`
+    pub fn with_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.name_prefix = prefix.into();
+        self
+    }
+
+    /// 运行时工作线程数，默认 4
+    pub fn with_runtime_threads(mut self, threads: usize) -> Self {
+        self.runtime_threads = threads;
+        self
+    }
+
+    /// 使用不启动任何线程的单线程运行时，连接的后台任务只在调用方阻塞于同步接口时推进；
+    /// 此时运行时线程数与 CPU 核的设置不起作用
+    pub fn with_current_thread_runtime(mut self, enabled: bool) -> Self {
+        self.current_thread_runtime = enabled;
+        self
+    }
+
+    /// 运行时线程只在这些 CPU 核上运行，默认不限制
+    pub fn with_runtime_cores(mut self, cores: impl IntoIterator<Item = usize>) -> Self {
+        self.runtime_cores = Some(cores.into_iter().collect());
+        self
+    }
+
+    /// 工作线程只在这些 CPU 核上运行，默认不限制
+    pub fn with_worker_cores(mut self, cores: impl IntoIterator<Item = usize>) -> Self {
+        self.worker_cores = Some(cores.into_iter().collect());
+        self
+    }
+
+    pub fn name_prefix(&self) -> &str {
+        if self.name_prefix.is_empty() {
+            DEFAULT_NAME_PREFIX
+        } else {
+            &self.name_prefix
+        }
+    }
+
+    pub fn runtime_threads(&self) -> usize {
+        self.runtime_threads
+    }
+
+    pub fn current_thread_runtime(&self) -> bool {
+        self.current_thread_runtime
+    }
+
+    pub fn runtime_cores(&self) -> Option<&[usize]> {
+        self.runtime_cores.as_deref()
+    }
+
+    pub fn worker_cores(&self) -> Option<&[usize]> {
+        self.worker_cores.as_deref()
+    }
+
+    /// 校验配置：运行时至少一个线程，核列表非空且编号小于 `CPU_SETSIZE`
+    pub fn validate(&self) -> Result<()> {
+        if self.runtime_threads == 0 {
+            return Err(VirgeError::ConfigError(
+                "runtime_threads must be at least 1".to_string(),
+            ));
+        }
+        for (what, cores) in [
+            ("runtime_cores", &self.runtime_cores),
+            ("worker_cores", &self.worker_cores),
+        ] {
+            let Some(cores) = cores else { continue };
+            if cores.is_empty() {
+                return Err(VirgeError::ConfigError(format!(
+                    "{} must not be empty",
+                    what
+                )));
+            }
+            if let Some(core) = cores.iter().find(|&&c| c >= libc::CPU_SETSIZE as usize) {
+                return Err(VirgeError::ConfigError(format!(
+                    "{} contains core {}, limit is {}",
+                    what,
+                    core,
+                    libc::CPU_SETSIZE
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn same_runtime(&self, other: &ThreadConfig) -> bool {
+        self.name_prefix() == other.name_prefix()
+            && self.runtime_threads == other.runtime_threads
+            && self.current_thread_runtime == other.current_thread_runtime
+            && self.runtime_cores == other.runtime_cores
+    }
+}
+
+impl Default for ThreadConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static CONFIG: RwLock<ThreadConfig> = RwLock::new(ThreadConfig::new());
+static RUNTIME_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// 设置内部线程的命名与 CPU 亲和性。运行时已创建后修改运行时相关的设置返回
+/// `ConfigError`，此时整个配置都不生效
+pub fn configure(config: ThreadConfig) -> Result<()> {
+    config.validate()?;
+    let mut current = CONFIG.write().unwrap_or_else(PoisonError::into_inner);
+    if RUNTIME_STARTED.load(Ordering::Acquire) && !current.same_runtime(&config) {
+        return Err(VirgeError::ConfigError(
+            "the runtime has already started, its threads can no longer be changed".to_string(),
+        ));
+    }
+    *current = config;
+    Ok(())
+}
+
+/// 当前的线程配置
+pub fn config() -> ThreadConfig {
+    CONFIG
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// 运行时创建时取用配置，之后不再允许修改运行时相关的设置
+#[cfg_attr(not(feature = "use-yamux"), allow(dead_code))]
+pub(crate) fn start_runtime() -> ThreadConfig {
+    let config = CONFIG.read().unwrap_or_else(PoisonError::into_inner);
+    RUNTIME_STARTED.store(true, Ordering::Release);
+    config.clone()
+}
+
+/// 启动名为 `<前缀>-<suffix>` 的工作线程，按配置限定其 CPU 核
+pub(crate) fn spawn<F, T>(suffix: impl std::fmt::Display, f: F) -> std::io::Result<JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let config = config();
+    let cores = config.worker_cores.clone();
+    thread::Builder::new()
+        .name(format!("{}-{}", config.name_prefix(), suffix))
+        .spawn(move || {
+            if let Some(cores) = cores {
+                pin_current(&cores);
+            }
+            f()
+        })
+}
+
+/// 同 [`spawn()`]，在 `scope` 内启动
+#[cfg(feature = "sync")]
+pub(crate) fn spawn_scoped<'scope, 'env, F, T>(
+    scope: &'scope Scope<'scope, 'env>,
+    suffix: impl std::fmt::Display,
+    f: F,
+) -> std::io::Result<ScopedJoinHandle<'scope, T>>
+where
+    F: FnOnce() -> T + Send + 'scope,
+    T: Send + 'scope,
+{
+    let config = config();
+    let cores = config.worker_cores.clone();
+    thread::Builder::new()
+        .name(format!("{}-{}", config.name_prefix(), suffix))
+        .spawn_scoped(scope, move || {
+            if let Some(cores) = cores {
+                pin_current(&cores);
+            }
+            f()
+        })
+}
+
+/// 把当前线程限定在 `cores` 上；失败（如核不存在）只记录日志，线程照常运行
+pub(crate) fn pin_current(cores: &[usize]) {
+    // SAFETY: cpu_set_t 是普通的位图，全零即空集合
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &core in cores {
+        // SAFETY: validate() 保证核编号小于 CPU_SETSIZE
+        unsafe { libc::CPU_SET(core, &mut set) };
+    }
+    // SAFETY: pid 0 表示调用线程，set 在调用期间有效
+    let rc = unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) };
+    if rc != 0 {
+        warn!(
+            "Failed to pin thread {:?} to cores {:?}: {}",
+            thread::current().name(),
+            cores,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_cores() -> Vec<usize> {
+        // SAFETY: 同 pin_current
+        let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+        let rc =
+            unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) };
+        assert_eq!(rc, 0);
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&c| unsafe { libc::CPU_ISSET(c, &set) })
+            .collect()
+    }
+
+    #[test]
+    fn validate_rejects_bad_core_lists() {
+        assert!(ThreadConfig::new().validate().is_ok());
+        assert!(ThreadConfig::new()
+            .with_runtime_threads(0)
+            .validate()
+            .is_err());
+        assert!(ThreadConfig::new()
+            .with_worker_cores([])
+            .validate()
+            .is_err());
+        assert!(ThreadConfig::new()
+            .with_runtime_cores([0, 1 << 20])
+            .validate()
+            .is_err());
+        assert_eq!(ThreadConfig::new().name_prefix(), "virga");
+    }
+
+    #[test]
+    fn pinned_thread_runs_only_on_its_cores() {
+        // 取本进程允许使用的第一个核，容器可能不允许使用 0 号核
+        let first = current_cores()[0];
+        let cores = thread::spawn(move || {
+            pin_current(&[first]);
+            current_cores()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(cores, [first]);
+    }
+}
@@ -0,0 +1,214 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 发送端合批
+//!
+//! 配置 `with_coalescing(max_delay, max_bytes)` 后，小消息先放入连接内的批次，
+//! 攒够 `max_bytes`、最早一条已等待 `max_delay`，或连接要接收、ping、断开、
+//! 发送压缩消息时，整批作为一帧发出；接收方按原边界拆回单条消息，`recv()` 逐条返回。
+//! 不小于 `max_bytes` 的消息不合批，先发出已攒的批次再单独发送。
+//!
+//! 接口是同步的、没有后台线程，等待时长只在下一次发送时检查：最后一批之后
+//! 不再发送时，调用 `Write::flush()` 把它发出去。
+//!
+//! 批次编码：
+//!
+//! ```text
+//! (len(4, 大端) | data)*
+//! ```
+
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+use super::MessageKind;
+use crate::error::{Result, VirgeError};
+use crate::stats::SendBacklog;
+use crate::units::ByteSize;
+
+const LEN_PREFIX: usize = 4;
+
+/// 合批参数，任一条件满足即发出当前批次
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Coalescing {
+    /// 批次中最早一条消息的最长等待时间
+    pub max_delay: Duration,
+    /// 批次的最大字节数
+    pub max_bytes: ByteSize,
+}
+
+impl Coalescing {
+    pub(crate) fn validate(&self) -> crate::Result<()> {
+        if self.max_bytes.as_u64() <= LEN_PREFIX as u64 || self.max_bytes.as_u64() > u32::MAX as u64
+        {
+            return Err(VirgeError::ConfigError(format!(
+                "coalescing max_bytes must be in {}..={}",
+                LEN_PREFIX + 1,
+                u32::MAX
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 一条连接尚未发出的批次
+#[derive(Debug)]
+pub(crate) struct Batcher {
+    config: Coalescing,
+    buf: Vec<u8>,
+    count: usize,
+    since: Option<Instant>,
+}
+
+impl Batcher {
+    pub(crate) fn new(config: Coalescing) -> Self {
+        Self {
+            config,
+            buf: Vec::new(),
+            count: 0,
+            since: None,
+        }
+    }
+
+    /// 长度为 `len` 的消息是否合批
+    pub(crate) fn fits(&self, len: usize) -> bool {
+        LEN_PREFIX + len < self.config.max_bytes.as_usize()
+    }
+
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        self.since.get_or_insert_with(Instant::now);
+        self.buf
+            .extend_from_slice(&(data.len() as u32).to_be_bytes());
+        self.buf.extend_from_slice(data);
+        self.count += 1;
+    }
+
+    /// 批次已攒够或最早一条已等待足够久
+    pub(crate) fn due(&self) -> bool {
+        self.buf.len() >= self.config.max_bytes.as_usize()
+            || self
+                .since
+                .is_some_and(|since| since.elapsed() >= self.config.max_delay)
+    }
+
+    /// 取出当前批次；只有一条消息时按普通消息发送，省去批次编码
+    pub(crate) fn take(&mut self) -> Option<(Vec<u8>, MessageKind)> {
+        self.since = None;
+        match std::mem::take(&mut self.count) {
+            0 => None,
+            1 => {
+                let mut data = std::mem::take(&mut self.buf);
+                data.drain(..LEN_PREFIX);
+                Some((data, MessageKind::Plain))
+            }
+            _ => Some((std::mem::take(&mut self.buf), MessageKind::Batch)),
+        }
+    }
+
+    /// 已攒的字节数
+    pub(crate) fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// 已攒的消息数与最早一条的等待时长
+    pub(crate) fn backlog(&self) -> SendBacklog {
+        SendBacklog {
+            messages: self.count,
+            oldest: self.since.map(|since| since.elapsed()),
+        }
+    }
+}
+
+/// 按原边界拆分一个批次
+pub(crate) fn split(mut data: &[u8]) -> Result<VecDeque<Vec<u8>>> {
+    let mut messages = VecDeque::new();
+    while !data.is_empty() {
+        let malformed = || VirgeError::transport(ErrorKind::InvalidData, "malformed message batch");
+        let (len, rest) = data
+            .split_first_chunk::<LEN_PREFIX>()
+            .ok_or_else(malformed)?;
+        let len = u32::from_be_bytes(*len) as usize;
+        if rest.len() < len {
+            return Err(malformed());
+        }
+        messages.push_back(rest[..len].to_vec());
+        data = &rest[len..];
+    }
+    if messages.is_empty() {
+        return Err(VirgeError::transport(
+            ErrorKind::InvalidData,
+            "empty message batch",
+        ));
+    }
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batcher(max_delay: Duration, max_bytes: u64) -> Batcher {
+        Batcher::new(Coalescing {
+            max_delay,
+            max_bytes: ByteSize::b(max_bytes),
+        })
+    }
+
+    #[test]
+    fn batch_round_trips_and_keeps_boundaries() {
+        let mut batch = batcher(Duration::from_secs(60), 1024);
+        assert!(batch.take().is_none());
+        for msg in [&b"a"[..], b"", b"hello"] {
+            assert!(batch.fits(msg.len()));
+            batch.push(msg);
+        }
+        assert!(!batch.due());
+        let (data, kind) = batch.take().unwrap();
+        assert_eq!(kind, MessageKind::Batch);
+        assert_eq!(
+            split(&data).unwrap(),
+            [b"a".to_vec(), vec![], b"hello".to_vec()]
+        );
+        assert_eq!(batch.len(), 0);
+
+        batch.push(b"only");
+        assert_eq!(
+            batch.take().unwrap(),
+            (b"only".to_vec(), MessageKind::Plain)
+        );
+    }
+
+    #[test]
+    fn due_by_size_or_delay() {
+        let mut batch = batcher(Duration::from_secs(60), 16);
+        assert!(!batch.fits(12));
+        assert_eq!(batch.backlog(), SendBacklog::default());
+        batch.push(&[0; 8]);
+        assert!(!batch.due());
+        batch.push(&[0; 8]);
+        assert!(batch.due());
+        let backlog = batch.backlog();
+        assert_eq!(backlog.messages, 2);
+        assert!(backlog.oldest.is_some());
+        batch.take();
+        assert_eq!(batch.backlog(), SendBacklog::default());
+
+        let mut batch = batcher(Duration::ZERO, 1024);
+        batch.push(b"x");
+        assert!(batch.due());
+    }
+
+    #[test]
+    fn malformed_batches_are_rejected() {
+        assert!(split(&[]).is_err());
+        assert!(split(&[0, 0]).is_err());
+        assert!(split(&[0, 0, 0, 5, 1]).is_err());
+        let config = |bytes| Coalescing {
+            max_delay: Duration::from_millis(1),
+            max_bytes: ByteSize::b(bytes),
+        };
+        assert!(config(4).validate().is_err());
+        assert!(config(16 * 1024).validate().is_ok());
+    }
+}
@@ -0,0 +1,507 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 传输层一致性检查
+//!
+//! 对任意 [`Transport`] 实现运行同一组检查：消息回显、大消息、多个连接并发收发、
+//! 消息发送途中对端断开、空闲超时。调用方提供创建一对互连连接的函数，每项检查使用
+//! 新的连接对，在单独的线程中运行，超过 [`CASE_TIMEOUT`] 未结束视为失败（卡住的线程
+//! 不会被回收）。第三方后端可在自己的测试中调用 [`assert_conforms()`]：
+//!
+//! ```ignore
+//! #[test]
+//! fn backend_conforms() {
+//!     virga::transport::conformance::assert_conforms(|| MyTransport::pair());
+//! }
+//! ```
+
+use std::fmt;
+use std::io::ErrorKind;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::Transport;
+use crate::error::{Result, VirgeError};
+use crate::threads;
+
+/// 单项检查的最长运行时间
+pub const CASE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 大消息检查所用的消息长度，远大于常见的分片与 socket 缓冲
+const LARGE_MESSAGE: usize = (2 << 20) + 3;
+/// 断开检查中发送的消息长度，足以让发送在对端断开时仍未完成
+const STALLED_MESSAGE: usize = 16 << 20;
+/// 并发检查同时使用的连接对数
+const CONCURRENT_PAIRS: usize = 4;
+/// 并发检查每个连接对往返的消息数
+const CONCURRENT_MESSAGES: usize = 32;
+/// 空闲超时检查所设的超时
+const IDLE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// 一项检查
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Case {
+    /// 不同长度的消息原样往返，顺序不变
+    Echo,
+    /// 远大于分片的消息双向完整送达
+    LargeMessage,
+    /// 多个连接在各自的线程中同时收发，消息互不串扰
+    Concurrent,
+    /// 发送途中对端断开时发送方得到错误而不是一直阻塞；阻塞在接收上的一方得到连接关闭
+    DisconnectMidMessage,
+    /// 空闲超时内没有消息时接收返回超时，之后连接仍可使用
+    IdleTimeout,
+}
+
+impl Case {
+    pub const ALL: [Case; 5] = [
+        Case::Echo,
+        Case::LargeMessage,
+        Case::Concurrent,
+        Case::DisconnectMidMessage,
+        Case::IdleTimeout,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Case::Echo => "echo",
+            Case::LargeMessage => "large-message",
+            Case::Concurrent => "concurrent",
+            Case::DisconnectMidMessage => "disconnect-mid-message",
+            Case::IdleTimeout => "idle-timeout",
+        }
+    }
+
+    /// 需要的连接对数
+    fn pairs(self) -> usize {
+        match self {
+            Case::Concurrent => CONCURRENT_PAIRS,
+            Case::DisconnectMidMessage => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 一项未通过的检查及原因
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+    pub case: Case,
+    pub reason: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.case, self.reason)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+type Outcome = std::result::Result<(), String>;
+
+/// 运行全部检查，返回未通过的各项。`pair` 每次返回一对已互连的连接
+pub fn run<T, F>(mut pair: F) -> std::result::Result<(), Vec<Failure>>
+where
+    T: Transport + Send + 'static,
+    F: FnMut() -> Result<(T, T)>,
+{
+    let failures: Vec<Failure> = Case::ALL
+        .into_iter()
+        .filter_map(|case| run_case(case, &mut pair).err())
+        .collect();
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(failures)
+    }
+}
+
+/// 运行全部检查，有未通过的项时 panic 并列出原因，供测试直接调用
+pub fn assert_conforms<T, F>(pair: F)
+where
+    T: Transport + Send + 'static,
+    F: FnMut() -> Result<(T, T)>,
+{
+    if let Err(failures) = run(pair) {
+        let list: Vec<String> = failures.iter().map(Failure::to_string).collect();
+        panic!("transport does not conform:\n  {}", list.join("\n  "));
+    }
+}
+
+/// 运行单项检查
+pub fn run_case<T, F>(case: Case, pair: &mut F) -> std::result::Result<(), Failure>
+where
+    T: Transport + Send + 'static,
+    F: FnMut() -> Result<(T, T)>,
+{
+    let fail = |reason: String| Failure { case, reason };
+    let mut pairs = Vec::with_capacity(case.pairs());
+    for _ in 0..case.pairs() {
+        pairs.push(pair().map_err(|e| fail(format!("could not connect a pair: {}", e)))?);
+    }
+
+    let (tx, rx) = channel();
+    threads::spawn(format!("conformance-{}", case), move || {
+        let outcome = match case {
+            Case::Echo => echo(pairs),
+            Case::LargeMessage => large_message(pairs),
+            Case::Concurrent => concurrent(pairs),
+            Case::DisconnectMidMessage => disconnect_mid_message(pairs),
+            Case::IdleTimeout => idle_timeout(pairs),
+        };
+        drop(tx.send(outcome));
+    })
+    .map_err(|e| fail(format!("could not start the check: {}", e)))?;
+
+    match rx.recv_timeout(CASE_TIMEOUT) {
+        Ok(outcome) => outcome.map_err(fail),
+        Err(RecvTimeoutError::Timeout) => {
+            Err(fail(format!("did not finish within {:?}", CASE_TIMEOUT)))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(fail("the check panicked".to_string())),
+    }
+}
+
+fn echo<T: Transport + Send + 'static>(mut pairs: Vec<(T, T)>) -> Outcome {
+    let (a, b) = pairs.remove(0);
+    let messages = vec![
+        b"ping".to_vec(),
+        vec![0x5a],
+        pattern(1000, 1),
+        pattern(64 << 10, 2),
+    ];
+    exchange(a, b, &messages)
+}
+
+fn large_message<T: Transport + Send + 'static>(mut pairs: Vec<(T, T)>) -> Outcome {
+    let (a, b) = pairs.remove(0);
+    exchange(a, b, &[pattern(LARGE_MESSAGE, 3)])
+}
+
+fn concurrent<T: Transport + Send + 'static>(pairs: Vec<(T, T)>) -> Outcome {
+    let handles = pairs
+        .into_iter()
+        .enumerate()
+        .map(|(i, (a, b))| {
+            let messages: Vec<Vec<u8>> = (0..CONCURRENT_MESSAGES)
+                .map(|n| {
+                    let mut msg = format!("pair {} message {}:", i, n).into_bytes();
+                    msg.extend(pattern(n * 97, i as u32));
+                    msg
+                })
+                .collect();
+            threads::spawn(format!("conformance-pair-{}", i), move || {
+                exchange(a, b, &messages).map_err(|e| format!("pair {}: {}", i, e))
+            })
+            .map_err(|e| format!("could not start pair {}: {}", i, e))
+        })
+        .collect::<std::result::Result<Vec<_>, String>>()?;
+    for handle in handles {
+        handle.join().map_err(|_| "a pair panicked".to_string())??;
+    }
+    Ok(())
+}
+
+fn disconnect_mid_message<T: Transport + Send + 'static>(mut pairs: Vec<(T, T)>) -> Outcome {
+    // 接收方断开时发送方正阻塞在一条放不进 socket 缓冲的消息上
+    let (mut sender, mut receiver) = pairs.remove(0);
+    let stalled = threads::spawn("conformance-stalled", move || {
+        let message = pattern(STALLED_MESSAGE, 4);
+        match send(&mut sender, &message) {
+            Ok(()) => match sender.recv() {
+                Ok(_) => Err("sender received a message from a disconnected peer".to_string()),
+                Err(e) => expect_closed("sender recv after the peer disconnected", &e),
+            },
+            Err(_) => Ok(()),
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    thread::sleep(Duration::from_millis(50));
+    receiver
+        .disconnect()
+        .map_err(|e| format!("disconnect failed: {}", e))?;
+    if receiver.is_connected() {
+        return Err("still connected after disconnect".to_string());
+    }
+    if receiver.send(b"late").is_ok() && receiver.flush().is_ok() {
+        return Err("send succeeded after disconnect".to_string());
+    }
+    stalled
+        .join()
+        .map_err(|_| "the stalled sender panicked".to_string())??;
+
+    // 一方阻塞在接收上时对端断开
+    let (mut waiting, mut closing) = pairs.remove(0);
+    let waiter = threads::spawn("conformance-waiting", move || match waiting.recv() {
+        Ok(_) => Err("received a message nobody sent".to_string()),
+        Err(e) => expect_closed("recv while the peer disconnected", &e),
+    })
+    .map_err(|e| e.to_string())?;
+    thread::sleep(Duration::from_millis(50));
+    closing
+        .disconnect()
+        .map_err(|e| format!("disconnect failed: {}", e))?;
+    waiter
+        .join()
+        .map_err(|_| "the waiting receiver panicked".to_string())?
+}
+
+fn idle_timeout<T: Transport + Send + 'static>(mut pairs: Vec<(T, T)>) -> Outcome {
+    let (a, mut b) = pairs.remove(0);
+    b.set_idle_timeout(Some(IDLE_TIMEOUT))
+        .map_err(|e| format!("set_idle_timeout failed: {}", e))?;
+    let started = Instant::now();
+    match b.recv() {
+        Ok(_) => return Err("received a message nobody sent".to_string()),
+        Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {}
+        Err(e) => {
+            return Err(format!(
+                "idle recv failed with {:?} instead of a timeout: {}",
+                e.kind(),
+                e
+            ))
+        }
+    }
+    let waited = started.elapsed();
+    if waited < IDLE_TIMEOUT / 2 {
+        return Err(format!(
+            "recv timed out after {:?}, the timeout is {:?}",
+            waited, IDLE_TIMEOUT
+        ));
+    }
+    b.set_idle_timeout(None)
+        .map_err(|e| format!("clearing the idle timeout failed: {}", e))?;
+    exchange(a, b, &[b"after timeout".to_vec()])
+}
+
+/// `a` 依次发送 `messages` 并等待 `b` 原样发回，之后断开 `a`，`b` 应在收到连接关闭后结束
+fn exchange<T: Transport + Send + 'static>(mut a: T, b: T, messages: &[Vec<u8>]) -> Outcome {
+    let echo = threads::spawn("conformance-echo", move || echo_until_closed(b))
+        .map_err(|e| e.to_string())?;
+    for (i, message) in messages.iter().enumerate() {
+        send(&mut a, message).map_err(|e| format!("send {} failed: {}", i, e))?;
+        let reply = a
+            .recv()
+            .map_err(|e| format!("recv of reply {} failed: {}", i, e))?;
+        if reply != *message {
+            return Err(format!(
+                "reply {} differs from the message sent ({} bytes back, {} sent)",
+                i,
+                reply.len(),
+                message.len()
+            ));
+        }
+    }
+    a.disconnect()
+        .map_err(|e| format!("disconnect failed: {}", e))?;
+    let echoed = echo
+        .join()
+        .map_err(|_| "the echo side panicked".to_string())??;
+    if echoed != messages.len() {
+        return Err(format!(
+            "the echo side saw {} messages, {} were sent",
+            echoed,
+            messages.len()
+        ));
+    }
+    Ok(())
+}
+
+/// 把收到的消息原样发回，直到连接关闭，返回消息数
+fn echo_until_closed<T: Transport>(mut b: T) -> std::result::Result<usize, String> {
+    let mut echoed = 0;
+    loop {
+        match b.recv() {
+            Ok(message) => {
+                send(&mut b, &message).map_err(|e| format!("echo send failed: {}", e))?;
+                echoed += 1;
+            }
+            Err(e) => {
+                expect_closed("echo recv after the peer disconnected", &e)?;
+                return Ok(echoed);
+            }
+        }
+    }
+}
+
+/// 发送一条消息并发出缓冲，检查返回的字节数
+fn send<T: Transport>(transport: &mut T, message: &[u8]) -> Result<()> {
+    let sent = transport.send(message)?;
+    transport.flush()?;
+    if sent != message.len() {
+        return Err(VirgeError::Other(format!(
+            "send returned {} for a {} byte message",
+            sent,
+            message.len()
+        )));
+    }
+    Ok(())
+}
+
+fn expect_closed(what: &str, e: &VirgeError) -> Outcome {
+    match e.kind() {
+        ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::UnexpectedEof
+        | ErrorKind::BrokenPipe
+        | ErrorKind::NotConnected => Ok(()),
+        kind => Err(format!(
+            "{} failed with {:?} instead of a closed connection: {}",
+            what, kind, e
+        )),
+    }
+}
+
+/// 可复现的测试数据，`seed` 不同的消息内容不同
+fn pattern(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(31).wrapping_add(seed.wrapping_mul(131)) >> 2) as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream;
+
+    /// 以长度前缀分帧的 Unix socket，发送时把消息截断到 `limit` 字节
+    struct Framed {
+        stream: UnixStream,
+        connected: bool,
+        limit: usize,
+    }
+
+    fn framed_pair(limit: usize) -> Result<(Framed, Framed)> {
+        let (a, b) = UnixStream::pair()?;
+        let framed = |stream| Framed {
+            stream,
+            connected: true,
+            limit,
+        };
+        Ok((framed(a), framed(b)))
+    }
+
+    impl Transport for Framed {
+        fn send(&mut self, data: &[u8]) -> Result<usize> {
+            if !self.connected {
+                return Err(VirgeError::transport(
+                    ErrorKind::NotConnected,
+                    "not connected",
+                ));
+            }
+            let kept = &data[..data.len().min(self.limit)];
+            self.stream.write_all(&(kept.len() as u32).to_be_bytes())?;
+            self.stream.write_all(kept)?;
+            Ok(data.len())
+        }
+
+        fn recv(&mut self) -> Result<Vec<u8>> {
+            let mut len = [0u8; 4];
+            self.stream.read_exact(&mut len)?;
+            let mut data = vec![0; u32::from_be_bytes(len) as usize];
+            self.stream.read_exact(&mut data)?;
+            Ok(data)
+        }
+
+        fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+            Ok(self.stream.set_read_timeout(timeout)?)
+        }
+
+        fn disconnect(&mut self) -> Result<()> {
+            self.connected = false;
+            drop(self.stream.shutdown(Shutdown::Both));
+            Ok(())
+        }
+
+        fn is_connected(&self) -> bool {
+            self.connected
+        }
+    }
+
+    #[test]
+    fn framed_socket_conforms() {
+        assert_conforms(|| framed_pair(usize::MAX));
+    }
+
+    #[test]
+    fn truncated_large_messages_are_reported() {
+        let failures = run(|| framed_pair(1 << 20)).unwrap_err();
+        assert_eq!(failures.len(), 1, "{:?}", failures);
+        assert_eq!(failures[0].case, Case::LargeMessage);
+        assert!(failures[0].reason.contains("differs"), "{}", failures[0]);
+    }
+
+    #[cfg(feature = "use-xtransport")]
+    mod xtransport {
+        use super::*;
+        use crate::transport::xtransport::{TransportConfig, XTransport};
+
+        /// XTransport 分帧跑在 Unix socket 上，另留一个句柄设置超时与断开
+        struct Framing {
+            transport: Option<XTransport<UnixStream>>,
+            socket: UnixStream,
+        }
+
+        fn pair() -> Result<(Framing, Framing)> {
+            let (a, b) = UnixStream::pair()?;
+            let framing = |socket: UnixStream| -> Result<Framing> {
+                let config = TransportConfig::default()
+                    .with_max_frame_size(4096)
+                    .with_ack(true);
+                Ok(Framing {
+                    transport: Some(XTransport::new(socket.try_clone()?, config)),
+                    socket,
+                })
+            };
+            Ok((framing(a)?, framing(b)?))
+        }
+
+        impl Framing {
+            fn transport(&mut self) -> Result<&mut XTransport<UnixStream>> {
+                self.transport
+                    .as_mut()
+                    .ok_or_else(|| VirgeError::transport(ErrorKind::NotConnected, "not connected"))
+            }
+        }
+
+        impl Transport for Framing {
+            fn send(&mut self, data: &[u8]) -> Result<usize> {
+                self.transport()?.send_message(data)?;
+                Ok(data.len())
+            }
+
+            fn recv(&mut self) -> Result<Vec<u8>> {
+                Ok(self.transport()?.recv_message()?)
+            }
+
+            fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+                Ok(self.socket.set_read_timeout(timeout)?)
+            }
+
+            fn disconnect(&mut self) -> Result<()> {
+                self.transport = None;
+                drop(self.socket.shutdown(Shutdown::Both));
+                Ok(())
+            }
+
+            fn is_connected(&self) -> bool {
+                self.transport.is_some()
+            }
+        }
+
+        #[test]
+        fn xtransport_framing_conforms() {
+            assert_conforms(pair);
+        }
+    }
+}
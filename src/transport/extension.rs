@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 应用自定义的旁路帧
+//!
+//! 控制帧类型中 [`EXTENSION_FRAME_TYPES`]（0x80–0xFF）留给应用：以 [`register_frame_handler()`]
+//! 为某个类型注册处理函数后，对端以 `send_extension()` 发来的该类型帧交给处理函数，不进入
+//! `recv()` 的消息流，进度通知、缓存提示这类旁路功能因此可以与普通消息共用一条连接，
+//! 不影响只收发消息的使用方。注册表是进程级的，对所有连接生效；收到未注册类型的旁路帧时
+//! 记录后丢弃（严格模式下也是如此），不认识旁路帧的旧版本对端同样会忽略它们。
+//!
+//! ```ignore
+//! virga::transport::register_frame_handler(0x80, Arc::new(|conn: ConnContext, data: &[u8]| {
+//!     progress.update(conn.conn_id, data);
+//! }))?;
+//! client.send_extension(0x80, &done.to_be_bytes())?;
+//! ```
+//!
+//! 处理函数在接收路径上调用：yamux 下是后台读任务所在的运行时线程，XTransport 下是正在
+//! 接收的线程（应用未接收时旁路帧留在连接中）。处理函数应尽快返回，耗时的工作交给其他
+//! 线程，也不要在其中收发同一条连接。旁路帧不经过认证建立的加密，最长
+//! [`MAX_EXTENSION_FRAME_SIZE`] 字节。
+
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use log::*;
+
+use crate::error::{ConnContext, Result, VirgeError};
+
+/// 留给应用的控制帧类型
+pub const EXTENSION_FRAME_TYPES: RangeInclusive<u8> = 0x80..=0xFF;
+
+/// 旁路帧负载的最大字节数（yamux 控制帧体上限 256 字节，减去 1 字节类型）
+pub const MAX_EXTENSION_FRAME_SIZE: usize = 255;
+
+/// 处理收到的旁路帧
+pub trait FrameHandler: Send + Sync {
+    fn handle(&self, conn: ConnContext, data: &[u8]);
+}
+
+impl<F> FrameHandler for F
+where
+    F: Fn(ConnContext, &[u8]) + Send + Sync,
+{
+    fn handle(&self, conn: ConnContext, data: &[u8]) {
+        self(conn, data)
+    }
+}
+
+static HANDLERS: RwLock<BTreeMap<u8, Arc<dyn FrameHandler>>> = RwLock::new(BTreeMap::new());
+
+/// 为 `frame_type` 注册处理函数。类型不在 [`EXTENSION_FRAME_TYPES`] 内或已被注册时返回
+/// `ConfigError`
+pub fn register_frame_handler(frame_type: u8, handler: Arc<dyn FrameHandler>) -> Result<()> {
+    if !EXTENSION_FRAME_TYPES.contains(&frame_type) {
+        return Err(VirgeError::ConfigError(format!(
+            "frame type {:#04x} is outside the extension range {:#04x}..={:#04x}",
+            frame_type,
+            EXTENSION_FRAME_TYPES.start(),
+            EXTENSION_FRAME_TYPES.end()
+        )));
+    }
+    let mut handlers = HANDLERS.write().unwrap_or_else(PoisonError::into_inner);
+    if handlers.contains_key(&frame_type) {
+        return Err(VirgeError::ConfigError(format!(
+            "frame type {:#04x} is already registered",
+            frame_type
+        )));
+    }
+    handlers.insert(frame_type, handler);
+    Ok(())
+}
+
+/// 移除 `frame_type` 的处理函数，返回它是否存在；之后收到的该类型帧被丢弃
+pub fn unregister_frame_handler(frame_type: u8) -> bool {
+    HANDLERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&frame_type)
+        .is_some()
+}
+
+/// 控制帧类型是否留给应用
+pub(crate) fn is_extension(frame_type: u8) -> bool {
+    EXTENSION_FRAME_TYPES.contains(&frame_type)
+}
+
+/// 发送前检查类型与长度，不合要求时返回 `InvalidInput`
+pub(crate) fn check_outgoing(frame_type: u8, data: &[u8]) -> Result<()> {
+    if !is_extension(frame_type) {
+        return Err(VirgeError::transport(
+            ErrorKind::InvalidInput,
+            format!("frame type {:#04x} is not an extension type", frame_type),
+        ));
+    }
+    if data.len() > MAX_EXTENSION_FRAME_SIZE {
+        return Err(VirgeError::transport(
+            ErrorKind::InvalidInput,
+            format!(
+                "extension frame of {} bytes exceeds {} bytes",
+                data.len(),
+                MAX_EXTENSION_FRAME_SIZE
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// 把收到的旁路帧交给注册的处理函数，没有处理函数时丢弃；返回是否已处理
+pub(crate) fn dispatch(frame_type: u8, conn: ConnContext, data: &[u8]) -> bool {
+    let handler = HANDLERS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&frame_type)
+        .cloned();
+    match handler {
+        Some(handler) => {
+            handler.handle(conn, data);
+            true
+        }
+        None => {
+            debug!(
+                "Dropping {} byte extension frame {:#04x} on {}: no handler",
+                data.len(),
+                frame_type,
+                conn
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn registered_handlers_receive_their_frames() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let handler: Arc<dyn FrameHandler> = Arc::new(move |conn: ConnContext, data: &[u8]| {
+            sink.lock().unwrap().push((conn.port, data.to_vec()));
+        });
+        assert!(matches!(
+            register_frame_handler(0x10, handler.clone()),
+            Err(VirgeError::ConfigError(_))
+        ));
+        register_frame_handler(0xF0, handler.clone()).unwrap();
+        assert!(matches!(
+            register_frame_handler(0xF0, handler),
+            Err(VirgeError::ConfigError(_))
+        ));
+
+        assert!(dispatch(0xF0, ConnContext::new(3, 1234), b"50%"));
+        assert!(!dispatch(0xF1, ConnContext::new(3, 1234), b"lost"));
+        assert_eq!(*seen.lock().unwrap(), [(1234, b"50%".to_vec())]);
+
+        assert!(unregister_frame_handler(0xF0));
+        assert!(!unregister_frame_handler(0xF0));
+        assert!(!dispatch(0xF0, ConnContext::new(3, 1234), b"late"));
+    }
+
+    #[test]
+    fn outgoing_frames_are_checked() {
+        assert!(check_outgoing(0x80, &[0; MAX_EXTENSION_FRAME_SIZE]).is_ok());
+        for (frame_type, len) in [(0x7F, 0), (0x80, MAX_EXTENSION_FRAME_SIZE + 1)] {
+            let err = check_outgoing(frame_type, &vec![0; len]).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 经 VMM 的 Unix socket 连接客户机的 vsock 端口（hybrid vsock）
+//!
+//! Firecracker、Cloud Hypervisor 等 VMM 不经内核的 vhost-vsock，而是为每个客户机创建一个
+//! Unix socket：宿主机连上它后写入 `CONNECT <端口>\n`，VMM 回复 `OK <宿主机端口>\n`，此后
+//! 该连接即通往客户机内监听该端口的程序。客户机内没有程序监听时 VMM 直接关闭连接。
+//! 握手完成后两端使用与 vsock 相同的 XTransport 分帧，见 [`SocketpairTransport::from_stream()`]。
+//!
+//! [`SocketpairTransport::from_stream()`]: super::SocketpairTransport::from_stream
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+use log::*;
+
+use crate::error::{Result, VirgeError};
+
+/// 等待 VMM 回复 `OK` 的时长
+pub const DEFAULT_HYBRID_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// VMM 回复行的长度上限
+const MAX_REPLY_LINE: usize = 64;
+
+/// 连接 VMM 的 Unix socket `path` 并请求转发到客户机的 vsock 端口 `port`，返回握手完成的连接。
+/// VMM 未回复 `OK`（客户机内没有程序监听该端口）时返回 `kind()` 为 `ConnectionRefused` 的错误，
+/// 超过 [`DEFAULT_HYBRID_HANDSHAKE_TIMEOUT`] 时返回 `TimedOut`
+pub fn connect_hybrid_vsock(path: impl AsRef<Path>, port: u32) -> Result<UnixStream> {
+    let path = path.as_ref();
+    let mut socket = UnixStream::connect(path).map_err(|e| {
+        VirgeError::connection_io(format!("Failed to connect {}", path.display()), e)
+    })?;
+    socket.set_read_timeout(Some(DEFAULT_HYBRID_HANDSHAKE_TIMEOUT))?;
+    socket.write_all(format!("CONNECT {}\n", port).as_bytes())?;
+
+    // 逐字节读取，不读走回复之后客户机发来的数据
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    loop {
+        match socket.read(&mut byte) {
+            Ok(0) => {
+                return Err(VirgeError::transport(
+                    ErrorKind::ConnectionRefused,
+                    format!("{} closed the connection to port {}", path.display(), port),
+                ))
+            }
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) if line.len() < MAX_REPLY_LINE => line.push(byte[0]),
+            Ok(_) => {
+                return Err(VirgeError::transport(
+                    ErrorKind::InvalidData,
+                    format!("{} sent an overlong reply", path.display()),
+                ))
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                return Err(VirgeError::transport(
+                    ErrorKind::TimedOut,
+                    format!("{} did not answer CONNECT {}", path.display(), port),
+                ))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let reply = String::from_utf8_lossy(&line);
+    if !reply.starts_with("OK ") {
+        return Err(VirgeError::transport(
+            ErrorKind::ConnectionRefused,
+            format!(
+                "{} refused port {}: {:?}",
+                path.display(),
+                port,
+                reply.trim_end()
+            ),
+        ));
+    }
+    socket.set_read_timeout(None)?;
+    debug!(
+        "Connected to port {} through {} ({})",
+        port,
+        path.display(),
+        reply.trim_end()
+    );
+    Ok(socket)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::transport::{SocketpairTransport, Transport};
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::thread;
+
+    /// 模拟 VMM：只接受到 `port` 的连接，握手后回显每条消息；返回 socket 路径
+    pub(crate) fn fake_vmm(name: &str, port: u32) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("virga-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let socket = socket.unwrap();
+                thread::spawn(move || {
+                    let mut request = String::new();
+                    let mut reader = BufReader::new(socket.try_clone().unwrap());
+                    reader.read_line(&mut request).unwrap();
+                    if request != format!("CONNECT {}\n", port) {
+                        return;
+                    }
+                    (&socket).write_all(b"OK 1073741824\n").unwrap();
+                    let framing = crate::transport::default_framing();
+                    let mut guest = SocketpairTransport::from_stream(socket, framing).unwrap();
+                    while let Ok(message) = guest.recv() {
+                        guest.send(&message).unwrap();
+                    }
+                });
+            }
+        });
+        path
+    }
+
+    #[test]
+    fn hybrid_vsock_connects_through_the_vmm() {
+        let path = fake_vmm("hybrid", 1234);
+        let socket = connect_hybrid_vsock(&path, 1234).unwrap();
+        let framing = crate::transport::default_framing();
+        let mut transport = SocketpairTransport::from_stream(socket, framing).unwrap();
+        transport.send(b"through the vmm").unwrap();
+        assert_eq!(transport.recv().unwrap(), b"through the vmm");
+
+        let err = connect_hybrid_vsock(&path, 4321).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        std::fs::remove_file(&path).unwrap();
+        let err = connect_hybrid_vsock(&path, 1234).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 不支持 `SO_RCVTIMEO` 的描述符（字符设备、管道）上的接收空闲超时
+
+use std::io::{self, ErrorKind};
+use std::os::fd::BorrowedFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::{Result, VirgeError};
+use crate::mux::poll_readable;
+
+/// 传输与其读取端共享的空闲超时，读取前以 `poll()` 等待数据
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IdleTimeout {
+    /// 微秒，0 表示一直等待
+    micros: Arc<AtomicU64>,
+}
+
+impl IdleTimeout {
+    pub(crate) fn set(&self, timeout: Option<Duration>) -> Result<()> {
+        let micros = match timeout {
+            Some(t) if t.is_zero() => {
+                return Err(VirgeError::transport(
+                    ErrorKind::InvalidInput,
+                    "idle timeout must be greater than zero",
+                ))
+            }
+            Some(t) => (t.as_micros() as u64).max(1),
+            None => 0,
+        };
+        self.micros.store(micros, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 等待 `fd` 可读，超时时返回 `TimedOut`
+    pub(crate) fn wait(&self, fd: BorrowedFd<'_>) -> io::Result<()> {
+        let micros = self.micros.load(Ordering::Relaxed);
+        if micros == 0 || poll_readable(fd, None, Some(Duration::from_micros(micros)))? {
+            return Ok(());
+        }
+        Err(io::Error::new(ErrorKind::TimedOut, "idle timeout"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::fd::AsFd;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn waits_until_readable_or_timeout() {
+        let (a, mut b) = UnixStream::pair().unwrap();
+        let idle = IdleTimeout::default();
+        assert!(idle.set(Some(Duration::ZERO)).is_err());
+        idle.set(Some(Duration::from_millis(20))).unwrap();
+        let err = idle.wait(a.as_fd()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        b.write_all(b"x").unwrap();
+        idle.wait(a.as_fd()).unwrap();
+        idle.set(None).unwrap();
+        idle.wait(a.as_fd()).unwrap();
+    }
+}
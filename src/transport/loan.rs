@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 借用式接收缓冲
+
+use std::fmt;
+use std::ops::Deref;
+
+/// `recv_loan()` 返回的消息视图
+///
+/// 直接引用传输层内部的接收缓冲，避免为每条消息分配新的 `Vec`。
+/// 它借用了连接本身，因此在下一次接收或被 drop 之前一直有效；
+/// 需要长期保存时用 `to_vec()` 拷贝出来。
+pub struct RecvLoan<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> RecvLoan<'a> {
+    #[cfg(feature = "sync")]
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl Deref for RecvLoan<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl AsRef<[u8]> for RecvLoan<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl fmt::Debug for RecvLoan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvLoan")
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loan_derefs_to_bytes() {
+        let buf = vec![1u8, 2, 3];
+        let loan = RecvLoan::new(&buf);
+        assert_eq!(&*loan, &[1, 2, 3]);
+        assert_eq!(loan.len(), 3);
+        assert_eq!(loan.to_vec(), buf);
+    }
+
+    #[test]
+    fn loan_debug_shows_len_only() {
+        let buf = vec![0u8; 42];
+        let loan = RecvLoan::new(&buf);
+        assert_eq!(format!("{:?}", loan), "RecvLoan { len: 42 }");
+    }
+}
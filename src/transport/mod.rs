@@ -4,16 +4,251 @@
 
 //! 传输协议层
 
+#[cfg(feature = "sync")]
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[cfg(feature = "sync")]
+use crate::compression::CompressionContext;
+use crate::error::Result;
+
+#[cfg(feature = "sync")]
+pub(crate) mod batch;
+#[cfg(feature = "sync")]
+pub use batch::Coalescing;
+pub mod conformance;
+#[cfg(feature = "sync")]
+mod extension;
+#[cfg(feature = "sync")]
+pub use extension::{
+    register_frame_handler, unregister_frame_handler, FrameHandler, EXTENSION_FRAME_TYPES,
+    MAX_EXTENSION_FRAME_SIZE,
+};
+mod registry;
+pub use registry::{register_transport, unregister_transport, TransportFactory, TransportType};
+mod loan;
+pub use loan::RecvLoan;
+mod race;
+pub use race::{ConnectRace, DEFAULT_HEAD_START, DEFAULT_RACE_TIMEOUT};
+#[cfg(all(test, any(feature = "auth", feature = "sync")))]
+pub(crate) mod testing;
+
+#[cfg(feature = "use-xtransport")]
+mod hybrid;
+#[cfg(feature = "use-xtransport")]
+mod idle;
+#[cfg(feature = "use-xtransport")]
+mod serial;
+#[cfg(feature = "use-xtransport")]
+mod socketpair;
+#[cfg(feature = "ssh")]
+mod ssh;
 #[cfg(feature = "use-xtransport")]
 pub mod xtransport;
 #[cfg(feature = "use-xtransport")]
 mod xtransport_impl;
 #[cfg(feature = "use-xtransport")]
+pub use hybrid::{connect_hybrid_vsock, DEFAULT_HYBRID_HANDSHAKE_TIMEOUT};
+#[cfg(feature = "use-xtransport")]
+pub use serial::SerialTransport;
+#[cfg(feature = "use-xtransport")]
+pub use socketpair::{SocketpairTransport, FD_ENV};
+#[cfg(feature = "ssh")]
+pub use ssh::{SshConfig, SshTransport, DEFAULT_BRIDGE};
+#[cfg(feature = "use-io-uring")]
+pub use xtransport_impl::UringStream;
+#[cfg(feature = "use-xtransport")]
 pub use xtransport_impl::XTransportHandler;
 
 #[cfg(feature = "use-yamux")]
 mod yamux_impl;
 #[cfg(feature = "use-yamux")]
-pub use yamux_impl::get_runtime;
+pub use yamux_impl::{get_runtime, use_runtime, YamuxRuntime};
 #[cfg(feature = "use-yamux")]
-pub use yamux_impl::YamuxTransportHandler;
+pub use yamux_impl::{VirgaStream, YamuxTransportHandler};
+
+/// 收到对端任何帧（含传输内部处理的 ping、pong 等控制帧）时调用，服务端以此刷新对端的存活记录
+#[cfg(feature = "sync")]
+pub(crate) type FrameHook = std::sync::Arc<dyn Fn() + Send + Sync>;
+
+/// 一个已建立连接上的按消息收发，由各传输后端实现。第三方后端实现后可用
+/// [`conformance`] 检查其行为是否与内置后端一致，用 [`register_transport()`] 注册后
+/// 经 `TransportType::Custom(name)` 选用，用 `TokenAuth::authenticate()` 等完成认证握手。
+///
+/// 错误以 `VirgeError::transport(kind, message)` 构造，或由 `io::Error` 经 `?` 转换；
+/// 调用方按 `kind()` 区分连接关闭、超时等情况，各方法的文档给出了约定的取值
+pub trait Transport {
+    /// 发送一条消息，返回消息的字节数；可以先放入缓冲，由 `flush()` 发出
+    fn send(&mut self, data: &[u8]) -> Result<usize>;
+
+    /// 发出缓冲中尚未发送的消息
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// 接收一条完整的消息。对端断开后返回 `kind()` 为 `ConnectionReset`、
+    /// `ConnectionAborted`、`UnexpectedEof`、`BrokenPipe` 或 `NotConnected` 的错误
+    fn recv(&mut self) -> Result<Vec<u8>>;
+
+    /// 设置接收的空闲超时，超时时 `recv()` 返回 `TimedOut` 或 `WouldBlock`，
+    /// 连接仍可继续使用；`None` 表示一直等待
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()>;
+
+    /// 断开连接，之后本端的收发返回错误，对端的接收返回连接关闭
+    fn disconnect(&mut self) -> Result<()>;
+
+    fn is_connected(&self) -> bool;
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        (**self).send(data)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>> {
+        (**self).recv()
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        (**self).set_idle_timeout(timeout)
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        (**self).disconnect()
+    }
+
+    fn is_connected(&self) -> bool {
+        (**self).is_connected()
+    }
+}
+
+#[cfg(feature = "use-xtransport")]
+impl Transport for XTransportHandler {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        XTransportHandler::send(self, data)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        XTransportHandler::flush(self)
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>> {
+        XTransportHandler::recv(self)
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        XTransportHandler::set_idle_timeout(self, timeout)
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        XTransportHandler::disconnect(self)
+    }
+
+    fn is_connected(&self) -> bool {
+        XTransportHandler::is_connected(self)
+    }
+}
+
+#[cfg(feature = "use-yamux")]
+impl Transport for YamuxTransportHandler {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        YamuxTransportHandler::send(self, data)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        YamuxTransportHandler::flush(self)
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>> {
+        YamuxTransportHandler::recv(self)
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        YamuxTransportHandler::set_idle_timeout(self, timeout)
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        YamuxTransportHandler::disconnect(self)
+    }
+
+    fn is_connected(&self) -> bool {
+        YamuxTransportHandler::is_connected(self)
+    }
+}
+
+/// 非 vsock 后端使用的 XTransport 分帧配置：分片大小、确认模式与发送窗口取 `Defaults`
+#[cfg(feature = "use-xtransport")]
+pub(crate) fn default_framing() -> xtransport::TransportConfig {
+    let defaults = crate::Defaults::get();
+    xtransport::TransportConfig::default()
+        .with_max_frame_size(defaults.chunk_size.get())
+        .with_ack(defaults.is_ack)
+        .with_send_window(defaults.send_window.get())
+}
+
+/// XTransport 分帧的最高版本：v2 的校验和同时覆盖包头，连接建立后由客户端提议、
+/// 服务端应答选定，任一侧不支持时保持 v1。yamux 的分帧没有版本之分
+pub const MAX_PROTOCOL_VERSION: u8 = 2;
+
+/// 校验配置的最高分帧版本在 1..=`MAX_PROTOCOL_VERSION` 内
+#[cfg(feature = "sync")]
+pub(crate) fn validate_protocol_version(version: u8) -> Result<()> {
+    if !(1..=MAX_PROTOCOL_VERSION).contains(&version) {
+        return Err(crate::error::VirgeError::ConfigError(format!(
+            "max_protocol_version {} is outside 1..={}",
+            version, MAX_PROTOCOL_VERSION
+        )));
+    }
+    Ok(())
+}
+
+/// 拒绝原因的最大字节数
+#[cfg(feature = "sync")]
+pub(crate) const MAX_REJECT_REASON: usize = 200;
+
+/// 把拒绝原因截断到 `MAX_REJECT_REASON` 字节以内，不拆开 UTF-8 字符
+#[cfg(feature = "sync")]
+pub(crate) fn truncate_reason(reason: &str) -> &str {
+    let mut end = reason.len().min(MAX_REJECT_REASON);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    &reason[..end]
+}
+
+/// 一帧消息的内容类型，由帧上的标记给出
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(not(feature = "sync"), allow(dead_code))]
+pub(crate) enum MessageKind {
+    /// 原文
+    #[default]
+    Plain,
+    /// zstd 压缩后的消息
+    Compressed,
+    /// 合批的多条消息，见 [`batch`]
+    Batch,
+}
+
+/// 按内容类型还原一帧：压缩消息解压；批次拆开后返回第一条，其余放入 `unbatched`
+#[cfg(feature = "sync")]
+pub(crate) fn unpack(
+    compression: &CompressionContext,
+    unbatched: &mut VecDeque<Vec<u8>>,
+    data: &[u8],
+    kind: MessageKind,
+) -> Result<Vec<u8>> {
+    match kind {
+        MessageKind::Plain => Ok(data.to_vec()),
+        MessageKind::Compressed => compression.decompress(data),
+        MessageKind::Batch => {
+            let mut messages = batch::split(data)?;
+            let first = messages.pop_front().unwrap_or_default();
+            unbatched.extend(messages);
+            Ok(first)
+        }
+    }
+}
@@ -0,0 +1,297 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 同时尝试多个传输后端，保留最先连上的一个
+//!
+//! 同一客户机既可经内核 vsock、又可经 VMM 的 Unix socket（hybrid vsock）到达时，逐个尝试会
+//! 在首选后端不通时白白等满它的超时。[`ConnectRace`] 按 happy eyeballs 的方式连接：首选后端
+//! 先出发，[`with_head_start()`](ConnectRace::with_head_start) 后仍未连上（或已经失败）时下一个
+//! 后端出发，最先连上的胜出，之后连上的随即断开。
+
+use std::fmt::Write as _;
+use std::io::ErrorKind;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use super::{Transport, TransportType};
+use crate::addr::Addr;
+use crate::error::{Result, VirgeError};
+use crate::threads;
+
+/// 首选后端领先下一个后端出发的默认时长
+pub const DEFAULT_HEAD_START: Duration = Duration::from_millis(250);
+/// 默认的连接期限
+pub const DEFAULT_RACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 一个后端的连接结果：候选序号与连接
+type Attempt = (usize, Result<Box<dyn Transport + Send>>);
+
+/// 同时尝试多个后端的连接，见[模块文档](self)
+///
+/// ```ignore
+/// let race = ConnectRace::new(TransportType::XTransport)
+///     .or("hybrid:/run/vm-3.sock".parse()?)
+///     .with_head_start(Duration::from_millis(100));
+/// let (winner, mut conn) = race.connect(Addr::new(3, 1234))?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct ConnectRace {
+    candidates: Vec<TransportType>,
+    head_start: Duration,
+    timeout: Duration,
+}
+
+impl ConnectRace {
+    /// 以 `preferred` 为首选后端
+    pub fn new(preferred: TransportType) -> Self {
+        Self {
+            candidates: vec![preferred],
+            head_start: DEFAULT_HEAD_START,
+            timeout: DEFAULT_RACE_TIMEOUT,
+        }
+    }
+
+    /// 添加一个后备后端，按添加顺序依次出发
+    pub fn or(mut self, fallback: TransportType) -> Self {
+        self.candidates.push(fallback);
+        self
+    }
+
+    /// 每个后端领先下一个后端出发的时长（默认 [`DEFAULT_HEAD_START`]），为 0 时同时出发
+    pub fn with_head_start(mut self, head_start: Duration) -> Self {
+        self.head_start = head_start;
+        self
+    }
+
+    /// 等待任一后端连上的期限（默认 [`DEFAULT_RACE_TIMEOUT`]），超过时返回 `TimedOut`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn candidates(&self) -> &[TransportType] {
+        &self.candidates
+    }
+
+    /// 连接到 `addr`，返回胜出的后端与它的连接。全部后端都失败时返回首选后端错误的
+    /// `kind()`，消息中列出各后端的错误；期限内无一连上时返回 `TimedOut`。
+    /// 仍在进行的尝试在后台继续，连上后随即断开
+    pub fn connect(&self, addr: Addr) -> Result<(TransportType, Box<dyn Transport + Send>)> {
+        let started = Instant::now();
+        let deadline = started + self.timeout;
+        let (tx, rx) = mpsc::channel::<Attempt>();
+        let mut launched = 0;
+        let mut next_launch = started;
+        let mut failures: Vec<(usize, VirgeError)> = Vec::new();
+        loop {
+            let now = Instant::now();
+            // 到了下一个后端的出发时刻，或已出发的都失败了
+            if launched < self.candidates.len()
+                && (now >= next_launch || failures.len() == launched)
+            {
+                self.launch(launched, addr, tx.clone())?;
+                launched += 1;
+                next_launch = now + self.head_start;
+                continue;
+            }
+            if failures.len() == self.candidates.len() {
+                return Err(self.all_failed(addr, failures));
+            }
+            let wake = if launched < self.candidates.len() {
+                next_launch.min(deadline)
+            } else {
+                deadline
+            };
+            match rx.recv_timeout(wake.saturating_duration_since(now)) {
+                Ok((index, Ok(transport))) => {
+                    let winner = self.candidates[index].clone();
+                    info!(
+                        "Connected to {} via {} after {:?}",
+                        addr,
+                        winner,
+                        started.elapsed()
+                    );
+                    return Ok((winner, transport));
+                }
+                Ok((index, Err(e))) => {
+                    debug!(
+                        "Connecting to {} via {} failed: {}",
+                        addr, self.candidates[index], e
+                    );
+                    failures.push((index, e));
+                }
+                Err(RecvTimeoutError::Timeout) if Instant::now() >= deadline => {
+                    return Err(VirgeError::transport(
+                        ErrorKind::TimedOut,
+                        format!(
+                            "no transport connected to {} within {:?}",
+                            addr, self.timeout
+                        ),
+                    ));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// 在后台线程中连接第 `index` 个后端，结果经 `tx` 送回；已有结果时断开
+    fn launch(&self, index: usize, addr: Addr, tx: mpsc::Sender<Attempt>) -> Result<()> {
+        let kind = self.candidates[index].clone();
+        threads::spawn(format!("race-{}", index), move || {
+            let result = kind.connect(addr);
+            if let Err(mpsc::SendError((_, Ok(mut transport)))) = tx.send((index, result)) {
+                debug!("Dropping the late connection to {} via {}", addr, kind);
+                let _ = transport.disconnect();
+            }
+        })?;
+        Ok(())
+    }
+
+    fn all_failed(&self, addr: Addr, mut failures: Vec<(usize, VirgeError)>) -> VirgeError {
+        failures.sort_by_key(|(index, _)| *index);
+        let mut message = format!("no transport connected to {}", addr);
+        for (index, e) in &failures {
+            let _ = write!(message, "; {}: {}", self.candidates[*index], e);
+        }
+        VirgeError::transport(failures[0].1.kind(), message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::register_transport;
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    /// 记录是否被断开的连接
+    struct Fake(Arc<AtomicUsize>);
+
+    impl Transport for Fake {
+        fn send(&mut self, data: &[u8]) -> Result<usize> {
+            Ok(data.len())
+        }
+
+        fn recv(&mut self) -> Result<Vec<u8>> {
+            Err(VirgeError::transport(ErrorKind::WouldBlock, "fake"))
+        }
+
+        fn set_idle_timeout(&mut self, _: Option<Duration>) -> Result<()> {
+            Ok(())
+        }
+
+        fn disconnect(&mut self) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn is_connected(&self) -> bool {
+            true
+        }
+    }
+
+    /// 注册一个在 `delay` 后连上（`refuse` 时拒绝）的后端，返回它与其连接被断开的次数
+    fn backend(name: &str, delay: Duration, refuse: bool) -> (TransportType, Arc<AtomicUsize>) {
+        let name = format!("race-{}", name);
+        let disconnects = Arc::new(AtomicUsize::new(0));
+        let counter = disconnects.clone();
+        register_transport(
+            name.as_str(),
+            Arc::new(move |addr: Addr| -> Result<Box<dyn Transport + Send>> {
+                thread::sleep(delay);
+                if refuse {
+                    return Err(VirgeError::transport(
+                        ErrorKind::ConnectionRefused,
+                        format!("refusing {}", addr),
+                    ));
+                }
+                Ok(Box::new(Fake(counter.clone())))
+            }),
+        )
+        .unwrap();
+        (name.parse().unwrap(), disconnects)
+    }
+
+    #[test]
+    fn the_preferred_transport_wins_within_its_head_start() {
+        let addr = Addr::new(3, 1234);
+        let (preferred, _) = backend("preferred", Duration::from_millis(20), false);
+        let (fallback, fallback_disconnects) = backend("fallback", Duration::ZERO, false);
+        let race = ConnectRace::new(preferred.clone())
+            .or(fallback.clone())
+            .with_head_start(Duration::from_millis(500));
+        let (winner, _conn) = race.connect(addr).unwrap();
+        assert_eq!(winner, preferred);
+        assert_eq!(fallback_disconnects.load(Ordering::SeqCst), 0);
+
+        // 首选后端慢于领先时长：后备后端胜出，首选后端连上后随即断开
+        let (slow, slow_disconnects) = backend("slow", Duration::from_millis(300), false);
+        let race = ConnectRace::new(slow)
+            .or(fallback.clone())
+            .with_head_start(Duration::from_millis(50));
+        let started = Instant::now();
+        let (winner, _conn) = race.connect(addr).unwrap();
+        assert_eq!(winner, fallback);
+        assert!(started.elapsed() < Duration::from_millis(250));
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(slow_disconnects.load(Ordering::SeqCst), 1);
+
+        // 首选后端立即失败时不等领先时长
+        let (refused, _) = backend("refused", Duration::ZERO, true);
+        let race = ConnectRace::new(refused)
+            .or(fallback.clone())
+            .with_head_start(Duration::from_secs(5));
+        let started = Instant::now();
+        assert_eq!(race.connect(addr).unwrap().0, fallback);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn races_fail_when_no_transport_connects() {
+        let addr = Addr::new(3, 1235);
+        let (first, _) = backend("first-refused", Duration::ZERO, true);
+        let (second, _) = backend("second-refused", Duration::from_millis(10), true);
+        let err = ConnectRace::new(first)
+            .or(second)
+            .or(TransportType::Custom("race-unregistered".to_string()))
+            .connect(addr)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        let message = err.to_string();
+        assert!(message.contains("race-first-refused: "), "{}", message);
+        assert!(message.contains("race-unregistered: "), "{}", message);
+
+        let (hung, disconnects) = backend("hung", Duration::from_millis(300), false);
+        let err = ConnectRace::new(hung)
+            .with_timeout(Duration::from_millis(50))
+            .connect(addr)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(disconnects.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "use-xtransport")]
+    #[test]
+    fn hybrid_vsock_races_native_vsock() {
+        use crate::transport::hybrid::tests::fake_vmm;
+
+        // 本机通常没有端口 1236 上的 vsock 服务：内核 vsock 失败后 hybrid vsock 胜出
+        let path = fake_vmm("race", 1236);
+        let hybrid = TransportType::Hybrid(path.clone());
+        let race = ConnectRace::new(TransportType::XTransport)
+            .or(hybrid.clone())
+            .with_timeout(Duration::from_secs(5));
+        let (winner, mut conn) = race.connect(Addr::new(3, 1236)).unwrap();
+        assert_eq!(winner, hybrid);
+        conn.send(b"raced").unwrap();
+        assert_eq!(conn.recv().unwrap(), b"raced");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
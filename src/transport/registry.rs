@@ -0,0 +1,334 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 按名称选择传输后端
+//!
+//! [`TransportType`] 给出内置后端或以 [`register_transport()`] 注册的自定义后端，
+//! `connect()` 建立到对端的连接并以 `Box<dyn Transport + Send>` 返回。注册表是进程级的，
+//! 名称 `xtransport`、`yamux`、`serial`、`inherited`、`ssh` 与 `hybrid` 保留给内置后端，
+//! `serial:<设备路径>` 选用 [`SerialTransport`](super::SerialTransport)，`inherited` 选用父进程
+//! 传下的 [`SocketpairTransport`](super::SocketpairTransport)，`ssh:<登录目标>` 选用
+//! `SshTransport`（`ssh` 特性），`hybrid:<socket 路径>` 经 VMM 的 Unix socket 连接客户机
+//! （见 [`connect_hybrid_vsock()`](super::connect_hybrid_vsock)）。多个后端可用
+//! [`ConnectRace`](super::ConnectRace) 同时尝试。
+
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(feature = "use-xtransport")]
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use super::Transport;
+use crate::addr::Addr;
+use crate::error::{Result, VirgeError};
+
+/// 内置后端的名称
+const BUILTIN_NAMES: [&str; 6] = [
+    "xtransport",
+    "yamux",
+    "serial",
+    "inherited",
+    "ssh",
+    "hybrid",
+];
+/// 串口后端在配置中的前缀，其后是设备路径
+const SERIAL_PREFIX: &str = "serial:";
+/// SSH 隧道在配置中的前缀，其后是登录目标
+const SSH_PREFIX: &str = "ssh:";
+/// hybrid vsock 在配置中的前缀，其后是 VMM 的 Unix socket 路径
+const HYBRID_PREFIX: &str = "hybrid:";
+
+/// 建立自定义后端的连接
+pub trait TransportFactory: Send + Sync {
+    fn connect(&self, addr: Addr) -> Result<Box<dyn Transport + Send>>;
+}
+
+impl<F> TransportFactory for F
+where
+    F: Fn(Addr) -> Result<Box<dyn Transport + Send>> + Send + Sync,
+{
+    fn connect(&self, addr: Addr) -> Result<Box<dyn Transport + Send>> {
+        self(addr)
+    }
+}
+
+static FACTORIES: RwLock<BTreeMap<String, Arc<dyn TransportFactory>>> =
+    RwLock::new(BTreeMap::new());
+
+/// 以 `name` 注册自定义后端，之后 `TransportType::Custom(name)` 解析到它。
+/// 名称为空、与内置后端同名或已被注册时返回 `ConfigError`
+pub fn register_transport(
+    name: impl Into<String>,
+    factory: Arc<dyn TransportFactory>,
+) -> Result<()> {
+    let name = name.into();
+    if name.is_empty()
+        || BUILTIN_NAMES.contains(&name.as_str())
+        || name.starts_with(SERIAL_PREFIX)
+        || name.starts_with(SSH_PREFIX)
+        || name.starts_with(HYBRID_PREFIX)
+    {
+        return Err(VirgeError::ConfigError(format!(
+            "transport name {:?} is reserved",
+            name
+        )));
+    }
+    let mut factories = FACTORIES.write().unwrap_or_else(PoisonError::into_inner);
+    if factories.contains_key(&name) {
+        return Err(VirgeError::ConfigError(format!(
+            "transport {:?} is already registered",
+            name
+        )));
+    }
+    factories.insert(name, factory);
+    Ok(())
+}
+
+/// 移除以 `name` 注册的后端，返回它是否存在；已建立的连接不受影响
+pub fn unregister_transport(name: &str) -> bool {
+    FACTORIES
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(name)
+        .is_some()
+}
+
+fn factory(name: &str) -> Option<Arc<dyn TransportFactory>> {
+    FACTORIES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(name)
+        .cloned()
+}
+
+/// 传输后端
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TransportType {
+    #[cfg(feature = "use-xtransport")]
+    XTransport,
+    #[cfg(feature = "use-yamux")]
+    Yamux,
+    /// virtio-serial 或串口设备，见 [`SerialTransport`](super::SerialTransport)
+    #[cfg(feature = "use-xtransport")]
+    Serial(PathBuf),
+    /// 父进程经 `SocketpairTransport::spawn()` 传下的 socket，见
+    /// [`SocketpairTransport::from_env()`](super::SocketpairTransport::from_env)
+    #[cfg(feature = "use-xtransport")]
+    Inherited,
+    /// 经 SSH 登录的宿主机上的客户机，见 [`SshTransport`](super::SshTransport)
+    #[cfg(feature = "ssh")]
+    Ssh(String),
+    /// 经 VMM 的 Unix socket 连接的客户机（Firecracker、Cloud Hypervisor），见
+    /// [`connect_hybrid_vsock()`](super::connect_hybrid_vsock)
+    #[cfg(feature = "use-xtransport")]
+    Hybrid(PathBuf),
+    /// 以 [`register_transport()`] 注册的后端
+    Custom(String),
+}
+
+impl TransportType {
+    pub fn name(&self) -> &str {
+        match self {
+            #[cfg(feature = "use-xtransport")]
+            TransportType::XTransport => BUILTIN_NAMES[0],
+            #[cfg(feature = "use-yamux")]
+            TransportType::Yamux => BUILTIN_NAMES[1],
+            #[cfg(feature = "use-xtransport")]
+            TransportType::Serial(_) => BUILTIN_NAMES[2],
+            #[cfg(feature = "use-xtransport")]
+            TransportType::Inherited => BUILTIN_NAMES[3],
+            #[cfg(feature = "ssh")]
+            TransportType::Ssh(_) => BUILTIN_NAMES[4],
+            #[cfg(feature = "use-xtransport")]
+            TransportType::Hybrid(_) => BUILTIN_NAMES[5],
+            TransportType::Custom(name) => name,
+        }
+    }
+
+    /// 连接到 `addr`。内置后端使用 [`Defaults`](crate::Defaults) 中的分片大小、
+    /// 确认模式与发送窗口；串口与继承的 socket 只有一个对端，忽略 `addr`；SSH 隧道使用
+    /// 默认的桥接命令连接宿主机上的 `addr`；hybrid vsock 忽略 CID，连接客户机的 `addr.port`；
+    /// 自定义后端未注册时返回 `ConfigError`
+    pub fn connect(&self, addr: Addr) -> Result<Box<dyn Transport + Send>> {
+        match self {
+            #[cfg(feature = "use-xtransport")]
+            TransportType::XTransport => {
+                let defaults = crate::Defaults::get();
+                let mut handler =
+                    super::XTransportHandler::new().with_send_window(defaults.send_window.get());
+                handler.connect(
+                    addr.cid,
+                    addr.port,
+                    defaults.chunk_size.get() as u32,
+                    defaults.is_ack,
+                )?;
+                Ok(Box::new(handler))
+            }
+            #[cfg(feature = "use-yamux")]
+            TransportType::Yamux => {
+                let defaults = crate::Defaults::get();
+                let mut handler = super::YamuxTransportHandler::new(yamux::Mode::Client);
+                handler.connect(
+                    addr.cid,
+                    addr.port,
+                    defaults.chunk_size.get() as u32,
+                    defaults.is_ack,
+                )?;
+                Ok(Box::new(handler))
+            }
+            #[cfg(feature = "use-xtransport")]
+            TransportType::Serial(path) => Ok(Box::new(super::SerialTransport::open(path)?)),
+            #[cfg(feature = "use-xtransport")]
+            TransportType::Inherited => Ok(Box::new(super::SocketpairTransport::from_env()?)),
+            #[cfg(feature = "ssh")]
+            TransportType::Ssh(destination) => Ok(Box::new(super::SshTransport::connect(
+                &super::SshConfig::new(destination.as_str()),
+                addr,
+            )?)),
+            #[cfg(feature = "use-xtransport")]
+            TransportType::Hybrid(path) => Ok(Box::new(super::SocketpairTransport::from_stream(
+                super::connect_hybrid_vsock(path, addr.port)?,
+                super::default_framing(),
+            )?)),
+            TransportType::Custom(name) => match factory(name) {
+                Some(factory) => factory.connect(addr),
+                None => Err(VirgeError::ConfigError(format!(
+                    "no transport registered as {:?}",
+                    name
+                ))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for TransportType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "use-xtransport")]
+            TransportType::Serial(path) => write!(f, "{}{}", SERIAL_PREFIX, path.display()),
+            #[cfg(feature = "ssh")]
+            TransportType::Ssh(destination) => write!(f, "{}{}", SSH_PREFIX, destination),
+            #[cfg(feature = "use-xtransport")]
+            TransportType::Hybrid(path) => write!(f, "{}{}", HYBRID_PREFIX, path.display()),
+            _ => f.write_str(self.name()),
+        }
+    }
+}
+
+/// 内置后端的名称解析为对应的变体，`serial:<路径>` 解析为 `Serial`，`ssh:<登录目标>`
+/// 解析为 `Ssh`，`hybrid:<路径>` 解析为 `Hybrid`，其余名称解析为 `Custom`
+impl FromStr for TransportType {
+    type Err = std::convert::Infallible;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        #[cfg(feature = "use-xtransport")]
+        if let Some(path) = name.strip_prefix(SERIAL_PREFIX) {
+            return Ok(TransportType::Serial(PathBuf::from(path)));
+        }
+        #[cfg(feature = "ssh")]
+        if let Some(destination) = name.strip_prefix(SSH_PREFIX) {
+            return Ok(TransportType::Ssh(destination.to_string()));
+        }
+        #[cfg(feature = "use-xtransport")]
+        if let Some(path) = name.strip_prefix(HYBRID_PREFIX) {
+            return Ok(TransportType::Hybrid(PathBuf::from(path)));
+        }
+        Ok(match name {
+            #[cfg(feature = "use-xtransport")]
+            "xtransport" => TransportType::XTransport,
+            #[cfg(feature = "use-yamux")]
+            "yamux" => TransportType::Yamux,
+            #[cfg(feature = "use-xtransport")]
+            "inherited" => TransportType::Inherited,
+            _ => TransportType::Custom(name.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn custom_names_resolve_to_registered_factories() {
+        let name = "registry-test";
+        let refuse: Arc<dyn TransportFactory> = Arc::new(|addr: Addr| {
+            Err(VirgeError::transport(
+                ErrorKind::ConnectionRefused,
+                format!("refusing {}", addr),
+            ))
+        });
+        let custom: TransportType = name.parse().unwrap();
+        assert_eq!(custom, TransportType::Custom(name.to_string()));
+        assert!(matches!(
+            custom.connect(Addr::new(3, 1234)),
+            Err(VirgeError::ConfigError(_))
+        ));
+
+        register_transport(name, refuse.clone()).unwrap();
+        assert!(matches!(
+            register_transport(name, refuse.clone()),
+            Err(VirgeError::ConfigError(_))
+        ));
+        let err = custom.connect(Addr::new(3, 1234)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(err.to_string().contains("refusing"));
+
+        assert!(unregister_transport(name));
+        assert!(!unregister_transport(name));
+    }
+
+    #[test]
+    fn builtin_names_are_reserved() {
+        let factory: Arc<dyn TransportFactory> =
+            Arc::new(|_: Addr| Err(VirgeError::Other("unused".to_string())));
+        for name in [
+            "",
+            "xtransport",
+            "yamux",
+            "serial",
+            "serial:/dev/hvc0",
+            "inherited",
+            "ssh",
+            "ssh:dev@host",
+            "hybrid",
+            "hybrid:/run/vm.sock",
+        ] {
+            assert!(register_transport(name, factory.clone()).is_err());
+        }
+        #[cfg(feature = "use-xtransport")]
+        assert_eq!(
+            "xtransport".parse::<TransportType>().unwrap(),
+            TransportType::XTransport
+        );
+        #[cfg(feature = "use-xtransport")]
+        {
+            let serial: TransportType = "serial:/dev/vport0p1".parse().unwrap();
+            assert_eq!(serial, TransportType::Serial("/dev/vport0p1".into()));
+            assert_eq!(serial.to_string(), "serial:/dev/vport0p1");
+            assert_eq!(serial.name(), "serial");
+            let inherited: TransportType = "inherited".parse().unwrap();
+            assert_eq!(inherited, TransportType::Inherited);
+            assert_eq!(inherited.to_string(), "inherited");
+            let hybrid: TransportType = "hybrid:/run/vm.sock".parse().unwrap();
+            assert_eq!(hybrid, TransportType::Hybrid("/run/vm.sock".into()));
+            assert_eq!(hybrid.to_string(), "hybrid:/run/vm.sock");
+            assert_eq!(hybrid.name(), "hybrid");
+        }
+        #[cfg(feature = "ssh")]
+        {
+            let ssh: TransportType = "ssh:dev@host".parse().unwrap();
+            assert_eq!(ssh, TransportType::Ssh("dev@host".to_string()));
+            assert_eq!(ssh.to_string(), "ssh:dev@host");
+            assert_eq!(ssh.name(), "ssh");
+        }
+        #[cfg(feature = "use-yamux")]
+        assert_eq!(
+            "yamux".parse::<TransportType>().unwrap(),
+            TransportType::Yamux
+        );
+    }
+}
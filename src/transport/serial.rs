@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! virtio-serial 与串口上的传输
+//!
+//! 部分 hypervisor 不提供 vsock，只提供 virtio-serial（客户机内的 `/dev/vport0p1`、
+//! `/dev/virtio-ports/<name>`）或模拟串口（`/dev/ttyS0`、`/dev/hvc0`）。[`SerialTransport`]
+//! 在这类字符设备上使用与 vsock 相同的 XTransport 分帧，实现 [`Transport`]，可经
+//! `TransportType::Serial(path)`（配置中写作 `serial:<path>`）选用。
+//!
+//! 字符设备没有连接的概念：一个设备只对应一个对端，地址被忽略；对端关闭设备后本端的
+//! 接收只在设备报告 EOF 或挂断时返回错误。终端设备打开时切换到原始模式，波特率保持不变。
+//! 不发起分帧版本协商，双方使用 v1 分帧。
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use log::*;
+
+use super::idle::IdleTimeout;
+use super::xtransport::{TransportConfig, XTransport};
+use super::Transport;
+use crate::error::{Result, VirgeError};
+
+/// 字符设备上的一条消息通道
+pub struct SerialTransport {
+    transport: Option<XTransport<SerialPort>>,
+    idle_timeout: IdleTimeout,
+}
+
+impl SerialTransport {
+    /// 打开字符设备，分片大小与确认模式取 [`Defaults`](crate::Defaults)
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(path, super::default_framing())
+    }
+
+    /// 以指定的分帧配置打开字符设备；对端须使用相同的分片大小与确认模式
+    pub fn open_with(path: impl AsRef<Path>, config: TransportConfig) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(path)
+            .map_err(|e| {
+                VirgeError::connection_io(format!("Failed to open {}", path.display()), e)
+            })?;
+        info!("Serial transport opened {}", path.display());
+        Self::from_file(file, config)
+    }
+
+    /// 使用已打开的字符设备（或其他可读写的文件描述符）
+    pub fn from_file(file: File, config: TransportConfig) -> Result<Self> {
+        raw_mode(&file)?;
+        let idle_timeout = IdleTimeout::default();
+        let port = SerialPort {
+            file,
+            idle_timeout: idle_timeout.clone(),
+        };
+        Ok(Self {
+            transport: Some(XTransport::new(port, config)),
+            idle_timeout,
+        })
+    }
+
+    fn transport(&mut self) -> Result<&mut XTransport<SerialPort>> {
+        self.transport
+            .as_mut()
+            .ok_or_else(|| VirgeError::transport(io::ErrorKind::NotConnected, "serial port closed"))
+    }
+}
+
+impl Transport for SerialTransport {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.transport()?.send_message(data)?;
+        Ok(data.len())
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>> {
+        Ok(self.transport()?.recv_message()?)
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.idle_timeout.set(timeout)
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        if self.transport.take().is_some() {
+            debug!("Serial transport closed");
+        }
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.transport.is_some()
+    }
+}
+
+/// 字符设备，读取前按空闲超时等待数据
+struct SerialPort {
+    file: File,
+    idle_timeout: IdleTimeout,
+}
+
+impl Read for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.idle_timeout.wait(self.file.as_fd())?;
+        self.file.read(buf)
+    }
+}
+
+impl Write for SerialPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// 终端设备切换到原始模式，避免行规程改写或缓冲分帧数据；其他设备不变
+fn raw_mode(file: &File) -> Result<()> {
+    let fd = file.as_raw_fd();
+    // SAFETY: fd 在 file 存活期间有效
+    if unsafe { libc::isatty(fd) } != 1 {
+        return Ok(());
+    }
+    // SAFETY: termios 是纯数据结构，由 tcgetattr 填充后再修改
+    unsafe {
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(fd, &mut termios) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(fd, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::conformance;
+    use std::os::fd::OwnedFd;
+    use std::os::unix::net::UnixStream;
+
+    /// 以 socketpair 代替 virtio-serial 端口的两端
+    fn pair() -> Result<(SerialTransport, SerialTransport)> {
+        let (a, b) = UnixStream::pair()?;
+        let port = |stream: UnixStream| {
+            let config = TransportConfig::default().with_max_frame_size(4096);
+            SerialTransport::from_file(File::from(OwnedFd::from(stream)), config)
+        };
+        Ok((port(a)?, port(b)?))
+    }
+
+    #[test]
+    fn serial_transport_conforms() {
+        conformance::assert_conforms(pair);
+    }
+
+    #[test]
+    fn open_missing_device_fails() {
+        let err = SerialTransport::open("/nonexistent/vport0p1")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn closed_port_refuses_io_and_zero_timeout() {
+        let (mut a, _b) = pair().unwrap();
+        assert!(a.set_idle_timeout(Some(Duration::ZERO)).is_err());
+        a.disconnect().unwrap();
+        assert!(!a.is_connected());
+        assert_eq!(
+            a.send(b"late").unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+    }
+}
@@ -0,0 +1,210 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 与本机子进程之间的传输
+//!
+//! 父进程用 [`SocketpairTransport::spawn()`] 启动（通常已沙箱化的）子进程：两者之间建立一对
+//! Unix socket，子进程继承其中一端，描述符号经环境变量 [`FD_ENV`] 传递；子进程用
+//! [`SocketpairTransport::from_env()`]（或 `TransportType::Inherited`，配置中写作
+//! `inherited`）取得另一端。两端使用与 vsock 相同的 XTransport 分帧，实现 [`Transport`]，
+//! 与虚拟机客户机通信的代码无需改动即可用于本机子进程。
+
+use std::io::{self, ErrorKind};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+use log::*;
+
+use super::xtransport::{TransportConfig, XTransport};
+use super::Transport;
+use crate::error::{Result, VirgeError};
+
+/// 子进程中保存继承描述符号的环境变量
+pub const FD_ENV: &str = "VIRGA_FD";
+
+/// socketpair 一端上的消息通道
+pub struct SocketpairTransport {
+    transport: Option<XTransport<UnixStream>>,
+    /// 与 transport 共享同一 socket，用于设置超时与关闭
+    socket: UnixStream,
+}
+
+impl SocketpairTransport {
+    /// 启动子进程并返回与它相连的一端。子进程继承另一端，描述符号写入环境变量
+    /// [`FD_ENV`]；父进程中的那一端随即关闭，子进程退出后本端的接收返回连接关闭。
+    /// `command` 只应用于一次 `spawn()`
+    pub fn spawn(command: &mut Command) -> Result<(Child, Self)> {
+        let (parent, child) = UnixStream::pair()?;
+        let fd = child.as_raw_fd();
+        command.env(FD_ENV, fd.to_string());
+        // SAFETY: 闭包在 fork 之后、exec 之前运行，只调用 async-signal-safe 的 fcntl
+        unsafe {
+            command.pre_exec(move || set_cloexec(fd, false));
+        }
+        let spawned = command
+            .spawn()
+            .map_err(|e| VirgeError::connection_io("Failed to spawn child process", e))?;
+        drop(child);
+        info!(
+            "Spawned child process {} with socketpair fd {}",
+            spawned.id(),
+            fd
+        );
+        Ok((
+            spawned,
+            Self::from_stream(parent, super::default_framing())?,
+        ))
+    }
+
+    /// 在子进程中取得父进程经 `spawn()` 传下的一端；未由 `spawn()` 启动、描述符无效或
+    /// 不是 socket 时返回 `ConfigError`。取得后该描述符在再次 exec 时关闭
+    pub fn from_env() -> Result<Self> {
+        let value = std::env::var(FD_ENV)
+            .map_err(|_| VirgeError::ConfigError(format!("{} is not set", FD_ENV)))?;
+        let fd: RawFd =
+            value.parse().ok().filter(|fd| *fd >= 0).ok_or_else(|| {
+                VirgeError::ConfigError(format!("invalid {}={:?}", FD_ENV, value))
+            })?;
+        if !is_socket(fd) {
+            return Err(VirgeError::ConfigError(format!(
+                "inherited fd {} is not a socket",
+                fd
+            )));
+        }
+        set_cloexec(fd, true)?;
+        // SAFETY: fd 是本进程中打开的 socket，由父进程专门传给本进程，只在此处取得所有权
+        let socket = UnixStream::from(unsafe { OwnedFd::from_raw_fd(fd) });
+        Self::from_stream(socket, super::default_framing())
+    }
+
+    /// 使用已建立的 Unix socket 连接；两端的分片大小与确认模式须一致
+    pub fn from_stream(socket: UnixStream, config: TransportConfig) -> Result<Self> {
+        let transport = XTransport::new(socket.try_clone()?, config);
+        Ok(Self {
+            transport: Some(transport),
+            socket,
+        })
+    }
+
+    fn transport(&mut self) -> Result<&mut XTransport<UnixStream>> {
+        self.transport
+            .as_mut()
+            .ok_or_else(|| VirgeError::transport(ErrorKind::NotConnected, "socketpair closed"))
+    }
+}
+
+impl Transport for SocketpairTransport {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        self.transport()?.send_message(data)?;
+        Ok(data.len())
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>> {
+        Ok(self.transport()?.recv_message()?)
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.socket.set_read_timeout(timeout)?)
+    }
+
+    fn disconnect(&mut self) -> Result<()> {
+        if self.transport.take().is_some() {
+            // 对端可能已关闭
+            if let Err(e) = self.socket.shutdown(std::net::Shutdown::Both) {
+                debug!("Socketpair shutdown failed: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.transport.is_some()
+    }
+}
+
+fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    // SAFETY: fcntl 只读写描述符标志，fd 无效时返回错误
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = if cloexec {
+        flags | libc::FD_CLOEXEC
+    } else {
+        flags & !libc::FD_CLOEXEC
+    };
+    // SAFETY: 同上
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn is_socket(fd: RawFd) -> bool {
+    // SAFETY: stat 是纯数据结构，由 fstat 填充；fd 无效时 fstat 返回错误
+    unsafe {
+        let mut stat: libc::stat = std::mem::zeroed();
+        libc::fstat(fd, &mut stat) == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFSOCK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::conformance;
+    use std::process::Stdio;
+
+    /// 子进程中运行的回显，由 `spawned_child_echoes_over_inherited_fd` 启动；
+    /// 直接运行测试时没有继承的描述符，什么也不做
+    #[test]
+    fn child_echo() {
+        if std::env::var_os(FD_ENV).is_none() {
+            return;
+        }
+        let mut parent = SocketpairTransport::from_env().unwrap();
+        while let Ok(message) = parent.recv() {
+            parent.send(&message).unwrap();
+        }
+    }
+
+    #[test]
+    fn spawned_child_echoes_over_inherited_fd() {
+        let mut command = Command::new(std::env::current_exe().unwrap());
+        command
+            .args(["--exact", "transport::socketpair::tests::child_echo"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let (mut child, mut transport) = SocketpairTransport::spawn(&mut command).unwrap();
+        for message in [&b"hello child"[..], &[7u8; 100_000]] {
+            transport.send(message).unwrap();
+            assert_eq!(transport.recv().unwrap(), message);
+        }
+        transport.disconnect().unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn socketpair_transport_conforms() {
+        conformance::assert_conforms(|| {
+            let (a, b) = UnixStream::pair()?;
+            let end = |socket| {
+                let config = TransportConfig::default().with_max_frame_size(4096);
+                SocketpairTransport::from_stream(socket, config)
+            };
+            Ok((end(a)?, end(b)?))
+        });
+    }
+
+    #[test]
+    fn only_inherited_sockets_are_accepted() {
+        let file = std::fs::File::open("/dev/null").unwrap();
+        assert!(!is_socket(file.as_raw_fd()));
+        let (a, _b) = UnixStream::pair().unwrap();
+        assert!(is_socket(a.as_raw_fd()));
+        assert!(!is_socket(-1));
+    }
+}
@@ -0,0 +1,403 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 经 SSH 隧道连接远程宿主机上的客户机
+//!
+//! 开发者在工作站上调试远程宿主机中的客户机服务时，[`SshTransport`] 启动系统的 `ssh`
+//! 客户端登录宿主机，在那里运行一个桥接命令（默认 `socat - VSOCK-CONNECT:{cid}:{port}`）
+//! 把标准输入输出转接到客户机的 vsock 端口。本端在 `ssh` 的标准输入输出上使用与 vsock
+//! 相同的 XTransport 分帧，客户机上的服务端看到的是一个普通的 vsock 客户端。
+//!
+//! 登录沿用用户的 SSH 配置（`~/.ssh/config`、ssh-agent），以 `BatchMode=yes` 运行，
+//! 需要输入口令时直接失败而不是等待终端输入。`ssh` 或桥接命令的错误输出记入日志，
+//! 隧道关闭时附在返回的错误中。可经 `TransportType::Ssh(destination)`（配置中写作
+//! `ssh:<destination>`）选用。
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use log::*;
+
+use super::idle::IdleTimeout;
+use super::xtransport::{TransportConfig, XTransport};
+use super::Transport;
+use crate::addr::Addr;
+use crate::error::{Result, VirgeError};
+
+/// 默认的桥接命令，`{cid}` 与 `{port}` 替换为目标地址
+pub const DEFAULT_BRIDGE: &str = "socat - VSOCK-CONNECT:{cid}:{port}";
+
+/// 断开时等待 `ssh` 自行退出的时间，超过后结束它
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// SSH 隧道的登录目标与桥接命令
+#[derive(Clone, Debug)]
+pub struct SshConfig {
+    destination: String,
+    program: PathBuf,
+    options: Vec<String>,
+    bridge: String,
+}
+
+impl SshConfig {
+    /// `destination` 为 `ssh` 的登录目标，如 `dev@host` 或 `~/.ssh/config` 中的主机别名
+    pub fn new(destination: impl Into<String>) -> Self {
+        Self {
+            destination: destination.into(),
+            program: PathBuf::from("ssh"),
+            options: Vec::new(),
+            bridge: DEFAULT_BRIDGE.to_string(),
+        }
+    }
+
+    /// 使用指定的 `ssh` 可执行文件，默认在 `PATH` 中查找 `ssh`
+    pub fn with_program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    /// 追加传给 `ssh` 的参数，如 `-p 2222` 或 `-J jump-host`；先于内置选项传入，
+    /// 以 `-o` 给出的同名选项会覆盖 `BatchMode=yes`
+    pub fn with_option(mut self, option: impl Into<String>) -> Self {
+        self.options.push(option.into());
+        self
+    }
+
+    /// 在宿主机上运行的桥接命令，由远程 shell 解释，`{cid}` 与 `{port}` 替换为目标地址
+    pub fn with_bridge(mut self, bridge: impl Into<String>) -> Self {
+        self.bridge = bridge.into();
+        self
+    }
+
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    fn command(&self, addr: Addr) -> Command {
+        let bridge = self
+            .bridge
+            .replace("{cid}", &addr.cid.to_string())
+            .replace("{port}", &addr.port.to_string());
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.options)
+            .args([
+                "-T",
+                "-o",
+                "BatchMode=yes",
+                "--",
+                &self.destination,
+                &bridge,
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        command
+    }
+}
+
+/// 经 SSH 隧道到远程客户机的一条消息通道
+pub struct SshTransport {
+    transport: Option<XTransport<Tunnel>>,
+    idle_timeout: IdleTimeout,
+    child: Child,
+    destination: String,
+    /// `ssh` 退出时收到其最后几行错误输出
+    stderr: Receiver<String>,
+    /// 隧道关闭的原因，取自错误输出
+    reason: Option<String>,
+}
+
+impl SshTransport {
+    /// 登录 `config` 中的宿主机并连接客户机的 `addr`，分片大小与确认模式取
+    /// [`Defaults`](crate::Defaults)
+    pub fn connect(config: &SshConfig, addr: Addr) -> Result<Self> {
+        Self::connect_with(config, addr, super::default_framing())
+    }
+
+    /// 以指定的分帧配置建立隧道；客户机上的服务端须使用相同的分片大小与确认模式。
+    /// 登录或桥接失败在建立时或第一次收发时以连接关闭的错误返回
+    pub fn connect_with(config: &SshConfig, addr: Addr, framing: TransportConfig) -> Result<Self> {
+        let mut child = config
+            .command(addr)
+            .spawn()
+            .map_err(|e| VirgeError::connection_io("Failed to spawn ssh", e))?;
+        let (Some(stdin), Some(stdout), Some(stderr)) =
+            (child.stdin.take(), child.stdout.take(), child.stderr.take())
+        else {
+            unreachable!("ssh stdio is piped");
+        };
+        let stderr = drain_stderr(config.destination.clone(), stderr)?;
+        info!(
+            "SSH tunnel to {} (pid {}) bridging {}",
+            config.destination,
+            child.id(),
+            addr
+        );
+
+        let idle_timeout = IdleTimeout::default();
+        let tunnel = Tunnel {
+            stdin,
+            stdout,
+            idle_timeout: idle_timeout.clone(),
+        };
+        let mut ssh = Self {
+            transport: Some(XTransport::new(tunnel, framing)),
+            idle_timeout,
+            child,
+            destination: config.destination.clone(),
+            stderr,
+            reason: None,
+        };
+        // 失败时 ssh 被丢弃，子进程随之回收
+        if let Err(e) = ssh.transport()?.offer_version() {
+            let err = VirgeError::xtransport("Failed to offer framing version", e);
+            return Err(ssh.closed(err));
+        }
+        Ok(ssh)
+    }
+
+    fn transport(&mut self) -> Result<&mut XTransport<Tunnel>> {
+        self.transport
+            .as_mut()
+            .ok_or_else(|| VirgeError::transport(io::ErrorKind::NotConnected, "ssh tunnel closed"))
+    }
+
+    /// 隧道关闭时以 `ssh` 的错误输出说明原因，错误类型不变
+    fn closed(&mut self, err: VirgeError) -> VirgeError {
+        let kind = err.kind();
+        if !matches!(
+            kind,
+            io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::BrokenPipe
+        ) {
+            return err;
+        }
+        if self.reason.is_none() {
+            self.reason = self
+                .stderr
+                .recv_timeout(EXIT_GRACE)
+                .ok()
+                .filter(|tail| !tail.is_empty());
+        }
+        match &self.reason {
+            Some(reason) => VirgeError::transport(
+                kind,
+                format!("ssh tunnel to {} closed: {}", self.destination, reason),
+            ),
+            None => err,
+        }
+    }
+}
+
+impl Transport for SshTransport {
+    fn send(&mut self, data: &[u8]) -> Result<usize> {
+        match self.transport()?.send_message(data) {
+            Ok(()) => Ok(data.len()),
+            Err(e) => Err(self.closed(e.into())),
+        }
+    }
+
+    fn recv(&mut self) -> Result<Vec<u8>> {
+        match self.transport()?.recv_message() {
+            Ok(message) => Ok(message),
+            Err(e) => Err(self.closed(e.into())),
+        }
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.idle_timeout.set(timeout)
+    }
+
+    /// 关闭 `ssh` 的标准输入输出，桥接命令随之结束；`ssh` 未及时退出时结束它
+    fn disconnect(&mut self) -> Result<()> {
+        if self.transport.take().is_none() {
+            return Ok(());
+        }
+        let deadline = Instant::now() + EXIT_GRACE;
+        while self.child.try_wait()?.is_none() {
+            if Instant::now() >= deadline {
+                debug!("ssh to {} did not exit, killing it", self.destination);
+                // 可能恰好已退出
+                let _ = self.child.kill();
+                self.child.wait()?;
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        debug!("SSH tunnel to {} closed", self.destination);
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.transport.is_some()
+    }
+}
+
+impl Drop for SshTransport {
+    fn drop(&mut self) {
+        if let Err(e) = self.disconnect() {
+            warn!("Failed to close ssh tunnel to {}: {}", self.destination, e);
+        }
+    }
+}
+
+/// `ssh` 的标准输出与标准输入，读取前按空闲超时等待数据
+struct Tunnel {
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    idle_timeout: IdleTimeout,
+}
+
+impl Read for Tunnel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.idle_timeout.wait(self.stdout.as_fd())?;
+        self.stdout.read(buf)
+    }
+}
+
+impl Write for Tunnel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdin.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.flush()
+    }
+}
+
+/// 错误输出保留的行数
+const STDERR_TAIL_LINES: usize = 4;
+
+/// 逐行记录 `ssh` 的错误输出，管道关闭时送出最后几行
+fn drain_stderr(destination: String, stderr: ChildStderr) -> Result<Receiver<String>> {
+    let (tx, rx) = mpsc::channel();
+    crate::threads::spawn("ssh-stderr", move || {
+        let mut tail = Vec::with_capacity(STDERR_TAIL_LINES);
+        for line in BufReader::new(stderr).lines() {
+            let Ok(line) = line else { break };
+            let line = line.trim().to_string();
+            if line.is_empty() {
+                continue;
+            }
+            warn!("ssh {}: {}", destination, line);
+            if tail.len() == STDERR_TAIL_LINES {
+                tail.remove(0);
+            }
+            tail.push(line);
+        }
+        let _ = tx.send(tail.join("; "));
+    })?;
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// 代替 `ssh` 的脚本：跳过选项与登录目标，在本机运行桥接命令
+    fn fake_ssh() -> PathBuf {
+        let path = std::env::temp_dir().join(format!("virga-fake-ssh-{}", std::process::id()));
+        if !path.exists() {
+            let script = "#!/bin/sh\nwhile [ \"$1\" != \"--\" ]; do shift; done\nshift 2\nexec sh -c \"$1\"\n";
+            let staging = path.with_extension(format!("{:?}", std::thread::current().id()));
+            std::fs::write(&staging, script).unwrap();
+            std::fs::set_permissions(&staging, std::fs::Permissions::from_mode(0o755)).unwrap();
+            std::fs::rename(&staging, &path).unwrap();
+        }
+        path
+    }
+
+    fn framing() -> TransportConfig {
+        TransportConfig::default().with_max_frame_size(4096)
+    }
+
+    #[test]
+    fn bridge_placeholders_and_options_reach_ssh() {
+        let config = SshConfig::new("dev@host")
+            .with_option("-p")
+            .with_option("2222");
+        let command = config.command(Addr::new(3, 1234));
+        let args: Vec<_> = command.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(command.get_program(), "ssh");
+        assert_eq!(
+            args,
+            [
+                "-p",
+                "2222",
+                "-T",
+                "-o",
+                "BatchMode=yes",
+                "--",
+                "dev@host",
+                "socat - VSOCK-CONNECT:3:1234"
+            ]
+        );
+    }
+
+    #[test]
+    fn messages_cross_the_tunnel() {
+        // 桥接命令原样回显，本端收到自己发出的消息
+        let config = SshConfig::new("guest-host")
+            .with_program(fake_ssh())
+            .with_bridge("cat");
+        let mut tunnel =
+            SshTransport::connect_with(&config, Addr::new(3, 1234), framing()).unwrap();
+        for message in [&b"over ssh"[..], &[9u8; 100_000]] {
+            tunnel.send(message).unwrap();
+            assert_eq!(tunnel.recv().unwrap(), message);
+        }
+
+        tunnel
+            .set_idle_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        assert_eq!(tunnel.recv().unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        tunnel.disconnect().unwrap();
+        assert!(!tunnel.is_connected());
+        assert_eq!(
+            tunnel.send(b"late").unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+    }
+
+    #[test]
+    fn bridge_failure_is_reported_on_receive() {
+        let config = SshConfig::new("guest-host")
+            .with_program(fake_ssh())
+            .with_bridge("echo 'VSOCK-CONNECT:{cid}:{port}: Connection refused' >&2; exit 1");
+        // 桥接命令可能在提议分帧版本之前就已退出
+        let err = match SshTransport::connect_with(&config, Addr::new(3, 1234), framing()) {
+            Ok(mut tunnel) => tunnel.recv().unwrap_err(),
+            Err(e) => e,
+        };
+        assert!(matches!(
+            err.kind(),
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::BrokenPipe
+        ));
+        let message = err.to_string();
+        assert!(message.contains("guest-host"), "{}", message);
+        assert!(
+            message.contains("3:1234: Connection refused"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn missing_ssh_fails_to_connect() {
+        let config = SshConfig::new("guest-host").with_program("/nonexistent/ssh");
+        let err = SshTransport::connect(&config, Addr::new(3, 1234))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
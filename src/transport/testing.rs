@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 测试用的传输：以一对 mpsc 通道代替连接，按消息收发
+
+use std::io::{Error, ErrorKind};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use super::Transport;
+
+/// 一端连接。对端被丢弃后发送返回 `BrokenPipe`、接收返回 `UnexpectedEof`；
+/// 设置了空闲超时时接收超时返回 `TimedOut`
+pub(crate) struct Pipe {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl Transport for Pipe {
+    fn send(&mut self, data: &[u8]) -> crate::Result<usize> {
+        self.tx
+            .send(data.to_vec())
+            .map_err(|_| Error::from(ErrorKind::BrokenPipe))?;
+        Ok(data.len())
+    }
+
+    fn recv(&mut self) -> crate::Result<Vec<u8>> {
+        let received = match self.timeout {
+            Some(timeout) => self.rx.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => ErrorKind::TimedOut,
+                RecvTimeoutError::Disconnected => ErrorKind::UnexpectedEof,
+            }),
+            None => self.rx.recv().map_err(|_| ErrorKind::UnexpectedEof),
+        };
+        Ok(received.map_err(Error::from)?)
+    }
+
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    fn is_connected(&self) -> bool {
+        true
+    }
+}
+
+/// 相互连通的两端
+pub(crate) fn pipe_pair() -> (Pipe, Pipe) {
+    let (a_tx, b_rx) = channel();
+    let (b_tx, a_rx) = channel();
+    let pipe = |tx, rx| Pipe {
+        tx,
+        rx,
+        timeout: None,
+    };
+    (pipe(a_tx, a_rx), pipe(b_tx, b_rx))
+}
@@ -0,0 +1,174 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+use std::time::Duration;
+
+// Measurements below this size are too noisy to steer the chunk size
+const MIN_SAMPLE_BYTES: usize = 16 * 1024;
+// Throughput must drop by more than this fraction before the search reverses
+const THROUGHPUT_TOLERANCE: f64 = 0.05;
+const DEFAULT_ACK_LATENCY_LIMIT: Duration = Duration::from_millis(50);
+
+/// Hill-climbing payload size controller.
+///
+/// Every large transfer is one throughput sample: while throughput keeps up the
+/// payload size keeps moving in the same direction (doubling or halving), and a
+/// drop reverses the direction. A slow ACK always halves the size.
+#[derive(Debug, Clone)]
+pub struct ChunkAdapter {
+    min: usize,
+    max: usize,
+    current: usize,
+    growing: bool,
+    last_throughput: Option<f64>,
+    ack_latency_limit: Duration,
+}
+
+impl ChunkAdapter {
+    pub fn new(initial: usize, min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        let max = max.max(min);
+        ChunkAdapter {
+            min,
+            max,
+            current: initial.clamp(min, max),
+            growing: true,
+            last_throughput: None,
+            ack_latency_limit: DEFAULT_ACK_LATENCY_LIMIT,
+        }
+    }
+
+    pub fn with_ack_latency_limit(mut self, limit: Duration) -> Self {
+        self.ack_latency_limit = limit;
+        self
+    }
+
+    /// Current payload size in bytes
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Feed the outcome of one multi-packet send
+    pub fn record_transfer(&mut self, bytes: usize, elapsed: Duration) {
+        if bytes < MIN_SAMPLE_BYTES || elapsed.is_zero() {
+            return;
+        }
+
+        let throughput = bytes as f64 / elapsed.as_secs_f64();
+        if let Some(last) = self.last_throughput {
+            if throughput < last * (1.0 - THROUGHPUT_TOLERANCE) {
+                self.growing = !self.growing;
+            }
+        }
+        self.last_throughput = Some(throughput);
+        self.step();
+    }
+
+    /// Feed the round-trip time of one acknowledged packet
+    pub fn record_ack_latency(&mut self, latency: Duration) {
+        if latency > self.ack_latency_limit && self.current > self.min {
+            self.growing = false;
+            self.current = (self.current / 2).max(self.min);
+            // Old samples were taken at a different size
+            self.last_throughput = None;
+            log::debug!(
+                "ACK latency {:?} over limit, payload size -> {}",
+                latency,
+                self.current
+            );
+        }
+    }
+
+    fn step(&mut self) {
+        let next = if self.growing {
+            self.current.saturating_mul(2).min(self.max)
+        } else {
+            (self.current / 2).max(self.min)
+        };
+
+        // Bounce off the limits instead of sticking to them
+        if next == self.current {
+            self.growing = !self.growing;
+        } else {
+            log::debug!("Adaptive payload size {} -> {}", self.current, next);
+            self.current = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: usize = 1024 * 1024;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn new_clamps_initial() {
+        assert_eq!(ChunkAdapter::new(10, 512, 4096).current(), 512);
+        assert_eq!(ChunkAdapter::new(100_000, 512, 4096).current(), 4096);
+        assert_eq!(ChunkAdapter::new(1024, 512, 4096).current(), 1024);
+    }
+
+    #[test]
+    fn new_fixes_inverted_range() {
+        let adapter = ChunkAdapter::new(1024, 2048, 1024);
+        assert_eq!(adapter.current(), 2048);
+    }
+
+    #[test]
+    fn grows_while_throughput_improves() {
+        let mut adapter = ChunkAdapter::new(1024, 512, 65535);
+        adapter.record_transfer(SAMPLE, ms(100));
+        assert_eq!(adapter.current(), 2048);
+        adapter.record_transfer(SAMPLE, ms(80));
+        assert_eq!(adapter.current(), 4096);
+    }
+
+    #[test]
+    fn reverses_when_throughput_drops() {
+        let mut adapter = ChunkAdapter::new(1024, 512, 65535);
+        adapter.record_transfer(SAMPLE, ms(100));
+        assert_eq!(adapter.current(), 2048);
+        adapter.record_transfer(SAMPLE, ms(200));
+        assert_eq!(adapter.current(), 1024);
+    }
+
+    #[test]
+    fn small_samples_ignored() {
+        let mut adapter = ChunkAdapter::new(1024, 512, 65535);
+        adapter.record_transfer(1000, ms(1));
+        assert_eq!(adapter.current(), 1024);
+        adapter.record_transfer(SAMPLE, Duration::ZERO);
+        assert_eq!(adapter.current(), 1024);
+    }
+
+    #[test]
+    fn bounces_off_max() {
+        let mut adapter = ChunkAdapter::new(4096, 512, 4096);
+        adapter.record_transfer(SAMPLE, ms(100));
+        assert_eq!(adapter.current(), 4096);
+        adapter.record_transfer(SAMPLE, ms(100));
+        assert_eq!(adapter.current(), 2048);
+    }
+
+    #[test]
+    fn slow_ack_halves_size() {
+        let mut adapter = ChunkAdapter::new(4096, 512, 65535).with_ack_latency_limit(ms(10));
+        adapter.record_ack_latency(ms(5));
+        assert_eq!(adapter.current(), 4096);
+        adapter.record_ack_latency(ms(20));
+        assert_eq!(adapter.current(), 2048);
+    }
+
+    #[test]
+    fn slow_ack_respects_min() {
+        let mut adapter = ChunkAdapter::new(512, 512, 65535).with_ack_latency_limit(ms(1));
+        adapter.record_ack_latency(ms(100));
+        assert_eq!(adapter.current(), 512);
+    }
+}
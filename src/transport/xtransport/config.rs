@@ -2,16 +2,41 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
-// Protocol constants
-pub const MAGIC: u32 = 0x58545250; // "XTRP"
-pub const VERSION: u8 = 0x01;
-pub const HEADER_SIZE: usize = 16;
-pub const MESSAGE_HEAD_SIZE: usize = 32;
+use std::path::PathBuf;
+
+// Protocol constants, shared with no_std guests through virga-core
+pub use virga_core::frame::{
+    HEADER_SIZE, LATEST_VERSION, MAGIC, MAX_FRAME_SIZE, MESSAGE_HEAD_SIZE, SHM_SEGMENT_SIZE,
+    VERSION,
+};
+pub const MIN_ADAPTIVE_FRAME_SIZE: usize = 512;
 const DEFAULT_MAX_FRAME_SIZE: usize = 4096; // 4KB
+const DEFAULT_SEND_WINDOW: usize = 16;
+
+/// Shared-memory region used for the payload fast path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShmConfig {
+    /// File backing the region (a /dev/shm file, or the ivshmem BAR inside a guest)
+    pub path: PathBuf,
+    /// Region size the offering side creates; the accepting side maps the file as is
+    pub size: usize,
+}
 
 pub struct TransportConfig {
     pub max_payload_size: usize,
     pub wait_for_ack: bool,
+    /// Max packets in flight (unacked, or batched per write without ACK)
+    pub send_window: usize,
+    /// Adapt the payload size to observed throughput and ACK latency
+    pub adaptive_chunk: bool,
+    /// Accept (and on `offer_shm`, propose) a shared-memory payload path
+    pub shm: Option<ShmConfig>,
+    /// Highest framing version to propose (`offer_version`) or agree to
+    pub max_version: u8,
+    /// Fail on anomalies that are otherwise tolerated: unknown or unsolicited
+    /// controls, control bodies or message headers longer than defined, and
+    /// fragments that don't add up to the announced message length
+    pub strict: bool,
 }
 
 impl TransportConfig {
@@ -19,6 +44,11 @@ impl TransportConfig {
         Self {
             max_payload_size: DEFAULT_MAX_FRAME_SIZE - HEADER_SIZE,
             wait_for_ack: false,
+            send_window: DEFAULT_SEND_WINDOW,
+            adaptive_chunk: false,
+            shm: None,
+            max_version: LATEST_VERSION,
+            strict: false,
         }
     }
 
@@ -31,6 +61,38 @@ impl TransportConfig {
         self.wait_for_ack = wait_for_ack;
         self
     }
+
+    /// A window of 1 degrades to stop-and-wait
+    pub fn with_send_window(mut self, packets: usize) -> Self {
+        self.send_window = packets.max(1);
+        self
+    }
+
+    /// Start at the configured frame size and adapt within
+    /// [`MIN_ADAPTIVE_FRAME_SIZE`, `MAX_FRAME_SIZE`]
+    pub fn with_adaptive_chunk(mut self, enabled: bool) -> Self {
+        self.adaptive_chunk = enabled;
+        self
+    }
+
+    /// Clamped to [`VERSION`, `LATEST_VERSION`]
+    pub fn with_max_version(mut self, version: u8) -> Self {
+        self.max_version = version.clamp(VERSION, LATEST_VERSION);
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_shm(mut self, path: impl Into<PathBuf>, size: usize) -> Self {
+        self.shm = Some(ShmConfig {
+            path: path.into(),
+            size,
+        });
+        self
+    }
 }
 
 impl Default for TransportConfig {
@@ -111,6 +173,49 @@ mod tests {
         assert!(config.wait_for_ack);
     }
 
+    #[test]
+    fn transport_config_default_send_window() {
+        let config = TransportConfig::new();
+        assert_eq!(config.send_window, 16);
+    }
+
+    #[test]
+    fn transport_config_with_send_window() {
+        let config = TransportConfig::new().with_send_window(4);
+        assert_eq!(config.send_window, 4);
+    }
+
+    #[test]
+    fn transport_config_with_send_window_zero_clamped() {
+        let config = TransportConfig::new().with_send_window(0);
+        assert_eq!(config.send_window, 1);
+    }
+
+    #[test]
+    fn transport_config_adaptive_chunk() {
+        assert!(!TransportConfig::new().adaptive_chunk);
+        assert!(
+            TransportConfig::new()
+                .with_adaptive_chunk(true)
+                .adaptive_chunk
+        );
+    }
+
+    #[test]
+    fn transport_config_shm() {
+        assert!(TransportConfig::new().shm.is_none());
+        let config = TransportConfig::new().with_shm("/dev/shm/virga", 1 << 20);
+        let shm = config.shm.unwrap();
+        assert_eq!(shm.path, PathBuf::from("/dev/shm/virga"));
+        assert_eq!(shm.size, 1 << 20);
+    }
+
+    #[test]
+    fn max_frame_size_fits_length_field() {
+        assert_eq!(MAX_FRAME_SIZE - HEADER_SIZE, u16::MAX as usize);
+        assert_eq!(MIN_ADAPTIVE_FRAME_SIZE, 512);
+    }
+
     #[test]
     fn transport_config_with_large_frame_size() {
         let config = TransportConfig::new().with_max_frame_size(1024 * 1024);
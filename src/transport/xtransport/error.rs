@@ -4,60 +4,106 @@
 
 use core::fmt;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum ErrorKind {
-    InvalidMagic,
-    InvalidVersion,
-    CrcMismatch,
-    UnexpectedEof,
-    InvalidPacket,
-    WriteZero,
-    Interrupted,
-    Other,
-}
+pub use virga_core::ErrorKind;
 
 #[derive(Debug)]
 pub struct Error {
     kind: ErrorKind,
+    source: Option<std::io::Error>,
 }
 
 impl Error {
     pub fn new(kind: ErrorKind) -> Self {
-        Self { kind }
+        Self { kind, source: None }
+    }
+
+    /// The peer refused the connection and gave `reason`
+    pub fn rejected(reason: String) -> Self {
+        Self {
+            kind: ErrorKind::Rejected,
+            source: Some(std::io::Error::new(
+                std::io::ErrorKind::ConnectionRefused,
+                reason,
+            )),
+        }
+    }
+
+    /// Reason sent by the peer when it refused the connection
+    pub fn rejection(&self) -> Option<String> {
+        match (self.kind, &self.source) {
+            (ErrorKind::Rejected, Some(reason)) => Some(reason.to_string()),
+            _ => None,
+        }
     }
 
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    /// Underlying IO error, when the failure came from the stream
+    pub fn io_error(&self) -> Option<&std::io::Error> {
+        self.source.as_ref()
+    }
+
+    /// The std kind this error maps to; keeps the stream's own kind
+    /// (ConnectionReset, TimedOut, ...) when there is one
+    pub fn io_kind(&self) -> std::io::ErrorKind {
+        if let Some(source) = &self.source {
+            return source.kind();
+        }
+        match self.kind {
+            ErrorKind::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
+            ErrorKind::WriteZero => std::io::ErrorKind::WriteZero,
+            ErrorKind::Interrupted => std::io::ErrorKind::Interrupted,
+            ErrorKind::TimedOut => std::io::ErrorKind::TimedOut,
+            _ => std::io::ErrorKind::Other,
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let msg = match self.kind {
-            ErrorKind::UnexpectedEof => "Unexpected end of file",
-            ErrorKind::WriteZero => "Write zero bytes",
-            ErrorKind::InvalidMagic => "Invalid magic number",
-            ErrorKind::CrcMismatch => "CRC checksum mismatch",
-            ErrorKind::InvalidPacket => "Invalid packet",
-            ErrorKind::InvalidVersion => "Invalid protocol version",
-            ErrorKind::Interrupted => "Operation interrupted",
-            ErrorKind::Other => "Other error",
-        };
-        f.write_str(msg)
+        write!(f, "{}", self.kind)?;
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
     }
 }
 
-impl std::error::Error for Error {}
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        let kind = match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => ErrorKind::UnexpectedEof,
+            std::io::ErrorKind::WriteZero => ErrorKind::WriteZero,
+            std::io::ErrorKind::Interrupted => ErrorKind::Interrupted,
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => ErrorKind::TimedOut,
+            _ => ErrorKind::Other,
+        };
+        Self {
+            kind,
+            source: Some(err),
+        }
+    }
+}
 
 impl From<Error> for std::io::Error {
     fn from(err: Error) -> std::io::Error {
-        let kind = match err.kind {
-            ErrorKind::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
-            ErrorKind::WriteZero => std::io::ErrorKind::WriteZero,
-            ErrorKind::Interrupted => std::io::ErrorKind::Interrupted,
-            _ => std::io::ErrorKind::Other,
-        };
-        std::io::Error::new(kind, err)
+        std::io::Error::new(err.io_kind(), err)
     }
 }
 
@@ -78,6 +124,8 @@ mod tests {
             ErrorKind::InvalidPacket,
             ErrorKind::WriteZero,
             ErrorKind::Interrupted,
+            ErrorKind::TimedOut,
+            ErrorKind::Rejected,
             ErrorKind::Other,
         ];
         for i in 0..kinds.len() {
@@ -87,6 +135,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn rejected_error_carries_the_reason() {
+        let err = Error::rejected("quota exceeded".into());
+        assert_eq!(err.kind(), ErrorKind::Rejected);
+        assert_eq!(err.io_kind(), std::io::ErrorKind::ConnectionRefused);
+        assert_eq!(err.rejection().as_deref(), Some("quota exceeded"));
+        assert_eq!(format!("{}", err), "Rejected by peer: quota exceeded");
+        assert_eq!(Error::new(ErrorKind::Other).rejection(), None);
+    }
+
     #[test]
     fn error_new_and_kind() {
         let err = Error::new(ErrorKind::CrcMismatch);
@@ -135,6 +193,14 @@ mod tests {
         assert_eq!(format!("{}", err), "Operation interrupted");
     }
 
+    #[test]
+    fn error_display_timed_out() {
+        let err = Error::new(ErrorKind::TimedOut);
+        assert_eq!(format!("{}", err), "Operation timed out");
+        let io_err: std::io::Error = Error::new(ErrorKind::TimedOut).into();
+        assert_eq!(io_err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
     #[test]
     fn error_display_other() {
         let err = Error::new(ErrorKind::Other);
@@ -197,4 +263,26 @@ mod tests {
         let err = Error::new(ErrorKind::Other);
         let _: &dyn std::error::Error = &err;
     }
+
+    #[test]
+    fn error_from_io_keeps_source() {
+        let io_err = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+        let err = Error::from(io_err);
+        assert_eq!(err.kind(), ErrorKind::Other);
+        assert_eq!(err.io_kind(), std::io::ErrorKind::ConnectionReset);
+        assert!(std::error::Error::source(&err).is_some());
+        assert!(format!("{}", err).starts_with("Other error: "));
+
+        let back: std::io::Error = err.into();
+        assert_eq!(back.kind(), std::io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn error_from_io_maps_kind() {
+        let eof = Error::from(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        assert_eq!(eof.kind(), ErrorKind::UnexpectedEof);
+        let timeout = Error::from(std::io::Error::from(std::io::ErrorKind::WouldBlock));
+        assert_eq!(timeout.kind(), ErrorKind::TimedOut);
+        assert_eq!(timeout.io_kind(), std::io::ErrorKind::WouldBlock);
+    }
 }
@@ -48,38 +48,17 @@ pub trait Write {
 // Blanket implementations for std types that implement std::io::{Read, Write}
 impl<T: std::io::Read> Read for T {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        std::io::Read::read(self, buf).map_err(|e| {
-            Error::new(match e.kind() {
-                std::io::ErrorKind::UnexpectedEof => {
-                    crate::transport::xtransport::error::ErrorKind::UnexpectedEof
-                }
-                std::io::ErrorKind::Interrupted => {
-                    crate::transport::xtransport::error::ErrorKind::Interrupted
-                }
-                _ => crate::transport::xtransport::error::ErrorKind::Other,
-            })
-        })
+        std::io::Read::read(self, buf).map_err(Error::from)
     }
 }
 
 impl<T: std::io::Write> Write for T {
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        std::io::Write::write(self, buf).map_err(|e| {
-            Error::new(match e.kind() {
-                std::io::ErrorKind::WriteZero => {
-                    crate::transport::xtransport::error::ErrorKind::WriteZero
-                }
-                std::io::ErrorKind::Interrupted => {
-                    crate::transport::xtransport::error::ErrorKind::Interrupted
-                }
-                _ => crate::transport::xtransport::error::ErrorKind::Other,
-            })
-        })
+        std::io::Write::write(self, buf).map_err(Error::from)
     }
 
     fn flush(&mut self) -> Result<()> {
-        std::io::Write::flush(self)
-            .map_err(|_| Error::new(crate::transport::xtransport::error::ErrorKind::Other))
+        std::io::Write::flush(self).map_err(Error::from)
     }
 }
 
@@ -91,7 +70,7 @@ mod tests {
 
     #[test]
     fn cursor_read_basic() {
-        let data = vec![1, 2, 3, 4, 5];
+        let data = [1, 2, 3, 4, 5];
         let mut cursor = Cursor::new(&data[..]);
         let mut buf = [0u8; 3];
         let n = cursor.read(&mut buf).unwrap();
@@ -101,7 +80,7 @@ mod tests {
 
     #[test]
     fn cursor_read_exact_success() {
-        let data = vec![10, 20, 30, 40, 50];
+        let data = [10, 20, 30, 40, 50];
         let mut cursor = Cursor::new(&data[..]);
         let mut buf = [0u8; 5];
         cursor.read_exact(&mut buf).unwrap();
@@ -110,7 +89,7 @@ mod tests {
 
     #[test]
     fn cursor_read_exact_insufficient_data() {
-        let data = vec![1, 2, 3];
+        let data = [1, 2, 3];
         let mut cursor = Cursor::new(&data[..]);
         let mut buf = [0u8; 5];
         let err = cursor.read_exact(&mut buf).unwrap_err();
@@ -119,7 +98,7 @@ mod tests {
 
     #[test]
     fn cursor_read_exact_empty() {
-        let data = vec![1, 2, 3];
+        let data = [1, 2, 3];
         let mut cursor = Cursor::new(&data[..]);
         let mut buf = [0u8; 0];
         cursor.read_exact(&mut buf).unwrap();
@@ -148,7 +127,7 @@ mod tests {
 
     #[test]
     fn cursor_read_sequential() {
-        let data = vec![1, 2, 3, 4, 5, 6];
+        let data = [1, 2, 3, 4, 5, 6];
         let mut cursor = Cursor::new(&data[..]);
         let mut buf = [0u8; 3];
 
@@ -163,7 +142,7 @@ mod tests {
 
     #[test]
     fn cursor_read_at_eof() {
-        let data = vec![];
+        let data: [u8; 0] = [];
         let mut cursor = Cursor::new(&data[..]);
         let mut buf = [0u8; 5];
         let n = cursor.read(&mut buf).unwrap();
@@ -255,10 +234,7 @@ mod tests {
             Ok(buf.len())
         }
         fn flush(&mut self) -> std::io::Result<()> {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "flush error",
-            ))
+            Err(std::io::Error::other("flush error"))
         }
     }
 
@@ -2,13 +2,21 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
+pub mod adaptive;
 pub mod config;
 pub mod error;
 pub mod io;
 pub mod protocol;
+pub mod shm;
+mod sink;
 pub mod transport;
 
-pub use config::{TransportConfig, HEADER_SIZE, MAGIC, MESSAGE_HEAD_SIZE, VERSION};
+pub use adaptive::ChunkAdapter;
+pub use config::{
+    ShmConfig, TransportConfig, HEADER_SIZE, LATEST_VERSION, MAGIC, MAX_FRAME_SIZE,
+    MESSAGE_HEAD_SIZE, MIN_ADAPTIVE_FRAME_SIZE, VERSION,
+};
 pub use error::{Error, Result};
 pub use io::{Read, Write};
-pub use transport::XTransport;
+pub use shm::{ShmChannel, ShmRegion, MIN_SHM_SIZE};
+pub use transport::{ExtensionHandler, FrameHook, XTransport};
@@ -0,0 +1,419 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Shared-memory payload rings.
+//!
+//! A region holds two single-producer/single-consumer byte rings, one per
+//! direction. Payload bytes are copied into the ring and only a small
+//! [`ShmSegment`](crate::transport::xtransport::protocol::ShmSegment)
+//! descriptor travels over the stream. The consumer publishes how far it has
+//! read so the producer can reuse the space without a round trip.
+//!
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! 0      magic (u64)
+//! 8      nonce (u64), stamped by the creating side
+//! 64     ring 0 write position (u64)   ring 0: creator -> opener
+//! 128    ring 0 read position (u64)
+//! 192    ring 1 write position (u64)   ring 1: opener -> creator
+//! 256    ring 1 read position (u64)
+//! 4096   ring 0 data, then ring 1 data
+//! ```
+
+use crate::transport::xtransport::error::{Error, ErrorKind};
+use crate::transport::xtransport::Result;
+use memmap2::{MmapMut, MmapOptions};
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const REGION_MAGIC: u64 = u64::from_le_bytes(*b"XTRPSHM\0");
+const NONCE_OFFSET: usize = 8;
+// Positions live on separate cache lines so producer and consumer don't share one
+const POSITION_STRIDE: usize = 64;
+const RING_OFFSET: usize = 64;
+const SHM_HEADER_SIZE: usize = 4096;
+const MIN_RING_SIZE: usize = 64 * 1024;
+/// Smallest region that still leaves a useful ring in each direction
+pub const MIN_SHM_SIZE: usize = SHM_HEADER_SIZE + 2 * MIN_RING_SIZE;
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+const SPIN_LIMIT: u32 = 64;
+
+/// A mapped shared-memory region
+pub struct ShmRegion {
+    map: MmapMut,
+}
+
+impl ShmRegion {
+    /// Create (or grow) the backing file, reset both rings and stamp a fresh nonce
+    pub fn create(path: &Path, size: usize) -> std::io::Result<Self> {
+        if size < MIN_SHM_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("shared memory region must be at least {MIN_SHM_SIZE} bytes"),
+            ));
+        }
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() < size as u64 {
+            file.set_len(size as u64)?;
+        }
+        // SAFETY: the mapping is only accessed through raw copies and atomics,
+        // the peer modifying it concurrently is the point of the region.
+        let map = unsafe { MmapOptions::new().len(size).map_mut(&file)? };
+
+        let region = ShmRegion { map };
+        for ring in 0..2 {
+            region.write_pos(ring).store(0, Ordering::Relaxed);
+            region.read_pos(ring).store(0, Ordering::Relaxed);
+        }
+        region
+            .word(NONCE_OFFSET)
+            .store(random_nonce()?, Ordering::Relaxed);
+        region.word(0).store(REGION_MAGIC, Ordering::Release);
+        Ok(region)
+    }
+
+    /// Map a region prepared by the peer
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // SAFETY: see `create`
+        let map = unsafe { MmapMut::map_mut(&file)? };
+
+        let region = ShmRegion { map };
+        if region.len() < MIN_SHM_SIZE || region.word(0).load(Ordering::Acquire) != REGION_MAGIC {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a shared memory transport region",
+            ));
+        }
+        Ok(region)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Value stamped by the creating side; proves both peers mapped the same memory
+    pub fn nonce(&self) -> u64 {
+        self.word(NONCE_OFFSET).load(Ordering::Acquire)
+    }
+
+    fn ring_size(&self) -> usize {
+        // Keep each ring 64-byte aligned
+        ((self.len() - SHM_HEADER_SIZE) / 2) & !63
+    }
+
+    fn word(&self, offset: usize) -> &AtomicU64 {
+        debug_assert!(offset.is_multiple_of(8) && offset + 8 <= SHM_HEADER_SIZE);
+        // SAFETY: the offset is 8-byte aligned inside the page-aligned mapping,
+        // and AtomicU64 tolerates concurrent access from the peer.
+        unsafe { &*(self.map.as_ptr().add(offset) as *const AtomicU64) }
+    }
+
+    fn write_pos(&self, ring: usize) -> &AtomicU64 {
+        self.word(RING_OFFSET + ring * 2 * POSITION_STRIDE)
+    }
+
+    fn read_pos(&self, ring: usize) -> &AtomicU64 {
+        self.word(RING_OFFSET + (ring * 2 + 1) * POSITION_STRIDE)
+    }
+
+    fn ring_data(&mut self, ring: usize) -> *mut u8 {
+        let offset = SHM_HEADER_SIZE + ring * self.ring_size();
+        // SAFETY: offset + ring_size stays within the mapping
+        unsafe { self.map.as_mut_ptr().add(offset) }
+    }
+}
+
+/// One side's view of a region: sends on one ring, receives on the other
+pub struct ShmChannel {
+    region: ShmRegion,
+    tx: usize,
+    capacity: usize,
+    timeout: Duration,
+}
+
+impl ShmChannel {
+    /// The creating side sends on ring 0, the opening side on ring 1
+    pub fn new(region: ShmRegion, creator: bool) -> Self {
+        let capacity = region.ring_size();
+        ShmChannel {
+            region,
+            tx: if creator { 0 } else { 1 },
+            capacity,
+            timeout: DEFAULT_WAIT_TIMEOUT,
+        }
+    }
+
+    /// How long `write` waits for the peer to free ring space
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Bytes per ring (and per direction)
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.region.nonce()
+    }
+
+    /// Copy `data` into the outgoing ring and return the position it was written at
+    pub fn write(&mut self, data: &[u8]) -> Result<u64> {
+        if data.len() > self.capacity {
+            return Err(Error::new(ErrorKind::InvalidPacket));
+        }
+
+        let tx = self.tx;
+        // Our cursor, but it sits in memory the peer can write
+        let position = self.region.write_pos(tx).load(Ordering::Relaxed);
+        let end = position
+            .checked_add(data.len() as u64)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidPacket))?;
+        self.wait_for_space(end)?;
+
+        let offset = (position % self.capacity as u64) as usize;
+        let first = data.len().min(self.capacity - offset);
+        let base = self.region.ring_data(tx);
+        // SAFETY: both copies stay inside the ring, and the consumer has
+        // released this range (checked by wait_for_space).
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), base.add(offset), first);
+            std::ptr::copy_nonoverlapping(data.as_ptr().add(first), base, data.len() - first);
+        }
+
+        self.region.write_pos(tx).store(end, Ordering::Release);
+        Ok(position)
+    }
+
+    /// Copy the segment at `position` out of the incoming ring and release its space
+    pub fn read(&mut self, position: u64, out: &mut [u8]) -> Result<()> {
+        let mut offset = 0;
+        self.read_with(position, out.len(), |part| {
+            out[offset..offset + part.len()].copy_from_slice(part);
+            offset += part.len();
+        })
+    }
+
+    /// Hand the segment at `position` to `f` (in one or two parts when it wraps),
+    /// then release its space
+    pub fn read_with(&mut self, position: u64, len: usize, mut f: impl FnMut(&[u8])) -> Result<()> {
+        let rx = 1 - self.tx;
+        let expected = self.region.read_pos(rx).load(Ordering::Relaxed);
+        let end = position.saturating_add(len as u64);
+        // Segments are consumed in order and must already be published
+        if position != expected
+            || len > self.capacity
+            || end > self.region.write_pos(rx).load(Ordering::Acquire)
+        {
+            return Err(Error::new(ErrorKind::InvalidPacket));
+        }
+
+        let offset = (position % self.capacity as u64) as usize;
+        let first = len.min(self.capacity - offset);
+        let base = self.region.ring_data(rx);
+        // SAFETY: both parts stay inside the ring, and the producer won't
+        // touch this range until read_pos moves past it.
+        unsafe {
+            f(std::slice::from_raw_parts(base.add(offset), first));
+            if len > first {
+                f(std::slice::from_raw_parts(base, len - first));
+            }
+        }
+
+        self.region.read_pos(rx).store(end, Ordering::Release);
+        Ok(())
+    }
+
+    fn wait_for_space(&self, end: u64) -> Result<()> {
+        let read_pos = self.region.read_pos(self.tx);
+        let started = Instant::now();
+        let mut spins = 0u32;
+        loop {
+            // The peer owns read_pos; one ahead of what we wrote is corrupt
+            let used = end
+                .checked_sub(read_pos.load(Ordering::Acquire))
+                .ok_or_else(|| Error::new(ErrorKind::InvalidPacket))?;
+            if used <= self.capacity as u64 {
+                return Ok(());
+            }
+            if spins < SPIN_LIMIT {
+                spins += 1;
+                std::hint::spin_loop();
+            } else if started.elapsed() > self.timeout {
+                log::warn!("Shared memory ring full for {:?}", self.timeout);
+                return Err(Error::new(ErrorKind::TimedOut));
+            } else {
+                std::thread::sleep(Duration::from_micros(50));
+            }
+        }
+    }
+}
+
+fn random_nonce() -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    crate::sources::os_random(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Temporary backing file, removed on drop
+    struct TempRegion(PathBuf);
+
+    impl TempRegion {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let name = format!(
+                "virga-shm-test-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            );
+            TempRegion(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempRegion {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn channel_pair(path: &Path) -> (ShmChannel, ShmChannel) {
+        let creator = ShmChannel::new(ShmRegion::create(path, MIN_SHM_SIZE).unwrap(), true);
+        let opener = ShmChannel::new(ShmRegion::open(path).unwrap(), false);
+        (creator, opener)
+    }
+
+    #[test]
+    fn create_rejects_small_region() {
+        let tmp = TempRegion::new();
+        assert!(ShmRegion::create(&tmp.0, MIN_SHM_SIZE - 1).is_err());
+    }
+
+    #[test]
+    fn open_rejects_foreign_file() {
+        let tmp = TempRegion::new();
+        std::fs::write(&tmp.0, vec![0u8; MIN_SHM_SIZE]).unwrap();
+        assert!(ShmRegion::open(&tmp.0).is_err());
+    }
+
+    #[test]
+    fn both_sides_see_same_nonce() {
+        let tmp = TempRegion::new();
+        let (creator, opener) = channel_pair(&tmp.0);
+        assert_eq!(creator.nonce(), opener.nonce());
+        assert_eq!(creator.capacity(), opener.capacity());
+        assert!(creator.capacity() >= MIN_RING_SIZE);
+    }
+
+    #[test]
+    fn create_stamps_new_nonce() {
+        let tmp = TempRegion::new();
+        let first = ShmRegion::create(&tmp.0, MIN_SHM_SIZE).unwrap().nonce();
+        let second = ShmRegion::create(&tmp.0, MIN_SHM_SIZE).unwrap().nonce();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn write_read_both_directions() {
+        let tmp = TempRegion::new();
+        let (mut creator, mut opener) = channel_pair(&tmp.0);
+
+        let pos = creator.write(b"hello").unwrap();
+        let mut out = [0u8; 5];
+        opener.read(pos, &mut out).unwrap();
+        assert_eq!(&out, b"hello");
+
+        let pos = opener.write(b"world").unwrap();
+        creator.read(pos, &mut out).unwrap();
+        assert_eq!(&out, b"world");
+    }
+
+    #[test]
+    fn write_wraps_around_ring() {
+        let tmp = TempRegion::new();
+        let (mut creator, mut opener) = channel_pair(&tmp.0);
+        let cap = creator.capacity();
+
+        // Leave the write position just short of the end
+        let filler = vec![0u8; cap - 10];
+        let pos = creator.write(&filler).unwrap();
+        let mut sink = vec![0u8; filler.len()];
+        opener.read(pos, &mut sink).unwrap();
+
+        let data: Vec<u8> = (0..100).collect();
+        let pos = creator.write(&data).unwrap();
+        let mut out = vec![0u8; data.len()];
+        opener.read(pos, &mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn read_rejects_unpublished_segment() {
+        let tmp = TempRegion::new();
+        let (_creator, mut opener) = channel_pair(&tmp.0);
+        let mut out = [0u8; 4];
+        let err = opener.read(0, &mut out).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidPacket);
+    }
+
+    #[test]
+    fn write_times_out_when_ring_full() {
+        let tmp = TempRegion::new();
+        let (creator, _opener) = channel_pair(&tmp.0);
+        let mut creator = creator.with_timeout(Duration::from_millis(20));
+        let cap = creator.capacity();
+
+        creator.write(&vec![1u8; cap]).unwrap();
+        let err = creator.write(&[1u8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn write_rejects_read_position_ahead_of_writes() {
+        let tmp = TempRegion::new();
+        let (mut creator, opener) = channel_pair(&tmp.0);
+        // The opener consumes ring 0, so its region view can move the read cursor
+        opener.region.read_pos(0).store(u64::MAX, Ordering::Release);
+        let err = creator.write(b"data").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidPacket);
+    }
+
+    #[test]
+    fn write_waits_for_reader() {
+        let tmp = TempRegion::new();
+        let (mut creator, mut opener) = channel_pair(&tmp.0);
+        let cap = creator.capacity();
+
+        let pos = creator.write(&vec![1u8; cap]).unwrap();
+        let reader = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            let mut out = vec![0u8; cap];
+            opener.read(pos, &mut out).unwrap();
+            opener
+        });
+        // Blocks until the reader releases the ring
+        creator.write(&[2u8; 16]).unwrap();
+        reader.join().unwrap();
+    }
+}
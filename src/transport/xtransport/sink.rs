@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+use crate::buffers;
+use std::io::IoSliceMut;
+use std::vec::Vec;
+
+enum Target<'a, 'b> {
+    Owned(Vec<u8>),
+    Slices {
+        bufs: &'a mut [IoSliceMut<'b>],
+        index: usize,
+        offset: usize,
+    },
+}
+
+/// Destination of a received message payload: a fresh `Vec` or caller-owned slices.
+///
+/// Bytes past the message length, or past the end of the slices, are dropped.
+pub(crate) struct Sink<'a, 'b> {
+    target: Target<'a, 'b>,
+    limit: usize,
+    written: usize,
+}
+
+impl<'a, 'b> Sink<'a, 'b> {
+    pub fn owned() -> Self {
+        Sink {
+            target: Target::Owned(Vec::new()),
+            limit: 0,
+            written: 0,
+        }
+    }
+
+    /// Reuse `vec`'s allocation for the next message
+    pub fn reuse(mut vec: Vec<u8>) -> Self {
+        vec.clear();
+        Sink {
+            target: Target::Owned(vec),
+            limit: 0,
+            written: 0,
+        }
+    }
+
+    pub fn slices(bufs: &'a mut [IoSliceMut<'b>]) -> Self {
+        Sink {
+            target: Target::Slices {
+                bufs,
+                index: 0,
+                offset: 0,
+            },
+            limit: 0,
+            written: 0,
+        }
+    }
+
+    /// Whether the payload lands in a buffer allocated here rather than in
+    /// caller-owned slices
+    pub fn allocates(&self) -> bool {
+        matches!(self.target, Target::Owned(_))
+    }
+
+    /// Message length, known once its first packet arrives. A fresh owned
+    /// buffer comes from the process-wide buffer source
+    pub fn set_len(&mut self, len: usize) {
+        self.limit = len;
+        if let Target::Owned(vec) = &mut self.target {
+            if vec.capacity() == 0 {
+                *vec = buffers::acquire(len);
+            } else {
+                vec.reserve_exact(len);
+            }
+        }
+    }
+
+    /// Next writable region of at most `max` bytes, `None` once nothing more fits
+    pub fn next_chunk(&mut self, max: usize) -> Option<&mut [u8]> {
+        let max = max.min(self.limit - self.written);
+        if max == 0 {
+            return None;
+        }
+
+        match &mut self.target {
+            Target::Owned(vec) => {
+                let start = vec.len();
+                vec.resize(start + max, 0);
+                self.written += max;
+                Some(&mut vec[start..])
+            }
+            Target::Slices {
+                bufs,
+                index,
+                offset,
+            } => {
+                while *index < bufs.len() && *offset == bufs[*index].len() {
+                    *index += 1;
+                    *offset = 0;
+                }
+                let buf = bufs.get_mut(*index)?;
+                let n = max.min(buf.len() - *offset);
+                let start = *offset;
+                *offset += n;
+                self.written += n;
+                Some(&mut buf[start..start + n])
+            }
+        }
+    }
+
+    /// Copy `data` in, dropping whatever doesn't fit
+    pub fn put(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let Some(chunk) = self.next_chunk(data.len()) else {
+                return;
+            };
+            let n = chunk.len();
+            chunk.copy_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+    }
+
+    /// The owned buffer, zero-padded to the message length
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.target {
+            Target::Owned(mut vec) => {
+                vec.resize(self.limit, 0);
+                vec
+            }
+            Target::Slices { .. } => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owned_collects_and_pads() {
+        let mut sink = Sink::owned();
+        sink.set_len(6);
+        sink.put(&[1, 2, 3]);
+        assert_eq!(sink.into_vec(), vec![1, 2, 3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn owned_drops_bytes_past_len() {
+        let mut sink = Sink::owned();
+        sink.set_len(2);
+        sink.put(&[1, 2, 3]);
+        assert!(sink.next_chunk(1).is_none());
+        assert_eq!(sink.into_vec(), vec![1, 2]);
+    }
+
+    #[test]
+    fn reuse_clears_and_keeps_capacity() {
+        let mut old = Vec::with_capacity(64);
+        old.extend_from_slice(&[9, 9, 9]);
+        let mut sink = Sink::reuse(old);
+        sink.set_len(2);
+        sink.put(&[1, 2]);
+        let out = sink.into_vec();
+        assert_eq!(out, vec![1, 2]);
+        assert!(out.capacity() >= 64);
+    }
+
+    #[test]
+    fn slices_fill_in_order() {
+        let mut head = [0u8; 2];
+        let mut body = [0u8; 4];
+        {
+            let mut bufs = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut body)];
+            let mut sink = Sink::slices(&mut bufs);
+            sink.set_len(6);
+            sink.put(&[1, 2, 3]);
+            sink.put(&[4, 5, 6]);
+        }
+        assert_eq!(head, [1, 2]);
+        assert_eq!(body, [3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn slices_skip_empty_and_drop_overflow() {
+        let mut empty = [0u8; 0];
+        let mut small = [0u8; 2];
+        {
+            let mut bufs = [IoSliceMut::new(&mut empty), IoSliceMut::new(&mut small)];
+            let mut sink = Sink::slices(&mut bufs);
+            sink.set_len(5);
+            sink.put(&[9, 8, 7, 6, 5]);
+            assert!(sink.next_chunk(1).is_none());
+        }
+        assert_eq!(small, [9, 8]);
+    }
+
+    #[test]
+    fn next_chunk_respects_max() {
+        let mut sink = Sink::owned();
+        sink.set_len(100);
+        assert_eq!(sink.next_chunk(10).unwrap().len(), 10);
+        assert_eq!(sink.next_chunk(200).unwrap().len(), 90);
+        assert!(sink.next_chunk(1).is_none());
+    }
+}
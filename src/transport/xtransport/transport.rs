@@ -2,15 +2,35 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
+use crate::buffers;
 use crate::transport::xtransport::{
-    config::{TransportConfig, HEADER_SIZE, MESSAGE_HEAD_SIZE},
+    adaptive::ChunkAdapter,
+    config::{
+        TransportConfig, HEADER_SIZE, MAX_FRAME_SIZE, MESSAGE_HEAD_SIZE, MIN_ADAPTIVE_FRAME_SIZE,
+        SHM_SEGMENT_SIZE, VERSION,
+    },
     error::{Error, ErrorKind},
     io::{Read, Write},
-    protocol::{MessageHead, Packet, PacketHeader, PacketType},
+    protocol::{
+        encode_packet, ControlType, MessageHead, Packet, PacketHeader, PacketType, ShmSegment,
+        EXTENSION_CONTROL_BASE,
+    },
+    shm::{ShmChannel, ShmRegion},
+    sink::Sink,
     Result,
 };
+use crc32fast::Hasher;
+use std::collections::VecDeque;
+use std::io::IoSliceMut;
+use std::time::Instant;
 use std::vec::Vec;
 
+/// Receives application control packets: the subtype and its body
+pub type ExtensionHandler = Box<dyn FnMut(u8, &[u8]) + Send>;
+
+/// Called for every packet read from the peer, control packets included
+pub type FrameHook = Box<dyn FnMut() + Send>;
+
 pub struct XTransport<T> {
     inner: T,
     send_seq: u32,
@@ -20,10 +40,36 @@ pub struct XTransport<T> {
     recv_pos: usize,
     recv_available: usize,
     config: TransportConfig,
+    adapter: Option<ChunkAdapter>,
+    shm: Option<ShmChannel>,
+    shm_pending: Option<ShmChannel>,
+    loan_buffer: Vec<u8>,
+    peeked: Option<PacketHeader>,
+    last_pong: Option<u64>,
+    last_time_reply: Option<(u64, u64, u64)>,
+    goaway: Option<u64>,
+    close_reason: Option<u64>,
+    rejection: Option<String>,
+    compressed_next: bool,
+    last_compressed: bool,
+    batch_next: bool,
+    last_batch: bool,
+    skip_fragments: bool,
+    version: u8,
+    extension: Option<ExtensionHandler>,
+    frame_hook: Option<FrameHook>,
+    recv_limit: Option<usize>,
 }
 
 impl<T: Read + Write> XTransport<T> {
     pub fn new(inner: T, config: TransportConfig) -> Self {
+        let adapter = config.adaptive_chunk.then(|| {
+            ChunkAdapter::new(
+                config.max_payload_size,
+                MIN_ADAPTIVE_FRAME_SIZE - HEADER_SIZE,
+                MAX_FRAME_SIZE - HEADER_SIZE,
+            )
+        });
         XTransport {
             inner,
             send_seq: 0,
@@ -33,94 +79,265 @@ impl<T: Read + Write> XTransport<T> {
             recv_pos: 0,
             recv_available: 0,
             config,
+            adapter,
+            shm: None,
+            shm_pending: None,
+            loan_buffer: Vec::new(),
+            peeked: None,
+            last_pong: None,
+            last_time_reply: None,
+            goaway: None,
+            close_reason: None,
+            rejection: None,
+            compressed_next: false,
+            last_compressed: false,
+            batch_next: false,
+            last_batch: false,
+            skip_fragments: false,
+            version: VERSION,
+            extension: None,
+            frame_hook: None,
+            recv_limit: None,
         }
     }
 
-    fn send_packet(&mut self, pkt_type: PacketType, data: &[u8]) -> Result<()> {
-        let packet = Packet::new(pkt_type, self.send_seq, data.to_vec());
-        let seq = packet.header.seq;
-        self.send_seq = self.send_seq.wrapping_add(1);
+    /// The underlying stream, e.g. to change socket options. Reading or
+    /// writing through it directly desynchronizes the framing
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
 
-        // Combine header and data into a single buffer for atomic send
-        let header_bytes = packet.header.to_bytes();
-        let mut combined = Vec::with_capacity(header_bytes.len() + packet.data.len());
-        combined.extend_from_slice(&header_bytes);
-        combined.extend_from_slice(&packet.data);
+    /// Payload size currently used for fragmentation
+    pub fn payload_size(&self) -> usize {
+        match &self.adapter {
+            Some(adapter) => adapter.current(),
+            None => self.config.max_payload_size,
+        }
+    }
 
-        // Send combined buffer in one write call
-        self.inner.write_all(&combined)?;
+    /// Frame size (header + payload) currently used for fragmentation
+    pub fn frame_size(&self) -> usize {
+        self.payload_size() + HEADER_SIZE
+    }
+
+    /// Bytes held by the reusable receive buffers
+    pub fn buffered_bytes(&self) -> usize {
+        self.recv_buffer.capacity() + self.loan_buffer.capacity()
+    }
+
+    /// Free the reusable receive buffers; an unread partial packet is kept
+    pub fn release_buffers(&mut self) {
+        buffers::recycle(std::mem::take(&mut self.loan_buffer));
+        if self.recv_pos >= self.recv_available {
+            self.recv_buffer = Vec::new();
+            self.recv_pos = 0;
+            self.recv_available = 0;
+        }
+    }
+
+    /// Encode a packet (header + data) onto the end of `out`, returning its seq
+    fn encode_packet(&mut self, pkt_type: PacketType, data: &[u8], out: &mut Vec<u8>) -> u32 {
+        let seq = self.send_seq;
+        self.send_seq = self.send_seq.wrapping_add(1);
+        encode_packet(self.version, pkt_type, seq, data, out);
 
         log::trace!(
-            "Sent packet type={:?}, seq={}, len={}",
+            "Encoded packet type={:?}, seq={}, len={}",
             pkt_type,
             seq,
-            packet.data.len()
+            data.len()
         );
+        seq
+    }
+
+    fn send_packet(&mut self, pkt_type: PacketType, data: &[u8]) -> Result<()> {
+        // Combine header and data into a single buffer for atomic send
+        let mut combined = buffers::acquire(HEADER_SIZE + data.len());
+        let seq = self.encode_packet(pkt_type, data, &mut combined);
+
+        // Send combined buffer in one write call
+        let sent_at = Instant::now();
+        let written = self.inner.write_all(&combined);
+        buffers::recycle(combined);
+        written?;
 
         // Wait for ACK if configured and not sending an ACK itself
         if self.config.wait_for_ack && pkt_type != PacketType::Ack {
-            let ack_packet = self.recv_packet_internal()?;
-            if ack_packet.header.pkt_type != PacketType::Ack as u8 {
-                return Err(Error::new(ErrorKind::InvalidPacket));
-            }
-            if ack_packet.data.len() < 4 {
-                return Err(Error::new(ErrorKind::InvalidPacket));
-            }
-            let ack_seq = u32::from_le_bytes([
-                ack_packet.data[0],
-                ack_packet.data[1],
-                ack_packet.data[2],
-                ack_packet.data[3],
-            ]);
-            if ack_seq != seq {
-                log::warn!("ACK seq mismatch: expected {}, got {}", seq, ack_seq);
-                return Err(Error::new(ErrorKind::InvalidPacket));
+            self.wait_ack(seq, sent_at)?;
+        }
+
+        Ok(())
+    }
+
+    fn wait_ack(&mut self, seq: u32, sent_at: Instant) -> Result<()> {
+        let ack_packet = self.recv_packet_internal()?;
+        if ack_packet.header.pkt_type != PacketType::Ack as u8 {
+            return Err(Error::new(ErrorKind::InvalidPacket));
+        }
+        if ack_packet.data.len() < 4 {
+            return Err(Error::new(ErrorKind::InvalidPacket));
+        }
+        let ack_seq = u32::from_le_bytes([
+            ack_packet.data[0],
+            ack_packet.data[1],
+            ack_packet.data[2],
+            ack_packet.data[3],
+        ]);
+        if ack_seq != seq {
+            log::warn!("ACK seq mismatch: expected {}, got {}", seq, ack_seq);
+            return Err(Error::new(ErrorKind::InvalidPacket));
+        }
+        log::trace!("Received ACK for seq={}", seq);
+        if let Some(adapter) = self.adapter.as_mut() {
+            adapter.record_ack_latency(sent_at.elapsed());
+        }
+        Ok(())
+    }
+
+    /// Send MessageHead + MessageData packets with up to `send_window` packets in flight.
+    ///
+    /// Without ACK the window only bounds how many packets are batched into one write.
+    fn send_pipelined(&mut self, head: &MessageHead, data: &[u8], payload: usize) -> Result<()> {
+        let window = self.config.send_window.max(1);
+        let mut batch = buffers::acquire(window * (HEADER_SIZE + payload));
+        let mut batched = 0usize;
+        let mut in_flight: VecDeque<(u32, Instant)> = VecDeque::with_capacity(window);
+
+        let head_bytes = head.to_bytes();
+        let chunks = core::iter::once((PacketType::MessageHead, &head_bytes[..]))
+            .chain(data.chunks(payload).map(|c| (PacketType::MessageData, c)));
+
+        for (pkt_type, chunk) in chunks {
+            let seq = self.encode_packet(pkt_type, chunk, &mut batch);
+            batched += 1;
+
+            if self.config.wait_for_ack {
+                in_flight.push_back((seq, Instant::now()));
+                if in_flight.len() >= window {
+                    // Must hit the wire before blocking on the oldest ACK
+                    self.inner.write_all(&batch)?;
+                    batch.clear();
+                    batched = 0;
+                    if let Some((oldest, sent_at)) = in_flight.pop_front() {
+                        self.wait_ack(oldest, sent_at)?;
+                    }
+                }
+            } else if batched >= window {
+                self.inner.write_all(&batch)?;
+                batch.clear();
+                batched = 0;
             }
-            log::trace!("Received ACK for seq={}", seq);
         }
 
+        if !batch.is_empty() {
+            self.inner.write_all(&batch)?;
+        }
+        buffers::recycle(batch);
+        while let Some((seq, sent_at)) = in_flight.pop_front() {
+            self.wait_ack(seq, sent_at)?;
+        }
         Ok(())
     }
 
     fn send_ack(&mut self, seq: u32) -> Result<()> {
-        let ack_data = seq.to_le_bytes();
-        let ack_packet = Packet::new(PacketType::Ack, self.send_seq, ack_data.to_vec());
-        self.send_seq = self.send_seq.wrapping_add(1);
-
-        let header_bytes = ack_packet.header.to_bytes();
-        let mut combined = Vec::with_capacity(header_bytes.len() + ack_packet.data.len());
-        combined.extend_from_slice(&header_bytes);
-        combined.extend_from_slice(&ack_packet.data);
+        let mut combined = Vec::with_capacity(HEADER_SIZE + 4);
+        self.encode_packet(PacketType::Ack, &seq.to_le_bytes(), &mut combined);
         self.inner.write_all(&combined)?;
 
         log::trace!("Sent ACK for seq={}", seq);
         Ok(())
     }
 
-    fn recv_packet_internal(&mut self) -> Result<Packet> {
-        // Read header
+    /// Whether a message header has already been read and awaits the next receive
+    pub fn message_pending(&self) -> bool {
+        self.peeked.is_some()
+    }
+
+    /// Read one packet header without receiving a message: a control packet is
+    /// handled on the spot, anything else is kept for the next receive.
+    /// Returns whether a message is now pending
+    pub fn poll_message(&mut self) -> Result<bool> {
+        if self.peeked.is_some() {
+            return Ok(true);
+        }
+        let header = self.read_header()?;
+        if header.pkt_type == PacketType::Control as u8 {
+            let data = self.read_body(&header)?;
+            self.handle_control(&data)?;
+            return Ok(false);
+        }
+        self.peeked = Some(header);
+        Ok(true)
+    }
+
+    /// Next non-control packet header; control packets are handled in passing
+    fn next_header(&mut self) -> Result<PacketHeader> {
+        if let Some(header) = self.peeked.take() {
+            return Ok(header);
+        }
+        loop {
+            let header = self.read_header()?;
+            if header.pkt_type != PacketType::Control as u8 {
+                return Ok(header);
+            }
+            let data = self.read_body(&header)?;
+            self.handle_control(&data)?;
+        }
+    }
+
+    fn read_header(&mut self) -> Result<PacketHeader> {
         let mut header_buf = [0u8; HEADER_SIZE];
         self.inner.read_exact(&mut header_buf)?;
         let header = PacketHeader::from_bytes(&header_buf)?;
+        if let Some(hook) = self.frame_hook.as_mut() {
+            hook();
+        }
+        Ok(header)
+    }
 
-        // Read data
+    fn read_body(&mut self, header: &PacketHeader) -> Result<Vec<u8>> {
         let mut data = std::vec![0u8; header.length as usize];
         self.inner.read_exact(&mut data)?;
 
-        let packet = Packet { header, data };
+        if header.checksum(&data) != header.crc32 {
+            return Err(Error::new(ErrorKind::CrcMismatch));
+        }
+        Ok(data)
+    }
+
+    /// Read a packet body straight into `sink`, checking its CRC on the way
+    fn read_body_into(&mut self, header: &PacketHeader, sink: &mut Sink) -> Result<()> {
+        let mut hasher = header.hasher();
+        let mut remaining = header.length as usize;
+        let mut scratch = [0u8; 512];
+
+        while remaining > 0 {
+            let chunk = match sink.next_chunk(remaining) {
+                Some(chunk) => chunk,
+                // No room left: drain the rest so framing stays intact
+                None => {
+                    let n = remaining.min(scratch.len());
+                    &mut scratch[..n]
+                }
+            };
+            self.inner.read_exact(chunk)?;
+            hasher.update(chunk);
+            remaining -= chunk.len();
+        }
 
-        // Verify CRC
-        if !packet.verify_crc() {
+        if hasher.finalize() != header.crc32 {
             return Err(Error::new(ErrorKind::CrcMismatch));
         }
+        Ok(())
+    }
 
-        log::trace!(
-            "Received packet seq={}, len={}",
-            packet.header.seq,
-            packet.data.len()
-        );
+    fn recv_packet_internal(&mut self) -> Result<Packet> {
+        let header = self.next_header()?;
+        let data = self.read_body(&header)?;
 
-        Ok(packet)
+        log::trace!("Received packet seq={}, len={}", header.seq, data.len());
+
+        Ok(Packet { header, data })
     }
 
     fn recv_packet(&mut self) -> Result<Packet> {
@@ -142,97 +359,202 @@ impl<T: Read + Write> XTransport<T> {
 
     /// Send a complete message (automatically handles fragmentation)
     pub fn send_message(&mut self, data: &[u8]) -> Result<()> {
-        if data.len() <= self.config.max_payload_size {
+        let payload = self.payload_size();
+        let started = Instant::now();
+
+        let fragmented = data.len() > payload && self.shm.is_none();
+
+        if data.len() <= payload {
             // Small message: single Data packet
             self.send_packet(PacketType::Data, data)?;
             log::debug!("Sent single-packet message: {} bytes", data.len());
+        } else if self.shm.is_some() {
+            // Large message: payload through shared memory, descriptors over the stream
+            self.send_shm(data)?;
+            log::debug!("Sent shared memory message: {} bytes", data.len());
         } else {
             // Large message: MessageHead + multiple MessageData packets
             let message_id = self.next_message_id;
             self.next_message_id = self.next_message_id.wrapping_add(1);
 
-            let packet_count = ((data.len() + self.config.max_payload_size - 1)
-                / self.config.max_payload_size) as u32;
-
-            // Send MessageHead
+            let packet_count = data.len().div_ceil(payload) as u32;
             let head = MessageHead::new(data.len() as u64, message_id, packet_count);
-            self.send_packet(PacketType::MessageHead, &head.to_bytes())?;
 
             log::debug!(
-                "Sending large message: id={}, total={} bytes, packets={}",
+                "Sending large message: id={}, total={} bytes, packets={}, window={}",
                 message_id,
                 data.len(),
-                packet_count
+                packet_count,
+                self.config.send_window
             );
 
-            // Send MessageData packets
-            for chunk in data.chunks(self.config.max_payload_size) {
-                self.send_packet(PacketType::MessageData, chunk)?;
-            }
+            // MessageHead + MessageData packets, pipelined within the send window
+            self.send_pipelined(&head, data, payload)?;
 
             log::debug!("Large message sent: id={}", message_id);
         }
 
         self.inner.flush()?;
+
+        // Only fragmented sends say anything about the payload size
+        if fragmented {
+            if let Some(adapter) = self.adapter.as_mut() {
+                adapter.record_transfer(data.len(), started.elapsed());
+            }
+        }
         Ok(())
     }
 
+    /// Send a message the caller has already compressed. A `Compressed`
+    /// control goes first so the receiver knows to decompress it
+    pub fn send_message_compressed(&mut self, data: &[u8]) -> Result<()> {
+        self.send_control(ControlType::Compressed, &(data.len() as u64).to_le_bytes())?;
+        self.send_message(data)
+    }
+
+    /// Whether the message just received was announced as compressed
+    pub fn last_compressed(&self) -> bool {
+        self.last_compressed
+    }
+
+    /// Send several messages the caller has packed into one. A `Batch`
+    /// control goes first so the receiver knows to split it
+    pub fn send_message_batch(&mut self, data: &[u8]) -> Result<()> {
+        self.send_control(ControlType::Batch, &(data.len() as u64).to_le_bytes())?;
+        self.send_message(data)
+    }
+
+    /// Whether the message just received was announced as a batch
+    pub fn last_batch(&self) -> bool {
+        self.last_batch
+    }
+
     /// Receive a complete message (automatically handles reassembly)
     pub fn recv_message(&mut self) -> Result<Vec<u8>> {
-        // Read first packet to determine type
-        let mut header_buf = [0u8; HEADER_SIZE];
-        self.inner.read_exact(&mut header_buf)?;
-        let header = PacketHeader::from_bytes(&header_buf)?;
+        let mut sink = Sink::owned();
+        self.recv_into(&mut sink)?;
+        Ok(sink.into_vec())
+    }
+
+    /// Receive a complete message straight into `bufs`, filling them in order.
+    ///
+    /// Returns the message length. A message longer than the buffers is still
+    /// consumed whole; the excess is dropped and the returned length exceeds
+    /// their combined size.
+    pub fn recv_message_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        let mut sink = Sink::slices(bufs);
+        self.recv_into(&mut sink)
+    }
+
+    /// Receive a complete message into a buffer reused across calls.
+    ///
+    /// The returned slice borrows the transport, so it stays valid until the
+    /// next receive.
+    pub fn recv_message_loaned(&mut self) -> Result<&[u8]> {
+        let mut sink = Sink::reuse(std::mem::take(&mut self.loan_buffer));
+        self.recv_into(&mut sink)?;
+        self.loan_buffer = sink.into_vec();
+        Ok(&self.loan_buffer)
+    }
+
+    /// The message returned by the last `recv_message_loaned`
+    pub fn loaned(&self) -> &[u8] {
+        &self.loan_buffer
+    }
+
+    /// Cap on the buffer a receive may allocate for one message. A longer
+    /// announced message is drained from the stream without being stored and
+    /// the receive fails with `OutOfMemory`; the connection stays usable.
+    /// Receives into caller-owned slices are not affected
+    pub fn set_recv_limit(&mut self, limit: Option<usize>) {
+        self.recv_limit = limit;
+    }
+
+    /// Size `sink` for a `total` byte message; returns false, leaving nothing
+    /// to allocate, when an owned buffer that long would exceed the receive limit
+    fn admit(&self, sink: &mut Sink, total: usize) -> bool {
+        let admitted = !sink.allocates() || self.recv_limit.is_none_or(|limit| total <= limit);
+        sink.set_len(if admitted { total } else { 0 });
+        admitted
+    }
+
+    /// The error for a message `admit` turned away, once it has been drained
+    fn over_limit(&self, total: usize) -> Error {
+        Error::from(std::io::Error::new(
+            std::io::ErrorKind::OutOfMemory,
+            format!(
+                "{} byte message exceeds the {} byte receive limit",
+                total,
+                self.recv_limit.unwrap_or_default()
+            ),
+        ))
+    }
+
+    /// Drop what is left of a message whose receive failed part-way: the next
+    /// receive discards leading `MessageData` packets up to the next message
+    /// start. Nothing is read here, so it never blocks.
+    ///
+    /// Only packet-level failures leave the stream on a packet boundary; a
+    /// corrupted header or a read cut off mid-packet can't be recovered
+    pub fn resync(&mut self) {
+        self.skip_fragments = true;
+    }
 
+    fn recv_into(&mut self, sink: &mut Sink) -> Result<usize> {
+        // Read first packet to determine type
+        let mut header = self.next_header()?;
+        while self.skip_fragments && header.pkt_type == PacketType::MessageData as u8 {
+            let mut discard = Sink::slices(&mut []);
+            match self.read_body_into(&header, &mut discard) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::CrcMismatch => {}
+                Err(e) => return Err(e),
+            }
+            if self.config.wait_for_ack {
+                self.send_ack(header.seq)?;
+            }
+            log::debug!("Discarded {} byte fragment while resyncing", header.length);
+            header = self.next_header()?;
+        }
+        self.skip_fragments = false;
+        // A Compressed or Batch control, if any, has been handled by now
+        self.last_compressed = std::mem::take(&mut self.compressed_next);
+        self.last_batch = std::mem::take(&mut self.batch_next);
         let pkt_type = PacketType::from_u8(header.pkt_type)
             .ok_or_else(|| Error::new(ErrorKind::InvalidPacket))?;
 
         match pkt_type {
             PacketType::Data => {
                 // Single packet message
-                let mut data = std::vec![0u8; header.length as usize];
-                self.inner.read_exact(&mut data)?;
-
-                let packet = Packet { header, data };
-                if !packet.verify_crc() {
-                    return Err(Error::new(ErrorKind::CrcMismatch));
-                }
+                sink.set_len(header.length as usize);
+                self.read_body_into(&header, sink)?;
 
                 // Send ACK if configured
                 if self.config.wait_for_ack {
-                    self.send_ack(packet.header.seq)?;
+                    self.send_ack(header.seq)?;
                 }
 
-                log::debug!(
-                    "Received single-packet message: {} bytes",
-                    packet.data.len()
-                );
-                Ok(packet.data)
+                log::debug!("Received single-packet message: {} bytes", header.length);
+                Ok(header.length as usize)
             }
             PacketType::MessageHead => {
                 // Multi-packet message
-                let mut head_data = std::vec![0u8; header.length as usize];
-                self.inner.read_exact(&mut head_data)?;
-
-                let packet = Packet {
-                    header,
-                    data: head_data,
-                };
-                if !packet.verify_crc() {
-                    return Err(Error::new(ErrorKind::CrcMismatch));
-                }
+                let head_data = self.read_body(&header)?;
 
                 // Send ACK for MessageHead if configured
                 if self.config.wait_for_ack {
-                    self.send_ack(packet.header.seq)?;
+                    self.send_ack(header.seq)?;
                 }
 
-                if packet.data.len() < MESSAGE_HEAD_SIZE {
+                if head_data.len() < MESSAGE_HEAD_SIZE {
                     return Err(Error::new(ErrorKind::InvalidPacket));
                 }
+                if head_data.len() > MESSAGE_HEAD_SIZE {
+                    self.tolerate(format_args!("{} byte message head", head_data.len()))?;
+                }
 
                 let mut head_bytes = [0u8; MESSAGE_HEAD_SIZE];
-                head_bytes.copy_from_slice(&packet.data[..MESSAGE_HEAD_SIZE]);
+                head_bytes.copy_from_slice(&head_data[..MESSAGE_HEAD_SIZE]);
                 let msg_head = MessageHead::from_bytes(&head_bytes)?;
 
                 log::debug!(
@@ -243,41 +565,26 @@ impl<T: Read + Write> XTransport<T> {
                 );
 
                 // Receive all data packets
-                let mut result = std::vec![0u8; msg_head.total_length as usize];
-                let mut offset = 0;
+                let total = msg_head.total_length as usize;
+                let admitted = self.admit(sink, total);
+                let mut received = 0usize;
 
                 for i in 0..msg_head.packet_count {
-                    let mut data_header_buf = [0u8; HEADER_SIZE];
-                    self.inner.read_exact(&mut data_header_buf)?;
-                    let data_header = PacketHeader::from_bytes(&data_header_buf)?;
-
-                    let data_type = PacketType::from_u8(data_header.pkt_type)
-                        .ok_or_else(|| Error::new(ErrorKind::InvalidPacket))?;
-
-                    if data_type != PacketType::MessageData {
+                    let data_header = self.next_header()?;
+                    if data_header.pkt_type != PacketType::MessageData as u8 {
+                        // Keep it: the peer may have given up on this message
+                        // and started the next one
+                        self.peeked = Some(data_header);
                         return Err(Error::new(ErrorKind::InvalidPacket));
                     }
-
-                    let mut chunk = std::vec![0u8; data_header.length as usize];
-                    self.inner.read_exact(&mut chunk)?;
-
-                    let data_packet = Packet {
-                        header: data_header,
-                        data: chunk,
-                    };
-                    if !data_packet.verify_crc() {
-                        return Err(Error::new(ErrorKind::CrcMismatch));
-                    }
+                    self.read_body_into(&data_header, sink)?;
+                    received += data_header.length as usize;
 
                     // Send ACK for each MessageData if configured
                     if self.config.wait_for_ack {
-                        self.send_ack(data_packet.header.seq)?;
+                        self.send_ack(data_header.seq)?;
                     }
 
-                    let to_copy = core::cmp::min(data_packet.data.len(), result.len() - offset);
-                    result[offset..offset + to_copy].copy_from_slice(&data_packet.data[..to_copy]);
-                    offset += to_copy;
-
                     if (i + 1) % 100 == 0 || i + 1 == msg_head.packet_count {
                         log::debug!(
                             "Progress: {}/{} packets received",
@@ -287,19 +594,436 @@ impl<T: Read + Write> XTransport<T> {
                     }
                 }
 
+                if received != total {
+                    self.tolerate(format_args!(
+                        "{} fragment bytes for a {} byte message",
+                        received, total
+                    ))?;
+                }
+                if !admitted {
+                    return Err(self.over_limit(total));
+                }
                 log::debug!(
                     "Large message received: id={}, {} bytes",
                     msg_head.message_id,
-                    result.len()
+                    total
                 );
-                Ok(result)
+                Ok(total)
+            }
+            PacketType::ShmData => {
+                let data = self.read_body(&header)?;
+                if self.config.wait_for_ack {
+                    self.send_ack(header.seq)?;
+                }
+                self.recv_shm(&data, sink)
             }
-            PacketType::MessageData | PacketType::Ack => {
+            PacketType::MessageData | PacketType::Ack | PacketType::Control => {
                 // Unexpected: should not receive MessageData or Ack as first packet
                 Err(Error::new(ErrorKind::InvalidPacket))
             }
         }
     }
+
+    /// Whether large payloads currently travel through shared memory
+    pub fn shm_active(&self) -> bool {
+        self.shm.is_some()
+    }
+
+    /// Propose the configured shared-memory region to the peer.
+    ///
+    /// The answer is handled by whichever receive sees it first; until the peer
+    /// accepts, payloads keep going over the stream. A region that can't be
+    /// created is logged and skipped rather than failing the connection.
+    pub fn offer_shm(&mut self) -> Result<()> {
+        let Some(shm) = self.config.shm.clone() else {
+            return Ok(());
+        };
+        let region = match ShmRegion::create(&shm.path, shm.size) {
+            Ok(region) => region,
+            Err(e) => {
+                log::warn!(
+                    "Shared memory {:?} unavailable, staying on stream: {}",
+                    shm.path,
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        let channel = ShmChannel::new(region, true);
+        let mut body = [0u8; 16];
+        body[0..8].copy_from_slice(&channel.nonce().to_le_bytes());
+        body[8..16].copy_from_slice(&(shm.size as u64).to_le_bytes());
+        self.shm = None;
+        self.shm_pending = Some(channel);
+        self.send_control(ControlType::ShmOffer, &body)?;
+        log::debug!("Offered shared memory region {:?}", shm.path);
+        Ok(())
+    }
+
+    /// Framing version used for sending; packets of any supported version
+    /// are accepted on receive
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Propose the highest framing version we speak. Sending switches once
+    /// the peer's `HelloAck` is handled; peers that predate the handshake
+    /// ignore the proposal and the connection stays on v1
+    pub fn offer_version(&mut self) -> Result<()> {
+        if self.config.max_version <= VERSION {
+            return Ok(());
+        }
+        let offer = self.config.max_version as u64;
+        self.send_control(ControlType::Hello, &offer.to_le_bytes())
+    }
+
+    /// Send a ping and wait for the matching pong. Any other packet from the
+    /// peer proves it alive as well; its header is kept for the next receive.
+    /// A message that is already pending counts the same, and no ping is sent
+    pub fn ping(&mut self, nonce: u64) -> Result<()> {
+        if self.peeked.is_some() {
+            return Ok(());
+        }
+        self.send_control(ControlType::Ping, &nonce.to_le_bytes())?;
+        while self.last_pong != Some(nonce) {
+            let header = self.read_header()?;
+            if header.pkt_type != PacketType::Control as u8 {
+                self.peeked = Some(header);
+                break;
+            }
+            let data = self.read_body(&header)?;
+            self.handle_control(&data)?;
+        }
+        Ok(())
+    }
+
+    /// Ask for the peer's wall clock and wait for the reply, like `ping`.
+    ///
+    /// Returns when the peer received the request and when it replied, in
+    /// nanoseconds since the Unix epoch, or `None` if a message arrives (or
+    /// is already pending) first. That message's header is kept for the next
+    /// receive and a late reply is handled after it
+    pub fn time_probe(&mut self, nonce: u64) -> Result<Option<(u64, u64)>> {
+        if self.peeked.is_some() {
+            return Ok(None);
+        }
+        self.send_control(ControlType::TimeRequest, &nonce.to_le_bytes())?;
+        loop {
+            if let Some((replied, received, sent)) = self.last_time_reply {
+                if replied == nonce {
+                    return Ok(Some((received, sent)));
+                }
+            }
+            let header = self.read_header()?;
+            if header.pkt_type != PacketType::Control as u8 {
+                self.peeked = Some(header);
+                return Ok(None);
+            }
+            let data = self.read_body(&header)?;
+            self.handle_control(&data)?;
+        }
+    }
+
+    /// Ask the peer to reconnect once it is done with in-flight work. The
+    /// connection stays usable; the peer sees the request on its next receive
+    pub fn go_away(&mut self, reason: u64) -> Result<()> {
+        self.send_control(ControlType::GoAway, &reason.to_le_bytes())
+    }
+
+    /// Reason code of the GOAWAY received from the peer, if any
+    pub fn goaway(&self) -> Option<u64> {
+        self.goaway
+    }
+
+    /// Tell the peer why the connection is about to close; the caller closes
+    /// the stream afterwards. The peer reads the reason before it sees EOF
+    pub fn close(&mut self, reason: u64) -> Result<()> {
+        self.send_control(ControlType::Close, &reason.to_le_bytes())
+    }
+
+    /// Reason code the peer gave before closing the connection, if any
+    pub fn close_reason(&self) -> Option<u64> {
+        self.close_reason
+    }
+
+    /// Tell the peer why the connection is being refused; the caller closes
+    /// the stream afterwards. The peer's receives then fail with `Rejected`
+    pub fn reject(&mut self, reason: &str) -> Result<()> {
+        let mut body = 0u64.to_le_bytes().to_vec();
+        body.extend_from_slice(reason.as_bytes());
+        self.send_control(ControlType::Reject, &body)
+    }
+
+    /// Reason given by the peer when it refused the connection, if any
+    pub fn rejection(&self) -> Option<&str> {
+        self.rejection.as_deref()
+    }
+
+    /// Hand application control packets (subtype `EXTENSION_CONTROL_BASE` and
+    /// up) to `handler` as they are read; without one they are dropped
+    pub fn set_extension_handler(&mut self, handler: Option<ExtensionHandler>) {
+        self.extension = handler;
+    }
+
+    /// Call `hook` for every packet read from the peer, including pings, pongs
+    /// and other control packets handled inside the transport
+    pub fn set_frame_hook(&mut self, hook: Option<FrameHook>) {
+        self.frame_hook = hook;
+    }
+
+    /// Send an application control packet. It bypasses fragmentation, shared
+    /// memory and acks, so `body` should stay small
+    pub fn send_extension(&mut self, ctrl: u8, body: &[u8]) -> Result<()> {
+        if ctrl < EXTENSION_CONTROL_BASE {
+            return Err(Error::new(ErrorKind::InvalidPacket));
+        }
+        self.send_control_raw(ctrl, body)
+    }
+
+    fn send_control(&mut self, ctrl: ControlType, body: &[u8]) -> Result<()> {
+        self.send_control_raw(ctrl as u8, body)
+    }
+
+    fn send_control_raw(&mut self, ctrl: u8, body: &[u8]) -> Result<()> {
+        let mut data = Vec::with_capacity(1 + body.len());
+        data.push(ctrl);
+        data.extend_from_slice(body);
+
+        let mut out = Vec::with_capacity(HEADER_SIZE + data.len());
+        self.encode_packet(PacketType::Control, &data, &mut out);
+        self.inner.write_all(&out)?;
+        self.inner.flush()
+    }
+
+    /// Tolerate a protocol anomaly, or fail on it in strict mode
+    fn tolerate(&self, what: core::fmt::Arguments<'_>) -> Result<()> {
+        if self.config.strict {
+            log::warn!("Rejecting {} (strict mode)", what);
+            return Err(Error::new(ErrorKind::InvalidPacket));
+        }
+        log::debug!("Ignoring {}", what);
+        Ok(())
+    }
+
+    fn handle_control(&mut self, data: &[u8]) -> Result<()> {
+        let (&ctrl, body) = data
+            .split_first()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidPacket))?;
+        if ctrl >= EXTENSION_CONTROL_BASE {
+            // Application-defined: never an anomaly, even in strict mode
+            match self.extension.as_mut() {
+                Some(handler) => handler(ctrl, body),
+                None => log::debug!("Dropping extension control {:#04x}", ctrl),
+            }
+            return Ok(());
+        }
+        let Some(ctrl) = ControlType::from_u8(ctrl) else {
+            // Newer peers may send controls we don't know; they are advisory
+            return self.tolerate(format_args!("unknown control type {}", ctrl));
+        };
+        if body.len() < 8 {
+            return Err(Error::new(ErrorKind::InvalidPacket));
+        }
+        let defined = match ctrl {
+            ControlType::ShmOffer => 16,
+            ControlType::TimeReply => 24,
+            ControlType::Reject => body.len(),
+            _ => 8,
+        };
+        if body.len() > defined {
+            self.tolerate(format_args!(
+                "{} trailing bytes after {:?} control",
+                body.len() - defined,
+                ctrl
+            ))?;
+        }
+        let nonce = u64::from_le_bytes(body[0..8].try_into().unwrap());
+
+        match ctrl {
+            ControlType::ShmOffer => {
+                if body.len() < 16 {
+                    return Err(Error::new(ErrorKind::InvalidPacket));
+                }
+                let size = u64::from_le_bytes(body[8..16].try_into().unwrap());
+                match self.accept_shm(nonce, size) {
+                    Some(channel) => {
+                        self.shm = Some(channel);
+                        self.send_control(ControlType::ShmAccept, &nonce.to_le_bytes())?;
+                        log::info!("Shared memory payload path active");
+                    }
+                    None => self.send_control(ControlType::ShmReject, &nonce.to_le_bytes())?,
+                }
+            }
+            ControlType::ShmAccept => {
+                if self.shm_pending.as_ref().map(|c| c.nonce()) == Some(nonce) {
+                    self.shm = self.shm_pending.take();
+                    log::info!("Shared memory payload path active");
+                } else {
+                    self.tolerate(format_args!("unsolicited shared memory accept"))?;
+                }
+            }
+            ControlType::ShmReject => {
+                if self.shm_pending.as_ref().map(|c| c.nonce()) == Some(nonce) {
+                    self.shm_pending = None;
+                    log::info!("Peer declined shared memory, staying on stream");
+                } else {
+                    self.tolerate(format_args!("unsolicited shared memory reject"))?;
+                }
+            }
+            ControlType::Ping => self.send_control(ControlType::Pong, &nonce.to_le_bytes())?,
+            ControlType::Pong => self.last_pong = Some(nonce),
+            ControlType::Compressed => self.compressed_next = true,
+            ControlType::Batch => self.batch_next = true,
+            ControlType::TimeRequest => {
+                let received = crate::clock::now_nanos();
+                let mut reply = [0u8; 24];
+                reply[0..8].copy_from_slice(&nonce.to_le_bytes());
+                reply[8..16].copy_from_slice(&received.to_le_bytes());
+                reply[16..24].copy_from_slice(&crate::clock::now_nanos().to_le_bytes());
+                self.send_control(ControlType::TimeReply, &reply)?;
+            }
+            ControlType::TimeReply => {
+                if body.len() < 24 {
+                    return Err(Error::new(ErrorKind::InvalidPacket));
+                }
+                let received = u64::from_le_bytes(body[8..16].try_into().unwrap());
+                let sent = u64::from_le_bytes(body[16..24].try_into().unwrap());
+                self.last_time_reply = Some((nonce, received, sent));
+            }
+            ControlType::GoAway => {
+                log::info!("Peer sent GOAWAY (reason {})", nonce);
+                self.goaway = Some(nonce);
+            }
+            ControlType::Close => {
+                log::debug!("Peer is closing the connection (reason {})", nonce);
+                self.close_reason = Some(nonce);
+            }
+            ControlType::Hello => {
+                let chosen = (nonce.min(self.config.max_version as u64) as u8).max(VERSION);
+                // The ack still goes out in the old framing; the peer accepts both
+                self.send_control(ControlType::HelloAck, &(chosen as u64).to_le_bytes())?;
+                self.version = chosen;
+                log::debug!("Framing v{} chosen at the peer's request", chosen);
+            }
+            ControlType::HelloAck => {
+                if (VERSION as u64..=self.config.max_version as u64).contains(&nonce) {
+                    self.version = nonce as u8;
+                    log::debug!("Peer agreed to framing v{}", nonce);
+                } else {
+                    self.tolerate(format_args!("HelloAck for framing v{}", nonce))?;
+                }
+            }
+            ControlType::Reject => {
+                let reason = String::from_utf8_lossy(&body[8..]).into_owned();
+                log::warn!("Peer rejected the connection: {}", reason);
+                self.rejection = Some(reason.clone());
+                return Err(Error::rejected(reason));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_segment_size(&self, data: &[u8]) -> Result<()> {
+        if data.len() > SHM_SEGMENT_SIZE {
+            self.tolerate(format_args!("{} byte shared memory segment", data.len()))?;
+        }
+        Ok(())
+    }
+
+    fn accept_shm(&self, nonce: u64, size: u64) -> Option<ShmChannel> {
+        let shm = self.config.shm.as_ref()?;
+        match ShmRegion::open(&shm.path) {
+            // A matching nonce proves both sides mapped the same memory
+            Ok(region) if region.len() as u64 == size && region.nonce() == nonce => {
+                Some(ShmChannel::new(region, false))
+            }
+            Ok(_) => {
+                log::debug!("Shared memory {:?} doesn't match the offer", shm.path);
+                None
+            }
+            Err(e) => {
+                log::debug!("Can't open shared memory {:?}: {}", shm.path, e);
+                None
+            }
+        }
+    }
+
+    /// Send a message through the shared-memory ring, one descriptor per segment
+    fn send_shm(&mut self, data: &[u8]) -> Result<()> {
+        let Some(channel) = self.shm.as_ref() else {
+            return Err(Error::new(ErrorKind::Other));
+        };
+        // Half a ring per segment lets the peer drain one while we fill the next
+        let segment_size = (channel.capacity() / 2).clamp(1, u32::MAX as usize);
+
+        for chunk in data.chunks(segment_size) {
+            let position = self
+                .shm
+                .as_mut()
+                .ok_or_else(|| Error::new(ErrorKind::Other))?
+                .write(chunk)?;
+            let mut hasher = Hasher::new();
+            hasher.update(chunk);
+            let segment = ShmSegment {
+                total_length: data.len() as u64,
+                position,
+                length: chunk.len() as u32,
+                crc32: hasher.finalize(),
+            };
+            self.send_packet(PacketType::ShmData, &segment.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn recv_shm(&mut self, first: &[u8], sink: &mut Sink) -> Result<usize> {
+        self.check_segment_size(first)?;
+        let mut segment = ShmSegment::from_bytes(first)?;
+        let total = segment.total_length as usize;
+        let admitted = self.admit(sink, total);
+        let mut offset = 0;
+
+        loop {
+            let len = segment.length as usize;
+            if segment.total_length as usize != total || len > total - offset {
+                return Err(Error::new(ErrorKind::InvalidPacket));
+            }
+            let channel = self
+                .shm
+                .as_mut()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidPacket))?;
+
+            let mut hasher = Hasher::new();
+            channel.read_with(segment.position, len, |part| {
+                hasher.update(part);
+                sink.put(part);
+            })?;
+            if hasher.finalize() != segment.crc32 {
+                return Err(Error::new(ErrorKind::CrcMismatch));
+            }
+            offset += len;
+            if offset == total {
+                break;
+            }
+
+            let header = self.next_header()?;
+            if header.pkt_type != PacketType::ShmData as u8 {
+                return Err(Error::new(ErrorKind::InvalidPacket));
+            }
+            let data = self.read_body(&header)?;
+            if self.config.wait_for_ack {
+                self.send_ack(header.seq)?;
+            }
+            self.check_segment_size(&data)?;
+            segment = ShmSegment::from_bytes(&data)?;
+        }
+
+        if !admitted {
+            return Err(self.over_limit(total));
+        }
+        log::debug!("Shared memory message received: {} bytes", total);
+        Ok(total)
+    }
 }
 
 impl<T: Read + Write> Read for XTransport<T> {
@@ -327,8 +1051,8 @@ impl<T: Read + Write> Write for XTransport<T> {
             return Ok(0);
         }
 
-        // Send first chunk (up to max_payload_size)
-        let to_send = core::cmp::min(buf.len(), self.config.max_payload_size);
+        // Send first chunk (up to the current payload size)
+        let to_send = core::cmp::min(buf.len(), self.payload_size());
         self.send_packet(PacketType::Data, &buf[..to_send])?;
 
         Ok(to_send)
@@ -511,6 +1235,152 @@ mod tests {
         assert_eq!(received, data);
     }
 
+    /// Helper: send `data` with ACK enabled and the given window over a pipe pair.
+    fn ack_roundtrip(data: Vec<u8>, max_frame_size: usize, window: usize) -> Vec<u8> {
+        let (c2s_reader, c2s_writer) = std::io::pipe().unwrap();
+        let (s2c_reader, s2c_writer) = std::io::pipe().unwrap();
+
+        let sender_handle = std::thread::spawn(move || {
+            let duplex = DuplexStream {
+                reader: s2c_reader,
+                writer: c2s_writer,
+            };
+            let config = TransportConfig::default()
+                .with_max_frame_size(max_frame_size)
+                .with_ack(true)
+                .with_send_window(window);
+            let mut sender = XTransport::new(duplex, config);
+            sender.send_message(&data).unwrap();
+        });
+
+        let duplex = DuplexStream {
+            reader: c2s_reader,
+            writer: s2c_writer,
+        };
+        let config = TransportConfig::default()
+            .with_max_frame_size(max_frame_size)
+            .with_ack(true);
+        let mut receiver = XTransport::new(duplex, config);
+        let received = receiver.recv_message().unwrap();
+
+        sender_handle.join().unwrap();
+        received
+    }
+
+    #[test]
+    fn send_recv_pipelined_with_ack_window() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let received = ack_roundtrip(data.clone(), 1024, 8);
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn send_recv_stop_and_wait_window_one() {
+        let data: Vec<u8> = (0..3000).map(|i| (i % 256) as u8).collect();
+        let received = ack_roundtrip(data.clone(), 256, 1);
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn send_recv_window_larger_than_packet_count() {
+        let data = vec![0x11; 1000];
+        let received = ack_roundtrip(data.clone(), 256, 64);
+        assert_eq!(received, data);
+    }
+
+    // Helper: writer counting how many write calls reach the stream.
+    struct CountingWriter {
+        buf: Vec<u8>,
+        writes: usize,
+    }
+
+    impl std::io::Read for CountingWriter {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl std::io::Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            self.buf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_without_ack_batches_window_packets_per_write() {
+        let writer = CountingWriter {
+            buf: Vec::new(),
+            writes: 0,
+        };
+        // 84-byte payloads: 10 MessageData + 1 MessageHead = 11 packets
+        let config = TransportConfig::default()
+            .with_max_frame_size(100)
+            .with_send_window(4);
+        let mut sender = XTransport::new(writer, config);
+        let data = vec![0x42; 84 * 10];
+        sender.send_message(&data).unwrap();
+        assert_eq!(sender.inner.writes, 3);
+
+        let cursor = Cursor::new(sender.inner.buf);
+        let config = TransportConfig::default().with_max_frame_size(100);
+        let mut receiver = XTransport::new(cursor, config);
+        assert_eq!(receiver.recv_message().unwrap(), data);
+    }
+
+    #[test]
+    fn payload_size_static_without_adaptive() {
+        let config = TransportConfig::default().with_max_frame_size(1024);
+        let transport = XTransport::new(Cursor::new(Vec::new()), config);
+        assert_eq!(transport.payload_size(), 1024 - HEADER_SIZE);
+        assert_eq!(transport.frame_size(), 1024);
+    }
+
+    #[test]
+    fn adaptive_payload_size_clamped_to_range() {
+        let config = TransportConfig::default()
+            .with_max_frame_size(100)
+            .with_adaptive_chunk(true);
+        let transport = XTransport::new(Cursor::new(Vec::new()), config);
+        assert_eq!(transport.frame_size(), MIN_ADAPTIVE_FRAME_SIZE);
+
+        let config = TransportConfig::default()
+            .with_max_frame_size(1024 * 1024)
+            .with_adaptive_chunk(true);
+        let transport = XTransport::new(Cursor::new(Vec::new()), config);
+        assert_eq!(transport.frame_size(), MAX_FRAME_SIZE);
+    }
+
+    #[test]
+    fn adaptive_send_roundtrip_across_size_changes() {
+        let mut buf: Vec<u8> = Vec::new();
+        let messages: Vec<Vec<u8>> = (0..6)
+            .map(|n| (0..64 * 1024 + n).map(|i| (i % 253) as u8).collect())
+            .collect();
+
+        {
+            let config = TransportConfig::default()
+                .with_max_frame_size(1024)
+                .with_adaptive_chunk(true);
+            let mut sender = XTransport::new(Cursor::new(&mut buf), config);
+            for msg in &messages {
+                sender.send_message(msg).unwrap();
+            }
+            let frame = sender.frame_size();
+            assert!((MIN_ADAPTIVE_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&frame));
+        }
+
+        let config = TransportConfig::default();
+        let mut receiver = XTransport::new(Cursor::new(buf), config);
+        for expected in &messages {
+            assert_eq!(&receiver.recv_message().unwrap(), expected);
+        }
+    }
+
     #[test]
     fn recv_message_truncated_header() {
         let buf = vec![0u8; 8];
@@ -711,6 +1581,41 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn resync_skips_the_rest_of_a_failed_message() {
+        // A three-packet message whose second packet is corrupted, then a
+        // complete single-packet message
+        let head = MessageHead::new(6, 1, 3);
+        let mut buf = build_raw_packet(PacketType::MessageHead, 0, &head.to_bytes());
+        buf.extend_from_slice(&build_raw_packet(PacketType::MessageData, 1, &[1, 2]));
+        buf.extend_from_slice(&build_corrupted_packet(PacketType::MessageData, 2, &[3, 4]));
+        buf.extend_from_slice(&build_raw_packet(PacketType::MessageData, 3, &[5, 6]));
+        buf.extend_from_slice(&build_raw_packet(PacketType::Data, 4, b"next"));
+
+        let mut receiver = XTransport::new(Cursor::new(buf.clone()), TransportConfig::default());
+        assert!(receiver.recv_message().is_err());
+        // Without resync the leftover fragment is taken for a new message
+        assert!(receiver.recv_message().is_err());
+
+        let mut receiver = XTransport::new(Cursor::new(buf), TransportConfig::default());
+        assert!(receiver.recv_message().is_err());
+        receiver.resync();
+        assert_eq!(receiver.recv_message().unwrap(), b"next");
+    }
+
+    #[test]
+    fn interrupted_message_keeps_the_next_one() {
+        // The peer abandons a message half-way and sends a new one
+        let head = MessageHead::new(4, 1, 2);
+        let mut buf = build_raw_packet(PacketType::MessageHead, 0, &head.to_bytes());
+        buf.extend_from_slice(&build_raw_packet(PacketType::MessageData, 1, &[1, 2]));
+        buf.extend_from_slice(&build_raw_packet(PacketType::Data, 2, b"next"));
+
+        let mut receiver = XTransport::new(Cursor::new(buf), TransportConfig::default());
+        assert!(receiver.recv_message().is_err());
+        assert_eq!(receiver.recv_message().unwrap(), b"next");
+    }
+
     #[test]
     fn recv_packet_internal_crc_mismatch() {
         // Build a corrupted raw packet and try to receive via recv_packet
@@ -752,4 +1657,697 @@ mod tests {
         let result = receiver.recv_message();
         assert!(result.is_err());
     }
+
+    type PipeTransport = XTransport<DuplexStream<std::io::PipeReader, std::io::PipeWriter>>;
+
+    /// Helper: connected pair over pipes, each side with its own config
+    fn duplex_pair(
+        client: TransportConfig,
+        server: TransportConfig,
+    ) -> (PipeTransport, PipeTransport) {
+        let (c2s_reader, c2s_writer) = std::io::pipe().unwrap();
+        let (s2c_reader, s2c_writer) = std::io::pipe().unwrap();
+        let client = XTransport::new(
+            DuplexStream {
+                reader: s2c_reader,
+                writer: c2s_writer,
+            },
+            client,
+        );
+        let server = XTransport::new(
+            DuplexStream {
+                reader: c2s_reader,
+                writer: s2c_writer,
+            },
+            server,
+        );
+        (client, server)
+    }
+
+    fn shm_test_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("virga-{}-{}", name, std::process::id()))
+    }
+
+    /// Client offers shm, then one request/response so both sides see the handshake
+    fn shm_handshake(ack: bool, server_shm: bool, path: &std::path::Path) -> (bool, bool) {
+        let big: Vec<u8> = (0..300_000).map(|i| (i % 253) as u8).collect();
+        let client_config = TransportConfig::default()
+            .with_ack(ack)
+            .with_shm(path, crate::transport::xtransport::MIN_SHM_SIZE);
+        let mut server_config = TransportConfig::default().with_ack(ack);
+        if server_shm {
+            server_config = server_config.with_shm(path, 0);
+        }
+        let (mut client, mut server) = duplex_pair(client_config, server_config);
+
+        let expected = big.clone();
+        let server_handle = std::thread::spawn(move || {
+            assert_eq!(server.recv_message().unwrap(), b"hello");
+            server.send_message(b"world").unwrap();
+            assert_eq!(server.recv_message().unwrap(), expected);
+            server.send_message(&expected).unwrap();
+            server.shm_active()
+        });
+
+        client.offer_shm().unwrap();
+        client.send_message(b"hello").unwrap();
+        assert_eq!(client.recv_message().unwrap(), b"world");
+        client.send_message(&big).unwrap();
+        assert_eq!(client.recv_message().unwrap(), big);
+
+        let server_active = server_handle.join().unwrap();
+        (client.shm_active(), server_active)
+    }
+
+    #[test]
+    fn shm_negotiated_large_messages() {
+        let path = shm_test_path("shm-negotiated");
+        let active = shm_handshake(false, true, &path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(active, (true, true));
+    }
+
+    #[test]
+    fn shm_negotiated_with_ack() {
+        let path = shm_test_path("shm-ack");
+        let active = shm_handshake(true, true, &path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(active, (true, true));
+    }
+
+    #[test]
+    fn shm_falls_back_when_peer_declines() {
+        let path = shm_test_path("shm-declined");
+        let active = shm_handshake(false, false, &path);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(active, (false, false));
+    }
+
+    #[test]
+    fn shm_offer_without_region_is_noop() {
+        let mut buf: Vec<u8> = Vec::new();
+        let mut transport = XTransport::new(Cursor::new(&mut buf), TransportConfig::default());
+        transport.offer_shm().unwrap();
+        assert!(!transport.shm_active());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn recv_message_shm_data_without_region_fails() {
+        let segment = ShmSegment {
+            total_length: 4,
+            position: 0,
+            length: 4,
+            crc32: 0,
+        };
+        let buf = build_raw_packet(PacketType::ShmData, 0, &segment.to_bytes());
+        let mut receiver = XTransport::new(Cursor::new(buf), TransportConfig::default());
+        let err = receiver.recv_message().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidPacket);
+    }
+
+    #[test]
+    fn oversized_message_head_is_drained_not_allocated() {
+        use std::os::unix::net::UnixStream;
+
+        let (mut peer, local) = UnixStream::pair().unwrap();
+        // A head claiming 8 GiB followed by one real fragment, then a normal message
+        let head = MessageHead::new(8 << 30, 1, 1).to_bytes();
+        let mut wire = build_raw_packet(PacketType::MessageHead, 0, &head);
+        wire.extend(build_raw_packet(PacketType::MessageData, 1, &[0xAA; 64]));
+        wire.extend(build_raw_packet(PacketType::Data, 2, &[7, 8, 9]));
+        peer.write_all(&wire).unwrap();
+
+        let mut receiver = XTransport::new(local, TransportConfig::default());
+        receiver.set_recv_limit(Some(1 << 20));
+        let err = receiver.recv_message().unwrap_err();
+        assert_eq!(err.io_kind(), std::io::ErrorKind::OutOfMemory);
+        assert_eq!(receiver.recv_message().unwrap(), vec![7, 8, 9]);
+
+        // Caller-owned slices allocate nothing, so the limit doesn't apply
+        let head = MessageHead::new(64, 2, 1).to_bytes();
+        let mut wire = build_raw_packet(PacketType::MessageHead, 3, &head);
+        wire.extend(build_raw_packet(PacketType::MessageData, 4, &[0xBB; 64]));
+        peer.write_all(&wire).unwrap();
+        receiver.set_recv_limit(Some(16));
+        let mut buf = [0u8; 64];
+        let len = receiver
+            .recv_message_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .unwrap();
+        assert_eq!((len, buf), (64, [0xBB; 64]));
+    }
+
+    #[test]
+    fn recv_message_skips_unknown_control() {
+        let mut buf = build_raw_packet(PacketType::Control, 0, &[0xEE, 1, 2, 3]);
+        buf.extend(build_raw_packet(PacketType::Data, 1, &[7, 8, 9]));
+        let mut receiver = XTransport::new(Cursor::new(buf), TransportConfig::default());
+        assert_eq!(receiver.recv_message().unwrap(), vec![7, 8, 9]);
+    }
+
+    #[test]
+    fn strict_mode_rejects_what_lenient_mode_skips() {
+        let strict = || TransportConfig::default().with_strict(true);
+        let mut pong = std::vec![ControlType::Pong as u8];
+        pong.extend_from_slice(&[0u8; 12]);
+        let mut accept = std::vec![ControlType::ShmAccept as u8];
+        accept.extend_from_slice(&9u64.to_le_bytes());
+        let mut head = MessageHead::new(3, 1, 1).to_bytes().to_vec();
+        head.push(0);
+        let cases: [&[(PacketType, &[u8])]; 5] = [
+            // unknown control type
+            &[(PacketType::Control, &[0x7E, 1, 2, 3])],
+            // control body longer than defined
+            &[(PacketType::Control, &pong)],
+            // shared memory accept nobody offered
+            &[(PacketType::Control, &accept)],
+            // message head longer than defined
+            &[
+                (PacketType::MessageHead, &head),
+                (PacketType::MessageData, &[7, 8, 9]),
+            ],
+            // fragments shorter than the announced length
+            &[
+                (
+                    PacketType::MessageHead,
+                    &MessageHead::new(4, 1, 1).to_bytes(),
+                ),
+                (PacketType::MessageData, &[7, 8, 9]),
+            ],
+        ];
+        for packets in cases {
+            let mut buf = Vec::new();
+            for (seq, (pkt_type, data)) in packets.iter().enumerate() {
+                buf.extend(build_raw_packet(*pkt_type, seq as u32, data));
+            }
+            if packets[0].0 == PacketType::Control {
+                buf.extend(build_raw_packet(PacketType::Data, 1, &[7, 8, 9]));
+            }
+            let mut lenient = XTransport::new(Cursor::new(buf.clone()), TransportConfig::default());
+            assert_eq!(lenient.recv_message().unwrap()[..3], [7, 8, 9]);
+            let mut receiver = XTransport::new(Cursor::new(buf), strict());
+            let err = receiver.recv_message().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidPacket, "{:?}", packets);
+        }
+    }
+
+    #[test]
+    fn extension_controls_bypass_the_message_stream() {
+        let (mut client, mut server) = duplex_pair(
+            TransportConfig::default(),
+            TransportConfig::default().with_strict(true),
+        );
+        let (tx, rx) = std::sync::mpsc::channel();
+        server.set_extension_handler(Some(Box::new(move |ctrl, body: &[u8]| {
+            tx.send((ctrl, body.to_vec())).unwrap();
+        })));
+        assert_eq!(
+            client
+                .send_extension(ControlType::Ping as u8, &[])
+                .unwrap_err()
+                .kind(),
+            ErrorKind::InvalidPacket
+        );
+        client.send_extension(0x80, b"50%").unwrap();
+        client.send_extension(0xFF, &[]).unwrap();
+        client.send_message(b"payload").unwrap();
+
+        assert_eq!(server.recv_message().unwrap(), b"payload");
+        assert_eq!(rx.try_recv().unwrap(), (0x80, b"50%".to_vec()));
+        assert_eq!(rx.try_recv().unwrap(), (0xFF, Vec::new()));
+
+        server.set_extension_handler(None);
+        client.send_extension(0x81, b"dropped").unwrap();
+        client.send_message(b"next").unwrap();
+        assert_eq!(server.recv_message().unwrap(), b"next");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn ping_answered_while_peer_receives() {
+        let (mut client, mut server) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        let server = std::thread::spawn(move || server.recv_message().unwrap());
+        client.ping(42).unwrap();
+        client.send_message(b"after ping").unwrap();
+        assert_eq!(server.join().unwrap(), b"after ping");
+    }
+
+    #[test]
+    fn time_probe_answered_while_peer_receives() {
+        let (mut client, mut server) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        let server = std::thread::spawn(move || server.recv_message().unwrap());
+        let before = crate::clock::now_nanos();
+        let (received, sent) = client.time_probe(7).unwrap().unwrap();
+        assert!(before <= received && received <= sent);
+        assert!(sent <= crate::clock::now_nanos());
+
+        client.send_message(b"after probe").unwrap();
+        assert_eq!(server.join().unwrap(), b"after probe");
+    }
+
+    #[test]
+    fn time_probe_yields_to_a_message() {
+        let buf = build_raw_packet(PacketType::Data, 0, &[1, 2, 3]);
+        let mut transport = XTransport::new(
+            DuplexStream {
+                reader: Cursor::new(buf),
+                writer: Vec::new(),
+            },
+            TransportConfig::default(),
+        );
+        assert_eq!(transport.time_probe(1).unwrap(), None);
+        assert_eq!(transport.time_probe(2).unwrap(), None);
+        assert_eq!(transport.recv_message().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn ping_keeps_data_that_arrives_first() {
+        let mut buf = build_raw_packet(PacketType::Data, 0, &[1, 2, 3]);
+        buf.extend(build_raw_packet(
+            PacketType::Control,
+            1,
+            &[5, 9, 0, 0, 0, 0, 0, 0, 0],
+        ));
+        let mut transport = XTransport::new(
+            DuplexStream {
+                reader: Cursor::new(buf),
+                writer: Vec::new(),
+            },
+            TransportConfig::default(),
+        );
+        transport.ping(9).unwrap();
+        assert_eq!(transport.recv_message().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn ping_after_poll_keeps_the_pending_message() {
+        let (mut sender, mut receiver) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        sender.send_message(b"queued").unwrap();
+        assert!(receiver.poll_message().unwrap());
+        receiver.ping(9).unwrap();
+        assert_eq!(receiver.recv_message().unwrap(), b"queued");
+    }
+
+    #[test]
+    fn back_to_back_pings_keep_data_that_arrives_first() {
+        let (mut client, mut server) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        server.send_message(b"early").unwrap();
+        client.ping(1).unwrap();
+        client.ping(2).unwrap();
+        assert_eq!(client.recv_message().unwrap(), b"early");
+        // The server answers both pings on its next receive
+        client.send_message(b"after pings").unwrap();
+        assert_eq!(server.recv_message().unwrap(), b"after pings");
+        server.send_message(b"reply").unwrap();
+        assert_eq!(client.recv_message().unwrap(), b"reply");
+    }
+
+    #[test]
+    fn compressed_control_flags_only_the_next_message() {
+        let (mut sender, mut receiver) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        sender.send_message_compressed(b"packed").unwrap();
+        sender.send_message(b"plain").unwrap();
+        assert_eq!(receiver.recv_message().unwrap(), b"packed");
+        assert!(receiver.last_compressed());
+        assert_eq!(receiver.recv_message().unwrap(), b"plain");
+        assert!(!receiver.last_compressed());
+    }
+
+    #[test]
+    fn batch_control_flags_only_the_next_message() {
+        let (mut sender, mut receiver) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        sender.send_message_batch(b"packed").unwrap();
+        sender.send_message(b"plain").unwrap();
+        assert_eq!(receiver.recv_message().unwrap(), b"packed");
+        assert!(receiver.last_batch());
+        assert!(!receiver.last_compressed());
+        assert_eq!(receiver.recv_message().unwrap(), b"plain");
+        assert!(!receiver.last_batch());
+    }
+
+    #[test]
+    fn poll_message_handles_controls_and_keeps_the_message() {
+        let (mut sender, mut receiver) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        sender
+            .send_control(ControlType::Ping, &7u64.to_le_bytes())
+            .unwrap();
+        sender.send_message_compressed(b"packed").unwrap();
+        assert!(!receiver.message_pending());
+        assert!(!receiver.poll_message().unwrap());
+        // The Compressed control is handled too; the data header after it is kept
+        assert!(!receiver.poll_message().unwrap());
+        assert!(receiver.poll_message().unwrap());
+        assert!(receiver.message_pending());
+        assert_eq!(receiver.recv_message().unwrap(), b"packed");
+        assert!(receiver.last_compressed());
+        assert!(!receiver.message_pending());
+        // The ping was answered while polling
+        assert!(!sender.poll_message().unwrap());
+        assert_eq!(sender.last_pong, Some(7));
+    }
+
+    #[test]
+    fn goaway_is_seen_with_the_next_message() {
+        let (mut sender, mut receiver) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        sender.go_away(1).unwrap();
+        sender.send_message(b"last reply").unwrap();
+        assert_eq!(receiver.goaway(), None);
+        assert_eq!(receiver.recv_message().unwrap(), b"last reply");
+        assert_eq!(receiver.goaway(), Some(1));
+    }
+
+    #[test]
+    fn close_reason_is_seen_before_eof() {
+        let (mut sender, mut receiver) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        sender.send_message(b"last reply").unwrap();
+        sender.close(3).unwrap();
+        drop(sender);
+        assert_eq!(receiver.recv_message().unwrap(), b"last reply");
+        assert_eq!(receiver.close_reason(), None);
+        assert!(receiver.recv_message().is_err());
+        assert_eq!(receiver.close_reason(), Some(3));
+    }
+
+    #[test]
+    fn hello_switches_both_sides_to_v2() {
+        let (mut client, mut server) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        client.offer_version().unwrap();
+        client.send_message(b"request").unwrap();
+        assert_eq!(server.recv_message().unwrap(), b"request");
+        assert_eq!(server.version(), 2);
+
+        server.send_message(b"reply").unwrap();
+        assert_eq!(client.version(), 1);
+        assert_eq!(client.recv_message().unwrap(), b"reply");
+        assert_eq!(client.version(), 2);
+
+        let large = vec![7u8; 20_000];
+        client.send_message(&large).unwrap();
+        assert_eq!(server.recv_message().unwrap(), large);
+    }
+
+    #[test]
+    fn hello_settles_on_the_lower_max_version() {
+        let (mut client, mut server) = duplex_pair(
+            TransportConfig::default().with_ack(true),
+            TransportConfig::default()
+                .with_ack(true)
+                .with_max_version(1),
+        );
+        client.offer_version().unwrap();
+        let sender = std::thread::spawn(move || {
+            client.send_message(b"request").unwrap();
+            client
+        });
+        assert_eq!(server.recv_message().unwrap(), b"request");
+        let client = sender.join().unwrap();
+        assert_eq!((client.version(), server.version()), (1, 1));
+    }
+
+    #[test]
+    fn v1_only_side_sends_no_hello() {
+        let mut out = Vec::new();
+        let config = TransportConfig::default().with_max_version(1);
+        let mut transport = XTransport::new(Cursor::new(&mut out), config);
+        transport.offer_version().unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn reject_fails_the_next_receive_with_the_reason() {
+        let (mut sender, mut receiver) =
+            duplex_pair(TransportConfig::default(), TransportConfig::default());
+        sender.reject("cid 5 is not allowed").unwrap();
+        let err = receiver.recv_message().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Rejected);
+        assert_eq!(err.rejection().as_deref(), Some("cid 5 is not allowed"));
+        assert_eq!(receiver.rejection(), Some("cid 5 is not allowed"));
+    }
+
+    fn send_all(messages: &[&[u8]], max_frame_size: usize) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        let config = TransportConfig::default().with_max_frame_size(max_frame_size);
+        let mut sender = XTransport::new(Cursor::new(&mut buf), config);
+        for msg in messages {
+            sender.send_message(msg).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn recv_vectored_splits_header_and_body() {
+        let data: Vec<u8> = (0..5000).map(|i| (i % 256) as u8).collect();
+        for frame in [8192, 1024] {
+            let buf = send_all(&[&data], frame);
+            let config = TransportConfig::default().with_max_frame_size(frame);
+            let mut receiver = XTransport::new(Cursor::new(buf), config);
+
+            let mut head = [0u8; 8];
+            let mut body = vec![0u8; 4992];
+            let mut bufs = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut body)];
+            assert_eq!(receiver.recv_message_vectored(&mut bufs).unwrap(), 5000);
+            assert_eq!(&head[..], &data[..8]);
+            assert_eq!(&body[..], &data[8..]);
+        }
+    }
+
+    #[test]
+    fn recv_vectored_truncates_and_keeps_framing() {
+        let big: Vec<u8> = (0..3000).map(|i| (i % 256) as u8).collect();
+        let buf = send_all(&[&big, b"next"], 1024);
+        let config = TransportConfig::default().with_max_frame_size(1024);
+        let mut receiver = XTransport::new(Cursor::new(buf), config);
+
+        let mut small = [0u8; 10];
+        let n = receiver
+            .recv_message_vectored(&mut [IoSliceMut::new(&mut small)])
+            .unwrap();
+        assert_eq!(n, 3000);
+        assert_eq!(&small[..], &big[..10]);
+        assert_eq!(receiver.recv_message().unwrap(), b"next");
+    }
+
+    #[test]
+    fn recv_vectored_detects_corruption() {
+        let buf = build_corrupted_packet(PacketType::Data, 0, &[1, 2, 3]);
+        let mut receiver = XTransport::new(Cursor::new(buf), TransportConfig::default());
+        let mut out = [0u8; 3];
+        let err = receiver
+            .recv_message_vectored(&mut [IoSliceMut::new(&mut out)])
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::CrcMismatch);
+    }
+
+    #[test]
+    fn recv_loaned_reuses_buffer() {
+        let big: Vec<u8> = (0..5000).map(|i| (i % 256) as u8).collect();
+        let buf = send_all(&[&big, b"tiny", b""], 1024);
+        let config = TransportConfig::default().with_max_frame_size(1024);
+        let mut receiver = XTransport::new(Cursor::new(buf), config);
+
+        let first = receiver.recv_message_loaned().unwrap();
+        assert_eq!(first, &big[..]);
+        let ptr = first.as_ptr();
+
+        let second = receiver.recv_message_loaned().unwrap();
+        assert_eq!(second, b"tiny");
+        assert_eq!(second.as_ptr(), ptr);
+
+        assert!(receiver.recv_message_loaned().unwrap().is_empty());
+    }
+
+    #[test]
+    fn release_buffers_frees_loan_buffer() {
+        let big: Vec<u8> = (0..5000).map(|i| (i % 256) as u8).collect();
+        let buf = send_all(&[&big, b"tiny"], 1024);
+        let config = TransportConfig::default().with_max_frame_size(1024);
+        let mut receiver = XTransport::new(Cursor::new(buf), config);
+
+        assert_eq!(receiver.buffered_bytes(), 0);
+        receiver.recv_message_loaned().unwrap();
+        assert!(receiver.buffered_bytes() >= big.len());
+        receiver.release_buffers();
+        assert_eq!(receiver.buffered_bytes(), 0);
+        assert_eq!(receiver.recv_message_loaned().unwrap(), b"tiny");
+    }
+
+    /// Writer that keeps a copy of every write, one entry per call
+    struct Recording<W> {
+        inner: W,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl<W: std::io::Write> std::io::Write for Recording<W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let n = self.inner.write(buf)?;
+            self.writes.push(buf[..n].to_vec());
+            Ok(n)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    type FixtureStream<R> = DuplexStream<R, Recording<Vec<u8>>>;
+
+    /// Config of one side: `<side> max_version <n>` and `<side> frame_size <n>`
+    /// steps (side may be `both`) apply before any packet is exchanged
+    fn fixture_config(steps: &[crate::fixtures::Step], side: &str) -> TransportConfig {
+        let mut config = TransportConfig::default().with_ack(false);
+        for step in steps {
+            if step.word(0) != side && step.word(0) != "both" {
+                continue;
+            }
+            match step.word(1) {
+                "max_version" => config = config.with_max_version(step.number(2) as u8),
+                "frame_size" => config = config.with_max_frame_size(step.number(2) as usize),
+                _ => {}
+            }
+        }
+        config
+    }
+
+    /// Run one step on its side. Returns true when the side closes its
+    /// stream afterwards
+    fn fixture_step<T: Read + Write>(
+        transport: &mut XTransport<T>,
+        step: &crate::fixtures::Step,
+    ) -> bool {
+        let context = step.words.join(" ");
+        match step.word(1) {
+            "max_version" | "frame_size" => {}
+            "hello" => transport.offer_version().unwrap(),
+            "send" => transport.send_message(&step.payload(2)).unwrap(),
+            "recv" => assert_eq!(
+                transport.recv_message().unwrap(),
+                step.payload(2),
+                "{}",
+                context
+            ),
+            "eof" => {
+                let err = transport.recv_message().unwrap_err();
+                assert_eq!(err.kind(), ErrorKind::UnexpectedEof, "{}: {}", context, err);
+            }
+            "rejected" => {
+                let err = transport.recv_message().unwrap_err();
+                assert_eq!(
+                    err.rejection().as_deref(),
+                    Some(step.word(2)),
+                    "{}",
+                    context
+                );
+            }
+            "version" => assert_eq!(transport.version() as u64, step.number(2), "{}", context),
+            "seen_goaway" => assert_eq!(transport.goaway(), Some(step.number(2)), "{}", context),
+            "seen_close" => {
+                assert_eq!(
+                    transport.close_reason(),
+                    Some(step.number(2)),
+                    "{}",
+                    context
+                )
+            }
+            "goaway" => transport.go_away(step.number(2)).unwrap(),
+            "close" => {
+                transport.close(step.number(2)).unwrap();
+                return true;
+            }
+            "reject" => {
+                transport.reject(step.word(2)).unwrap();
+                return true;
+            }
+            other => panic!("unknown fixture action {:?}", other),
+        }
+        false
+    }
+
+    /// Play both sides of a fixture against each other over pipes, in
+    /// order, and collect what each step wrote
+    fn fixture_live(steps: &[crate::fixtures::Step]) -> Vec<Vec<Vec<u8>>> {
+        let (c2s_reader, c2s_writer) = std::io::pipe().unwrap();
+        let (s2c_reader, s2c_writer) = std::io::pipe().unwrap();
+        let endpoint = |reader, writer, side| {
+            let stream = DuplexStream {
+                reader,
+                writer: Recording {
+                    inner: writer,
+                    writes: Vec::new(),
+                },
+            };
+            Some(XTransport::new(stream, fixture_config(steps, side)))
+        };
+        let mut client = endpoint(s2c_reader, c2s_writer, "client");
+        let mut server = endpoint(c2s_reader, s2c_writer, "server");
+        let mut written = Vec::new();
+        for step in steps {
+            let side = match step.word(0) {
+                "client" => &mut client,
+                "server" => &mut server,
+                _ => {
+                    written.push(Vec::new());
+                    continue;
+                }
+            };
+            let transport = side.as_mut().expect("step after the side closed");
+            let closes = fixture_step(transport, step);
+            written.push(std::mem::take(&mut transport.inner.writer.writes));
+            if closes {
+                *side = None;
+            }
+        }
+        written
+    }
+
+    /// Play one side of a fixture alone: it reads exactly the bytes the
+    /// fixture records for the other side and must write exactly its own
+    fn fixture_replay(name: &str, steps: &[crate::fixtures::Step], side: &str) {
+        let input: Vec<u8> = steps
+            .iter()
+            .filter(|step| step.word(0) != side)
+            .flat_map(|step| step.bytes.iter().copied())
+            .collect();
+        let stream: FixtureStream<Cursor<Vec<u8>>> = DuplexStream {
+            reader: Cursor::new(input),
+            writer: Recording {
+                inner: Vec::new(),
+                writes: Vec::new(),
+            },
+        };
+        let mut transport = XTransport::new(stream, fixture_config(steps, side));
+        for step in steps.iter().filter(|step| step.word(0) == side) {
+            let closes = fixture_step(&mut transport, step);
+            let wrote = std::mem::take(&mut transport.inner.writer.writes).concat();
+            assert_eq!(wrote, step.bytes, "{}: {}", name, step.words.join(" "));
+            if closes {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn wire_format_matches_fixtures() {
+        for name in [
+            "xtransport/v1-message.txt",
+            "xtransport/hello.txt",
+            "xtransport/multipacket.txt",
+            "xtransport/goaway-close.txt",
+            "xtransport/reject.txt",
+        ] {
+            let steps = crate::fixtures::load(name);
+            crate::fixtures::verify(name, &steps, &fixture_live(&steps));
+            let steps = crate::fixtures::load(name);
+            fixture_replay(name, &steps, "client");
+            fixture_replay(name, &steps, "server");
+        }
+    }
 }
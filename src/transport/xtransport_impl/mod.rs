@@ -9,7 +9,12 @@
 //! # 特点
 //! - 针对 vsock 优化的传输协议
 //! - 轻量级设计
+//! - 可选 io_uring IO 路径（`use-io-uring` 特性）
 
 mod transfer_handler;
+#[cfg(feature = "use-io-uring")]
+mod uring;
 
 pub use transfer_handler::XTransportHandler;
+#[cfg(feature = "use-io-uring")]
+pub use uring::UringStream;
@@ -10,17 +10,104 @@
 //! - 针对 vsock 优化的传输协议
 //! - 轻量级设计
 
-use crate::error::{Result, VirgeError};
-use crate::transport::xtransport::{TransportConfig, XTransport};
+use crate::addr::Addr;
+use crate::auth::secure::SecureChannel;
+use crate::budget::{self, BufferAccount, BUDGET_WAIT_TIMEOUT};
+use crate::buffers;
+use crate::clock::{self, Sample};
+use crate::compression::CompressionContext;
+use crate::error::{ConnContext, Result, ResultExt, VirgeError};
+use crate::mux::{poll_readable, Wake};
+use crate::stats::ConnectionStats;
+use crate::transport::batch::{Batcher, Coalescing};
+use crate::transport::extension;
+use crate::transport::xtransport::{ExtensionHandler, ShmConfig, TransportConfig, XTransport};
+use crate::transport::{truncate_reason, unpack, FrameHook, MessageKind, RecvLoan};
+use crate::units::ByteSize;
+use crate::{GoAwayReason, ShutdownReason};
 use log::*;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, IoSliceMut};
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use vsock::{VsockAddr, VsockStream};
 
+#[cfg(feature = "use-io-uring")]
+use super::uring::UringStream;
+
+/// xtransport 使用的底层 IO：默认为标准阻塞 socket 读写，可选 io_uring
+enum VsockIo {
+    Std(VsockStream),
+    #[cfg(feature = "use-io-uring")]
+    Uring(Box<UringStream<VsockStream>>),
+}
+
+impl VsockIo {
+    /// io_uring 路径记下超时自行计时，因此读超时须经这里设置而不是直接设在 socket 上
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> std::io::Result<()> {
+        match self {
+            VsockIo::Std(s) => s.set_read_timeout(timeout),
+            #[cfg(feature = "use-io-uring")]
+            VsockIo::Uring(s) => s.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl std::io::Read for VsockIo {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            VsockIo::Std(s) => s.read(buf),
+            #[cfg(feature = "use-io-uring")]
+            VsockIo::Uring(s) => s.read(buf),
+        }
+    }
+}
+
+impl std::io::Write for VsockIo {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            VsockIo::Std(s) => s.write(buf),
+            #[cfg(feature = "use-io-uring")]
+            VsockIo::Uring(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            VsockIo::Std(s) => s.flush(),
+            #[cfg(feature = "use-io-uring")]
+            VsockIo::Uring(s) => s.flush(),
+        }
+    }
+}
+
 /// XTransport 传输协议实现
 ///
 /// 直接管理 vsock 连接并使用 xtransport 进行传输。
 pub struct XTransportHandler {
     stream: Option<VsockStream>,
-    transport: Option<XTransport<VsockStream>>,
+    transport: Option<XTransport<VsockIo>>,
+    send_window: Option<usize>,
+    adaptive_chunk: bool,
+    io_uring: bool,
+    shm: Option<ShmConfig>,
+    stats: ConnectionStats,
+    conn: ConnContext,
+    secure: Option<SecureChannel>,
+    compression: CompressionContext,
+    coalescing: Option<Coalescing>,
+    batch: Option<Batcher>,
+    unbatched: VecDeque<Vec<u8>>,
+    plain: Vec<u8>,
+    budget: BufferAccount,
+    pending: usize,
+    idle_timeout: Option<Duration>,
+    pings: u64,
+    max_version: u8,
+    strict: bool,
+    /// 上一次接收因空闲超时失败
+    idle: bool,
 }
 
 impl XTransportHandler {
@@ -28,24 +115,172 @@ impl XTransportHandler {
         Self {
             stream: None,
             transport: None,
+            send_window: None,
+            adaptive_chunk: false,
+            io_uring: false,
+            shm: None,
+            stats: ConnectionStats::default(),
+            conn: ConnContext::default(),
+            secure: None,
+            compression: CompressionContext::default(),
+            coalescing: None,
+            batch: None,
+            unbatched: VecDeque::new(),
+            plain: Vec::new(),
+            budget: BufferAccount::default(),
+            pending: 0,
+            idle_timeout: None,
+            pings: 0,
+            max_version: crate::transport::MAX_PROTOCOL_VERSION,
+            strict: false,
+            idle: false,
+        }
+    }
+
+    /// 设置发送窗口（同时在途的最大包数），不设置时使用 xtransport 默认值
+    pub fn with_send_window(mut self, packets: usize) -> Self {
+        self.send_window = Some(packets);
+        self
+    }
+
+    /// 开启自适应分片：以配置的 chunk_size 为起点，根据吞吐和 ACK 延迟调整
+    pub fn with_adaptive_chunk(mut self, enabled: bool) -> Self {
+        self.adaptive_chunk = enabled;
+        self
+    }
+
+    /// 使用 io_uring 读写 vsock（默认使用标准阻塞 socket 读写），
+    /// 需要启用 `use-io-uring` 特性
+    pub fn with_io_uring(mut self, enabled: bool) -> Self {
+        self.io_uring = enabled;
+        self
+    }
+
+    /// 启用共享内存快速通道：大消息负载经共享内存传输，vsock 只承载控制帧。
+    /// 客户端以 `size` 创建区域并在连接后发起协商，服务端按对端提议打开同一文件；
+    /// 任一侧不支持时自动退回纯 vsock 传输
+    pub fn with_shared_memory(mut self, path: impl Into<PathBuf>, size: usize) -> Self {
+        self.shm = Some(ShmConfig {
+            path: path.into(),
+            size,
+        });
+        self
+    }
+
+    /// 本连接内部缓冲的上限，超过时每次接收前释放复用缓冲
+    pub fn with_memory_limit(mut self, limit: Option<ByteSize>) -> Self {
+        self.budget = BufferAccount::with_limit(limit);
+        self
+    }
+
+    /// 开启发送合批：小消息攒成一帧发送，接收方按原边界拆回
+    pub fn with_coalescing(mut self, coalescing: Option<Coalescing>) -> Self {
+        self.coalescing = coalescing;
+        self
+    }
+
+    /// 最高使用的分帧版本，客户端连接后提议、服务端应答时以此为上限
+    pub fn with_max_version(mut self, version: u8) -> Self {
+        self.max_version = version;
+        self
+    }
+
+    /// 严格模式：未知或不该出现的控制包、超出定义长度的包头等按协议错误处理，
+    /// 默认记录后跳过以兼容较新的对端
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    fn make_io(&self, stream: VsockStream) -> Result<VsockIo> {
+        if !self.io_uring {
+            return Ok(VsockIo::Std(stream));
+        }
+
+        #[cfg(feature = "use-io-uring")]
+        {
+            debug!("XTransport using io_uring IO path");
+            Ok(VsockIo::Uring(Box::new(UringStream::new(stream)?)))
+        }
+        #[cfg(not(feature = "use-io-uring"))]
+        {
+            drop(stream);
+            Err(VirgeError::ConfigError(
+                "io_uring requested but the use-io-uring feature is not enabled".to_string(),
+            ))
+        }
+    }
+
+    fn transport_config(&self, chunksize: u32, isack: bool) -> TransportConfig {
+        let config = TransportConfig::default()
+            .with_max_frame_size(chunksize as usize)
+            .with_ack(isack)
+            .with_adaptive_chunk(self.adaptive_chunk)
+            .with_max_version(self.max_version)
+            .with_strict(self.strict);
+        let config = match self.send_window {
+            Some(window) => config.with_send_window(window),
+            None => config,
+        };
+        match &self.shm {
+            Some(shm) => config.with_shm(&shm.path, shm.size),
+            None => config,
         }
     }
 }
 
+/// 把连接上收到的旁路帧交给进程级注册表
+fn extension_handler(conn: ConnContext) -> ExtensionHandler {
+    Box::new(move |frame_type, data| {
+        extension::dispatch(frame_type, conn, data);
+    })
+}
+
+impl Default for XTransportHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl XTransportHandler {
     pub fn connect(&mut self, cid: u32, port: u32, chunksize: u32, isack: bool) -> Result<()> {
-        debug!("XTransport connecting to cid={}, port={}", cid, port);
+        let addr = Addr::new(cid, port);
+        debug!("XTransport connecting to {}", addr);
 
-        let stream = VsockStream::connect(&VsockAddr::new(cid, port))
-            .map_err(|e| VirgeError::ConnectionError(format!("Failed to connect vsock: {}", e)))?;
+        let started = Instant::now();
+        let conn = ConnContext::new(cid, port);
+        let stream = VsockStream::connect(&VsockAddr::from(addr))
+            .map_err(|e| {
+                VirgeError::connection_io("Failed to connect vsock", e).with_addr(cid, port)
+            })
+            .ctx(&conn, "connect")?;
 
-        let config = TransportConfig::default()
-            .with_max_frame_size(chunksize as usize)
-            .with_ack(isack);
-        let transport = XTransport::new(stream.try_clone()?, config);
+        let config = self.transport_config(chunksize, isack);
+        let io = stream
+            .try_clone()
+            .map_err(VirgeError::from)
+            .and_then(|s| self.make_io(s))
+            .ctx(&conn, "connect")?;
+        let mut transport = XTransport::new(io, config);
+        transport.set_extension_handler(Some(extension_handler(conn)));
+        transport
+            .offer_shm()
+            .map_err(|e| VirgeError::xtransport("Failed to offer shm", e))
+            .ctx(&conn, "connect")?;
+        transport
+            .offer_version()
+            .map_err(|e| VirgeError::xtransport("Failed to offer framing version", e))
+            .ctx(&conn, "connect")?;
 
         self.stream = Some(stream);
         self.transport = Some(transport);
+        self.conn = conn;
+        self.secure = None;
+        self.compression = CompressionContext::default();
+        self.batch = self.coalescing.map(Batcher::new);
+        self.unbatched.clear();
+        self.idle = false;
+        self.stats.record_connect(started.elapsed());
 
         debug!("XTransport connected successfully");
         Ok(())
@@ -54,69 +289,721 @@ impl XTransportHandler {
     pub fn disconnect(&mut self) -> Result<()> {
         debug!("XTransport disconnecting");
 
+        if let Err(e) = self.flush() {
+            warn!("XTransport dropped unsent batch on disconnect: {}", e);
+        }
         self.transport = None;
+        self.plain = Vec::new();
+        self.unbatched.clear();
+        self.set_pending(0);
         if let Some(stream) = &self.stream {
-            stream.shutdown(std::net::Shutdown::Both).map_err(|e| {
-                VirgeError::ConnectionError(format!("Failed to disconnect vsock: {}", e))
-            })?;
+            stream
+                .shutdown(std::net::Shutdown::Both)
+                .map_err(|e| {
+                    VirgeError::connection_io("Failed to disconnect vsock", e)
+                        .with_addr(self.conn.cid, self.conn.port)
+                })
+                .ctx(&self.conn, "disconnect")?;
         }
 
         debug!("XTransport disconnected");
         Ok(())
     }
 
+    /// 告知对端连接结束的原因后断开。未给出原因时，上一次接收空闲超时则为
+    /// [`ShutdownReason::IdleTimeout`]，否则为 [`ShutdownReason::GracefulClose`]；
+    /// 原因发送失败（对端已断开）不影响断开
+    pub(crate) fn close(&mut self, reason: Option<ShutdownReason>) -> Result<()> {
+        let reason = reason.unwrap_or(if self.idle {
+            ShutdownReason::IdleTimeout
+        } else {
+            ShutdownReason::GracefulClose
+        });
+        if let Err(e) = self.flush() {
+            warn!("XTransport dropped unsent batch on close: {}", e);
+        }
+        if let Some(transport) = self.transport.as_mut() {
+            if let Err(e) = transport.close(reason.code()) {
+                debug!("XTransport could not send close reason {}: {}", reason, e);
+            }
+        }
+        self.disconnect()
+    }
+
+    /// 发送一条消息，启用加密时先加密，必要时先发换钥帧。
+    /// 开启合批时小消息先放入批次，批次满足条件时才发出
     pub fn send(&mut self, data: &[u8]) -> Result<usize> {
-        let transport = self
-            .transport
-            .as_mut()
-            .ok_or_else(|| VirgeError::TransportError("XTransport not connected".to_string()))?;
+        let started = Instant::now();
+        match self.batch.as_mut() {
+            Some(batch) if self.transport.is_some() && batch.fits(data.len()) => {
+                batch.push(data);
+                if batch.due() {
+                    self.flush()?;
+                }
+            }
+            _ => {
+                self.flush()?;
+                self.send_message(data, MessageKind::Plain)?;
+            }
+        }
+        self.stats.record_send(data.len(), started.elapsed());
+        debug!("XTransport sent {} bytes", data.len());
+        Ok(data.len())
+    }
 
-        transport
-            .send_message(data)
-            .map_err(|e| VirgeError::Other(format!("XTransport send error: {}", e)))?;
+    /// 发出已合批但尚未发送的消息；未开启合批或批次为空时什么也不做
+    pub fn flush(&mut self) -> Result<()> {
+        match self.batch.as_mut().and_then(Batcher::take) {
+            Some((data, kind)) => self.send_message(&data, kind),
+            None => Ok(()),
+        }
+    }
 
-        debug!("XTransport sent {} bytes", data.len());
+    /// 压缩后发送一条消息，对端接收时自动解压；返回原文长度
+    #[cfg(feature = "compression")]
+    pub fn send_compressed(&mut self, data: &[u8]) -> Result<usize> {
+        let started = Instant::now();
+        let packed = self.compression.compress(data).ctx(&self.conn, "send")?;
+        self.flush()?;
+        self.send_message(&packed, MessageKind::Compressed)?;
+        self.stats.record_send(data.len(), started.elapsed());
+        debug!(
+            "XTransport sent {} bytes compressed to {}",
+            data.len(),
+            packed.len()
+        );
         Ok(data.len())
     }
 
-    pub fn recv(&mut self) -> Result<Vec<u8>> {
-        let transport = self
-            .transport
-            .as_mut()
-            .ok_or_else(|| VirgeError::TransportError("XTransport not connected".to_string()))?;
+    /// 启用加密时内容类型记在加密帧类型中，否则压缩消息与批次前
+    /// 分别发送 `Compressed`、`Batch` 控制包
+    fn send_message(&mut self, data: &[u8], kind: MessageKind) -> Result<()> {
+        let transport = self.transport.as_mut().ok_or_else(|| {
+            VirgeError::transport(ErrorKind::NotConnected, "XTransport not connected")
+        })?;
 
-        let data = transport
-            .recv_message()
-            .map_err(|e| VirgeError::Other(format!("XTransport recv error: {}", e)))?;
+        let xt_err = |e| VirgeError::xtransport("XTransport send error", e);
+        let sent = match self.secure.as_mut() {
+            None => match kind {
+                MessageKind::Plain => transport.send_message(data),
+                MessageKind::Compressed => transport.send_message_compressed(data),
+                MessageKind::Batch => transport.send_message_batch(data),
+            }
+            .map_err(xt_err),
+            Some(secure) => secure
+                .key_update_due()
+                .map_err(VirgeError::from)
+                .and_then(|update| match update {
+                    Some(update) => transport.send_message(&update).map_err(xt_err),
+                    None => Ok(()),
+                })
+                .and_then(|_| secure.seal_as(data, kind).map_err(VirgeError::from))
+                .and_then(|frame| transport.send_message(&frame).map_err(xt_err)),
+        };
+        match sent {
+            Err(e) if matches!(e.kind(), ErrorKind::BrokenPipe | ErrorKind::ConnectionReset) => {
+                Err(self.pending_rejection().map_or(e, VirgeError::Rejected))
+            }
+            sent => sent,
+        }
+        .ctx(&self.conn, "send")
+    }
 
+    /// 发送失败后查看对端关闭连接前是否说明了拒绝原因，只读取已到达的数据
+    fn pending_rejection(&mut self) -> Option<String> {
+        let (stream, transport) = (self.stream.as_ref()?, self.transport.as_mut()?);
+        while transport.rejection().is_none()
+            && matches!(
+                poll_readable(stream.as_fd(), None, Some(Duration::ZERO)),
+                Ok(true)
+            )
+        {
+            // 数据消息留给下一次接收，出错时拒绝原因可能已经记下
+            if !matches!(transport.poll_message(), Ok(false)) {
+                break;
+            }
+        }
+        transport.rejection().map(str::to_string)
+    }
+
+    /// 接收一条消息，启用加密时原地解密并跳过换钥帧。
+    /// 先发出未发送的批次；收到批次时逐条返回
+    pub fn recv(&mut self) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        self.flush()?;
+        let data = match self.unbatched.pop_front() {
+            Some(data) => data,
+            None => self.recv_frame()?,
+        };
+
+        self.stats.record_recv(data.len(), started.elapsed());
+        self.account();
         debug!("XTransport received {} bytes", data.len());
         Ok(data)
     }
 
+    /// 等待下一条消息到达、被 `wake` 唤醒或超过 `timeout`，有消息可接收时返回 `true`。
+    /// 等待期间到达的控制包（ping、pong 等）就地处理，不会让随后的 `recv()` 阻塞
+    pub(crate) fn wait_readable(
+        &mut self,
+        wake: Option<&Wake>,
+        timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let (Some(stream), Some(transport)) = (&self.stream, self.transport.as_mut()) else {
+            return Err(VirgeError::transport(
+                ErrorKind::NotConnected,
+                "XTransport not connected",
+            ));
+        };
+        let deadline = timeout.map(|t| Instant::now() + t);
+        loop {
+            if !self.unbatched.is_empty() || transport.message_pending() {
+                return Ok(true);
+            }
+            // 超时为 0 时也检查一次连接，供调用方不阻塞地探测
+            let left = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            if !poll_readable(stream.as_fd(), wake, left)
+                .map_err(VirgeError::from)
+                .ctx(&self.conn, "wait_readable")?
+            {
+                return Ok(false);
+            }
+            if transport
+                .poll_message()
+                .map_err(|e| recv_error(transport, e))
+                .ctx(&self.conn, "wait_readable")?
+            {
+                return Ok(true);
+            }
+            if left.is_some_and(|left| left.is_zero()) {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// 从连接接收一帧并还原为消息
+    fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        self.reserve_recv()?;
+        let transport = self.transport.as_mut().ok_or_else(|| {
+            VirgeError::transport(ErrorKind::NotConnected, "XTransport not connected")
+        })?;
+
+        let (data, kind) = loop {
+            let mut data = transport
+                .recv_message()
+                .map_err(|e| recv_error(transport, e))
+                .inspect_err(|e| self.idle = timed_out(e))
+                .ctx(&self.conn, "recv")?;
+            self.idle = false;
+            let marked = marked_kind(transport);
+            let Some(secure) = self.secure.as_mut() else {
+                break (data, marked);
+            };
+            if secure
+                .open_in_place(&mut data)
+                .map_err(VirgeError::from)
+                .ctx(&self.conn, "recv")?
+            {
+                break (data, secure.last_kind());
+            }
+        };
+        match kind {
+            MessageKind::Plain => Ok(data),
+            kind => {
+                let unpacked = unpack(&self.compression, &mut self.unbatched, &data, kind);
+                buffers::recycle(data);
+                unpacked.ctx(&self.conn, "recv")
+            }
+        }
+    }
+
+    /// 接收一条消息并按顺序填入 `bufs`，不经过中间缓冲；返回消息长度，
+    /// 超出缓冲总长的部分被丢弃。启用加密时先解密完整消息再拷贝；
+    /// 压缩消息与批次先原样收入 `bufs`，还原后再写回，缓冲放不下时返回 `InvalidData`
+    pub fn recv_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        self.flush()?;
+        if self.secure.is_some() || !self.unbatched.is_empty() {
+            let data = self.recv()?;
+            return Ok(scatter(&data, bufs));
+        }
+
+        let started = Instant::now();
+        self.reserve_recv()?;
+        let transport = self.transport.as_mut().ok_or_else(|| {
+            VirgeError::transport(ErrorKind::NotConnected, "XTransport not connected")
+        })?;
+
+        let len = transport
+            .recv_message_vectored(bufs)
+            .map_err(|e| recv_error(transport, e))
+            .inspect_err(|e| self.idle = timed_out(e))
+            .ctx(&self.conn, "recv_vectored")?;
+        self.idle = false;
+        let len = match marked_kind(transport) {
+            MessageKind::Plain => len,
+            kind => unpack_vectored(&self.compression, &mut self.unbatched, kind, len, bufs)
+                .ctx(&self.conn, "recv_vectored")?,
+        };
+
+        self.stats.record_recv(len, started.elapsed());
+        self.account();
+        debug!("XTransport received {} bytes (vectored)", len);
+        Ok(len)
+    }
+
+    /// 接收一条消息到连接内部复用的缓冲区，返回的视图在下一次接收前有效。
+    /// 启用加密、消息经过压缩或合批时解密、还原到连接内的另一块复用缓冲区
+    pub fn recv_loan(&mut self) -> Result<RecvLoan<'_>> {
+        let started = Instant::now();
+        self.flush()?;
+        if let Some(data) = self.unbatched.pop_front() {
+            self.plain = data;
+            self.stats.record_recv(self.plain.len(), started.elapsed());
+            self.account();
+            debug!("XTransport received {} bytes (loaned)", self.plain.len());
+            return Ok(RecvLoan::new(&self.plain));
+        }
+        self.reserve_recv()?;
+        let transport = self.transport.as_mut().ok_or_else(|| {
+            VirgeError::transport(ErrorKind::NotConnected, "XTransport not connected")
+        })?;
+
+        if let Some(secure) = self.secure.as_mut() {
+            loop {
+                // 出错时不再借用连接，才能读取对端说明的结束原因
+                let frame = match transport.recv_message_loaned().err() {
+                    Some(e) => {
+                        let e = recv_error(transport, e);
+                        self.idle = timed_out(&e);
+                        return Err(e).ctx(&self.conn, "recv_loan");
+                    }
+                    None => transport.loaned(),
+                };
+                self.idle = false;
+                self.plain.clear();
+                self.plain.extend_from_slice(frame);
+                if secure
+                    .open_in_place(&mut self.plain)
+                    .map_err(VirgeError::from)
+                    .ctx(&self.conn, "recv_loan")?
+                {
+                    break;
+                }
+            }
+            let kind = secure.last_kind();
+            if kind != MessageKind::Plain {
+                self.plain = unpack(&self.compression, &mut self.unbatched, &self.plain, kind)
+                    .ctx(&self.conn, "recv_loan")?;
+            }
+            self.stats.record_recv(self.plain.len(), started.elapsed());
+            self.budget
+                .update(transport.buffered_bytes() + self.plain.capacity() + self.pending);
+            debug!("XTransport received {} bytes (loaned)", self.plain.len());
+            return Ok(RecvLoan::new(&self.plain));
+        }
+
+        if let Some(e) = transport.recv_message_loaned().err() {
+            let e = recv_error(transport, e);
+            self.idle = timed_out(&e);
+            return Err(e).ctx(&self.conn, "recv_loan");
+        }
+        self.idle = false;
+        let data = match marked_kind(transport) {
+            MessageKind::Plain => transport.loaned(),
+            kind => {
+                self.plain = unpack(
+                    &self.compression,
+                    &mut self.unbatched,
+                    transport.loaned(),
+                    kind,
+                )
+                .ctx(&self.conn, "recv_loan")?;
+                &self.plain[..]
+            }
+        };
+
+        self.stats.record_recv(data.len(), started.elapsed());
+        debug!("XTransport received {} bytes (loaned)", data.len());
+        Ok(RecvLoan::new(data))
+    }
+
     pub fn is_connected(&self) -> bool {
         self.stream.is_some() && self.transport.is_some()
     }
 
+    /// 当前连接的标识（连接 ID 与对端地址）
+    pub fn conn(&self) -> ConnContext {
+        self.conn
+    }
+
+    /// 设置空闲超时（socket 读超时），超时后接收返回 `WouldBlock`/`TimedOut`。
+    /// io_uring 路径以链接的超时请求实现同样的行为
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        if let Some(transport) = self.transport.as_mut() {
+            transport
+                .get_mut()
+                .set_read_timeout(timeout)
+                .map_err(VirgeError::from)
+                .ctx(&self.conn, "set_idle_timeout")?;
+        }
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+
+    /// 发送 ping 并等待对端回应，返回往返时长。对端在接收消息时自动回应；
+    /// 等待期间先到的消息同样说明对端存活，会留给下一次接收
+    pub fn ping(&mut self, timeout: Duration) -> Result<Duration> {
+        let started = Instant::now();
+        self.flush()?;
+        let Some(transport) = self.transport.as_mut() else {
+            return Err(VirgeError::transport(
+                ErrorKind::NotConnected,
+                "XTransport not connected",
+            ));
+        };
+        self.pings += 1;
+        transport
+            .get_mut()
+            .set_read_timeout(Some(timeout))
+            .map_err(VirgeError::from)
+            .ctx(&self.conn, "ping")?;
+        let result = transport.ping(self.pings);
+        transport
+            .get_mut()
+            .set_read_timeout(self.idle_timeout)
+            .map_err(VirgeError::from)
+            .ctx(&self.conn, "ping")?;
+        result
+            .map_err(|e| VirgeError::xtransport("XTransport ping error", e))
+            .ctx(&self.conn, "ping")?;
+        Ok(started.elapsed())
+    }
+
+    /// 与对端进行一轮时钟交换，超过 `timeout` 未回复返回超时错误。对端在接收消息时
+    /// 回复；等待期间先到达消息时返回 `Interrupted`，消息留给下一次接收
+    pub(crate) fn time_probe(&mut self, timeout: Duration) -> Result<Sample> {
+        self.flush()?;
+        let Some(transport) = self.transport.as_mut() else {
+            return Err(VirgeError::transport(
+                ErrorKind::NotConnected,
+                "XTransport not connected",
+            ));
+        };
+        self.pings += 1;
+        transport
+            .get_mut()
+            .set_read_timeout(Some(timeout))
+            .map_err(VirgeError::from)
+            .ctx(&self.conn, "time_sync")?;
+        let t1 = clock::now_nanos();
+        let result = transport.time_probe(self.pings);
+        let t4 = clock::now_nanos();
+        transport
+            .get_mut()
+            .set_read_timeout(self.idle_timeout)
+            .map_err(VirgeError::from)
+            .ctx(&self.conn, "time_sync")?;
+        let reply = result
+            .map_err(|e| VirgeError::xtransport("XTransport time sync error", e))
+            .ctx(&self.conn, "time_sync")?;
+        let Some((t2, t3)) = reply else {
+            return Err(VirgeError::transport(
+                ErrorKind::Interrupted,
+                "a message arrived during time sync, receive it first",
+            ))
+            .ctx(&self.conn, "time_sync");
+        };
+        Ok(Sample { t1, t2, t3, t4 })
+    }
+
+    /// 内部缓冲占用的字节数：接收缓冲、解密缓冲、批次与上层未读完的数据
+    fn buffered_bytes(&self) -> usize {
+        self.transport.as_ref().map_or(0, |t| t.buffered_bytes())
+            + self.plain.capacity()
+            + self.batch.as_ref().map_or(0, Batcher::len)
+            + self.unbatched.iter().map(Vec::len).sum::<usize>()
+            + self.pending
+    }
+
+    fn account(&mut self) {
+        let bytes = self.buffered_bytes();
+        self.budget.update(bytes);
+    }
+
+    /// 请对端在完成手头的请求后重连，连接本身仍可使用
+    pub(crate) fn go_away(&mut self, reason: GoAwayReason) -> Result<()> {
+        self.flush()?;
+        let Some(transport) = self.transport.as_mut() else {
+            return Err(VirgeError::transport(
+                ErrorKind::NotConnected,
+                "XTransport not connected",
+            ));
+        };
+        transport
+            .go_away(reason.code())
+            .map_err(|e| VirgeError::xtransport("XTransport send goaway error", e))
+            .ctx(&self.conn, "go_away")
+    }
+
+    /// 发送一个旁路帧，先发出缓冲中的消息以保持先后顺序。旁路帧不加密，
+    /// 对端在接收消息时交给注册的处理函数
+    pub fn send_extension(&mut self, frame_type: u8, data: &[u8]) -> Result<()> {
+        extension::check_outgoing(frame_type, data)?;
+        self.flush()?;
+        let Some(transport) = self.transport.as_mut() else {
+            return Err(VirgeError::transport(
+                ErrorKind::NotConnected,
+                "XTransport not connected",
+            ));
+        };
+        transport
+            .send_extension(frame_type, data)
+            .map_err(|e| VirgeError::xtransport("XTransport send extension error", e))
+            .ctx(&self.conn, "send_extension")
+    }
+
+    /// 告知对端拒绝连接的原因，随后由调用方断开连接
+    pub(crate) fn reject(&mut self, reason: &str) -> Result<()> {
+        let Some(transport) = self.transport.as_mut() else {
+            return Err(VirgeError::transport(
+                ErrorKind::NotConnected,
+                "XTransport not connected",
+            ));
+        };
+        transport
+            .reject(truncate_reason(reason))
+            .map_err(|e| VirgeError::xtransport("XTransport send reject error", e))
+            .ctx(&self.conn, "reject")
+    }
+
+    /// 对端发来的 GOAWAY 原因；XTransport 只在接收消息时处理控制包
+    pub(crate) fn goaway(&self) -> Option<GoAwayReason> {
+        self.transport
+            .as_ref()
+            .and_then(|t| t.goaway())
+            .map(GoAwayReason::from_code)
+    }
+
+    /// 让下一次接收跳过上次接收失败时残留的分片，从下一条消息开始
+    pub(crate) fn resync(&mut self) {
+        if let Some(transport) = self.transport.as_mut() {
+            transport.resync();
+        }
+    }
+
+    /// 上层（`Read` 实现）暂存的未读字节数，计入本连接的占用
+    pub(crate) fn set_pending(&mut self, bytes: usize) {
+        self.pending = bytes;
+        self.account();
+    }
+
+    /// 接收前检查内存预算：超出本连接或全局上限时释放复用缓冲，
+    /// 全局仍超限时等待其他连接释放；之后按剩余额度限制下一条消息可分配的缓冲
+    fn reserve_recv(&mut self) -> Result<()> {
+        self.account();
+        let over_global = budget::over_global_limit();
+        if over_global || self.budget.over_limit() {
+            if let Some(transport) = self.transport.as_mut() {
+                transport.release_buffers();
+            }
+            self.plain = Vec::new();
+            self.account();
+        }
+        if over_global {
+            budget::wait_for_room(BUDGET_WAIT_TIMEOUT).ctx(&self.conn, "recv")?;
+        }
+        let room = budget::room(self.budget.limit(), self.buffered_bytes());
+        if let Some(transport) = self.transport.as_mut() {
+            transport.set_recv_limit(room);
+        }
+        Ok(())
+    }
+
+    /// 之后从对端读到任何包（含就地处理的控制包）时调用 `hook`；未连接时不起作用
+    pub(crate) fn set_frame_hook(&mut self, hook: FrameHook) {
+        if let Some(transport) = self.transport.as_mut() {
+            transport.set_frame_hook(Some(Box::new(move || hook())));
+        }
+    }
+
+    /// 认证握手后启用加密通道，之后的收发都经过它
+    pub(crate) fn set_secure(&mut self, secure: Option<SecureChannel>) {
+        self.secure = secure;
+    }
+
+    /// 字典协商后设置压缩状态，之后的压缩消息都使用协商出的字典
+    #[cfg(feature = "compression")]
+    pub(crate) fn set_compression(&mut self, compression: CompressionContext) {
+        self.compression = compression;
+    }
+
+    /// 握手协商出的压缩字典 ID，未协商出共同字典时为 `None`
+    #[cfg(feature = "compression")]
+    pub fn dictionary_id(&self) -> Option<u32> {
+        self.compression.dictionary_id()
+    }
+
+    /// 发送所用的分帧版本，未连接时为 1
+    pub fn protocol_version(&self) -> u8 {
+        self.transport
+            .as_ref()
+            .map_or(crate::transport::xtransport::VERSION, |t| t.version())
+    }
+
+    /// 连接统计（含当前分片帧大小）
+    pub fn stats(&self) -> ConnectionStats {
+        let mut stats = self.stats.clone();
+        stats.chunk_size = self.transport.as_ref().map(|t| t.frame_size()).unwrap_or(0);
+        stats.key_rotations = self.secure.as_ref().map_or(0, |s| s.rekeys());
+        stats.buffered_bytes = self.buffered_bytes();
+        stats.send_backlog = self
+            .batch
+            .as_ref()
+            .map_or_else(Default::default, Batcher::backlog);
+        stats
+    }
+
     pub fn from_stream(&mut self, stream: VsockStream, chunksize: u32, isack: bool) -> Result<()> {
         debug!("XTransport initializing from existing stream");
 
-        let config = TransportConfig::default()
-            .with_max_frame_size(chunksize as usize)
-            .with_ack(isack);
-        let transport = XTransport::new(stream.try_clone()?, config);
+        let conn = match stream.peer_addr() {
+            Ok(addr) => ConnContext::new(addr.cid(), addr.port()),
+            Err(_) => ConnContext::new(0, 0),
+        };
+        let config = self.transport_config(chunksize, isack);
+        let io = stream
+            .try_clone()
+            .map_err(VirgeError::from)
+            .and_then(|s| self.make_io(s))
+            .ctx(&conn, "accept")?;
+        let mut transport = XTransport::new(io, config);
+        transport.set_extension_handler(Some(extension_handler(conn)));
 
         self.stream = Some(stream);
         self.transport = Some(transport);
+        self.conn = conn;
+        self.secure = None;
+        self.compression = CompressionContext::default();
+        self.batch = self.coalescing.map(Batcher::new);
+        self.unbatched.clear();
+        self.idle = false;
 
         debug!("XTransport initialized from stream successfully");
         Ok(())
     }
 }
 
+/// 接收失败的错误；连接已结束时附带对端关闭前说明的原因，见 [`ShutdownReason`]
+fn recv_error(
+    transport: &XTransport<VsockIo>,
+    e: crate::transport::xtransport::Error,
+) -> VirgeError {
+    VirgeError::xtransport("XTransport recv error", e).ended(
+        transport.close_reason(),
+        transport.goaway().map(GoAwayReason::from_code),
+    )
+}
+
+/// 接收因空闲超时失败
+fn timed_out(e: &VirgeError) -> bool {
+    matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
+
+/// 未加密时由控制包给出的刚收到消息的内容类型
+fn marked_kind(transport: &XTransport<VsockIo>) -> MessageKind {
+    if transport.last_compressed() {
+        MessageKind::Compressed
+    } else if transport.last_batch() {
+        MessageKind::Batch
+    } else {
+        MessageKind::Plain
+    }
+}
+
+/// 把 `data` 按顺序拷入 `bufs`，超出的部分丢弃，返回 `data` 的长度
+fn scatter(data: &[u8], bufs: &mut [IoSliceMut<'_>]) -> usize {
+    let mut rest = data;
+    for buf in bufs.iter_mut() {
+        let n = buf.len().min(rest.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        rest = &rest[n..];
+    }
+    data.len()
+}
+
+/// `bufs` 前 `len` 字节是一条压缩消息或一个批次：还原后写回，返回还原出的消息长度
+fn unpack_vectored(
+    compression: &CompressionContext,
+    unbatched: &mut VecDeque<Vec<u8>>,
+    kind: MessageKind,
+    len: usize,
+    bufs: &mut [IoSliceMut<'_>],
+) -> Result<usize> {
+    let capacity: usize = bufs.iter().map(|buf| buf.len()).sum();
+    if len > capacity {
+        return Err(VirgeError::transport(
+            ErrorKind::InvalidData,
+            format!(
+                "{:?} message of {} bytes truncated to {} byte buffers",
+                kind, len, capacity
+            ),
+        ));
+    }
+    let mut packed = Vec::with_capacity(len);
+    for buf in bufs.iter() {
+        let n = buf.len().min(len - packed.len());
+        packed.extend_from_slice(&buf[..n]);
+    }
+    let data = unpack(compression, unbatched, &packed, kind)?;
+    Ok(scatter(&data, bufs))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// 以一对 Unix socket 代替 vsock 连接的两端
+    fn handler_pair() -> (XTransportHandler, XTransportHandler) {
+        use std::os::fd::{FromRawFd, IntoRawFd};
+        let (a, b) = std::os::unix::net::UnixStream::pair().unwrap();
+        let [a, b] = [a, b].map(|socket| {
+            // SAFETY: fd 来自刚创建且已交出所有权的 socket
+            let stream = unsafe { VsockStream::from_raw_fd(socket.into_raw_fd()) };
+            let mut handler = XTransportHandler::new();
+            handler.from_stream(stream, 1024, false).unwrap();
+            handler
+        });
+        (a, b)
+    }
+
+    #[test]
+    fn recv_reports_why_the_peer_closed() {
+        let (mut client, mut server) = handler_pair();
+        client.send(b"bye").unwrap();
+        client.close(Some(ShutdownReason::AuthRevoked)).unwrap();
+        assert_eq!(server.recv().unwrap(), b"bye");
+        let err = server.recv().unwrap_err();
+        assert_eq!(err.shutdown_reason(), Some(ShutdownReason::AuthRevoked));
+        assert!(!err.shutdown_reason().unwrap().should_reconnect());
+
+        // 空闲超时后关闭，对端得知原因
+        let (mut client, mut server) = handler_pair();
+        server
+            .set_idle_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        let err = server.recv().unwrap_err();
+        assert_eq!(err.shutdown_reason(), None);
+        server.close(None).unwrap();
+        let err = client.recv().unwrap_err();
+        assert_eq!(err.shutdown_reason(), Some(ShutdownReason::IdleTimeout));
+
+        // 对端没有说明原因就断开
+        let (mut client, mut server) = handler_pair();
+        server.disconnect().unwrap();
+        let err = client.recv().unwrap_err();
+        assert_eq!(err.shutdown_reason(), Some(ShutdownReason::PeerReset));
+    }
+
     #[test]
     fn new_handler_not_connected() {
         let handler = XTransportHandler::new();
@@ -132,6 +1019,79 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn recv_vectored_without_connection_fails() {
+        let mut handler = XTransportHandler::new();
+        let mut buf = [0u8; 4];
+        assert!(handler
+            .recv_vectored(&mut [IoSliceMut::new(&mut buf)])
+            .is_err());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn unpack_vectored_restores_message() {
+        let data = b"vectored ".repeat(20);
+        let ctx = CompressionContext::default();
+        let packed = ctx.compress(&data).unwrap();
+        let (mut head, mut body) = ([0u8; 8], [0u8; 256]);
+        let mut bufs = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut body)];
+        scatter(&packed, &mut bufs);
+        let mut unbatched = VecDeque::new();
+        let kind = MessageKind::Compressed;
+        assert_eq!(
+            unpack_vectored(&ctx, &mut unbatched, kind, packed.len(), &mut bufs).unwrap(),
+            data.len()
+        );
+        assert_eq!(&head, &data[..8]);
+        assert_eq!(&body[..data.len() - 8], &data[8..]);
+
+        let mut small = [0u8; 4];
+        let err = unpack_vectored(
+            &ctx,
+            &mut unbatched,
+            kind,
+            packed.len(),
+            &mut [IoSliceMut::new(&mut small)],
+        )
+        .unwrap_err();
+        assert_eq!(std::io::Error::from(err).kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unpack_splits_batch_and_keeps_the_rest() {
+        let mut batch = Batcher::new(Coalescing {
+            max_delay: Duration::from_secs(1),
+            max_bytes: ByteSize::kib(1),
+        });
+        for msg in [&b"one"[..], b"two", b"three"] {
+            batch.push(msg);
+        }
+        let (data, kind) = batch.take().unwrap();
+        let mut unbatched = VecDeque::new();
+        let ctx = CompressionContext::default();
+        let first = unpack(&ctx, &mut unbatched, &data, kind).unwrap();
+        assert_eq!(first, b"one");
+        assert_eq!(unbatched, [b"two".to_vec(), b"three".to_vec()]);
+    }
+
+    #[test]
+    fn coalescing_send_without_connection_fails() {
+        let mut handler = XTransportHandler::new().with_coalescing(Some(Coalescing {
+            max_delay: Duration::from_millis(1),
+            max_bytes: ByteSize::kib(16),
+        }));
+        let err = handler.send(&[1, 2, 3]).unwrap_err();
+        assert_eq!(std::io::Error::from(err).kind(), ErrorKind::NotConnected);
+        assert!(handler.flush().is_ok());
+    }
+
+    #[test]
+    fn recv_loan_without_connection_fails() {
+        let mut handler = XTransportHandler::new();
+        assert!(handler.recv_loan().is_err());
+    }
+
     #[test]
     fn recv_without_connection_fails() {
         let mut handler = XTransportHandler::new();
@@ -170,6 +1130,16 @@ mod tests {
         assert!(!handler.is_connected());
     }
 
+    #[test]
+    fn connect_error_carries_context() {
+        let mut handler = XTransportHandler::new();
+        let err = handler.connect(999999, 999999, 1024, false).unwrap_err();
+        assert_eq!(err.operation(), Some("connect"));
+        let conn = err.conn().unwrap();
+        assert!(conn.conn_id > 0);
+        assert_eq!((conn.cid, conn.port), (999999, 999999));
+    }
+
     #[test]
     fn disconnect_clears_state() {
         let mut handler = XTransportHandler::new();
@@ -186,8 +1156,9 @@ mod tests {
         assert!(result.is_err());
         if let Err(e) = result {
             match e {
-                VirgeError::TransportError(msg) => {
-                    assert!(msg.contains("not connected"));
+                VirgeError::TransportError { message, kind, .. } => {
+                    assert!(message.contains("not connected"));
+                    assert_eq!(kind, ErrorKind::NotConnected);
                 }
                 _ => panic!("Expected TransportError"),
             }
@@ -201,8 +1172,9 @@ mod tests {
         assert!(result.is_err());
         if let Err(e) = result {
             match e {
-                VirgeError::TransportError(msg) => {
-                    assert!(msg.contains("not connected"));
+                VirgeError::TransportError { message, kind, .. } => {
+                    assert!(message.contains("not connected"));
+                    assert_eq!(kind, ErrorKind::NotConnected);
                 }
                 _ => panic!("Expected TransportError"),
             }
@@ -216,6 +1188,83 @@ mod tests {
         assert!(handler.transport.is_none());
     }
 
+    #[test]
+    fn transport_config_caps_framing_version() {
+        let handler = XTransportHandler::new();
+        assert_eq!(
+            handler.transport_config(1024, false).max_version,
+            crate::transport::xtransport::LATEST_VERSION
+        );
+        assert_eq!(crate::transport::MAX_PROTOCOL_VERSION, handler.max_version);
+        let handler = handler.with_max_version(1);
+        assert_eq!(handler.transport_config(1024, false).max_version, 1);
+        assert_eq!(handler.protocol_version(), 1);
+    }
+
+    #[test]
+    fn transport_config_carries_strict_mode() {
+        let handler = XTransportHandler::new();
+        assert!(!handler.transport_config(1024, false).strict);
+        let handler = handler.with_strict(true);
+        assert!(handler.transport_config(1024, false).strict);
+    }
+
+    #[test]
+    fn transport_config_uses_default_window() {
+        let handler = XTransportHandler::new();
+        let config = handler.transport_config(1024, true);
+        assert_eq!(config.send_window, TransportConfig::default().send_window);
+        assert!(config.wait_for_ack);
+    }
+
+    #[test]
+    fn transport_config_adaptive_chunk() {
+        let handler = XTransportHandler::new();
+        assert!(!handler.transport_config(1024, false).adaptive_chunk);
+        let handler = XTransportHandler::new().with_adaptive_chunk(true);
+        assert!(handler.transport_config(1024, false).adaptive_chunk);
+    }
+
+    #[test]
+    fn shared_memory_reaches_transport_config() {
+        let handler = XTransportHandler::new().with_shared_memory("/dev/shm/virga", 1 << 20);
+        let shm = handler.transport_config(4096, false).shm.unwrap();
+        assert_eq!(shm.path, PathBuf::from("/dev/shm/virga"));
+        assert_eq!(shm.size, 1 << 20);
+        assert!(XTransportHandler::new()
+            .transport_config(4096, false)
+            .shm
+            .is_none());
+    }
+
+    #[test]
+    fn io_uring_off_by_default() {
+        assert!(!XTransportHandler::new().io_uring);
+        assert!(XTransportHandler::new().with_io_uring(true).io_uring);
+    }
+
+    #[test]
+    fn stats_empty_when_not_connected() {
+        let handler = XTransportHandler::new();
+        assert_eq!(handler.stats(), ConnectionStats::default());
+    }
+
+    #[test]
+    fn stats_not_updated_by_failed_send() {
+        let mut handler = XTransportHandler::new();
+        let _ = handler.send(&[1, 2, 3]);
+        let _ = handler.recv();
+        assert_eq!(handler.stats().messages_sent, 0);
+        assert_eq!(handler.stats().messages_received, 0);
+    }
+
+    #[test]
+    fn transport_config_uses_send_window() {
+        let handler = XTransportHandler::new().with_send_window(4);
+        let config = handler.transport_config(1024, false);
+        assert_eq!(config.send_window, 4);
+    }
+
     #[test]
     fn connect_sets_debug_logs() {
         // Test that connect attempts generate debug logs
@@ -257,13 +1306,17 @@ mod tests {
         // Test that error messages contain expected content
         let send_err = handler.send(&[1, 2, 3]).unwrap_err();
         match send_err {
-            VirgeError::TransportError(msg) => assert!(msg.contains("not connected")),
+            VirgeError::TransportError { message, .. } => {
+                assert!(message.contains("not connected"))
+            }
             _ => panic!("Expected TransportError"),
         }
 
         let recv_err = handler.recv().unwrap_err();
         match recv_err {
-            VirgeError::TransportError(msg) => assert!(msg.contains("not connected")),
+            VirgeError::TransportError { message, .. } => {
+                assert!(message.contains("not connected"))
+            }
             _ => panic!("Expected TransportError"),
         }
     }
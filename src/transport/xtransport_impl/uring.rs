@@ -0,0 +1,374 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! io_uring IO 路径
+//!
+//! 每个连接持有一个独立的小 ring，读写以 `IORING_OP_RECV` / `IORING_OP_SEND`
+//! 提交并同步等待完成，对上层仍表现为阻塞的 `std::io::{Read, Write}`。
+//!
+//! io_uring 不理会 socket 的 `SO_RCVTIMEO` / `SO_SNDTIMEO`，因此读写超时记在本结构中：
+//! 创建时从 socket 读出一次，之后须经 [`UringStream::set_read_timeout`] /
+//! [`UringStream::set_write_timeout`] 修改。设置了超时时在请求后链接一个
+//! `IORING_OP_LINK_TIMEOUT`，每次读写只需一次 `io_uring_enter`；超时取消请求后与阻塞
+//! socket 一样返回 `WouldBlock`，空闲超时、ping 与握手超时在两条 IO 路径上表现一致。
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+/// 每个连接同一时刻只有一个请求（及其超时）在途，ring 不需要很大
+const RING_ENTRIES: u32 = 8;
+
+/// 读写请求、链接的超时请求与取消请求的 user_data
+const IO_REQUEST: u64 = 1;
+const TIMEOUT_REQUEST: u64 = 2;
+const CANCEL_REQUEST: u64 = 3;
+
+/// 内核暂时缺少资源时重试提交的间隔
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+/// 基于 io_uring 的 socket 读写封装
+pub struct UringStream<S> {
+    inner: S,
+    ring: IoUring,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl<S: AsRawFd> UringStream<S> {
+    pub fn new(inner: S) -> Result<Self> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+        let fd = inner.as_raw_fd();
+        Ok(Self {
+            read_timeout: socket_timeout(fd, libc::SO_RCVTIMEO),
+            write_timeout: socket_timeout(fd, libc::SO_SNDTIMEO),
+            inner,
+            ring,
+        })
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// 设置读超时，同时写入 socket 的 `SO_RCVTIMEO`。直接修改 socket 的超时不会生效
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        set_socket_timeout(self.inner.as_raw_fd(), libc::SO_RCVTIMEO, timeout)?;
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    /// 设置写超时，同时写入 socket 的 `SO_SNDTIMEO`
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        set_socket_timeout(self.inner.as_raw_fd(), libc::SO_SNDTIMEO, timeout)?;
+        self.write_timeout = timeout;
+        Ok(())
+    }
+
+    /// 提交一个读写请求并等待完成，`timeout` 为 `Some` 时超过期限取消请求，返回 `WouldBlock`
+    fn submit(&mut self, entry: squeue::Entry, timeout: Option<Duration>) -> Result<usize> {
+        let timespec = timeout.map(types::Timespec::from);
+        let mut pending = 1;
+        // SAFETY: 调用方传入的缓冲区与 `timespec` 在本函数返回前一直有效，
+        // 而 `complete()` 无论成败都等到所有请求完成后才返回。
+        unsafe {
+            let mut queue = self.ring.submission();
+            match &timespec {
+                Some(timespec) => {
+                    let entry = entry.flags(squeue::Flags::IO_LINK).user_data(IO_REQUEST);
+                    let link = opcode::LinkTimeout::new(timespec)
+                        .build()
+                        .user_data(TIMEOUT_REQUEST);
+                    queue
+                        .push_multiple(&[entry, link])
+                        .map_err(|_| Error::other("io_uring submission queue full"))?;
+                    pending = 2;
+                }
+                None => queue
+                    .push(&entry.user_data(IO_REQUEST))
+                    .map_err(|_| Error::other("io_uring submission queue full"))?,
+            }
+        }
+
+        match self.complete(pending, timespec.is_some())? {
+            res if res == -libc::ECANCELED && timespec.is_some() => Err(Error::new(
+                ErrorKind::WouldBlock,
+                "io_uring request timed out",
+            )),
+            res if res < 0 => Err(Error::from_raw_os_error(-res)),
+            res => Ok(res as usize),
+        }
+    }
+
+    /// 等待已放入提交队列的 `pending` 个请求全部完成，返回读写请求的结果。
+    ///
+    /// 提交出错时取消读写请求（`linked` 时连同链接的超时），仍等到每个请求的完成项都取回后
+    /// 才返回该错误：请求引用着调用方的缓冲区与栈上的 `timespec`，不能在返回后留给内核继续
+    /// 使用。取消请求也无法提交时中止进程
+    fn complete(&mut self, mut pending: usize, linked: bool) -> Result<i32> {
+        let mut res = None;
+        let mut failure = None;
+        while pending > 0 {
+            match self.ring.submit_and_wait(pending) {
+                Ok(_) => {}
+                // 请求已提交，被信号打断时只需继续等待
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                // 内核暂时缺少资源或完成队列已满：取回已有的完成项后重试
+                Err(e) if is_transient(&e) => std::thread::sleep(RETRY_INTERVAL),
+                Err(e) if failure.is_none() => {
+                    log::warn!(
+                        "io_uring submit failed, cancelling in-flight request: {}",
+                        e
+                    );
+                    pending += self.cancel(linked);
+                    failure = Some(e);
+                }
+                Err(e) => {
+                    log::error!("io_uring cannot wait for cancelled request: {}", e);
+                    std::process::abort();
+                }
+            }
+            for cqe in self.ring.completion() {
+                pending -= 1;
+                if cqe.user_data() == IO_REQUEST {
+                    res = Some(cqe.result());
+                }
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => res.ok_or_else(|| Error::other("io_uring completion missing")),
+        }
+    }
+
+    /// 放入取消读写请求（`linked` 时还有链接的超时）的请求，返回放入的个数。请求已完成时
+    /// 取消以 `ENOENT` 完成；提交队列放不下时中止进程
+    fn cancel(&mut self, linked: bool) -> usize {
+        let targets: &[u64] = if linked {
+            &[IO_REQUEST, TIMEOUT_REQUEST]
+        } else {
+            &[IO_REQUEST]
+        };
+        let entries: Vec<_> = targets
+            .iter()
+            .map(|&target| {
+                opcode::AsyncCancel::new(target)
+                    .build()
+                    .user_data(CANCEL_REQUEST)
+            })
+            .collect();
+        // SAFETY: 取消请求不引用任何内存
+        if unsafe { self.ring.submission().push_multiple(&entries) }.is_err() {
+            log::error!("io_uring submission queue full, cannot cancel in-flight request");
+            std::process::abort();
+        }
+        entries.len()
+    }
+}
+
+/// 提交因内核暂时缺少资源（`EAGAIN`、`ENOMEM`）或完成队列已满（`EBUSY`）失败，可以重试
+fn is_transient(e: &Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EAGAIN | libc::ENOMEM | libc::EBUSY)
+    )
+}
+
+/// 读出 socket 的 `SO_RCVTIMEO` / `SO_SNDTIMEO`，未设置（为零）或取不到时为 `None`。
+/// 只在创建时调用一次
+fn socket_timeout(fd: RawFd, name: libc::c_int) -> Option<Duration> {
+    let mut value = libc::timeval {
+        tv_sec: 0,
+        tv_usec: 0,
+    };
+    let mut len = std::mem::size_of::<libc::timeval>() as libc::socklen_t;
+    // SAFETY: value 与 len 指向足够大的本地变量
+    let ret = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            name,
+            &mut value as *mut libc::timeval as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    let timeout = Duration::new(value.tv_sec as u64, value.tv_usec as u32 * 1000);
+    (ret == 0 && !timeout.is_zero()).then_some(timeout)
+}
+
+/// 写入 socket 的 `SO_RCVTIMEO` / `SO_SNDTIMEO`，`None` 表示不超时
+fn set_socket_timeout(fd: RawFd, name: libc::c_int, timeout: Option<Duration>) -> Result<()> {
+    if timeout == Some(Duration::ZERO) {
+        // 与标准库一致：零超时无法与“不超时”区分
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "cannot set a 0 duration timeout",
+        ));
+    }
+    let timeout = timeout.unwrap_or_default();
+    let value = libc::timeval {
+        tv_sec: timeout.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    };
+    // SAFETY: value 指向大小为 timeval 的本地变量
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            name,
+            &value as *const libc::timeval as *const libc::c_void,
+            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+impl<S: AsRawFd> Read for UringStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let fd = types::Fd(self.inner.as_raw_fd());
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let entry = opcode::Recv::new(fd, buf.as_mut_ptr(), len).build();
+        self.submit(entry, self.read_timeout)
+    }
+}
+
+impl<S: AsRawFd> Write for UringStream<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let fd = types::Fd(self.inner.as_raw_fd());
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let entry = opcode::Send::new(fd, buf.as_ptr(), len).build();
+        self.submit(entry, self.write_timeout)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    // 沙箱或老内核可能禁用 io_uring，此时跳过
+    fn pair() -> Option<(UringStream<UnixStream>, UringStream<UnixStream>)> {
+        let (a, b) = UnixStream::pair().unwrap();
+        Some((UringStream::new(a).ok()?, UringStream::new(b).ok()?))
+    }
+
+    #[test]
+    fn write_then_read() {
+        let Some((mut a, mut b)) = pair() else {
+            return;
+        };
+        assert_eq!(a.write(&[1, 2, 3]).unwrap(), 3);
+        let mut buf = [0u8; 8];
+        let n = b.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], &[1, 2, 3]);
+    }
+
+    #[test]
+    fn read_after_peer_closed_is_eof() {
+        let Some((a, mut b)) = pair() else {
+            return;
+        };
+        drop(a);
+        let mut buf = [0u8; 8];
+        assert_eq!(b.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_honours_socket_read_timeout() {
+        let Some((mut a, mut b)) = pair() else {
+            return;
+        };
+        let timeout = Duration::from_millis(50);
+        b.set_read_timeout(Some(timeout)).unwrap();
+        let started = std::time::Instant::now();
+        let err = b.read(&mut [0u8; 8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+        assert!(started.elapsed() >= timeout);
+
+        // 超时后 ring 中没有残留的完成项，之后的读写照常进行
+        b.set_read_timeout(None).unwrap();
+        assert_eq!(a.write(&[7]).unwrap(), 1);
+        let mut buf = [0u8; 8];
+        assert_eq!(b.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 7);
+    }
+
+    #[test]
+    fn cancel_waits_for_the_in_flight_request() {
+        let Some((mut a, mut b)) = pair() else {
+            return;
+        };
+        let mut buf = [0u8; 8];
+        let fd = types::Fd(b.inner.as_raw_fd());
+        let entry = opcode::Recv::new(fd, buf.as_mut_ptr(), buf.len() as u32)
+            .build()
+            .user_data(IO_REQUEST);
+        // SAFETY: buf 在 complete() 返回前一直有效
+        unsafe { b.ring.submission().push(&entry).unwrap() };
+        b.ring.submit().unwrap();
+        let pending = 1 + b.cancel(false);
+        assert_eq!(b.complete(pending, false).unwrap(), -libc::ECANCELED);
+
+        // 取消后 ring 中没有残留的完成项，之后的读写照常进行
+        assert_eq!(a.write(&[7]).unwrap(), 1);
+        assert_eq!(b.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 7);
+    }
+
+    #[test]
+    fn new_picks_up_existing_socket_timeouts() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        // Whole seconds survive the kernel rounding to jiffies
+        let timeout = Duration::from_secs(1);
+        a.set_read_timeout(Some(timeout)).unwrap();
+        let Ok(stream) = UringStream::new(a) else {
+            return;
+        };
+        assert_eq!(stream.read_timeout, Some(timeout));
+        assert_eq!(stream.write_timeout, None);
+    }
+
+    #[test]
+    fn set_timeouts_update_the_socket() {
+        let Some((mut a, _b)) = pair() else {
+            return;
+        };
+        // Whole seconds survive the kernel rounding to jiffies
+        let timeout = Duration::from_secs(1);
+        a.set_write_timeout(Some(timeout)).unwrap();
+        assert_eq!(a.get_ref().write_timeout().unwrap(), Some(timeout));
+        a.set_write_timeout(None).unwrap();
+        assert_eq!(a.get_ref().write_timeout().unwrap(), None);
+        assert!(a.set_read_timeout(Some(Duration::ZERO)).is_err());
+    }
+
+    #[test]
+    fn xtransport_roundtrip_over_uring() {
+        use crate::transport::xtransport::{TransportConfig, XTransport};
+
+        let Some((a, b)) = pair() else {
+            return;
+        };
+        let data: Vec<u8> = (0..20_000).map(|i| (i % 256) as u8).collect();
+        let expected = data.clone();
+        let sender = std::thread::spawn(move || {
+            let config = TransportConfig::default().with_max_frame_size(1024);
+            XTransport::new(a, config).send_message(&data).unwrap();
+        });
+        let config = TransportConfig::default().with_max_frame_size(1024);
+        let received = XTransport::new(b, config).recv_message().unwrap();
+        sender.join().unwrap();
+        assert_eq!(received, expected);
+    }
+}
@@ -4,6 +4,9 @@
 
 //! Yamux 传输协议实现
 
+mod runtime;
+mod stream;
 mod transfer_handler;
-pub use transfer_handler::get_runtime;
+pub use runtime::{get_runtime, use_runtime, YamuxRuntime};
+pub use stream::VirgaStream;
 pub use transfer_handler::YamuxTransportHandler;
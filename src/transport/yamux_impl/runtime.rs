@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! yamux 使用的 tokio 运行时
+//!
+//! 同步接口在运行时上驱动 yamux 连接：每条连接的驱动、读任务在运行时中常驻，收发时
+//! 阻塞等待对应的任务完成。运行时的来源按优先级为：
+//!
+//! 1. 应用在首次建立连接前用 [`use_runtime()`] 交给库的运行时句柄。句柄所属的运行时
+//!    应为多线程运行时，或由应用的其他线程持续驱动；否则连接的后台任务无法推进
+//! 2. 否则在首次使用时按 `threads::configure()` 的配置创建库自己的运行时：默认为
+//!    4 个工作线程的多线程运行时；配置 `with_current_thread_runtime()` 时不启动任何
+//!    线程，后台任务（回应 ping、记录 GOAWAY 等）只在调用方阻塞于同步接口时推进
+//!
+//! 运行时一经确定便不再改变，之后调用 [`use_runtime()`] 返回 `ConfigError`。
+
+use std::future::Future;
+use std::sync::OnceLock;
+
+use tokio::runtime::{Handle, Runtime};
+use tokio::task::JoinHandle;
+
+use crate::error::{Result, VirgeError};
+use crate::threads;
+
+/// yamux 连接所在的运行时：库创建的运行时或应用交给库的句柄
+#[derive(Debug)]
+pub enum YamuxRuntime {
+    /// 库按 `threads::configure()` 的配置创建的运行时
+    Owned(Runtime),
+    /// 应用通过 [`use_runtime()`] 交给库的运行时句柄
+    Shared(Handle),
+}
+
+impl YamuxRuntime {
+    /// 运行时句柄
+    pub fn handle(&self) -> &Handle {
+        match self {
+            YamuxRuntime::Owned(runtime) => runtime.handle(),
+            YamuxRuntime::Shared(handle) => handle,
+        }
+    }
+
+    /// 阻塞当前线程直到 `future` 完成；在异步上下文中调用会 panic
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        match self {
+            // 单线程运行时只有经由 Runtime::block_on 才会推进 IO 与定时器
+            YamuxRuntime::Owned(runtime) => runtime.block_on(future),
+            YamuxRuntime::Shared(handle) => handle.block_on(future),
+        }
+    }
+
+    /// 在运行时上启动任务
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.handle().spawn(future)
+    }
+}
+
+static RUNTIME: OnceLock<YamuxRuntime> = OnceLock::new();
+
+/// 让 yamux 连接使用应用自己的运行时，须在首次建立连接或监听前调用；
+/// 运行时已确定时返回 `ConfigError`
+pub fn use_runtime(handle: Handle) -> Result<()> {
+    let mut handle = Some(handle);
+    RUNTIME.get_or_init(|| YamuxRuntime::Shared(handle.take().expect("initialised once")));
+    match handle {
+        None => Ok(()),
+        Some(_) => Err(VirgeError::ConfigError(
+            "the yamux runtime is already in use".to_string(),
+        )),
+    }
+}
+
+/// yamux 连接所在的运行时，见[模块文档](self)
+pub fn get_runtime() -> &'static YamuxRuntime {
+    RUNTIME.get_or_init(|| YamuxRuntime::Owned(build()))
+}
+
+/// 按 `threads::configure()` 的配置创建运行时：工作线程名为 `<前缀>-yamux`，
+/// 配置了 CPU 核时每个线程启动时先限定在这些核上
+fn build() -> Runtime {
+    let config = threads::start_runtime();
+    if config.current_thread_runtime() {
+        return tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to create tokio runtime for yamux");
+    }
+    let cores = config.runtime_cores().map(<[usize]>::to_vec);
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.runtime_threads())
+        .thread_name(format!("{}-yamux", config.name_prefix()))
+        .on_thread_start(move || {
+            if let Some(cores) = &cores {
+                threads::pin_current(cores);
+            }
+        })
+        .enable_all()
+        .build()
+        .expect("Failed to create tokio runtime for yamux")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_is_fixed_once_in_use() {
+        let runtime = get_runtime();
+        assert_eq!(runtime.block_on(runtime.spawn(async { 7 })).unwrap(), 7);
+        let other = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let err = use_runtime(other.handle().clone()).unwrap_err();
+        assert!(matches!(err, VirgeError::ConfigError(_)));
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 同一条 yamux 连接上的附加流
+//!
+//! 客户端以 `open_stream()` 打开、服务端以 `accept_stream()` 接受，每条流有各自的
+//! 收发与流控窗口，与主流及其他附加流并发使用、互不阻塞。附加流沿用主流的长度前缀
+//! 分帧，但只承载普通消息：不经过连接认证建立的加密，也不做压缩与合批。
+
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+use futures::{AsyncReadExt, AsyncWriteExt};
+use log::*;
+use yamux::Stream;
+
+use super::runtime::get_runtime;
+use super::transfer_handler::{CONTROL_FLAG, CONTROL_OPEN, LENGTH_PREFIX_SIZE, MAX_CONTROL_SIZE};
+use crate::buffers;
+use crate::error::ConnContext;
+
+/// 一条 yamux 附加流，可移交给其他线程独立收发
+pub struct VirgaStream {
+    stream: Stream,
+    conn: ConnContext,
+    idle_timeout: Option<Duration>,
+    strict: bool,
+    closed: bool,
+}
+
+impl VirgaStream {
+    /// 包装本端刚打开的流，先写出打开帧让对端立即看到它
+    pub(super) async fn opened(
+        mut stream: Stream,
+        conn: ConnContext,
+        strict: bool,
+    ) -> Result<Self> {
+        write_frame(&mut stream, CONTROL_FLAG | 1, &[CONTROL_OPEN]).await?;
+        debug!("Yamux opened stream {} on {}", stream.id(), conn);
+        Ok(Self::accepted(stream, conn, strict))
+    }
+
+    /// 包装对端打开的流；`strict` 时打开帧以外的控制帧按协议错误处理
+    pub(super) fn accepted(stream: Stream, conn: ConnContext, strict: bool) -> Self {
+        Self {
+            stream,
+            conn,
+            idle_timeout: None,
+            strict,
+            closed: false,
+        }
+    }
+
+    /// 流在连接内的编号，客户端打开的流为奇数
+    pub fn id(&self) -> u32 {
+        self.stream.id().val()
+    }
+
+    /// 所属连接
+    pub fn conn(&self) -> ConnContext {
+        self.conn
+    }
+
+    /// 设置接收的空闲超时，`None` 表示一直等待
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle_timeout = timeout;
+    }
+
+    /// 发送一条消息，返回发送字节数
+    pub fn send(&mut self, data: &[u8]) -> Result<usize> {
+        if self.closed {
+            return Err(Error::new(ErrorKind::NotConnected, "Yamux stream closed"));
+        }
+        get_runtime().block_on(write_frame(&mut self.stream, data.len() as u64, data))?;
+        Ok(data.len())
+    }
+
+    /// 接收一条消息；对端关闭流后返回 `UnexpectedEof`，空闲超时返回 `TimedOut`
+    pub fn recv(&mut self) -> Result<Vec<u8>> {
+        let (idle_timeout, strict) = (self.idle_timeout, self.strict);
+        let stream = &mut self.stream;
+        get_runtime().block_on(async {
+            match idle_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, read_message(stream, strict))
+                    .await
+                    .map_err(|_| Error::new(ErrorKind::TimedOut, "yamux stream idle timeout"))?,
+                None => read_message(stream, strict).await,
+            }
+        })
+    }
+
+    /// 关闭本端的发送方向，对端取完已发出的消息后收到 `UnexpectedEof`
+    pub fn close(&mut self) -> Result<()> {
+        if std::mem::replace(&mut self.closed, true) {
+            return Ok(());
+        }
+        get_runtime().block_on(self.stream.close())
+    }
+}
+
+/// 未关闭就丢弃时先关闭发送方向；在异步上下文中无法阻塞等待，直接丢弃时对端收到重置
+impl Drop for VirgaStream {
+    fn drop(&mut self) {
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return;
+        }
+        if let Err(e) = self.close() {
+            debug!("Yamux stream {} close failed: {}", self.id(), e);
+        }
+    }
+}
+
+/// 以 `prefix` 为长度前缀写出 `data` 并刷新
+async fn write_frame(stream: &mut Stream, prefix: u64, data: &[u8]) -> Result<()> {
+    let mut frame = buffers::acquire(LENGTH_PREFIX_SIZE + data.len());
+    frame.extend_from_slice(&prefix.to_be_bytes());
+    frame.extend_from_slice(data);
+    let written = stream.write_all(&frame).await;
+    buffers::recycle(frame);
+    written?;
+    stream.flush().await
+}
+
+/// 读取下一条消息，跳过控制帧；`strict` 时只接受打开帧
+async fn read_message(stream: &mut Stream, strict: bool) -> Result<Vec<u8>> {
+    loop {
+        let mut len_buf = [0u8; LENGTH_PREFIX_SIZE];
+        stream.read_exact(&mut len_buf).await?;
+        let prefix = u64::from_be_bytes(len_buf);
+        if prefix & CONTROL_FLAG != 0 {
+            let len = (prefix & !CONTROL_FLAG) as usize;
+            if len == 0 || len > MAX_CONTROL_SIZE {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid yamux control frame length {}", len),
+                ));
+            }
+            let mut body = [0u8; MAX_CONTROL_SIZE];
+            stream.read_exact(&mut body[..len]).await?;
+            if strict && (len, body[0]) != (1, CONTROL_OPEN) {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "unexpected yamux control frame type {} on a stream",
+                        body[0]
+                    ),
+                ));
+            }
+            continue;
+        }
+        let mut buf = buffers::acquire(prefix as usize);
+        buf.resize(prefix as usize, 0);
+        stream.read_exact(&mut buf).await?;
+        return Ok(buf);
+    }
+}
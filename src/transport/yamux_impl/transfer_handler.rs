@@ -2,35 +2,104 @@
 // Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
 // See LICENSES for license details.
 
+use std::collections::VecDeque;
+use std::io::{ErrorKind, IoSliceMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, OnceLock};
+use std::task::Poll;
+use std::time::{Duration, Instant};
 
-use crate::error::{Result, VirgeError};
+use super::runtime::get_runtime;
+use crate::addr::Addr;
+use crate::auth::secure::SecureChannel;
+use crate::budget::{self, BufferAccount, BUDGET_WAIT_TIMEOUT};
+use crate::buffers;
+use crate::clock::{self, Sample};
+use crate::compression::CompressionContext;
+use crate::error::{ConnContext, Result, ResultExt, VirgeError};
+use crate::events::{self, VirgaEvent};
+use crate::mux::Wake;
+use crate::stats::ConnectionStats;
+use crate::transport::batch::{Batcher, Coalescing};
+use crate::transport::extension;
+use crate::transport::{truncate_reason, unpack, FrameHook, MessageKind, RecvLoan};
+use crate::units::ByteSize;
+use crate::{GoAwayReason, ShutdownReason};
 use futures::future::poll_fn;
-use futures::AsyncReadExt;
-use futures::AsyncWriteExt;
+use futures::io::{ReadHalf, WriteHalf};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use log::*;
-use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio::task::JoinHandle;
 use tokio_util::compat::TokioAsyncReadCompatExt;
 use tokio_vsock::{VsockAddr, VsockStream};
 
+use super::stream::VirgaStream;
 use yamux::Stream;
-use yamux::{Config, Connection, Mode};
+use yamux::{Config, Connection, ConnectionError, Mode};
 
 /// 消息长度前缀的字节数（使用 usize, 8字节）
-const LENGTH_PREFIX_SIZE: usize = 8;
-
-/// 全局 tokio 运行时（多线程）
-static TOKIO_RT: OnceLock<Runtime> = OnceLock::new();
-
-pub fn get_runtime() -> &'static Runtime {
-    TOKIO_RT.get_or_init(|| {
-        tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(4)
-            .enable_all()
-            .build()
-            .expect("Failed to create tokio runtime for yamux")
-    })
+pub(super) const LENGTH_PREFIX_SIZE: usize = 8;
+
+/// 长度前缀最高位置 1 表示控制帧，其后为 1 字节类型，ping/pong 与时钟请求再跟
+/// 8 字节 nonce（大端），时钟回复在 nonce 后再跟对端收到请求、发出回复的时刻
+/// （各 8 字节，自 Unix 纪元起的纳秒）；接收时在内部处理，不交给上层
+pub(super) const CONTROL_FLAG: u64 = 1 << 63;
+/// 控制帧体的最大长度，超出视为损坏
+pub(super) const MAX_CONTROL_SIZE: usize = 256;
+const CONTROL_PING: u8 = 1;
+const CONTROL_PONG: u8 = 2;
+/// 紧随其后的消息帧经过 zstd 压缩，帧体只有类型字节
+const CONTROL_COMPRESSED: u8 = 3;
+/// 紧随其后的消息帧是合批的多条消息，帧体只有类型字节
+const CONTROL_BATCH: u8 = 4;
+const CONTROL_TIME_REQUEST: u8 = 5;
+const CONTROL_TIME_REPLY: u8 = 6;
+/// 请对端重连，负载为 8 字节原因码（大端）
+const CONTROL_GOAWAY: u8 = 7;
+/// 拒绝连接，负载为 UTF-8 原因；发送方随后断开连接
+const CONTROL_REJECT: u8 = 8;
+/// 连接结束的原因，负载为 8 字节原因码（大端）；发送方随后关闭 stream
+const CONTROL_CLOSE: u8 = 9;
+/// 新流的第一帧，帧体只有类型字节：yamux 在流上写出数据时才通知对端，
+/// 打开后立即发送，对端按打开的先后看到各条流
+pub(super) const CONTROL_OPEN: u8 = 10;
+// 0x80 及以上的类型是应用的旁路帧，见 `transport::extension`
+
+/// 服务端等待 `accept_stream()` 取走的附加流上限，超出时重置新流
+const ACCEPT_BACKLOG: usize = 32;
+
+/// 发送共享的 stream 写半部分；后台读任务回应 ping 时也经由它写出
+type Writer = Arc<tokio::sync::Mutex<WriteHalf<Stream>>>;
+
+/// 读队列中的一项：消息帧及其内容类型，或使读任务退出的错误
+type Frame = Result<(Vec<u8>, MessageKind)>;
+
+/// 交给 driver 任务的打开新流请求，结果经 oneshot 送回
+type Open = oneshot::Sender<std::result::Result<Stream, ConnectionError>>;
+
+/// 在 yamux 运行时上启动任务
+///
+/// 启用 `tokio-console` 特性并以 `--cfg tokio_unstable` 编译时任务带上名字
+/// （如 `yamux-driver conn 1 (cid=3, port=1234)`），`tokio-console` 中可直接看出
+/// 卡住的是哪条连接的哪个任务；否则名字不产生任何开销。
+fn spawn_named<F>(name: std::fmt::Arguments<'_>, future: F) -> JoinHandle<F::Output>
+where
+    F: std::future::Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "tokio-console"))]
+    {
+        tokio::task::Builder::new()
+            .name(&name.to_string())
+            .spawn_on(future, get_runtime().handle())
+            .expect("Failed to spawn yamux task")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "tokio-console")))]
+    {
+        let _ = name;
+        get_runtime().spawn(future)
+    }
 }
 
 /// Yamux 传输协议处理器
@@ -38,32 +107,135 @@ pub fn get_runtime() -> &'static Runtime {
 /// 对外提供同步接口，内部通过 tokio runtime 驱动 yamux 异步操作。
 /// Connection 所有权在获取 stream 后移交给 driver task，避免死锁。
 ///
-/// 使用 Arc<Mutex<Stream>> 在 block_on 和 driver task 之间共享 stream，
-/// 避免 block_on 阻塞整个 runtime 导致死锁。
+/// stream 拆为读写两半：写半部分以 Arc<Mutex<..>> 供发送任务共享；读半部分交给
+/// 后台读任务，持续把消息帧读入有界队列，`recv()` 只是从队列取出一项。应用不调用
+/// `recv()` 时 ping 仍能得到回应；队列满时读任务暂停，由 yamux 流控反压对端。
 pub struct YamuxTransportHandler {
-    yamux_stream: Option<Arc<tokio::sync::Mutex<Stream>>>,
+    yamux_stream: Option<Writer>,
+    reader: Option<Reader>,
+    read_queue_depth: usize,
+    strict: bool,
     driver_handle: Option<JoinHandle<()>>,
+    /// 向 driver 任务请求打开新流
+    opens: Option<mpsc::UnboundedSender<Open>>,
+    /// 服务端：对端打开的附加流
+    accepted: Option<mpsc::Receiver<Stream>>,
     mode: Mode,
+    stats: ConnectionStats,
+    loan_buffer: Vec<u8>,
+    conn: ConnContext,
+    idle_timeout: Option<Duration>,
+    secure: Option<SecureChannel>,
+    compression: CompressionContext,
+    coalescing: Option<Coalescing>,
+    batch: Option<Batcher>,
+    unbatched: VecDeque<Vec<u8>>,
+    budget: BufferAccount,
+    pending: usize,
+    pings: u64,
+    /// 上一次接收因空闲超时失败
+    idle: bool,
+}
+
+/// 后台读任务的接收端
+struct Reader {
+    frames: mpsc::Receiver<Frame>,
+    /// `wait_readable()` 已从队列取出、留给下一次接收的帧
+    ready: Option<Frame>,
+    /// 最近收到的 pong 携带的 nonce
+    pongs: watch::Receiver<u64>,
+    /// 最近收到的时钟回复：(nonce, 对端收到请求的时刻, 对端发出回复的时刻)
+    clocks: watch::Receiver<(u64, u64, u64)>,
+    /// 对端发来的 GOAWAY 原因码
+    goaway: watch::Receiver<Option<u64>>,
+    /// 对端关闭连接前说明的结束原因码
+    close: watch::Receiver<Option<u64>>,
+    /// 队列中消息帧的总字节数
+    queued: Arc<AtomicUsize>,
+    /// 读任务读到任何帧时调用，见 [`YamuxTransportHandler::set_frame_hook()`]
+    frame_hook: Arc<OnceLock<FrameHook>>,
+    handle: JoinHandle<()>,
 }
 
 impl YamuxTransportHandler {
     pub fn new(mode: Mode) -> Self {
         Self {
             yamux_stream: None,
+            reader: None,
+            read_queue_depth: crate::Defaults::get().read_queue_depth.get(),
+            strict: false,
             driver_handle: None,
+            opens: None,
+            accepted: None,
             mode,
+            stats: ConnectionStats::default(),
+            loan_buffer: Vec::new(),
+            conn: ConnContext::default(),
+            idle_timeout: None,
+            secure: None,
+            compression: CompressionContext::default(),
+            coalescing: None,
+            batch: None,
+            unbatched: VecDeque::new(),
+            budget: BufferAccount::default(),
+            pending: 0,
+            pings: 0,
+            idle: false,
         }
     }
+
+    /// 本连接内部缓冲的上限，超过时每次接收前释放复用缓冲
+    pub fn with_memory_limit(mut self, limit: Option<ByteSize>) -> Self {
+        self.budget = BufferAccount::with_limit(limit);
+        self
+    }
+
+    /// 开启发送合批：小消息攒成一帧发送，接收方按原边界拆回
+    pub fn with_coalescing(mut self, coalescing: Option<Coalescing>) -> Self {
+        self.coalescing = coalescing;
+        self
+    }
+
+    /// 后台读任务预读的最大消息帧数，至少为 1
+    pub fn with_read_queue_depth(mut self, frames: usize) -> Self {
+        self.read_queue_depth = frames.max(1);
+        self
+    }
+
+    /// 严格模式：未知类型或长度不符的控制帧按协议错误处理，默认跳过以兼容较新的对端
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// 拆分 stream：写半部分供发送共享，读半部分交给后台读任务
+    fn start(&mut self, stream: Stream) {
+        let (writer, reader) = spawn_reader(
+            stream,
+            self.read_queue_depth,
+            self.conn,
+            self.strict,
+            self.budget.limit(),
+        );
+        self.yamux_stream = Some(writer);
+        self.reader = Some(reader);
+    }
 }
 
 impl YamuxTransportHandler {
     /// 客户端连接到 vsock 地址
     pub fn connect(&mut self, cid: u32, port: u32, _chunk_size: u32, _is_ack: bool) -> Result<()> {
-        info!("Yamux transport connecting to cid={}, port={}", cid, port);
+        let addr = Addr::new(cid, port);
+        info!("Yamux transport connecting to {}", addr);
 
+        let started = Instant::now();
+        let conn = ConnContext::new(cid, port);
         let vsock_stream = get_runtime()
-            .block_on(async { VsockStream::connect(VsockAddr::new(cid, port)).await })
-            .map_err(|e| VirgeError::ConnectionError(format!("Failed to connect vsock: {}", e)))?;
+            .block_on(async { VsockStream::connect(VsockAddr::from(addr)).await })
+            .map_err(|e| {
+                VirgeError::connection_io("Failed to connect vsock", e).with_addr(cid, port)
+            })
+            .ctx(&conn, "connect")?;
 
         let config = Config::default();
         let mut connection = Connection::new(vsock_stream.compat(), config, Mode::Client);
@@ -73,36 +245,49 @@ impl YamuxTransportHandler {
         let stream = get_runtime()
             .block_on(async { poll_fn(|cx| connection.poll_new_outbound(cx)).await })
             .map_err(|e| {
-                VirgeError::TransportError(format!("Failed to open yamux outbound stream: {}", e))
-            })?;
-        self.yamux_stream = Some(Arc::new(tokio::sync::Mutex::new(stream)));
+                VirgeError::yamux_connection("Failed to open yamux outbound stream", e)
+                    .with_addr(cid, port)
+            })
+            .ctx(&conn, "connect")?;
+        self.conn = conn;
+        self.start(stream);
+        self.secure = None;
+        self.compression = CompressionContext::default();
+        self.batch = self.coalescing.map(Batcher::new);
+        self.unbatched.clear();
+        self.stats.record_connect(started.elapsed());
 
         // 将 connection 移交给 driver task
-        let handle = get_runtime().spawn(async move {
-            debug!("Yamux connection driver started");
-            loop {
-                match poll_fn(|cx| connection.poll_next_inbound(cx)).await {
-                    Some(Ok(_stream)) => {}
-                    Some(Err(e)) => {
-                        warn!("Yamux connection error in driver: {}", e);
-                        break;
-                    }
-                    None => {
-                        debug!("Yamux connection closed (driver)");
-                        break;
-                    }
-                }
-            }
-            debug!("Yamux connection driver stopped");
-        });
+        let (opens, requests) = mpsc::unbounded_channel();
+        self.opens = Some(opens);
+        self.accepted = None;
+        let handle = spawn_named(
+            format_args!("yamux-driver {}", conn),
+            drive(connection, conn, requests, None),
+        );
         self.driver_handle = Some(handle);
 
+        // 先发出打开帧，对端随即接受主流，之后打开的附加流都排在它后面
+        if let Err(e) = self.send_prefixed(CONTROL_FLAG | 1, &[CONTROL_OPEN]) {
+            if let Some(handle) = self.driver_handle.take() {
+                handle.abort();
+            }
+            if let Some(reader) = self.reader.take() {
+                reader.handle.abort();
+            }
+            return Err(e);
+        }
+
         info!("Yamux transport connected successfully");
         Ok(())
     }
 
     /// 从已有的 VsockStream 初始化（服务端模式）
     pub fn from_tokio_stream(&mut self, vsock_stream: VsockStream) -> Result<()> {
+        let conn = match vsock_stream.peer_addr() {
+            Ok(addr) => ConnContext::new(addr.cid(), addr.port()),
+            Err(_) => ConnContext::new(0, 0),
+        };
         let config = Config::default();
         let mut connection = Connection::new(vsock_stream.compat(), config, Mode::Server);
         self.mode = Mode::Server;
@@ -113,39 +298,40 @@ impl YamuxTransportHandler {
 
         match stream_result {
             Some(Ok(s)) => {
-                self.yamux_stream = Some(Arc::new(tokio::sync::Mutex::new(s)));
+                self.conn = conn;
+                self.start(s);
+                self.secure = None;
+                self.compression = CompressionContext::default();
+                self.batch = self.coalescing.map(Batcher::new);
+                self.unbatched.clear();
             }
             Some(Err(e)) => {
-                return Err(VirgeError::TransportError(format!(
-                    "Failed to accept yamux inbound stream: {}",
-                    e
-                )));
+                return Err(VirgeError::yamux_connection(
+                    "Failed to accept yamux inbound stream",
+                    e,
+                )
+                .with_addr(conn.cid, conn.port)
+                .with_context(&conn, "accept"));
             }
             None => {
-                return Err(VirgeError::TransportError(
-                    "Yamux connection closed, no inbound stream".into(),
-                ));
+                return Err(VirgeError::yamux_connection(
+                    "Yamux connection closed, no inbound stream",
+                    yamux::ConnectionError::Closed,
+                )
+                .with_addr(conn.cid, conn.port)
+                .with_context(&conn, "accept"));
             }
         }
 
-        // 将 connection 移交给 driver task
-        let handle = get_runtime().spawn(async move {
-            debug!("Yamux server connection driver started");
-            loop {
-                match poll_fn(|cx| connection.poll_next_inbound(cx)).await {
-                    Some(Ok(_stream)) => {}
-                    Some(Err(e)) => {
-                        warn!("Yamux server connection error in driver: {}", e);
-                        break;
-                    }
-                    None => {
-                        debug!("Yamux server connection poll returned None (connection closed)");
-                        break;
-                    }
-                }
-            }
-            debug!("Yamux server connection driver stopped");
-        });
+        // 将 connection 移交给 driver task，之后对端打开的流留给 accept_stream()
+        let (opens, requests) = mpsc::unbounded_channel();
+        let (inbound, accepted) = mpsc::channel(ACCEPT_BACKLOG);
+        self.opens = Some(opens);
+        self.accepted = Some(accepted);
+        let handle = spawn_named(
+            format_args!("yamux-driver {}", conn),
+            drive(connection, conn, requests, Some(inbound)),
+        );
         self.driver_handle = Some(handle);
 
         info!("Yamux transport initialized from stream (server mode)");
@@ -154,10 +340,18 @@ impl YamuxTransportHandler {
 
     pub fn disconnect(&mut self) -> Result<()> {
         info!("Yamux transport disconnecting");
+        if let Err(e) = self.flush() {
+            warn!("Yamux dropped unsent batch on disconnect: {}", e);
+        }
+        self.loan_buffer = Vec::new();
+        self.unbatched.clear();
+        self.set_pending(0);
+        self.opens = None;
+        self.accepted = None;
 
         // 关闭 stream（会发送 FIN 帧）
         if let Some(stream) = self.yamux_stream.take() {
-            let _ = get_runtime().block_on(async {
+            get_runtime().block_on(async {
                 let mut s = stream.lock().await;
                 // 先 flush 确保所有数据发送完成
                 let _ = s.flush().await;
@@ -171,47 +365,120 @@ impl YamuxTransportHandler {
 
         // 等待 driver 退出
         if let Some(handle) = self.driver_handle.take() {
-            let _ = get_runtime().block_on(async {
+            get_runtime().block_on(async {
                 let _ = tokio::time::timeout(std::time::Duration::from_secs(2), handle).await;
             });
         }
+        if let Some(reader) = self.reader.take() {
+            reader.handle.abort();
+        }
 
         info!("Yamux transport disconnected");
         Ok(())
     }
 
-    /// 发送数据（使用长度前缀协议）
+    /// 发送数据（使用长度前缀协议），启用加密时先加密，必要时先发换钥帧。
+    /// 开启合批时小消息先放入批次，批次满足条件时才发出
     pub fn send(&mut self, data: &[u8]) -> Result<usize> {
+        let started = Instant::now();
+        match self.batch.as_mut() {
+            Some(batch) if self.yamux_stream.is_some() && batch.fits(data.len()) => {
+                batch.push(data);
+                if batch.due() {
+                    self.flush()?;
+                }
+            }
+            _ => {
+                self.flush()?;
+                self.send_message(data, MessageKind::Plain)?;
+            }
+        }
+        self.stats.record_send(data.len(), started.elapsed());
+        Ok(data.len())
+    }
+
+    /// 发出已合批但尚未发送的消息；未开启合批或批次为空时什么也不做
+    pub fn flush(&mut self) -> Result<()> {
+        match self.batch.as_mut().and_then(Batcher::take) {
+            Some((data, kind)) => self.send_message(&data, kind),
+            None => Ok(()),
+        }
+    }
+
+    /// 压缩后发送一条消息，对端接收时自动解压；返回原文长度
+    #[cfg(feature = "compression")]
+    pub fn send_compressed(&mut self, data: &[u8]) -> Result<usize> {
+        let started = Instant::now();
+        let packed = self.compression.compress(data).ctx(&self.conn, "send")?;
+        self.flush()?;
+        self.send_message(&packed, MessageKind::Compressed)?;
+        self.stats.record_send(data.len(), started.elapsed());
+        debug!(
+            "Yamux sent {} bytes compressed to {}",
+            data.len(),
+            packed.len()
+        );
+        Ok(data.len())
+    }
+
+    /// 启用加密时内容类型记在加密帧类型中，否则压缩消息与批次前发送对应的控制帧
+    fn send_message(&mut self, data: &[u8], kind: MessageKind) -> Result<()> {
+        let Some(secure) = self.secure.as_mut() else {
+            match kind {
+                MessageKind::Plain => {}
+                MessageKind::Compressed => {
+                    self.send_prefixed(CONTROL_FLAG | 1, &[CONTROL_COMPRESSED])?
+                }
+                MessageKind::Batch => self.send_prefixed(CONTROL_FLAG | 1, &[CONTROL_BATCH])?,
+            }
+            return self.send_frame(data);
+        };
+        let update = secure
+            .key_update_due()
+            .map_err(VirgeError::from)
+            .ctx(&self.conn, "send")?;
+        let frame = secure
+            .seal_as(data, kind)
+            .map_err(VirgeError::from)
+            .ctx(&self.conn, "send")?;
+        if let Some(update) = update {
+            self.send_frame(&update)?;
+        }
+        self.send_frame(&frame)
+    }
+
+    fn send_frame(&mut self, data: &[u8]) -> Result<()> {
+        self.send_prefixed(data.len() as u64, data)
+    }
+
+    /// 以 `prefix` 为长度前缀发送 `data`，控制帧的前缀带 `CONTROL_FLAG`
+    fn send_prefixed(&mut self, prefix: u64, data: &[u8]) -> Result<()> {
         let stream = self
             .yamux_stream
             .as_ref()
-            .ok_or_else(|| VirgeError::TransportError("Yamux stream not available".into()))?
+            .ok_or_else(|| {
+                VirgeError::transport(ErrorKind::NotConnected, "Yamux stream not available")
+            })?
             .clone();
 
         let data_len = data.len();
-        let data = data.to_vec();
+        // 长度前缀与数据拼在同一个缓冲中一次写出
+        let mut frame = buffers::acquire(LENGTH_PREFIX_SIZE + data_len);
+        frame.extend_from_slice(&prefix.to_be_bytes());
+        frame.extend_from_slice(data);
 
         // 使用 spawn 在独立任务中执行，避免阻塞 driver
-        get_runtime().block_on(async {
-            let send_task = tokio::spawn(async move {
+        let sent = get_runtime().block_on(async {
+            let send_task = spawn_named(format_args!("yamux-send {}", self.conn), async move {
                 let mut s = stream.lock().await;
-
-                // 先发送8字节的长度前缀
-                let len = data.len() as usize;
-                let len_bytes = len.to_be_bytes();
-                s.write_all(&len_bytes)
-                    .await
-                    .map_err(|e| VirgeError::Other(format!("yamux send length error: {}", e)))?;
-
-                // 再发送实际数据
-                s.write_all(&data)
-                    .await
-                    .map_err(|e| VirgeError::Other(format!("yamux send error: {}", e)))?;
+                let written = s.write_all(&frame).await;
+                buffers::recycle(frame);
+                written.map_err(|e| VirgeError::yamux_stream("yamux send error", e))?;
 
                 // flush 确保数据发送出去
                 s.flush()
                     .await
-                    .map_err(|e| VirgeError::Other(format!("yamux flush error: {}", e)))?;
+                    .map_err(|e| VirgeError::yamux_stream("yamux flush error", e))?;
 
                 Ok::<_, VirgeError>(())
             });
@@ -219,52 +486,1129 @@ impl YamuxTransportHandler {
             send_task
                 .await
                 .map_err(|e| VirgeError::Other(format!("send task join error: {}", e)))?
-        })?;
+        });
+        sent.map_err(|e| self.pending_rejection().map_or(e, VirgeError::Rejected))
+            .ctx(&self.conn, "send")?;
 
         debug!("Yamux sent {} bytes (with length prefix)", data_len);
-        Ok(data_len)
+        Ok(())
     }
 
     /// 接收数据（使用长度前缀协议）
     pub fn recv(&mut self) -> Result<Vec<u8>> {
-        let stream = self
-            .yamux_stream
+        let data = self.recv_message()?;
+        self.account();
+        Ok(data)
+    }
+
+    /// 接收一条消息到连接内部的缓冲区，返回的视图在下一次接收前有效
+    pub fn recv_loan(&mut self) -> Result<RecvLoan<'_>> {
+        let data = self.recv_message()?;
+        buffers::recycle(std::mem::replace(&mut self.loan_buffer, data));
+        self.account();
+        Ok(RecvLoan::new(&self.loan_buffer))
+    }
+
+    /// 从读队列取出一条消息；启用加密时原地解密并跳过换钥帧，
+    /// 压缩消息与批次还原到新的缓冲。先发出未发送的批次；收到批次时逐条返回
+    fn recv_message(&mut self) -> Result<Vec<u8>> {
+        let started = Instant::now();
+        self.flush()?;
+        if let Some(data) = self.unbatched.pop_front() {
+            self.stats.record_recv(data.len(), started.elapsed());
+            return Ok(data);
+        }
+        self.reserve_recv()?;
+        loop {
+            let (mut data, marked) = self.recv_frame()?;
+            let kind = match self.secure.as_mut() {
+                None => Some(marked),
+                Some(secure) => secure
+                    .open_in_place(&mut data)
+                    .map_err(VirgeError::from)
+                    .ctx(&self.conn, "recv")?
+                    .then(|| secure.last_kind()),
+            };
+            if let Some(kind) = kind {
+                let data = match kind {
+                    MessageKind::Plain => data,
+                    kind => {
+                        let unpacked = unpack(&self.compression, &mut self.unbatched, &data, kind);
+                        buffers::recycle(data);
+                        unpacked.ctx(&self.conn, "recv")?
+                    }
+                };
+                self.stats.record_recv(data.len(), started.elapsed());
+                return Ok(data);
+            }
+        }
+    }
+
+    /// 从读队列取出下一条消息帧及其内容类型，空闲超时内队列一直为空时返回 `TimedOut`；
+    /// 读任务出错退出时先返回它的错误，之后返回 `NotConnected`
+    fn recv_frame(&mut self) -> Result<(Vec<u8>, MessageKind)> {
+        let reader = self.reader.as_mut().ok_or_else(|| {
+            VirgeError::transport(ErrorKind::NotConnected, "Yamux stream not available")
+        })?;
+
+        let idle_timeout = self.idle_timeout;
+        let next = match reader.ready.take() {
+            Some(frame) => Ok(Some(frame)),
+            None => get_runtime().block_on(async {
+                let next = reader.frames.recv();
+                match idle_timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, next).await.map_err(|_| {
+                        VirgeError::transport(ErrorKind::TimedOut, "yamux idle timeout")
+                    }),
+                    None => Ok(next.await),
+                }
+            }),
+        };
+        let (data, kind) = next
+            .and_then(|frame| {
+                frame.unwrap_or_else(|| {
+                    Err(VirgeError::transport(
+                        ErrorKind::NotConnected,
+                        "yamux read loop stopped",
+                    ))
+                })
+            })
+            .map_err(|e| {
+                let goaway = reader.goaway.borrow().map(GoAwayReason::from_code);
+                e.ended(*reader.close.borrow(), goaway)
+            })
+            .inspect_err(|e| self.idle = e.kind() == ErrorKind::TimedOut)
+            .ctx(&self.conn, "recv")?;
+        self.idle = false;
+        reader.queued.fetch_sub(data.len(), Ordering::Relaxed);
+
+        debug!("Yamux received {} bytes", data.len());
+        Ok((data, kind))
+    }
+
+    /// 等待读队列中出现下一帧、被 `wake` 唤醒或超过 `timeout`，有消息可接收时返回
+    /// `true`；取出的帧留给下一次接收
+    pub(crate) fn wait_readable(
+        &mut self,
+        wake: Option<&Wake>,
+        timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let reader = self.reader.as_mut().ok_or_else(|| {
+            VirgeError::transport(ErrorKind::NotConnected, "Yamux stream not available")
+        })?;
+        if !self.unbatched.is_empty() || reader.ready.is_some() {
+            return Ok(true);
+        }
+        let frame = get_runtime().block_on(async {
+            let woken = async {
+                match wake {
+                    Some(wake) => wake.woken().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let expired = async {
+                match timeout {
+                    Some(timeout) => tokio::time::sleep(timeout).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                frame = reader.frames.recv() => Some(frame),
+                _ = woken => None,
+                _ = expired => None,
+            }
+        });
+        let Some(frame) = frame else {
+            return Ok(false);
+        };
+        reader.ready = Some(frame.unwrap_or_else(|| {
+            Err(VirgeError::transport(
+                ErrorKind::NotConnected,
+                "yamux read loop stopped",
+            ))
+        }));
+        Ok(true)
+    }
+
+    /// 发送 ping 并等待对端回应，返回往返时长。对端的后台读任务收到 ping 即回应，
+    /// 不必等应用接收；等待期间先到的消息留在读队列中
+    pub fn ping(&mut self, timeout: Duration) -> Result<Duration> {
+        let started = Instant::now();
+        self.flush()?;
+        let mut pongs = self
+            .reader
             .as_ref()
-            .ok_or_else(|| VirgeError::TransportError("Yamux stream not available".into()))?
+            .ok_or_else(|| {
+                VirgeError::transport(ErrorKind::NotConnected, "Yamux stream not available")
+            })?
+            .pongs
             .clone();
+        self.pings += 1;
+        let nonce = self.pings;
+        let mut body = vec![CONTROL_PING];
+        body.extend_from_slice(&nonce.to_be_bytes());
+        self.send_prefixed(CONTROL_FLAG | body.len() as u64, &body)?;
 
-        let data = get_runtime().block_on(async {
-            let recv_task = tokio::spawn(async move {
-                let mut s = stream.lock().await;
+        get_runtime()
+            .block_on(async {
+                match tokio::time::timeout(timeout, pongs.wait_for(|pong| *pong >= nonce)).await {
+                    Ok(Ok(_)) => Ok(()),
+                    Ok(Err(_)) => Err(VirgeError::transport(
+                        ErrorKind::NotConnected,
+                        "yamux read loop stopped",
+                    )),
+                    Err(_) => Err(VirgeError::transport(
+                        ErrorKind::TimedOut,
+                        "yamux ping timeout",
+                    )),
+                }
+            })
+            .ctx(&self.conn, "ping")?;
+        Ok(started.elapsed())
+    }
 
-                // 先读取8字节的长度前缀
-                let mut len_buf = [0u8; LENGTH_PREFIX_SIZE];
-                s.read_exact(&mut len_buf)
-                    .await
-                    .map_err(|e| VirgeError::Other(format!("yamux recv length error: {}", e)))?;
+    /// 与对端进行一轮时钟交换，超过 `timeout` 未回复返回超时错误。对端的后台读任务
+    /// 收到请求即回复；等待期间先到的消息留在读队列中
+    pub(crate) fn time_probe(&mut self, timeout: Duration) -> Result<Sample> {
+        self.flush()?;
+        let mut clocks = self
+            .reader
+            .as_ref()
+            .ok_or_else(|| {
+                VirgeError::transport(ErrorKind::NotConnected, "Yamux stream not available")
+            })?
+            .clocks
+            .clone();
+        self.pings += 1;
+        let nonce = self.pings;
+        let mut body = vec![CONTROL_TIME_REQUEST];
+        body.extend_from_slice(&nonce.to_be_bytes());
+        let t1 = clock::now_nanos();
+        self.send_prefixed(CONTROL_FLAG | body.len() as u64, &body)?;
 
-                let len = u64::from_be_bytes(len_buf) as usize;
-                debug!("Yamux expecting to receive {} bytes", len);
+        let (t2, t3) = get_runtime()
+            .block_on(async {
+                let reply = clocks.wait_for(|(replied, ..)| *replied >= nonce);
+                match tokio::time::timeout(timeout, reply).await {
+                    Ok(Ok(reply)) => Ok((reply.1, reply.2)),
+                    Ok(Err(_)) => Err(VirgeError::transport(
+                        ErrorKind::NotConnected,
+                        "yamux read loop stopped",
+                    )),
+                    Err(_) => Err(VirgeError::transport(
+                        ErrorKind::TimedOut,
+                        "yamux time sync timeout",
+                    )),
+                }
+            })
+            .ctx(&self.conn, "time_sync")?;
+        Ok(Sample {
+            t1,
+            t2,
+            t3,
+            t4: clock::now_nanos(),
+        })
+    }
 
-                // 读取实际数据
-                let mut buf = vec![0u8; len];
-                s.read_exact(&mut buf)
-                    .await
-                    .map_err(|e| VirgeError::Other(format!("yamux recv error: {}", e)))?;
+    /// 请对端在完成手头的请求后重连，连接本身仍可使用
+    pub(crate) fn go_away(&mut self, reason: GoAwayReason) -> Result<()> {
+        self.flush()?;
+        let mut body = vec![CONTROL_GOAWAY];
+        body.extend_from_slice(&reason.code().to_be_bytes());
+        self.send_prefixed(CONTROL_FLAG | body.len() as u64, &body)
+    }
 
-                Ok::<Vec<u8>, VirgeError>(buf)
-            });
+    /// 发送一个旁路帧，先发出缓冲中的消息以保持先后顺序。旁路帧不加密，
+    /// 由对端的后台读任务交给注册的处理函数
+    pub fn send_extension(&mut self, frame_type: u8, data: &[u8]) -> Result<()> {
+        extension::check_outgoing(frame_type, data)?;
+        self.flush()?;
+        let mut body = vec![frame_type];
+        body.extend_from_slice(data);
+        self.send_prefixed(CONTROL_FLAG | body.len() as u64, &body)
+    }
 
-            recv_task
-                .await
-                .map_err(|e| VirgeError::Other(format!("recv task join error: {}", e)))?
+    /// 告知对端拒绝连接的原因，随后由调用方断开连接
+    pub(crate) fn reject(&mut self, reason: &str) -> Result<()> {
+        let mut body = vec![CONTROL_REJECT];
+        body.extend_from_slice(truncate_reason(reason).as_bytes());
+        self.send_prefixed(CONTROL_FLAG | body.len() as u64, &body)
+    }
+
+    /// 告知对端连接结束的原因后断开。未给出原因时，上一次接收空闲超时则为
+    /// [`ShutdownReason::IdleTimeout`]，否则为 [`ShutdownReason::GracefulClose`]；
+    /// 原因发送失败（对端已断开）不影响断开
+    pub(crate) fn close(&mut self, reason: Option<ShutdownReason>) -> Result<()> {
+        let reason = reason.unwrap_or(if self.idle {
+            ShutdownReason::IdleTimeout
+        } else {
+            ShutdownReason::GracefulClose
+        });
+        if self.yamux_stream.is_some() {
+            if let Err(e) = self.flush() {
+                warn!("Yamux dropped unsent batch on close: {}", e);
+            }
+            let mut body = vec![CONTROL_CLOSE];
+            body.extend_from_slice(&reason.code().to_be_bytes());
+            if let Err(e) = self.send_prefixed(CONTROL_FLAG | body.len() as u64, &body) {
+                debug!("Yamux could not send close reason {}: {}", reason, e);
+            }
+        }
+        self.disconnect()
+    }
+
+    /// 发送失败后查看读任务是否已收到对端的拒绝原因，不等待
+    fn pending_rejection(&mut self) -> Option<String> {
+        let reader = self.reader.as_mut()?;
+        if reader.ready.is_none() {
+            reader.ready = reader.frames.try_recv().ok();
+        }
+        match &reader.ready {
+            Some(Err(e)) => e.rejection().map(str::to_string),
+            _ => None,
+        }
+    }
+
+    /// 在同一连接上打开一条附加流，与主流及其他附加流并发收发；对端以 `accept_stream()` 接受。
+    /// 附加流不经过认证建立的加密，启用加密时返回 `Unsupported`
+    pub fn open_stream(&mut self) -> Result<VirgaStream> {
+        if self.secure.is_some() {
+            return Err(VirgeError::transport(
+                ErrorKind::Unsupported,
+                "yamux streams are not encrypted; open_stream() is unavailable on secure connections",
+            ))
+            .ctx(&self.conn, "open_stream");
+        }
+        let opens = self.opens.as_ref().ok_or_else(|| {
+            VirgeError::transport(ErrorKind::NotConnected, "Yamux connection not available")
         })?;
+        let (reply, opened) = oneshot::channel();
+        let conn = self.conn;
+        let stream = get_runtime()
+            .block_on(async {
+                opens.send(reply).map_err(|_| ())?;
+                opened.await.map_err(|_| ())
+            })
+            .map_err(|_| {
+                VirgeError::transport(ErrorKind::NotConnected, "yamux connection driver stopped")
+            })
+            .and_then(|opened| {
+                opened.map_err(|e| VirgeError::yamux_connection("Failed to open yamux stream", e))
+            })
+            .ctx(&conn, "open_stream")?;
+        get_runtime()
+            .block_on(VirgaStream::opened(stream, conn, self.strict))
+            .map_err(|e| VirgeError::yamux_stream("yamux stream open error", e))
+            .ctx(&conn, "open_stream")
+    }
 
-        debug!("Yamux received {} bytes", data.len());
-        Ok(data)
+    /// 服务端：等待对端打开的下一条附加流，超过 `timeout` 仍没有时返回 `None`；
+    /// 连接关闭后返回 `NotConnected`。启用加密时返回 `Unsupported`
+    pub fn accept_stream(&mut self, timeout: Option<Duration>) -> Result<Option<VirgaStream>> {
+        if self.secure.is_some() {
+            return Err(VirgeError::transport(
+                ErrorKind::Unsupported,
+                "yamux streams are not encrypted; accept_stream() is unavailable on secure connections",
+            ))
+            .ctx(&self.conn, "accept_stream");
+        }
+        let accepted = self.accepted.as_mut().ok_or_else(|| {
+            VirgeError::transport(ErrorKind::NotConnected, "Yamux connection not available")
+        })?;
+        let next = get_runtime().block_on(async {
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, accepted.recv()).await.ok(),
+                None => Some(accepted.recv().await),
+            }
+        });
+        match next {
+            None => Ok(None),
+            Some(Some(stream)) => {
+                debug!("Yamux accepted stream {} on {}", stream.id(), self.conn);
+                Ok(Some(VirgaStream::accepted(stream, self.conn, self.strict)))
+            }
+            Some(None) => Err(VirgeError::transport(
+                ErrorKind::NotConnected,
+                "yamux connection driver stopped",
+            ))
+            .ctx(&self.conn, "accept_stream"),
+        }
+    }
+
+    /// 对端发来的 GOAWAY 原因；后台读任务收到即记录，不必等应用接收
+    pub(crate) fn goaway(&self) -> Option<GoAwayReason> {
+        let reader = self.reader.as_ref()?;
+        let reason = *reader.goaway.borrow();
+        reason.map(GoAwayReason::from_code)
+    }
+
+    /// 接收一条消息并按顺序填入 `bufs`，返回消息长度，超出缓冲总长的部分被丢弃。
+    /// yamux 的读取在运行时任务中完成，因此先收完整消息再拷贝
+    pub fn recv_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        let data = self.recv()?;
+        let mut rest = &data[..];
+        for buf in bufs.iter_mut() {
+            let n = buf.len().min(rest.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            rest = &rest[n..];
+        }
+        Ok(data.len())
     }
 
     pub fn is_connected(&self) -> bool {
         self.yamux_stream.is_some()
     }
+
+    /// 当前连接的标识（连接 ID 与对端地址）
+    pub fn conn(&self) -> ConnContext {
+        self.conn
+    }
+
+    /// 设置空闲超时：读队列空着等待超过该时长时接收返回 `TimedOut`
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+
+    /// 内部缓冲占用的字节数：借出的接收缓冲、读队列、批次与上层未读完的数据
+    fn buffered_bytes(&self) -> usize {
+        self.loan_buffer.capacity()
+            + self
+                .reader
+                .as_ref()
+                .map_or(0, |r| r.queued.load(Ordering::Relaxed))
+            + self.batch.as_ref().map_or(0, Batcher::len)
+            + self.unbatched.iter().map(Vec::len).sum::<usize>()
+            + self.pending
+    }
+
+    fn account(&mut self) {
+        let bytes = self.buffered_bytes();
+        self.budget.update(bytes);
+    }
+
+    /// yamux 按整帧接收，接收失败不会留下残片，无需处理
+    pub(crate) fn resync(&mut self) {}
+
+    /// 上层（`Read` 实现）暂存的未读字节数，计入本连接的占用
+    pub(crate) fn set_pending(&mut self, bytes: usize) {
+        self.pending = bytes;
+        self.account();
+    }
+
+    /// 接收前检查内存预算：超出本连接或全局上限时释放借出的缓冲，
+    /// 全局仍超限时等待其他连接释放
+    fn reserve_recv(&mut self) -> Result<()> {
+        let over_global = budget::over_global_limit();
+        if over_global || self.budget.over_limit() {
+            self.loan_buffer = Vec::new();
+            self.account();
+        }
+        if over_global {
+            budget::wait_for_room(BUDGET_WAIT_TIMEOUT).ctx(&self.conn, "recv")?;
+        }
+        Ok(())
+    }
+
+    /// 之后后台读任务读到任何帧（含就地回应的 ping 等控制帧）时调用 `hook`，应用不调用
+    /// `recv()` 时同样生效。每个连接只能设置一次；未连接时不起作用
+    pub(crate) fn set_frame_hook(&mut self, hook: FrameHook) {
+        if let Some(reader) = &self.reader {
+            if reader.frame_hook.set(hook).is_err() {
+                warn!("Frame hook already set on {}", self.conn);
+            }
+        }
+    }
+
+    /// 认证握手后启用加密通道，之后的收发都经过它
+    pub(crate) fn set_secure(&mut self, secure: Option<SecureChannel>) {
+        self.secure = secure;
+    }
+
+    /// 字典协商后设置压缩状态，之后的压缩消息都使用协商出的字典
+    #[cfg(feature = "compression")]
+    pub(crate) fn set_compression(&mut self, compression: CompressionContext) {
+        self.compression = compression;
+    }
+
+    /// 握手协商出的压缩字典 ID，未协商出共同字典时为 `None`
+    #[cfg(feature = "compression")]
+    pub fn dictionary_id(&self) -> Option<u32> {
+        self.compression.dictionary_id()
+    }
+
+    /// 分帧版本；yamux 自行分帧，恒为 1
+    pub fn protocol_version(&self) -> u8 {
+        1
+    }
+
+    /// 连接统计（yamux 自行分帧，chunk_size 恒为 0）
+    pub fn stats(&self) -> ConnectionStats {
+        let mut stats = self.stats.clone();
+        stats.key_rotations = self.secure.as_ref().map_or(0, |s| s.rekeys());
+        stats.buffered_bytes = self.buffered_bytes();
+        stats.send_backlog = self
+            .batch
+            .as_ref()
+            .map_or_else(Default::default, Batcher::backlog);
+        stats
+    }
+}
+
+/// driver 任务：驱动 yamux 连接，按 `requests` 打开新流；对端打开的流交给 `inbound`，
+/// 没有 `inbound` 或队列已满时丢弃（对端收到重置）
+async fn drive<T>(
+    mut connection: Connection<T>,
+    conn: ConnContext,
+    mut requests: mpsc::UnboundedReceiver<Open>,
+    inbound: Option<mpsc::Sender<Stream>>,
+) where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    debug!("Yamux connection driver started ({})", conn);
+    let mut pending: Option<Open> = None;
+    let closed = poll_fn(|cx| loop {
+        if pending.is_none() {
+            if let Poll::Ready(Some(open)) = requests.poll_recv(cx) {
+                pending = Some(open);
+            }
+        }
+        if let Some(open) = pending.take() {
+            match connection.poll_new_outbound(cx) {
+                Poll::Ready(opened) => {
+                    let _ = open.send(opened);
+                    continue;
+                }
+                Poll::Pending => pending = Some(open),
+            }
+        }
+        match connection.poll_next_inbound(cx) {
+            Poll::Ready(Some(Ok(stream))) => match &inbound {
+                Some(inbound) => {
+                    if let Err(e) = inbound.try_send(stream) {
+                        warn!("Yamux dropped inbound stream on {}: {}", conn, e);
+                    }
+                }
+                None => debug!("Yamux ignoring inbound stream {} on {}", stream.id(), conn),
+            },
+            Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(e)),
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => return Poll::Pending,
+        }
+    })
+    .await;
+    match closed {
+        Some(e) => {
+            warn!("Yamux connection error in driver: {}", e);
+            events::emit(VirgaEvent::DriverDied {
+                conn,
+                reason: e.to_string(),
+            });
+        }
+        None => debug!("Yamux connection closed (driver)"),
+    }
+    debug!("Yamux connection driver stopped");
+}
+
+/// 以后台读任务接管 `stream` 的读半部分，队列最多容纳 `depth` 条消息帧
+fn spawn_reader(
+    stream: Stream,
+    depth: usize,
+    conn: ConnContext,
+    strict: bool,
+    memory_limit: Option<usize>,
+) -> (Writer, Reader) {
+    let (read, write) = stream.split();
+    let writer = Arc::new(tokio::sync::Mutex::new(write));
+    let (frames_tx, frames) = mpsc::channel(depth);
+    let (pongs_tx, pongs) = watch::channel(0);
+    let (clocks_tx, clocks) = watch::channel((0, 0, 0));
+    let (goaway_tx, goaway) = watch::channel(None);
+    let (close_tx, close) = watch::channel(None);
+    let frame_hook = Arc::new(OnceLock::new());
+    let replies = Replies {
+        pongs: pongs_tx,
+        clocks: clocks_tx,
+        goaway: goaway_tx,
+        close: close_tx,
+        frame_hook: frame_hook.clone(),
+    };
+    let queued = Arc::new(AtomicUsize::new(0));
+    let handle = spawn_named(
+        format_args!("yamux-reader {}", conn),
+        read_loop(
+            read,
+            writer.clone(),
+            frames_tx,
+            replies,
+            queued.clone(),
+            ReadOptions {
+                conn,
+                strict,
+                memory_limit,
+            },
+        ),
+    );
+    let reader = Reader {
+        frames,
+        ready: None,
+        pongs,
+        clocks,
+        goaway,
+        close,
+        queued,
+        frame_hook,
+        handle,
+    };
+    (writer, reader)
+}
+
+/// 读任务收到的控制回复，发布给等待中的 `ping()`、`time_probe()`，以及对端的 GOAWAY
+/// 与结束原因
+struct Replies {
+    pongs: watch::Sender<u64>,
+    clocks: watch::Sender<(u64, u64, u64)>,
+    goaway: watch::Sender<Option<u64>>,
+    close: watch::Sender<Option<u64>>,
+    frame_hook: Arc<OnceLock<FrameHook>>,
+}
+
+impl Replies {
+    /// 读到一帧
+    fn frame_seen(&self) {
+        if let Some(hook) = self.frame_hook.get() {
+            hook();
+        }
+    }
+}
+
+/// 读任务所属连接的设置
+#[derive(Debug, Clone, Copy)]
+struct ReadOptions {
+    conn: ConnContext,
+    strict: bool,
+    /// 本连接的内存上限，队列中的消息计入其中
+    memory_limit: Option<usize>,
+}
+
+/// 后台读任务：持续读取消息帧放入有界队列，队列满时暂停读取；读取出错时
+/// 把错误放入队列后退出
+async fn read_loop(
+    mut r: ReadHalf<Stream>,
+    w: Writer,
+    frames: mpsc::Sender<Frame>,
+    replies: Replies,
+    queued: Arc<AtomicUsize>,
+    options: ReadOptions,
+) {
+    let ReadOptions {
+        conn,
+        strict,
+        memory_limit,
+    } = options;
+    debug!("Yamux read loop started");
+    loop {
+        // 队列中的消息计入本连接的占用，其余额度留给下一条消息
+        let room = budget::room(memory_limit, queued.load(Ordering::Relaxed));
+        let frame = read_frame(&mut r, &w, &replies, conn, strict, room).await;
+        let failed = frame.is_err();
+        if let Ok((data, _)) = &frame {
+            queued.fetch_add(data.len(), Ordering::Relaxed);
+        }
+        if frames.send(frame).await.is_err() || failed {
+            break;
+        }
+    }
+    debug!("Yamux read loop stopped");
+}
+
+/// 由读任务写出一个控制帧
+async fn write_control<W: AsyncWrite + Unpin>(
+    w: &tokio::sync::Mutex<W>,
+    body: &[u8],
+) -> Result<()> {
+    let mut frame = (CONTROL_FLAG | body.len() as u64).to_be_bytes().to_vec();
+    frame.extend_from_slice(body);
+    let mut w = w.lock().await;
+    w.write_all(&frame)
+        .await
+        .map_err(|e| VirgeError::yamux_stream("yamux send control error", e))?;
+    w.flush()
+        .await
+        .map_err(|e| VirgeError::yamux_stream("yamux flush error", e))
+}
+
+/// 读取下一条消息帧及其内容类型，途中处理控制帧：回应 ping 与时钟请求，
+/// 把 pong、时钟回复、GOAWAY 与结束原因发布到 `replies`，收到拒绝时返回 `Rejected`，
+/// 旁路帧交给注册的处理函数。未知类型或长度不符的控制帧默认跳过，`strict` 时返回
+/// `InvalidData`；消息长度超过 `room` 时不分配缓冲，返回 `OutOfMemory`
+async fn read_frame<R, W>(
+    r: &mut R,
+    w: &tokio::sync::Mutex<W>,
+    replies: &Replies,
+    conn: ConnContext,
+    strict: bool,
+    room: Option<usize>,
+) -> Frame
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut kind = MessageKind::Plain;
+    loop {
+        let mut len_buf = [0u8; LENGTH_PREFIX_SIZE];
+        r.read_exact(&mut len_buf)
+            .await
+            .map_err(|e| VirgeError::yamux_stream("yamux recv length error", e))?;
+        let prefix = u64::from_be_bytes(len_buf);
+        replies.frame_seen();
+
+        if prefix & CONTROL_FLAG != 0 {
+            let len = (prefix & !CONTROL_FLAG) as usize;
+            if len == 0 || len > MAX_CONTROL_SIZE {
+                return Err(VirgeError::transport(
+                    ErrorKind::InvalidData,
+                    format!("invalid yamux control frame length {}", len),
+                ));
+            }
+            let mut body = [0u8; MAX_CONTROL_SIZE];
+            r.read_exact(&mut body[..len])
+                .await
+                .map_err(|e| VirgeError::yamux_stream("yamux recv control error", e))?;
+            match (body[0], len) {
+                (CONTROL_PING, 9) => {
+                    let mut reply = vec![CONTROL_PONG];
+                    reply.extend_from_slice(&body[1..9]);
+                    write_control(w, &reply).await?;
+                }
+                (CONTROL_PONG, 9) => {
+                    replies
+                        .pongs
+                        .send_replace(u64::from_be_bytes(body[1..9].try_into().unwrap()));
+                }
+                (CONTROL_TIME_REQUEST, 9) => {
+                    let received = clock::now_nanos();
+                    let mut reply = vec![CONTROL_TIME_REPLY];
+                    reply.extend_from_slice(&body[1..9]);
+                    reply.extend_from_slice(&received.to_be_bytes());
+                    reply.extend_from_slice(&clock::now_nanos().to_be_bytes());
+                    write_control(w, &reply).await?;
+                }
+                (CONTROL_TIME_REPLY, 25) => {
+                    let field = |i: usize| u64::from_be_bytes(body[i..i + 8].try_into().unwrap());
+                    replies.clocks.send_replace((field(1), field(9), field(17)));
+                }
+                (CONTROL_GOAWAY, 9) => {
+                    let reason = u64::from_be_bytes(body[1..9].try_into().unwrap());
+                    info!("Peer sent GOAWAY (reason {})", reason);
+                    replies.goaway.send_replace(Some(reason));
+                }
+                (CONTROL_CLOSE, 9) => {
+                    let reason = u64::from_be_bytes(body[1..9].try_into().unwrap());
+                    debug!("Peer is closing the connection (reason {})", reason);
+                    replies.close.send_replace(Some(reason));
+                }
+                (CONTROL_REJECT, len) => {
+                    let reason = String::from_utf8_lossy(&body[1..len]).into_owned();
+                    warn!("Peer rejected the connection: {}", reason);
+                    return Err(VirgeError::Rejected(reason));
+                }
+                (CONTROL_OPEN, 1) => {}
+                (CONTROL_COMPRESSED, 1) => kind = MessageKind::Compressed,
+                (CONTROL_BATCH, 1) => kind = MessageKind::Batch,
+                (ctrl, len) if extension::is_extension(ctrl) => {
+                    extension::dispatch(ctrl, conn, &body[1..len]);
+                }
+                (ctrl, len) if strict => {
+                    warn!(
+                        "Rejecting yamux control type {} ({} bytes, strict mode)",
+                        ctrl, len
+                    );
+                    return Err(VirgeError::transport(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "unexpected yamux control frame type {} ({} bytes)",
+                            ctrl, len
+                        ),
+                    ));
+                }
+                // 较新的对端可能发送未知的控制帧，忽略即可
+                (ctrl, _) => debug!("Ignoring unknown yamux control type {}", ctrl),
+            }
+            continue;
+        }
+
+        let len = prefix as usize;
+        if let Some(room) = room.filter(|&room| len > room) {
+            return Err(VirgeError::transport(
+                ErrorKind::OutOfMemory,
+                format!(
+                    "{} byte yamux message exceeds the {} byte memory budget",
+                    len, room
+                ),
+            ));
+        }
+        debug!("Yamux expecting to receive {} bytes", len);
+        let mut buf = buffers::acquire(len);
+        buf.resize(len, 0);
+        r.read_exact(&mut buf)
+            .await
+            .map_err(|e| VirgeError::yamux_stream("yamux recv error", e))?;
+        return Ok((buf, kind));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 内存中的一对 yamux 流，两端的连接由后台任务驱动
+    async fn stream_pair() -> (Stream, Stream) {
+        let (a, b) = tokio::io::duplex(64 * 1024);
+        let mut client = Connection::new(a.compat(), Config::default(), Mode::Client);
+        let mut server = Connection::new(b.compat(), Config::default(), Mode::Server);
+        let mut outbound = poll_fn(|cx| client.poll_new_outbound(cx)).await.unwrap();
+        tokio::spawn(
+            async move { while poll_fn(|cx| client.poll_next_inbound(cx)).await.is_some() {} },
+        );
+        // 对端在收到第一帧数据后才能看到新流
+        outbound.write_all(&0u64.to_be_bytes()).await.unwrap();
+        outbound.flush().await.unwrap();
+        let mut inbound = poll_fn(|cx| server.poll_next_inbound(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        tokio::spawn(
+            async move { while poll_fn(|cx| server.poll_next_inbound(cx)).await.is_some() {} },
+        );
+        let mut len = [0u8; LENGTH_PREFIX_SIZE];
+        inbound.read_exact(&mut len).await.unwrap();
+        assert_eq!(len, 0u64.to_be_bytes());
+        (outbound, inbound)
+    }
+
+    async fn write_frames(s: &mut Stream, payloads: &[&[u8]]) {
+        for payload in payloads {
+            s.write_all(&(payload.len() as u64).to_be_bytes())
+                .await
+                .unwrap();
+            s.write_all(payload).await.unwrap();
+        }
+        s.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_loop_reports_control_frames_to_the_frame_hook() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+        let frames = Arc::new(AtomicUsize::new(0));
+        let counter = frames.clone();
+        let hook: FrameHook = Arc::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        assert!(reader.frame_hook.set(hook).is_ok());
+
+        // 只有 ping，应用从未接收
+        let mut ping = (CONTROL_FLAG | 9).to_be_bytes().to_vec();
+        ping.push(CONTROL_PING);
+        ping.extend_from_slice(&7u64.to_be_bytes());
+        client.write_all(&ping).await.unwrap();
+        client.flush().await.unwrap();
+        let mut pong = [0u8; LENGTH_PREFIX_SIZE + 9];
+        client.read_exact(&mut pong).await.unwrap();
+        assert_eq!(frames.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn read_loop_answers_ping_without_recv() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+
+        // 服务端从未取队列，读任务仍回应 ping
+        write_frames(&mut client, &[b"queued"]).await;
+        let mut ping = (CONTROL_FLAG | 9).to_be_bytes().to_vec();
+        ping.push(CONTROL_PING);
+        ping.extend_from_slice(&7u64.to_be_bytes());
+        client.write_all(&ping).await.unwrap();
+        client.flush().await.unwrap();
+        let mut pong = [0u8; LENGTH_PREFIX_SIZE + 9];
+        client.read_exact(&mut pong).await.unwrap();
+        assert_eq!(pong[..LENGTH_PREFIX_SIZE], (CONTROL_FLAG | 9).to_be_bytes());
+        assert_eq!(pong[LENGTH_PREFIX_SIZE], CONTROL_PONG);
+        assert_eq!(pong[LENGTH_PREFIX_SIZE + 1..], 7u64.to_be_bytes());
+
+        let (data, kind) = reader.frames.recv().await.unwrap().unwrap();
+        assert_eq!(
+            (data.as_slice(), kind),
+            (&b"queued"[..], MessageKind::Plain)
+        );
+        assert_eq!(reader.queued.load(Ordering::Relaxed), 6);
+    }
+
+    #[tokio::test]
+    async fn read_loop_publishes_pongs() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+        let mut pong = (CONTROL_FLAG | 9).to_be_bytes().to_vec();
+        pong.push(CONTROL_PONG);
+        pong.extend_from_slice(&3u64.to_be_bytes());
+        client.write_all(&pong).await.unwrap();
+        client.flush().await.unwrap();
+        let waited = reader.pongs.wait_for(|pong| *pong >= 3);
+        tokio::time::timeout(Duration::from_secs(5), waited)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_loop_answers_time_requests_and_publishes_replies() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+
+        let mut request = (CONTROL_FLAG | 9).to_be_bytes().to_vec();
+        request.push(CONTROL_TIME_REQUEST);
+        request.extend_from_slice(&5u64.to_be_bytes());
+        let before = clock::now_nanos();
+        client.write_all(&request).await.unwrap();
+        client.flush().await.unwrap();
+        let mut reply = [0u8; LENGTH_PREFIX_SIZE + 25];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(
+            reply[..LENGTH_PREFIX_SIZE],
+            (CONTROL_FLAG | 25).to_be_bytes()
+        );
+        assert_eq!(reply[LENGTH_PREFIX_SIZE], CONTROL_TIME_REPLY);
+        let field = |i: usize| u64::from_be_bytes(reply[i..i + 8].try_into().unwrap());
+        assert_eq!(field(LENGTH_PREFIX_SIZE + 1), 5);
+        let (received, sent) = (
+            field(LENGTH_PREFIX_SIZE + 9),
+            field(LENGTH_PREFIX_SIZE + 17),
+        );
+        assert!(before <= received && received <= sent);
+
+        // 把回复原样送回，读任务把它发布出来
+        client.write_all(&reply).await.unwrap();
+        client.flush().await.unwrap();
+        let published = reader.clocks.wait_for(|(nonce, ..)| *nonce == 5);
+        let published = *tokio::time::timeout(Duration::from_secs(5), published)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(published, (5, received, sent));
+    }
+
+    #[tokio::test]
+    async fn read_loop_records_goaway() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+
+        let mut goaway = (CONTROL_FLAG | 9).to_be_bytes().to_vec();
+        goaway.push(CONTROL_GOAWAY);
+        goaway.extend_from_slice(&GoAwayReason::MaxAge.code().to_be_bytes());
+        client.write_all(&goaway).await.unwrap();
+        client.flush().await.unwrap();
+        let seen = reader.goaway.wait_for(Option::is_some);
+        let seen = *tokio::time::timeout(Duration::from_secs(5), seen)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            seen.map(GoAwayReason::from_code),
+            Some(GoAwayReason::MaxAge)
+        );
+    }
+
+    #[tokio::test]
+    async fn read_loop_records_close_reason() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+
+        let mut close = (CONTROL_FLAG | 9).to_be_bytes().to_vec();
+        close.push(CONTROL_CLOSE);
+        close.extend_from_slice(&ShutdownReason::ServerDraining.code().to_be_bytes());
+        client.write_all(&close).await.unwrap();
+        client.close().await.unwrap();
+        let err = reader.frames.recv().await.unwrap().unwrap_err();
+        let reason = *reader.close.borrow();
+        let err = err.ended(reason, None);
+        assert_eq!(err.shutdown_reason(), Some(ShutdownReason::ServerDraining));
+        assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    }
+
+    #[tokio::test]
+    async fn read_loop_reports_rejection() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+
+        let reason = b"too many connections from cid 3";
+        let mut reject = (CONTROL_FLAG | (1 + reason.len() as u64))
+            .to_be_bytes()
+            .to_vec();
+        reject.push(CONTROL_REJECT);
+        reject.extend_from_slice(reason);
+        client.write_all(&reject).await.unwrap();
+        client.flush().await.unwrap();
+        let err = reader.frames.recv().await.unwrap().unwrap_err();
+        assert_eq!(err.rejection(), Some("too many connections from cid 3"));
+        assert!(reader.frames.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn read_loop_is_bounded_and_reports_eof() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 1, ConnContext::default(), false, None);
+        write_frames(&mut client, &[b"a", b"bb", b"ccc"]).await;
+        client.close().await.unwrap();
+        for expected in [&b"a"[..], b"bb", b"ccc"] {
+            let (data, _) = reader.frames.recv().await.unwrap().unwrap();
+            assert_eq!(data, expected);
+        }
+        assert!(reader.frames.recv().await.unwrap().is_err());
+        assert!(reader.frames.recv().await.is_none());
+        reader.handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn kind_control_marks_next_frame() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+        for (control, kind) in [
+            (CONTROL_COMPRESSED, MessageKind::Compressed),
+            (CONTROL_BATCH, MessageKind::Batch),
+            (CONTROL_OPEN, MessageKind::Plain),
+        ] {
+            let mut frames = (CONTROL_FLAG | 1).to_be_bytes().to_vec();
+            frames.push(control);
+            client.write_all(&frames).await.unwrap();
+            write_frames(&mut client, &[&[1, 2], &[3]]).await;
+            let first = reader.frames.recv().await.unwrap().unwrap();
+            assert_eq!(first, (vec![1, 2], kind));
+            let second = reader.frames.recv().await.unwrap().unwrap();
+            assert_eq!(second, (vec![3], MessageKind::Plain));
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_control_frame_is_rejected() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::default(), false, None);
+        client
+            .write_all(&(CONTROL_FLAG | 1000).to_be_bytes())
+            .await
+            .unwrap();
+        client.flush().await.unwrap();
+        let err = reader.frames.recv().await.unwrap().unwrap_err();
+        assert_eq!(std::io::Error::from(err).kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn strict_reader_rejects_unknown_and_malformed_controls() {
+        for body in [&[0x7E][..], &[CONTROL_PING, 1, 2]] {
+            for strict in [false, true] {
+                let (mut client, server) = stream_pair().await;
+                let (_writer, mut reader) =
+                    spawn_reader(server, 4, ConnContext::default(), strict, None);
+                let mut frames = (CONTROL_FLAG | body.len() as u64).to_be_bytes().to_vec();
+                frames.extend_from_slice(body);
+                client.write_all(&frames).await.unwrap();
+                write_frames(&mut client, &[b"after"]).await;
+                let frame = reader.frames.recv().await.unwrap();
+                if strict {
+                    let err = std::io::Error::from(frame.unwrap_err());
+                    assert_eq!(err.kind(), ErrorKind::InvalidData);
+                } else {
+                    assert_eq!(frame.unwrap(), (b"after".to_vec(), MessageKind::Plain));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn read_loop_rejects_frames_over_the_memory_limit() {
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) =
+            spawn_reader(server, 4, ConnContext::default(), false, Some(16));
+        // 只发出长度前缀：声明 8 GiB 的消息在分配前就被拒绝
+        client.write_all(&(8u64 << 30).to_be_bytes()).await.unwrap();
+        client.flush().await.unwrap();
+        let err = std::io::Error::from(reader.frames.recv().await.unwrap().unwrap_err());
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+    }
+
+    #[tokio::test]
+    async fn read_loop_hands_extension_frames_to_their_handler() {
+        let (seen_tx, mut seen) = mpsc::unbounded_channel();
+        let handler = move |conn: ConnContext, data: &[u8]| {
+            seen_tx.send((conn.port, data.to_vec())).unwrap();
+        };
+        extension::register_frame_handler(0xE0, Arc::new(handler)).unwrap();
+
+        let (mut client, server) = stream_pair().await;
+        let (_writer, mut reader) = spawn_reader(server, 4, ConnContext::new(3, 4321), true, None);
+        for body in [&[0xE0, 5, 0][..], &[0xE1, 1]] {
+            let mut frame = (CONTROL_FLAG | body.len() as u64).to_be_bytes().to_vec();
+            frame.extend_from_slice(body);
+            client.write_all(&frame).await.unwrap();
+        }
+        write_frames(&mut client, &[b"after"]).await;
+
+        let frame = reader.frames.recv().await.unwrap();
+        assert_eq!(frame.unwrap(), (b"after".to_vec(), MessageKind::Plain));
+        assert_eq!(seen.try_recv().unwrap(), (4321, vec![5, 0]));
+        assert!(seen.try_recv().is_err());
+        assert!(extension::unregister_frame_handler(0xE0));
+    }
+
+    #[test]
+    fn opened_streams_are_accepted_in_order_and_do_not_block_each_other() {
+        let (a, b) = tokio::io::duplex(64 * 1024);
+        let client = Connection::new(a.compat(), Config::default(), Mode::Client);
+        let server = Connection::new(b.compat(), Config::default(), Mode::Server);
+        let conn = ConnContext::default();
+        let (opens, requests) = mpsc::unbounded_channel();
+        let (_server_opens, server_requests) = mpsc::unbounded_channel();
+        let (inbound, mut accepted) = mpsc::channel(ACCEPT_BACKLOG);
+        get_runtime().spawn(drive(client, conn, requests, None));
+        get_runtime().spawn(drive(server, conn, server_requests, Some(inbound)));
+
+        let open = || {
+            let (reply, opened) = oneshot::channel();
+            opens.send(reply).unwrap();
+            let stream = get_runtime().block_on(opened).unwrap().unwrap();
+            get_runtime()
+                .block_on(VirgaStream::opened(stream, conn, false))
+                .unwrap()
+        };
+        let mut first = open();
+        let mut second = open();
+        let mut accept =
+            || VirgaStream::accepted(get_runtime().block_on(accepted.recv()).unwrap(), conn, true);
+        let mut first_peer = accept();
+        let mut second_peer = accept();
+        assert_eq!(first_peer.id(), first.id());
+        assert_eq!(second_peer.id(), second.id());
+
+        // 第一条流的大消息超出流控窗口，在对端读取前一直阻塞，第二条流照常收发
+        let bulk = std::thread::spawn(move || {
+            first.send(&vec![7u8; 1 << 20]).unwrap();
+            first
+        });
+        second.send(b"ping").unwrap();
+        assert_eq!(second_peer.recv().unwrap(), b"ping");
+        second_peer.send(b"pong").unwrap();
+        assert_eq!(second.recv().unwrap(), b"pong");
+        assert_eq!(first_peer.recv().unwrap(), vec![7u8; 1 << 20]);
+
+        let mut first = bulk.join().unwrap();
+        first.close().unwrap();
+        let err = first_peer.recv().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(
+            first.send(b"late").unwrap_err().kind(),
+            ErrorKind::NotConnected
+        );
+        second_peer.set_idle_timeout(Some(Duration::from_millis(50)));
+        assert_eq!(second_peer.recv().unwrap_err().kind(), ErrorKind::TimedOut);
+    }
 }
@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 配置中使用的单位类型
+//!
+//! 超时统一使用 [`std::time::Duration`]；字节大小使用 [`ByteSize`]，
+//! 可由整数构造，也可从 `"64KiB"`、`"16MiB"` 这类字符串解析。
+//! 启用 `serde` 特性后，`ByteSize` 可从整数或字符串反序列化。
+
+use crate::error::VirgeError;
+use std::fmt;
+use std::str::FromStr;
+
+/// 字节大小
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    pub const fn b(bytes: u64) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn kib(n: u64) -> Self {
+        Self(n * 1024)
+    }
+
+    pub const fn mib(n: u64) -> Self {
+        Self(n * 1024 * 1024)
+    }
+
+    pub const fn gib(n: u64) -> Self {
+        Self(n * 1024 * 1024 * 1024)
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// 超出 `usize` 时截断为 `usize::MAX`
+    pub fn as_usize(self) -> usize {
+        usize::try_from(self.0).unwrap_or(usize::MAX)
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<u32> for ByteSize {
+    fn from(bytes: u32) -> Self {
+        Self(bytes as u64)
+    }
+}
+
+impl From<usize> for ByteSize {
+    fn from(bytes: usize) -> Self {
+        Self(bytes as u64)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    /// 能整除时使用最大的二进制单位，如 `64KiB`，否则输出字节数
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [(u64, &str); 3] = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+        for (scale, unit) in UNITS {
+            if self.0 >= scale && self.0.is_multiple_of(scale) {
+                return write!(f, "{}{}", self.0 / scale, unit);
+            }
+        }
+        write!(f, "{}B", self.0)
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = VirgeError;
+
+    /// 接受 `4096`、`4096B`、`64KiB`/`64K`（二进制）与 `64KB`（十进制），
+    /// 单位不区分大小写，数字与单位之间可以有空格
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || VirgeError::ConfigError(format!("invalid byte size: {:?}", s));
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (digits, unit) = s.split_at(split);
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        let scale: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kib" => 1 << 10,
+            "m" | "mib" => 1 << 20,
+            "g" | "gib" => 1 << 30,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            _ => return Err(invalid()),
+        };
+        value.checked_mul(scale).map(Self).ok_or_else(invalid)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ByteSize {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ByteSize {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = ByteSize;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a byte count or a size string such as \"64KiB\"")
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<ByteSize, E> {
+                Ok(ByteSize(v))
+            }
+
+            fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<ByteSize, E> {
+                u64::try_from(v)
+                    .map(ByteSize)
+                    .map_err(|_| E::custom("byte size cannot be negative"))
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<ByteSize, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constructors() {
+        assert_eq!(ByteSize::b(10).as_u64(), 10);
+        assert_eq!(ByteSize::kib(64).as_u64(), 64 * 1024);
+        assert_eq!(ByteSize::mib(16).as_usize(), 16 * 1024 * 1024);
+        assert_eq!(ByteSize::gib(1).as_u64(), 1 << 30);
+        assert_eq!(ByteSize::from(4096usize), ByteSize::kib(4));
+    }
+
+    #[test]
+    fn parse_units() {
+        assert_eq!("4096".parse::<ByteSize>().unwrap(), ByteSize::kib(4));
+        assert_eq!("64KiB".parse::<ByteSize>().unwrap(), ByteSize::kib(64));
+        assert_eq!("64k".parse::<ByteSize>().unwrap(), ByteSize::kib(64));
+        assert_eq!("16 MiB".parse::<ByteSize>().unwrap(), ByteSize::mib(16));
+        assert_eq!("1GiB".parse::<ByteSize>().unwrap(), ByteSize::gib(1));
+        assert_eq!("2KB".parse::<ByteSize>().unwrap(), ByteSize::b(2000));
+        assert_eq!("512B".parse::<ByteSize>().unwrap(), ByteSize::b(512));
+    }
+
+    #[test]
+    fn parse_rejects_garbage() {
+        for s in ["", "KiB", "12XB", "-1", "1.5MiB", "99999999999999999999GiB"] {
+            assert!(
+                matches!(s.parse::<ByteSize>(), Err(VirgeError::ConfigError(_))),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for size in [
+            ByteSize::b(1000),
+            ByteSize::kib(64),
+            ByteSize::mib(3),
+            ByteSize::b(0),
+        ] {
+            assert_eq!(size.to_string().parse::<ByteSize>().unwrap(), size);
+        }
+        assert_eq!(ByteSize::kib(64).to_string(), "64KiB");
+        assert_eq!(ByteSize::b(1000).to_string(), "1000B");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_from_string_or_number() {
+        use serde::de::value::{Error, StrDeserializer, U64Deserializer};
+        use serde::Deserialize;
+
+        let from_str = ByteSize::deserialize(StrDeserializer::<Error>::new("64KiB")).unwrap();
+        assert_eq!(from_str, ByteSize::kib(64));
+        let from_num = ByteSize::deserialize(U64Deserializer::<Error>::new(4096)).unwrap();
+        assert_eq!(from_num, ByteSize::kib(4));
+        assert!(ByteSize::deserialize(StrDeserializer::<Error>::new("lots")).is_err());
+    }
+}
@@ -0,0 +1,628 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! 把 Virga 连接作为 WebSocket 端点提供给浏览器
+//!
+//! 网页控制台要显示客户机经 vsock 送来的控制台输出、日志时，在宿主机上运行
+//! [`WebSocketBridge`]：浏览器按路径连接 WebSocket，桥为每个 WebSocket 连接建立一条到
+//! 该路径对应的客户机服务的 Virga 连接，双向转发：每条 Virga 消息作为一条二进制消息发给
+//! 浏览器，浏览器的二进制或文本消息各作为一条 Virga 消息发给客户机。任一方关闭后，
+//! 另一方随之关闭；客户机连接失败或断开时，WebSocket 以 1011 关闭，原因中带错误信息。
+//!
+//! ```ignore
+//! let bridge = WebSocketBridge::bind("127.0.0.1:8080")?
+//!     .route("/console", ClientConfig::new(3, 1024, 4096, false))
+//!     .route("/logs", ClientConfig::new(3, 1025, 4096, false))
+//!     .with_allowed_origin("https://console.example.com");
+//! bridge.serve()?;
+//! ```
+//!
+//! 桥本身不做认证，应只监听本机地址或放在已认证的反向代理之后。浏览器发起的请求带
+//! `Origin` 头，只接受 `with_allowed_origin()` 列出的来源，防止其他网页借用户的浏览器
+//! 连到桥上；不带 `Origin` 的请求（命令行工具）不受此限制。
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::fd::AsFd;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::*;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tungstenite::{Message, WebSocket};
+
+use crate::agent::ShutdownHandle;
+use crate::client::{ClientConfig, VirgeClient};
+use crate::mux::{Endpoint, Wake};
+use crate::poll;
+use crate::units::ByteSize;
+
+/// 默认的 WebSocket 握手超时
+pub const DEFAULT_WS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// 默认的同时转发的连接数上限
+pub const DEFAULT_WS_MAX_CONNECTIONS: usize = 64;
+
+/// 等待新连接时检查停止请求的间隔
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// 每个方向上排队等待转发的消息个数上限，满时暂停读取来源一方
+const QUEUE_DEPTH: usize = 64;
+/// 发出关闭帧后等待浏览器回应的时间
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+type Connector = Arc<dyn Fn() -> Result<Box<dyn Endpoint>> + Send + Sync>;
+
+/// 接受 WebSocket 连接并转发到客户机服务的桥
+pub struct WebSocketBridge {
+    listener: TcpListener,
+    routes: BTreeMap<String, Connector>,
+    allowed_origins: Vec<String>,
+    max_connections: usize,
+    max_message_size: ByteSize,
+    handshake_timeout: Duration,
+    shutdown: ShutdownHandle,
+}
+
+impl WebSocketBridge {
+    /// 在 TCP 地址 `addr` 上监听，通常为本机地址
+    pub fn bind(addr: impl ToSocketAddrs) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            routes: BTreeMap::new(),
+            allowed_origins: Vec::new(),
+            max_connections: DEFAULT_WS_MAX_CONNECTIONS,
+            max_message_size: ByteSize::mib(16),
+            handshake_timeout: DEFAULT_WS_HANDSHAKE_TIMEOUT,
+            shutdown: ShutdownHandle::default(),
+        })
+    }
+
+    /// 路径 `path`（如 `/console`）上的 WebSocket 连接转发到 `config` 指定的客户机服务；
+    /// 每个 WebSocket 连接各建立一条 Virga 连接。同一路径再次设置时替换
+    pub fn route(self, path: impl Into<String>, config: ClientConfig) -> Self {
+        self.route_to(
+            path,
+            Arc::new(move || {
+                let mut client = VirgeClient::new(config.clone());
+                client.connect()?;
+                Ok(Box::new(client) as Box<dyn Endpoint>)
+            }),
+        )
+    }
+
+    fn route_to(mut self, path: impl Into<String>, connector: Connector) -> Self {
+        self.routes.insert(path.into(), connector);
+        self
+    }
+
+    /// 接受来自 `origin`（如 `https://console.example.com`）的浏览器连接
+    pub fn with_allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        self.allowed_origins.push(origin.into());
+        self
+    }
+
+    /// 同时转发的连接数上限，超过时新的握手以 503 拒绝
+    pub fn with_max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// 浏览器发来的单条消息上限，超过时关闭该连接
+    pub fn with_max_message_size(mut self, size: ByteSize) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// WebSocket 握手的超时
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// 实际监听的地址
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// 用于请求停止的句柄：`serve()` 不再接受新连接并返回 `Ok`，已建立的连接继续转发
+    /// 直到任一方关闭
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// 接受并转发连接，直到请求停止；每个连接由两个工作线程转发
+    pub fn serve(self) -> Result<()> {
+        if self.routes.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "websocket bridge has no routes",
+            ));
+        }
+        info!(
+            "WebSocket bridge listening on {} for {:?}",
+            self.listener.local_addr()?,
+            self.routes.keys().collect::<Vec<_>>()
+        );
+        let shared = Arc::new(Shared {
+            routes: self.routes,
+            allowed_origins: self.allowed_origins,
+            max_connections: self.max_connections,
+            max_message_size: self.max_message_size.as_usize(),
+            handshake_timeout: self.handshake_timeout,
+            active: AtomicUsize::new(0),
+        });
+        while !self.shutdown.is_shutdown() {
+            if !poll::wait(
+                self.listener.as_fd(),
+                libc::POLLIN,
+                None,
+                Some(SHUTDOWN_POLL_INTERVAL),
+            )? {
+                continue;
+            }
+            let (stream, peer) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                Err(e) => {
+                    warn!("WebSocket bridge accept failed: {}", e);
+                    continue;
+                }
+            };
+            let shared = shared.clone();
+            if let Err(e) = crate::threads::spawn("ws", move || shared.handle(stream, peer)) {
+                warn!("Failed to start WebSocket connection thread: {}", e);
+            }
+        }
+        info!("WebSocket bridge stopped");
+        Ok(())
+    }
+}
+
+/// 各连接线程共享的桥配置
+struct Shared {
+    routes: BTreeMap<String, Connector>,
+    allowed_origins: Vec<String>,
+    max_connections: usize,
+    max_message_size: usize,
+    handshake_timeout: Duration,
+    active: AtomicUsize,
+}
+
+impl Shared {
+    fn handle(&self, stream: TcpStream, peer: SocketAddr) {
+        if self.active.fetch_add(1, Ordering::AcqRel) >= self.max_connections {
+            self.active.fetch_sub(1, Ordering::AcqRel);
+            reject(stream, StatusCode::SERVICE_UNAVAILABLE);
+            return;
+        }
+        if let Err(e) = self.bridge(stream, peer) {
+            debug!("WebSocket connection from {} ended: {}", peer, e);
+        }
+        self.active.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    // 握手回调的错误类型由 tungstenite 决定
+    #[allow(clippy::result_large_err)]
+    fn bridge(&self, stream: TcpStream, peer: SocketAddr) -> Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.handshake_timeout))?;
+        stream.set_write_timeout(Some(self.handshake_timeout))?;
+        let mut route = None;
+        let callback = |request: &Request, response: Response| {
+            let (path, connector) = self.check(request).map_err(error_response)?;
+            route = Some((path, connector));
+            Ok(response)
+        };
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(self.max_message_size))
+            .max_frame_size(Some(self.max_message_size));
+        let mut ws = tungstenite::accept_hdr_with_config(stream, callback, Some(config))
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("handshake failed: {}", e)))?;
+        let (path, connector) = route.expect("route is set on a successful handshake");
+
+        let endpoint = match connector() {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                warn!("WebSocket {} from {}: guest unavailable: {}", path, peer, e);
+                let frame = close_frame(CloseCode::Error, &format!("guest unavailable: {}", e));
+                // 浏览器可能已断开
+                if ws.close(Some(frame)).is_ok() {
+                    let _ = ws.flush();
+                }
+                return Ok(());
+            }
+        };
+        info!("WebSocket {} from {} bridged", path, peer);
+        ws.get_ref().set_read_timeout(None)?;
+        ws.get_ref().set_write_timeout(None)?;
+        ws.get_ref().set_nonblocking(true)?;
+        relay(ws, endpoint)?;
+        info!("WebSocket {} from {} closed", path, peer);
+        Ok(())
+    }
+
+    /// 按路径与来源决定是否接受握手
+    fn check(&self, request: &Request) -> std::result::Result<(String, Connector), StatusCode> {
+        if let Some(origin) = request.headers().get("origin") {
+            let allowed = origin
+                .to_str()
+                .is_ok_and(|origin| self.allowed_origins.iter().any(|o| o == origin));
+            if !allowed {
+                warn!("WebSocket request from origin {:?} refused", origin);
+                return Err(StatusCode::FORBIDDEN);
+            }
+        }
+        let path = request.uri().path();
+        match self.routes.get(path) {
+            Some(connector) => Ok((path.to_string(), connector.clone())),
+            None => Err(StatusCode::NOT_FOUND),
+        }
+    }
+}
+
+fn error_response(status: StatusCode) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(status.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+/// 不读取请求，直接以 `status` 回应并关闭
+fn reject(mut stream: TcpStream, status: StatusCode) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+        status
+    );
+    // 对端可能已断开
+    let _ = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.write_all(response.as_bytes()));
+}
+
+fn close_frame(code: CloseCode, reason: &str) -> CloseFrame {
+    // 关闭原因最长 123 字节
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    CloseFrame {
+        code,
+        reason: reason[..end].into(),
+    }
+}
+
+/// 送往浏览器的内容
+enum Outbound {
+    Message(Vec<u8>),
+    /// 客户机连接已关闭，附原因；`None` 表示正常关闭
+    Closed(Option<String>),
+}
+
+/// 在当前线程驱动 WebSocket，另起一个线程驱动 Virga 连接，直到任一方关闭
+fn relay(mut ws: WebSocket<TcpStream>, endpoint: Box<dyn Endpoint>) -> Result<()> {
+    let wake = Arc::new(Wake::new()?);
+    let doorbell = Arc::new(poll::Wake::new()?);
+    let stop = Arc::new(AtomicBool::new(false));
+    let (to_guest, from_browser) = mpsc::sync_channel(QUEUE_DEPTH);
+    let (to_browser, from_guest) = mpsc::sync_channel(QUEUE_DEPTH);
+    let guest = {
+        let (wake, doorbell, stop) = (wake.clone(), doorbell.clone(), stop.clone());
+        crate::threads::spawn("ws-guest", move || {
+            guest_loop(endpoint, from_browser, to_browser, &wake, &doorbell, &stop)
+        })?
+    };
+    let result = browser_loop(&mut ws, &to_guest, &from_guest, &wake, &doorbell);
+    stop.store(true, Ordering::Release);
+    wake.wake();
+    drop(from_guest);
+    // 客户机线程在检查 stop 或发送失败后退出
+    let _ = guest.join();
+    result
+}
+
+fn guest_loop(
+    mut endpoint: Box<dyn Endpoint>,
+    from_browser: Receiver<Vec<u8>>,
+    to_browser: SyncSender<Outbound>,
+    wake: &Wake,
+    doorbell: &poll::Wake,
+    stop: &AtomicBool,
+) {
+    let result = (|| -> Result<()> {
+        while !stop.load(Ordering::Acquire) {
+            let mut sent = false;
+            loop {
+                match from_browser.try_recv() {
+                    Ok(message) => {
+                        endpoint.send_frame(message)?;
+                        sent = true;
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            if sent {
+                endpoint.flush_frames()?;
+                // 队列有了空位，让浏览器一侧继续读取
+                doorbell.wake();
+            }
+            if endpoint.wait_readable(Some(wake), None)? {
+                let message = endpoint.recv_frame()?;
+                if to_browser.send(Outbound::Message(message)).is_err() {
+                    return Ok(());
+                }
+                doorbell.wake();
+            }
+        }
+        Ok(())
+    })();
+    let reason = match result {
+        Ok(()) => None,
+        Err(e) if is_closed(&e) => None,
+        Err(e) => Some(e.to_string()),
+    };
+    endpoint.close();
+    let _ = to_browser.send(Outbound::Closed(reason));
+    doorbell.wake();
+}
+
+fn is_closed(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::BrokenPipe
+            | ErrorKind::NotConnected
+    )
+}
+
+fn browser_loop(
+    ws: &mut WebSocket<TcpStream>,
+    to_guest: &SyncSender<Vec<u8>>,
+    from_guest: &Receiver<Outbound>,
+    wake: &Wake,
+    doorbell: &poll::Wake,
+) -> Result<()> {
+    // 客户机队列已满、等待送出的消息
+    let mut pending: Option<Vec<u8>> = None;
+    let mut closing: Option<Instant> = None;
+    loop {
+        while closing.is_none() {
+            match from_guest.try_recv() {
+                Ok(Outbound::Message(message)) => ws_result(ws.write(Message::binary(message)))?,
+                Ok(Outbound::Closed(reason)) => {
+                    let frame = match reason {
+                        None => close_frame(CloseCode::Normal, "guest closed"),
+                        Some(reason) => close_frame(CloseCode::Error, &reason),
+                    };
+                    ws_result(ws.close(Some(frame)))?;
+                    closing = Some(Instant::now() + CLOSE_GRACE);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    ws_result(ws.close(Some(close_frame(CloseCode::Error, "bridge failed"))))?;
+                    closing = Some(Instant::now() + CLOSE_GRACE);
+                }
+            }
+        }
+        let want_write = match ws.flush() {
+            Ok(()) => false,
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => true,
+            Err(e) => return ws_closed(e),
+        };
+
+        if let Some(message) = pending.take() {
+            pending = forward(to_guest, message, wake);
+        }
+        while pending.is_none() {
+            let message = match ws.read() {
+                Ok(Message::Binary(data)) => data.to_vec(),
+                Ok(Message::Text(text)) => text.as_bytes().to_vec(),
+                // ping 的回应与关闭握手由 tungstenite 排队，随下一次 flush 发出
+                Ok(Message::Close(_)) => {
+                    closing.get_or_insert_with(|| Instant::now() + CLOSE_GRACE);
+                    continue;
+                }
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return ws_closed(e),
+            };
+            if closing.is_none() {
+                pending = forward(to_guest, message, wake);
+            }
+        }
+
+        let timeout = match closing {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Ok(());
+                }
+                Some(left)
+            }
+            None => None,
+        };
+        let mut events = 0;
+        if pending.is_none() {
+            events |= libc::POLLIN;
+        }
+        if want_write {
+            events |= libc::POLLOUT;
+        }
+        poll::wait(ws.get_ref().as_fd(), events, Some(doorbell), timeout)?;
+    }
+}
+
+/// 把消息放入客户机队列；队列已满时退回消息，由客户机线程腾出空位后重试
+fn forward(to_guest: &SyncSender<Vec<u8>>, message: Vec<u8>, wake: &Wake) -> Option<Vec<u8>> {
+    let pending = match to_guest.try_send(message) {
+        Ok(()) => None,
+        Err(TrySendError::Full(message)) => Some(message),
+        // 客户机线程已退出，关闭原因随后到达
+        Err(TrySendError::Disconnected(_)) => None,
+    };
+    wake.wake();
+    pending
+}
+
+fn ws_result(result: tungstenite::Result<()>) -> Result<()> {
+    match result {
+        Ok(()) => Ok(()),
+        // 已放入写缓冲，等 socket 可写后 flush
+        Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+        Err(e) => ws_closed(e),
+    }
+}
+
+/// 关闭握手完成时正常结束，其余错误原样返回
+fn ws_closed(e: tungstenite::Error) -> Result<()> {
+    match e {
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => Ok(()),
+        tungstenite::Error::Io(e) => Err(e),
+        e => Err(Error::new(ErrorKind::InvalidData, e)),
+    }
+}
+
+#[cfg(all(test, feature = "use-xtransport"))]
+mod tests {
+    use super::*;
+    use crate::mux::testing::link_pair;
+    use std::thread;
+    use tungstenite::client::IntoClientRequest;
+
+    /// 桥的一条路由连到测试用的客户机一端，返回桥的地址、停止句柄与客户机一端
+    fn start(path: &str) -> (SocketAddr, ShutdownHandle, Receiver<Box<dyn Endpoint>>) {
+        let (guests, guest_rx) = mpsc::channel();
+        let guests = std::sync::Mutex::new(guests);
+        let bridge = WebSocketBridge::bind("127.0.0.1:0")
+            .unwrap()
+            .with_allowed_origin("https://console.example")
+            .route_to(
+                path,
+                Arc::new(move || {
+                    let (host, guest) = link_pair();
+                    guests
+                        .lock()
+                        .unwrap()
+                        .send(Box::new(guest) as Box<dyn Endpoint>)
+                        .unwrap();
+                    Ok(Box::new(host) as Box<dyn Endpoint>)
+                }),
+            );
+        let addr = bridge.local_addr().unwrap();
+        let shutdown = bridge.shutdown_handle();
+        thread::spawn(move || bridge.serve().unwrap());
+        (addr, shutdown, guest_rx)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn connect(
+        addr: SocketAddr,
+        path: &str,
+        origin: Option<&str>,
+    ) -> tungstenite::Result<WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>> {
+        let mut request = format!("ws://{}{}", addr, path).into_client_request()?;
+        if let Some(origin) = origin {
+            request
+                .headers_mut()
+                .insert("origin", origin.parse().unwrap());
+        }
+        tungstenite::connect(request).map(|(ws, _)| ws)
+    }
+
+    #[test]
+    fn messages_flow_both_ways() {
+        let (addr, shutdown, guests) = start("/console");
+        let mut ws = connect(addr, "/console", Some("https://console.example")).unwrap();
+        let mut guest = guests.recv().unwrap();
+
+        guest.send_frame(b"boot: ok".to_vec()).unwrap();
+        guest.send_frame(vec![3u8; 200_000]).unwrap();
+        assert_eq!(ws.read().unwrap(), Message::binary(&b"boot: ok"[..]));
+        assert_eq!(ws.read().unwrap().into_data().len(), 200_000);
+
+        ws.send(Message::text("ls\n")).unwrap();
+        ws.send(Message::binary(vec![1u8, 2, 3])).unwrap();
+        assert_eq!(guest.recv_frame().unwrap(), b"ls\n");
+        assert_eq!(guest.recv_frame().unwrap(), [1, 2, 3]);
+
+        // 客户机断开后浏览器收到正常关闭
+        guest.close();
+        drop(guest);
+        let frame = loop {
+            match ws.read() {
+                Ok(Message::Close(frame)) => break frame.unwrap(),
+                Ok(_) => continue,
+                Err(e) => panic!("{}", e),
+            }
+        };
+        assert_eq!(frame.code, CloseCode::Normal);
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn browser_close_disconnects_the_guest() {
+        let (addr, shutdown, guests) = start("/logs");
+        let mut ws = connect(addr, "/logs", None).unwrap();
+        let mut guest = guests.recv().unwrap();
+        ws.close(None).unwrap();
+        while ws.read().is_ok() {}
+        assert!(guest.recv_frame().is_err());
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn unknown_paths_and_foreign_origins_are_refused() {
+        let (addr, shutdown, _guests) = start("/console");
+        let status = |result: tungstenite::Result<_>| match result {
+            Err(tungstenite::Error::Http(response)) => response.status(),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("handshake should fail"),
+        };
+        assert_eq!(status(connect(addr, "/other", None)), StatusCode::NOT_FOUND);
+        assert_eq!(
+            status(connect(addr, "/console", Some("https://evil.example"))),
+            StatusCode::FORBIDDEN
+        );
+        shutdown.shutdown();
+    }
+
+    #[test]
+    fn unavailable_guest_closes_with_an_error() {
+        let bridge = WebSocketBridge::bind("127.0.0.1:0").unwrap().route_to(
+            "/console",
+            Arc::new(|| Err(Error::new(ErrorKind::ConnectionRefused, "no listener"))),
+        );
+        let addr = bridge.local_addr().unwrap();
+        let shutdown = bridge.shutdown_handle();
+        let server = thread::spawn(move || bridge.serve());
+        let mut ws = connect(addr, "/console", None).unwrap();
+        match ws.read().unwrap() {
+            Message::Close(Some(frame)) => {
+                assert_eq!(frame.code, CloseCode::Error);
+                assert!(frame.reason.contains("no listener"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        shutdown.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn close_reasons_fit_a_control_frame() {
+        let frame = close_frame(CloseCode::Error, &"错".repeat(100));
+        assert!(frame.reason.len() <= 123);
+        assert!(WebSocketBridge::bind("127.0.0.1:0")
+            .unwrap()
+            .serve()
+            .is_err());
+    }
+}
@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+use core::fmt;
+
+pub type Result<T> = core::result::Result<T, ErrorKind>;
+
+/// Why a frame could not be encoded, decoded or carried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    InvalidMagic,
+    InvalidVersion,
+    CrcMismatch,
+    UnexpectedEof,
+    InvalidPacket,
+    WriteZero,
+    Interrupted,
+    TimedOut,
+    Rejected,
+    Other,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ErrorKind::UnexpectedEof => "Unexpected end of file",
+            ErrorKind::WriteZero => "Write zero bytes",
+            ErrorKind::InvalidMagic => "Invalid magic number",
+            ErrorKind::CrcMismatch => "CRC checksum mismatch",
+            ErrorKind::InvalidPacket => "Invalid packet",
+            ErrorKind::InvalidVersion => "Invalid protocol version",
+            ErrorKind::Interrupted => "Operation interrupted",
+            ErrorKind::TimedOut => "Operation timed out",
+            ErrorKind::Rejected => "Rejected by peer",
+            ErrorKind::Other => "Other error",
+        })
+    }
+}
+
+impl core::error::Error for ErrorKind {}
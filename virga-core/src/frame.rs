@@ -0,0 +1,600 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! XTransport packets: a 16-byte little-endian header followed by up to
+//! `u16::MAX` bytes of payload
+
+use crate::error::{ErrorKind, Result};
+use alloc::vec::Vec;
+use crc32fast::Hasher;
+
+// Protocol constants
+pub const MAGIC: u32 = 0x58545250; // "XTRP"
+pub const VERSION: u8 = 0x01;
+/// Framing v2: the CRC also covers the header fields before it
+pub const LATEST_VERSION: u8 = 0x02;
+pub const HEADER_SIZE: usize = 16;
+pub const MESSAGE_HEAD_SIZE: usize = 32;
+pub const SHM_SEGMENT_SIZE: usize = 24;
+/// Largest frame the u16 length field can describe
+pub const MAX_FRAME_SIZE: usize = HEADER_SIZE + u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PacketType {
+    Data = 0,        // Single packet message
+    MessageHead = 1, // Multi-packet message header
+    MessageData = 2, // Multi-packet message data
+    Ack = 3,         // Acknowledgment packet
+    Control = 4,     // Connection control (never acknowledged)
+    ShmData = 5,     // Message segment carried in shared memory
+}
+
+impl PacketType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PacketType::Data),
+            1 => Some(PacketType::MessageHead),
+            2 => Some(PacketType::MessageData),
+            3 => Some(PacketType::Ack),
+            4 => Some(PacketType::Control),
+            5 => Some(PacketType::ShmData),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct PacketHeader {
+    pub magic: u32,   // 4 bytes
+    pub version: u8,  // 1 byte
+    pub pkt_type: u8, // 1 byte - Packet type
+    pub seq: u32,     // 4 bytes
+    pub length: u16,  // 2 bytes
+    pub crc32: u32,   // 4 bytes
+}
+
+impl PacketHeader {
+    pub fn new(pkt_type: PacketType, seq: u32, length: u16) -> Self {
+        PacketHeader {
+            magic: MAGIC,
+            version: VERSION,
+            pkt_type: pkt_type as u8,
+            seq,
+            length,
+            crc32: 0,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0u8; HEADER_SIZE];
+        buf[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        buf[4] = self.version;
+        buf[5] = self.pkt_type;
+        buf[6..10].copy_from_slice(&self.seq.to_le_bytes());
+        buf[10..12].copy_from_slice(&self.length.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.crc32.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; HEADER_SIZE]) -> Result<Self> {
+        let magic = u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        if magic != MAGIC {
+            return Err(ErrorKind::InvalidMagic);
+        }
+
+        let version = buf[4];
+        if !(VERSION..=LATEST_VERSION).contains(&version) {
+            return Err(ErrorKind::InvalidVersion);
+        }
+
+        let pkt_type = buf[5];
+        let seq = u32::from_le_bytes([buf[6], buf[7], buf[8], buf[9]]);
+        let length = u16::from_le_bytes([buf[10], buf[11]]);
+        let crc32 = u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]);
+
+        Ok(PacketHeader {
+            magic,
+            version,
+            pkt_type,
+            seq,
+            length,
+            crc32,
+        })
+    }
+
+    /// Hasher to feed the packet body into: v1 checks the body only, v2 also
+    /// covers magic, version, type, seq and length
+    pub fn hasher(&self) -> Hasher {
+        let mut hasher = Hasher::new();
+        if self.version >= 0x02 {
+            hasher.update(&self.to_bytes()[..12]);
+        }
+        hasher
+    }
+
+    /// CRC of `data` under this header's framing version
+    pub fn checksum(&self, data: &[u8]) -> u32 {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct MessageHead {
+    pub total_length: u64, // 8 bytes - Total message length
+    pub message_id: u64,   // 8 bytes - Unique message ID
+    pub packet_count: u32, // 4 bytes - Total packet count
+    pub flags: u32,        // 4 bytes - Message flags
+    pub reserved: [u8; 8], // 8 bytes - Reserved for extension
+}
+
+impl MessageHead {
+    pub fn new(total_length: u64, message_id: u64, packet_count: u32) -> Self {
+        MessageHead {
+            total_length,
+            message_id,
+            packet_count,
+            flags: 0,
+            reserved: [0; 8],
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; MESSAGE_HEAD_SIZE] {
+        let mut buf = [0u8; MESSAGE_HEAD_SIZE];
+        buf[0..8].copy_from_slice(&self.total_length.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.message_id.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.packet_count.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.flags.to_le_bytes());
+        buf[24..32].copy_from_slice(&self.reserved);
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; MESSAGE_HEAD_SIZE]) -> Result<Self> {
+        let total_length = u64::from_le_bytes([
+            buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7],
+        ]);
+        let message_id = u64::from_le_bytes([
+            buf[8], buf[9], buf[10], buf[11], buf[12], buf[13], buf[14], buf[15],
+        ]);
+        let packet_count = u32::from_le_bytes([buf[16], buf[17], buf[18], buf[19]]);
+        let flags = u32::from_le_bytes([buf[20], buf[21], buf[22], buf[23]]);
+        let mut reserved = [0u8; 8];
+        reserved.copy_from_slice(&buf[24..32]);
+
+        Ok(MessageHead {
+            total_length,
+            message_id,
+            packet_count,
+            flags,
+            reserved,
+        })
+    }
+}
+
+/// Control subtypes from here up are left to applications: the body is opaque
+/// to the framing layer and unknown ones are dropped by the receiver
+pub const EXTENSION_CONTROL_BASE: u8 = 0x80;
+
+/// Subtype carried in the first byte of a `Control` packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ControlType {
+    ShmOffer = 1,    // nonce (u64) + region size (u64)
+    ShmAccept = 2,   // nonce (u64)
+    ShmReject = 3,   // nonce (u64)
+    Ping = 4,        // nonce (u64), answered with Pong
+    Pong = 5,        // nonce (u64) echoed from the Ping
+    Compressed = 6,  // compressed length (u64); the next message is zstd-compressed
+    Batch = 7,       // batch length (u64); the next message packs several messages
+    TimeRequest = 8, // nonce (u64), answered with TimeReply
+    TimeReply = 9,   // nonce (u64) + receive and reply wall-clock times (u64 ns each)
+    GoAway = 10,     // reason code (u64); the peer should reconnect once idle
+    Reject = 11,     // reserved (u64) + UTF-8 reason; sent right before closing
+    Hello = 12,      // highest framing version the sender speaks (u64)
+    HelloAck = 13,   // framing version chosen for the connection (u64)
+    Close = 14,      // shutdown reason code (u64); sent right before closing
+}
+
+impl ControlType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(ControlType::ShmOffer),
+            2 => Some(ControlType::ShmAccept),
+            3 => Some(ControlType::ShmReject),
+            4 => Some(ControlType::Ping),
+            5 => Some(ControlType::Pong),
+            6 => Some(ControlType::Compressed),
+            7 => Some(ControlType::Batch),
+            8 => Some(ControlType::TimeRequest),
+            9 => Some(ControlType::TimeReply),
+            10 => Some(ControlType::GoAway),
+            11 => Some(ControlType::Reject),
+            12 => Some(ControlType::Hello),
+            13 => Some(ControlType::HelloAck),
+            14 => Some(ControlType::Close),
+            _ => None,
+        }
+    }
+}
+
+/// Descriptor of one message segment written to the shared-memory ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct ShmSegment {
+    pub total_length: u64, // 8 bytes - Total message length
+    pub position: u64,     // 8 bytes - Ring write position of the segment
+    pub length: u32,       // 4 bytes - Segment length
+    pub crc32: u32,        // 4 bytes - CRC32 of the segment bytes
+}
+
+impl ShmSegment {
+    pub fn to_bytes(&self) -> [u8; SHM_SEGMENT_SIZE] {
+        let mut buf = [0u8; SHM_SEGMENT_SIZE];
+        buf[0..8].copy_from_slice(&self.total_length.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.position.to_le_bytes());
+        buf[16..20].copy_from_slice(&self.length.to_le_bytes());
+        buf[20..24].copy_from_slice(&self.crc32.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        if buf.len() < SHM_SEGMENT_SIZE {
+            return Err(ErrorKind::InvalidPacket);
+        }
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        Ok(ShmSegment {
+            total_length: u64_at(0),
+            position: u64_at(8),
+            length: u32_at(16),
+            crc32: u32_at(20),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Packet {
+    pub header: PacketHeader,
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+    pub fn new(pkt_type: PacketType, seq: u32, data: Vec<u8>) -> Self {
+        Self::with_version(VERSION, pkt_type, seq, data)
+    }
+
+    pub fn with_version(version: u8, pkt_type: PacketType, seq: u32, data: Vec<u8>) -> Self {
+        let length = data.len() as u16;
+        let mut header = PacketHeader::new(pkt_type, seq, length);
+        header.version = version;
+        header.crc32 = header.checksum(&data);
+
+        Packet { header, data }
+    }
+
+    pub fn verify_crc(&self) -> bool {
+        self.header.checksum(&self.data) == self.header.crc32
+    }
+}
+
+/// Encode one packet (header + `data`) onto the end of `out` under framing
+/// `version`. `data` must fit the u16 length field
+pub fn encode_packet(version: u8, pkt_type: PacketType, seq: u32, data: &[u8], out: &mut Vec<u8>) {
+    debug_assert!(data.len() <= u16::MAX as usize);
+    let mut header = PacketHeader::new(pkt_type, seq, data.len() as u16);
+    header.version = version;
+    header.crc32 = header.checksum(data);
+
+    out.reserve(HEADER_SIZE + data.len());
+    out.extend_from_slice(&header.to_bytes());
+    out.extend_from_slice(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    // ==================== PacketType tests ====================
+
+    #[test]
+    fn packet_type_from_u8_valid() {
+        assert_eq!(PacketType::from_u8(0), Some(PacketType::Data));
+        assert_eq!(PacketType::from_u8(1), Some(PacketType::MessageHead));
+        assert_eq!(PacketType::from_u8(2), Some(PacketType::MessageData));
+        assert_eq!(PacketType::from_u8(3), Some(PacketType::Ack));
+        assert_eq!(PacketType::from_u8(4), Some(PacketType::Control));
+        assert_eq!(PacketType::from_u8(5), Some(PacketType::ShmData));
+    }
+
+    #[test]
+    fn packet_type_from_u8_invalid() {
+        assert_eq!(PacketType::from_u8(6), None);
+        assert_eq!(PacketType::from_u8(255), None);
+        assert_eq!(PacketType::from_u8(128), None);
+    }
+
+    #[test]
+    fn packet_type_as_u8_roundtrip() {
+        for val in 0..=5u8 {
+            let pt = PacketType::from_u8(val).unwrap();
+            assert_eq!(pt as u8, val);
+        }
+    }
+
+    #[test]
+    fn packet_type_equality() {
+        assert_eq!(PacketType::Data, PacketType::Data);
+        assert_ne!(PacketType::Data, PacketType::Ack);
+    }
+
+    // ==================== PacketHeader tests ====================
+
+    #[test]
+    fn packet_header_new_sets_fields() {
+        let header = PacketHeader::new(PacketType::Data, 42, 100);
+        assert_eq!(header.magic, MAGIC);
+        assert_eq!(header.version, VERSION);
+        assert_eq!(header.pkt_type, PacketType::Data as u8);
+        assert_eq!(header.seq, 42);
+        assert_eq!(header.length, 100);
+        assert_eq!(header.crc32, 0);
+    }
+
+    #[test]
+    fn packet_header_to_bytes_from_bytes_roundtrip() {
+        let header = PacketHeader::new(PacketType::MessageHead, 12345, 256);
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), HEADER_SIZE);
+
+        let restored = PacketHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.magic, MAGIC);
+        assert_eq!(restored.version, VERSION);
+        assert_eq!(restored.pkt_type, PacketType::MessageHead as u8);
+        assert_eq!(restored.seq, 12345);
+        assert_eq!(restored.length, 256);
+        assert_eq!(restored.crc32, 0);
+    }
+
+    #[test]
+    fn packet_header_to_bytes_from_bytes_all_types() {
+        let types = [
+            PacketType::Data,
+            PacketType::MessageHead,
+            PacketType::MessageData,
+            PacketType::Ack,
+        ];
+        for pt in types {
+            let header = PacketHeader::new(pt, 999, 50);
+            let bytes = header.to_bytes();
+            let restored = PacketHeader::from_bytes(&bytes).unwrap();
+            assert_eq!(restored.pkt_type, pt as u8);
+        }
+    }
+
+    #[test]
+    fn packet_header_from_bytes_invalid_magic() {
+        let mut bytes = PacketHeader::new(PacketType::Data, 0, 0).to_bytes();
+        bytes[0] = 0xFF;
+        let err = PacketHeader::from_bytes(&bytes).unwrap_err();
+        assert_eq!(err, ErrorKind::InvalidMagic);
+    }
+
+    #[test]
+    fn packet_header_from_bytes_invalid_version() {
+        let mut bytes = PacketHeader::new(PacketType::Data, 0, 0).to_bytes();
+        bytes[4] = 0xFF;
+        let err = PacketHeader::from_bytes(&bytes).unwrap_err();
+        assert_eq!(err, ErrorKind::InvalidVersion);
+    }
+
+    #[test]
+    fn v2_checksum_covers_the_header() {
+        let v1 = Packet::new(PacketType::Data, 7, vec![1, 2, 3]);
+        let v2 = Packet::with_version(LATEST_VERSION, PacketType::Data, 7, vec![1, 2, 3]);
+        assert_eq!(v2.header.version, 0x02);
+        assert!(v1.verify_crc() && v2.verify_crc());
+        assert_ne!(v1.header.crc32, v2.header.crc32);
+
+        let restored = PacketHeader::from_bytes(&v2.header.to_bytes()).unwrap();
+        assert_eq!(restored.version, LATEST_VERSION);
+        // A flipped seq goes unnoticed in v1 but fails the v2 check
+        let (mut v1, mut v2) = (v1, v2);
+        v1.header.seq ^= 1;
+        v2.header.seq ^= 1;
+        assert!(v1.verify_crc());
+        assert!(!v2.verify_crc());
+    }
+
+    #[test]
+    fn packet_header_with_max_seq() {
+        let header = PacketHeader::new(PacketType::Data, u32::MAX, 0);
+        let bytes = header.to_bytes();
+        let restored = PacketHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.seq, u32::MAX);
+    }
+
+    #[test]
+    fn packet_header_with_max_length() {
+        let header = PacketHeader::new(PacketType::Data, 0, u16::MAX);
+        let bytes = header.to_bytes();
+        let restored = PacketHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.length, u16::MAX);
+    }
+
+    #[test]
+    fn packet_header_preserves_crc32() {
+        let mut header = PacketHeader::new(PacketType::Data, 0, 0);
+        header.crc32 = 0xDEADBEEF;
+        let bytes = header.to_bytes();
+        let restored = PacketHeader::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.crc32, 0xDEADBEEF);
+    }
+
+    // ==================== MessageHead tests ====================
+
+    #[test]
+    fn message_head_new_sets_fields() {
+        let head = MessageHead::new(1024, 42, 10);
+        assert_eq!(head.total_length, 1024);
+        assert_eq!(head.message_id, 42);
+        assert_eq!(head.packet_count, 10);
+        assert_eq!(head.flags, 0);
+        assert_eq!(head.reserved, [0u8; 8]);
+    }
+
+    #[test]
+    fn message_head_to_bytes_from_bytes_roundtrip() {
+        let head = MessageHead::new(65536, 99, 100);
+        let bytes = head.to_bytes();
+        assert_eq!(bytes.len(), MESSAGE_HEAD_SIZE);
+
+        let restored = MessageHead::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.total_length, 65536);
+        assert_eq!(restored.message_id, 99);
+        assert_eq!(restored.packet_count, 100);
+        assert_eq!(restored.flags, 0);
+        assert_eq!(restored.reserved, [0u8; 8]);
+    }
+
+    #[test]
+    fn message_head_max_values() {
+        let head = MessageHead::new(u64::MAX, u64::MAX, u32::MAX);
+        let bytes = head.to_bytes();
+        let restored = MessageHead::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.total_length, u64::MAX);
+        assert_eq!(restored.message_id, u64::MAX);
+        assert_eq!(restored.packet_count, u32::MAX);
+    }
+
+    #[test]
+    fn message_head_zero_values() {
+        let head = MessageHead::new(0, 0, 0);
+        let bytes = head.to_bytes();
+        let restored = MessageHead::from_bytes(&bytes).unwrap();
+        assert_eq!(restored.total_length, 0);
+        assert_eq!(restored.message_id, 0);
+        assert_eq!(restored.packet_count, 0);
+    }
+
+    // ==================== Control / ShmSegment tests ====================
+
+    #[test]
+    fn control_type_from_u8() {
+        assert_eq!(ControlType::from_u8(1), Some(ControlType::ShmOffer));
+        assert_eq!(ControlType::from_u8(2), Some(ControlType::ShmAccept));
+        assert_eq!(ControlType::from_u8(3), Some(ControlType::ShmReject));
+        assert_eq!(ControlType::from_u8(4), Some(ControlType::Ping));
+        assert_eq!(ControlType::from_u8(5), Some(ControlType::Pong));
+        assert_eq!(ControlType::from_u8(6), Some(ControlType::Compressed));
+        assert_eq!(ControlType::from_u8(7), Some(ControlType::Batch));
+        assert_eq!(ControlType::from_u8(8), Some(ControlType::TimeRequest));
+        assert_eq!(ControlType::from_u8(9), Some(ControlType::TimeReply));
+        assert_eq!(ControlType::from_u8(10), Some(ControlType::GoAway));
+        assert_eq!(ControlType::from_u8(11), Some(ControlType::Reject));
+        assert_eq!(ControlType::from_u8(12), Some(ControlType::Hello));
+        assert_eq!(ControlType::from_u8(13), Some(ControlType::HelloAck));
+        assert_eq!(ControlType::from_u8(14), Some(ControlType::Close));
+        assert_eq!(ControlType::from_u8(0), None);
+        assert_eq!(ControlType::from_u8(15), None);
+    }
+
+    #[test]
+    fn shm_segment_roundtrip() {
+        let seg = ShmSegment {
+            total_length: 1 << 40,
+            position: 123_456_789,
+            length: 65536,
+            crc32: 0xDEADBEEF,
+        };
+        let bytes = seg.to_bytes();
+        assert_eq!(bytes.len(), SHM_SEGMENT_SIZE);
+        assert_eq!(ShmSegment::from_bytes(&bytes).unwrap(), seg);
+    }
+
+    #[test]
+    fn shm_segment_from_short_buffer() {
+        let err = ShmSegment::from_bytes(&[0u8; SHM_SEGMENT_SIZE - 1]).unwrap_err();
+        assert_eq!(err, ErrorKind::InvalidPacket);
+    }
+
+    // ==================== Packet tests ====================
+
+    #[test]
+    fn packet_new_computes_crc() {
+        let data = vec![1, 2, 3, 4, 5];
+        let packet = Packet::new(PacketType::Data, 0, data.clone());
+        assert_eq!(packet.header.pkt_type, PacketType::Data as u8);
+        assert_eq!(packet.header.length, 5);
+        assert_eq!(packet.data, data);
+        assert_ne!(packet.header.crc32, 0);
+    }
+
+    #[test]
+    fn packet_verify_crc_valid() {
+        let data = vec![10, 20, 30, 40, 50];
+        let packet = Packet::new(PacketType::Data, 1, data);
+        assert!(packet.verify_crc());
+    }
+
+    #[test]
+    fn packet_verify_crc_corrupted_data() {
+        let data = vec![10, 20, 30, 40, 50];
+        let mut packet = Packet::new(PacketType::Data, 1, data);
+        packet.data[0] = 0xFF;
+        assert!(!packet.verify_crc());
+    }
+
+    #[test]
+    fn packet_verify_crc_corrupted_crc() {
+        let data = vec![10, 20, 30];
+        let mut packet = Packet::new(PacketType::Data, 1, data);
+        packet.header.crc32 ^= 0xFFFFFFFF;
+        assert!(!packet.verify_crc());
+    }
+
+    #[test]
+    fn packet_empty_data() {
+        let packet = Packet::new(PacketType::Data, 0, vec![]);
+        assert_eq!(packet.header.length, 0);
+        assert!(packet.verify_crc());
+    }
+
+    #[test]
+    fn packet_large_data() {
+        let data = vec![0xAB; 4096];
+        let packet = Packet::new(PacketType::MessageData, 100, data.clone());
+        assert_eq!(packet.header.length, 4096);
+        assert_eq!(packet.data.len(), 4096);
+        assert!(packet.verify_crc());
+    }
+
+    #[test]
+    fn packet_different_data_different_crc() {
+        let p1 = Packet::new(PacketType::Data, 0, vec![1, 2, 3]);
+        let p2 = Packet::new(PacketType::Data, 0, vec![4, 5, 6]);
+        assert_ne!(p1.header.crc32, p2.header.crc32);
+    }
+
+    #[test]
+    fn packet_same_data_same_crc() {
+        let data = vec![1, 2, 3, 4, 5];
+        let p1 = Packet::new(PacketType::Data, 0, data.clone());
+        let p2 = Packet::new(PacketType::Data, 99, data);
+        assert_eq!(p1.header.crc32, p2.header.crc32);
+    }
+
+    #[test]
+    fn packet_ack_type() {
+        let ack_data = 42u32.to_le_bytes().to_vec();
+        let packet = Packet::new(PacketType::Ack, 5, ack_data.clone());
+        assert_eq!(packet.header.pkt_type, PacketType::Ack as u8);
+        assert!(packet.verify_crc());
+        assert_eq!(packet.data, ack_data);
+    }
+}
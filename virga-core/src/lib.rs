@@ -0,0 +1,28 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+//! Framing core of the Virga wire format, usable with `alloc` only.
+//!
+//! The `virga` crate runs XTransport over std streams and threads; a guest
+//! without std or tokio (a unikernel, a firmware shim) can still speak the
+//! same bytes with this crate:
+//!
+//! - [`frame`]: packet header, message head, control and shared-memory
+//!   descriptors, with their encode/decode
+//! - [`ReadState`]: progress of a message read in pieces through a byte
+//!   stream interface
+//!
+//! Fragmentation, acknowledgements, version negotiation and shared memory
+//! are left to the caller, which also moves the bytes.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod error;
+pub mod frame;
+mod read_state;
+
+pub use error::{ErrorKind, Result};
+pub use read_state::ReadState;
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright 2025 KylinSoft Co., Ltd. <https://www.kylinos.cn/>
+// See LICENSES for license details.
+
+/// Progress of a message handed out in pieces through a byte-stream
+/// interface (`Read`, `AsyncRead`, or a guest's own equivalent)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReadState {
+    /// At a message boundary: the next read starts a new message
+    #[default]
+    Idle,
+    /// `read` of the current message's `total` bytes have been handed out
+    Reading { total: usize, read: usize },
+}
+
+impl ReadState {
+    /// Record that `n` more bytes of the current message were handed out;
+    /// back to `Idle` once all of it has been
+    pub fn advance(&mut self, n: usize) {
+        if let ReadState::Reading { total, read } = *self {
+            let read = read + n;
+            *self = if read >= total {
+                ReadState::Idle
+            } else {
+                ReadState::Reading { total, read }
+            };
+        }
+    }
+
+    /// Bytes of the current message not yet handed out
+    pub fn remaining(&self) -> usize {
+        match *self {
+            ReadState::Idle => 0,
+            ReadState::Reading { total, read } => total - read,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_returns_to_idle_at_the_end_of_the_message() {
+        let mut state = ReadState::Reading { total: 10, read: 0 };
+        state.advance(4);
+        assert_eq!(state, ReadState::Reading { total: 10, read: 4 });
+        assert_eq!(state.remaining(), 6);
+        state.advance(6);
+        assert_eq!(state, ReadState::Idle);
+        state.advance(3);
+        assert_eq!(state, ReadState::Idle);
+        assert_eq!(state.remaining(), 0);
+    }
+}